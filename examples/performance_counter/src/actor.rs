@@ -182,7 +182,7 @@ fn _b2_stable_vec_get(count: u32) -> u64 {
     {
         let before = performance_counter(0);
 
-        for i in 0..count as usize {
+        for i in 0..count as u64 {
             let j = *vec.get(i).unwrap();
         }
 
@@ -1,7 +1,11 @@
 #[cfg(test)]
 mod derive_tests {
     use candid::{CandidType, Deserialize, Principal};
-    use ic_stable_memory::derive::{AsFixedSizeBytes, CandidAsDynSizeBytes, StableType};
+    use ic_stable_memory::derive::{
+        AsFixedSizeBytes, AsHashableBytes, CandidAsDynSizeBytes, CandidAsHashTree,
+        CborAsDynSizeBytes, CompactAsDynSizeBytes, StableType, VersionedAsDynSizeBytes,
+    };
+    use ic_stable_memory::encoding::Upgrade;
 
     #[derive(StableType, AsFixedSizeBytes, PartialEq, Eq, Debug)]
     struct A1 {
@@ -90,6 +94,190 @@ mod derive_tests {
 
         assert_eq!(c, c_copy);
     }
+
+    #[derive(StableType, CompactAsDynSizeBytes, PartialEq, Eq, Debug)]
+    struct G {
+        x: u64,
+        s: String,
+        v: Vec<u8>,
+        h: H,
+    }
+
+    #[derive(StableType, CompactAsDynSizeBytes, PartialEq, Eq, Debug)]
+    enum H {
+        X,
+        Y(u32, String),
+        Z { a: u64 },
+    }
+
+    #[derive(StableType, CompactAsDynSizeBytes, PartialEq, Eq, Debug)]
+    struct I;
+
+    #[test]
+    fn compact_works_fine() {
+        use ic_stable_memory::AsDynSizeBytes;
+
+        for h in [H::X, H::Y(10, String::from("str")), H::Z { a: 20 }] {
+            let g = G {
+                x: 1,
+                s: String::from("hello"),
+                v: vec![1, 2, 3],
+                h,
+            };
+
+            let mut g_buf = g.as_dyn_size_bytes();
+            g_buf.extend(vec![0u8; 10]);
+
+            assert_eq!(G::from_dyn_size_bytes(&g_buf), g);
+        }
+
+        assert!(I.as_dyn_size_bytes().is_empty());
+        assert_eq!(I::from_dyn_size_bytes(&[]), I);
+    }
+
+    #[derive(
+        StableType,
+        serde::Serialize,
+        serde::Deserialize,
+        CborAsDynSizeBytes,
+        PartialEq,
+        Eq,
+        Debug,
+    )]
+    enum J {
+        X,
+        Y { s: String, v: Vec<u64> },
+    }
+
+    #[test]
+    fn cbor_works_fine() {
+        use ic_stable_memory::AsDynSizeBytes;
+
+        for j in [
+            J::X,
+            J::Y {
+                s: String::from("str"),
+                v: vec![1, 2, 3],
+            },
+        ] {
+            let j_buf = j.as_dyn_size_bytes();
+            assert_eq!(serde_cbor::from_slice::<J>(&j_buf).unwrap(), j);
+
+            let mut j_buf = j_buf;
+            j_buf.extend(vec![0u8; 10]);
+
+            assert_eq!(J::from_dyn_size_bytes(&j_buf), j);
+        }
+    }
+
+    #[derive(StableType, AsFixedSizeBytes, AsHashableBytes)]
+    struct E(u64, u32);
+
+    #[derive(CandidType, Deserialize, CandidAsHashTree)]
+    struct F {
+        x: u32,
+        s: String,
+    }
+
+    #[test]
+    fn certification_works_fine() {
+        use ic_stable_memory::utils::certification::leaf_hash;
+        use ic_stable_memory::{AsFixedSizeBytes, AsHashTree, AsHashableBytes};
+
+        let e = E(1, 2);
+        assert_eq!(e.as_hashable_bytes(), e.as_new_fixed_size_bytes().to_vec());
+
+        let f = F {
+            x: 10,
+            s: String::from("str"),
+        };
+        let f_buf = candid::encode_one(&f).unwrap();
+
+        assert_eq!(f.root_hash(), leaf_hash(&f_buf));
+        assert_eq!(f.hash_tree().reconstruct(), f.root_hash());
+    }
+
+    #[derive(CandidType, Deserialize, VersionedAsDynSizeBytes)]
+    #[version(1)]
+    struct D1 {
+        name: String,
+    }
+
+    #[derive(CandidType, Deserialize, VersionedAsDynSizeBytes)]
+    #[version(2)]
+    #[version(1, D1)]
+    struct D2 {
+        first_name: String,
+        last_name: String,
+    }
+
+    #[derive(
+        StableType, CandidType, Deserialize, VersionedAsDynSizeBytes, PartialEq, Eq, Debug,
+    )]
+    #[version(3)]
+    #[version(1, D1)]
+    #[version(2, D2)]
+    struct D3 {
+        first_name: String,
+        last_name: String,
+        age: Option<u32>,
+    }
+
+    impl Upgrade for D1 {
+        type Next = D2;
+
+        fn upgrade(self) -> D2 {
+            let (first_name, last_name) = self.name.split_once(' ').unwrap();
+
+            D2 {
+                first_name: String::from(first_name),
+                last_name: String::from(last_name),
+            }
+        }
+    }
+
+    impl Upgrade for D2 {
+        type Next = D3;
+
+        fn upgrade(self) -> D3 {
+            D3 {
+                first_name: self.first_name,
+                last_name: self.last_name,
+                age: None,
+            }
+        }
+    }
+
+    #[test]
+    fn versioned_works_fine() {
+        use ic_stable_memory::AsDynSizeBytes;
+
+        let expected = D3 {
+            first_name: String::from("John"),
+            last_name: String::from("Doe"),
+            age: None,
+        };
+
+        let d_1 = D1 {
+            name: String::from("John Doe"),
+        };
+        assert_eq!(D3::from_dyn_size_bytes(&d_1.as_dyn_size_bytes()), expected);
+
+        let d_2 = D2 {
+            first_name: String::from("John"),
+            last_name: String::from("Doe"),
+        };
+        assert_eq!(D3::from_dyn_size_bytes(&d_2.as_dyn_size_bytes()), expected);
+
+        let d_3 = D3 {
+            age: Some(30),
+            ..expected
+        };
+        let mut d_3_buf = d_3.as_dyn_size_bytes();
+        d_3_buf.extend(vec![0u8; 10]);
+
+        assert_eq!(D3::from_dyn_size_bytes(&d_3_buf), d_3);
+    }
 }
 
 #[cfg(test)]
@@ -342,6 +530,51 @@ mod tests {
         _debug_validate_allocator();
         assert_eq!(get_allocated_size(), 0);
     }
+
+    #[ic_stable_memory::derive::stable_state]
+    #[derive(AsFixedSizeBytes, StableType)]
+    struct RootState {
+        vec: SVec<u64>,
+        map: SBTreeMap<u64, SBox<String>>,
+    }
+
+    #[test]
+    fn stable_state_works_fine() {
+        ic_stable_memory::stable::clear();
+
+        init_state(RootState {
+            vec: SVec::new(),
+            map: SBTreeMap::new(),
+        });
+
+        for i in 0..10 {
+            let mut state = state_mut();
+
+            state.vec.push(i).debugless_unwrap();
+            state
+                .map
+                .insert(i, SBox::new(i.to_string()).debugless_unwrap())
+                .debugless_unwrap();
+        }
+
+        pre_upgrade_state();
+
+        // upgrade happens
+
+        post_upgrade_state();
+
+        {
+            let state = state();
+
+            assert_eq!(state.vec.len(), 10);
+            for i in 0..10 {
+                assert_eq!(*state.vec.get(i as usize).unwrap(), i);
+                assert_eq!(**state.map.get(&i).unwrap(), i.to_string());
+            }
+        }
+
+        _debug_validate_allocator();
+    }
 }
 
 #[cfg(test)]
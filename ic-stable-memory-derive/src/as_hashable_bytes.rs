@@ -0,0 +1,18 @@
+use proc_macro2::{self, TokenStream};
+use quote::quote;
+use syn::{Generics, Ident};
+
+pub fn derive_as_hashable_bytes_impl(ident: &Ident, generics: &Generics) -> TokenStream {
+    if !generics.params.is_empty() {
+        panic!("Generics not supported");
+    }
+
+    quote! {
+        impl ic_stable_memory::AsHashableBytes for #ident {
+            #[inline]
+            fn as_hashable_bytes(&self) -> Vec<u8> {
+                ic_stable_memory::AsFixedSizeBytes::as_new_fixed_size_bytes(self).to_vec()
+            }
+        }
+    }
+}
@@ -1,22 +1,26 @@
+use crate::candid_version::{candid_decode, candid_path};
 use proc_macro2::{self, TokenStream};
 use quote::quote;
-use syn::{Generics, Ident, Index};
+use syn::{Generics, Ident};
 
 pub fn derive_candid_as_dyn_size_bytes_impl(ident: &Ident, generics: &Generics) -> TokenStream {
     if !generics.params.is_empty() {
         panic!("Generics not supported");
     }
 
+    let candid = candid_path();
+    let decode = candid_decode(quote! { _ });
+
     quote! {
         impl ic_stable_memory::AsDynSizeBytes for #ident {
             #[inline]
             fn as_dyn_size_bytes(&self) -> Vec<u8> {
-                candid::encode_one(self).unwrap()
+                #candid::encode_one(self).unwrap()
             }
 
             #[inline]
             fn from_dyn_size_bytes(arr: &[u8]) -> Self {
-                ic_stable_memory::encoding::dyn_size::candid_decode_one_allow_trailing(arr).unwrap()
+                #decode
             }
         }
     }
@@ -0,0 +1,26 @@
+use crate::candid_version::candid_path;
+use proc_macro2::{self, TokenStream};
+use quote::quote;
+use syn::{Generics, Ident};
+
+pub fn derive_candid_as_hash_tree_impl(ident: &Ident, generics: &Generics) -> TokenStream {
+    if !generics.params.is_empty() {
+        panic!("Generics not supported");
+    }
+
+    let candid = candid_path();
+
+    quote! {
+        impl ic_stable_memory::AsHashTree for #ident {
+            #[inline]
+            fn root_hash(&self) -> ic_stable_memory::utils::certification::Hash {
+                ic_stable_memory::leaf_hash(&#candid::encode_one(self).unwrap())
+            }
+
+            #[inline]
+            fn hash_tree(&self) -> ic_stable_memory::utils::certification::HashTree {
+                ic_stable_memory::leaf(#candid::encode_one(self).unwrap())
+            }
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use proc_macro2::{self, TokenStream};
+use quote::quote;
+
+const SELECTED: bool = cfg!(any(
+    feature = "candid_0_8",
+    feature = "candid_0_9",
+    feature = "candid_0_10"
+));
+
+// with one of candid_0_* features the generated code uses the candid version, selected in
+// ic-stable-memory, otherwise - the candid crate of the user
+pub fn candid_path() -> TokenStream {
+    if SELECTED {
+        quote! { ic_stable_memory::candid }
+    } else {
+        quote! { candid }
+    }
+}
+
+// decodes `arr` into `ty`, ignoring trailing bytes
+pub fn candid_decode(ty: TokenStream) -> TokenStream {
+    if SELECTED {
+        quote! { ic_stable_memory::encoding::dyn_size::candid_decode_one_allow_trailing::<#ty>(arr).unwrap() }
+    } else {
+        quote! { candid::de::IDLDeserialize::new(arr).unwrap().get_value::<#ty>().unwrap() }
+    }
+}
@@ -0,0 +1,23 @@
+use proc_macro2::{self, TokenStream};
+use quote::quote;
+use syn::{Generics, Ident};
+
+pub fn derive_cbor_as_dyn_size_bytes_impl(ident: &Ident, generics: &Generics) -> TokenStream {
+    if !generics.params.is_empty() {
+        panic!("Generics not supported");
+    }
+
+    quote! {
+        impl ic_stable_memory::AsDynSizeBytes for #ident {
+            #[inline]
+            fn as_dyn_size_bytes(&self) -> Vec<u8> {
+                ic_stable_memory::encoding::dyn_size::cbor_encode(self).unwrap()
+            }
+
+            #[inline]
+            fn from_dyn_size_bytes(arr: &[u8]) -> Self {
+                ic_stable_memory::encoding::dyn_size::cbor_decode_allow_trailing(arr).unwrap()
+            }
+        }
+    }
+}
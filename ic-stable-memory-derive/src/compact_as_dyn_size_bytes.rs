@@ -0,0 +1,135 @@
+use proc_macro2::{self, TokenStream};
+use quote::{format_ident, quote};
+use syn::{Data, Fields, Generics, Ident, Index};
+
+pub fn derive_compact_as_dyn_size_bytes_impl(
+    ident: &Ident,
+    data: &Data,
+    generics: &Generics,
+) -> TokenStream {
+    if !generics.params.is_empty() {
+        panic!("Generics not supported");
+    }
+
+    let (as_dyn_size_body, from_dyn_size_body) = match data {
+        Data::Struct(d) => {
+            let mut as_dyn_size_body = quote! {};
+            let mut from_dyn_size_body = quote! {};
+
+            for (idx, f) in d.fields.iter().enumerate() {
+                if let Some(i) = f.ident.clone() {
+                    as_dyn_size_body = quote! { #as_dyn_size_body ic_stable_memory::encoding::dyn_size::compact_encode_field(&self.#i, &mut buf); };
+                    from_dyn_size_body = quote! { #from_dyn_size_body #i: ic_stable_memory::encoding::dyn_size::compact_decode_field(buf, &mut offset), };
+                } else {
+                    let idx = Index::from(idx);
+
+                    as_dyn_size_body = quote! { #as_dyn_size_body ic_stable_memory::encoding::dyn_size::compact_encode_field(&self.#idx, &mut buf); };
+                    from_dyn_size_body = quote! { #from_dyn_size_body ic_stable_memory::encoding::dyn_size::compact_decode_field(buf, &mut offset), };
+                }
+            }
+
+            from_dyn_size_body = match d.fields {
+                Fields::Unit => quote! {
+                    Self
+                },
+                Fields::Named(_) => quote! {
+                    Self { #from_dyn_size_body }
+                },
+                Fields::Unnamed(_) => quote! {
+                    Self ( #from_dyn_size_body )
+                },
+            };
+
+            (as_dyn_size_body, from_dyn_size_body)
+        }
+        Data::Enum(d) => {
+            let mut as_dyn_size_body_total = quote! {};
+            let mut from_dyn_size_body_total = quote! {};
+
+            for (v_idx, v) in d.variants.iter().enumerate() {
+                let v_name = &v.ident;
+
+                let mut as_dyn_size_body = quote! {};
+                let mut from_dyn_size_body = quote! {};
+
+                let mut enum_header = quote! {};
+
+                for (idx, f) in v.fields.iter().enumerate() {
+                    if let Some(i) = f.ident.clone() {
+                        enum_header = quote! { #enum_header #i, };
+
+                        as_dyn_size_body = quote! { #as_dyn_size_body ic_stable_memory::encoding::dyn_size::compact_encode_field(#i, &mut buf); };
+                        from_dyn_size_body = quote! { #from_dyn_size_body #i: ic_stable_memory::encoding::dyn_size::compact_decode_field(buf, &mut offset), };
+                    } else {
+                        let val_i = format_ident!("val_{}", Index::from(idx));
+                        enum_header = quote! { #enum_header #val_i, };
+
+                        as_dyn_size_body = quote! { #as_dyn_size_body ic_stable_memory::encoding::dyn_size::compact_encode_field(#val_i, &mut buf); };
+                        from_dyn_size_body = quote! { #from_dyn_size_body ic_stable_memory::encoding::dyn_size::compact_decode_field(buf, &mut offset), };
+                    }
+                }
+
+                let (from, to) = match &v.fields {
+                    Fields::Unit => (quote! { Self::#v_name }, quote! { Self::#v_name }),
+                    Fields::Named(_) => (
+                        quote! { Self::#v_name { #from_dyn_size_body } },
+                        quote! { Self::#v_name { #enum_header } },
+                    ),
+                    Fields::Unnamed(_) => (
+                        quote! { Self::#v_name( #from_dyn_size_body ) },
+                        quote! { Self::#v_name( #enum_header ) },
+                    ),
+                };
+
+                from_dyn_size_body_total = quote! {
+                    #from_dyn_size_body_total
+                    #v_idx => #from,
+                };
+
+                as_dyn_size_body_total = quote! {
+                    #as_dyn_size_body_total
+                    #to => {
+                        ic_stable_memory::encoding::dyn_size::compact_encode_len(#v_idx, &mut buf);
+                        #as_dyn_size_body
+                    }
+                };
+            }
+
+            let as_dyn_size_body_total = quote! {
+                match self {
+                    #as_dyn_size_body_total
+                }
+            };
+
+            let from_dyn_size_body_total = quote! {
+                match ic_stable_memory::encoding::dyn_size::compact_decode_len(buf, &mut offset) {
+                    #from_dyn_size_body_total
+                    _ => unreachable!(),
+                }
+            };
+
+            (as_dyn_size_body_total, from_dyn_size_body_total)
+        }
+        _ => panic!("Unions not supported!"),
+    };
+
+    quote! {
+        impl ic_stable_memory::AsDynSizeBytes for #ident {
+            #[allow(unused_mut)]
+            fn as_dyn_size_bytes(&self) -> Vec<u8> {
+                let mut buf = Vec::new();
+
+                #as_dyn_size_body
+
+                buf
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn from_dyn_size_bytes(buf: &[u8]) -> Self {
+                let mut offset = 0usize;
+
+                #from_dyn_size_body
+            }
+        }
+    }
+}
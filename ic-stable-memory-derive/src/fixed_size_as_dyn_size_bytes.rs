@@ -14,6 +14,14 @@ pub fn derive_fixed_size_as_dyn_size_bytes_impl(ident: &Ident, generics: &Generi
                 ic_stable_memory::AsFixedSizeBytes::as_new_fixed_size_bytes(self).to_vec()
             }
 
+            #[inline]
+            fn as_dyn_size_bytes_into(&self, buf: &mut Vec<u8>) {
+                let from = buf.len();
+                buf.resize(from + <Self as ic_stable_memory::AsFixedSizeBytes>::SIZE, 0);
+
+                ic_stable_memory::AsFixedSizeBytes::as_fixed_size_bytes(self, &mut buf[from..]);
+            }
+
             #[inline]
             fn from_dyn_size_bytes(arr: &[u8]) -> Self {
                 ic_stable_memory::AsFixedSizeBytes::from_fixed_size_bytes(arr)
@@ -1,16 +1,27 @@
 use crate::as_fixed_size_bytes::derive_as_fixed_size_bytes_impl;
+use crate::as_hashable_bytes::derive_as_hashable_bytes_impl;
 use crate::candid_as_dyn_size_bytes::derive_candid_as_dyn_size_bytes_impl;
+use crate::candid_as_hash_tree::derive_candid_as_hash_tree_impl;
+use crate::cbor_as_dyn_size_bytes::derive_cbor_as_dyn_size_bytes_impl;
+use crate::compact_as_dyn_size_bytes::derive_compact_as_dyn_size_bytes_impl;
 use crate::fixed_size_as_dyn_size_bytes::derive_fixed_size_as_dyn_size_bytes_impl;
+use crate::stable_state::stable_state_impl;
 use crate::stable_type::derive_stable_type_impl;
+use crate::versioned_as_dyn_size_bytes::derive_versioned_as_dyn_size_bytes_impl;
 use proc_macro::TokenStream as Tokens;
-use proc_macro2::{self, TokenStream};
-use quote::quote;
-use syn::{parse_macro_input, DeriveInput, Fields, Ident, Index};
+use syn::{parse_macro_input, DeriveInput};
 
 mod as_fixed_size_bytes;
+mod as_hashable_bytes;
 mod candid_as_dyn_size_bytes;
+mod candid_as_hash_tree;
+mod candid_version;
+mod cbor_as_dyn_size_bytes;
+mod compact_as_dyn_size_bytes;
 mod fixed_size_as_dyn_size_bytes;
+mod stable_state;
 mod stable_type;
+mod versioned_as_dyn_size_bytes;
 
 /// Derives [ic_stable_memory::StableType] proxying flag toggling calls
 #[proc_macro_derive(StableType)]
@@ -57,3 +68,92 @@ pub fn derive_fixed_size_as_dyn_size_bytes(input: Tokens) -> Tokens {
 
     derive_fixed_size_as_dyn_size_bytes_impl(&ident, &generics).into()
 }
+
+/// Derives [ic_stable_memory::AsDynSizeBytes] for a type that already implements [serde::Serialize] and [serde::Deserialize].
+///
+/// Values are encoded into plain CBOR, so the encoded bytes can also be served as is, for example, in certified HTTP responses.
+#[proc_macro_derive(CborAsDynSizeBytes)]
+pub fn derive_cbor_as_dyn_size_bytes(input: Tokens) -> Tokens {
+    let DeriveInput {
+        ident, generics, ..
+    } = parse_macro_input!(input);
+
+    derive_cbor_as_dyn_size_bytes_impl(&ident, &generics).into()
+}
+
+/// Derives [ic_stable_memory::AsDynSizeBytes] for a type, whose fields all implement [ic_stable_memory::AsDynSizeBytes],
+/// without using candid.
+///
+/// Each field is encoded with its own [ic_stable_memory::AsDynSizeBytes] implementation and prefixed with its length.
+/// Enums are prefixed with the index of the variant. Lengths and indices take a single byte, if they are less than `128`.
+/// Does not support generics at the moment.
+#[proc_macro_derive(CompactAsDynSizeBytes)]
+pub fn derive_compact_as_dyn_size_bytes(input: Tokens) -> Tokens {
+    let DeriveInput {
+        ident,
+        data,
+        generics,
+        ..
+    } = parse_macro_input!(input);
+
+    derive_compact_as_dyn_size_bytes_impl(&ident, &data, &generics).into()
+}
+
+/// Derives [ic_stable_memory::AsDynSizeBytes] for a type that already implements [candid::CandidType] and [candid::Deserialize],
+/// prepending a version tag to the encoded value.
+///
+/// The current version is set with `#[version(n)]`. Previous versions of the type are listed with
+/// `#[version(n, PreviousType)]` attributes - values, encoded with these versions, are decoded as `PreviousType`
+/// and then upgraded to the next version with [ic_stable_memory::encoding::Upgrade], until they reach the current one.
+/// Does not support generics at the moment.
+#[proc_macro_derive(VersionedAsDynSizeBytes, attributes(version))]
+pub fn derive_versioned_as_dyn_size_bytes(input: Tokens) -> Tokens {
+    let DeriveInput {
+        ident,
+        attrs,
+        generics,
+        ..
+    } = parse_macro_input!(input);
+
+    derive_versioned_as_dyn_size_bytes_impl(&ident, &attrs, &generics).into()
+}
+
+/// Derives [ic_stable_memory::AsHashableBytes] for a type that already implements [ic_stable_memory::AsFixedSizeBytes].
+///
+/// The fixed size encoding of the value is used as its hashable bytes.
+#[proc_macro_derive(AsHashableBytes)]
+pub fn derive_as_hashable_bytes(input: Tokens) -> Tokens {
+    let DeriveInput {
+        ident, generics, ..
+    } = parse_macro_input!(input);
+
+    derive_as_hashable_bytes_impl(&ident, &generics).into()
+}
+
+/// Derives [ic_stable_memory::AsHashTree] for a type that already implements [candid::CandidType].
+///
+/// The hash tree of the value is a single leaf, containing its candid encoding.
+#[proc_macro_derive(CandidAsHashTree)]
+pub fn derive_candid_as_hash_tree(input: Tokens) -> Tokens {
+    let DeriveInput {
+        ident, generics, ..
+    } = parse_macro_input!(input);
+
+    derive_candid_as_hash_tree_impl(&ident, &generics).into()
+}
+
+/// Declares the root state of a canister, generating functions to manage it.
+///
+/// Generates `init_state(state)`, `pre_upgrade_state()` and `post_upgrade_state()` functions, which
+/// should be called in `#[init]`, `#[pre_upgrade]` and `#[post_upgrade]` canister methods, and `state()`
+/// and `state_mut()` accessors. The state should implement [ic_stable_memory::StableType] and
+/// [ic_stable_memory::AsDynSizeBytes]. See [ic_stable_memory::utils::stable_state::StableState] for details.
+/// Only one root state can be declared in a module. Does not support generics.
+#[proc_macro_attribute]
+pub fn stable_state(args: Tokens, input: Tokens) -> Tokens {
+    if !args.is_empty() {
+        panic!("No arguments expected");
+    }
+
+    stable_state_impl(&parse_macro_input!(input)).into()
+}
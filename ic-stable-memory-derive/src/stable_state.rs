@@ -0,0 +1,57 @@
+use proc_macro2::{self, TokenStream};
+use quote::quote;
+use syn::DeriveInput;
+
+pub fn stable_state_impl(input: &DeriveInput) -> TokenStream {
+    if !input.generics.params.is_empty() {
+        panic!("Generics not supported");
+    }
+
+    let ident = &input.ident;
+    let vis = &input.vis;
+
+    quote! {
+        #input
+
+        fn __ic_stable_memory_state() -> &'static ic_stable_memory::utils::stable_state::StableState<#ident> {
+            thread_local! {
+                static STATE: &'static ic_stable_memory::utils::stable_state::StableState<#ident> =
+                    Box::leak(Box::new(ic_stable_memory::utils::stable_state::StableState::new(
+                        concat!(module_path!(), "::", stringify!(#ident))
+                    )));
+            }
+
+            STATE.with(|it| *it)
+        }
+
+        /// Initializes stable memory and sets the root state. Call it in `#[init]`.
+        #[inline]
+        #vis fn init_state(state: #ident) {
+            __ic_stable_memory_state().init(state)
+        }
+
+        /// Moves the root state to stable memory and persists the allocator. Call it in `#[pre_upgrade]`.
+        #[inline]
+        #vis fn pre_upgrade_state() {
+            __ic_stable_memory_state().pre_upgrade()
+        }
+
+        /// Retrieves the allocator and the root state from stable memory. Call it in `#[post_upgrade]`.
+        #[inline]
+        #vis fn post_upgrade_state() {
+            __ic_stable_memory_state().post_upgrade()
+        }
+
+        /// Borrows the root state
+        #[inline]
+        #vis fn state() -> std::cell::Ref<'static, #ident> {
+            __ic_stable_memory_state().get()
+        }
+
+        /// Mutably borrows the root state
+        #[inline]
+        #vis fn state_mut() -> std::cell::RefMut<'static, #ident> {
+            __ic_stable_memory_state().get_mut()
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::candid_version::{candid_decode, candid_path};
+use proc_macro2::{self, TokenStream};
+use quote::quote;
+use syn::parse::ParseStream;
+use syn::{Attribute, Generics, Ident, LitInt, Token, Type};
+
+pub fn derive_versioned_as_dyn_size_bytes_impl(
+    ident: &Ident,
+    attrs: &[Attribute],
+    generics: &Generics,
+) -> TokenStream {
+    if !generics.params.is_empty() {
+        panic!("Generics not supported");
+    }
+
+    let mut current_version = None;
+    let mut previous_versions = Vec::new();
+
+    for attr in attrs.iter().filter(|it| it.path.is_ident("version")) {
+        let (version, ty) = attr
+            .parse_args_with(parse_version_args)
+            .expect("Expected #[version(n)] or #[version(n, PreviousType)]");
+
+        match ty {
+            None => {
+                if current_version.replace(version).is_some() {
+                    panic!("Only one current #[version(n)] is allowed");
+                }
+            }
+            Some(ty) => previous_versions.push((version, ty)),
+        }
+    }
+
+    let current_version = current_version.expect("#[version(n)] attribute is required");
+
+    previous_versions.sort_by_key(|(version, _)| *version);
+    for (i, (version, _)) in previous_versions.iter().enumerate() {
+        if *version >= current_version {
+            panic!("Previous versions should be less than the current one");
+        }
+
+        if i > 0 && previous_versions[i - 1].0 == *version {
+            panic!("Version {} is declared twice", version);
+        }
+    }
+
+    // the i-th previous version has to be upgraded (previous_versions.len() - i) times
+    let previous_arms = previous_versions
+        .iter()
+        .enumerate()
+        .map(|(i, (version, ty))| {
+            let upgrades = (i..previous_versions.len()).map(|_| {
+                quote! {
+                    let it = ic_stable_memory::encoding::Upgrade::upgrade(it);
+                }
+            });
+
+            let decode = candid_decode(quote! { #ty });
+
+            quote! {
+                #version => {
+                    let it = #decode;
+                    #(#upgrades)*
+
+                    it
+                }
+            }
+        });
+
+    let candid = candid_path();
+    let decode = candid_decode(quote! { _ });
+
+    quote! {
+        impl ic_stable_memory::AsDynSizeBytes for #ident {
+            #[inline]
+            fn as_dyn_size_bytes(&self) -> Vec<u8> {
+                let mut buf = #current_version.to_le_bytes().to_vec();
+                buf.extend(#candid::encode_one(self).unwrap());
+
+                buf
+            }
+
+            fn from_dyn_size_bytes(arr: &[u8]) -> Self {
+                let mut version_buf = [0u8; 4];
+                version_buf.copy_from_slice(&arr[0..4]);
+
+                let version = u32::from_le_bytes(version_buf);
+                let arr = &arr[4..];
+
+                match version {
+                    #current_version => #decode,
+                    #(#previous_arms)*
+                    _ => panic!("Unknown version {} of {}", version, stringify!(#ident)),
+                }
+            }
+        }
+    }
+}
+
+fn parse_version_args(input: ParseStream) -> syn::Result<(u32, Option<Type>)> {
+    let version = input.parse::<LitInt>()?.base10_parse::<u32>()?;
+
+    if input.is_empty() {
+        return Ok((version, None));
+    }
+
+    input.parse::<Token![,]>()?;
+    let ty = input.parse::<Type>()?;
+
+    Ok((version, Some(ty)))
+}
@@ -1,3 +1,33 @@
+//! Benchmark harness, comparing stable collections with their `std` counterparts, enabled with
+//! `benches` feature.
+//!
+//! Each workload runs the same sequence of operations against a `std` collection and against the
+//! stable one, measuring the cost of every step. Inside a canister, the cost is the number of IC
+//! instructions (via [ic_cdk::api::performance_counter]), otherwise it is the wall time in
+//! nanoseconds. The results can be printed as a comparison table with [print_table].
+//!
+//! Workloads are generic over keys and values, so you can check how stable collections behave on
+//! your own data. Stable memory should be initialized before running them.
+//!
+//! # Example
+//! ```rust
+//! # use ic_stable_memory::stable_memory_init;
+//! use ic_stable_memory::benches::{hash_map_workload, print_table};
+//! # unsafe { ic_stable_memory::mem::clear(); }
+//! # stable_memory_init();
+//!
+//! let entries = (0..1000u64).map(|it| (it, [it; 4])).collect::<Vec<_>>();
+//! let measurements = hash_map_workload(&entries);
+//!
+//! print_table(&measurements);
+//! ```
+
+use crate::collections::{SBTreeMap, SHashMap, SVec};
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::StableType;
+use crate::utils::isoprint;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 mod btree_map;
@@ -8,7 +38,7 @@ mod hash_set;
 mod log;
 mod vec;
 
-#[ignore]
+#[doc(hidden)]
 pub fn now_milli() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -16,6 +46,261 @@ pub fn now_milli() -> u128 {
         .as_millis()
 }
 
+/// Units of [Measurement], depend on the target platform
+#[cfg(target_family = "wasm")]
+pub const UNITS: &str = "instructions";
+
+/// Units of [Measurement], depend on the target platform
+#[cfg(not(target_family = "wasm"))]
+pub const UNITS: &str = "ns";
+
+/// Returns the current value of the cost counter, see [UNITS]
+#[inline]
+pub fn counter() -> u64 {
+    crate::utils::cost_counter()
+}
+
+/// Returns the cost of executing the provided lambda, see [UNITS]
+#[inline]
+pub fn measure<F: FnOnce()>(f: F) -> u64 {
+    let before = counter();
+    f();
+
+    counter() - before
+}
+
+/// The cost of a single step of some workload, for both collections
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Measurement {
+    /// The name of the step, e.g. `"insert"`
+    pub name: String,
+    /// How many operations were made during this step
+    pub iterations: u64,
+    /// The cost of this step for the `std` collection
+    pub std_cost: u64,
+    /// The cost of this step for the stable collection
+    pub stable_cost: u64,
+}
+
+impl Measurement {
+    /// How many times the stable collection is slower than the `std` one
+    #[inline]
+    pub fn ratio(&self) -> f64 {
+        self.stable_cost as f64 / self.std_cost.max(1) as f64
+    }
+}
+
+/// Prints measurements as a table, using [isoprint]
+pub fn print_table(measurements: &[Measurement]) {
+    isoprint(&format!(
+        "{:<24} {:>12} {:>16} {:>16} {:>8}",
+        "step", "iterations", "std", "stable", "ratio"
+    ));
+
+    for it in measurements {
+        isoprint(&format!(
+            "{:<24} {:>12} {:>16} {:>16} {:>8.2}",
+            it.name,
+            it.iterations,
+            it.std_cost,
+            it.stable_cost,
+            it.ratio()
+        ));
+    }
+
+    isoprint(&format!("(costs are in {})", UNITS));
+}
+
+/// Compares [SVec] with [Vec]: pushes all the values, reads each of them by index, then pops them
+///
+/// # Panics
+/// Panics if the canister is out of stable memory.
+pub fn vec_workload<T: StableType + AsFixedSizeBytes + Clone>(values: &[T]) -> Vec<Measurement> {
+    let mut std_vec = Vec::new();
+    let mut stable_vec = SVec::<T>::new();
+
+    let n = values.len();
+    let mut result = Vec::new();
+
+    let std_cost = measure(|| {
+        for it in values {
+            std_vec.push(it.clone());
+        }
+    });
+    let stable_cost = measure(|| {
+        for it in values {
+            if stable_vec.push(it.clone()).is_err() {
+                panic!("Out of memory");
+            }
+        }
+    });
+    result.push(step("push", n, std_cost, stable_cost));
+
+    let std_cost = measure(|| {
+        for i in 0..n {
+            std_vec.get(i).unwrap();
+        }
+    });
+    let stable_cost = measure(|| {
+        for i in 0..n {
+            stable_vec.get(i as u64).unwrap();
+        }
+    });
+    result.push(step("get", n, std_cost, stable_cost));
+
+    let std_cost = measure(|| {
+        for _ in 0..n {
+            std_vec.pop().unwrap();
+        }
+    });
+    let stable_cost = measure(|| {
+        for _ in 0..n {
+            stable_vec.pop().unwrap();
+        }
+    });
+    result.push(step("pop", n, std_cost, stable_cost));
+
+    result
+}
+
+/// Compares [SHashMap] with [HashMap]: inserts all the entries, searches for each key, then removes
+/// them
+///
+/// # Panics
+/// Panics if the canister is out of stable memory.
+pub fn hash_map_workload<K, V>(entries: &[(K, V)]) -> Vec<Measurement>
+where
+    K: StableType + AsFixedSizeBytes + Hash + Eq + Clone,
+    V: StableType + AsFixedSizeBytes + Clone,
+{
+    map_workload::<K, V, HashMap<K, V>, SHashMap<K, V>>(entries)
+}
+
+/// Compares [SBTreeMap] with [BTreeMap]: inserts all the entries, searches for each key, then
+/// removes them
+///
+/// # Panics
+/// Panics if the canister is out of stable memory.
+pub fn btree_map_workload<K, V>(entries: &[(K, V)]) -> Vec<Measurement>
+where
+    K: StableType + AsFixedSizeBytes + Ord + Clone,
+    V: StableType + AsFixedSizeBytes + Clone,
+{
+    map_workload::<K, V, BTreeMap<K, V>, SBTreeMap<K, V>>(entries)
+}
+
+trait BenchMap<K, V>: Default {
+    fn bench_insert(&mut self, key: K, value: V);
+    fn bench_get(&self, key: &K);
+    fn bench_remove(&mut self, key: &K);
+}
+
+impl<K: Hash + Eq, V> BenchMap<K, V> for HashMap<K, V> {
+    fn bench_insert(&mut self, key: K, value: V) {
+        self.insert(key, value);
+    }
+
+    fn bench_get(&self, key: &K) {
+        assert!(self.get(key).is_some());
+    }
+
+    fn bench_remove(&mut self, key: &K) {
+        assert!(self.remove(key).is_some());
+    }
+}
+
+impl<K: Ord, V> BenchMap<K, V> for BTreeMap<K, V> {
+    fn bench_insert(&mut self, key: K, value: V) {
+        self.insert(key, value);
+    }
+
+    fn bench_get(&self, key: &K) {
+        assert!(self.get(key).is_some());
+    }
+
+    fn bench_remove(&mut self, key: &K) {
+        assert!(self.remove(key).is_some());
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> BenchMap<K, V>
+    for SHashMap<K, V>
+{
+    fn bench_insert(&mut self, key: K, value: V) {
+        if self.insert(key, value).is_err() {
+            panic!("Out of memory");
+        }
+    }
+
+    fn bench_get(&self, key: &K) {
+        assert!(self.get(key).is_some());
+    }
+
+    fn bench_remove(&mut self, key: &K) {
+        assert!(self.remove(key).is_some());
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> BenchMap<K, V>
+    for SBTreeMap<K, V>
+{
+    fn bench_insert(&mut self, key: K, value: V) {
+        if self.insert(key, value).is_err() {
+            panic!("Out of memory");
+        }
+    }
+
+    fn bench_get(&self, key: &K) {
+        assert!(self.get(key).is_some());
+    }
+
+    fn bench_remove(&mut self, key: &K) {
+        assert!(self.remove(key).is_some());
+    }
+}
+
+fn map_workload<K: Clone, V: Clone, A: BenchMap<K, V>, B: BenchMap<K, V>>(
+    entries: &[(K, V)],
+) -> Vec<Measurement> {
+    let mut std_map = A::default();
+    let mut stable_map = B::default();
+
+    let n = entries.len();
+    let mut result = Vec::new();
+
+    let std_cost = measure(|| {
+        for (k, v) in entries {
+            std_map.bench_insert(k.clone(), v.clone());
+        }
+    });
+    let stable_cost = measure(|| {
+        for (k, v) in entries {
+            stable_map.bench_insert(k.clone(), v.clone());
+        }
+    });
+    result.push(step("insert", n, std_cost, stable_cost));
+
+    let std_cost = measure(|| entries.iter().for_each(|(k, _)| std_map.bench_get(k)));
+    let stable_cost = measure(|| entries.iter().for_each(|(k, _)| stable_map.bench_get(k)));
+    result.push(step("get", n, std_cost, stable_cost));
+
+    let std_cost = measure(|| entries.iter().for_each(|(k, _)| std_map.bench_remove(k)));
+    let stable_cost = measure(|| entries.iter().for_each(|(k, _)| stable_map.bench_remove(k)));
+    result.push(step("remove", n, std_cost, stable_cost));
+
+    result
+}
+
+#[inline]
+fn step(name: &str, iterations: usize, std_cost: u64, stable_cost: u64) -> Measurement {
+    Measurement {
+        name: name.to_string(),
+        iterations: iterations as u64,
+        std_cost,
+        stable_cost,
+    }
+}
+
 #[macro_export]
 macro_rules! measure {
     ($name:literal, $iterations:expr, $it:block) => {
@@ -31,3 +316,30 @@ macro_rules! measure {
         );
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::benches::{btree_map_workload, hash_map_workload, print_table, vec_workload};
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+
+    #[test]
+    fn workloads_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        let entries = (0..1000u64).map(|it| (it, [it; 4])).collect::<Vec<_>>();
+
+        let mut measurements = vec_workload(&entries);
+        measurements.extend(hash_map_workload(&entries));
+        measurements.extend(btree_map_workload(&entries));
+
+        assert_eq!(measurements.len(), 9);
+        assert!(measurements.iter().all(|it| it.iterations == 1000));
+
+        print_table(&measurements);
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
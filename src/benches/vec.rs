@@ -57,7 +57,7 @@ mod vec_benchmark {
 
             measure!("Stable vec search", ITERATIONS, {
                 for i in 0..ITERATIONS {
-                    stable_vec.get(i).unwrap();
+                    stable_vec.get(i as u64).unwrap();
                 }
             });
 
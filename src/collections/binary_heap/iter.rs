@@ -0,0 +1,33 @@
+use crate::collections::binary_heap::SBinaryHeap;
+use crate::collections::vec::iter::size_hint;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::StableType;
+use std::iter::FusedIterator;
+
+pub struct SBinaryHeapIntoSortedIter<T: StableType + AsFixedSizeBytes + Ord> {
+    heap: SBinaryHeap<T>,
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> SBinaryHeapIntoSortedIter<T> {
+    pub(crate) fn new(heap: SBinaryHeap<T>) -> Self {
+        Self { heap }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> Iterator for SBinaryHeapIntoSortedIter<T> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.heap.pop()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        size_hint(self.heap.len())
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> ExactSizeIterator for SBinaryHeapIntoSortedIter<T> {}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> FusedIterator for SBinaryHeapIntoSortedIter<T> {}
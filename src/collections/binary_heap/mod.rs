@@ -0,0 +1,312 @@
+use crate::collections::binary_heap::iter::SBinaryHeapIntoSortedIter;
+use crate::collections::vec::growth::DoubleGrowth;
+use crate::collections::vec::iter::SVecIter;
+use crate::collections::vec::SVec;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use crate::OutOfMemory;
+use std::fmt::{Debug, Formatter};
+
+#[doc(hidden)]
+pub mod iter;
+
+/// Stable analog of [BinaryHeap](std::collections::BinaryHeap) - a priority queue
+///
+/// This is a max-heap on top of [SVec] - the greatest element is always on top. To get a min-heap,
+/// wrap elements into [Reverse](std::cmp::Reverse)-like wrapper with an inverted [Ord] implementation.
+///
+/// `T` has to implement [StableType], [AsFixedSizeBytes] and [Ord]. [SBinaryHeap] itself implements
+/// [StableType] and [AsFixedSizeBytes], so it can be nested inside other stable data structures.
+///
+/// Both [SBinaryHeap::push] and [SBinaryHeap::pop] are O(logN), but unlike an [SVec], they
+/// perform O(logN) swaps of elements in stable memory. [SBinaryHeap::peek] is O(1).
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SBinaryHeap;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut tasks = SBinaryHeap::<(u8, u64)>::new();
+///
+/// // (priority, task id)
+/// tasks.push((1, 100)).expect("Out of memory");
+/// tasks.push((5, 101)).expect("Out of memory");
+/// tasks.push((3, 102)).expect("Out of memory");
+///
+/// assert_eq!(*tasks.peek().unwrap(), (5, 101));
+///
+/// let ids: Vec<u64> = tasks.into_sorted_iter().map(|(_, id)| id).collect();
+/// assert_eq!(ids, vec![101, 102, 100]);
+/// ```
+pub struct SBinaryHeap<T: StableType + AsFixedSizeBytes + Ord> {
+    inner: SVec<T>,
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> SBinaryHeap<T> {
+    /// Creates a new [SBinaryHeap]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self { inner: SVec::new() }
+    }
+
+    /// Creates a [SBinaryHeap] of requested capacity
+    ///
+    /// See [SVec::new_with_capacity].
+    #[inline]
+    pub fn new_with_capacity(capacity: u64) -> Result<Self, OutOfMemory> {
+        Ok(Self {
+            inner: SVec::new_with_capacity(capacity)?,
+        })
+    }
+
+    /// Returns the length of this [SBinaryHeap]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    /// Returns [true] if the length of this [SBinaryHeap] is `0`
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns the capacity of this [SBinaryHeap]
+    #[inline]
+    pub fn capacity(&self) -> u64 {
+        self.inner.capacity()
+    }
+
+    /// Inserts a new element into this [SBinaryHeap]
+    ///
+    /// Will try to reallocate if `capacity == length`. If the canister is out of stable memory,
+    /// will return [Err] with the element that was about to get inserted.
+    pub fn push(&mut self, element: T) -> Result<(), T> {
+        self.inner.push(element)?;
+        self.sift_up(self.len() - 1);
+
+        Ok(())
+    }
+
+    /// Removes the greatest element from this [SBinaryHeap] and returns it
+    ///
+    /// If the [SBinaryHeap] is empty, returns [None].
+    pub fn pop(&mut self) -> Option<T> {
+        let len = self.len();
+
+        if len > 1 {
+            self.inner.swap(0, len - 1);
+        }
+
+        let it = self.inner.pop()?;
+
+        if self.len() > 1 {
+            self.sift_down(0);
+        }
+
+        Some(it)
+    }
+
+    /// Returns a [SRef] pointing to the greatest element of this [SBinaryHeap]
+    ///
+    /// If the [SBinaryHeap] is empty, returns [None].
+    #[inline]
+    pub fn peek(&self) -> Option<SRef<'_, T>> {
+        self.inner.get(0)
+    }
+
+    /// Removes all elements from this [SBinaryHeap]
+    ///
+    /// Does not reallocate or shrink the underlying memory block.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    /// Returns an iterator over elements of this [SBinaryHeap] in arbitrary order
+    #[inline]
+    pub fn iter(&self) -> SVecIter<'_, T, DoubleGrowth> {
+        self.inner.iter()
+    }
+
+    /// Returns a consuming iterator, which yields elements of this [SBinaryHeap] from the greatest
+    /// to the smallest
+    ///
+    /// Elements are popped from the heap one by one, while the iterator is consumed. Once the
+    /// iterator is dropped, all elements that were not consumed are stable-dropped.
+    #[inline]
+    pub fn into_sorted_iter(self) -> SBinaryHeapIntoSortedIter<T> {
+        SBinaryHeapIntoSortedIter::new(self)
+    }
+
+    fn sift_up(&mut self, mut idx: u64) {
+        while idx > 0 {
+            let parent_idx = (idx - 1) / 2;
+
+            if *self.inner.get(idx).unwrap() <= *self.inner.get(parent_idx).unwrap() {
+                break;
+            }
+
+            self.inner.swap(idx, parent_idx);
+            idx = parent_idx;
+        }
+    }
+
+    fn sift_down(&mut self, mut idx: u64) {
+        let len = self.len();
+
+        loop {
+            let left_idx = idx * 2 + 1;
+            if left_idx >= len {
+                break;
+            }
+
+            let right_idx = left_idx + 1;
+
+            let mut child_idx = left_idx;
+            if right_idx < len
+                && *self.inner.get(right_idx).unwrap() > *self.inner.get(left_idx).unwrap()
+            {
+                child_idx = right_idx;
+            }
+
+            if *self.inner.get(idx).unwrap() >= *self.inner.get(child_idx).unwrap() {
+                break;
+            }
+
+            self.inner.swap(idx, child_idx);
+            idx = child_idx;
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> Default for SBinaryHeap<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> AsFixedSizeBytes for SBinaryHeap<T> {
+    const SIZE: usize = SVec::<T>::SIZE;
+    type Buf = <SVec<T> as AsFixedSizeBytes>::Buf;
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.inner.as_fixed_size_bytes(buf);
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        let inner = SVec::<T>::from_fixed_size_bytes(arr);
+        Self { inner }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> StableType for SBinaryHeap<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.inner.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.inner.stable_drop_flag_off();
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord + Debug> Debug for SBinaryHeap<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::binary_heap::SBinaryHeap;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::seq::SliceRandom;
+    use rand::{thread_rng, Rng};
+    use std::collections::BinaryHeap;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+
+            let mut heap = SBinaryHeap::new();
+            let mut check = BinaryHeap::new();
+
+            assert!(heap.peek().is_none());
+            assert!(heap.pop().is_none());
+
+            for _ in 0..3000 {
+                if rng.gen_bool(0.6) {
+                    let it = rng.gen_range(0..1000u64);
+
+                    heap.push(SBox::new(it).unwrap()).unwrap();
+                    check.push(it);
+                } else {
+                    assert_eq!(heap.pop().map(|it| it.into_inner()), check.pop());
+                }
+
+                assert_eq!(heap.len(), check.len() as u64);
+                assert_eq!(heap.peek().map(|it| **it), check.peek().copied());
+            }
+
+            let sorted = heap
+                .into_sorted_iter()
+                .map(|it| it.into_inner())
+                .collect::<Vec<_>>();
+
+            let mut expected = check.into_sorted_vec();
+            expected.reverse();
+
+            assert_eq!(sorted, expected);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn partially_consumed_sorted_iter_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut items = (0..100u64).collect::<Vec<_>>();
+            items.shuffle(&mut thread_rng());
+
+            let mut heap = SBinaryHeap::default();
+            for it in items {
+                heap.push(SBox::new(it).unwrap()).unwrap();
+            }
+
+            let mut vec = SVec::new();
+            vec.push(heap).unwrap();
+            let heap = vec.pop().unwrap();
+
+            let top = heap
+                .into_sorted_iter()
+                .take(10)
+                .map(|it| it.into_inner())
+                .collect::<Vec<_>>();
+
+            assert_eq!(top, (90..100u64).rev().collect::<Vec<_>>());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
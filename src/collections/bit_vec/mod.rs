@@ -0,0 +1,456 @@
+use crate::collections::vec::SVec;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::StableType;
+use crate::OutOfMemory;
+use std::fmt::{Debug, Formatter};
+
+const WORD_BITS: u64 = u64::BITS as u64;
+
+/// Compact vector of booleans, one bit per element
+///
+/// Bits are packed into `u64` words, stored in an [SVec]. Compared to `SVec<bool>`, this collection
+/// uses 8 times less stable memory, while scans ([SBitVec::count_ones], [SBitVec::rank],
+/// [SBitVec::select]) and bulk bitwise operations ([SBitVec::and], [SBitVec::or], [SBitVec::xor],
+/// [SBitVec::not]) process a whole word of 64 bits per stable memory read.
+///
+/// [SBitVec::rank] and [SBitVec::select] are O(n / 64) - no auxiliary index is maintained.
+///
+/// [SBitVec] implements both [StableType] and [AsFixedSizeBytes] and can be nested inside other
+/// stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SBitVec;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut flags = SBitVec::new_with_len(1000).expect("Out of memory");
+///
+/// flags.set(10, true);
+/// flags.set(500, true);
+/// flags.set(999, true);
+///
+/// assert_eq!(flags.count_ones(), 3);
+/// assert_eq!(flags.rank(500), 1);
+/// assert_eq!(flags.select(1), Some(500));
+/// ```
+pub struct SBitVec {
+    words: SVec<u64>,
+    len: u64,
+}
+
+impl SBitVec {
+    /// Creates a new empty [SBitVec]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            words: SVec::new(),
+            len: 0,
+        }
+    }
+
+    /// Creates a new [SBitVec] of the requested length, with all bits set to `false`
+    ///
+    /// Returns [OutOfMemory] if there is not enough stable memory.
+    pub fn new_with_len(len: u64) -> Result<Self, OutOfMemory> {
+        let words_count = len.div_ceil(WORD_BITS);
+
+        let mut words = SVec::new_with_capacity(words_count)?;
+        for _ in 0..words_count {
+            words.push(0).map_err(|_| OutOfMemory)?;
+        }
+
+        Ok(Self { words, len })
+    }
+
+    /// Returns the number of bits in this [SBitVec]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if there are no bits in this [SBitVec]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends a bit to the end of this [SBitVec]
+    ///
+    /// Allocates a new word every 64 bits. If the canister is out of stable memory, returns
+    /// [OutOfMemory] leaving the [SBitVec] unchanged.
+    pub fn push(&mut self, bit: bool) -> Result<(), OutOfMemory> {
+        if self.len.is_multiple_of(WORD_BITS) {
+            self.words.push(bit as u64).map_err(|_| OutOfMemory)?;
+        } else if bit {
+            let idx = self.words.len() - 1;
+            *self.words.get_mut(idx).unwrap() |= Self::mask(self.len);
+        }
+
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Removes the last bit from this [SBitVec] and returns it
+    ///
+    /// If the [SBitVec] is empty, returns [None].
+    pub fn pop(&mut self) -> Option<bool> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let idx = self.len - 1;
+        let bit = self.get(idx).unwrap();
+
+        if idx.is_multiple_of(WORD_BITS) {
+            self.words.pop();
+        } else if bit {
+            *self.words.get_mut(Self::word_idx(idx)).unwrap() &= !Self::mask(idx);
+        }
+
+        self.len -= 1;
+
+        Some(bit)
+    }
+
+    /// Returns the bit at the requested index
+    ///
+    /// If the index is out of bounds, returns [None].
+    #[inline]
+    pub fn get(&self, idx: u64) -> Option<bool> {
+        if idx >= self.len {
+            return None;
+        }
+
+        let word = *self.words.get(Self::word_idx(idx)).unwrap();
+
+        Some(word & Self::mask(idx) != 0)
+    }
+
+    /// Sets the bit at the requested index
+    ///
+    /// # Panics
+    /// Panics if the index is out of bounds.
+    pub fn set(&mut self, idx: u64, bit: bool) {
+        assert!(idx < self.len, "out of bounds");
+
+        let mut word = self.words.get_mut(Self::word_idx(idx)).unwrap();
+
+        if bit {
+            *word |= Self::mask(idx);
+        } else {
+            *word &= !Self::mask(idx);
+        }
+    }
+
+    /// Returns the number of bits set to `true`
+    pub fn count_ones(&self) -> u64 {
+        self.words.iter().map(|it| it.count_ones() as u64).sum()
+    }
+
+    /// Returns the number of bits set to `false`
+    #[inline]
+    pub fn count_zeros(&self) -> u64 {
+        self.len - self.count_ones()
+    }
+
+    /// Returns the number of bits set to `true` in `[0, idx)` range
+    ///
+    /// # Panics
+    /// Panics if `idx` is greater than the length of this [SBitVec].
+    pub fn rank(&self, idx: u64) -> u64 {
+        assert!(idx <= self.len, "out of bounds");
+
+        let full_words = Self::word_idx(idx);
+        let mut result = (0..full_words)
+            .map(|it| self.words.get(it).unwrap().count_ones() as u64)
+            .sum();
+
+        if !idx.is_multiple_of(WORD_BITS) {
+            let word = *self.words.get(full_words).unwrap();
+            result += (word & (Self::mask(idx) - 1)).count_ones() as u64;
+        }
+
+        result
+    }
+
+    /// Returns the index of the `n`-th (starting from `0`) bit set to `true`
+    ///
+    /// If there are not enough bits set, returns [None].
+    pub fn select(&self, n: u64) -> Option<u64> {
+        let mut remaining = n;
+
+        for (word_idx, word) in (0u64..).zip(self.words.iter()) {
+            let mut word = *word;
+            let ones = word.count_ones() as u64;
+
+            if remaining >= ones {
+                remaining -= ones;
+                continue;
+            }
+
+            // clearing lowest set bits, until the required one is the lowest
+            for _ in 0..remaining {
+                word &= word - 1;
+            }
+
+            return Some(word_idx * WORD_BITS + word.trailing_zeros() as u64);
+        }
+
+        None
+    }
+
+    /// Sets each bit of this [SBitVec] to `self[i] & other[i]`
+    ///
+    /// # Panics
+    /// Panics if lengths of bit vectors are different.
+    #[inline]
+    pub fn and(&mut self, other: &SBitVec) {
+        self.zip_words(other, |a, b| a & b);
+    }
+
+    /// Sets each bit of this [SBitVec] to `self[i] | other[i]`
+    ///
+    /// # Panics
+    /// Panics if lengths of bit vectors are different.
+    #[inline]
+    pub fn or(&mut self, other: &SBitVec) {
+        self.zip_words(other, |a, b| a | b);
+    }
+
+    /// Sets each bit of this [SBitVec] to `self[i] ^ other[i]`
+    ///
+    /// # Panics
+    /// Panics if lengths of bit vectors are different.
+    #[inline]
+    pub fn xor(&mut self, other: &SBitVec) {
+        self.zip_words(other, |a, b| a ^ b);
+    }
+
+    /// Inverts each bit of this [SBitVec]
+    pub fn not(&mut self) {
+        for idx in 0..self.words.len() {
+            let word = *self.words.get(idx).unwrap();
+            self.words.replace(idx, !word);
+        }
+
+        self.clear_tail();
+    }
+
+    /// Removes all bits from this [SBitVec]
+    ///
+    /// Does not reallocate or shrink the underlying memory block.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.words.clear();
+        self.len = 0;
+    }
+
+    fn zip_words<F: Fn(u64, u64) -> u64>(&mut self, other: &SBitVec, op: F) {
+        assert_eq!(self.len, other.len, "lengths are different");
+
+        for (idx, b) in (0u64..).zip(other.words.iter()) {
+            let a = *self.words.get(idx).unwrap();
+            self.words.replace(idx, op(a, *b));
+        }
+    }
+
+    // unused bits of the last word are always kept unset, so counting doesn't need masking
+    fn clear_tail(&mut self) {
+        if self.len.is_multiple_of(WORD_BITS) {
+            return;
+        }
+
+        let idx = self.words.len() - 1;
+        *self.words.get_mut(idx).unwrap() &= Self::mask(self.len) - 1;
+    }
+
+    #[inline]
+    fn word_idx(idx: u64) -> u64 {
+        idx / WORD_BITS
+    }
+
+    #[inline]
+    fn mask(idx: u64) -> u64 {
+        1 << (idx % WORD_BITS)
+    }
+}
+
+impl Default for SBitVec {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsFixedSizeBytes for SBitVec {
+    const SIZE: usize = SVec::<u64>::SIZE + u64::SIZE;
+    type Buf = [u8; SVec::<u64>::SIZE + u64::SIZE];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.words
+            .as_fixed_size_bytes(&mut buf[0..SVec::<u64>::SIZE]);
+        self.len
+            .as_fixed_size_bytes(&mut buf[SVec::<u64>::SIZE..Self::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let words = SVec::<u64>::from_fixed_size_bytes(&buf[0..SVec::<u64>::SIZE]);
+        let len = u64::from_fixed_size_bytes(&buf[SVec::<u64>::SIZE..Self::SIZE]);
+
+        Self { words, len }
+    }
+}
+
+impl StableType for SBitVec {
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.words.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.words.stable_drop_flag_off();
+    }
+}
+
+impl Debug for SBitVec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[")?;
+        for idx in 0..self.len {
+            f.write_str(if self.get(idx).unwrap() { "1" } else { "0" })?;
+        }
+        f.write_str("]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::bit_vec::SBitVec;
+    use crate::collections::SVec;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut bits = SBitVec::new();
+            let mut check = Vec::new();
+
+            assert!(bits.pop().is_none());
+            assert!(bits.get(0).is_none());
+            assert_eq!(bits.select(0), None);
+
+            for _ in 0..5000 {
+                match rng.gen_range(0..10) {
+                    0..=4 => {
+                        let bit = rng.gen_bool(0.3);
+                        bits.push(bit).unwrap();
+                        check.push(bit);
+                    }
+                    5 => {
+                        assert_eq!(bits.pop(), check.pop());
+                    }
+                    _ => {
+                        if !check.is_empty() {
+                            let idx = rng.gen_range(0..check.len());
+                            let bit = rng.gen_bool(0.5);
+
+                            bits.set(idx as u64, bit);
+                            check[idx] = bit;
+                        }
+                    }
+                }
+
+                assert_eq!(bits.len(), check.len() as u64);
+            }
+
+            let ones = check.iter().filter(|it| **it).count() as u64;
+            assert_eq!(bits.count_ones(), ones);
+            assert_eq!(bits.count_zeros(), check.len() as u64 - ones);
+
+            let mut rank = 0;
+            let mut n = 0;
+            for (idx, bit) in check.iter().enumerate() {
+                assert_eq!(bits.get(idx as u64), Some(*bit));
+                assert_eq!(bits.rank(idx as u64), rank);
+
+                if *bit {
+                    assert_eq!(bits.select(n), Some(idx as u64));
+
+                    rank += 1;
+                    n += 1;
+                }
+            }
+
+            assert_eq!(bits.rank(check.len() as u64), ones);
+            assert_eq!(bits.select(ones), None);
+
+            let mut vec = SVec::new();
+            vec.push(bits).unwrap();
+
+            let mut bits = vec.pop().unwrap();
+            bits.clear();
+            assert!(bits.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn bitwise_ops_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let len = 1000;
+
+            let a_check = (0..len).map(|_| rng.gen_bool(0.5)).collect::<Vec<_>>();
+            let b_check = (0..len).map(|_| rng.gen_bool(0.5)).collect::<Vec<_>>();
+
+            let mut a = SBitVec::new_with_len(len as u64).unwrap();
+            let mut b = SBitVec::new_with_len(len as u64).unwrap();
+            assert_eq!(a.count_ones(), 0);
+
+            for i in 0..len {
+                a.set(i as u64, a_check[i]);
+                b.set(i as u64, b_check[i]);
+            }
+
+            let check = |bits: &SBitVec, f: &dyn Fn(bool, bool) -> bool| {
+                for i in 0..len {
+                    assert_eq!(bits.get(i as u64).unwrap(), f(a_check[i], b_check[i]));
+                }
+            };
+
+            a.and(&b);
+            check(&a, &|x, y| x & y);
+
+            a.or(&b);
+            check(&a, &|x, y| (x & y) | y);
+
+            a.xor(&b);
+            check(&a, &|x, y| ((x & y) | y) ^ y);
+            assert_eq!(a.count_ones(), 0);
+
+            a.not();
+            assert_eq!(a.count_ones(), len as u64);
+
+            a.push(false).unwrap();
+            assert_eq!(a.count_ones(), len as u64);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::s_slice::{SSlice, MAX_BLOCK_SIZE};
+use crate::mem::StablePtr;
+use crate::primitive::StableType;
+use crate::utils::hasher::StableHasher;
+use crate::{allocate, deallocate, OutOfMemory};
+use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
+
+// mixed into the second hash, so it is independent from the first one
+const SECOND_HASH_SALT: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Probabilistic set, answering "definitely not seen" or "maybe seen"
+///
+/// A Bloom filter is a fixed-size bit array in stable memory. Each inserted item sets
+/// `num_hashes` bits, chosen by hashing the item. [SBloomFilter::may_contain] checks whether all
+/// these bits are set - if at least one of them is not, the item was never inserted. False
+/// positives are possible, false negatives are not. Items can't be removed.
+///
+/// Useful to cheaply pre-filter lookups into much bigger collections (e.g. "have we seen this
+/// transaction hash"), since both operations only touch `num_hashes` words of stable memory.
+///
+/// The underlying memory block is allocated once, at construction, and is never reallocated.
+/// Items are not stored, so any [Hash] type can be used. Hashing is done with
+/// [zwohash](https://github.com/jix/zwohash) (its 64-bit version with `wasm64` feature enabled),
+/// which is deterministic, so the filter stays valid across canister upgrades.
+///
+/// [SBloomFilter] implements both [StableType] and [AsFixedSizeBytes] and can be nested inside
+/// other stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SBloomFilter;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut seen_txs = SBloomFilter::with_false_positive_rate(10_000, 0.01).expect("Out of memory");
+///
+/// seen_txs.insert(&[1u8; 32]);
+///
+/// assert!(seen_txs.may_contain(&[1u8; 32]));
+/// assert!(!seen_txs.may_contain(&[2u8; 32]));
+/// ```
+pub struct SBloomFilter {
+    ptr: StablePtr,
+    num_bits: u64,
+    num_hashes: u32,
+    stable_drop_flag: bool,
+}
+
+impl SBloomFilter {
+    /// Creates a new [SBloomFilter] with the requested number of bits and hash functions
+    ///
+    /// Allocates a zeroed memory block for all bits at once, returning [OutOfMemory] if there is
+    /// not enough stable memory.
+    ///
+    /// # Panics
+    /// Panics if `num_bits` or `num_hashes` is `0`, or if `num_bits` is greater than
+    /// [SBloomFilter::max_num_bits].
+    pub fn new(num_bits: u64, num_hashes: u32) -> Result<Self, OutOfMemory> {
+        assert!(num_bits > 0, "num_bits must be non-zero");
+        assert!(num_hashes > 0, "num_hashes must be non-zero");
+        assert!(num_bits <= Self::max_num_bits());
+
+        let ptr = unsafe { allocate(Self::words_count(num_bits) * u64::SIZE as u64)?.as_ptr() };
+
+        let it = Self {
+            ptr,
+            num_bits,
+            num_hashes,
+            stable_drop_flag: true,
+        };
+        it.zero_bits();
+
+        Ok(it)
+    }
+
+    /// Creates a new [SBloomFilter], optimally sized to hold `expected_items` with the requested
+    /// probability of false positives
+    ///
+    /// See [SBloomFilter::new].
+    ///
+    /// # Panics
+    /// Panics if `false_positive_rate` is not in `(0, 1)` range, or if the resulting filter is
+    /// bigger than [SBloomFilter::max_num_bits].
+    pub fn with_false_positive_rate(
+        expected_items: u64,
+        false_positive_rate: f64,
+    ) -> Result<Self, OutOfMemory> {
+        assert!(
+            false_positive_rate > 0.0 && false_positive_rate < 1.0,
+            "false_positive_rate must be in (0, 1)"
+        );
+
+        let items = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+
+        let num_bits = (-items * false_positive_rate.ln() / (ln2 * ln2)).ceil() as u64;
+        let num_hashes = ((num_bits as f64 / items) * ln2).round() as u32;
+
+        Self::new(num_bits.max(1), num_hashes.max(1))
+    }
+
+    /// Returns the size of the bit array of this [SBloomFilter]
+    #[inline]
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    /// Returns the number of bits set per inserted item
+    #[inline]
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    /// Returns the maximum possible size of the bit array
+    #[inline]
+    pub const fn max_num_bits() -> u64 {
+        (MAX_BLOCK_SIZE / u64::SIZE as u64) * u64::BITS as u64
+    }
+
+    /// Adds an item to this [SBloomFilter]
+    ///
+    /// Never allocates stable memory.
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        let (h1, h2) = Self::hashes(item);
+
+        for i in 0..self.num_hashes {
+            let bit = self.bit_idx(h1, h2, i);
+            let word_ptr = self.word_ptr(bit);
+
+            let word = unsafe { crate::mem::read_fixed_for_reference::<u64>(word_ptr) };
+            let mut new_word = word | (1 << (bit % u64::BITS as u64));
+
+            if new_word != word {
+                unsafe { crate::mem::write_fixed(word_ptr, &mut new_word) };
+            }
+        }
+    }
+
+    /// Returns [false] if the item was definitely never inserted into this [SBloomFilter], and
+    /// [true] if it may have been inserted
+    pub fn may_contain<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        let (h1, h2) = Self::hashes(item);
+
+        (0..self.num_hashes).all(|i| {
+            let bit = self.bit_idx(h1, h2, i);
+            let word = unsafe { crate::mem::read_fixed_for_reference::<u64>(self.word_ptr(bit)) };
+
+            word & (1 << (bit % u64::BITS as u64)) != 0
+        })
+    }
+
+    /// Forgets all inserted items
+    ///
+    /// Does not reallocate or shrink the underlying memory block.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.zero_bits();
+    }
+
+    fn zero_bits(&self) {
+        let total = Self::words_count(self.num_bits) * u64::SIZE as u64;
+
+        unsafe { crate::mem::write_zeroes(SSlice::_offset(self.ptr, 0), total) };
+    }
+
+    fn hashes<T: Hash + ?Sized>(item: &T) -> (u64, u64) {
+        let mut hasher = StableHasher::default();
+        item.hash(&mut hasher);
+        let h1 = hasher.finish();
+
+        hasher.write_u64(SECOND_HASH_SALT);
+        let h2 = hasher.finish();
+
+        // an odd step never degenerates into hitting the same bit over and over
+        (h1, h2 | 1)
+    }
+
+    // double hashing: i-th hash is h1 + i * h2
+    #[inline]
+    fn bit_idx(&self, h1: u64, h2: u64, i: u32) -> u64 {
+        h1.wrapping_add((i as u64).wrapping_mul(h2)) % self.num_bits
+    }
+
+    #[inline]
+    fn word_ptr(&self, bit: u64) -> StablePtr {
+        SSlice::_offset(self.ptr, (bit / u64::BITS as u64) * u64::SIZE as u64)
+    }
+
+    #[inline]
+    fn words_count(num_bits: u64) -> u64 {
+        num_bits.div_ceil(u64::BITS as u64)
+    }
+}
+
+impl AsFixedSizeBytes for SBloomFilter {
+    const SIZE: usize = u64::SIZE * 2 + u32::SIZE;
+    type Buf = [u8; u64::SIZE * 2 + u32::SIZE];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+        self.num_bits
+            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE * 2)]);
+        self.num_hashes
+            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 2)..Self::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let ptr = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
+        let num_bits = u64::from_fixed_size_bytes(&buf[u64::SIZE..(u64::SIZE * 2)]);
+        let num_hashes = u32::from_fixed_size_bytes(&buf[(u64::SIZE * 2)..Self::SIZE]);
+
+        Self {
+            ptr,
+            num_bits,
+            num_hashes,
+            stable_drop_flag: false,
+        }
+    }
+}
+
+impl StableType for SBloomFilter {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    unsafe fn stable_drop(&mut self) {
+        let slice = SSlice::from_ptr(self.ptr).unwrap();
+
+        deallocate(slice);
+    }
+}
+
+impl Drop for SBloomFilter {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+impl Debug for SBloomFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SBloomFilter")
+            .field("num_bits", &self.num_bits)
+            .field("num_hashes", &self.num_hashes)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::bloom_filter::SBloomFilter;
+    use crate::collections::SVec;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut filter = SBloomFilter::with_false_positive_rate(1000, 0.01).unwrap();
+            assert!(filter.num_bits() > 1000 * 8);
+            assert_eq!(filter.num_hashes(), 7);
+
+            assert!(!filter.may_contain(&0u64));
+
+            for i in 0..1000u64 {
+                filter.insert(&i);
+            }
+
+            for i in 0..1000u64 {
+                assert!(filter.may_contain(&i));
+            }
+
+            let false_positives = (1000..11000u64).filter(|i| filter.may_contain(i)).count();
+            assert!(
+                false_positives < 300,
+                "too many false positives: {}",
+                false_positives
+            );
+
+            let mut vec = SVec::new();
+            vec.push(filter).unwrap();
+
+            let mut filter = vec.pop().unwrap();
+            assert!(filter.may_contain(&10u64));
+
+            filter.clear();
+            assert!((0..1000u64).all(|i| !filter.may_contain(&i)));
+
+            filter.insert("some string");
+            filter.insert(b"some bytes".as_slice());
+
+            assert!(filter.may_contain("some string"));
+            assert!(filter.may_contain(b"some bytes".as_slice()));
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::collections::btree_map::iter::SBTreeMapIter;
+use crate::collections::btree_map::SBTreeMap;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use candid::{CandidType, Deserialize};
+
+/// A position inside an [SBTreeMap], which can be persisted between messages
+///
+/// The cursor stores the encoded key of the last visited entry, not a pointer to some node, so it
+/// stays valid, no matter how the map is modified between messages. Iteration resumes from the
+/// first key, which is greater than the stored one - entries inserted before that key in the
+/// meantime are skipped, entries inserted after it are visited.
+///
+/// Since the key is stored by value, keys which own stable memory (like `SBox`) should not be used
+/// with cursors - the key may be removed and its memory released before the cursor is used again.
+///
+/// See [SBTreeMap::iter_from_cursor].
+#[derive(CandidType, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct SBTreeMapCursor {
+    last_key: Option<Vec<u8>>,
+}
+
+impl SBTreeMapCursor {
+    /// Creates a cursor, pointing to the very beginning of a map
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a cursor, pointing to the first entry after the provided key
+    #[inline]
+    pub fn after<K: AsFixedSizeBytes>(key: &K) -> Self {
+        let mut buf = vec![0u8; K::SIZE];
+        key.as_fixed_size_bytes(&mut buf);
+
+        Self {
+            last_key: Some(buf),
+        }
+    }
+
+    /// Returns [true] if this cursor points to the very beginning of a map
+    #[inline]
+    pub fn is_start(&self) -> bool {
+        self.last_key.is_none()
+    }
+
+    fn decode_key<K: AsFixedSizeBytes>(&self) -> Option<K> {
+        self.last_key.as_ref().map(|it| {
+            assert_eq!(
+                it.len(),
+                K::SIZE,
+                "The cursor belongs to a map of other type"
+            );
+
+            K::from_fixed_size_bytes(it)
+        })
+    }
+}
+
+/// Iterator over [SBTreeMap] entries, returned by [SBTreeMap::iter_from_cursor]
+///
+/// Keeps track of the last returned entry, see [SBTreeMapCursorIter::cursor].
+pub struct SBTreeMapCursorIter<'a, K, V> {
+    inner: SBTreeMapIter<'a, K, V>,
+    skip: Option<K>,
+    cursor: SBTreeMapCursor,
+}
+
+impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes>
+    SBTreeMapCursorIter<'a, K, V>
+{
+    pub(crate) fn new(map: &'a SBTreeMap<K, V>, cursor: &SBTreeMapCursor) -> Self {
+        let skip = cursor.decode_key::<K>();
+        let inner = match &skip {
+            Some(key) => map.iter_from(key),
+            None => map.iter(),
+        };
+
+        Self {
+            inner,
+            skip,
+            cursor: cursor.clone(),
+        }
+    }
+
+    /// Returns a cursor, pointing to the first entry, which was not yet returned by this iterator
+    #[inline]
+    pub fn cursor(&self) -> SBTreeMapCursor {
+        self.cursor.clone()
+    }
+}
+
+impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Iterator
+    for SBTreeMapCursorIter<'a, K, V>
+{
+    type Item = (SRef<'a, K>, SRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next()?;
+
+        // the key, the cursor points after, may still be in the map
+        let (k, v) = match self.skip.take() {
+            Some(key) if *k == key => self.inner.next()?,
+            _ => (k, v),
+        };
+
+        let buf = self.cursor.last_key.get_or_insert_with(Vec::new);
+        buf.resize(K::SIZE, 0);
+        k.as_fixed_size_bytes(buf);
+
+        Some((k, v))
+    }
+}
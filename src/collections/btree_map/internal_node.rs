@@ -87,10 +87,18 @@ impl<K: StableType + AsFixedSizeBytes + Ord> InternalBTreeNode<K> {
         deallocate(slice);
     }
 
+    #[inline]
     pub fn binary_search<Q>(&self, k: &Q, len: usize) -> Result<usize, usize>
     where
         K: Borrow<Q>,
         Q: Ord + ?Sized,
+    {
+        self.binary_search_by(|key| key.borrow().cmp(k), len)
+    }
+
+    pub fn binary_search_by<F>(&self, mut f: F, len: usize) -> Result<usize, usize>
+    where
+        F: FnMut(&K) -> Ordering,
     {
         let mut min = 0;
         let mut max = len;
@@ -101,7 +109,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord> InternalBTreeNode<K> {
 
             let key: K = unsafe { crate::mem::read_fixed_for_reference(ptr) };
 
-            match key.borrow().cmp(k) {
+            match f(&key) {
                 Ordering::Equal => return Ok(mid),
                 // actually LESS
                 Ordering::Greater => {
@@ -1,8 +1,9 @@
 use crate::collections::btree_map::leaf_node::LeafBTreeNode;
-use crate::collections::btree_map::{BTreeNode, IBTreeNode, SBTreeMap};
+use crate::collections::btree_map::{BTreeNode, CompositeKey, IBTreeNode, SBTreeMap};
 use crate::encoding::AsFixedSizeBytes;
 use crate::primitive::s_ref::SRef;
 use crate::primitive::StableType;
+use std::collections::VecDeque;
 
 pub struct SBTreeMapIter<'a, K, V> {
     root: &'a Option<BTreeNode<K, V>>,
@@ -23,6 +24,22 @@ impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeByte
             node_len: 0,
         }
     }
+
+    #[inline]
+    pub(crate) fn new_from(
+        map: &'a SBTreeMap<K, V>,
+        node: LeafBTreeNode<K, V>,
+        node_idx: usize,
+    ) -> Self {
+        let node_len = node.read_len();
+
+        Self {
+            root: &map.root,
+            node: Some(node),
+            node_idx,
+            node_len,
+        }
+    }
 }
 
 impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Iterator
@@ -138,3 +155,159 @@ impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeByte
         }
     }
 }
+
+pub struct SBTreeMapPrefixIter<'a, K: CompositeKey, V> {
+    inner: SBTreeMapIter<'a, K, V>,
+    prefix: &'a K::Prefix,
+    finished: bool,
+}
+
+impl<'a, K: CompositeKey, V> SBTreeMapPrefixIter<'a, K, V> {
+    #[inline]
+    pub(crate) fn new(inner: SBTreeMapIter<'a, K, V>, prefix: &'a K::Prefix) -> Self {
+        Self {
+            inner,
+            prefix,
+            finished: false,
+        }
+    }
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Ord + CompositeKey,
+        V: StableType + AsFixedSizeBytes,
+    > Iterator for SBTreeMapPrefixIter<'a, K, V>
+{
+    type Item = (SRef<'a, K>, SRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let (k, v) = self.inner.next()?;
+
+        if k.prefix() != self.prefix {
+            self.finished = true;
+
+            return None;
+        }
+
+        Some((k, v))
+    }
+}
+
+pub struct SBTreeMapIntoIter<
+    K: StableType + AsFixedSizeBytes + Ord,
+    V: StableType + AsFixedSizeBytes,
+> {
+    node: Option<LeafBTreeNode<K, V>>,
+    node_idx: usize,
+    node_len: usize,
+    remaining: u64,
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes>
+    SBTreeMapIntoIter<K, V>
+{
+    pub(crate) fn new(mut map: SBTreeMap<K, V>) -> Self {
+        let remaining = map.len;
+
+        // the map won't release anything by itself after this
+        map.len = 0;
+        let root = map.root.take();
+
+        let node = root.map(|root| {
+            let mut leftmost = unsafe { root.copy() };
+            let leftmost = loop {
+                match leftmost {
+                    BTreeNode::Internal(i) => {
+                        let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(0));
+                        leftmost = BTreeNode::<K, V>::from_ptr(child_ptr);
+                    }
+                    BTreeNode::Leaf(l) => break l,
+                }
+            };
+
+            // internal nodes only hold copies of keys, so they can be released right away -
+            // leaves are released one by one, while the iterator is consumed
+            let mut internal_nodes = VecDeque::new();
+            if let BTreeNode::Internal(i) = root {
+                internal_nodes.push_back(i);
+            }
+
+            while let Some(internal) = internal_nodes.pop_front() {
+                for j in 0..(internal.read_len() + 1) {
+                    let child_ptr = u64::from_fixed_size_bytes(&internal.read_child_ptr_buf(j));
+
+                    if let BTreeNode::Internal(i) = BTreeNode::<K, V>::from_ptr(child_ptr) {
+                        internal_nodes.push_back(i);
+                    }
+                }
+
+                internal.destroy();
+            }
+
+            leftmost
+        });
+
+        let node_len = node.as_ref().map(|it| it.read_len()).unwrap_or_default();
+
+        Self {
+            node,
+            node_idx: 0,
+            node_len,
+            remaining,
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Iterator
+    for SBTreeMapIntoIter<K, V>
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node = self.node.as_mut()?;
+
+            if self.node_idx < self.node_len {
+                let k = node.read_and_disown_key(self.node_idx);
+                let v = node.read_and_disown_value(self.node_idx);
+
+                self.node_idx += 1;
+                self.remaining -= 1;
+
+                return Some((k, v));
+            }
+
+            let ptr = u64::from_fixed_size_bytes(&node.read_next_ptr_buf());
+            unsafe { self.node.take().unwrap_unchecked() }.destroy();
+
+            if ptr == 0 {
+                return None;
+            }
+
+            let new_node = unsafe { LeafBTreeNode::<K, V>::from_ptr(ptr) };
+
+            self.node_len = new_node.read_len();
+            self.node_idx = 0;
+            self.node = Some(new_node);
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Drop
+    for SBTreeMapIntoIter<K, V>
+{
+    fn drop(&mut self) {
+        // stable-dropping entries that were not consumed and releasing the rest of leaves
+        for _ in self.by_ref() {}
+    }
+}
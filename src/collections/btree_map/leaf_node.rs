@@ -72,10 +72,18 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         deallocate(slice);
     }
 
+    #[inline]
     pub fn binary_search<Q>(&self, k: &Q, len: usize) -> Result<usize, usize>
     where
         K: Borrow<Q>,
         Q: Ord + ?Sized,
+    {
+        self.binary_search_by(|key| key.borrow().cmp(k), len)
+    }
+
+    pub fn binary_search_by<F>(&self, mut f: F, len: usize) -> Result<usize, usize>
+    where
+        F: FnMut(&K) -> Ordering,
     {
         if len == 0 {
             return Err(0);
@@ -89,7 +97,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
             let ptr = SSlice::_offset(self.ptr, KEYS_OFFSET + (mid * K::SIZE) as u64);
             let key: K = unsafe { crate::mem::read_fixed_for_reference(ptr) };
 
-            match key.borrow().cmp(k) {
+            match f(&key) {
                 Ordering::Equal => return Ok(mid),
                 // actually LESS
                 Ordering::Greater => {
@@ -132,16 +140,14 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         if let Some((k, v)) = left_insert_last_element {
             parent.write_key_buf(parent_idx, k);
 
-            self.insert_key_buf(0, k, self_len, buf);
-            self.insert_value_buf(0, v, self_len, buf);
+            self.insert_entry_buf(0, k, v, self_len, buf);
         } else {
             let replace_key = left_sibling.read_key_buf(left_sibling_len - 1);
             let replace_value = left_sibling.read_value_buf(left_sibling_len - 1);
 
             parent.write_key_buf(parent_idx, &replace_key);
 
-            self.insert_key_buf(0, &replace_key, self_len, buf);
-            self.insert_value_buf(0, &replace_value, self_len, buf);
+            self.insert_entry_buf(0, &replace_key, &replace_value, self_len, buf);
         }
     }
 
@@ -164,8 +170,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
 
             parent.write_key_buf(parent_idx, k);
         } else {
-            right_sibling.remove_key_buf(0, right_sibling_len, buf);
-            right_sibling.remove_value_buf(0, right_sibling_len, buf);
+            right_sibling.remove_entry_buf(0, right_sibling_len, buf);
 
             parent.write_key_buf(parent_idx, &right_sibling.read_key_buf(0));
         };
@@ -185,11 +190,8 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
 
         let min_idx = if right_biased { MIN_LEN_AFTER_SPLIT } else { B };
 
-        self.read_many_keys_to_buf(min_idx, CAPACITY - min_idx, buf);
-        right.write_many_keys_from_buf(0, buf);
-
-        self.read_many_values_to_buf(min_idx, CAPACITY - min_idx, buf);
-        right.write_many_values_from_buf(0, buf);
+        self.read_many_entries_to_buf(min_idx, CAPACITY - min_idx, buf);
+        right.write_many_entries_from_buf(0, CAPACITY - min_idx, buf);
 
         let self_next = self.read_next_ptr_buf();
         let mut buf = <u64 as AsFixedSizeBytes>::Buf::new(<u64 as AsFixedSizeBytes>::SIZE);
@@ -201,15 +203,19 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         right.write_prev_ptr_buf(&buf);
         right.write_next_ptr_buf(&self_next);
 
+        if self_next != [0u8; u64::SIZE] {
+            let self_next_ptr = u64::from_fixed_size_bytes(&self_next);
+            let mut self_next = unsafe { Self::from_ptr(self_next_ptr) };
+
+            self_next.write_prev_ptr_buf(&right.ptr.as_new_fixed_size_bytes());
+        }
+
         Ok(right)
     }
 
     pub fn merge_min_len(&mut self, right: Self, buf: &mut Vec<u8>) {
-        right.read_many_keys_to_buf(0, MIN_LEN_AFTER_SPLIT, buf);
-        self.write_many_keys_from_buf(MIN_LEN_AFTER_SPLIT, buf);
-
-        right.read_many_values_to_buf(0, MIN_LEN_AFTER_SPLIT, buf);
-        self.write_many_values_from_buf(MIN_LEN_AFTER_SPLIT, buf);
+        right.read_many_entries_to_buf(0, MIN_LEN_AFTER_SPLIT, buf);
+        self.write_many_entries_from_buf(MIN_LEN_AFTER_SPLIT, MIN_LEN_AFTER_SPLIT, buf);
 
         let right_next_buf = right.read_next_ptr_buf();
         self.write_next_ptr_buf(&right_next_buf);
@@ -229,8 +235,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         self.read_and_disown_key(idx);
         let v = self.read_and_disown_value(idx);
 
-        self.remove_key_buf(idx, len, buf);
-        self.remove_value_buf(idx, len, buf);
+        self.remove_entry_buf(idx, len, buf);
 
         v
     }
@@ -240,25 +245,34 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         self.write_key_buf(len, key);
     }
 
-    pub fn insert_key_buf(&mut self, idx: usize, key: &K::Buf, len: usize, buf: &mut Vec<u8>) {
-        if idx == len {
-            self.push_key_buf(key, len);
-            return;
+    pub fn insert_entry_buf(
+        &mut self,
+        idx: usize,
+        key: &K::Buf,
+        value: &V::Buf,
+        len: usize,
+        buf: &mut Vec<u8>,
+    ) {
+        if idx < len {
+            self.read_many_entries_to_buf(idx, len - idx, buf);
+            self.write_many_entries_from_buf(idx + 1, len - idx, buf);
         }
 
-        self.read_many_keys_to_buf(idx, len - idx, buf);
-        self.write_many_keys_from_buf(idx + 1, buf);
-
-        self.write_key_buf(idx, key);
+        unsafe {
+            crate::mem::write_bytes_vectored(&[
+                (self.get_key_ptr(idx), key._deref()),
+                (self.get_value_ptr(idx), value._deref()),
+            ])
+        };
     }
 
-    fn remove_key_buf(&mut self, idx: usize, len: usize, buf: &mut Vec<u8>) {
+    fn remove_entry_buf(&mut self, idx: usize, len: usize, buf: &mut Vec<u8>) {
         if idx == len - 1 {
             return;
         }
 
-        self.read_many_keys_to_buf(idx + 1, len - idx - 1, buf);
-        self.write_many_keys_from_buf(idx, buf);
+        self.read_many_entries_to_buf(idx + 1, len - idx - 1, buf);
+        self.write_many_entries_from_buf(idx, len - idx - 1, buf);
     }
 
     #[inline]
@@ -266,30 +280,9 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         self.write_value_buf(len, value);
     }
 
-    pub fn insert_value_buf(&mut self, idx: usize, value: &V::Buf, len: usize, buf: &mut Vec<u8>) {
-        if idx == len {
-            self.push_value_buf(value, len);
-            return;
-        }
-
-        self.read_many_values_to_buf(idx, len - idx, buf);
-        self.write_many_values_from_buf(idx + 1, buf);
-
-        self.write_value_buf(idx, value);
-    }
-
-    fn remove_value_buf(&mut self, idx: usize, len: usize, buf: &mut Vec<u8>) {
-        if idx == len - 1 {
-            return;
-        }
-
-        self.read_many_values_to_buf(idx + 1, len - idx - 1, buf);
-        self.write_many_values_from_buf(idx, buf);
-    }
-
     #[inline]
     pub fn get_key<'a>(&self, idx: usize) -> SRef<'a, K> {
-        unsafe { SRef::new(self.get_key_ptr(idx)) }
+        unsafe { SRef::new_in(self.ptr, self.get_key_ptr(idx)) }
     }
 
     #[inline]
@@ -304,12 +297,12 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
 
     #[inline]
     pub fn get_value<'a>(&self, idx: usize) -> SRef<'a, V> {
-        unsafe { SRef::new(self.get_value_ptr(idx)) }
+        unsafe { SRef::new_in(self.ptr, self.get_value_ptr(idx)) }
     }
 
     #[inline]
     pub fn get_value_mut<'a>(&mut self, idx: usize) -> SRefMut<'a, V> {
-        unsafe { SRefMut::new(self.get_value_ptr(idx)) }
+        unsafe { SRefMut::new_in(self.ptr, self.get_value_ptr(idx)) }
     }
 
     #[inline]
@@ -327,11 +320,6 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         unsafe { crate::mem::write_bytes(self.get_key_ptr(idx), key._deref()) };
     }
 
-    #[inline]
-    fn write_many_keys_from_buf(&self, from_idx: usize, buf: &Vec<u8>) {
-        unsafe { crate::mem::write_bytes(self.get_key_ptr(from_idx), buf) };
-    }
-
     #[inline]
     fn get_key_ptr(&self, idx: usize) -> u64 {
         SSlice::_offset(self.ptr, KEYS_OFFSET + (idx * K::SIZE) as u64)
@@ -357,23 +345,11 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         k
     }
 
-    #[inline]
-    fn read_many_keys_to_buf(&self, from_idx: usize, len: usize, buf: &mut Vec<u8>) {
-        buf.resize(len * K::SIZE, 0);
-
-        unsafe { crate::mem::read_bytes(self.get_key_ptr(from_idx), buf) };
-    }
-
     #[inline]
     pub fn write_value_buf(&mut self, idx: usize, value: &V::Buf) {
         unsafe { crate::mem::write_bytes(self.get_value_ptr(idx), value._deref()) };
     }
 
-    #[inline]
-    fn write_many_values_from_buf(&self, from_idx: usize, buf: &Vec<u8>) {
-        unsafe { crate::mem::write_bytes(self.get_value_ptr(from_idx), buf) };
-    }
-
     #[inline]
     fn get_value_ptr(&self, idx: usize) -> u64 {
         SSlice::_offset(self.ptr, values_offset::<K>() + (idx * V::SIZE) as u64)
@@ -398,11 +374,28 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         v
     }
 
-    #[inline]
-    fn read_many_values_to_buf(&self, from_idx: usize, len: usize, buf: &mut Vec<u8>) {
-        buf.resize(len * V::SIZE, 0);
+    // keys are followed by values in the buffer
+    pub fn read_many_entries_to_buf(&self, from_idx: usize, len: usize, buf: &mut Vec<u8>) {
+        buf.resize(len * (K::SIZE + V::SIZE), 0);
+        let (keys, values) = buf.split_at_mut(len * K::SIZE);
+
+        unsafe {
+            crate::mem::read_bytes_vectored(&mut [
+                (self.get_key_ptr(from_idx), keys),
+                (self.get_value_ptr(from_idx), values),
+            ])
+        };
+    }
+
+    pub fn write_many_entries_from_buf(&self, from_idx: usize, len: usize, buf: &[u8]) {
+        let (keys, values) = buf.split_at(len * K::SIZE);
 
-        unsafe { crate::mem::read_bytes(self.get_value_ptr(from_idx), buf) };
+        unsafe {
+            crate::mem::write_bytes_vectored(&[
+                (self.get_key_ptr(from_idx), keys),
+                (self.get_value_ptr(from_idx), values),
+            ])
+        };
     }
 
     #[inline]
@@ -552,14 +545,9 @@ mod tests {
             }
 
             for i in (0..CAPACITY).rev() {
-                node.insert_key_buf(
+                node.insert_entry_buf(
                     0,
                     &(i as u64).as_new_fixed_size_bytes(),
-                    CAPACITY - i - 1,
-                    &mut buf,
-                );
-                node.insert_value_buf(
-                    0,
                     &(i as u64).as_new_fixed_size_bytes(),
                     CAPACITY - i - 1,
                     &mut buf,
@@ -573,14 +561,12 @@ mod tests {
                 let k = node.read_key_buf(i);
                 let v = node.read_value_buf(i);
 
-                node.remove_key_buf(i, CAPACITY, &mut buf);
-                node.remove_value_buf(i, CAPACITY, &mut buf);
+                node.remove_entry_buf(i, CAPACITY, &mut buf);
 
                 assert_eq!(k, (i as u64).as_new_fixed_size_bytes());
                 assert_eq!(v, (i as u64).as_new_fixed_size_bytes());
 
-                node.insert_key_buf(i, &k, CAPACITY - 1, &mut buf);
-                node.insert_value_buf(i, &v, CAPACITY - 1, &mut buf);
+                node.insert_entry_buf(i, &k, &v, CAPACITY - 1, &mut buf);
             }
 
             let right = node.split_max_len(true, &mut buf, false).unwrap();
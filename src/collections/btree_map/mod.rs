@@ -1,18 +1,30 @@
+use crate::collections::btree_map::cursor::{SBTreeMapCursor, SBTreeMapCursorIter};
 use crate::collections::btree_map::internal_node::InternalBTreeNode;
-use crate::collections::btree_map::iter::SBTreeMapIter;
+use crate::collections::btree_map::iter::{SBTreeMapIntoIter, SBTreeMapIter, SBTreeMapPrefixIter};
 use crate::collections::btree_map::leaf_node::LeafBTreeNode;
+use crate::collections::btree_map::page::SBTreeMapPage;
+use crate::collections::btree_map::stats::SBTreeMapStats;
 use crate::encoding::AsFixedSizeBytes;
 use crate::mem::allocator::EMPTY_PTR;
 use crate::mem::free_block::FreeBlock;
+use crate::mem::write_coalescing::with_write_coalescing;
 use crate::mem::{StablePtr, StablePtrBuf};
 use crate::primitive::s_ref::SRef;
 use crate::primitive::s_ref_mut::SRefMut;
-use crate::primitive::StableType;
-use crate::utils::math::shuffle_bits;
+use crate::primitive::{StableType, TryClone, TryFromIterator};
+use crate::utils::buf_pool::PooledBuf;
+use crate::utils::math::{shuffle_bits, splitmix64_f64};
 use crate::{isoprint, make_sure_can_allocate, OutOfMemory, SSlice};
+use candid::types::{Compound, Type};
+use candid::CandidType;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
 use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap};
 use std::fmt::{Debug, Formatter};
 use std::mem;
+use std::ops::{Bound, RangeBounds};
 
 pub(crate) const B: usize = 8;
 pub(crate) const CAPACITY: usize = 2 * B - 1;
@@ -25,9 +37,12 @@ pub(crate) const NODE_TYPE_INTERNAL: u8 = 127;
 pub(crate) const NODE_TYPE_LEAF: u8 = 255;
 pub(crate) const NODE_TYPE_OFFSET: u64 = 0;
 
+pub mod cursor;
 pub(crate) mod internal_node;
 pub mod iter;
 pub(crate) mod leaf_node;
+pub mod page;
+pub mod stats;
 
 /// Right-biased B-plus tree based map data structure
 ///
@@ -48,7 +63,7 @@ pub struct SBTreeMap<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsF
     certified: bool,
     stable_drop_flag: bool,
     _stack: Vec<(InternalBTreeNode<K>, usize, usize)>,
-    _buf: Vec<u8>,
+    _buf: PooledBuf,
 }
 
 impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> SBTreeMap<K, V> {
@@ -63,7 +78,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
             certified: false,
             stable_drop_flag: true,
             _stack: Vec::default(),
-            _buf: Vec::default(),
+            _buf: PooledBuf::default(),
         }
     }
 
@@ -75,7 +90,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
             certified: true,
             stable_drop_flag: true,
             _stack: Vec::default(),
-            _buf: Vec::default(),
+            _buf: PooledBuf::default(),
         }
     }
 
@@ -102,7 +117,34 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
     /// ```
     #[inline]
     pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
-        self._insert(key, value, &mut LeveledList::None)
+        with_write_coalescing(|| self._insert(key, value, &mut LeveledList::None))
+    }
+
+    /// Inserts all entries of the provided iterator into this [SBTreeMap]
+    ///
+    /// If the canister is out of stable memory, will return [Err] with the entry that was about to
+    /// get inserted. All entries inserted before it stay in this [SBTreeMap], the rest of the
+    /// iterator is left unconsumed.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// map.try_extend((0..100u64).map(|it| (it, it * 2)))
+    ///     .expect("Out of memory");
+    ///
+    /// assert_eq!(map.len(), 100);
+    /// ```
+    pub fn try_extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) -> Result<(), (K, V)> {
+        for (k, v) in iter {
+            self.insert(k, v)?;
+        }
+
+        Ok(())
     }
 
     pub(crate) fn _insert(
@@ -234,7 +276,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
         K: Borrow<Q>,
         Q: Ord + ?Sized,
     {
-        self._remove(key, &mut LeveledList::None)
+        with_write_coalescing(|| self._remove(key, &mut LeveledList::None))
     }
 
     pub(crate) fn _remove<Q>(&mut self, key: &Q, modified: &mut LeveledList) -> Option<V>
@@ -272,7 +314,16 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
         };
 
         let leaf_len = leaf.read_len();
-        let idx = leaf.binary_search(key, leaf_len).ok()?;
+        let idx = match leaf.binary_search(key, leaf_len) {
+            Ok(idx) => idx,
+            Err(_) => {
+                // nothing was modified, so the stack is simply dropped, otherwise the next
+                // operation would rebalance nodes from this lookup
+                self._stack.clear();
+
+                return None;
+            }
+        };
 
         self.len -= 1;
 
@@ -312,6 +363,69 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
         )
     }
 
+    /// Removes all key-value pairs with keys from the provided range, returning the number of
+    /// removed pairs
+    ///
+    /// Borrowed type is also accepted, like in [SBTreeMap::remove]. An empty or a reversed range
+    /// removes nothing.
+    ///
+    /// Small ranges are removed key by key. If the range holds a noticeable part of the map, leaves
+    /// inside it are deallocated as a whole, only the two leaves at its edges get trimmed and
+    /// rebalanced, and then internal nodes are rebuilt on top of the remaining leaves. So pruning
+    /// of a big range takes `O(r + n / B)` node writes instead of `O(r * logN)`, where `r` is the
+    /// number of removed pairs.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// // timestamp -> event
+    /// let mut events = SBTreeMap::new();
+    /// events.try_extend((0..1000u64).map(|it| (it, it * 10))).expect("Out of memory");
+    ///
+    /// // all events older than 600
+    /// assert_eq!(events.remove_range(..600), 600);
+    /// assert_eq!(events.remove_range(900..=1000), 100);
+    ///
+    /// assert_eq!(events.len(), 300);
+    /// assert_eq!(*events.iter().next().unwrap().0, 600);
+    /// ```
+    pub fn remove_range<Q, R>(&mut self, range: R) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        with_write_coalescing(|| {
+            let (leaf, idx) = match self.range_start(range.start_bound()) {
+                Some(it) => it,
+                None => return 0,
+            };
+
+            // rebuilding internal nodes takes about `n / B` node writes - only do it, when
+            // removing key by key costs more
+            let threshold = self.len / CAPACITY as u64;
+            let count =
+                Self::count_until(unsafe { leaf.copy() }, idx, range.end_bound(), threshold);
+
+            if count > threshold {
+                return self.remove_range_pruning(leaf, idx, range.end_bound());
+            }
+
+            for _ in 0..count {
+                let (leaf, idx) =
+                    unsafe { self.range_start(range.start_bound()).unwrap_unchecked() };
+                let key = leaf.read_key_as_reference(idx);
+
+                self._remove(key.borrow(), &mut LeveledList::None);
+            }
+
+            count
+        })
+    }
+
     /// Returns an immutable reference [SRef] to a value stored by the key
     ///
     /// See also [SBTreeMap::get_mut].
@@ -383,6 +497,131 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
         }
     }
 
+    /// Returns `k` distinct pseudo-random entries, deterministically deriving the randomness from
+    /// the seed.
+    ///
+    /// Unlike [SBTreeMap::get_random_key], each entry has exactly the same chance to be picked. If
+    /// the collection has less than `k` entries, returns all of them. Entries are returned in the
+    /// ascending order of their keys.
+    ///
+    /// Same seed on the same collection leads to the same returned entries.
+    ///
+    /// Takes `O(N)` time, where `N` is the number of entries.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// let winners = map.sample(42, 3);
+    ///
+    /// assert_eq!(winners.len(), 3);
+    /// ```
+    pub fn sample(&self, seed: u32, k: usize) -> Vec<(SRef<'_, K>, SRef<'_, V>)> {
+        let mut state = seed as u64;
+        let mut left = self.len();
+        let mut result = Vec::with_capacity(k.min(self.len() as usize));
+
+        for entry in self.iter() {
+            if result.len() == k {
+                break;
+            }
+
+            // selection sampling - an entry is picked with probability (k - picked) / left
+            if splitmix64_f64(&mut state) * (left as f64) < (k - result.len()) as f64 {
+                result.push(entry);
+            }
+
+            left -= 1;
+        }
+
+        result
+    }
+
+    /// Returns up to `k` distinct pseudo-random entries, where the chance of an entry to be picked
+    /// is proportional to its weight, deterministically deriving the randomness from the seed.
+    ///
+    /// Weights are calculated by the provided lambda over values. Entries are picked without
+    /// replacement: as if they were drawn one by one and each drawn entry was removed from the
+    /// lottery. Entries with zero weight are never picked, so less than `k` entries are returned, if
+    /// there are not enough entries with positive weights. Entries are returned in the ascending
+    /// order of their keys.
+    ///
+    /// Same seed on the same collection leads to the same returned entries.
+    ///
+    /// Takes `O(N * logK)` time, where `N` is the number of entries.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// // validator id -> stake
+    /// let mut stakes = SBTreeMap::new();
+    ///
+    /// stakes.insert(1u64, 1000u64).expect("Out of memory");
+    /// stakes.insert(2u64, 10u64).expect("Out of memory");
+    /// stakes.insert(3u64, 0u64).expect("Out of memory");
+    ///
+    /// let validators = stakes.sample_weighted(42, 2, |stake| *stake);
+    /// let ids = validators.iter().map(|(id, _)| **id).collect::<Vec<_>>();
+    ///
+    /// assert_eq!(ids, vec![1, 2]);
+    /// ```
+    pub fn sample_weighted<F: FnMut(&V) -> u64>(
+        &self,
+        seed: u32,
+        k: usize,
+        mut weight: F,
+    ) -> Vec<(SRef<'_, K>, SRef<'_, V>)> {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        let mut state = seed as u64;
+        let mut heap = BinaryHeap::with_capacity(k);
+
+        // Efraimidis-Spirakis: picks k entries with the biggest ln(u) / weight, u is in (0, 1]
+        for (idx, (key, value)) in self.iter().enumerate() {
+            let u = 1.0 - splitmix64_f64(&mut state);
+
+            let w = weight(&value);
+            if w == 0 {
+                continue;
+            }
+
+            let priority = u.ln() / w as f64;
+
+            if heap.len() == k {
+                match heap.peek() {
+                    Some(WeightedSample { priority: min, .. }) if *min < priority => {
+                        heap.pop();
+                    }
+                    _ => continue,
+                }
+            }
+
+            heap.push(WeightedSample {
+                priority,
+                idx,
+                entry: (key, value),
+            });
+        }
+
+        let mut result = heap.into_vec();
+        result.sort_unstable_by_key(|it| it.idx);
+
+        result.into_iter().map(|it| it.entry).collect()
+    }
+
     /// Returns a mutable reference [SRefMut] to a value stored by the key
     ///
     /// See also [SBTreeMap::get].
@@ -520,6 +759,122 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
         SBTreeMapIter::<K, V>::new(self)
     }
 
+    /// Returns a page of at most `limit` entries, starting from the provided bound, in ascending
+    /// key order
+    ///
+    /// The page is a lightweight view, which implements [CandidType] exactly as `Vec<(K, V)>`, so
+    /// it can be replied from a query method without copying entries to the heap first. To get the
+    /// next page, pass the [last key](SBTreeMapPage::last_key) of the current one as
+    /// [Bound::Excluded].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # use std::ops::Bound;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    /// map.try_extend((0..100u64).map(|it| (it, it * 10))).expect("Out of memory");
+    ///
+    /// let page = map.page(Bound::Excluded(&10), 5);
+    /// assert_eq!(*page.last_key().unwrap(), 15);
+    ///
+    /// // the same as replying with `Vec<(u64, u64)>`
+    /// let reply = candid::encode_one(&page).unwrap();
+    /// let entries: Vec<(u64, u64)> = candid::decode_one(&reply).unwrap();
+    ///
+    /// assert_eq!(entries, (11..16).map(|it| (it, it * 10)).collect::<Vec<_>>());
+    /// ```
+    #[inline]
+    pub fn page<'a>(&'a self, from: Bound<&'a K>, limit: usize) -> SBTreeMapPage<'a, K, V> {
+        SBTreeMapPage::new(self, from, limit)
+    }
+
+    /// Returns an iterator, resuming from the provided [SBTreeMapCursor]
+    ///
+    /// Useful for scans of big maps, which don't fit into a single message: save the
+    /// [cursor](SBTreeMapCursorIter::cursor) of the iterator at the end of a message and resume
+    /// the scan in the next one. The map may be modified in between.
+    ///
+    /// # Panics
+    /// Panics if the cursor was created for a map with keys of other size.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::{SBTreeMap, SBTreeMapCursor};
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    /// map.try_extend((0..100u64).map(|it| (it, it))).expect("Out of memory");
+    ///
+    /// // first message
+    /// let mut iter = map.iter_from_cursor(&SBTreeMapCursor::new());
+    /// let sum: u64 = iter.by_ref().take(50).map(|(_, v)| *v).sum();
+    /// let cursor = iter.cursor();
+    ///
+    /// // the map gets modified in between
+    /// map.remove(&50);
+    /// map.insert(1000, 1000).expect("Out of memory");
+    ///
+    /// // second message
+    /// let rest: Vec<u64> = map.iter_from_cursor(&cursor).map(|(k, _)| *k).collect();
+    ///
+    /// assert_eq!(sum, (0..50u64).sum::<u64>());
+    /// assert_eq!(rest.first(), Some(&51));
+    /// assert_eq!(rest.last(), Some(&1000));
+    /// ```
+    #[inline]
+    pub fn iter_from_cursor(&self, cursor: &SBTreeMapCursor) -> SBTreeMapCursorIter<'_, K, V> {
+        SBTreeMapCursorIter::new(self, cursor)
+    }
+
+    // returns an iterator, starting from the first key that is greater than or equal to the provided one
+    #[inline]
+    pub(crate) fn iter_from<Q>(&self, key: &Q) -> SBTreeMapIter<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.iter_from_by(|it| it.borrow().cmp(key))
+    }
+
+    // same as above, but compares keys with the provided function
+    pub(crate) fn iter_from_by<F>(&self, mut f: F) -> SBTreeMapIter<'_, K, V>
+    where
+        F: FnMut(&K) -> Ordering,
+    {
+        let mut node = match self.get_root() {
+            Some(it) => it,
+            None => return SBTreeMapIter::<K, V>::new(self),
+        };
+
+        loop {
+            match node {
+                BTreeNode::Internal(internal_node) => {
+                    let child_idx =
+                        match internal_node.binary_search_by(&mut f, internal_node.read_len()) {
+                            Ok(idx) => idx + 1,
+                            Err(idx) => idx,
+                        };
+
+                    let child_ptr =
+                        u64::from_fixed_size_bytes(&internal_node.read_child_ptr_buf(child_idx));
+                    node = BTreeNode::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(leaf_node) => {
+                    let idx = match leaf_node.binary_search_by(&mut f, leaf_node.read_len()) {
+                        Ok(idx) => idx,
+                        Err(idx) => idx,
+                    };
+
+                    return SBTreeMapIter::<K, V>::new_from(self, leaf_node, idx);
+                }
+            }
+        }
+    }
+
     /// Returns the length of this [SBTreeMap]
     #[inline]
     pub fn len(&self) -> u64 {
@@ -532,6 +887,71 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
         self.len() == 0
     }
 
+    /// Returns the [shape](SBTreeMapStats) of this [SBTreeMap]
+    ///
+    /// Visits every node, so it takes `O(n / B)` time.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    /// map.try_extend((0..1000u64).map(|it| (it, it))).expect("Out of memory");
+    ///
+    /// let stats = map.stats();
+    ///
+    /// assert_eq!(stats.nodes_per_level.len() as u64, stats.height);
+    /// assert_eq!(stats.nodes_per_level[0], 1);
+    /// assert!(stats.fill_factor > 0.45);
+    /// ```
+    pub fn stats(&self) -> SBTreeMapStats {
+        let mut stats = SBTreeMapStats::default();
+        let mut keys_len = 0;
+
+        let mut level = Vec::new();
+        level.extend(self.get_root());
+
+        while !level.is_empty() {
+            let mut next_level = Vec::new();
+
+            stats.height += 1;
+            stats.nodes_per_level.push(level.len() as u64);
+
+            for node in level {
+                let slice = unsafe { SSlice::from_ptr(node.as_ptr()).unwrap() };
+                stats.size_bytes += slice.get_total_size_bytes();
+
+                match node {
+                    BTreeNode::Internal(internal_node) => {
+                        let len = internal_node.read_len();
+                        keys_len += len;
+
+                        for i in 0..(len + 1) {
+                            let child_ptr =
+                                u64::from_fixed_size_bytes(&internal_node.read_child_ptr_buf(i));
+
+                            next_level.push(BTreeNode::from_ptr(child_ptr));
+                        }
+                    }
+                    BTreeNode::Leaf(leaf_node) => {
+                        keys_len += leaf_node.read_len();
+                    }
+                }
+            }
+
+            level = next_level;
+        }
+
+        let nodes_len = stats.nodes_per_level.iter().sum::<u64>();
+        if nodes_len > 0 {
+            stats.fill_factor = keys_len as f64 / (nodes_len * CAPACITY as u64) as f64;
+        }
+
+        stats
+    }
+
     /// Removes all key-value pairs from this collection, releasing all occupied stable memory
     #[inline]
     pub fn clear(&mut self) {
@@ -615,51 +1035,389 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
         }
     }
 
-    fn insert_leaf(
-        &mut self,
-        leaf_node: &mut LeafBTreeNode<K, V>,
-        mut key: K,
-        mut value: V,
-        modified: &mut LeveledList,
-    ) -> Result<Result<V, Option<LeafBTreeNode<K, V>>>, (K, V)> {
-        let leaf_node_len = leaf_node.read_len();
-        let insert_idx = match leaf_node.binary_search(&key, leaf_node_len) {
-            Ok(existing_idx) => {
-                // if there is already a key like that, return early
-                let prev_value: V = leaf_node.read_and_disown_value(existing_idx);
-                leaf_node.write_and_own_value(existing_idx, value);
+    // returns the position of the first key within the bound, or None if there is no such key
+    fn range_start<Q>(&self, from: Bound<&Q>) -> Option<(LeafBTreeNode<K, V>, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let cmp = |key: &K| match from {
+            Bound::Included(it) | Bound::Excluded(it) => key.borrow().cmp(it),
+            Bound::Unbounded => Ordering::Greater,
+        };
 
-                modified.push(self.current_depth(), leaf_node.as_ptr());
+        let mut node = self.get_root()?;
+        let leaf = loop {
+            match node {
+                BTreeNode::Internal(internal_node) => {
+                    let child_idx =
+                        match internal_node.binary_search_by(cmp, internal_node.read_len()) {
+                            Ok(idx) => idx + 1,
+                            Err(idx) => idx,
+                        };
 
-                return Ok(Ok(prev_value));
+                    let child_ptr =
+                        u64::from_fixed_size_bytes(&internal_node.read_child_ptr_buf(child_idx));
+                    node = BTreeNode::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(leaf_node) => break leaf_node,
             }
-            Err(idx) => idx,
         };
 
-        let k = key.as_new_fixed_size_bytes();
-        let v = value.as_new_fixed_size_bytes();
-
-        // if there is enough space - simply insert and return early
-        if leaf_node_len < CAPACITY {
-            leaf_node.insert_key_buf(insert_idx, &k, leaf_node_len, &mut self._buf);
-            leaf_node.insert_value_buf(insert_idx, &v, leaf_node_len, &mut self._buf);
-
-            leaf_node.write_len(leaf_node_len + 1);
-
-            modified.push(self.current_depth(), leaf_node.as_ptr());
-
-            unsafe { key.stable_drop_flag_off() };
-            unsafe { value.stable_drop_flag_off() };
+        let len = leaf.read_len();
+        let idx = match (leaf.binary_search_by(cmp, len), from) {
+            (Ok(idx), Bound::Excluded(_)) => idx + 1,
+            (Ok(idx), _) | (Err(idx), _) => idx,
+        };
 
-            return Ok(Err(None));
+        if idx < len {
+            return Some((leaf, idx));
         }
 
-        // try passing an element to a neighbor, to make room for a new one
-        if self.pass_elem_to_sibling_leaf(leaf_node, &k, &v, insert_idx, modified) {
-            unsafe { key.stable_drop_flag_off() };
-            unsafe { value.stable_drop_flag_off() };
-
-            return Ok(Err(None));
+        // leaves of a non-empty tree are never empty, so the next one starts with the key
+        let next_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+        if next_ptr == 0 {
+            return None;
+        }
+
+        Some((unsafe { LeafBTreeNode::from_ptr(next_ptr) }, 0))
+    }
+
+    // returns the index of the first key of the leaf, which is beyond the bound
+    fn range_end_idx<Q>(leaf: &LeafBTreeNode<K, V>, len: usize, to: Bound<&Q>) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match to {
+            Bound::Included(key) => match leaf.binary_search(key, len) {
+                Ok(idx) => idx + 1,
+                Err(idx) => idx,
+            },
+            Bound::Excluded(key) => match leaf.binary_search(key, len) {
+                Ok(idx) | Err(idx) => idx,
+            },
+            Bound::Unbounded => len,
+        }
+    }
+
+    // counts keys from the position up to the bound, stops as soon as there are more than `limit`
+    fn count_until<Q>(
+        mut leaf: LeafBTreeNode<K, V>,
+        mut idx: usize,
+        to: Bound<&Q>,
+        limit: u64,
+    ) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut count = 0;
+
+        loop {
+            let len = leaf.read_len();
+            let end_idx = Self::range_end_idx(&leaf, len, to).max(idx);
+
+            count += (end_idx - idx) as u64;
+
+            let next_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+            if end_idx < len || count > limit || next_ptr == 0 {
+                return count;
+            }
+
+            leaf = unsafe { LeafBTreeNode::from_ptr(next_ptr) };
+            idx = 0;
+        }
+    }
+
+    // removes keys from the position up to the bound, deallocating leaves inside the range as a
+    // whole; internal nodes are rebuilt afterwards, reusing their memory
+    fn remove_range_pruning<Q>(
+        &mut self,
+        mut leaf: LeafBTreeNode<K, V>,
+        mut idx: usize,
+        to: Bound<&Q>,
+    ) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (mut internal_nodes, mut first_leaf_ptr) = self.take_internal_nodes();
+
+        // at most two leaves - at both edges of the range - can get trimmed without being emptied
+        let mut trimmed = Vec::new();
+        let mut removed = 0;
+
+        loop {
+            let len = leaf.read_len();
+            let end_idx = Self::range_end_idx(&leaf, len, to).max(idx);
+
+            for i in idx..end_idx {
+                leaf.read_and_disown_key(i);
+                leaf.read_and_disown_value(i);
+            }
+
+            if end_idx < len {
+                leaf.read_many_entries_to_buf(end_idx, len - end_idx, &mut self._buf);
+                leaf.write_many_entries_from_buf(idx, len - end_idx, &self._buf);
+            }
+
+            let new_len = len - (end_idx - idx);
+            removed += (end_idx - idx) as u64;
+
+            let next_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+
+            if new_len == 0 {
+                if leaf.as_ptr() == first_leaf_ptr {
+                    first_leaf_ptr = next_ptr;
+                }
+
+                Self::unlink_and_destroy_leaf(leaf);
+            } else if new_len < len {
+                leaf.write_len(new_len);
+                trimmed.push(leaf);
+            }
+
+            if end_idx < len || next_ptr == 0 {
+                break;
+            }
+
+            leaf = unsafe { LeafBTreeNode::from_ptr(next_ptr) };
+            idx = 0;
+        }
+
+        self.len -= removed;
+
+        if self.len == 0 {
+            debug_assert_eq!(first_leaf_ptr, 0);
+
+            for node in internal_nodes {
+                node.destroy();
+            }
+
+            return removed;
+        }
+
+        // the right one goes first: its left sibling is never deallocated by this
+        while let Some(leaf) = trimmed.pop() {
+            self.fix_underfull_leaf(leaf);
+        }
+
+        // each level of the rebuilt tree is at least as short as the same level of the old one,
+        // so there are always enough internal nodes to reuse
+        let mut level = Vec::new();
+        let mut leaf_ptr = first_leaf_ptr;
+
+        while leaf_ptr != 0 {
+            let leaf = unsafe { LeafBTreeNode::<K, V>::from_ptr(leaf_ptr) };
+
+            level.push((leaf_ptr, leaf.read_key_buf(0)));
+            leaf_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+        }
+
+        while level.len() > 1 {
+            let nodes_len = level.len().div_ceil(CHILDREN_CAPACITY);
+            let children_len = level.len() / nodes_len;
+            let mut rest = level.len() % nodes_len;
+
+            let mut children = level.into_iter();
+            level = Vec::with_capacity(nodes_len);
+
+            for _ in 0..nodes_len {
+                let mut node = internal_nodes.pop().unwrap();
+                let mut node_children_len = children_len;
+
+                if rest > 0 {
+                    node_children_len += 1;
+                    rest -= 1;
+                }
+
+                let (first_child_ptr, first_key) = children.next().unwrap();
+                node.write_child_ptr_buf(0, &first_child_ptr.as_new_fixed_size_bytes());
+
+                for i in 1..node_children_len {
+                    let (child_ptr, key) = children.next().unwrap();
+
+                    node.write_child_ptr_buf(i, &child_ptr.as_new_fixed_size_bytes());
+                    node.write_key_buf(i - 1, &key);
+                }
+
+                node.write_len(node_children_len - 1);
+                level.push((node.as_ptr(), first_key));
+            }
+        }
+
+        for node in internal_nodes {
+            node.destroy();
+        }
+
+        self.root = Some(BTreeNode::from_ptr(level[0].0));
+
+        removed
+    }
+
+    // takes internal nodes out of the tree, returning them with the pointer to the leftmost leaf
+    fn take_internal_nodes(&mut self) -> (Vec<InternalBTreeNode<K>>, StablePtr) {
+        let mut internal_nodes = Vec::new();
+        let mut level = match self.root.take() {
+            Some(BTreeNode::Internal(root)) => vec![root],
+            Some(BTreeNode::Leaf(root)) => return (internal_nodes, root.as_ptr()),
+            None => return (internal_nodes, 0),
+        };
+
+        // all leaves are at the same depth, so it is enough to only check the first child
+        loop {
+            let first_child_ptr = u64::from_fixed_size_bytes(&level[0].read_child_ptr_buf(0));
+            let children_are_leaves = matches!(
+                BTreeNode::<K, V>::from_ptr(first_child_ptr),
+                BTreeNode::Leaf(_)
+            );
+
+            if children_are_leaves {
+                internal_nodes.extend(level);
+
+                return (internal_nodes, first_child_ptr);
+            }
+
+            let mut next_level = Vec::new();
+
+            for node in level {
+                for i in 0..(node.read_len() + 1) {
+                    let child_ptr = u64::from_fixed_size_bytes(&node.read_child_ptr_buf(i));
+
+                    next_level.push(unsafe { InternalBTreeNode::from_ptr(child_ptr) });
+                }
+
+                internal_nodes.push(node);
+            }
+
+            level = next_level;
+        }
+    }
+
+    // merges the leaf with a sibling, or moves some entries from it, until the leaf is long enough
+    fn fix_underfull_leaf(&mut self, mut leaf: LeafBTreeNode<K, V>) {
+        while leaf.read_len() < MIN_LEN_AFTER_SPLIT {
+            let next_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+            if next_ptr != 0 {
+                self.rebalance_leaves(&mut leaf, unsafe { LeafBTreeNode::from_ptr(next_ptr) });
+                continue;
+            }
+
+            let prev_ptr = u64::from_fixed_size_bytes(&leaf.read_prev_ptr_buf());
+            if prev_ptr == 0 {
+                // the only leaf is the root - it can be of any length
+                return;
+            }
+
+            let mut prev = unsafe { LeafBTreeNode::from_ptr(prev_ptr) };
+            self.rebalance_leaves(&mut prev, leaf);
+
+            leaf = prev;
+        }
+    }
+
+    // merges the right leaf into the left one, if they fit, or evenly distributes their entries
+    fn rebalance_leaves(&mut self, left: &mut LeafBTreeNode<K, V>, mut right: LeafBTreeNode<K, V>) {
+        let left_len = left.read_len();
+        let right_len = right.read_len();
+
+        if left_len + right_len <= CAPACITY {
+            right.read_many_entries_to_buf(0, right_len, &mut self._buf);
+            left.write_many_entries_from_buf(left_len, right_len, &self._buf);
+            left.write_len(left_len + right_len);
+
+            Self::unlink_and_destroy_leaf(right);
+
+            return;
+        }
+
+        let new_left_len = (left_len + right_len) / 2;
+        let new_right_len = left_len + right_len - new_left_len;
+
+        if new_left_len > left_len {
+            let moved_len = new_left_len - left_len;
+
+            right.read_many_entries_to_buf(0, moved_len, &mut self._buf);
+            left.write_many_entries_from_buf(left_len, moved_len, &self._buf);
+
+            right.read_many_entries_to_buf(moved_len, new_right_len, &mut self._buf);
+            right.write_many_entries_from_buf(0, new_right_len, &self._buf);
+        } else {
+            let moved_len = left_len - new_left_len;
+
+            right.read_many_entries_to_buf(0, right_len, &mut self._buf);
+            right.write_many_entries_from_buf(moved_len, right_len, &self._buf);
+
+            left.read_many_entries_to_buf(new_left_len, moved_len, &mut self._buf);
+            right.write_many_entries_from_buf(0, moved_len, &self._buf);
+        }
+
+        left.write_len(new_left_len);
+        right.write_len(new_right_len);
+    }
+
+    fn unlink_and_destroy_leaf(leaf: LeafBTreeNode<K, V>) {
+        let prev_ptr_buf = leaf.read_prev_ptr_buf();
+        let next_ptr_buf = leaf.read_next_ptr_buf();
+
+        let prev_ptr = u64::from_fixed_size_bytes(&prev_ptr_buf);
+        if prev_ptr != 0 {
+            unsafe { LeafBTreeNode::<K, V>::from_ptr(prev_ptr) }.write_next_ptr_buf(&next_ptr_buf);
+        }
+
+        let next_ptr = u64::from_fixed_size_bytes(&next_ptr_buf);
+        if next_ptr != 0 {
+            unsafe { LeafBTreeNode::<K, V>::from_ptr(next_ptr) }.write_prev_ptr_buf(&prev_ptr_buf);
+        }
+
+        leaf.destroy();
+    }
+
+    fn insert_leaf(
+        &mut self,
+        leaf_node: &mut LeafBTreeNode<K, V>,
+        mut key: K,
+        mut value: V,
+        modified: &mut LeveledList,
+    ) -> Result<Result<V, Option<LeafBTreeNode<K, V>>>, (K, V)> {
+        let leaf_node_len = leaf_node.read_len();
+        let insert_idx = match leaf_node.binary_search(&key, leaf_node_len) {
+            Ok(existing_idx) => {
+                // if there is already a key like that, return early
+                let prev_value: V = leaf_node.read_and_disown_value(existing_idx);
+                leaf_node.write_and_own_value(existing_idx, value);
+
+                modified.push(self.current_depth(), leaf_node.as_ptr());
+
+                return Ok(Ok(prev_value));
+            }
+            Err(idx) => idx,
+        };
+
+        let k = key.as_new_fixed_size_bytes();
+        let v = value.as_new_fixed_size_bytes();
+
+        // if there is enough space - simply insert and return early
+        if leaf_node_len < CAPACITY {
+            leaf_node.insert_entry_buf(insert_idx, &k, &v, leaf_node_len, &mut self._buf);
+
+            leaf_node.write_len(leaf_node_len + 1);
+
+            modified.push(self.current_depth(), leaf_node.as_ptr());
+
+            unsafe { key.stable_drop_flag_off() };
+            unsafe { value.stable_drop_flag_off() };
+
+            return Ok(Err(None));
+        }
+
+        // try passing an element to a neighbor, to make room for a new one
+        if self.pass_elem_to_sibling_leaf(leaf_node, &k, &v, insert_idx, modified) {
+            unsafe { key.stable_drop_flag_off() };
+            unsafe { value.stable_drop_flag_off() };
+
+            return Ok(Err(None));
         }
 
         // cheking if it is possible to allocate worst-case scenario amount of memory
@@ -680,16 +1438,26 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
             let right = leaf_node
                 .split_max_len(true, &mut self._buf, self.certified)
                 .unwrap();
-            leaf_node.insert_key_buf(insert_idx, &k, MIN_LEN_AFTER_SPLIT, &mut self._buf);
-            leaf_node.insert_value_buf(insert_idx, &v, MIN_LEN_AFTER_SPLIT, &mut self._buf);
+            leaf_node.insert_entry_buf(
+                insert_idx,
+                &k,
+                &v,
+                MIN_LEN_AFTER_SPLIT,
+                &mut self._buf,
+            );
 
             right
         } else {
             let mut right = leaf_node
                 .split_max_len(false, &mut self._buf, self.certified)
                 .unwrap();
-            right.insert_key_buf(insert_idx - B, &k, MIN_LEN_AFTER_SPLIT, &mut self._buf);
-            right.insert_value_buf(insert_idx - B, &v, MIN_LEN_AFTER_SPLIT, &mut self._buf);
+            right.insert_entry_buf(
+                insert_idx - B,
+                &k,
+                &v,
+                MIN_LEN_AFTER_SPLIT,
+                &mut self._buf,
+            );
 
             right
         };
@@ -832,8 +1600,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
         if i_idx != CAPACITY {
             rs.steal_from_left(rs_len, leaf, CAPACITY, p, p_idx, None, &mut self._buf);
 
-            leaf.insert_key_buf(i_idx, key, CAPACITY - 1, &mut self._buf);
-            leaf.insert_value_buf(i_idx, value, CAPACITY - 1, &mut self._buf);
+            leaf.insert_entry_buf(i_idx, key, value, CAPACITY - 1, &mut self._buf);
 
             rs.write_len(rs_len + 1);
             return;
@@ -858,8 +1625,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
         if i_idx != 1 {
             ls.steal_from_right(ls_len, leaf, CAPACITY, p, p_idx - 1, None, &mut self._buf);
 
-            leaf.insert_key_buf(i_idx - 1, key, CAPACITY - 1, &mut self._buf);
-            leaf.insert_value_buf(i_idx - 1, value, CAPACITY - 1, &mut self._buf);
+            leaf.insert_entry_buf(i_idx - 1, key, value, CAPACITY - 1, &mut self._buf);
 
             ls.write_len(ls_len + 1);
             return;
@@ -1504,6 +2270,77 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
     }
 }
 
+/// Composite key, made of several parts, which is ordered by its first part before anything else
+///
+/// Tuples are ordered lexicographically, so keys like `(user_id, order_id)` keep all entries of the
+/// same user next to each other. This trait exposes the first part of such a key, which allows
+/// scanning all entries sharing it with [SBTreeMap::iter_prefix], instead of keeping a nested map
+/// of collections.
+///
+/// Implemented for `(A, B)` and `(A, B, C)`.
+pub trait CompositeKey {
+    /// The first part of the key
+    type Prefix: Ord;
+
+    /// Returns the first part of the key
+    fn prefix(&self) -> &Self::Prefix;
+}
+
+impl<A: Ord, B: Ord> CompositeKey for (A, B) {
+    type Prefix = A;
+
+    #[inline]
+    fn prefix(&self) -> &Self::Prefix {
+        &self.0
+    }
+}
+
+impl<A: Ord, B: Ord, C: Ord> CompositeKey for (A, B, C) {
+    type Prefix = A;
+
+    #[inline]
+    fn prefix(&self) -> &Self::Prefix {
+        &self.0
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord + CompositeKey, V: StableType + AsFixedSizeBytes>
+    SBTreeMap<K, V>
+{
+    /// Returns an iterator over all entries, which keys start with the provided prefix, in
+    /// ascending order
+    ///
+    /// Works in O(logN) to find the first entry and then in O(1) per entry.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// // (user id, order id) -> order amount
+    /// let mut orders = SBTreeMap::<(u64, u64), u128>::new();
+    ///
+    /// orders.insert((1, 10), 100).expect("Out of memory");
+    /// orders.insert((2, 11), 200).expect("Out of memory");
+    /// orders.insert((1, 12), 300).expect("Out of memory");
+    ///
+    /// let user_1_orders: Vec<u64> = orders.iter_prefix(&1).map(|(k, _)| k.1).collect();
+    ///
+    /// assert_eq!(user_1_orders, vec![10, 12]);
+    /// ```
+    #[inline]
+    pub fn iter_prefix<'a>(&'a self, prefix: &'a K::Prefix) -> SBTreeMapPrefixIter<'a, K, V> {
+        // equal prefixes are treated as greater, so the search stops at the first one of them
+        let inner = self.iter_from_by(|it| match it.prefix().cmp(prefix) {
+            Ordering::Less => Ordering::Less,
+            _ => Ordering::Greater,
+        });
+
+        SBTreeMapPrefixIter::new(inner, prefix)
+    }
+}
+
 impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> StableType
     for SBTreeMap<K, V>
 {
@@ -1637,46 +2474,153 @@ impl<K: StableType + AsFixedSizeBytes + Ord + Debug, V: StableType + AsFixedSize
     }
 }
 
-impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Default
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> IntoIterator
     for SBTreeMap<K, V>
 {
-    fn default() -> Self {
-        Self::new()
+    type Item = (K, V);
+    type IntoIter = SBTreeMapIntoIter<K, V>;
+
+    /// Returns a consuming iterator over entries of this [SBTreeMap]
+    ///
+    /// Entries are moved out of stable memory one by one, in ascending order of their keys. Internal
+    /// nodes are released right away, each leaf node is released as soon as all its entries are
+    /// consumed. Once the iterator is dropped, all entries that were not consumed are stable-dropped.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// for (k, v) in map {
+    ///     assert_eq!(v, k * 2);
+    /// }
+    /// ```
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        SBTreeMapIntoIter::new(self)
     }
 }
 
-impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> AsFixedSizeBytes
-    for SBTreeMap<K, V>
+impl<
+        K: StableType + AsFixedSizeBytes + Ord + TryClone,
+        V: StableType + AsFixedSizeBytes + TryClone,
+    > TryClone for SBTreeMap<K, V>
 {
-    const SIZE: usize = u64::SIZE * 2;
-    type Buf = [u8; u64::SIZE * 2];
+    /// Copies all entries into a new [SBTreeMap]
+    fn try_clone(&self) -> Result<Self, OutOfMemory> {
+        let mut res = Self::new();
 
-    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        let ptr = if let Some(root) = &self.root {
-            root.as_ptr()
-        } else {
-            EMPTY_PTR
-        };
+        for (k, v) in self.iter() {
+            res.insert(k.try_clone()?, v.try_clone()?)
+                .map_err(|_| OutOfMemory)?;
+        }
 
-        ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
-        self.len
-            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE * 2)]);
+        Ok(res)
     }
+}
 
-    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        let ptr = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
-        let len = u64::from_fixed_size_bytes(&buf[u64::SIZE..(u64::SIZE * 2)]);
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes>
+    TryFromIterator<(K, V)> for SBTreeMap<K, V>
+{
+    /// See [SBTreeMap::try_extend]
+    fn try_from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Result<Self, (Self, OutOfMemory)> {
+        let mut it = Self::new();
 
-        Self {
-            root: if ptr == EMPTY_PTR {
-                None
-            } else {
-                Some(BTreeNode::from_ptr(ptr))
+        match it.try_extend(iter) {
+            Ok(_) => Ok(it),
+            Err(_) => Err((it, OutOfMemory)),
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes>
+    TryFrom<BTreeMap<K, V>> for SBTreeMap<K, V>
+{
+    type Error = OutOfMemory;
+
+    /// Moves all entries of the provided [BTreeMap] into a new [SBTreeMap]
+    ///
+    /// # Errors
+    /// Returns [OutOfMemory] if the canister is out of stable memory. Everything allocated so far is
+    /// released.
+    #[inline]
+    fn try_from(value: BTreeMap<K, V>) -> Result<Self, Self::Error> {
+        Self::try_from_iter(value).map_err(|(_, e)| e)
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord + Clone, V: StableType + AsFixedSizeBytes + Clone>
+    SBTreeMap<K, V>
+{
+    /// Copies all entries into a heap [BTreeMap]
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # use std::collections::BTreeMap;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let heap_state = BTreeMap::from([(1u64, 10u64), (2, 20)]);
+    ///
+    /// let map = SBTreeMap::try_from(heap_state.clone()).expect("Out of memory");
+    ///
+    /// assert_eq!(map.to_std(), heap_state);
+    /// ```
+    pub fn to_std(&self) -> BTreeMap<K, V> {
+        self.iter()
+            .map(|(k, v)| ((*k).clone(), (*v).clone()))
+            .collect()
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Default
+    for SBTreeMap<K, V>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> AsFixedSizeBytes
+    for SBTreeMap<K, V>
+{
+    const SIZE: usize = u64::SIZE * 2;
+    type Buf = [u8; u64::SIZE * 2];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let ptr = if let Some(root) = &self.root {
+            root.as_ptr()
+        } else {
+            EMPTY_PTR
+        };
+
+        ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+        self.len
+            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE * 2)]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let ptr = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
+        let len = u64::from_fixed_size_bytes(&buf[u64::SIZE..(u64::SIZE * 2)]);
+
+        Self {
+            root: if ptr == EMPTY_PTR {
+                None
+            } else {
+                Some(BTreeNode::from_ptr(ptr))
             },
             certified: false,
             len,
             stable_drop_flag: false,
-            _buf: Vec::default(),
+            _buf: PooledBuf::default(),
             _stack: Vec::default(),
         }
     }
@@ -1751,6 +2695,13 @@ impl LeveledList {
         }
     }
 
+    pub(crate) fn is_empty(&self) -> bool {
+        match self {
+            LeveledList::None => true,
+            LeveledList::Some((v, _)) => v.iter().all(|it| it.is_empty()),
+        }
+    }
+
     pub(crate) fn pop(&mut self) -> Option<u64> {
         match self {
             LeveledList::None => unreachable!(),
@@ -1792,6 +2743,49 @@ impl LeveledList {
     }
 }
 
+/// Encoded exactly as `BTreeMap<K, V>` (`vec record { K; V }`), reading entries from stable memory
+/// one by one
+///
+/// For big maps, prefer [SBTreeMap::page], since replies of the IC are limited in size.
+impl<
+        K: StableType + AsFixedSizeBytes + Ord + CandidType,
+        V: StableType + AsFixedSizeBytes + CandidType,
+    > CandidType for SBTreeMap<K, V>
+{
+    #[inline]
+    fn _ty() -> Type {
+        <Vec<(K, V)>>::_ty()
+    }
+
+    fn idl_serialize<S>(&self, serializer: S) -> Result<(), S::Error>
+    where
+        S: candid::types::Serializer,
+    {
+        let mut ser = serializer.serialize_vec(self.len() as usize)?;
+        for (k, v) in self.iter() {
+            Compound::serialize_element(&mut ser, &(&*k, &*v))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Serialized as a map, reading entries from stable memory one by one, in ascending key order
+impl<
+        K: StableType + AsFixedSizeBytes + Ord + Serialize,
+        V: StableType + AsFixedSizeBytes + Serialize,
+    > Serialize for SBTreeMap<K, V>
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len() as usize))?;
+        for (k, v) in self.iter() {
+            map.serialize_entry(&*k, &*v)?;
+        }
+
+        map.end()
+    }
+}
+
 impl<K: StableType + AsFixedSizeBytes + Ord + Debug, V: StableType + AsFixedSizeBytes + Debug> Debug
     for SBTreeMap<K, V>
 {
@@ -1852,9 +2846,39 @@ impl<K, V> BTreeNode<K, V> {
     }
 }
 
+// ordered by the priority in reverse, so a BinaryHeap pops the lowest one
+struct WeightedSample<E> {
+    priority: f64,
+    idx: usize,
+    entry: E,
+}
+
+impl<E> PartialEq for WeightedSample<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<E> Eq for WeightedSample<E> {}
+
+impl<E> PartialOrd for WeightedSample<E> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<E> Ord for WeightedSample<E> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::collections::btree_map::cursor::SBTreeMapCursor;
     use crate::collections::btree_map::SBTreeMap;
+    use crate::primitive::s_ref::SRef;
+    use crate::primitive::TryClone;
     use crate::utils::test::generate_random_string;
     use crate::{
         _debug_validate_allocator, get_allocated_size, init_allocator, retrieve_custom_data,
@@ -1864,7 +2888,178 @@ mod tests {
     use rand::rngs::ThreadRng;
     use rand::seq::SliceRandom;
     use rand::{thread_rng, Rng};
+    use serde_test::{assert_ser_tokens, Token};
     use std::collections::BTreeMap;
+    use std::ops::Bound;
+
+    #[test]
+    fn cursor_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMap::new();
+            let start = SBTreeMapCursor::new();
+            assert!(start.is_start());
+            assert!(map.iter_from_cursor(&start).next().is_none());
+
+            for i in 0..1000u64 {
+                map.insert(i * 2, i).unwrap();
+            }
+
+            let mut cursor = SBTreeMapCursor::new();
+            let mut keys = Vec::new();
+            let mut removed = Vec::new();
+
+            loop {
+                let mut iter = map.iter_from_cursor(&cursor);
+                let batch = iter.by_ref().take(100).map(|(k, _)| *k).collect::<Vec<_>>();
+                if batch.is_empty() {
+                    break;
+                }
+
+                let full = batch.len() == 100;
+                keys.extend(batch);
+
+                // cursors survive candid round-trips and modifications of the map
+                let buf = candid::encode_one(iter.cursor()).unwrap();
+                cursor = candid::decode_one(&buf).unwrap();
+
+                if full {
+                    let last = *keys.last().unwrap();
+                    map.remove(&last);
+                    removed.push(last);
+                    map.insert(last + 1, 0).unwrap();
+                    map.insert(1, 0).unwrap();
+                }
+            }
+
+            // every entry is visited once, except for the ones inserted behind the cursor
+            let mut expected = map.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+            expected.retain(|it| *it != 1);
+            expected.extend(removed);
+            expected.sort();
+
+            assert_eq!(keys, expected);
+
+            let cursor = SBTreeMapCursor::after(&1001u64);
+            let (k, _) = map.iter_from_cursor(&cursor).next().unwrap();
+            assert_eq!(*k, 1002);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn page_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let heap_state = (0..100u64)
+                .map(|it| (it * 2, it))
+                .collect::<BTreeMap<_, _>>();
+            let map = SBTreeMap::try_from(heap_state.clone()).unwrap();
+
+            let buf = candid::encode_one(&map).unwrap();
+            assert_eq!(buf, candid::encode_one(&heap_state).unwrap());
+
+            let page = map.page(Bound::Unbounded, 3);
+            assert_eq!(page.len(), 3);
+            assert_eq!(*page.last_key().unwrap(), 4);
+
+            // both existing and missing keys work as bounds
+            let keys = |from: Bound<&u64>| {
+                let page = map.page(from, 2);
+                let keys = page.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+
+                keys
+            };
+            assert_eq!(keys(Bound::Included(&10)), vec![10, 12]);
+            assert_eq!(keys(Bound::Excluded(&10)), vec![12, 14]);
+            assert_eq!(keys(Bound::Excluded(&11)), vec![12, 14]);
+
+            let page = map.page(Bound::Excluded(&196), 10);
+            assert_eq!(page.len(), 1);
+            assert!(map.page(Bound::Excluded(&198), 10).is_empty());
+            assert!(map.page(Bound::Unbounded, 0).is_empty());
+
+            // paginating through the whole map
+            let mut entries = Vec::new();
+            let mut cursor = None;
+            loop {
+                let from = match &cursor {
+                    Some(key) => Bound::Excluded(key),
+                    None => Bound::Unbounded,
+                };
+                let page = map.page(from, 7);
+                if page.is_empty() {
+                    break;
+                }
+
+                let buf = candid::encode_one(&page).unwrap();
+                entries.extend(candid::decode_one::<Vec<(u64, u64)>>(&buf).unwrap());
+                cursor = page.last_key().map(|it| *it);
+            }
+
+            assert_eq!(entries, heap_state.into_iter().collect::<Vec<_>>());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn serialize_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMap::new();
+            map.insert(2u32, true).unwrap();
+            map.insert(1u32, false).unwrap();
+
+            assert_ser_tokens(
+                &map,
+                &[
+                    Token::Map { len: Some(2) },
+                    Token::U32(1),
+                    Token::Bool(false),
+                    Token::U32(2),
+                    Token::Bool(true),
+                    Token::MapEnd,
+                ],
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn std_conversions_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let heap_state = (0..1000u64)
+                .map(|it| (it, it.to_le_bytes()))
+                .collect::<BTreeMap<_, _>>();
+
+            let map = SBTreeMap::try_from(heap_state.clone()).unwrap();
+            assert_eq!(map.len(), 1000);
+            assert_eq!(*map.get(&500).unwrap(), 500u64.to_le_bytes());
+
+            assert_eq!(map.to_std(), heap_state);
+
+            let empty = SBTreeMap::<u64, u64>::try_from(BTreeMap::new()).unwrap();
+            assert!(empty.to_std().is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
     #[test]
     fn random_works_fine() {
@@ -1932,6 +3127,217 @@ mod tests {
         assert_eq!(get_allocated_size(), 0);
     }
 
+    #[test]
+    fn remove_of_missing_key_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMap::<u64, u64>::default();
+
+            for i in (0..100).step_by(2) {
+                map.insert(i, i).unwrap();
+            }
+
+            for i in (0..100).step_by(2) {
+                // a failed lookup should not leave anything for the next removal to rebalance
+                assert!(map.remove(&(i + 1)).is_none());
+                assert!(map._stack.is_empty());
+
+                assert_eq!(map.remove(&i), Some(i));
+
+                let expected = (i + 2..100).step_by(2).collect::<Vec<_>>();
+                let actual = map.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+
+                assert_eq!(actual, expected);
+            }
+
+            assert!(map.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn remove_range_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        let mut rng = thread_rng();
+
+        for len in [0u64, 1, 10, 15, 16, 100, 1000, 3000] {
+            let mut map = SBTreeMap::<SBox<u64>, SBox<u64>>::default();
+            let mut example = BTreeMap::new();
+
+            for i in 0..len {
+                map.insert(SBox::new(i * 2).unwrap(), SBox::new(i * 2).unwrap())
+                    .unwrap();
+                example.insert(i * 2, i * 2);
+            }
+
+            for _ in 0..20 {
+                let mut random_bound = || match rng.gen_range(0..3) {
+                    0 => Bound::Included(rng.gen_range(0..len * 2 + 2)),
+                    1 => Bound::Excluded(rng.gen_range(0..len * 2 + 2)),
+                    _ => Bound::Unbounded,
+                };
+                let range = (random_bound(), random_bound());
+
+                let expected = example
+                    .keys()
+                    .filter(|it| std::ops::RangeBounds::contains(&range, *it))
+                    .copied()
+                    .collect::<Vec<_>>();
+                for key in &expected {
+                    example.remove(key);
+                }
+
+                assert_eq!(map.remove_range(range), expected.len() as u64);
+                assert_eq!(map.len(), example.len() as u64);
+
+                // the tree should stay valid for further modifications
+                for _ in 0..10 {
+                    let key = rng.gen_range(0..len * 2 + 2);
+
+                    if rng.gen_bool(0.5) {
+                        map.insert(SBox::new(key).unwrap(), SBox::new(key).unwrap())
+                            .unwrap();
+                        example.insert(key, key);
+                    } else {
+                        assert_eq!(map.remove(&key).map(|it| *it), example.remove(&key));
+                    }
+                }
+
+                let actual = map.iter().map(|(k, v)| (**k, **v)).collect::<Vec<_>>();
+                assert_eq!(actual, example.clone().into_iter().collect::<Vec<_>>());
+
+                let actual_rev = map.iter().rev().map(|(k, _)| **k).collect::<Vec<_>>();
+                assert_eq!(
+                    actual_rev,
+                    example.keys().rev().copied().collect::<Vec<_>>()
+                );
+            }
+
+            assert_eq!(map.remove_range::<u64, _>(..), example.len() as u64);
+            assert!(map.is_empty());
+            assert!(map.iter().next().is_none());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn stats_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMap::<u64, u64>::default();
+            assert_eq!(map.stats(), Default::default());
+
+            map.insert(1, 1).unwrap();
+            map.remove(&1);
+
+            let stats = map.stats();
+            assert_eq!(stats.height, 1);
+            assert_eq!(stats.fill_factor, 0.0);
+
+            for i in 0..5000 {
+                map.insert(i, i).unwrap();
+            }
+
+            let stats = map.stats();
+            assert_eq!(stats.height, stats.nodes_per_level.len() as u64);
+            assert_eq!(stats.nodes_per_level[0], 1);
+            assert!(stats.nodes_per_level.windows(2).all(|it| it[0] < it[1]));
+            assert!(stats.fill_factor > 0.45 && stats.fill_factor <= 1.0);
+
+            // the map only owns its nodes
+            assert_eq!(stats.size_bytes, get_allocated_size());
+
+            // leaves, emptied by the removal, are deallocated
+            map.remove_range(..4900);
+
+            let stats_after = map.stats();
+            assert!(stats_after.height < stats.height);
+            assert!(stats_after.fill_factor > 0.45);
+            assert_eq!(stats_after.size_bytes, get_allocated_size());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn sample_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMap::<u64, u64>::new();
+            assert!(map.sample(0, 3).is_empty());
+
+            for i in 0..10 {
+                map.insert(i, i % 4).unwrap();
+            }
+
+            assert_eq!(map.sample(0, 100).len(), 10);
+            assert!(map.sample(0, 0).is_empty());
+
+            let keys = |sample: Vec<(SRef<u64>, SRef<u64>)>| {
+                sample.iter().map(|(k, _)| **k).collect::<Vec<_>>()
+            };
+
+            let mut counts = [0u64; 10];
+            for seed in 0..3000 {
+                let sample = keys(map.sample(seed, 3));
+
+                assert_eq!(sample.len(), 3);
+                assert!(sample.windows(2).all(|it| it[0] < it[1]));
+                assert_eq!(sample, keys(map.sample(seed, 3)));
+
+                for k in sample {
+                    counts[k as usize] += 1;
+                }
+            }
+
+            // each key is expected to be picked 900 times
+            assert!(
+                counts.iter().all(|it| (750..1050).contains(it)),
+                "{:?}",
+                counts
+            );
+
+            let mut counts = [0u64; 10];
+            for seed in 0..4000 {
+                let sample = keys(map.sample_weighted(seed, 1, |v| *v));
+
+                assert_eq!(sample, keys(map.sample_weighted(seed, 1, |v| *v)));
+
+                counts[sample[0] as usize] += 1;
+            }
+
+            // weights are 0, 1, 2, 3, 0, 1, 2, 3, 0, 1 - 12 in total
+            for (k, count) in counts.iter().enumerate() {
+                let expected = 4000 * (k as u64 % 4) / 12;
+
+                assert!(
+                    (expected * 3 / 4..=expected * 5 / 4).contains(count),
+                    "{:?}",
+                    counts
+                );
+            }
+
+            let sample = keys(map.sample_weighted(0, 100, |v| *v));
+            assert_eq!(sample, vec![1, 2, 3, 5, 6, 7, 9]);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[test]
     fn iters_work_fine() {
         stable::clear();
@@ -1960,6 +3366,59 @@ mod tests {
             assert_eq!(i, 200);
         }
 
+        {
+            let mut map = SBTreeMap::<u64, u64>::default();
+
+            // splits leaves in the middle of the tree, which should keep backward links valid
+            for i in (0..200).rev() {
+                map.insert(i, i).unwrap();
+            }
+
+            let keys = map.iter().rev().map(|(k, _)| *k).collect::<Vec<_>>();
+            assert_eq!(keys, (0..200).rev().collect::<Vec<_>>());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn into_iter_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let map = SBTreeMap::<u64, u64>::default();
+            assert_eq!(map.into_iter().count(), 0);
+
+            let mut map = SBTreeMap::default();
+            for i in 0..500u64 {
+                map.insert(SBox::new(i).unwrap(), SBox::new(i * 2).unwrap())
+                    .unwrap();
+            }
+
+            let res = map
+                .into_iter()
+                .map(|(k, v)| (k.into_inner(), v.into_inner()))
+                .collect::<Vec<_>>();
+            assert_eq!(res, (0..500).map(|i| (i, i * 2)).collect::<Vec<_>>());
+
+            let mut map = SBTreeMap::default();
+            for i in 0..500u64 {
+                map.insert(SBox::new(i).unwrap(), SBox::new(i).unwrap())
+                    .unwrap();
+            }
+
+            let mut iter = map.into_iter();
+            for i in 0..100 {
+                let (k, v) = iter.next().unwrap();
+
+                assert_eq!(*k, i);
+                assert_eq!(*v, i);
+            }
+            assert_eq!(iter.size_hint(), (400, Some(400)));
+        }
+
         _debug_validate_allocator();
         assert_eq!(get_allocated_size(), 0);
     }
@@ -1984,6 +3443,67 @@ mod tests {
         assert_eq!(get_allocated_size(), 0);
     }
 
+    #[test]
+    fn try_clone_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMap::new();
+            for i in 0..500u64 {
+                map.insert(SBox::new(i).unwrap(), SBox::new(i.to_string()).unwrap())
+                    .unwrap();
+            }
+
+            let copy = map.try_clone().unwrap();
+            drop(map);
+
+            assert_eq!(copy.len(), 500);
+            for (i, (k, v)) in copy.iter().enumerate() {
+                assert_eq!(**k, i as u64);
+                assert_eq!(**v, i.to_string());
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn iter_prefix_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut map = SBTreeMap::<(u32, u64), u64>::default();
+            let mut example = BTreeMap::new();
+
+            for i in 0..3000u64 {
+                let key = (rng.gen_range(0..50u32), rng.gen::<u64>());
+
+                map.insert(key, i).unwrap();
+                example.insert(key, i);
+            }
+
+            for user in 0..51u32 {
+                let actual = map
+                    .iter_prefix(&user)
+                    .map(|(k, v)| (*k, *v))
+                    .collect::<Vec<_>>();
+                let expected = example
+                    .range((user, 0)..=(user, u64::MAX))
+                    .map(|(k, v)| (*k, *v))
+                    .collect::<Vec<_>>();
+
+                assert_eq!(actual, expected);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[derive(Debug)]
     enum Action {
         Insert,
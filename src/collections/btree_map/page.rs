@@ -0,0 +1,89 @@
+use crate::collections::btree_map::SBTreeMap;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use candid::types::{Compound, Serializer, Type};
+use candid::CandidType;
+use std::ops::Bound;
+
+/// A bounded page of [SBTreeMap] entries, returned by [SBTreeMap::page]
+///
+/// Does not copy anything - entries are read from stable memory only when the page is iterated or
+/// encoded. Implements [CandidType] exactly as `Vec<(K, V)>` (`vec record { K; V }`), so it can be
+/// replied from a query method as is.
+pub struct SBTreeMapPage<
+    'a,
+    K: StableType + AsFixedSizeBytes + Ord,
+    V: StableType + AsFixedSizeBytes,
+> {
+    map: &'a SBTreeMap<K, V>,
+    from: Bound<&'a K>,
+    limit: usize,
+}
+
+impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes>
+    SBTreeMapPage<'a, K, V>
+{
+    #[inline]
+    pub(crate) fn new(map: &'a SBTreeMap<K, V>, from: Bound<&'a K>, limit: usize) -> Self {
+        Self { map, from, limit }
+    }
+
+    /// Returns an iterator over entries of this page
+    pub fn iter(&self) -> impl Iterator<Item = (SRef<'a, K>, SRef<'a, V>)> + 'a {
+        let (iter, excluded) = match self.from {
+            Bound::Included(key) => (self.map.iter_from(key), None),
+            Bound::Excluded(key) => (self.map.iter_from(key), Some(key)),
+            Bound::Unbounded => (self.map.iter(), None),
+        };
+
+        iter.skip_while(move |(k, _)| excluded.map(|it| **k == *it).unwrap_or_default())
+            .take(self.limit)
+    }
+
+    /// Returns the number of entries in this page
+    ///
+    /// This operation is O(limit), since entries are not cached.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Returns [true] if there are no entries in this page
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Returns the key of the last entry of this page
+    ///
+    /// Pass it as [Bound::Excluded] to [SBTreeMap::page] to get the next page.
+    #[inline]
+    pub fn last_key(&self) -> Option<SRef<'a, K>> {
+        self.iter().last().map(|(k, _)| k)
+    }
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Ord + CandidType,
+        V: StableType + AsFixedSizeBytes + CandidType,
+    > CandidType for SBTreeMapPage<'a, K, V>
+{
+    #[inline]
+    fn _ty() -> Type {
+        <Vec<(K, V)>>::_ty()
+    }
+
+    fn idl_serialize<S>(&self, serializer: S) -> Result<(), S::Error>
+    where
+        S: Serializer,
+    {
+        let mut ser = serializer.serialize_vec(self.len())?;
+        for (k, v) in self.iter() {
+            Compound::serialize_element(&mut ser, &(&*k, &*v))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+use candid::{CandidType, Deserialize};
+
+/// Shape of a [SBTreeMap](crate::collections::SBTreeMap), returned by
+/// [SBTreeMap::stats](crate::collections::SBTreeMap::stats)
+///
+/// Useful to tune key sizes or to check, that a bulk operation left nodes dense. Implements
+/// [CandidType], so it can be returned from a metrics query as is.
+#[derive(Debug, Default, Clone, PartialEq, CandidType, Deserialize)]
+pub struct SBTreeMapStats {
+    /// The number of levels in the tree, `0` for a tree without nodes
+    pub height: u64,
+    /// The number of nodes on each level, starting from the root - the last level holds leaves
+    pub nodes_per_level: Vec<u64>,
+    /// How full nodes are on average, from `0.0` to `1.0` - the total number of keys in all nodes,
+    /// divided by the total capacity of all nodes
+    pub fill_factor: f64,
+    /// The total size of memory blocks, occupied by nodes, in bytes, including memory block
+    /// metadata - memory, owned by keys or values themselves (e.g. [SBox](crate::SBox)), is not
+    /// counted
+    pub size_bytes: u64,
+}
@@ -0,0 +1,43 @@
+use crate::collections::btree_map::iter::SBTreeMapIter;
+use crate::collections::btree_map_by::{ByKey, Comparator, SBTreeMapBy};
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+
+pub struct SBTreeMapByIter<'a, K, V, C> {
+    iter: SBTreeMapIter<'a, ByKey<K, C>, V>,
+}
+
+impl<'a, K: StableType + AsFixedSizeBytes, V: StableType + AsFixedSizeBytes, C: Comparator<K>>
+    SBTreeMapByIter<'a, K, V, C>
+{
+    pub fn new(map: &'a SBTreeMapBy<K, V, C>) -> Self {
+        Self {
+            iter: SBTreeMapIter::new(&map.map),
+        }
+    }
+}
+
+// ByKey is transparent, so a reference to it is also a valid reference to the key itself
+#[inline]
+fn unwrap_key<'a, K, C>(it: SRef<'a, ByKey<K, C>>) -> SRef<'a, K> {
+    unsafe { SRef::new(it.as_ptr()) }
+}
+
+impl<'a, K: StableType + AsFixedSizeBytes, V: StableType + AsFixedSizeBytes, C: Comparator<K>>
+    Iterator for SBTreeMapByIter<'a, K, V, C>
+{
+    type Item = (SRef<'a, K>, SRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(k, v)| (unwrap_key(k), v))
+    }
+}
+
+impl<'a, K: StableType + AsFixedSizeBytes, V: StableType + AsFixedSizeBytes, C: Comparator<K>>
+    DoubleEndedIterator for SBTreeMapByIter<'a, K, V, C>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.iter.next_back().map(|(k, v)| (unwrap_key(k), v))
+    }
+}
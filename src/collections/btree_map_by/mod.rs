@@ -0,0 +1,463 @@
+use crate::collections::btree_map::SBTreeMap;
+use crate::collections::btree_map_by::iter::SBTreeMapByIter;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+pub mod iter;
+
+/// Ordering of keys of a [SBTreeMapBy]
+///
+/// Implemented by a zero-sized type, which never gets stored anywhere - only its `cmp` function is used.
+/// This allows to order keys differently from their [Ord] impl (e.g. case-insensitive or locale-aware
+/// strings), without wrapping them into a newtype.
+///
+/// If the map is queried by a borrowed form `Q` of its key type `K`, the comparator has to be
+/// implemented for `Q` too and both impls should order values the same way.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::Comparator;
+/// # use std::cmp::Ordering;
+/// struct CaseInsensitive;
+///
+/// impl Comparator<String> for CaseInsensitive {
+///     fn cmp(a: &String, b: &String) -> Ordering {
+///         a.to_lowercase().cmp(&b.to_lowercase())
+///     }
+/// }
+/// ```
+pub trait Comparator<T: ?Sized> {
+    fn cmp(a: &T, b: &T) -> Ordering;
+}
+
+/// Stored form of a key - has exactly the same encoding as `K`, but is ordered by `C`
+#[repr(transparent)]
+pub struct ByKey<K, C>(K, PhantomData<C>);
+
+/// Borrowed form of a key, used for lookups - ordered by `C`
+#[repr(transparent)]
+pub struct ByRef<Q: ?Sized, C>(PhantomData<C>, Q);
+
+impl<Q: ?Sized, C> ByRef<Q, C> {
+    #[inline]
+    fn from_ref(it: &Q) -> &Self {
+        // this is safe, since ByRef is transparent
+        unsafe { &*(it as *const Q as *const Self) }
+    }
+}
+
+impl<K: Borrow<Q>, Q: ?Sized, C> Borrow<ByRef<Q, C>> for ByKey<K, C> {
+    #[inline]
+    fn borrow(&self) -> &ByRef<Q, C> {
+        ByRef::from_ref(self.0.borrow())
+    }
+}
+
+impl<K, C: Comparator<K>> PartialEq for ByKey<K, C> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        C::cmp(&self.0, &other.0).is_eq()
+    }
+}
+
+impl<K, C: Comparator<K>> Eq for ByKey<K, C> {}
+
+impl<K, C: Comparator<K>> PartialOrd for ByKey<K, C> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K, C: Comparator<K>> Ord for ByKey<K, C> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        C::cmp(&self.0, &other.0)
+    }
+}
+
+impl<Q: ?Sized, C: Comparator<Q>> PartialEq for ByRef<Q, C> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        C::cmp(&self.1, &other.1).is_eq()
+    }
+}
+
+impl<Q: ?Sized, C: Comparator<Q>> Eq for ByRef<Q, C> {}
+
+impl<Q: ?Sized, C: Comparator<Q>> PartialOrd for ByRef<Q, C> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<Q: ?Sized, C: Comparator<Q>> Ord for ByRef<Q, C> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        C::cmp(&self.1, &other.1)
+    }
+}
+
+impl<K: AsFixedSizeBytes, C> AsFixedSizeBytes for ByKey<K, C> {
+    const SIZE: usize = K::SIZE;
+    type Buf = K::Buf;
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.0.as_fixed_size_bytes(buf);
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        Self(K::from_fixed_size_bytes(buf), PhantomData)
+    }
+}
+
+impl<K: StableType, C> StableType for ByKey<K, C> {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.0.stable_drop_flag_off();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.0.stable_drop_flag_on();
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.0.should_stable_drop()
+    }
+
+    #[inline]
+    unsafe fn stable_drop(&mut self) {
+        self.0.stable_drop();
+    }
+}
+
+/// B-plus tree based map data structure, ordered by a [Comparator]
+///
+/// This is just a wrapper around [SBTreeMap], read its documentation for more info on the internals.
+/// The only difference is that keys are ordered by `C`, instead of their own [Ord] impl - `K` doesn't
+/// even have to implement [Ord]. Keys are stored exactly the same way, as they would be stored in
+/// [SBTreeMap].
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::{Comparator, SBTreeMapBy};
+/// # use ic_stable_memory::stable_memory_init;
+/// # use std::cmp::Ordering;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// struct Descending;
+///
+/// impl Comparator<u64> for Descending {
+///     fn cmp(a: &u64, b: &u64) -> Ordering {
+///         b.cmp(a)
+///     }
+/// }
+///
+/// let mut map = SBTreeMapBy::<u64, u64, Descending>::new();
+///
+/// map.insert(1, 10).expect("Out of memory");
+/// map.insert(2, 20).expect("Out of memory");
+///
+/// let keys: Vec<u64> = map.iter().map(|(k, _)| *k).collect();
+/// assert_eq!(keys, vec![2, 1]);
+/// ```
+pub struct SBTreeMapBy<K, V, C>
+where
+    K: StableType + AsFixedSizeBytes,
+    V: StableType + AsFixedSizeBytes,
+    C: Comparator<K>,
+{
+    map: SBTreeMap<ByKey<K, C>, V>,
+}
+
+impl<K: StableType + AsFixedSizeBytes, V: StableType + AsFixedSizeBytes, C: Comparator<K>>
+    SBTreeMapBy<K, V, C>
+{
+    /// See [SBTreeMap::new]
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            map: SBTreeMap::new(),
+        }
+    }
+
+    /// See [SBTreeMap::insert]
+    #[inline]
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        self.map
+            .insert(ByKey(key, PhantomData), value)
+            .map_err(|(k, v)| (k.0, v))
+    }
+
+    /// See [SBTreeMap::remove]
+    #[inline]
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.map.remove(ByRef::<Q, C>::from_ref(key))
+    }
+
+    /// See [SBTreeMap::get]
+    #[inline]
+    pub fn get<Q>(&self, key: &Q) -> Option<SRef<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.map.get(ByRef::<Q, C>::from_ref(key))
+    }
+
+    /// See [SBTreeMap::get_mut]
+    #[inline]
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<SRefMut<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.map.get_mut(ByRef::<Q, C>::from_ref(key))
+    }
+
+    /// See [SBTreeMap::contains_key]
+    #[inline]
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        C: Comparator<Q>,
+    {
+        self.map.contains_key(ByRef::<Q, C>::from_ref(key))
+    }
+
+    /// See [SBTreeMap::iter]
+    #[inline]
+    pub fn iter(&self) -> SBTreeMapByIter<'_, K, V, C> {
+        SBTreeMapByIter::new(self)
+    }
+
+    /// See [SBTreeMap::len]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    /// See [SBTreeMap::is_empty]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// See [SBTreeMap::clear]
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes, V: StableType + AsFixedSizeBytes, C: Comparator<K>> Default
+    for SBTreeMapBy<K, V, C>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes, V: StableType + AsFixedSizeBytes, C: Comparator<K>>
+    AsFixedSizeBytes for SBTreeMapBy<K, V, C>
+{
+    const SIZE: usize = SBTreeMap::<ByKey<K, C>, V>::SIZE;
+    type Buf = <SBTreeMap<ByKey<K, C>, V> as AsFixedSizeBytes>::Buf;
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.map.as_fixed_size_bytes(buf);
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        let map = SBTreeMap::<ByKey<K, C>, V>::from_fixed_size_bytes(arr);
+        Self { map }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes, V: StableType + AsFixedSizeBytes, C: Comparator<K>>
+    StableType for SBTreeMapBy<K, V, C>
+{
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.map.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.map.stable_drop_flag_off();
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Debug,
+        V: StableType + AsFixedSizeBytes + Debug,
+        C: Comparator<K>,
+    > Debug for SBTreeMapBy<K, V, C>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("{")?;
+        for (idx, (k, v)) in self.iter().enumerate() {
+            k.fmt(f)?;
+            f.write_str(": ")?;
+            v.fmt(f)?;
+
+            if idx < (self.len() - 1) as usize {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::btree_map_by::{Comparator, SBTreeMapBy};
+    use crate::encoding::{AsFixedSizeBytes, Buffer};
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, retrieve_custom_data, stable,
+        stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
+        store_custom_data, SBox,
+    };
+    use std::borrow::Borrow;
+    use std::cmp::Ordering;
+
+    struct CaseInsensitive;
+
+    impl<T: Borrow<String>> Comparator<T> for CaseInsensitive {
+        fn cmp(a: &T, b: &T) -> Ordering {
+            a.borrow().to_lowercase().cmp(&b.borrow().to_lowercase())
+        }
+    }
+
+    struct Descending;
+
+    impl Comparator<u64> for Descending {
+        fn cmp(a: &u64, b: &u64) -> Ordering {
+            b.cmp(a)
+        }
+    }
+
+    #[test]
+    fn it_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMapBy::<SBox<String>, u64, CaseInsensitive>::new();
+
+            for (idx, key) in ["banana", "Apple", "cherry", "BANANA", "apple"]
+                .into_iter()
+                .enumerate()
+            {
+                map.insert(SBox::new(key.to_string()).unwrap(), idx as u64)
+                    .unwrap();
+            }
+
+            assert_eq!(map.len(), 3);
+            assert_eq!(*map.get(&String::from("APPLE")).unwrap(), 4);
+            assert_eq!(*map.get(&String::from("Banana")).unwrap(), 3);
+            assert!(map.contains_key(&String::from("CHERRY")));
+            assert!(!map.contains_key(&String::from("date")));
+
+            *map.get_mut(&String::from("cherry")).unwrap() = 10;
+            assert_eq!(*map.get(&String::from("Cherry")).unwrap(), 10);
+
+            let keys: Vec<String> = map.iter().map(|(k, _)| (**k).clone()).collect();
+            assert_eq!(keys, vec!["Apple", "banana", "cherry"]);
+
+            assert_eq!(map.remove(&String::from("BaNaNa")), Some(3));
+            assert_eq!(map.remove(&String::from("banana")), None);
+            assert_eq!(map.len(), 2);
+
+            map.clear();
+            assert!(map.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn ordering_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMapBy::<u64, u64, Descending>::default();
+            for i in 0..500 {
+                map.insert(i, i * 2).unwrap();
+            }
+
+            for i in (0..500).step_by(3) {
+                assert_eq!(map.remove(&i), Some(i * 2));
+            }
+
+            let expected: Vec<u64> = (0..500).rev().filter(|it| it % 3 != 0).collect();
+
+            let keys: Vec<u64> = map.iter().map(|(k, _)| *k).collect();
+            assert_eq!(keys, expected);
+
+            let keys: Vec<u64> = map.iter().rev().map(|(k, _)| *k).collect();
+            assert_eq!(keys, expected.into_iter().rev().collect::<Vec<_>>());
+
+            let buf = map.as_new_fixed_size_bytes();
+            let map_copy = SBTreeMapBy::<u64, u64, Descending>::from_fixed_size_bytes(buf._deref());
+            assert_eq!(map_copy.len(), map.len());
+            std::mem::forget(map_copy);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn survives_upgrade() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMapBy::<SBox<String>, u64, CaseInsensitive>::new();
+            map.insert(SBox::new(String::from("B")).unwrap(), 2)
+                .unwrap();
+            map.insert(SBox::new(String::from("a")).unwrap(), 1)
+                .unwrap();
+
+            store_custom_data(1, SBox::new(map).unwrap());
+
+            stable_memory_pre_upgrade().unwrap();
+            stable_memory_post_upgrade();
+
+            let map = retrieve_custom_data::<SBTreeMapBy<SBox<String>, u64, CaseInsensitive>>(1)
+                .unwrap()
+                .into_inner();
+
+            assert_eq!(*map.get(&String::from("A")).unwrap(), 1);
+            assert_eq!(*map.get(&String::from("b")).unwrap(), 2);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
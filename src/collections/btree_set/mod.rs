@@ -2,7 +2,8 @@ use crate::collections::btree_map::SBTreeMap;
 use crate::collections::btree_set::iter::SBTreeSetIter;
 use crate::encoding::AsFixedSizeBytes;
 use crate::primitive::s_ref::SRef;
-use crate::primitive::StableType;
+use crate::primitive::{StableType, TryClone};
+use crate::OutOfMemory;
 use std::borrow::Borrow;
 use std::fmt::{Debug, Formatter};
 
@@ -84,6 +85,16 @@ impl<T: Ord + StableType + AsFixedSizeBytes> SBTreeSet<T> {
     }
 }
 
+impl<T: Ord + StableType + AsFixedSizeBytes + TryClone> TryClone for SBTreeSet<T> {
+    /// See [SBTreeMap::try_clone]
+    #[inline]
+    fn try_clone(&self) -> Result<Self, OutOfMemory> {
+        Ok(Self {
+            map: self.map.try_clone()?,
+        })
+    }
+}
+
 impl<T: Ord + StableType + AsFixedSizeBytes> Default for SBTreeSet<T> {
     #[inline]
     fn default() -> Self {
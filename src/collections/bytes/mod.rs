@@ -0,0 +1,526 @@
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::primitive::{StableType, TryClone};
+use crate::{allocate, deallocate, OutOfMemory};
+use std::fmt::{Debug, Formatter};
+use std::io::{Read, Seek, SeekFrom};
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+/// Raw fixed-length region of bytes in stable memory
+///
+/// Unlike `SBox<Vec<u8>>` or `SVec<u8>`, [SBytes] doesn't interpret its content in any way - it is
+/// just a single memory block, which can be read and written at arbitrary offsets. This makes it a
+/// good foundation for custom binary formats (an index, a blob store, a file system), which don't
+/// want to copy the whole region to the heap each time a few bytes are needed.
+///
+/// Parts of the region can be accessed without copying with [SBytes::slice], which returns a
+/// lightweight [SBytesRef] view. Views can be read with [SBytesRef::read_at], copied into another
+/// region with [SBytesRef::copy_to], or streamed with [SBytesRef::as_reader], which implements
+/// [std::io::Read] and [std::io::Seek].
+///
+/// [SBytes] implements both [StableType] and [AsFixedSizeBytes] and can be nested inside other
+/// stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SBytes;
+/// # use ic_stable_memory::stable_memory_init;
+/// # use std::io::Read;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut region = SBytes::new(1024).expect("Out of memory");
+///
+/// region.write_at(100, b"header");
+///
+/// let mut buf = [0u8; 6];
+/// region.read_at(100, &mut buf);
+/// assert_eq!(&buf, b"header");
+///
+/// let mut content = String::new();
+/// region.slice(100..106).as_reader().read_to_string(&mut content).unwrap();
+/// assert_eq!(content, "header");
+/// ```
+pub struct SBytes {
+    ptr: StablePtr,
+    len: u64,
+    stable_drop_flag: bool,
+}
+
+impl SBytes {
+    /// Allocates a new region of `len` zero bytes
+    ///
+    /// Empty regions don't allocate any stable memory.
+    pub fn new(len: u64) -> Result<Self, OutOfMemory> {
+        let it = unsafe { Self::allocate(len)? };
+
+        if len > 0 {
+            unsafe { crate::mem::write_zeroes(it.data_ptr(), len) };
+        }
+
+        Ok(it)
+    }
+
+    /// Allocates a new region, containing a copy of the provided bytes
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, OutOfMemory> {
+        let mut it = unsafe { Self::allocate(bytes.len() as u64)? };
+        it.write_at(0, bytes);
+
+        Ok(it)
+    }
+
+    unsafe fn allocate(len: u64) -> Result<Self, OutOfMemory> {
+        let ptr = if len > 0 {
+            allocate(len)?.as_ptr()
+        } else {
+            EMPTY_PTR
+        };
+
+        Ok(Self {
+            ptr,
+            len,
+            stable_drop_flag: true,
+        })
+    }
+
+    #[inline]
+    fn data_ptr(&self) -> StablePtr {
+        SSlice::_offset(self.ptr, 0)
+    }
+
+    /// Returns the length of this region in bytes
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if this region is empty
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a view of the whole region
+    #[inline]
+    pub fn as_bytes_ref(&self) -> SBytesRef<'_> {
+        if self.is_empty() {
+            SBytesRef::new(EMPTY_PTR, 0)
+        } else {
+            SBytesRef::new(self.data_ptr(), self.len)
+        }
+    }
+
+    /// Returns a view of a part of this region
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds.
+    #[inline]
+    pub fn slice<R: RangeBounds<u64>>(&self, range: R) -> SBytesRef<'_> {
+        self.as_bytes_ref().slice(range)
+    }
+
+    /// Fills the buffer with bytes, starting from the provided offset, see [SBytesRef::read_at]
+    #[inline]
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) {
+        self.as_bytes_ref().read_at(offset, buf)
+    }
+
+    /// Overwrites bytes, starting from the provided offset, with the content of the buffer
+    ///
+    /// # Panics
+    /// Panics if `offset + buf.len()` is bigger than the length of this region.
+    pub fn write_at(&mut self, offset: u64, buf: &[u8]) {
+        check_bounds(offset, buf.len() as u64, self.len);
+
+        if !buf.is_empty() {
+            unsafe { crate::mem::write_bytes(self.data_ptr() + offset, buf) };
+        }
+    }
+
+    /// Copies the whole region into another one, see [SBytesRef::copy_to]
+    #[inline]
+    pub fn copy_to(&self, target: &mut SBytes, offset: u64) {
+        self.as_bytes_ref().copy_to(target, offset)
+    }
+
+    /// Returns a reader of the whole region, see [SBytesRef::as_reader]
+    #[inline]
+    pub fn as_reader(&self) -> SBytesReader<'_> {
+        self.as_bytes_ref().as_reader()
+    }
+
+    /// Copies the content of this region into a heap [Vec] of bytes
+    #[inline]
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.as_bytes_ref().to_vec()
+    }
+}
+
+impl TryClone for SBytes {
+    fn try_clone(&self) -> Result<Self, OutOfMemory> {
+        let mut it = unsafe { Self::allocate(self.len)? };
+        self.copy_to(&mut it, 0);
+
+        Ok(it)
+    }
+}
+
+impl Default for SBytes {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            ptr: EMPTY_PTR,
+            len: 0,
+            stable_drop_flag: true,
+        }
+    }
+}
+
+impl Debug for SBytes {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.as_bytes_ref(), f)
+    }
+}
+
+impl AsFixedSizeBytes for SBytes {
+    const SIZE: usize = u64::SIZE * 2;
+    type Buf = [u8; u64::SIZE * 2];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+        self.len
+            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE * 2)]);
+    }
+
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        let ptr = u64::from_fixed_size_bytes(&arr[0..u64::SIZE]);
+        let len = u64::from_fixed_size_bytes(&arr[u64::SIZE..(u64::SIZE * 2)]);
+
+        Self {
+            ptr,
+            len,
+            stable_drop_flag: false,
+        }
+    }
+}
+
+impl StableType for SBytes {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    unsafe fn stable_drop(&mut self) {
+        if self.ptr != EMPTY_PTR {
+            deallocate(SSlice::from_ptr(self.ptr).unwrap());
+        }
+    }
+}
+
+impl Drop for SBytes {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+/// Immutable view of a part of an [SBytes]
+///
+/// Does not hold any data by itself, only a pointer and a length, so it is cheap to create and to
+/// narrow down with [SBytesRef::slice].
+#[derive(Copy, Clone)]
+pub struct SBytesRef<'a> {
+    ptr: StablePtr,
+    len: u64,
+    _marker: PhantomData<&'a SBytes>,
+}
+
+impl<'a> SBytesRef<'a> {
+    #[inline]
+    fn new(ptr: StablePtr, len: u64) -> Self {
+        Self {
+            ptr,
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the length of this view in bytes
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if this view is empty
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a narrower view
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds.
+    pub fn slice<R: RangeBounds<u64>>(&self, range: R) -> SBytesRef<'a> {
+        let start = match range.start_bound() {
+            Bound::Included(s) => *s,
+            Bound::Excluded(s) => *s + 1,
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(e) => *e + 1,
+            Bound::Excluded(e) => *e,
+            Bound::Unbounded => self.len,
+        };
+
+        assert!(
+            start <= end && end <= self.len,
+            "byte range {}..{} is out of bounds of region of length {}",
+            start,
+            end,
+            self.len
+        );
+
+        if start == end {
+            SBytesRef::new(EMPTY_PTR, 0)
+        } else {
+            SBytesRef::new(self.ptr + start, end - start)
+        }
+    }
+
+    /// Fills the buffer with bytes of this view, starting from the provided offset
+    ///
+    /// Reads directly from stable memory, without any intermediate heap buffers.
+    ///
+    /// # Panics
+    /// Panics if `offset + buf.len()` is bigger than the length of this view.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) {
+        check_bounds(offset, buf.len() as u64, self.len);
+
+        if !buf.is_empty() {
+            unsafe { crate::mem::read_bytes(self.ptr + offset, buf) };
+        }
+    }
+
+    /// Copies this view into another region, starting from the provided offset
+    ///
+    /// Copies in small batches, so it doesn't need a heap buffer of the size of the view.
+    ///
+    /// # Panics
+    /// Panics if `offset + self.len()` is bigger than the length of the target region.
+    pub fn copy_to(&self, target: &mut SBytes, offset: u64) {
+        check_bounds(offset, self.len, target.len);
+
+        if !self.is_empty() {
+            unsafe { crate::mem::copy_bytes(self.ptr, target.data_ptr() + offset, self.len) };
+        }
+    }
+
+    /// Returns a reader of this view, implementing [std::io::Read] and [std::io::Seek]
+    #[inline]
+    pub fn as_reader(&self) -> SBytesReader<'a> {
+        SBytesReader {
+            bytes: *self,
+            pos: 0,
+        }
+    }
+
+    /// Copies the content of this view into a heap [Vec] of bytes
+    #[inline]
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; self.len as usize];
+        self.read_at(0, &mut buf);
+
+        buf
+    }
+}
+
+impl<'a> Debug for SBytesRef<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SBytes({} bytes)", self.len)
+    }
+}
+
+/// Cursor over an [SBytesRef], implementing [std::io::Read] and [std::io::Seek]
+///
+/// Reads past the end of the view return zero bytes, seeking before the start returns an error.
+#[derive(Copy, Clone, Debug)]
+pub struct SBytesReader<'a> {
+    bytes: SBytesRef<'a>,
+    pos: u64,
+}
+
+impl<'a> SBytesReader<'a> {
+    /// Returns the current position of this reader
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+}
+
+impl<'a> Read for SBytesReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.bytes.len.saturating_sub(self.pos);
+        let n = (buf.len() as u64).min(remaining) as usize;
+
+        self.bytes.read_at(self.pos, &mut buf[..n]);
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl<'a> Seek for SBytesReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.bytes.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+
+        match new_pos {
+            Some(p) => {
+                self.pos = p;
+
+                Ok(p)
+            }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[inline]
+fn check_bounds(offset: u64, len: u64, total: u64) {
+    assert!(
+        offset
+            .checked_add(len)
+            .map(|end| end <= total)
+            .unwrap_or_default(),
+        "byte range {}..{} is out of bounds of region of length {}",
+        offset,
+        offset.saturating_add(len),
+        total
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::bytes::SBytes;
+    use crate::collections::SVec;
+    use crate::encoding::AsFixedSizeBytes;
+    use crate::primitive::TryClone;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init};
+    use std::io::{Read, Seek, SeekFrom};
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut bytes = SBytes::new(1000).unwrap();
+            assert_eq!(bytes.len(), 1000);
+            assert_eq!(bytes.to_vec(), vec![0u8; 1000]);
+
+            bytes.write_at(10, &[1, 2, 3, 4, 5]);
+
+            let mut buf = [0u8; 7];
+            bytes.read_at(9, &mut buf);
+            assert_eq!(buf, [0, 1, 2, 3, 4, 5, 0]);
+
+            let view = bytes.slice(10..15);
+            assert_eq!(view.len(), 5);
+            assert_eq!(view.to_vec(), vec![1, 2, 3, 4, 5]);
+            assert_eq!(view.slice(1..=2).to_vec(), vec![2, 3]);
+            assert!(view.slice(5..).is_empty());
+
+            let mut other = SBytes::from_slice(&[9u8; 10]).unwrap();
+            view.copy_to(&mut other, 5);
+            assert_eq!(other.to_vec(), vec![9, 9, 9, 9, 9, 1, 2, 3, 4, 5]);
+
+            let copy = other.try_clone().unwrap();
+            other.write_at(0, &[0]);
+            assert_eq!(copy.to_vec(), vec![9, 9, 9, 9, 9, 1, 2, 3, 4, 5]);
+
+            let empty = SBytes::default();
+            assert!(empty.is_empty());
+            assert!(empty.to_vec().is_empty());
+            empty.copy_to(&mut other, 10);
+
+            let mut vec = SVec::new();
+            vec.push(bytes).unwrap();
+            vec.push(SBytes::new(0).unwrap()).unwrap();
+
+            let buf = vec.get(0).unwrap().as_new_fixed_size_bytes();
+            let bytes = SBytes::from_fixed_size_bytes(&buf);
+            assert_eq!(bytes.slice(10..15).to_vec(), vec![1, 2, 3, 4, 5]);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_bounds_panics() {
+        stable::clear();
+        stable_memory_init();
+
+        let mut bytes = SBytes::new(10).unwrap();
+        bytes.write_at(8, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn reader_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let content = (0..10_000u32).map(|it| it as u8).collect::<Vec<_>>();
+            let bytes = SBytes::from_slice(&content).unwrap();
+
+            let mut buf = Vec::new();
+            bytes.as_reader().read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, content);
+
+            let mut reader = bytes.slice(100..200).as_reader();
+            let mut buf = [0u8; 10];
+
+            reader.seek(SeekFrom::Start(90)).unwrap();
+            assert_eq!(reader.read(&mut buf).unwrap(), 10);
+            assert_eq!(buf.as_slice(), &content[190..200]);
+            assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+            assert_eq!(reader.seek(SeekFrom::End(-50)).unwrap(), 50);
+            assert_eq!(reader.seek(SeekFrom::Current(-10)).unwrap(), 40);
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf.as_slice(), &content[140..150]);
+            assert_eq!(reader.position(), 50);
+
+            assert!(reader.seek(SeekFrom::Current(-100)).is_err());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
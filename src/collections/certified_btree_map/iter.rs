@@ -0,0 +1,100 @@
+use crate::collections::btree_map::iter::SBTreeMapIter;
+use crate::collections::certified_btree_map::SCertifiedBTreeMap;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use crate::utils::certification::{AsHashTree, AsHashableBytes, HashTree};
+use std::borrow::Borrow;
+use std::iter::FusedIterator;
+use std::ops::Deref;
+
+pub struct SCertifiedBTreeMapRangeIter<
+    'a,
+    Q: Ord + ?Sized,
+    K: StableType + AsFixedSizeBytes + Ord + AsHashableBytes + Borrow<Q>,
+    V: StableType + AsFixedSizeBytes + AsHashTree,
+> {
+    map: &'a SCertifiedBTreeMap<K, V>,
+    iter: SBTreeMapIter<'a, K, V>,
+    from: &'a Q,
+    to: &'a Q,
+    first: Option<SRef<'a, K>>,
+    last: Option<SRef<'a, K>>,
+    finished: bool,
+}
+
+impl<
+        'a,
+        Q: Ord + ?Sized,
+        K: StableType + AsFixedSizeBytes + Ord + AsHashableBytes + Borrow<Q>,
+        V: StableType + AsFixedSizeBytes + AsHashTree,
+    > SCertifiedBTreeMapRangeIter<'a, Q, K, V>
+{
+    pub(crate) fn new(map: &'a SCertifiedBTreeMap<K, V>, from: &'a Q, to: &'a Q) -> Self {
+        Self {
+            map,
+            iter: map.inner.iter_from(from),
+            from,
+            to,
+            first: None,
+            last: None,
+            finished: false,
+        }
+    }
+
+    /// Constructs a Merkle proof that includes exactly the keys visited by this iterator so far
+    ///
+    /// If no keys were visited yet, constructs a proof for the whole requested range, the same way
+    /// [SCertifiedBTreeMap::prove_range] does.
+    pub fn witness(&self) -> HashTree {
+        match (&self.first, &self.last) {
+            (Some(first), Some(last)) => self.map.prove_range::<K>(first.deref(), last.deref()),
+            _ => self.map.prove_range(self.from, self.to),
+        }
+    }
+}
+
+impl<
+        'a,
+        Q: Ord + ?Sized,
+        K: StableType + AsFixedSizeBytes + Ord + AsHashableBytes + Borrow<Q>,
+        V: StableType + AsFixedSizeBytes + AsHashTree,
+    > Iterator for SCertifiedBTreeMapRangeIter<'a, Q, K, V>
+{
+    type Item = (SRef<'a, K>, SRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let (k, v) = match self.iter.next() {
+            Some(it) => it,
+            None => {
+                self.finished = true;
+                return None;
+            }
+        };
+
+        if (*k).borrow() > self.to {
+            self.finished = true;
+            return None;
+        }
+
+        if self.first.is_none() {
+            self.first = Some(unsafe { SRef::new(k.as_ptr()) });
+        }
+        self.last = Some(unsafe { SRef::new(k.as_ptr()) });
+
+        Some((k, v))
+    }
+}
+
+impl<
+        'a,
+        Q: Ord + ?Sized,
+        K: StableType + AsFixedSizeBytes + Ord + AsHashableBytes + Borrow<Q>,
+        V: StableType + AsFixedSizeBytes + AsHashTree,
+    > FusedIterator for SCertifiedBTreeMapRangeIter<'a, Q, K, V>
+{
+}
@@ -2,6 +2,7 @@ use crate::collections::btree_map::internal_node::InternalBTreeNode;
 use crate::collections::btree_map::iter::SBTreeMapIter;
 use crate::collections::btree_map::leaf_node::LeafBTreeNode;
 use crate::collections::btree_map::{BTreeNode, LeveledList, SBTreeMap};
+use crate::collections::certified_btree_map::iter::SCertifiedBTreeMapRangeIter;
 use crate::encoding::AsFixedSizeBytes;
 use crate::primitive::s_ref::SRef;
 use crate::primitive::s_ref_mut::SRefMut;
@@ -14,6 +15,13 @@ use std::borrow::Borrow;
 use std::fmt::{Debug, Formatter};
 use std::ops::Deref;
 
+#[doc(hidden)]
+pub mod iter;
+
+/// Indicates that the key is present in the map, so its absence can't be proven
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyExists;
+
 /// Merkle tree certified map on top of [SBTreeMap]
 ///
 /// All logic, not related to the undelying Merkle tree is simply proxied from the underlying [SBTreeMap],
@@ -358,19 +366,40 @@ impl<
     /// While [SCertifiedBTreeMap] is in the `uncommited` state, every call that touches the underlying
     /// Merkle tree will panic ([SCertifiedBTreeMap::prove_absence], [SCertifiedBTreeMap::witness_with],
     /// [SCertifiedBTreeMap::prove_range], [SCertifiedBTreeMap::as_hash_tree]).
+    #[inline]
     pub fn commit(&mut self) {
+        self.commit_with_budget(usize::MAX);
+    }
+
+    /// Same as [SCertifiedBTreeMap::commit], but recalculates at most `max_nodes` modified nodes of
+    /// the underlying Merkle tree
+    ///
+    /// Returns [true], if there are more modified nodes left to recalculate. In that case, this map
+    /// stays in the `uncommited` state and this method should be called again (for example, in the
+    /// next message or timer tick). Further modifications between these calls are allowed.
+    ///
+    /// Useful for large batch updates, which recalculation can't fit into a single message.
+    pub fn commit_with_budget(&mut self, max_nodes: usize) -> bool {
         if !self.uncommited {
-            return;
+            return false;
         }
-        self.uncommited = false;
 
-        while let Some(ptr) = self.modified.pop() {
+        for _ in 0..max_nodes {
+            let ptr = match self.modified.pop() {
+                Some(it) => it,
+                None => break,
+            };
+
             let mut node = BTreeNode::<K, V>::from_ptr(ptr);
             match &mut node {
                 BTreeNode::Internal(n) => n.commit::<V>(),
                 BTreeNode::Leaf(n) => n.commit(),
             };
         }
+
+        self.uncommited = !self.modified.is_empty();
+
+        self.uncommited
     }
 
     /// Constructs a Merkle proof that is enough to be sure that the requested key **is not** present
@@ -382,10 +411,32 @@ impl<
     /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
     /// then you can get the value by [String].
     ///
+    /// See also [SCertifiedBTreeMap::try_prove_absence].
+    ///
     /// # Panics
     /// Panics if this map is the `uncommited` state.
     /// Panics if the key is actually present in this map.
+    #[inline]
     pub fn prove_absence<Q>(&self, index: &Q) -> HashTree
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match self.try_prove_absence(index) {
+            Ok(w) => w,
+            Err(KeyExists) => panic!("The key is present!"),
+        }
+    }
+
+    /// Same as [SCertifiedBTreeMap::prove_absence], but returns [Err] instead of panicking, if the
+    /// key is actually present in this map
+    ///
+    /// Useful in query methods, where the presence of the key is exactly what is being determined.
+    /// In that case, use [SCertifiedBTreeMap::witness] to prove the presence.
+    ///
+    /// # Panics
+    /// Panics if this map is the `uncommited` state.
+    pub fn try_prove_absence<Q>(&self, index: &Q) -> Result<HashTree, KeyExists>
     where
         K: Borrow<Q>,
         Q: Ord + ?Sized,
@@ -394,11 +445,15 @@ impl<
 
         let root_opt = self.inner.get_root();
         if root_opt.is_none() {
-            return HashTree::Empty;
+            return Ok(HashTree::Empty);
+        }
+
+        if self.inner.contains_key(index) {
+            return Err(KeyExists);
         }
 
         let node = unsafe { root_opt.unwrap_unchecked() };
-        match node {
+        let witness = match node {
             BTreeNode::Internal(n) => match n.prove_absence::<V, Q>(index) {
                 Ok(w) => w,
                 Err(w) => w,
@@ -406,7 +461,7 @@ impl<
             BTreeNode::Leaf(n) => {
                 let len = n.read_len();
                 let idx = match n.binary_search(index, len) {
-                    Ok(_) => panic!("The key is present!"),
+                    Ok(_) => unreachable!(),
                     Err(idx) => idx,
                 };
 
@@ -415,7 +470,9 @@ impl<
                     Err(w) => w,
                 }
             }
-        }
+        };
+
+        Ok(witness)
     }
 
     /// Constructs a Merkle proof that includes all keys of the requested range
@@ -445,6 +502,34 @@ impl<
         }
     }
 
+    /// Returns an iterator over entries with keys in `from..=to` range, which is able to prove the
+    /// entries it has visited
+    ///
+    /// Call [SCertifiedBTreeMapRangeIter::witness] at any moment to get a range proof, that includes
+    /// exactly the keys visited so far. This makes it easy to build paginated certified listings -
+    /// take as many entries as fit into a page and then prove them.
+    ///
+    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
+    /// then you can get the value by [String].
+    ///
+    /// # Panics
+    /// Panics if this map is the `uncommited` state.
+    #[inline]
+    pub fn range_with_witness<'a, Q>(
+        &'a self,
+        from: &'a Q,
+        to: &'a Q,
+    ) -> SCertifiedBTreeMapRangeIter<'a, Q, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        assert!(!self.uncommited);
+        assert!(from.le(to));
+
+        SCertifiedBTreeMapRangeIter::new(self, from, to)
+    }
+
     /// Proves that the key-value pair is present in this [SCertifiedBTreeMap], revealing the value itself
     ///
     /// This method accepts a lambda, so it is possible to witness nested [SCertifiedBTreeMap]s.
@@ -952,7 +1037,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord + AsHashableBytes> InternalBTreeNode
 
 #[cfg(test)]
 mod tests {
-    use crate::collections::certified_btree_map::SCertifiedBTreeMap;
+    use crate::collections::certified_btree_map::{KeyExists, SCertifiedBTreeMap};
     use crate::utils::certification::{
         leaf, leaf_hash, merge_hash_trees, traverse_hashtree, AsHashTree, AsHashableBytes, Hash,
         HashTree,
@@ -1212,6 +1297,75 @@ mod tests {
         assert_eq!(get_allocated_size(), 0);
     }
 
+    #[test]
+    fn try_prove_absence_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SCertifiedBTreeMap::<u64, u64>::new();
+
+            assert!(matches!(map.try_prove_absence(&1), Ok(HashTree::Empty)));
+
+            for i in 0..100 {
+                map.insert(i * 2, i).unwrap();
+            }
+
+            map.commit();
+
+            for i in 0..200 {
+                match map.try_prove_absence(&i) {
+                    Ok(proof) => {
+                        assert_eq!(i % 2, 1);
+                        assert_eq!(proof.reconstruct(), map.root_hash());
+                    }
+                    Err(KeyExists) => assert_eq!(i % 2, 0),
+                }
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn commit_with_budget_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SCertifiedBTreeMap::<u64, u64>::new();
+            let mut example = SCertifiedBTreeMap::<u64, u64>::new();
+
+            assert!(!map.commit_with_budget(1));
+
+            for i in 0..500 {
+                map.insert(i, i).unwrap();
+                example.insert(i, i).unwrap();
+            }
+
+            let mut calls = 0;
+            while map.commit_with_budget(5) {
+                calls += 1;
+
+                // modifications in between are fine
+                if calls % 3 == 0 {
+                    map.remove(&(calls * 2));
+                    example.remove(&(calls * 2));
+                }
+            }
+
+            example.commit();
+
+            assert!(calls > 1);
+            assert_eq!(map.root_hash(), example.root_hash());
+            assert_eq!(map.witness(&1).reconstruct(), map.root_hash());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[test]
     fn merge_works_fine() {
         stable::clear();
@@ -1300,6 +1454,57 @@ mod tests {
         assert_eq!(get_allocated_size(), 0);
     }
 
+    #[test]
+    fn range_with_witness_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SCertifiedBTreeMap::<u64, u64>::default();
+
+            let iter = map.range_with_witness(&0, &10);
+            assert!(matches!(iter.witness(), HashTree::Empty));
+
+            for i in 0..100 {
+                map.insert(i * 2, i).unwrap();
+            }
+
+            map.commit();
+
+            for from in (0..200).step_by(7) {
+                for page_size in [1, 5, 20, 200] {
+                    let to = from + 60;
+
+                    let mut iter = map.range_with_witness(&from, &to);
+                    let page = iter
+                        .by_ref()
+                        .take(page_size)
+                        .map(|(k, v)| (*k, *v))
+                        .collect::<Vec<_>>();
+
+                    let expected = (from..=to)
+                        .filter(|it| it % 2 == 0 && *it < 200)
+                        .take(page_size)
+                        .map(|it| (it, it / 2))
+                        .collect::<Vec<_>>();
+
+                    assert_eq!(page, expected);
+
+                    let witness = iter.witness();
+                    assert_eq!(witness.reconstruct(), map.root_hash());
+
+                    if !page.is_empty() {
+                        let leaves = hash_tree_to_labeled_leaves(witness);
+                        assert_eq!(leaves.len(), page.len());
+                    }
+                }
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[test]
     fn nested_maps_work_fine() {
         stable::clear();
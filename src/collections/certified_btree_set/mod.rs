@@ -1,4 +1,4 @@
-use crate::collections::certified_btree_map::SCertifiedBTreeMap;
+use crate::collections::certified_btree_map::{KeyExists, SCertifiedBTreeMap};
 use crate::collections::certified_btree_set::iter::SCertifiedBTreeSetIter;
 use crate::encoding::AsFixedSizeBytes;
 use crate::primitive::s_ref::SRef;
@@ -83,6 +83,12 @@ impl<T: Ord + StableType + AsFixedSizeBytes + AsHashableBytes> SCertifiedBTreeSe
         self.map.commit();
     }
 
+    /// See [SCertifiedBTreeMap::commit_with_budget]
+    #[inline]
+    pub fn commit_with_budget(&mut self, max_nodes: usize) -> bool {
+        self.map.commit_with_budget(max_nodes)
+    }
+
     /// See [SCertifiedBTreeMap::prove_absence]
     #[inline]
     pub fn prove_absence<Q>(&self, index: &Q) -> HashTree
@@ -93,6 +99,16 @@ impl<T: Ord + StableType + AsFixedSizeBytes + AsHashableBytes> SCertifiedBTreeSe
         self.map.prove_absence(index)
     }
 
+    /// See [SCertifiedBTreeMap::try_prove_absence]
+    #[inline]
+    pub fn try_prove_absence<Q>(&self, index: &Q) -> Result<HashTree, KeyExists>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.map.try_prove_absence(index)
+    }
+
     /// See [SCertifiedBTreeMap::prove_range]
     #[inline]
     pub fn prove_range<Q>(&self, from: &Q, to: &Q) -> HashTree
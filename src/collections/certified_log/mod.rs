@@ -0,0 +1,433 @@
+use crate::collections::log::iter::SLogRangeIter;
+use crate::collections::log::SLog;
+use crate::collections::vec::SVec;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use crate::utils::certification::{
+    fork, fork_hash, labeled, labeled_hash, pruned, AsHashTree, Hash, HashForker, HashTree,
+    WitnessForker,
+};
+use std::fmt::{Debug, Formatter};
+
+/// Append-only certified log, backed by a Merkle mountain range
+///
+/// Stores elements in an [SLog], and, next to them, hashes of all perfect binary Merkle trees
+/// (`mountains`) built on top of these elements. Each element is hashed as a labeled subtree,
+/// where the label is the big-endian index of this element, so the resulting [HashTree] is
+/// completely compatible with [Dfinity's ic-certified-map](https://github.com/dfinity/cdk-rs/tree/main/library/ic-certified-map)
+/// and can be verified using [agent-js library](https://github.com/dfinity/agent-js).
+///
+/// Elements can't be modified nor removed, which makes this data structure a good fit for
+/// transaction logs (e.g. ICRC-3 blocks), where each new entry is appended at the end and old
+/// entries should be provable forever.
+///
+/// `T` has to implement [StableType], [AsFixedSizeBytes] and [AsHashTree]. [SCertifiedLog] itself
+/// also implements these traits, so you can nest it into other stable structures (e.g. into
+/// [SCertifiedBTreeMap](crate::collections::SCertifiedBTreeMap)).
+///
+/// Features:
+/// 1. Amortized O(1) pushes - the Merkle tree is updated on each push, there is no need to commit.
+/// 2. O(logN) root hash calculation and O(logN) witness size.
+/// 3. Old elements and their hashes are never moved nor rewritten.
+pub struct SCertifiedLog<T: StableType + AsFixedSizeBytes + AsHashTree> {
+    entries: SLog<T>,
+    levels: SVec<SLog<Hash>>,
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree> SCertifiedLog<T> {
+    /// Creates a new [SCertifiedLog]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            entries: SLog::new(),
+            levels: SVec::new(),
+        }
+    }
+
+    /// Appends a new element to the end of the [SCertifiedLog], updating the underlying Merkle tree
+    ///
+    /// If the canister is out of stable memory, returns [Err] with the element, leaving the
+    /// [SCertifiedLog] unchanged.
+    pub fn push(&mut self, it: T) -> Result<(), T> {
+        let idx = self.entries.len();
+        let leaf_hash = labeled_hash(&idx.to_be_bytes(), &it.root_hash());
+
+        self.entries.push(it)?;
+
+        let mut pushed_levels = 0u64;
+        let mut hash = leaf_hash;
+
+        loop {
+            if self.push_hash(pushed_levels, hash).is_err() {
+                for level in 0..pushed_levels {
+                    self.levels.get_mut(level).unwrap().pop();
+                }
+
+                return Err(self.entries.pop().unwrap());
+            }
+
+            pushed_levels += 1;
+
+            let level = self.levels.get(pushed_levels - 1).unwrap();
+            let len = level.len();
+
+            if len % 2 == 1 {
+                break;
+            }
+
+            let lh = *level.get(len - 2).unwrap();
+            let rh = *level.get(len - 1).unwrap();
+
+            hash = fork_hash(&lh, &rh);
+        }
+
+        Ok(())
+    }
+
+    /// Returns a reference to an element at the provided index
+    #[inline]
+    pub fn get(&self, idx: u64) -> Option<SRef<'_, T>> {
+        self.entries.get(idx)
+    }
+
+    /// Returns a reference to the last element of this [SCertifiedLog]
+    #[inline]
+    pub fn last(&self) -> Option<SRef<'_, T>> {
+        self.entries.last()
+    }
+
+    /// Returns the length of this [SCertifiedLog]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    /// Returns [true] if the length of this [SCertifiedLog] is `0`
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns a front-to-back iterator over elements of this [SCertifiedLog]
+    #[inline]
+    pub fn iter(&self) -> SLogRangeIter<'_, T> {
+        self.entries.iter()
+    }
+
+    /// Constructs a witness of an element at the provided index, using the provided lambda to
+    /// build the witness of the element itself
+    ///
+    /// The witness contains O(logN) pruned nodes and its reconstructed hash always equals to the
+    /// [root hash](AsHashTree::root_hash) of this [SCertifiedLog].
+    ///
+    /// # Panics
+    /// Panics if the index is out of bounds.
+    pub fn witness_with<Fn: FnMut(&T) -> HashTree>(&self, idx: u64, mut f: Fn) -> HashTree {
+        assert!(idx < self.len(), "Index out of bounds");
+
+        let mut witness = WitnessForker::default();
+
+        self.for_each_peak(|level, peak_idx, offset| {
+            let peak = if idx >= offset && idx - offset < (1u64 << level) {
+                self.witness_node(level, peak_idx, idx, &mut f)
+            } else {
+                pruned(self.get_hash(level, peak_idx))
+            };
+
+            witness.fork_with(peak);
+        });
+
+        witness.finish()
+    }
+
+    /// Constructs a witness of an element at the provided index
+    ///
+    /// The witness reveals the full [HashTree] of the element.
+    ///
+    /// # Panics
+    /// Panics if the index is out of bounds.
+    #[inline]
+    pub fn witness(&self, idx: u64) -> HashTree {
+        self.witness_with(idx, |it| it.hash_tree())
+    }
+
+    fn witness_node<Fn: FnMut(&T) -> HashTree>(
+        &self,
+        level: u64,
+        node_idx: u64,
+        idx: u64,
+        f: &mut Fn,
+    ) -> HashTree {
+        if level == 0 {
+            let it = self.entries.get(idx).unwrap();
+
+            return labeled(idx.to_be_bytes().to_vec(), f(&it));
+        }
+
+        let left_idx = node_idx * 2;
+        let right_idx = left_idx + 1;
+
+        if idx < right_idx << (level - 1) {
+            fork(
+                self.witness_node(level - 1, left_idx, idx, f),
+                pruned(self.get_hash(level - 1, right_idx)),
+            )
+        } else {
+            fork(
+                pruned(self.get_hash(level - 1, left_idx)),
+                self.witness_node(level - 1, right_idx, idx, f),
+            )
+        }
+    }
+
+    fn full_tree_node(&self, level: u64, node_idx: u64) -> HashTree {
+        if level == 0 {
+            let it = self.entries.get(node_idx).unwrap();
+
+            return labeled(node_idx.to_be_bytes().to_vec(), it.hash_tree());
+        }
+
+        fork(
+            self.full_tree_node(level - 1, node_idx * 2),
+            self.full_tree_node(level - 1, node_idx * 2 + 1),
+        )
+    }
+
+    // calls the function for each mountain peak, from the biggest to the smallest one,
+    // passing its level, its index at that level and the index of its first element
+    fn for_each_peak<F: FnMut(u64, u64, u64)>(&self, mut f: F) {
+        let len = self.len();
+        let mut offset = 0u64;
+
+        for level in (0..u64::BITS as u64).rev() {
+            if len & (1u64 << level) == 0 {
+                continue;
+            }
+
+            f(level, offset >> level, offset);
+
+            offset += 1u64 << level;
+        }
+    }
+
+    #[inline]
+    fn get_hash(&self, level: u64, idx: u64) -> Hash {
+        *self.levels.get(level).unwrap().get(idx).unwrap()
+    }
+
+    fn push_hash(&mut self, level: u64, hash: Hash) -> Result<(), Hash> {
+        if level == self.levels.len() {
+            self.levels.push(SLog::new()).map_err(|_| hash)?;
+        }
+
+        self.levels.get_mut(level).unwrap().push(hash)
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree> AsHashTree for SCertifiedLog<T> {
+    /// Bags all mountain peaks together, from the biggest to the smallest one
+    ///
+    /// Returns the hash of an empty [HashTree], if the [SCertifiedLog] is empty.
+    fn root_hash(&self) -> Hash {
+        let mut hash = HashForker::default();
+
+        self.for_each_peak(|level, peak_idx, _| {
+            hash.fork_with(self.get_hash(level, peak_idx));
+        });
+
+        hash.finish()
+    }
+
+    /// Returns the full [HashTree] of this [SCertifiedLog], revealing all elements
+    ///
+    /// Be careful, the size of this tree is O(N).
+    fn hash_tree(&self) -> HashTree {
+        let mut tree = WitnessForker::default();
+
+        self.for_each_peak(|level, peak_idx, _| {
+            tree.fork_with(self.full_tree_node(level, peak_idx));
+        });
+
+        tree.finish()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree> Default for SCertifiedLog<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree> AsFixedSizeBytes for SCertifiedLog<T> {
+    const SIZE: usize = SLog::<T>::SIZE + SVec::<SLog<Hash>>::SIZE;
+    type Buf = Vec<u8>;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.entries
+            .as_fixed_size_bytes(&mut buf[0..SLog::<T>::SIZE]);
+        self.levels
+            .as_fixed_size_bytes(&mut buf[SLog::<T>::SIZE..Self::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let entries = SLog::<T>::from_fixed_size_bytes(&buf[0..SLog::<T>::SIZE]);
+        let levels = SVec::<SLog<Hash>>::from_fixed_size_bytes(&buf[SLog::<T>::SIZE..Self::SIZE]);
+
+        Self { entries, levels }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree> StableType for SCertifiedLog<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.entries.stable_drop_flag_on();
+        self.levels.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.entries.stable_drop_flag_off();
+        self.levels.stable_drop_flag_off();
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree + Debug> Debug for SCertifiedLog<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[")?;
+        for (idx, elem) in self.iter().enumerate() {
+            elem.fmt(f)?;
+
+            if (idx as u64) < self.len() - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::certified_log::SCertifiedLog;
+    use crate::collections::SVec;
+    use crate::encoding::AsFixedSizeBytes;
+    use crate::primitive::StableType;
+    use crate::utils::certification::{
+        leaf, leaf_hash, traverse_hashtree, AsHashTree, Hash, HashTree,
+    };
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init};
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    struct Block(u64);
+
+    impl StableType for Block {}
+
+    impl AsFixedSizeBytes for Block {
+        const SIZE: usize = u64::SIZE;
+        type Buf = <u64 as AsFixedSizeBytes>::Buf;
+
+        fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+            self.0.as_fixed_size_bytes(buf)
+        }
+
+        fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+            Block(u64::from_fixed_size_bytes(buf))
+        }
+    }
+
+    impl AsHashTree for Block {
+        fn root_hash(&self) -> Hash {
+            leaf_hash(&self.0.to_le_bytes())
+        }
+
+        fn hash_tree(&self) -> HashTree {
+            leaf(self.0.to_le_bytes().to_vec())
+        }
+    }
+
+    fn revealed_leaves(tree: &HashTree) -> Vec<Vec<u8>> {
+        let mut leaves = Vec::new();
+        traverse_hashtree(tree, &mut |it| {
+            if let HashTree::Labeled(l, _) = it {
+                leaves.push(l.clone());
+            }
+        });
+
+        leaves
+    }
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SCertifiedLog::<Block>::new();
+            assert!(log.is_empty());
+            assert_eq!(log.root_hash(), HashTree::Empty.reconstruct());
+
+            let mut prev_root_hashes = Vec::new();
+
+            for i in 0..300u64 {
+                log.push(Block(i)).unwrap();
+                assert_eq!(log.len(), i + 1);
+                assert_eq!(*log.last().unwrap(), Block(i));
+
+                let root_hash = log.root_hash();
+                assert!(!prev_root_hashes.contains(&root_hash));
+                assert_eq!(log.hash_tree().reconstruct(), root_hash);
+
+                prev_root_hashes.push(root_hash);
+            }
+
+            for i in 0..300u64 {
+                assert_eq!(*log.get(i).unwrap(), Block(i));
+
+                let witness = log.witness(i);
+                assert_eq!(witness.reconstruct(), log.root_hash());
+                assert_eq!(revealed_leaves(&witness), vec![i.to_be_bytes().to_vec()]);
+            }
+
+            let iterated = log.iter().map(|it| *it).collect::<Vec<_>>();
+            assert_eq!(iterated, (0..300u64).map(Block).collect::<Vec<_>>());
+
+            let mut vec = SVec::new();
+            vec.push(log).unwrap();
+
+            let log = vec.pop().unwrap();
+            assert_eq!(log.root_hash(), *prev_root_hashes.last().unwrap());
+            assert_eq!(log.witness(150).reconstruct(), log.root_hash());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn witness_size_is_logarithmic() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SCertifiedLog::<Block>::new();
+            for i in 0..1024u64 {
+                log.push(Block(i)).unwrap();
+            }
+
+            let mut pruned = 0;
+            traverse_hashtree(&log.witness(777), &mut |it| {
+                if let HashTree::Pruned(_) = it {
+                    pruned += 1;
+                }
+            });
+
+            assert_eq!(pruned, 10);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
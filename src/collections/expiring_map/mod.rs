@@ -0,0 +1,623 @@
+use crate::collections::btree_map::SBTreeMap;
+use crate::collections::hash_map::SHashMap;
+use crate::encoding::{AsFixedSizeBytes, Buffer};
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use crate::{allocate, deallocate, OutOfMemory, SSlice};
+use std::borrow::Borrow;
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+// Node layout:
+// EXPIRES_AT: u64
+// KEY: K (a non-owning copy, the key is owned by the map)
+// VALUE: V
+
+const EXPIRES_AT_OFFSET: u64 = 0;
+const KEY_OFFSET: u64 = EXPIRES_AT_OFFSET + u64::SIZE as u64;
+
+#[inline]
+const fn value_offset<K: AsFixedSizeBytes>() -> u64 {
+    KEY_OFFSET + K::SIZE as u64
+}
+
+/// Hash map, which entries expire at a given moment of time
+///
+/// Built on top of [SHashMap], which maps keys to separate blocks of stable memory, holding values
+/// and their expiry timestamps. These blocks are also indexed by expiry timestamp with an
+/// [SBTreeMap], so expired entries can be found without scanning the whole map.
+///
+/// This collection does not read the clock itself - every method, which cares about expiration,
+/// accepts the current timestamp `now` as an argument. Timestamps can be in any unit (e.g.
+/// nanoseconds, returned by `ic_cdk::api::time()`), as long as they are the same for all calls. An
+/// entry is considered expired, once `now >= expires_at`.
+///
+/// Expired entries are evicted lazily - when they get accessed by [SExpiringMap::get] and
+/// similar methods. Entries, which are never accessed again, stay in stable memory until
+/// [SExpiringMap::purge_expired] gets called, so it is advised to call it periodically (e.g. from
+/// a timer or a heartbeat).
+///
+/// Both `K` and `V` have to implement [StableType] and [AsFixedSizeBytes] traits. [SExpiringMap]
+/// also implements these traits itself, so you can nest it inside other stable structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SExpiringMap;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut sessions = SExpiringMap::<u64, u64>::new();
+///
+/// sessions.insert(1, 10, 100).expect("Out of memory");
+/// sessions.insert(2, 20, 200).expect("Out of memory");
+///
+/// assert_eq!(*sessions.get(&1, 50).unwrap(), 10);
+///
+/// // evicts the key 1
+/// assert!(sessions.get(&1, 150).is_none());
+/// assert_eq!(sessions.len(), 1);
+///
+/// // evicts the key 2
+/// assert_eq!(sessions.purge_expired(250, 10), 1);
+/// assert!(sessions.is_empty());
+/// ```
+pub struct SExpiringMap<
+    K: StableType + AsFixedSizeBytes + Hash + Eq,
+    V: StableType + AsFixedSizeBytes,
+> {
+    map: SHashMap<K, u64>,
+    index: SBTreeMap<(u64, u64), ()>,
+    _marker: PhantomData<V>,
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+    SExpiringMap<K, V>
+{
+    /// Creates a new empty [SExpiringMap]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            map: SHashMap::new(),
+            index: SBTreeMap::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of entries in this [SExpiringMap]
+    ///
+    /// Expired entries, which were not evicted yet, are also counted.
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    /// Returns [true] if there are no entries in this [SExpiringMap]
+    ///
+    /// Expired entries, which were not evicted yet, are also counted.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Inserts a new entry, which expires at `expires_at`
+    ///
+    /// If the key already exists, replaces both its value and its expiry timestamp and returns the
+    /// previous value, even if it has already expired.
+    ///
+    /// If the canister is out of stable memory, returns [Err] with the key and the value back,
+    /// leaving this [SExpiringMap] unchanged.
+    pub fn insert(
+        &mut self,
+        key: K,
+        mut value: V,
+        mut expires_at: u64,
+    ) -> Result<Option<V>, (K, V)> {
+        if let Some(node) = self.map.get(&key).map(|it| *it) {
+            if self.set_node_expiry(node, expires_at).is_err() {
+                return Err((key, value));
+            }
+
+            let value_ptr = Self::value_ptr(node);
+
+            let prev_value = unsafe { crate::mem::read_fixed_for_move(value_ptr) };
+            unsafe { crate::mem::write_fixed(value_ptr, &mut value) };
+
+            return Ok(Some(prev_value));
+        }
+
+        let node = match unsafe { allocate(value_offset::<K>() + V::SIZE as u64) } {
+            Ok(slice) => slice.as_ptr(),
+            Err(_) => return Err((key, value)),
+        };
+
+        if self.index.insert((expires_at, node), ()).is_err() {
+            deallocate(unsafe { SSlice::from_ptr(node).unwrap() });
+
+            return Err((key, value));
+        }
+
+        let mut key_buf = K::Buf::new(K::SIZE);
+        key.as_fixed_size_bytes(key_buf._deref_mut());
+
+        unsafe {
+            crate::mem::write_fixed(SSlice::_offset(node, EXPIRES_AT_OFFSET), &mut expires_at);
+            crate::mem::write_bytes(SSlice::_offset(node, KEY_OFFSET), key_buf._deref());
+            crate::mem::write_fixed(Self::value_ptr(node), &mut value);
+        }
+
+        if let Err((key, _)) = self.map.insert(key, node) {
+            self.index.remove(&(expires_at, node));
+
+            let value = unsafe { crate::mem::read_fixed_for_move(Self::value_ptr(node)) };
+            deallocate(unsafe { SSlice::from_ptr(node).unwrap() });
+
+            return Err((key, value));
+        }
+
+        Ok(None)
+    }
+
+    /// Returns an immutable reference [SRef] to a value stored by the key
+    ///
+    /// If the entry has expired by `now`, evicts it, stable-dropping both the key and the value.
+    ///
+    /// If no such key-value pair is found, returns [None]
+    pub fn get<Q>(&mut self, key: &Q, now: u64) -> Option<SRef<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = self.live_node(key, now)?;
+
+        unsafe { Some(SRef::new(Self::value_ptr(node))) }
+    }
+
+    /// Returns a mutable reference [SRefMut] to a value stored by the key
+    ///
+    /// If the entry has expired by `now`, evicts it, stable-dropping both the key and the value.
+    ///
+    /// If no such key-value pair is found, returns [None]
+    pub fn get_mut<Q>(&mut self, key: &Q, now: u64) -> Option<SRefMut<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = self.live_node(key, now)?;
+
+        unsafe { Some(SRefMut::new(Self::value_ptr(node))) }
+    }
+
+    /// Returns [true] if there exists an entry with the provided key, which has not expired by `now`
+    ///
+    /// If the entry has expired, evicts it, stable-dropping both the key and the value.
+    #[inline]
+    pub fn contains_key<Q>(&mut self, key: &Q, now: u64) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.live_node(key, now).is_some()
+    }
+
+    /// Returns the expiry timestamp of an entry stored by the key
+    ///
+    /// Does not evict anything, so the returned timestamp may already be in the past.
+    ///
+    /// If no such key-value pair is found, returns [None]
+    #[inline]
+    pub fn expires_at<Q>(&self, key: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = *self.map.get(key)?;
+
+        Some(Self::expires_at_of(node))
+    }
+
+    /// Sets a new expiry timestamp of an entry stored by the key, returning [true] if it was updated
+    ///
+    /// Useful to prolong sessions on activity. If the entry has already expired by `now`, evicts
+    /// it and returns [false] - an expired entry can't be revived.
+    ///
+    /// If the canister is out of stable memory, returns [Err], leaving the entry unchanged.
+    pub fn set_expiry<Q>(&mut self, key: &Q, expires_at: u64, now: u64) -> Result<bool, OutOfMemory>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.live_node(key, now) {
+            Some(node) => self.set_node_expiry(node, expires_at).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Removes an entry by the key, returning its value
+    ///
+    /// If the entry has expired by `now`, it is still removed, but [None] is returned.
+    ///
+    /// If no such key-value pair is found, returns [None]
+    pub fn remove<Q>(&mut self, key: &Q, now: u64) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = *self.map.get(key)?;
+        let expired = Self::expires_at_of(node) <= now;
+
+        let (_, value) = self.evict(node);
+
+        if expired {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    /// Evicts up to `budget` entries, which have expired by `now`, returning the number of evicted
+    /// entries
+    ///
+    /// Entries are evicted in order of their expiry timestamps, so the ones, that expired first,
+    /// are also the first to go. Both keys and values of evicted entries are stable-dropped.
+    ///
+    /// The `budget` allows to split a big cleanup into several calls, so a single call stays
+    /// within the instruction limit. If the returned number is equal to `budget`, there may be
+    /// more expired entries left.
+    pub fn purge_expired(&mut self, now: u64, budget: usize) -> usize {
+        let mut purged = 0;
+
+        while purged < budget {
+            let node = match self.index.iter().next() {
+                Some((it, _)) if it.0 <= now => it.1,
+                _ => break,
+            };
+
+            self.evict(node);
+            purged += 1;
+        }
+
+        purged
+    }
+
+    /// Returns the smallest expiry timestamp among all entries
+    ///
+    /// Useful to schedule the next [SExpiringMap::purge_expired] call. If this [SExpiringMap] is
+    /// empty, returns [None].
+    #[inline]
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.index.iter().next().map(|(it, _)| it.0)
+    }
+
+    /// Removes all entries from this [SExpiringMap], stable-dropping them
+    pub fn clear(&mut self) {
+        for (it, _) in self.index.iter() {
+            let node = it.1;
+
+            unsafe { crate::mem::read_fixed_for_move::<V>(Self::value_ptr(node)) };
+            deallocate(unsafe { SSlice::from_ptr(node).unwrap() });
+        }
+
+        self.index.clear();
+        self.map.clear();
+    }
+
+    #[inline]
+    fn value_ptr(node: StablePtr) -> StablePtr {
+        SSlice::_offset(node, value_offset::<K>())
+    }
+
+    #[inline]
+    fn expires_at_of(node: StablePtr) -> u64 {
+        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(node, EXPIRES_AT_OFFSET)) }
+    }
+
+    // inserts the new index entry first, so nothing changes if the canister is out of stable memory
+    fn set_node_expiry(&mut self, node: StablePtr, mut expires_at: u64) -> Result<(), OutOfMemory> {
+        let prev_expires_at = Self::expires_at_of(node);
+
+        if prev_expires_at == expires_at {
+            return Ok(());
+        }
+
+        if self.index.insert((expires_at, node), ()).is_err() {
+            return Err(OutOfMemory);
+        }
+        self.index.remove(&(prev_expires_at, node));
+
+        unsafe {
+            crate::mem::write_fixed(SSlice::_offset(node, EXPIRES_AT_OFFSET), &mut expires_at)
+        };
+
+        Ok(())
+    }
+
+    // returns the node of an entry, if it has not expired yet, evicting it otherwise
+    fn live_node<Q>(&mut self, key: &Q, now: u64) -> Option<StablePtr>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = *self.map.get(key)?;
+
+        if Self::expires_at_of(node) <= now {
+            self.evict(node);
+
+            None
+        } else {
+            Some(node)
+        }
+    }
+
+    fn evict(&mut self, node: StablePtr) -> (K, V) {
+        self.index.remove(&(Self::expires_at_of(node), node));
+
+        let key_ref: K =
+            unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(node, KEY_OFFSET)) };
+        let (key, _) = self.map.remove_entry(&key_ref).unwrap();
+
+        let value = unsafe { crate::mem::read_fixed_for_move(Self::value_ptr(node)) };
+        deallocate(unsafe { SSlice::from_ptr(node).unwrap() });
+
+        (key, value)
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Default
+    for SExpiringMap<K, V>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+    AsFixedSizeBytes for SExpiringMap<K, V>
+{
+    const SIZE: usize = SHashMap::<K, u64>::SIZE + SBTreeMap::<(u64, u64), ()>::SIZE;
+    type Buf = Vec<u8>;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let map_size = SHashMap::<K, u64>::SIZE;
+
+        self.map.as_fixed_size_bytes(&mut buf[0..map_size]);
+        self.index
+            .as_fixed_size_bytes(&mut buf[map_size..Self::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let map_size = SHashMap::<K, u64>::SIZE;
+
+        let map = SHashMap::<K, u64>::from_fixed_size_bytes(&buf[0..map_size]);
+        let index = SBTreeMap::<(u64, u64), ()>::from_fixed_size_bytes(&buf[map_size..Self::SIZE]);
+
+        Self {
+            map,
+            index,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> StableType
+    for SExpiringMap<K, V>
+{
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.map.stable_drop_flag_on();
+        self.index.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.map.stable_drop_flag_off();
+        self.index.stable_drop_flag_off();
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.map.should_stable_drop()
+    }
+
+    #[inline]
+    unsafe fn stable_drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Drop
+    for SExpiringMap<K, V>
+{
+    fn drop(&mut self) {
+        // the map and the index will stable-drop themselves right after
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq + Debug,
+        V: StableType + AsFixedSizeBytes + Debug,
+    > Debug for SExpiringMap<K, V>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("{")?;
+        for (idx, (it, _)) in (0u64..).zip(self.index.iter()) {
+            let (key, value): (SRef<K>, SRef<V>) = unsafe {
+                (
+                    SRef::new(SSlice::_offset(it.1, KEY_OFFSET)),
+                    SRef::new(Self::value_ptr(it.1)),
+                )
+            };
+
+            key.fmt(f)?;
+            f.write_str(": ")?;
+            value.fmt(f)?;
+
+            if idx < self.len() - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::expiring_map::SExpiringMap;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+    use std::collections::HashMap;
+
+    #[test]
+    fn basic_flow_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SExpiringMap::new();
+
+            for i in 0..5u64 {
+                assert!(map.insert(i, i * 10, 100 + i * 10).unwrap().is_none());
+            }
+
+            assert_eq!(map.next_expiry(), Some(100));
+            assert_eq!(map.expires_at(&3), Some(130));
+
+            assert_eq!(*map.get(&0, 99).unwrap(), 0);
+            *map.get_mut(&1, 99).unwrap() = 11;
+
+            // expires exactly at 100
+            assert!(!map.contains_key(&0, 100));
+            assert_eq!(map.len(), 4);
+            assert_eq!(map.next_expiry(), Some(110));
+
+            assert!(map.set_expiry(&1, 200, 105).unwrap());
+            assert!(!map.set_expiry(&0, 200, 105).unwrap());
+            assert_eq!(map.next_expiry(), Some(120));
+
+            assert_eq!(map.insert(2, 21, 300).unwrap(), Some(20));
+            assert_eq!(map.expires_at(&2), Some(300));
+
+            // keys 3 and 4 are expired, only one of them gets purged
+            assert_eq!(map.purge_expired(150, 1), 1);
+            assert_eq!(map.expires_at(&3), None);
+            assert_eq!(map.expires_at(&4), Some(140));
+            assert_eq!(map.purge_expired(150, 10), 1);
+            assert_eq!(map.purge_expired(150, 10), 0);
+            assert_eq!(map.len(), 2);
+            assert_eq!(format!("{:?}", map), "{1: 11, 2: 21}");
+
+            // the key 1 is expired, the value is dropped
+            assert_eq!(map.remove(&1, 250), None);
+            assert_eq!(map.remove(&1, 250), None);
+
+            let mut vec = SVec::new();
+            vec.push(map).unwrap();
+
+            let mut map = vec.pop().unwrap();
+            assert_eq!(map.remove(&2, 250), Some(21));
+            assert!(map.is_empty());
+            assert_eq!(map.next_expiry(), None);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn random_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut map = SExpiringMap::new();
+            // key -> (value, expires_at), including expired entries
+            let mut check: HashMap<u64, (u64, u64)> = HashMap::new();
+            let mut now = 0u64;
+
+            for i in 0..5000u64 {
+                let key = rng.gen_range(0..100u64);
+                now += rng.gen_range(0..3u64);
+
+                match rng.gen_range(0..5) {
+                    0 | 1 => {
+                        let expires_at = now + rng.gen_range(1..50u64);
+
+                        let prev = map
+                            .insert(SBox::new(key).unwrap(), SBox::new(i).unwrap(), expires_at)
+                            .unwrap()
+                            .map(|it| it.into_inner());
+
+                        let expected = check.insert(key, (i, expires_at)).map(|it| it.0);
+                        assert_eq!(prev, expected);
+                    }
+                    2 => {
+                        let value = map.get(&key, now).map(|it| **it);
+
+                        let expected = match check.get(&key) {
+                            Some((v, e)) if *e > now => Some(*v),
+                            Some(_) => {
+                                check.remove(&key);
+                                None
+                            }
+                            None => None,
+                        };
+                        assert_eq!(value, expected);
+                    }
+                    3 => {
+                        let value = map.remove(&key, now).map(|it| it.into_inner());
+
+                        let expected =
+                            check
+                                .remove(&key)
+                                .and_then(|(v, e)| if e > now { Some(v) } else { None });
+                        assert_eq!(value, expected);
+                    }
+                    _ => {
+                        let budget = rng.gen_range(0..10usize);
+                        let purged = map.purge_expired(now, budget);
+
+                        let expired = check.values().filter(|(_, e)| *e <= now).count();
+                        assert_eq!(purged, expired.min(budget));
+
+                        // entries with equal timestamps may be purged in any order
+                        let mut max_purged = 0;
+                        check.retain(|k, (_, e)| {
+                            if map.expires_at(k).is_some() {
+                                return true;
+                            }
+
+                            assert!(*e <= now);
+                            max_purged = max_purged.max(*e);
+
+                            false
+                        });
+
+                        for (_, e) in check.values() {
+                            assert!(*e >= max_purged);
+                        }
+                    }
+                }
+
+                assert_eq!(map.len(), check.len() as u64);
+                assert_eq!(map.next_expiry(), check.values().map(|(_, e)| *e).min());
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::collections::graph::NodeId;
+use crate::collections::vec::iter::size_hint;
+use crate::collections::vec::SVec;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+
+pub struct SGraphNeighborsIter<'a, E: StableType + AsFixedSizeBytes> {
+    // a non-owning copy of the adjacency list
+    edges: SVec<(NodeId, E)>,
+    idx: u64,
+    _marker: PhantomData<&'a E>,
+}
+
+impl<'a, E: StableType + AsFixedSizeBytes> SGraphNeighborsIter<'a, E> {
+    #[inline]
+    pub(crate) fn new(edges: SVec<(NodeId, E)>) -> Self {
+        Self {
+            edges,
+            idx: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, E: StableType + AsFixedSizeBytes> Iterator for SGraphNeighborsIter<'a, E> {
+    type Item = (NodeId, SRef<'a, E>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ptr = self.edges.get_element_ptr(self.idx)?;
+        self.idx += 1;
+
+        let to = unsafe { crate::mem::read_fixed_for_reference::<NodeId>(ptr) };
+        let payload = unsafe { SRef::new(ptr + NodeId::SIZE as u64) };
+
+        Some((to, payload))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        size_hint(self.edges.len() - self.idx)
+    }
+}
+
+impl<'a, E: StableType + AsFixedSizeBytes> ExactSizeIterator for SGraphNeighborsIter<'a, E> {}
+
+impl<'a, E: StableType + AsFixedSizeBytes> FusedIterator for SGraphNeighborsIter<'a, E> {}
@@ -0,0 +1,414 @@
+use crate::collections::graph::iter::SGraphNeighborsIter;
+use crate::collections::vec::SVec;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use std::fmt::{Debug, Formatter};
+
+#[doc(hidden)]
+pub mod iter;
+
+/// Identifier of a node of [SGraph] - its index in insertion order
+pub type NodeId = u64;
+
+/// Directed graph with node and edge payloads, stored as adjacency lists
+///
+/// Nodes are identified by [NodeId]s, which are assigned sequentially, starting from `0`, and never
+/// change. Each node owns an [SVec] of its outgoing edges - pairs of the target node and the edge
+/// payload. So [SGraph::add_edge] is amortized O(1) and [SGraph::neighbors] only touches the
+/// edges of a single node. Undirected graphs can be modeled by adding edges in both directions.
+///
+/// Nodes can't be removed, since it would invalidate [NodeId]s of other nodes - mark them
+/// as deleted inside the payload instead.
+///
+/// `N` and `E` have to implement both [StableType] and [AsFixedSizeBytes]. [SGraph] itself
+/// implements these traits and can be nested inside other stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SGraph;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// // user ids as nodes, follow timestamps as edges
+/// let mut follows = SGraph::<u64, u64>::new();
+///
+/// let alice = follows.add_node(1).expect("Out of memory");
+/// let bob = follows.add_node(2).expect("Out of memory");
+/// let carol = follows.add_node(3).expect("Out of memory");
+///
+/// follows.add_edge(alice, bob, 1000).expect("Out of memory");
+/// follows.add_edge(alice, carol, 1001).expect("Out of memory");
+///
+/// let followed: Vec<u64> = follows
+///     .neighbors(alice)
+///     .map(|(node, _)| *follows.node(node).unwrap())
+///     .collect();
+///
+/// assert_eq!(followed, vec![2, 3]);
+/// ```
+pub struct SGraph<N: StableType + AsFixedSizeBytes, E: StableType + AsFixedSizeBytes> {
+    nodes: SVec<N>,
+    adjacency: SVec<SVec<(NodeId, E)>>,
+    edges_count: u64,
+}
+
+impl<N: StableType + AsFixedSizeBytes, E: StableType + AsFixedSizeBytes> SGraph<N, E> {
+    /// Creates a new [SGraph]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            nodes: SVec::new(),
+            adjacency: SVec::new(),
+            edges_count: 0,
+        }
+    }
+
+    /// Returns the number of nodes of this [SGraph]
+    #[inline]
+    pub fn nodes_count(&self) -> u64 {
+        self.nodes.len()
+    }
+
+    /// Returns the number of edges of this [SGraph]
+    #[inline]
+    pub fn edges_count(&self) -> u64 {
+        self.edges_count
+    }
+
+    /// Returns [true] if there are no nodes in this [SGraph]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Adds a new node with the payload, returning its [NodeId]
+    ///
+    /// If the canister is out of stable memory, returns [Err] with the payload.
+    pub fn add_node(&mut self, payload: N) -> Result<NodeId, N> {
+        self.nodes.push(payload)?;
+
+        if self.adjacency.push(SVec::new()).is_err() {
+            return Err(self.nodes.pop().unwrap());
+        }
+
+        Ok(self.nodes.len() as NodeId - 1)
+    }
+
+    /// Adds a new directed edge `from -> to` with the payload
+    ///
+    /// Parallel edges are allowed. If the canister is out of stable memory, returns [Err] with
+    /// the payload.
+    ///
+    /// # Panics
+    /// Panics if any of nodes does not exist.
+    pub fn add_edge(&mut self, from: NodeId, to: NodeId, payload: E) -> Result<(), E> {
+        assert!(to < self.nodes_count(), "node does not exist");
+
+        self.edges_mut(from)
+            .push((to, payload))
+            .map_err(|(_, payload)| payload)?;
+
+        self.edges_count += 1;
+
+        Ok(())
+    }
+
+    /// Removes the first edge `from -> to`, returning its payload
+    ///
+    /// The order of remaining edges of `from` is preserved. If there is no such edge, returns
+    /// [None].
+    ///
+    /// # Panics
+    /// Panics if the `from` node does not exist.
+    pub fn remove_edge(&mut self, from: NodeId, to: NodeId) -> Option<E> {
+        let (idx, _) = (0u64..)
+            .zip(self.neighbors(from))
+            .find(|(_, (node, _))| *node == to)?;
+        let (_, payload) = self.edges_mut(from).remove(idx);
+
+        self.edges_count -= 1;
+
+        Some(payload)
+    }
+
+    /// Returns an immutable reference [SRef] to the payload of the node
+    ///
+    /// If there is no such node, returns [None].
+    #[inline]
+    pub fn node(&self, id: NodeId) -> Option<SRef<'_, N>> {
+        self.nodes.get(id)
+    }
+
+    /// Returns a mutable reference [SRefMut] to the payload of the node
+    ///
+    /// If there is no such node, returns [None].
+    #[inline]
+    pub fn node_mut(&mut self, id: NodeId) -> Option<SRefMut<'_, N>> {
+        self.nodes.get_mut(id)
+    }
+
+    /// Returns an immutable reference [SRef] to the payload of the first edge `from -> to`
+    ///
+    /// If there is no such edge, returns [None].
+    ///
+    /// # Panics
+    /// Panics if the `from` node does not exist.
+    #[inline]
+    pub fn edge(&self, from: NodeId, to: NodeId) -> Option<SRef<'_, E>> {
+        self.neighbors(from)
+            .find(|(node, _)| *node == to)
+            .map(|(_, payload)| payload)
+    }
+
+    /// Returns the number of outgoing edges of the node
+    ///
+    /// # Panics
+    /// Panics if the node does not exist.
+    #[inline]
+    pub fn out_degree(&self, id: NodeId) -> u64 {
+        self.edges(id).len()
+    }
+
+    /// Returns an iterator over outgoing edges of the node - pairs of the target [NodeId] and a
+    /// [SRef] to the edge payload, in insertion order
+    ///
+    /// # Panics
+    /// Panics if the node does not exist.
+    #[inline]
+    pub fn neighbors(&self, id: NodeId) -> SGraphNeighborsIter<'_, E> {
+        SGraphNeighborsIter::new(self.edges(id))
+    }
+
+    /// Removes all nodes and edges from this [SGraph]
+    #[inline]
+    pub fn clear(&mut self) {
+        self.adjacency.clear();
+        self.nodes.clear();
+        self.edges_count = 0;
+    }
+
+    // a non-owning copy of the adjacency list
+    fn edges(&self, id: NodeId) -> SVec<(NodeId, E)> {
+        let ptr = self
+            .adjacency
+            .get_element_ptr(id)
+            .expect("node does not exist");
+
+        unsafe { crate::mem::read_fixed_for_reference(ptr) }
+    }
+
+    fn edges_mut(&mut self, id: NodeId) -> SRefMut<'_, SVec<(NodeId, E)>> {
+        self.adjacency.get_mut(id).expect("node does not exist")
+    }
+}
+
+impl<N: StableType + AsFixedSizeBytes, E: StableType + AsFixedSizeBytes> Default for SGraph<N, E> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N: StableType + AsFixedSizeBytes, E: StableType + AsFixedSizeBytes> AsFixedSizeBytes
+    for SGraph<N, E>
+{
+    const SIZE: usize = SVec::<N>::SIZE * 2 + u64::SIZE;
+    type Buf = Vec<u8>;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let vec_size = SVec::<N>::SIZE;
+
+        self.nodes.as_fixed_size_bytes(&mut buf[0..vec_size]);
+        self.adjacency
+            .as_fixed_size_bytes(&mut buf[vec_size..(vec_size * 2)]);
+        self.edges_count
+            .as_fixed_size_bytes(&mut buf[(vec_size * 2)..Self::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let vec_size = SVec::<N>::SIZE;
+
+        let nodes = SVec::<N>::from_fixed_size_bytes(&buf[0..vec_size]);
+        let adjacency =
+            SVec::<SVec<(NodeId, E)>>::from_fixed_size_bytes(&buf[vec_size..(vec_size * 2)]);
+        let edges_count = u64::from_fixed_size_bytes(&buf[(vec_size * 2)..Self::SIZE]);
+
+        Self {
+            nodes,
+            adjacency,
+            edges_count,
+        }
+    }
+}
+
+impl<N: StableType + AsFixedSizeBytes, E: StableType + AsFixedSizeBytes> StableType
+    for SGraph<N, E>
+{
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.nodes.stable_drop_flag_on();
+        self.adjacency.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.nodes.stable_drop_flag_off();
+        self.adjacency.stable_drop_flag_off();
+    }
+}
+
+impl<N: StableType + AsFixedSizeBytes + Debug, E: StableType + AsFixedSizeBytes + Debug> Debug
+    for SGraph<N, E>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("{")?;
+        for id in 0..self.nodes_count() {
+            id.fmt(f)?;
+            f.write_str(" ")?;
+            self.node(id).unwrap().fmt(f)?;
+            f.write_str(": [")?;
+
+            let degree = self.out_degree(id);
+            for (idx, (to, payload)) in (0u64..).zip(self.neighbors(id)) {
+                f.write_str("-")?;
+                payload.fmt(f)?;
+                f.write_str("-> ")?;
+                to.fmt(f)?;
+
+                if idx < degree - 1 {
+                    f.write_str(", ")?;
+                }
+            }
+            f.write_str("]")?;
+
+            if id < self.nodes_count() - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::graph::SGraph;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+    use std::collections::VecDeque;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut graph = SGraph::new();
+            let mut check: Vec<Vec<(u64, u64)>> = Vec::new();
+
+            for i in 0..100u64 {
+                assert_eq!(graph.add_node(SBox::new(i * 10).unwrap()).unwrap(), i);
+                check.push(Vec::new());
+            }
+
+            for i in 0..2000u64 {
+                let from = rng.gen_range(0..100u64);
+                let to = rng.gen_range(0..100u64);
+
+                if rng.gen_bool(0.7) {
+                    graph.add_edge(from, to, SBox::new(i).unwrap()).unwrap();
+                    check[from as usize].push((to, i));
+                } else {
+                    let expected = check[from as usize]
+                        .iter()
+                        .position(|(node, _)| *node == to)
+                        .map(|idx| check[from as usize].remove(idx).1);
+
+                    assert_eq!(
+                        graph.remove_edge(from, to).map(|it| it.into_inner()),
+                        expected
+                    );
+                }
+            }
+
+            let edges_count = check.iter().map(|it| it.len() as u64).sum::<u64>();
+            assert_eq!(graph.edges_count(), edges_count);
+            assert_eq!(graph.nodes_count(), 100);
+
+            for (from, edges) in check.iter().enumerate() {
+                let actual = graph
+                    .neighbors(from as u64)
+                    .map(|(to, payload)| (to, **payload))
+                    .collect::<Vec<_>>();
+
+                assert_eq!(&actual, edges);
+                assert_eq!(graph.out_degree(from as u64), edges.len() as u64);
+
+                if let Some((to, payload)) = edges.first() {
+                    assert_eq!(**graph.edge(from as u64, *to).unwrap(), *payload);
+                }
+            }
+
+            *graph.node_mut(5).unwrap() = SBox::new(5).unwrap();
+            assert_eq!(**graph.node(5).unwrap(), 5);
+            assert!(graph.node(100).is_none());
+
+            let mut vec = SVec::new();
+            vec.push(graph).unwrap();
+
+            let graph = vec.pop().unwrap();
+            assert_eq!(graph.edges_count(), edges_count);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn bfs_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            // a chain 0 -> 1 -> ... -> 9 with a shortcut 0 -> 5
+            let mut graph = SGraph::<u64, ()>::new();
+
+            for i in 0..10 {
+                graph.add_node(i).unwrap();
+            }
+            for i in 0..9 {
+                graph.add_edge(i, i + 1, ()).unwrap();
+            }
+            graph.add_edge(0, 5, ()).unwrap();
+
+            let mut distances = vec![u64::MAX; 10];
+            let mut queue = VecDeque::from([0u64]);
+            distances[0] = 0;
+
+            while let Some(node) = queue.pop_front() {
+                for (next, _) in graph.neighbors(node) {
+                    if distances[next as usize] == u64::MAX {
+                        distances[next as usize] = distances[node as usize] + 1;
+                        queue.push_back(next);
+                    }
+                }
+            }
+
+            assert_eq!(distances, vec![0, 1, 2, 3, 4, 1, 2, 3, 4, 5]);
+
+            graph.clear();
+            assert!(graph.is_empty());
+            assert_eq!(graph.edges_count(), 0);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
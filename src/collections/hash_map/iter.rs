@@ -1,4 +1,5 @@
 use crate::collections::hash_map::SHashMap;
+use crate::collections::vec::iter::size_hint;
 use crate::encoding::AsFixedSizeBytes;
 use crate::primitive::s_ref::SRef;
 use crate::primitive::StableType;
@@ -10,7 +11,7 @@ pub struct SHashMapIter<
     V: StableType + AsFixedSizeBytes,
 > {
     map: &'a SHashMap<K, V>,
-    i: usize,
+    i: u64,
 }
 
 impl<'a, K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
@@ -48,3 +49,72 @@ impl<'a, K: StableType + AsFixedSizeBytes + Eq + Hash, V: StableType + AsFixedSi
         }
     }
 }
+
+pub struct SHashMapIntoIter<
+    K: StableType + AsFixedSizeBytes + Hash + Eq,
+    V: StableType + AsFixedSizeBytes,
+> {
+    map: SHashMap<K, V>,
+    i: u64,
+    remaining: u64,
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+    SHashMapIntoIter<K, V>
+{
+    pub(crate) fn new(map: SHashMap<K, V>) -> Self {
+        let remaining = map.len();
+
+        Self {
+            map,
+            i: 0,
+            remaining,
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Iterator
+    for SHashMapIntoIter<K, V>
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        loop {
+            let k = self.map.read_and_disown_key(self.i);
+            self.i += 1;
+
+            if let Some(k) = k {
+                let v = self.map.read_and_disown_val(self.i - 1);
+                self.remaining -= 1;
+
+                return Some((k, v));
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        size_hint(self.remaining)
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+    ExactSizeIterator for SHashMapIntoIter<K, V>
+{
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Drop
+    for SHashMapIntoIter<K, V>
+{
+    fn drop(&mut self) {
+        // stable-dropping entries that were not consumed
+        for _ in self.by_ref() {}
+
+        // all entries are moved out - the map will only release its table
+        self.map.len = 0;
+    }
+}
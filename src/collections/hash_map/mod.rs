@@ -1,17 +1,24 @@
-use crate::collections::hash_map::iter::SHashMapIter;
+use crate::collections::hash_map::iter::{SHashMapIntoIter, SHashMapIter};
+use crate::encoding::counters::{decode_header, encode_header, header_size};
 use crate::encoding::{AsFixedSizeBytes, Buffer};
 use crate::mem::allocator::EMPTY_PTR;
 use crate::mem::StablePtr;
 use crate::primitive::s_ref::SRef;
 use crate::primitive::s_ref_mut::SRefMut;
-use crate::primitive::StableType;
+use crate::primitive::{StableType, TryClone, TryFromIterator};
+use crate::utils::hasher::StableHasher;
+use crate::utils::math::max_elements;
 use crate::utils::DebuglessUnwrap;
 use crate::{allocate, deallocate, OutOfMemory, SSlice};
+use candid::types::{Compound, Type};
+use candid::CandidType;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
 use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
-use std::hash::{Hash, Hasher};
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::marker::PhantomData;
-use zwohash::ZwoHasher;
 
 #[doc(hidden)]
 pub mod iter;
@@ -20,28 +27,32 @@ pub mod iter;
 // KEYS: [K; CAPACITY] = [zeroed(K); CAPACITY]
 // VALUES: [V; CAPACITY] = [zeroed(V); CAPACITY]
 
-const KEYS_OFFSET: usize = 0;
+const KEYS_OFFSET: u64 = 0;
 
 #[inline]
-const fn values_offset<K: AsFixedSizeBytes>(capacity: usize) -> usize {
-    KEYS_OFFSET + (1 + K::SIZE) * capacity
+const fn values_offset<K: AsFixedSizeBytes>(capacity: u64) -> u64 {
+    KEYS_OFFSET + (1 + K::SIZE) as u64 * capacity
 }
 
-const DEFAULT_CAPACITY: usize = 7;
+const DEFAULT_CAPACITY: u64 = 7;
 
 const EMPTY: u8 = 0;
 const OCCUPIED: u8 = 255;
 
+// hashes are reduced to an index only after the modulo, so they don't depend on the pointer width
+#[cfg(not(feature = "wasm64"))]
 type KeyHash = usize;
+#[cfg(feature = "wasm64")]
+type KeyHash = u64;
 
 /// Reallocating, open addressing, linear probing, eager removes hash map
 ///
 /// Conceptually the same thing as [std::collections::HashMap], but with a couple of twists:
 /// 1. [zwohash](https://github.com/jix/zwohash) is used, instead of `SipHash`, to make hashes faster
-/// and deterministic between canister upgrades.
+/// and deterministic between canister upgrades (its 64-bit version with `wasm64` feature enabled).
 /// 2. eager removes (no tombstones) are performed in order to prevent performance degradation.
 ///
-/// This is a "finite" data structure - it can only handle up to [u32::MAX] / `(1 + K::SIZE + V::SIZE)`
+/// This is a "finite" data structure - it can only handle up to `1TB / (1 + K::SIZE + V::SIZE)`
 /// elements total. Putting more elements inside will panic.
 ///
 /// Both `K` and `V` have to implement [StableType] and [AsFixedSizeBytes] traits. [SHashMap] also
@@ -49,8 +60,8 @@ type KeyHash = usize;
 pub struct SHashMap<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
 {
     table_ptr: u64,
-    len: usize,
-    cap: usize,
+    len: u64,
+    cap: u64,
     stable_drop_flag: bool,
     _marker_k: PhantomData<K>,
     _marker_v: PhantomData<V>,
@@ -99,14 +110,13 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
     /// let mut at_least_10_number_pairs = SHashMap::<u64, u64>::new_with_capacity(10)
     ///     .expect("Out of memory");
     /// ```
-    pub fn new_with_capacity(capacity: usize) -> Result<Self, OutOfMemory> {
+    pub fn new_with_capacity(capacity: u64) -> Result<Self, OutOfMemory> {
         assert!(capacity <= Self::max_capacity());
 
-        let size = (1 + K::SIZE + V::SIZE) * capacity;
-        let table = unsafe { allocate(size as u64)? };
+        let size = (1 + K::SIZE + V::SIZE) as u64 * capacity;
+        let table = unsafe { allocate(size)? };
 
-        let zeroed = vec![0u8; size];
-        unsafe { crate::mem::write_bytes(table.offset(0), &zeroed) };
+        unsafe { crate::mem::write_zeroes(table.offset(0), size) };
 
         Ok(Self {
             table_ptr: table.as_ptr(),
@@ -144,10 +154,9 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
     /// ```
     pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
         if self.table_ptr == EMPTY_PTR {
-            let size = (1 + K::SIZE + V::SIZE) * self.capacity();
-            if let Ok(table) = unsafe { allocate(size as u64) } {
-                let zeroed = vec![0u8; size];
-                unsafe { crate::mem::write_bytes(table.offset(0), &zeroed) };
+            let size = (1 + K::SIZE + V::SIZE) as u64 * self.capacity();
+            if let Ok(table) = unsafe { allocate(size) } {
+                unsafe { crate::mem::write_zeroes(table.offset(0), size) };
 
                 self.table_ptr = table.as_ptr();
             } else {
@@ -156,7 +165,7 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
         }
 
         let key_hash = Self::hash(&key);
-        let mut i = key_hash % self.capacity();
+        let mut i = self.bucket(key_hash);
 
         loop {
             match self.get_key(i) {
@@ -216,6 +225,33 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
         }
     }
 
+    /// Inserts all entries of the provided iterator into this [SHashMap]
+    ///
+    /// If the canister is out of stable memory, will return [Err] with the entry that was about to
+    /// get inserted. All entries inserted before it stay in this [SHashMap], the rest of the iterator
+    /// is left unconsumed.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new();
+    ///
+    /// map.try_extend((0..100u64).map(|it| (it, it * 2)))
+    ///     .expect("Out of memory");
+    ///
+    /// assert_eq!(map.len(), 100);
+    /// ```
+    pub fn try_extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) -> Result<(), (K, V)> {
+        for (k, v) in iter {
+            self.insert(k, v)?;
+        }
+
+        Ok(())
+    }
+
     /// Removes a key-value pair by the provided key
     ///
     /// Returns [None] if no pair was found by this key
@@ -249,6 +285,18 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
     /// assert_eq!(map.remove(&str_key).unwrap(), 10);
     /// ```
     pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        Some(self.remove_by_idx(self.find_inner_idx(key)?).1)
+    }
+
+    /// Removes a key-value pair by the key, returning both of them, if such a key exists
+    ///
+    /// Same as [SHashMap::remove], but also returns the stored key back to the caller.
+    #[inline]
+    pub(crate) fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
@@ -321,20 +369,20 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
 
     /// Returns the length of this [SHashMap]
     #[inline]
-    pub const fn len(&self) -> usize {
+    pub const fn len(&self) -> u64 {
         self.len
     }
 
     /// Returns the capacity of this [SHashMap]
     #[inline]
-    pub const fn capacity(&self) -> usize {
+    pub const fn capacity(&self) -> u64 {
         self.cap
     }
 
     /// Returns the maximum possible capacity of this [SHashMap]
     #[inline]
-    pub const fn max_capacity() -> usize {
-        u32::MAX as usize / (K::SIZE + V::SIZE)
+    pub const fn max_capacity() -> u64 {
+        max_elements(1 + K::SIZE + V::SIZE)
     }
 
     /// Returns true if the length of this [SHashMap] is `0`
@@ -421,15 +469,20 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
     }
 
     fn hash<T: Hash + ?Sized>(val: &T) -> KeyHash {
-        let mut hasher = ZwoHasher::default();
+        let mut hasher = StableHasher::default();
         val.hash(&mut hasher);
 
         hasher.finish() as KeyHash
     }
 
-    fn remove_by_idx(&mut self, idx: usize) -> V {
+    #[inline]
+    fn bucket(&self, key_hash: KeyHash) -> u64 {
+        key_hash as u64 % self.capacity()
+    }
+
+    fn remove_by_idx(&mut self, idx: u64) -> (K, V) {
         let prev_value = self.read_and_disown_val(idx);
-        self.read_and_disown_key(idx).unwrap();
+        let prev_key = self.read_and_disown_key(idx).unwrap();
 
         let mut i = idx;
         let mut j = idx;
@@ -441,7 +494,7 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
             }
 
             if let Some(next_key) = self.read_key_for_reference(j) {
-                let k = Self::hash(&next_key) % self.capacity();
+                let k = self.bucket(Self::hash(&next_key));
 
                 if (j < i) ^ (k <= i) ^ (k > j) {
                     self.write_and_own_key(i, Some(next_key));
@@ -459,10 +512,10 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
         self.write_and_own_key(i, None);
         self.len -= 1;
 
-        prev_value
+        (prev_key, prev_value)
     }
 
-    fn find_inner_idx<Q>(&self, key: &Q) -> Option<usize>
+    fn find_inner_idx<Q>(&self, key: &Q) -> Option<u64>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
@@ -472,7 +525,7 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
         }
 
         let key_hash = Self::hash(key);
-        let mut i = key_hash % self.capacity();
+        let mut i = self.bucket(key_hash);
 
         loop {
             if (*self.get_key(i)?).borrow().eq(key) {
@@ -483,18 +536,18 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
         }
     }
 
-    fn get_key(&self, idx: usize) -> Option<SRef<K>> {
+    fn get_key(&self, idx: u64) -> Option<SRef<K>> {
         let ptr = self.get_key_flag_ptr(idx);
         let flag: u8 = unsafe { crate::mem::read_fixed_for_reference(ptr) };
 
         match flag {
             EMPTY => None,
-            OCCUPIED => Some(unsafe { SRef::new(ptr + 1) }),
+            OCCUPIED => Some(unsafe { SRef::new_in(self.table_ptr, ptr + 1) }),
             _ => unreachable!(),
         }
     }
 
-    fn read_and_disown_key(&self, idx: usize) -> Option<K> {
+    fn read_and_disown_key(&self, idx: u64) -> Option<K> {
         let ptr = self.get_key_flag_ptr(idx);
         let flag: u8 = unsafe { crate::mem::read_fixed_for_reference(ptr) };
 
@@ -505,7 +558,7 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
         }
     }
 
-    fn read_key_for_reference(&self, idx: usize) -> Option<K> {
+    fn read_key_for_reference(&self, idx: u64) -> Option<K> {
         let ptr = self.get_key_flag_ptr(idx);
         let flag: u8 = unsafe { crate::mem::read_fixed_for_reference(ptr) };
 
@@ -516,7 +569,7 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
         }
     }
 
-    fn write_and_own_key(&mut self, idx: usize, key: Option<K>) {
+    fn write_and_own_key(&mut self, idx: u64, key: Option<K>) {
         let ptr = self.get_key_flag_ptr(idx);
 
         if let Some(mut k) = key {
@@ -530,44 +583,41 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
     }
 
     #[inline]
-    fn get_val(&self, idx: usize) -> SRef<V> {
-        unsafe { SRef::new(self.get_value_ptr(idx)) }
+    fn get_val(&self, idx: u64) -> SRef<V> {
+        unsafe { SRef::new_in(self.table_ptr, self.get_value_ptr(idx)) }
     }
 
     #[inline]
-    fn get_val_mut(&self, idx: usize) -> SRefMut<V> {
-        unsafe { SRefMut::new(self.get_value_ptr(idx)) }
+    fn get_val_mut(&self, idx: u64) -> SRefMut<V> {
+        unsafe { SRefMut::new_in(self.table_ptr, self.get_value_ptr(idx)) }
     }
 
     #[inline]
-    fn read_and_disown_val(&self, idx: usize) -> V {
+    fn read_and_disown_val(&self, idx: u64) -> V {
         unsafe { crate::mem::read_fixed_for_move(self.get_value_ptr(idx)) }
     }
 
     #[inline]
-    fn write_and_own_val(&mut self, idx: usize, mut val: V) {
+    fn write_and_own_val(&mut self, idx: u64, mut val: V) {
         unsafe { crate::mem::write_fixed(self.get_value_ptr(idx), &mut val) }
     }
 
     #[inline]
-    fn get_value_ptr(&self, idx: usize) -> StablePtr {
+    fn get_value_ptr(&self, idx: u64) -> StablePtr {
         SSlice::_offset(
             self.table_ptr,
-            (values_offset::<K>(self.capacity()) + V::SIZE * idx) as u64,
+            values_offset::<K>(self.capacity()) + V::SIZE as u64 * idx,
         )
     }
 
     #[inline]
-    fn get_key_flag_ptr(&self, idx: usize) -> StablePtr {
-        SSlice::_offset(self.table_ptr, (KEYS_OFFSET + (1 + K::SIZE) * idx) as u64)
+    fn get_key_flag_ptr(&self, idx: u64) -> StablePtr {
+        SSlice::_offset(self.table_ptr, KEYS_OFFSET + (1 + K::SIZE) as u64 * idx)
     }
 
     #[inline]
-    fn get_key_data_ptr(&self, idx: usize) -> StablePtr {
-        SSlice::_offset(
-            self.table_ptr,
-            (KEYS_OFFSET + (1 + K::SIZE) * idx + 1) as u64,
-        )
+    fn get_key_data_ptr(&self, idx: u64) -> StablePtr {
+        SSlice::_offset(self.table_ptr, KEYS_OFFSET + (1 + K::SIZE) as u64 * idx + 1)
     }
 
     /// Prints byte representation of this [SHashMap]
@@ -602,6 +652,47 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
     }
 }
 
+/// Serialized as a map, reading entries from stable memory one by one, in the order of buckets
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq + Serialize,
+        V: StableType + AsFixedSizeBytes + Serialize,
+    > Serialize for SHashMap<K, V>
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(usize::try_from(self.len()).ok())?;
+        for (k, v) in self.iter() {
+            map.serialize_entry(&*k, &*v)?;
+        }
+
+        map.end()
+    }
+}
+
+/// Encoded exactly as `HashMap<K, V>` (`vec record { K; V }`), reading entries from stable memory one
+/// by one
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq + CandidType,
+        V: StableType + AsFixedSizeBytes + CandidType,
+    > CandidType for SHashMap<K, V>
+{
+    #[inline]
+    fn _ty() -> Type {
+        <Vec<(K, V)>>::_ty()
+    }
+
+    fn idl_serialize<S>(&self, serializer: S) -> Result<(), S::Error>
+    where
+        S: candid::types::Serializer,
+    {
+        let mut ser = serializer.serialize_vec(usize::try_from(self.len()).unwrap())?;
+        for (k, v) in self.iter() {
+            Compound::serialize_element(&mut ser, &(&*k, &*v))?;
+        }
+
+        Ok(())
+    }
+}
+
 impl<
         K: StableType + AsFixedSizeBytes + Hash + Eq + Debug,
         V: StableType + AsFixedSizeBytes + Debug,
@@ -609,7 +700,7 @@ impl<
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str("{")?;
-        for (idx, (k, v)) in self.iter().enumerate() {
+        for (idx, (k, v)) in (0u64..).zip(self.iter()) {
             k.fmt(f)?;
             f.write_str(": ")?;
             v.fmt(f)?;
@@ -622,6 +713,105 @@ impl<
     }
 }
 
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> IntoIterator
+    for SHashMap<K, V>
+{
+    type Item = (K, V);
+    type IntoIter = SHashMapIntoIter<K, V>;
+
+    /// Returns a consuming iterator over entries of this [SHashMap]
+    ///
+    /// Entries are moved out of stable memory one by one, in unpredictable order. Once the iterator
+    /// is dropped, all entries that were not consumed are stable-dropped and the table of this
+    /// [SHashMap] is released.
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        SHashMapIntoIter::new(self)
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq + TryClone,
+        V: StableType + AsFixedSizeBytes + TryClone,
+    > TryClone for SHashMap<K, V>
+{
+    /// Copies all entries into a new [SHashMap] of the same capacity
+    fn try_clone(&self) -> Result<Self, OutOfMemory> {
+        if self.table_ptr == EMPTY_PTR {
+            return Ok(Self::new());
+        }
+
+        let mut res = Self::new_with_capacity(self.cap)?;
+
+        for (k, v) in self.iter() {
+            res.insert(k.try_clone()?, v.try_clone()?)
+                .map_err(|_| OutOfMemory)?;
+        }
+
+        Ok(res)
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+    TryFromIterator<(K, V)> for SHashMap<K, V>
+{
+    /// See [SHashMap::try_extend]
+    fn try_from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Result<Self, (Self, OutOfMemory)> {
+        let mut it = Self::new();
+
+        match it.try_extend(iter) {
+            Ok(_) => Ok(it),
+            Err(_) => Err((it, OutOfMemory)),
+        }
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: BuildHasher,
+    > TryFrom<HashMap<K, V, S>> for SHashMap<K, V>
+{
+    type Error = OutOfMemory;
+
+    /// Moves all entries of the provided [HashMap] into a new [SHashMap]
+    ///
+    /// # Errors
+    /// Returns [OutOfMemory] if the canister is out of stable memory. Everything allocated so far is
+    /// released.
+    #[inline]
+    fn try_from(value: HashMap<K, V, S>) -> Result<Self, Self::Error> {
+        Self::try_from_iter(value).map_err(|(_, e)| e)
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq + Clone,
+        V: StableType + AsFixedSizeBytes + Clone,
+    > SHashMap<K, V>
+{
+    /// Copies all entries into a heap [HashMap]
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # use std::collections::HashMap;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let heap_state = HashMap::from([(1u64, 10u64), (2, 20)]);
+    ///
+    /// let map = SHashMap::try_from(heap_state.clone()).expect("Out of memory");
+    ///
+    /// assert_eq!(map.to_std(), heap_state);
+    /// ```
+    pub fn to_std(&self) -> HashMap<K, V> {
+        self.iter()
+            .map(|(k, v)| ((*k).clone(), (*v).clone()))
+            .collect()
+    }
+}
+
 impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Default
     for SHashMap<K, V>
 {
@@ -634,24 +824,16 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
 impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
     AsFixedSizeBytes for SHashMap<K, V>
 {
-    const SIZE: usize = u64::SIZE + usize::SIZE * 2;
-    type Buf = [u8; u64::SIZE + usize::SIZE * 2];
+    // see encoding::counters for the layout of counters
+    const SIZE: usize = header_size(2);
+    type Buf = [u8; header_size(2)];
 
     fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        self.table_ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
-        self.len
-            .as_fixed_size_bytes(&mut buf[u64::SIZE..(usize::SIZE + u64::SIZE)]);
-        self.cap.as_fixed_size_bytes(
-            &mut buf[(usize::SIZE + u64::SIZE)..(usize::SIZE * 2 + u64::SIZE)],
-        );
+        encode_header(self.table_ptr, [self.len, self.cap], buf);
     }
 
     fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        let table_ptr = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
-        let len = usize::from_fixed_size_bytes(&buf[u64::SIZE..(usize::SIZE + u64::SIZE)]);
-        let cap = usize::from_fixed_size_bytes(
-            &buf[(usize::SIZE + u64::SIZE)..(usize::SIZE * 2 + u64::SIZE)],
-        );
+        let (table_ptr, [len, cap]) = decode_header(buf);
 
         Self {
             table_ptr,
@@ -707,16 +889,17 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
 #[cfg(test)]
 mod tests {
     use crate::collections::hash_map::SHashMap;
+    use crate::collections::{SHashSet, SLog};
     use crate::encoding::AsFixedSizeBytes;
     use crate::primitive::s_box::SBox;
-    use crate::primitive::StableType;
+    use crate::primitive::{StableType, TryClone, TryFromIterator};
     use crate::utils::mem_context::stable;
     use crate::utils::test::generate_random_string;
     use crate::utils::DebuglessUnwrap;
     use crate::{
-        _debug_validate_allocator, get_allocated_size, init_allocator, retrieve_custom_data,
-        stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
-        store_custom_data,
+        _debug_validate_allocator, deinit_allocator, get_allocated_size, init_allocator,
+        retrieve_custom_data, stable_memory_init, stable_memory_post_upgrade,
+        stable_memory_pre_upgrade, store_custom_data,
     };
     use rand::rngs::ThreadRng;
     use rand::seq::SliceRandom;
@@ -724,6 +907,81 @@ mod tests {
     use std::collections::HashMap;
     use std::ops::Deref;
 
+    #[test]
+    fn candid_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let heap_state = (0..100u64)
+                .map(|it| (it, it * 2))
+                .collect::<HashMap<_, _>>();
+            let map = SHashMap::try_from(heap_state.clone()).unwrap();
+
+            let buf = candid::encode_one(&map).unwrap();
+            assert_eq!(
+                candid::decode_one::<HashMap<u64, u64>>(&buf).unwrap(),
+                heap_state
+            );
+
+            let log = SLog::try_from_iter(0..10u8).ok().unwrap();
+            let buf = candid::encode_one(&log).unwrap();
+            assert_eq!(
+                buf,
+                candid::encode_one((0..10u8).collect::<Vec<_>>()).unwrap()
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn serialize_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let heap_state = (0..100u64)
+                .map(|it| (it, it * 2))
+                .collect::<HashMap<_, _>>();
+            let map = SHashMap::try_from(heap_state.clone()).unwrap();
+
+            let cbor = serde_cbor::to_vec(&map).unwrap();
+            assert_eq!(
+                serde_cbor::from_slice::<HashMap<u64, u64>>(&cbor).unwrap(),
+                heap_state
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn try_clone_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SHashMap::new();
+            for i in 0..100u64 {
+                map.insert(i, SBox::new(i.to_string()).unwrap()).unwrap();
+            }
+
+            let copy = map.try_clone().unwrap();
+            map.clear();
+
+            assert_eq!(copy.len(), 100);
+            for i in 0..100u64 {
+                assert_eq!(**copy.get(&i).unwrap(), i.to_string());
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[test]
     fn simple_flow_works_well() {
         stable::clear();
@@ -917,6 +1175,41 @@ mod tests {
         assert_eq!(get_allocated_size(), 0);
     }
 
+    #[test]
+    fn into_iter_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SHashMap::new();
+            for i in 0..100 {
+                map.insert(i, SBox::new(i).unwrap()).unwrap();
+            }
+
+            let mut res = map
+                .into_iter()
+                .map(|(k, v)| (k, v.into_inner()))
+                .collect::<Vec<_>>();
+            res.sort();
+
+            assert_eq!(res, (0..100).map(|i| (i, i)).collect::<Vec<_>>());
+
+            let mut map = SHashMap::new();
+            for i in 0..100 {
+                map.insert(SBox::new(i).unwrap(), SBox::new(i).unwrap())
+                    .unwrap();
+            }
+
+            let mut iter = map.into_iter();
+            let (k, v) = iter.next().unwrap();
+            assert_eq!(*k, *v);
+            assert_eq!(iter.len(), 99);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[test]
     fn sboxes_work_fine() {
         stable::clear();
@@ -1007,7 +1300,7 @@ mod tests {
                         return self.next();
                     }
 
-                    let idx = self.rng.gen_range(0..len);
+                    let idx = self.rng.gen_range(0..len as usize);
                     let key = self.keys.remove(idx);
 
                     self.map().remove(&key).unwrap();
@@ -1043,7 +1336,7 @@ mod tests {
             }
 
             _debug_validate_allocator();
-            assert_eq!(self.map().len(), self.example.len());
+            assert_eq!(self.map().len(), self.example.len() as u64);
 
             for key in self.keys.clone() {
                 let contains = self.map().contains_key(&key);
@@ -1057,6 +1350,49 @@ mod tests {
         }
     }
 
+    #[test]
+    fn try_from_iter_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SHashMap::try_from_iter((0..100u64).map(|it| (it, it * 2))).unwrap();
+            map.try_extend((50..150u64).map(|it| (it, it))).unwrap();
+
+            assert_eq!(map.len(), 150);
+            assert_eq!(*map.get(&10).unwrap(), 20);
+            assert_eq!(*map.get(&60).unwrap(), 60);
+
+            let set = SHashSet::try_from_iter(map.iter().map(|(k, _)| *k % 10)).unwrap();
+            assert_eq!(set.len(), 10);
+
+            let log = SLog::try_from_iter(map.iter().map(|(_, v)| *v))
+                .ok()
+                .unwrap();
+            assert_eq!(log.len(), 150);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+
+        deinit_allocator().unwrap();
+        stable::clear();
+        init_allocator(1);
+
+        {
+            let (map, _) =
+                SHashMap::<u64, u64>::try_from_iter((0..1_000_000).map(|it| (it, it))).unwrap_err();
+
+            assert!(!map.is_empty());
+            for (k, v) in map.iter() {
+                assert_eq!(*k, *v);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[test]
     fn fuzzer_works_fine() {
         stable::clear();
@@ -1,7 +1,7 @@
 use crate::collections::hash_map::SHashMap;
 use crate::collections::hash_set::iter::SHashSetIter;
 use crate::encoding::AsFixedSizeBytes;
-use crate::primitive::StableType;
+use crate::primitive::{StableType, TryClone, TryFromIterator};
 use crate::OutOfMemory;
 use std::borrow::Borrow;
 use std::fmt::{Debug, Formatter};
@@ -28,7 +28,7 @@ impl<T: StableType + AsFixedSizeBytes + Hash + Eq> SHashSet<T> {
 
     /// See [SHashMap::new_with_capacity]
     #[inline]
-    pub fn new_with_capacity(capacity: usize) -> Result<Self, OutOfMemory> {
+    pub fn new_with_capacity(capacity: u64) -> Result<Self, OutOfMemory> {
         Ok(Self {
             map: SHashMap::new_with_capacity(capacity)?,
         })
@@ -43,6 +43,14 @@ impl<T: StableType + AsFixedSizeBytes + Hash + Eq> SHashSet<T> {
             .map_err(|(k, _)| k)
     }
 
+    /// See [SHashMap::try_extend]
+    #[inline]
+    pub fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), T> {
+        self.map
+            .try_extend(iter.into_iter().map(|it| (it, ())))
+            .map_err(|(k, _)| k)
+    }
+
     /// See [SHashMap::remove]
     #[inline]
     pub fn remove<Q>(&mut self, value: &Q) -> bool
@@ -65,13 +73,13 @@ impl<T: StableType + AsFixedSizeBytes + Hash + Eq> SHashSet<T> {
 
     /// See [SHashMap::len]
     #[inline]
-    pub fn len(&self) -> usize {
+    pub fn len(&self) -> u64 {
         self.map.len()
     }
 
     /// See [SHashMap::capacity]
     #[inline]
-    pub fn capacity(&self) -> usize {
+    pub fn capacity(&self) -> u64 {
         self.map.capacity()
     }
 
@@ -100,6 +108,26 @@ impl<T: StableType + AsFixedSizeBytes + Hash + Eq> SHashSet<T> {
     }
 }
 
+impl<T: StableType + AsFixedSizeBytes + Hash + Eq + TryClone> TryClone for SHashSet<T> {
+    /// See [SHashMap::try_clone]
+    #[inline]
+    fn try_clone(&self) -> Result<Self, OutOfMemory> {
+        Ok(Self {
+            map: self.map.try_clone()?,
+        })
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Hash + Eq> TryFromIterator<T> for SHashSet<T> {
+    /// See [SHashMap::try_from_iter]
+    #[inline]
+    fn try_from_iter<I: IntoIterator<Item = T>>(iter: I) -> Result<Self, (Self, OutOfMemory)> {
+        SHashMap::try_from_iter(iter.into_iter().map(|it| (it, ())))
+            .map(|map| Self { map })
+            .map_err(|(map, e)| (Self { map }, e))
+    }
+}
+
 impl<T: StableType + AsFixedSizeBytes + Hash + Eq> Default for SHashSet<T> {
     #[inline]
     fn default() -> Self {
@@ -138,7 +166,7 @@ impl<T: StableType + AsFixedSizeBytes + Hash + Eq> StableType for SHashSet<T> {
 impl<T: StableType + AsFixedSizeBytes + Hash + Eq + Debug> Debug for SHashSet<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str("(")?;
-        for (idx, elem) in self.iter().enumerate() {
+        for (idx, elem) in (0u64..).zip(self.iter()) {
             elem.fmt(f)?;
 
             if idx < self.len() - 1 {
@@ -301,7 +329,7 @@ mod tests {
                         return self.next();
                     }
 
-                    let idx = self.rng.gen_range(0..len);
+                    let idx = self.rng.gen_range(0..len as usize);
                     let key = self.keys.remove(idx);
 
                     self.set().remove(&key);
@@ -0,0 +1,51 @@
+use crate::collections::index_map::SIndexMap;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::hash::Hash;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+
+pub struct SIndexMapIter<'a, K, V> {
+    node: StablePtr,
+    _marker: PhantomData<&'a (K, V)>,
+}
+
+impl<'a, K, V> SIndexMapIter<'a, K, V> {
+    pub(crate) fn new(head: StablePtr) -> Self {
+        Self {
+            node: head,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Hash + Eq + 'a,
+        V: StableType + AsFixedSizeBytes + 'a,
+    > Iterator for SIndexMapIter<'a, K, V>
+{
+    type Item = (SRef<'a, K>, SRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.node == EMPTY_PTR {
+            return None;
+        }
+
+        let node = self.node;
+        self.node = SIndexMap::<K, V>::next_of(node);
+
+        Some(SIndexMap::<K, V>::node_refs(node))
+    }
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Hash + Eq + 'a,
+        V: StableType + AsFixedSizeBytes + 'a,
+    > FusedIterator for SIndexMapIter<'a, K, V>
+{
+}
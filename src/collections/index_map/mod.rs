@@ -0,0 +1,581 @@
+use crate::collections::hash_map::SHashMap;
+use crate::collections::index_map::iter::SIndexMapIter;
+use crate::encoding::{AsFixedSizeBytes, Buffer};
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::{StableType, TryFromIterator};
+use crate::{allocate, deallocate, OutOfMemory, SSlice};
+use std::borrow::Borrow;
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+#[doc(hidden)]
+pub mod iter;
+
+// Node layout:
+// PREV: u64
+// NEXT: u64
+// KEY: K (a non-owning copy, the key is owned by the map)
+// VALUE: V
+
+const PREV_OFFSET: u64 = 0;
+const NEXT_OFFSET: u64 = PREV_OFFSET + u64::SIZE as u64;
+const KEY_OFFSET: u64 = NEXT_OFFSET + u64::SIZE as u64;
+
+#[inline]
+const fn value_offset<K: AsFixedSizeBytes>() -> u64 {
+    KEY_OFFSET + K::SIZE as u64
+}
+
+/// Hashmap, which remembers the insertion order of its keys
+///
+/// Iteration order of [SHashMap] depends on the capacity of the underlying table, so it changes
+/// after each rehash. [SIndexMap] always iterates over its entries in the order they were first
+/// inserted. Since this order is stored in stable memory itself, it is also preserved between
+/// canister upgrades, which makes it a good fit for query methods, whose results are paginated.
+///
+/// Built on top of [SHashMap], which maps keys to nodes of an intrusive doubly-linked list. Each
+/// node is a separate block of stable memory, which holds the value and is never moved, so removing
+/// an entry does not shift any other entries and does not affect their order. Replacing a value of
+/// an existing key keeps the entry at its original position.
+///
+/// Both `K` and `V` have to implement [StableType] and [AsFixedSizeBytes] traits. [SIndexMap] also
+/// implements these traits itself, so you can nest it inside other stable structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SIndexMap;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut map = SIndexMap::<u64, u64>::new();
+///
+/// map.insert(3, 30).expect("Out of memory");
+/// map.insert(1, 10).expect("Out of memory");
+/// map.insert(2, 20).expect("Out of memory");
+///
+/// // replacing a value does not move the entry
+/// map.insert(3, 31).expect("Out of memory");
+/// map.remove(&1);
+///
+/// let keys = map.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+/// assert_eq!(keys, vec![3, 2]);
+/// ```
+pub struct SIndexMap<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+{
+    map: SHashMap<K, u64>,
+    head: StablePtr,
+    tail: StablePtr,
+    _marker: PhantomData<V>,
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+    SIndexMap<K, V>
+{
+    /// Creates a new empty [SIndexMap]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            map: SHashMap::new(),
+            head: EMPTY_PTR,
+            tail: EMPTY_PTR,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of entries in this [SIndexMap]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    /// Returns [true] if there are no entries in this [SIndexMap]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Inserts a new entry at the end of this [SIndexMap]
+    ///
+    /// If the key already exists, replaces its value, keeping the entry at its position, and
+    /// returns the previous value.
+    ///
+    /// If the canister is out of stable memory, returns [Err] with the provided key-value pair.
+    pub fn insert(&mut self, key: K, mut value: V) -> Result<Option<V>, (K, V)> {
+        if let Some(node) = self.map.get(&key).map(|it| *it) {
+            let value_ptr = Self::value_ptr(node);
+
+            let prev_value = unsafe { crate::mem::read_fixed_for_move(value_ptr) };
+            unsafe { crate::mem::write_fixed(value_ptr, &mut value) };
+
+            return Ok(Some(prev_value));
+        }
+
+        let node = match unsafe { allocate(value_offset::<K>() + V::SIZE as u64) } {
+            Ok(slice) => slice.as_ptr(),
+            Err(_) => return Err((key, value)),
+        };
+
+        let mut key_buf = K::Buf::new(K::SIZE);
+        key.as_fixed_size_bytes(key_buf._deref_mut());
+
+        unsafe {
+            crate::mem::write_bytes(SSlice::_offset(node, KEY_OFFSET), key_buf._deref());
+            crate::mem::write_fixed(Self::value_ptr(node), &mut value);
+        }
+
+        if let Err((key, _)) = self.map.insert(key, node) {
+            let value = unsafe { crate::mem::read_fixed_for_move(Self::value_ptr(node)) };
+            deallocate(unsafe { SSlice::from_ptr(node).unwrap() });
+
+            return Err((key, value));
+        }
+
+        self.link_back(node);
+
+        Ok(None)
+    }
+
+    /// Inserts all entries of the provided iterator, in the order they are returned
+    ///
+    /// If the canister is out of stable memory, stops and returns [Err] with the key-value pair,
+    /// which could not be inserted. Entries inserted before that remain in this [SIndexMap].
+    pub fn try_extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) -> Result<(), (K, V)> {
+        for (k, v) in iter {
+            self.insert(k, v)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns an immutable reference [SRef] to a value stored by the key
+    ///
+    /// If no such key-value pair is found, returns [None]
+    pub fn get<Q>(&self, key: &Q) -> Option<SRef<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = *self.map.get(key)?;
+
+        unsafe { Some(SRef::new(Self::value_ptr(node))) }
+    }
+
+    /// Returns a mutable reference [SRefMut] to a value stored by the key
+    ///
+    /// If no such key-value pair is found, returns [None]
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<SRefMut<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = *self.map.get(key)?;
+
+        unsafe { Some(SRefMut::new(Self::value_ptr(node))) }
+    }
+
+    /// Returns [true] if there exists an entry with the provided key
+    #[inline]
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Removes an entry by the key, returning its value
+    ///
+    /// The order of other entries stays the same. If no such key-value pair is found, returns [None]
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = self.map.remove(key)?;
+
+        Some(self.destroy_node(node))
+    }
+
+    /// Returns the oldest entry of this [SIndexMap]
+    ///
+    /// If this [SIndexMap] is empty, returns [None]
+    pub fn first(&self) -> Option<(SRef<'_, K>, SRef<'_, V>)> {
+        if self.head == EMPTY_PTR {
+            return None;
+        }
+
+        Some(Self::node_refs(self.head))
+    }
+
+    /// Returns the most recently inserted entry of this [SIndexMap]
+    ///
+    /// If this [SIndexMap] is empty, returns [None]
+    pub fn last(&self) -> Option<(SRef<'_, K>, SRef<'_, V>)> {
+        if self.tail == EMPTY_PTR {
+            return None;
+        }
+
+        Some(Self::node_refs(self.tail))
+    }
+
+    /// Removes the oldest entry, returning it
+    ///
+    /// If this [SIndexMap] is empty, returns [None]
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        if self.head == EMPTY_PTR {
+            return None;
+        }
+
+        Some(self.pop_node(self.head))
+    }
+
+    /// Removes the most recently inserted entry, returning it
+    ///
+    /// If this [SIndexMap] is empty, returns [None]
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        if self.tail == EMPTY_PTR {
+            return None;
+        }
+
+        Some(self.pop_node(self.tail))
+    }
+
+    /// Removes all entries from this [SIndexMap], stable-dropping them
+    pub fn clear(&mut self) {
+        while self.pop_last().is_some() {}
+    }
+
+    /// Returns an iterator over entries of this [SIndexMap], in the order they were inserted
+    #[inline]
+    pub fn iter(&self) -> SIndexMapIter<'_, K, V> {
+        SIndexMapIter::new(self.head)
+    }
+
+    pub(crate) fn node_refs<'a>(node: StablePtr) -> (SRef<'a, K>, SRef<'a, V>) {
+        unsafe {
+            (
+                SRef::new(SSlice::_offset(node, KEY_OFFSET)),
+                SRef::new(Self::value_ptr(node)),
+            )
+        }
+    }
+
+    #[inline]
+    pub(crate) fn next_of(node: StablePtr) -> StablePtr {
+        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(node, NEXT_OFFSET)) }
+    }
+
+    #[inline]
+    fn prev_of(node: StablePtr) -> StablePtr {
+        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(node, PREV_OFFSET)) }
+    }
+
+    #[inline]
+    fn set_next(node: StablePtr, mut next: StablePtr) {
+        unsafe { crate::mem::write_fixed(SSlice::_offset(node, NEXT_OFFSET), &mut next) };
+    }
+
+    #[inline]
+    fn set_prev(node: StablePtr, mut prev: StablePtr) {
+        unsafe { crate::mem::write_fixed(SSlice::_offset(node, PREV_OFFSET), &mut prev) };
+    }
+
+    #[inline]
+    fn value_ptr(node: StablePtr) -> StablePtr {
+        SSlice::_offset(node, value_offset::<K>())
+    }
+
+    fn link_back(&mut self, node: StablePtr) {
+        Self::set_next(node, EMPTY_PTR);
+        Self::set_prev(node, self.tail);
+
+        if self.tail != EMPTY_PTR {
+            Self::set_next(self.tail, node);
+        } else {
+            self.head = node;
+        }
+
+        self.tail = node;
+    }
+
+    fn unlink(&mut self, node: StablePtr) {
+        let prev = Self::prev_of(node);
+        let next = Self::next_of(node);
+
+        if prev != EMPTY_PTR {
+            Self::set_next(prev, next);
+        } else {
+            self.head = next;
+        }
+
+        if next != EMPTY_PTR {
+            Self::set_prev(next, prev);
+        } else {
+            self.tail = prev;
+        }
+    }
+
+    fn pop_node(&mut self, node: StablePtr) -> (K, V) {
+        let key_ref: K =
+            unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(node, KEY_OFFSET)) };
+
+        let (key, _) = self.map.remove_entry(&key_ref).unwrap();
+        let value = self.destroy_node(node);
+
+        (key, value)
+    }
+
+    // the key should already be removed from the map
+    fn destroy_node(&mut self, node: StablePtr) -> V {
+        self.unlink(node);
+
+        let value = unsafe { crate::mem::read_fixed_for_move(Self::value_ptr(node)) };
+        deallocate(unsafe { SSlice::from_ptr(node).unwrap() });
+
+        value
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+    TryFromIterator<(K, V)> for SIndexMap<K, V>
+{
+    /// See [SIndexMap::try_extend]
+    fn try_from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Result<Self, (Self, OutOfMemory)> {
+        let mut it = Self::new();
+
+        match it.try_extend(iter) {
+            Ok(_) => Ok(it),
+            Err(_) => Err((it, OutOfMemory)),
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Default
+    for SIndexMap<K, V>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+    AsFixedSizeBytes for SIndexMap<K, V>
+{
+    const SIZE: usize = SHashMap::<K, u64>::SIZE + u64::SIZE * 2;
+    type Buf = Vec<u8>;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let map_size = SHashMap::<K, u64>::SIZE;
+
+        self.map.as_fixed_size_bytes(&mut buf[0..map_size]);
+        self.head
+            .as_fixed_size_bytes(&mut buf[map_size..(map_size + u64::SIZE)]);
+        self.tail
+            .as_fixed_size_bytes(&mut buf[(map_size + u64::SIZE)..(map_size + u64::SIZE * 2)]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let map_size = SHashMap::<K, u64>::SIZE;
+
+        let map = SHashMap::<K, u64>::from_fixed_size_bytes(&buf[0..map_size]);
+        let head = u64::from_fixed_size_bytes(&buf[map_size..(map_size + u64::SIZE)]);
+        let tail =
+            u64::from_fixed_size_bytes(&buf[(map_size + u64::SIZE)..(map_size + u64::SIZE * 2)]);
+
+        Self {
+            map,
+            head,
+            tail,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> StableType
+    for SIndexMap<K, V>
+{
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.map.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.map.stable_drop_flag_off();
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.map.should_stable_drop()
+    }
+
+    #[inline]
+    unsafe fn stable_drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Drop
+    for SIndexMap<K, V>
+{
+    fn drop(&mut self) {
+        // the map will stable-drop itself right after
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq + Debug,
+        V: StableType + AsFixedSizeBytes + Debug,
+    > Debug for SIndexMap<K, V>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("{")?;
+        for (idx, (k, v)) in (0u64..).zip(self.iter()) {
+            k.fmt(f)?;
+            f.write_str(": ")?;
+            v.fmt(f)?;
+
+            if idx < self.len() - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::index_map::SIndexMap;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::primitive::TryFromIterator;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn basic_flow_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SIndexMap::new();
+
+            for i in (0..100u64).rev() {
+                assert!(map.insert(i, i * 10).unwrap().is_none());
+            }
+
+            // no rehash affects the order
+            let keys = map.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+            assert_eq!(keys, (0..100u64).rev().collect::<Vec<_>>());
+
+            assert_eq!(map.insert(50, 51).unwrap(), Some(500));
+            *map.get_mut(&51).unwrap() = 1;
+            assert_eq!(*map.get(&50).unwrap(), 51);
+            assert!(map.contains_key(&51));
+
+            for i in (0..100u64).filter(|it| it % 2 == 0 && *it != 50) {
+                assert_eq!(map.remove(&i), Some(i * 10));
+                assert!(map.remove(&i).is_none());
+            }
+
+            let entries = map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+            let expected = (0..100u64)
+                .rev()
+                .filter(|it| it % 2 == 1 || *it == 50)
+                .map(|it| match it {
+                    50 => (50, 51),
+                    51 => (51, 1),
+                    _ => (it, it * 10),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(entries, expected);
+
+            assert_eq!(*map.first().unwrap().0, 99);
+            assert_eq!(*map.last().unwrap().0, 1);
+            assert_eq!(map.pop_first(), Some((99, 990)));
+            assert_eq!(map.pop_last(), Some((1, 10)));
+
+            let mut vec = SVec::new();
+            vec.push(map).unwrap();
+
+            let mut map = vec.pop().unwrap();
+            assert_eq!(map.len(), expected.len() as u64 - 2);
+            map.clear();
+
+            assert!(map.is_empty());
+            assert!(map.pop_first().is_none());
+            assert!(map.first().is_none());
+
+            let map = SIndexMap::try_from_iter((0..10u64).map(|it| (it, it))).unwrap();
+            assert_eq!(
+                format!("{:?}", map),
+                "{0: 0, 1: 1, 2: 2, 3: 3, 4: 4, 5: 5, 6: 6, 7: 7, 8: 8, 9: 9}"
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn random_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut map = SIndexMap::new();
+            // insertion order
+            let mut check: Vec<(u64, u64)> = Vec::new();
+
+            for i in 0..5000u64 {
+                let key = rng.gen_range(0..300u64);
+
+                if rng.gen_bool(0.6) {
+                    let prev = map
+                        .insert(SBox::new(key).unwrap(), SBox::new(i).unwrap())
+                        .unwrap()
+                        .map(|it| it.into_inner());
+
+                    let expected = match check.iter_mut().find(|(k, _)| *k == key) {
+                        Some(entry) => Some(std::mem::replace(&mut entry.1, i)),
+                        None => {
+                            check.push((key, i));
+                            None
+                        }
+                    };
+                    assert_eq!(prev, expected);
+                } else {
+                    let value = map.remove(&key).map(|it| it.into_inner());
+
+                    let expected = check
+                        .iter()
+                        .position(|(k, _)| *k == key)
+                        .map(|idx| check.remove(idx).1);
+                    assert_eq!(value, expected);
+                }
+
+                assert_eq!(map.len(), check.len() as u64);
+            }
+
+            let entries = map.iter().map(|(k, v)| (**k, **v)).collect::<Vec<_>>();
+            assert_eq!(entries, check);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
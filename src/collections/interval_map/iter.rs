@@ -0,0 +1,72 @@
+use crate::collections::btree_map::iter::SBTreeMapIter;
+use crate::collections::interval_map::SIntervalMap;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::iter::FusedIterator;
+use std::ops::Range;
+
+pub struct SIntervalMapIter<'a, K, V> {
+    inner: SBTreeMapIter<'a, K, (K, V)>,
+    // intervals ending at or before this point are skipped
+    after: Option<K>,
+    // iteration stops at the first interval starting at or after this point
+    until: Option<K>,
+    finished: bool,
+}
+
+impl<'a, K, V> SIntervalMapIter<'a, K, V> {
+    #[inline]
+    pub(crate) fn new(
+        inner: SBTreeMapIter<'a, K, (K, V)>,
+        after: Option<K>,
+        until: Option<K>,
+    ) -> Self {
+        Self {
+            inner,
+            after,
+            until,
+            finished: false,
+        }
+    }
+}
+
+impl<'a, K: StableType + AsFixedSizeBytes + Ord + Clone, V: StableType + AsFixedSizeBytes> Iterator
+    for SIntervalMapIter<'a, K, V>
+{
+    type Item = (Range<K>, SRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        loop {
+            let (end, entry) = self.inner.next()?;
+
+            if let Some(after) = &self.after {
+                if *end <= *after {
+                    continue;
+                }
+            }
+
+            if let Some(until) = &self.until {
+                if entry.0 >= *until {
+                    self.finished = true;
+
+                    return None;
+                }
+            }
+
+            return Some((
+                entry.0.clone()..(*end).clone(),
+                SIntervalMap::value_ref(&entry),
+            ));
+        }
+    }
+}
+
+impl<'a, K: StableType + AsFixedSizeBytes + Ord + Clone, V: StableType + AsFixedSizeBytes>
+    FusedIterator for SIntervalMapIter<'a, K, V>
+{
+}
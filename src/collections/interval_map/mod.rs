@@ -0,0 +1,315 @@
+use crate::collections::btree_map::SBTreeMap;
+use crate::collections::interval_map::iter::SIntervalMapIter;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::fmt::{Debug, Formatter};
+use std::ops::Range;
+
+#[doc(hidden)]
+pub mod iter;
+
+/// Map of non-overlapping half-open intervals `[start, end)` to values on top of [SBTreeMap]
+///
+/// Answers "which interval contains point `x`" (a stabbing query) in O(logN) and lists all
+/// intervals intersecting a given range. Useful for IP-range allow-lists or time-slot reservations.
+///
+/// Intervals are stored in an [SBTreeMap], keyed by their (exclusive) end, with their start stored
+/// next to the value. Since intervals never overlap, ordering them by end is the same as ordering
+/// them by start, and the interval containing a point is always the first one ending after it.
+///
+/// `K` has to implement [StableType], [AsFixedSizeBytes], [Ord] and [Clone] - bounds of intervals
+/// are cloned out of stable memory, when returned. `V` has to implement both [StableType] and
+/// [AsFixedSizeBytes]. [SIntervalMap] itself implements these traits and can be nested inside other
+/// stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SIntervalMap;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut allow_list = SIntervalMap::<u32, u8>::new();
+///
+/// // 10.0.0.0 - 10.0.0.255
+/// allow_list.insert(0x0a000000..0x0a000100, 1).expect("Overlaps or out of memory");
+/// // 192.168.0.0 - 192.168.255.255
+/// allow_list.insert(0xc0a80000..0xc0a90000, 2).expect("Overlaps or out of memory");
+///
+/// let (range, level) = allow_list.get(&0xc0a80101).unwrap();
+/// assert_eq!(range, 0xc0a80000..0xc0a90000);
+/// assert_eq!(*level, 2);
+///
+/// assert!(allow_list.get(&0x0a000100).is_none());
+/// assert!(allow_list.insert(0x0a0000ff..0x0a000200, 3).is_err());
+/// ```
+pub struct SIntervalMap<
+    K: StableType + AsFixedSizeBytes + Ord + Clone,
+    V: StableType + AsFixedSizeBytes,
+> {
+    inner: SBTreeMap<K, (K, V)>,
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord + Clone, V: StableType + AsFixedSizeBytes>
+    SIntervalMap<K, V>
+{
+    /// Creates a new [SIntervalMap]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            inner: SBTreeMap::new(),
+        }
+    }
+
+    /// Returns the number of intervals in this [SIntervalMap]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    /// Returns [true] if there are no intervals in this [SIntervalMap]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Inserts a new interval with a value attached to it
+    ///
+    /// If the interval overlaps with any interval already stored in this [SIntervalMap], or if the
+    /// canister is out of stable memory, returns [Err] with the interval and the value. Use
+    /// [SIntervalMap::overlaps] to tell these two cases apart.
+    ///
+    /// # Panics
+    /// Panics if the interval is empty (`start >= end`).
+    pub fn insert(&mut self, range: Range<K>, value: V) -> Result<(), (Range<K>, V)> {
+        assert!(range.start < range.end, "empty interval");
+
+        if self.overlaps(&range) {
+            return Err((range, value));
+        }
+
+        self.inner
+            .insert(range.end, (range.start, value))
+            .map(|_| ())
+            .map_err(|(end, (start, value))| (start..end, value))
+    }
+
+    /// Returns [true] if any interval stored in this [SIntervalMap] intersects with the provided one
+    #[inline]
+    pub fn overlaps(&self, range: &Range<K>) -> bool {
+        self.iter_overlapping(range).next().is_some()
+    }
+
+    /// Returns the interval containing the point and a [SRef] to its value
+    ///
+    /// If there is no such interval, returns [None].
+    pub fn get(&self, point: &K) -> Option<(Range<K>, SRef<'_, V>)> {
+        let (end, entry) = self.first_ending_after(point)?;
+
+        if entry.0 > *point {
+            return None;
+        }
+
+        Some((entry.0.clone()..end, Self::value_ref(&entry)))
+    }
+
+    /// Returns [true] if any interval contains the point
+    #[inline]
+    pub fn contains(&self, point: &K) -> bool {
+        self.get(point).is_some()
+    }
+
+    /// Removes the interval containing the point, returning it back together with its value
+    ///
+    /// If there is no such interval, returns [None].
+    pub fn remove(&mut self, point: &K) -> Option<(Range<K>, V)> {
+        let (range, _) = self.get(point)?;
+        let (start, value) = self.inner.remove(&range.end).unwrap();
+
+        Some((start..range.end, value))
+    }
+
+    /// Removes all intervals from this [SIntervalMap]
+    #[inline]
+    pub fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    /// Returns an iterator over all intervals, intersecting with the provided one, in ascending order
+    #[inline]
+    pub fn iter_overlapping(&self, range: &Range<K>) -> SIntervalMapIter<'_, K, V> {
+        SIntervalMapIter::new(
+            self.inner.iter_from(&range.start),
+            Some(range.start.clone()),
+            Some(range.end.clone()),
+        )
+    }
+
+    /// Returns an iterator over all intervals of this [SIntervalMap] in ascending order
+    #[inline]
+    pub fn iter(&self) -> SIntervalMapIter<'_, K, V> {
+        SIntervalMapIter::new(self.inner.iter(), None, None)
+    }
+
+    fn first_ending_after(&self, point: &K) -> Option<(K, SRef<'_, (K, V)>)> {
+        self.inner
+            .iter_from(point)
+            .find(|(end, _)| **end > *point)
+            .map(|(end, entry)| ((*end).clone(), entry))
+    }
+
+    // the value is stored right after the start of the interval
+    #[inline]
+    pub(crate) fn value_ref<'a>(entry: &SRef<'a, (K, V)>) -> SRef<'a, V> {
+        unsafe { SRef::new(entry.as_ptr() + K::SIZE as u64) }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord + Clone, V: StableType + AsFixedSizeBytes> Default
+    for SIntervalMap<K, V>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord + Clone, V: StableType + AsFixedSizeBytes>
+    AsFixedSizeBytes for SIntervalMap<K, V>
+{
+    const SIZE: usize = SBTreeMap::<K, (K, V)>::SIZE;
+    type Buf = <SBTreeMap<K, (K, V)> as AsFixedSizeBytes>::Buf;
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.inner.as_fixed_size_bytes(buf)
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        let inner = SBTreeMap::<K, (K, V)>::from_fixed_size_bytes(arr);
+        Self { inner }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord + Clone, V: StableType + AsFixedSizeBytes> StableType
+    for SIntervalMap<K, V>
+{
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.inner.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.inner.stable_drop_flag_off();
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Ord + Clone + Debug,
+        V: StableType + AsFixedSizeBytes + Debug,
+    > Debug for SIntervalMap<K, V>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("{")?;
+        for (idx, (range, value)) in self.iter().enumerate() {
+            range.fmt(f)?;
+            f.write_str(": ")?;
+            value.fmt(f)?;
+
+            if idx < (self.len() - 1) as usize {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::interval_map::SIntervalMap;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+    use std::ops::Range;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut map = SIntervalMap::new();
+            let mut check: Vec<(Range<u64>, u64)> = Vec::new();
+
+            for i in 0..2000u64 {
+                let start = rng.gen_range(0..10_000u64);
+                let range = start..(start + rng.gen_range(1..50));
+
+                if rng.gen_bool(0.7) {
+                    let overlaps = check
+                        .iter()
+                        .any(|(r, _)| r.start < range.end && range.start < r.end);
+
+                    assert_eq!(map.overlaps(&range), overlaps);
+
+                    let res = map.insert(range.clone(), SBox::new(i).unwrap());
+                    assert_eq!(res.is_err(), overlaps);
+
+                    if !overlaps {
+                        check.push((range, i));
+                    }
+                } else {
+                    let point = range.start;
+                    let pos = check.iter().position(|(r, _)| r.contains(&point));
+
+                    let removed = map.remove(&point).map(|(r, v)| (r, v.into_inner()));
+                    assert_eq!(removed, pos.map(|it| check.remove(it)));
+                }
+
+                assert_eq!(map.len(), check.len() as u64);
+            }
+
+            for point in 0..10_050u64 {
+                let expected = check.iter().find(|(r, _)| r.contains(&point)).cloned();
+                let actual = map.get(&point).map(|(r, v)| (r, **v));
+
+                assert_eq!(actual, expected);
+            }
+
+            check.sort_by_key(|(r, _)| r.start);
+
+            let all = map.iter().map(|(r, v)| (r, **v)).collect::<Vec<_>>();
+            assert_eq!(all, check);
+
+            let query = 2000..3000u64;
+            let overlapping = map
+                .iter_overlapping(&query)
+                .map(|(r, v)| (r, **v))
+                .collect::<Vec<_>>();
+            let expected = check
+                .iter()
+                .filter(|(r, _)| r.start < query.end && query.start < r.end)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            assert_eq!(overlapping, expected);
+
+            let mut vec = SVec::new();
+            vec.push(map).unwrap();
+
+            let mut map = vec.pop().unwrap();
+            map.clear();
+            assert!(map.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
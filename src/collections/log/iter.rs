@@ -4,6 +4,7 @@ use crate::mem::allocator::EMPTY_PTR;
 use crate::mem::StablePtr;
 use crate::primitive::s_ref::SRef;
 use crate::primitive::StableType;
+use std::iter::FusedIterator;
 
 struct CurSector {
     ptr: StablePtr,
@@ -52,8 +53,8 @@ impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SLogIter<'a, T> {
 
         self.init_from_back();
 
-        let p = self.log.cur_sector_ptr;
-        let c = self.log.cur_sector_capacity;
+        let first_sector_ptr = self.log.first_sector_ptr;
+        let pruned_len = self.log.pruned_len;
 
         let cur_sector = self.get_cur_sector_mut();
 
@@ -64,18 +65,229 @@ impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SLogIter<'a, T> {
         let sector = Sector::<T>::from_ptr(cur_sector.ptr);
         let ptr = sector.get_element_ptr(cur_sector.idx * T::SIZE as u64);
 
-        if cur_sector.idx == 0 {
-            cur_sector.len = if cur_sector.ptr == p {
-                c / 2
-            } else {
-                cur_sector.len / 2
-            };
+        let min_idx = if cur_sector.ptr == first_sector_ptr {
+            pruned_len
+        } else {
+            0
+        };
+
+        if cur_sector.idx == min_idx {
             cur_sector.ptr = sector.read_prev_ptr();
-            cur_sector.idx = cur_sector.len - 1;
+
+            if cur_sector.ptr != EMPTY_PTR {
+                cur_sector.len = Sector::<T>::from_ptr(cur_sector.ptr).read_capacity();
+                cur_sector.idx = cur_sector.len - 1;
+            }
         } else {
             cur_sector.idx -= 1;
         }
 
-        unsafe { Some(SRef::new(ptr)) }
+        unsafe { Some(SRef::new_in(sector.as_ptr(), ptr)) }
+    }
+}
+
+struct SectorCursor {
+    ptr: StablePtr,
+    start_idx: u64,
+    len: u64,
+}
+
+pub struct SLogRangeIter<'a, T: StableType + AsFixedSizeBytes> {
+    log: &'a SLog<T>,
+    front_idx: u64,
+    back_idx: u64,
+    front: Option<SectorCursor>,
+    back: Option<SectorCursor>,
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> SLogRangeIter<'a, T> {
+    pub(crate) fn new(log: &'a SLog<T>, from: u64, to: u64) -> Self {
+        // indices are counted from the beginning of the first sector, including pruned elements
+        Self {
+            log,
+            front_idx: from + log.pruned_len,
+            back_idx: to + log.pruned_len,
+            front: None,
+            back: None,
+        }
+    }
+
+    fn cursor_for_idx(&self, idx: u64) -> SectorCursor {
+        let (sector, start_idx) = self
+            .log
+            .find_sector_for_idx(idx - self.log.pruned_len)
+            .unwrap();
+        let len = self.log.sector_len(&sector);
+
+        SectorCursor {
+            ptr: sector.as_ptr(),
+            start_idx,
+            len,
+        }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SLogRangeIter<'a, T> {
+    type Item = SRef<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front_idx == self.back_idx {
+            return None;
+        }
+
+        let front = match self.front.take() {
+            Some(c) if self.front_idx < c.start_idx + c.len => c,
+            // moving forward by one sector, if possible
+            Some(c) if self.front_idx == c.start_idx + c.len => {
+                let next = Sector::<T>::from_ptr(Sector::<T>::from_ptr(c.ptr).read_next_ptr());
+
+                SectorCursor {
+                    ptr: next.as_ptr(),
+                    start_idx: c.start_idx + c.len,
+                    len: self.log.sector_len(&next),
+                }
+            }
+            _ => self.cursor_for_idx(self.front_idx),
+        };
+
+        let sector = Sector::<T>::from_ptr(front.ptr);
+        let ptr = sector.get_element_ptr((self.front_idx - front.start_idx) * T::SIZE as u64);
+
+        self.front_idx += 1;
+        self.front = Some(front);
+
+        unsafe { Some(SRef::new_in(sector.as_ptr(), ptr)) }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.back_idx - self.front_idx) as usize;
+
+        (len, Some(len))
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> DoubleEndedIterator for SLogRangeIter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front_idx == self.back_idx {
+            return None;
+        }
+
+        self.back_idx -= 1;
+
+        let back = match self.back.take() {
+            Some(c) if self.back_idx >= c.start_idx => c,
+            // moving backward by one sector, if possible
+            Some(c) if self.back_idx + 1 == c.start_idx => {
+                let prev = Sector::<T>::from_ptr(Sector::<T>::from_ptr(c.ptr).read_prev_ptr());
+                let len = prev.read_capacity();
+
+                SectorCursor {
+                    ptr: prev.as_ptr(),
+                    start_idx: c.start_idx - len,
+                    len,
+                }
+            }
+            _ => self.cursor_for_idx(self.back_idx),
+        };
+
+        let sector = Sector::<T>::from_ptr(back.ptr);
+        let ptr = sector.get_element_ptr((self.back_idx - back.start_idx) * T::SIZE as u64);
+
+        self.back = Some(back);
+
+        unsafe { Some(SRef::new_in(sector.as_ptr(), ptr)) }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> FusedIterator for SLogRangeIter<'a, T> {}
+
+pub struct SLogIntoIter<T: StableType + AsFixedSizeBytes> {
+    sector: Option<Sector<T>>,
+    sector_len: u64,
+    idx: u64,
+    last_sector_ptr: StablePtr,
+    last_sector_len: u64,
+    remaining: u64,
+}
+
+impl<T: StableType + AsFixedSizeBytes> SLogIntoIter<T> {
+    pub(crate) fn new(mut log: SLog<T>) -> Self {
+        let remaining = log.len;
+        let last_sector_ptr = log.cur_sector_ptr;
+        let last_sector_len = log.cur_sector_len;
+        let first_idx = log.pruned_len;
+
+        let sector = log.get_first_sector();
+        let sector_len = match &sector {
+            Some(s) if s.as_ptr() != last_sector_ptr => s.read_capacity(),
+            _ => last_sector_len,
+        };
+
+        // the log won't release anything by itself after this
+        log.len = 0;
+        log.cur_sector_ptr = EMPTY_PTR;
+
+        Self {
+            sector,
+            sector_len,
+            idx: first_idx,
+            last_sector_ptr,
+            last_sector_len,
+            remaining,
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Iterator for SLogIntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let sector = self.sector.as_ref()?;
+
+            if self.idx < self.sector_len {
+                let it = sector.read_and_disown_element(self.idx * T::SIZE as u64);
+
+                self.idx += 1;
+                self.remaining -= 1;
+
+                return Some(it);
+            }
+
+            let next_ptr = if sector.as_ptr() == self.last_sector_ptr {
+                EMPTY_PTR
+            } else {
+                sector.read_next_ptr()
+            };
+
+            unsafe { self.sector.take().unwrap_unchecked() }.destroy();
+
+            if next_ptr == EMPTY_PTR {
+                return None;
+            }
+
+            let next_sector = Sector::<T>::from_ptr(next_ptr);
+
+            self.sector_len = if next_ptr == self.last_sector_ptr {
+                self.last_sector_len
+            } else {
+                next_sector.read_capacity()
+            };
+            self.idx = 0;
+            self.sector = Some(next_sector);
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Drop for SLogIntoIter<T> {
+    fn drop(&mut self) {
+        // stable-dropping elements that were not consumed and releasing the rest of sectors
+        for _ in self.by_ref() {}
     }
 }
@@ -1,19 +1,28 @@
-use crate::collections::log::iter::SLogIter;
+use crate::collections::log::iter::{SLogIntoIter, SLogIter, SLogRangeIter};
 use crate::encoding::AsFixedSizeBytes;
 use crate::mem::allocator::EMPTY_PTR;
 use crate::mem::StablePtr;
 use crate::primitive::s_ref::SRef;
 use crate::primitive::s_ref_mut::SRefMut;
-use crate::primitive::StableType;
+use crate::primitive::{StableType, TryFromIterator};
 use crate::{allocate, deallocate, OutOfMemory, SSlice};
+use candid::types::{Compound, Type};
+use candid::{encode_one, CandidType, Deserialize};
+use serde::ser::SerializeSeq;
+use serde::{Serialize, Serializer};
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
 
 #[doc(hidden)]
 pub mod iter;
 
 pub(crate) const DEFAULT_CAPACITY: u64 = 2;
 
+// pruned elements of the first sector are counted in a pointer-sized slot, so on wasm32 a sector
+// should never hold more elements than fit into u32
+const MAX_SECTOR_CAPACITY: u64 = u32::MAX as u64;
+
 /// Non-reallocating growing vector optimized for storing logs or history entries
 ///
 /// Very similar to [SVec](crate::collections::SVec), but internally does not perform reallocations
@@ -39,6 +48,7 @@ pub struct SLog<T: StableType + AsFixedSizeBytes> {
     cur_sector_last_item_offset: u64,
     cur_sector_capacity: u64,
     cur_sector_len: u64,
+    pruned_len: u64,
     stable_drop_flag: bool,
     _marker: PhantomData<T>,
 }
@@ -56,6 +66,7 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
             cur_sector_last_item_offset: 0,
             cur_sector_capacity: DEFAULT_CAPACITY,
             cur_sector_len: 0,
+            pruned_len: 0,
             stable_drop_flag: true,
             _marker: PhantomData::default(),
         }
@@ -76,9 +87,85 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
     ///
     /// log.push(10u64).expect("Out of memory");
     /// ```
+    #[inline]
     pub fn push(&mut self, it: T) -> Result<(), T> {
+        self.push_inner(it, MAX_SECTOR_CAPACITY)
+    }
+
+    /// Inserts all elements of the provided iterator at the end of the [SLog]
+    ///
+    /// If the canister is out of stable memory, will return [Err] with the element that was about
+    /// to get inserted. All elements inserted before it stay in this [SLog], the rest of the
+    /// iterator is left unconsumed.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// log.try_extend(0..100u64).expect("Out of memory");
+    ///
+    /// assert_eq!(log.len(), 100);
+    /// ```
+    pub fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), T> {
+        for it in iter {
+            self.push(it)?;
+        }
+
+        Ok(())
+    }
+
+    /// Inserts a new element at the end of the [SLog], evicting the oldest elements, so the length
+    /// never exceeds `max_len`
+    ///
+    /// Using only this method to insert elements turns this [SLog] into a ring buffer, holding the
+    /// last `max_len` elements. Evicted elements are stable-dropped and `Sectors` which become empty
+    /// are deallocated, so their memory is reused by new `Sectors`. New `Sectors` never get bigger
+    /// than `max_len` elements.
+    ///
+    /// Eviction happens before the insertion, so if the canister is out of stable memory, oldest
+    /// elements are still evicted, but the element is returned back as [Err].
+    ///
+    /// # Panics
+    /// Panics if `max_len` is `0`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..1000u64 {
+    ///     log.push_bounded(i, 100).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(log.len(), 100);
+    /// assert_eq!(*log.first().unwrap(), 900);
+    /// assert_eq!(*log.last().unwrap(), 999);
+    /// ```
+    pub fn push_bounded(&mut self, it: T, max_len: u64) -> Result<(), T> {
+        assert!(max_len > 0, "max_len should be greater than zero");
+
+        if self.len >= max_len {
+            self.prune_front(self.len - max_len + 1);
+        }
+
+        let max_sector_capacity = u64::max(max_len, DEFAULT_CAPACITY * 2);
+
+        self.push_inner(it, u64::min(max_sector_capacity, MAX_SECTOR_CAPACITY))
+    }
+
+    fn push_inner(&mut self, it: T, max_sector_capacity: u64) -> Result<(), T> {
         if let Ok(mut sector) = self.get_or_create_current_sector() {
-            if self.move_to_next_sector_if_needed(&mut sector).is_ok() {
+            if self
+                .move_to_next_sector_if_needed(&mut sector, max_sector_capacity)
+                .is_ok()
+            {
                 sector.write_and_own_element(self.cur_sector_last_item_offset, it);
                 self.cur_sector_last_item_offset += T::SIZE as u64;
                 self.cur_sector_len += 1;
@@ -110,6 +197,7 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
 
         let it = sector.read_and_disown_element(self.cur_sector_last_item_offset);
 
+        self.reset_pruned_if_empty();
         self.move_to_prev_sector_if_needed(sector);
 
         Some(it)
@@ -123,6 +211,160 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
         while self.pop().is_some() {}
     }
 
+    /// Removes `n` oldest elements from the front of this [SLog]
+    ///
+    /// Removed elements are stable-dropped. Every `Sector` that becomes empty gets deallocated,
+    /// freeing the memory. No elements are moved, so this call only touches removed elements.
+    ///
+    /// After this call, indices of all remaining elements are shifted by `n`. If `n` is greater
+    /// than the length of this [SLog], all elements are removed.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// log.prune_front(90);
+    ///
+    /// assert_eq!(log.len(), 10);
+    /// assert_eq!(*log.first().unwrap(), 90);
+    /// ```
+    pub fn prune_front(&mut self, n: u64) {
+        let mut remaining = u64::min(n, self.len);
+
+        while remaining > 0 {
+            let sector = Sector::<T>::from_ptr(self.first_sector_ptr);
+            let is_current = sector.as_ptr() == self.cur_sector_ptr;
+            let alive = self.sector_len(&sector) - self.pruned_len;
+
+            let pruned = u64::min(remaining, alive);
+            for i in self.pruned_len..(self.pruned_len + pruned) {
+                sector.read_and_disown_element(i * T::SIZE as u64);
+            }
+
+            self.len -= pruned;
+            remaining -= pruned;
+
+            if pruned == alive && !is_current {
+                let next_sector_ptr = sector.read_next_ptr();
+                sector.destroy();
+
+                let mut next_sector = Sector::<T>::from_ptr(next_sector_ptr);
+                next_sector.write_prev_ptr(EMPTY_PTR);
+
+                self.first_sector_ptr = next_sector_ptr;
+                self.pruned_len = 0;
+            } else {
+                self.pruned_len += pruned;
+            }
+        }
+
+        self.reset_pruned_if_empty();
+
+        // only sectors, allocated before their capacity was limited, can get here
+        if self.pruned_len > MAX_SECTOR_CAPACITY {
+            self.shift_first_sector();
+        }
+    }
+
+    /// Rewrites partially filled `Sectors` into dense ones, releasing unused memory, and returns
+    /// the number of reclaimed bytes
+    ///
+    /// Only two `Sectors` can be partially filled: the first one, if some elements were removed by
+    /// [SLog::prune_front], and the last one, which keeps spare capacity for next elements or is
+    /// left half-empty by [SLog::pop]. Only these two are rewritten, so this call takes time,
+    /// proportional to their size. If this [SLog] is empty, all its memory is released.
+    ///
+    /// After this call the next [SLog::push] allocates a new `Sector`. If the canister is out of
+    /// stable memory, returns [OutOfMemory] - this [SLog] stays valid, but can be left compacted
+    /// only partially.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::{get_allocated_size, stable_memory_init};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    /// log.try_extend(0..1000u64).expect("Out of memory");
+    ///
+    /// log.prune_front(900);
+    ///
+    /// let before = get_allocated_size();
+    /// let reclaimed = log.compact().expect("Out of memory");
+    ///
+    /// assert!(reclaimed > 0);
+    /// assert_eq!(get_allocated_size(), before - reclaimed);
+    /// assert_eq!(*log.first().unwrap(), 900);
+    /// ```
+    pub fn compact(&mut self) -> Result<u64, OutOfMemory> {
+        if self.cur_sector_ptr == EMPTY_PTR {
+            return Ok(0);
+        }
+
+        // emptied sectors are always deallocated, so only the current one is left
+        if self.len == 0 {
+            let sector = Sector::<T>::from_ptr(self.cur_sector_ptr);
+            let reclaimed = sector.get_total_size_bytes();
+
+            sector.destroy();
+
+            self.first_sector_ptr = EMPTY_PTR;
+            self.cur_sector_ptr = EMPTY_PTR;
+            self.cur_sector_capacity = DEFAULT_CAPACITY;
+            self.cur_sector_len = 0;
+            self.cur_sector_last_item_offset = 0;
+            self.pruned_len = 0;
+
+            return Ok(reclaimed);
+        }
+
+        let mut reclaimed = 0;
+
+        if self.first_sector_ptr != self.cur_sector_ptr && self.pruned_len > 0 {
+            let sector = Sector::<T>::from_ptr(self.first_sector_ptr);
+            let capacity = sector.read_capacity();
+
+            reclaimed += self.rewrite_sector(
+                sector,
+                self.pruned_len,
+                capacity,
+                capacity - self.pruned_len,
+            )?;
+            self.pruned_len = 0;
+        }
+
+        let from = if self.first_sector_ptr == self.cur_sector_ptr {
+            self.pruned_len
+        } else {
+            0
+        };
+        let len = self.cur_sector_len - from;
+
+        // the next sector is twice as big as the current one, so it should not be too small
+        let capacity = u64::max(len, DEFAULT_CAPACITY);
+
+        if capacity < self.cur_sector_capacity {
+            let sector = Sector::<T>::from_ptr(self.cur_sector_ptr);
+
+            reclaimed += self.rewrite_sector(sector, from, self.cur_sector_len, capacity)?;
+
+            self.cur_sector_capacity = capacity;
+            self.cur_sector_len = len;
+            self.cur_sector_last_item_offset = len * T::SIZE as u64;
+            self.pruned_len = 0;
+        }
+
+        Ok(reclaimed)
+    }
+
     /// Returns an immutable reference [SRef] to the last element of this [SLog]
     ///
     /// If the [SLog] is empty, returns [None].
@@ -147,7 +389,7 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
         let sector = self.get_current_sector()?;
         let ptr = sector.get_element_ptr(self.cur_sector_last_item_offset - T::SIZE as u64);
 
-        unsafe { Some(SRef::new(ptr)) }
+        unsafe { Some(SRef::new_in(sector.as_ptr(), ptr)) }
     }
 
     /// Efficiently returns an immutable reference [SRef] to the first element of this [SLog]
@@ -172,9 +414,9 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
         }
 
         let sector = self.get_first_sector()?;
-        let ptr = sector.get_element_ptr(0);
+        let ptr = sector.get_element_ptr(self.pruned_len * T::SIZE as u64);
 
-        unsafe { Some(SRef::new(ptr)) }
+        unsafe { Some(SRef::new_in(sector.as_ptr(), ptr)) }
     }
 
     /// Returns an immutable reference [SRef] to an element at the requested index
@@ -187,9 +429,9 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
     #[inline]
     pub fn get(&self, idx: u64) -> Option<SRef<T>> {
         let (sector, dif) = self.find_sector_for_idx(idx)?;
-        let ptr = sector.get_element_ptr((idx - dif) * T::SIZE as u64);
+        let ptr = sector.get_element_ptr((idx + self.pruned_len - dif) * T::SIZE as u64);
 
-        unsafe { Some(SRef::new(ptr)) }
+        unsafe { Some(SRef::new_in(sector.as_ptr(), ptr)) }
     }
 
     /// Returns a mutable reference [SRefMut] to an element at the requested index
@@ -202,9 +444,9 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
     #[inline]
     pub fn get_mut(&mut self, idx: u64) -> Option<SRefMut<T>> {
         let (sector, dif) = self.find_sector_for_idx(idx)?;
-        let ptr = sector.get_element_ptr((idx - dif) * T::SIZE as u64);
+        let ptr = sector.get_element_ptr((idx + self.pruned_len - dif) * T::SIZE as u64);
 
-        unsafe { Some(SRefMut::new(ptr)) }
+        unsafe { Some(SRefMut::new_in(sector.as_ptr(), ptr)) }
     }
 
     /// Returns the length of this [SLog]
@@ -246,15 +488,198 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
         SLogIter::new(self)
     }
 
+    /// Returns a double-ended iterator over the requested range of elements of this [SLog]
+    ///
+    /// Unlike [SLog::get], this iterator only looks for the `Sector` of the first element once and
+    /// then simply follows links between `Sectors`. Iterating in reverse (see [Iterator::rev]) is
+    /// just as efficient, which makes this method a good fit for paginated queries over the history.
+    ///
+    /// # Panics
+    /// Panics if the start of the range is greater than its end, or if the end of the range is out of bounds.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// let page: Vec<u64> = log.iter_range(10..15).map(|it| *it).collect();
+    /// assert_eq!(page, vec![10, 11, 12, 13, 14]);
+    ///
+    /// let latest_first: Vec<u64> = log.iter_range(..=50).rev().take(3).map(|it| *it).collect();
+    /// assert_eq!(latest_first, vec![50, 49, 48]);
+    /// ```
+    pub fn iter_range<R: RangeBounds<u64>>(&self, range: R) -> SLogRangeIter<'_, T> {
+        let from = match range.start_bound() {
+            Bound::Included(&idx) => idx,
+            Bound::Excluded(&idx) => idx.checked_add(1).expect("out of bounds"),
+            Bound::Unbounded => 0,
+        };
+
+        let to = match range.end_bound() {
+            Bound::Included(&idx) => idx.checked_add(1).expect("out of bounds"),
+            Bound::Excluded(&idx) => idx,
+            Bound::Unbounded => self.len,
+        };
+
+        assert!(from <= to, "invalid range");
+        assert!(to <= self.len, "out of bounds");
+
+        SLogRangeIter::new(self, from, to)
+    }
+
+    /// Returns a double-ended front-to-back iterator over all elements of this [SLog]
+    ///
+    /// The same as `iter_range(..)`.
+    #[inline]
+    pub fn iter(&self) -> SLogRangeIter<'_, T> {
+        self.iter_range(..)
+    }
+
+    /// Exports a chunk of elements, starting from the `cursor` index, into a candid-friendly [SLogChunk]
+    ///
+    /// Each element is converted with the provided function. Elements are added to the chunk until
+    /// their total candid-encoded size reaches `max_bytes`. The first element is always added,
+    /// even if it is bigger than `max_bytes`, so the export always makes progress. The size of each
+    /// element is measured by encoding it separately, which slightly overestimates the size of the
+    /// whole chunk.
+    ///
+    /// The returned chunk contains a cursor to continue the export from, or [None], if this chunk
+    /// contains the last element of this [SLog]. This makes it easy to build paginated query
+    /// endpoints, like ICRC-3's `get_blocks`. If `cursor` is out of bounds, returns an empty chunk.
+    ///
+    /// Keep in mind that [SLog::prune_front] shifts indices of elements, which invalidates cursors.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// let mut cursor = Some(0);
+    /// let mut exported = Vec::new();
+    ///
+    /// while let Some(c) = cursor {
+    ///     let chunk = log.export_chunk(c, 256, |it| *it);
+    ///
+    ///     exported.extend(chunk.entries);
+    ///     cursor = chunk.next_cursor;
+    /// }
+    ///
+    /// assert_eq!(exported, (0..100).collect::<Vec<_>>());
+    /// ```
+    pub fn export_chunk<U: CandidType, F: FnMut(&T) -> U>(
+        &self,
+        cursor: u64,
+        max_bytes: usize,
+        mut f: F,
+    ) -> SLogChunk<U> {
+        let mut entries = Vec::new();
+
+        if cursor >= self.len {
+            return SLogChunk {
+                entries,
+                log_len: self.len,
+                next_cursor: None,
+            };
+        }
+
+        let mut size = 0usize;
+        let mut next_cursor = cursor;
+
+        for it in self.iter_range(cursor..) {
+            let entry = f(&it);
+            let entry_size = encode_one(&entry)
+                .expect("Unable to encode a log entry")
+                .len();
+
+            if !entries.is_empty() && size + entry_size > max_bytes {
+                break;
+            }
+
+            size += entry_size;
+            next_cursor += 1;
+            entries.push(entry);
+        }
+
+        SLogChunk {
+            entries,
+            log_len: self.len,
+            next_cursor: if next_cursor < self.len {
+                Some(next_cursor)
+            } else {
+                None
+            },
+        }
+    }
+
+    // only pruned elements are left in the current sector - it can be reused from the beginning
+    #[inline]
+    fn reset_pruned_if_empty(&mut self) {
+        if self.len == 0 {
+            self.cur_sector_len = 0;
+            self.cur_sector_last_item_offset = 0;
+            self.pruned_len = 0;
+        }
+    }
+
+    // moves alive elements of the first sector to its beginning, so none of them are pruned
+    fn shift_first_sector(&mut self) {
+        let mut sector = Sector::<T>::from_ptr(self.first_sector_ptr);
+        let alive = self.sector_len(&sector) - self.pruned_len;
+
+        unsafe {
+            crate::mem::move_bytes(
+                sector.get_element_ptr(self.pruned_len * T::SIZE as u64),
+                sector.get_element_ptr(0),
+                alive * T::SIZE as u64,
+            )
+        };
+
+        if sector.as_ptr() == self.cur_sector_ptr {
+            self.cur_sector_len = alive;
+            self.cur_sector_last_item_offset = alive * T::SIZE as u64;
+        } else {
+            // the tail of the sector stays allocated until the whole sector is pruned
+            sector.write_capacity(alive);
+        }
+
+        self.pruned_len = 0;
+    }
+
+    #[inline]
+    fn sector_len(&self, sector: &Sector<T>) -> u64 {
+        if sector.as_ptr() == self.cur_sector_ptr {
+            self.cur_sector_len
+        } else {
+            sector.read_capacity()
+        }
+    }
+
+    // returns the sector and its starting index, counting pruned elements of the first sector
     fn find_sector_for_idx(&self, idx: u64) -> Option<(Sector<T>, u64)> {
         if idx >= self.len || self.len == 0 {
             return None;
         }
 
+        let idx = idx + self.pruned_len;
+
         let mut sector = Sector::<T>::from_ptr(self.cur_sector_ptr);
         let mut sector_len = self.cur_sector_len;
 
-        let mut len = self.len;
+        let mut len = self.len + self.pruned_len;
 
         loop {
             len -= sector_len;
@@ -302,6 +727,54 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
         }
     }
 
+    // moves elements [from, to) of the sector into a new sector of the provided capacity, which
+    // takes its place in the list, returns the number of reclaimed bytes
+    fn rewrite_sector(
+        &mut self,
+        sector: Sector<T>,
+        from: u64,
+        to: u64,
+        capacity: u64,
+    ) -> Result<u64, OutOfMemory> {
+        let prev_sector_ptr = sector.read_prev_ptr();
+        let next_sector_ptr = sector.read_next_ptr();
+
+        let mut new_sector = Sector::<T>::new(capacity, prev_sector_ptr)?;
+        new_sector.write_next_ptr(next_sector_ptr);
+
+        unsafe {
+            crate::mem::copy_bytes(
+                sector.get_element_ptr(from * T::SIZE as u64),
+                new_sector.get_element_ptr(0),
+                (to - from) * T::SIZE as u64,
+            )
+        };
+
+        if prev_sector_ptr != EMPTY_PTR {
+            Sector::<T>::from_ptr(prev_sector_ptr).write_next_ptr(new_sector.as_ptr());
+        }
+
+        if next_sector_ptr != EMPTY_PTR {
+            Sector::<T>::from_ptr(next_sector_ptr).write_prev_ptr(new_sector.as_ptr());
+        }
+
+        if self.first_sector_ptr == sector.as_ptr() {
+            self.first_sector_ptr = new_sector.as_ptr();
+        }
+
+        if self.cur_sector_ptr == sector.as_ptr() {
+            self.cur_sector_ptr = new_sector.as_ptr();
+        }
+
+        let reclaimed = sector
+            .get_total_size_bytes()
+            .saturating_sub(new_sector.get_total_size_bytes());
+
+        sector.destroy();
+
+        Ok(reclaimed)
+    }
+
     fn move_to_prev_sector_if_needed(&mut self, sector: Sector<T>) {
         if self.cur_sector_len > 0 {
             return;
@@ -324,12 +797,19 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
         self.cur_sector_last_item_offset = self.cur_sector_capacity * T::SIZE as u64;
     }
 
-    fn move_to_next_sector_if_needed(&mut self, sector: &mut Sector<T>) -> Result<(), OutOfMemory> {
+    fn move_to_next_sector_if_needed(
+        &mut self,
+        sector: &mut Sector<T>,
+        max_capacity: u64,
+    ) -> Result<(), OutOfMemory> {
         if self.cur_sector_len < self.cur_sector_capacity {
             return Ok(());
         }
 
-        let mut next_sector_capacity = self.cur_sector_capacity.checked_mul(2).unwrap();
+        let mut next_sector_capacity = u64::min(
+            self.cur_sector_capacity.checked_mul(2).unwrap(),
+            max_capacity,
+        );
         let mut new_sector = loop {
             if next_sector_capacity <= DEFAULT_CAPACITY {
                 return Err(OutOfMemory);
@@ -358,6 +838,97 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
     }
 }
 
+impl<T: StableType + AsFixedSizeBytes> IntoIterator for SLog<T> {
+    type Item = T;
+    type IntoIter = SLogIntoIter<T>;
+
+    /// Returns a consuming front-to-back iterator over elements of this [SLog]
+    ///
+    /// Elements are moved out of stable memory one by one, starting from the first one. Each `Sector`
+    /// is released as soon as all its elements are consumed. Once the iterator is dropped, all
+    /// elements that were not consumed are stable-dropped.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// let entries: Vec<u64> = log.into_iter().collect();
+    ///
+    /// assert_eq!(entries, (0..100).collect::<Vec<_>>());
+    /// ```
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        SLogIntoIter::new(self)
+    }
+}
+
+/// A chunk of [SLog] elements, returned by [SLog::export_chunk]
+///
+/// Can be returned from canister query methods as is.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SLogChunk<U> {
+    /// Exported elements
+    pub entries: Vec<U>,
+    /// The length of the [SLog] at the moment of the export
+    pub log_len: u64,
+    /// An index of the element to continue the export from, or [None] if there are no more elements
+    pub next_cursor: Option<u64>,
+}
+
+impl<T: StableType + AsFixedSizeBytes> TryFromIterator<T> for SLog<T> {
+    /// See [SLog::try_extend]
+    fn try_from_iter<I: IntoIterator<Item = T>>(iter: I) -> Result<Self, (Self, OutOfMemory)> {
+        let mut it = Self::new();
+
+        match it.try_extend(iter) {
+            Ok(_) => Ok(it),
+            Err(_) => Err((it, OutOfMemory)),
+        }
+    }
+}
+
+/// Serialized as a sequence, reading elements from stable memory one by one
+impl<T: StableType + AsFixedSizeBytes + Serialize> Serialize for SLog<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len() as usize))?;
+        for it in self.iter() {
+            seq.serialize_element(&*it)?;
+        }
+
+        seq.end()
+    }
+}
+
+/// Encoded exactly as `Vec<T>` (`vec T`), reading elements from stable memory one by one
+///
+/// For big logs, prefer [SLog::export_chunk], since replies of the IC are limited in size.
+impl<T: StableType + AsFixedSizeBytes + CandidType> CandidType for SLog<T> {
+    #[inline]
+    fn _ty() -> Type {
+        <Vec<T>>::_ty()
+    }
+
+    fn idl_serialize<S>(&self, serializer: S) -> Result<(), S::Error>
+    where
+        S: candid::types::Serializer,
+    {
+        let mut ser = serializer.serialize_vec(self.len() as usize)?;
+        for it in self.iter() {
+            Compound::serialize_element(&mut ser, &*it)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl<T: StableType + AsFixedSizeBytes> Default for SLog<T> {
     fn default() -> Self {
         Self::new()
@@ -388,6 +959,11 @@ impl<T: StableType + AsFixedSizeBytes> Sector<T> {
         deallocate(slice);
     }
 
+    #[inline]
+    fn get_total_size_bytes(&self) -> u64 {
+        unsafe { SSlice::from_ptr(self.0).unwrap().get_total_size_bytes() }
+    }
+
     #[inline]
     fn as_ptr(&self) -> StablePtr {
         self.0
@@ -440,12 +1016,12 @@ impl<T: StableType + AsFixedSizeBytes> Sector<T> {
 
     #[inline]
     fn get_element(&self, offset: u64) -> SRef<T> {
-        unsafe { SRef::new(self.get_element_ptr(offset)) }
+        unsafe { SRef::new_in(self.0, self.get_element_ptr(offset)) }
     }
 
     #[inline]
     fn get_element_mut(&mut self, offset: u64) -> SRefMut<T> {
-        unsafe { SRefMut::new(self.get_element_ptr(offset)) }
+        unsafe { SRefMut::new_in(self.0, self.get_element_ptr(offset)) }
     }
 
     #[inline]
@@ -466,8 +1042,6 @@ impl<T: StableType + AsFixedSizeBytes + Debug> SLog<T> {
             return;
         };
 
-        let mut current_sector_len = DEFAULT_CAPACITY * 2;
-
         print!(
             "SLog({}, {}, {}, {}, {}, {})",
             self.len,
@@ -482,14 +1056,16 @@ impl<T: StableType + AsFixedSizeBytes + Debug> SLog<T> {
 
         loop {
             print!("[");
-            let len = if sector.as_ptr() == self.cur_sector_ptr {
-                self.cur_sector_len
+            let len = self.sector_len(&sector);
+
+            let from = if sector.as_ptr() == self.first_sector_ptr {
+                self.pruned_len
             } else {
-                current_sector_len
+                0
             };
 
-            let mut offset = 0;
-            for i in 0..len {
+            let mut offset = from * T::SIZE as u64;
+            for i in from..len {
                 let elem = sector.get_element(offset);
                 offset += T::SIZE as u64;
 
@@ -510,7 +1086,6 @@ impl<T: StableType + AsFixedSizeBytes + Debug> SLog<T> {
             assert_ne!(next_sector_ptr, EMPTY_PTR);
 
             sector = Sector::<T>::from_ptr(next_sector_ptr);
-            current_sector_len *= 2;
         }
 
         println!("]");
@@ -533,6 +1108,9 @@ impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SLog<T> {
             .as_fixed_size_bytes(&mut buf[(u64::SIZE * 4)..(u64::SIZE * 5)]);
         self.cur_sector_len
             .as_fixed_size_bytes(&mut buf[(u64::SIZE * 5)..(u64::SIZE * 6)]);
+        // never exceeds MAX_SECTOR_CAPACITY, so it fits into this pointer-sized slot on wasm32 too
+        (self.pruned_len as usize)
+            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 6)..(u64::SIZE * 6 + usize::SIZE)]);
     }
 
     fn from_fixed_size_bytes(buf: &[u8]) -> Self {
@@ -544,6 +1122,9 @@ impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SLog<T> {
         let cur_sector_capacity =
             u64::from_fixed_size_bytes(&buf[(u64::SIZE * 4)..(u64::SIZE * 5)]);
         let cur_sector_len = u64::from_fixed_size_bytes(&buf[(u64::SIZE * 5)..(u64::SIZE * 6)]);
+        let pruned_len =
+            usize::from_fixed_size_bytes(&buf[(u64::SIZE * 6)..(u64::SIZE * 6 + usize::SIZE)])
+                as u64;
 
         Self {
             len,
@@ -552,6 +1133,7 @@ impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SLog<T> {
             cur_sector_len,
             cur_sector_capacity,
             cur_sector_last_item_offset,
+            pruned_len,
             stable_drop_flag: false,
             _marker: PhantomData::default(),
         }
@@ -606,6 +1188,34 @@ mod tests {
     };
     use rand::rngs::ThreadRng;
     use rand::{thread_rng, Rng};
+    use serde_test::{assert_ser_tokens, Token};
+
+    #[test]
+    fn serialize_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::new();
+            for i in 0..3u8 {
+                log.push(i).unwrap();
+            }
+
+            assert_ser_tokens(
+                &log,
+                &[
+                    Token::Seq { len: Some(3) },
+                    Token::U8(0),
+                    Token::U8(1),
+                    Token::U8(2),
+                    Token::SeqEnd,
+                ],
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
     #[test]
     fn works_fine() {
@@ -702,9 +1312,387 @@ mod tests {
         assert_eq!(get_allocated_size(), 0);
     }
 
+    #[test]
+    fn iter_range_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::new();
+            assert_eq!(log.iter().count(), 0);
+
+            for i in 0..300u64 {
+                log.push(i).unwrap();
+            }
+
+            assert_eq!(
+                log.iter().map(|it| *it).collect::<Vec<_>>(),
+                (0..300).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                log.iter().rev().map(|it| *it).collect::<Vec<_>>(),
+                (0..300).rev().collect::<Vec<_>>()
+            );
+
+            for (from, to) in [
+                (0, 0),
+                (0, 1),
+                (3, 4),
+                (5, 7),
+                (6, 30),
+                (10, 290),
+                (299, 300),
+            ] {
+                assert_eq!(
+                    log.iter_range(from..to).map(|it| *it).collect::<Vec<_>>(),
+                    (from..to).collect::<Vec<_>>()
+                );
+                assert_eq!(
+                    log.iter_range(from..to)
+                        .rev()
+                        .map(|it| *it)
+                        .collect::<Vec<_>>(),
+                    (from..to).rev().collect::<Vec<_>>()
+                );
+            }
+
+            let mut iter = log.iter_range(10..=20);
+            assert_eq!(iter.size_hint(), (11, Some(11)));
+
+            let mut res = Vec::new();
+            loop {
+                match (iter.next(), iter.next_back()) {
+                    (Some(a), Some(b)) => {
+                        res.push(*a);
+                        res.push(*b);
+                    }
+                    (Some(a), None) => res.push(*a),
+                    _ => break,
+                }
+            }
+            res.sort();
+            assert_eq!(res, (10..=20).collect::<Vec<_>>());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn shift_first_sector_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::new();
+            log.try_extend(0..100u64).unwrap();
+
+            // the first sector is not the current one
+            log.prune_front(1);
+            log.shift_first_sector();
+            assert_eq!(log.pruned_len, 0);
+            assert_eq!(
+                log.iter().map(|it| *it).collect::<Vec<_>>(),
+                (1..100).collect::<Vec<_>>()
+            );
+
+            log.prune_front(90);
+            log.shift_first_sector();
+            assert_eq!(
+                log.iter().map(|it| *it).collect::<Vec<_>>(),
+                (91..100).collect::<Vec<_>>()
+            );
+
+            log.push(100).unwrap();
+            assert_eq!(*log.get(9).unwrap(), 100);
+            assert_eq!(
+                log.rev_iter().map(|it| *it).collect::<Vec<_>>(),
+                (91..101).rev().collect::<Vec<_>>()
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn prune_front_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::new();
+
+            for i in 0..100u64 {
+                log.push(i).unwrap();
+            }
+
+            log.prune_front(0);
+            assert_eq!(log.len(), 100);
+
+            log.prune_front(1);
+            assert_eq!(*log.first().unwrap(), 1);
+
+            log.prune_front(40);
+            assert_eq!(log.len(), 59);
+            assert_eq!(*log.first().unwrap(), 41);
+            assert_eq!(*log.last().unwrap(), 99);
+
+            for i in 0..59 {
+                assert_eq!(*log.get(i).unwrap(), i + 41);
+            }
+            assert_eq!(
+                log.iter().map(|it| *it).collect::<Vec<_>>(),
+                (41..100).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                log.rev_iter().map(|it| *it).collect::<Vec<_>>(),
+                (41..100).rev().collect::<Vec<_>>()
+            );
+
+            for i in 100..200u64 {
+                log.push(i).unwrap();
+            }
+            while log.len() > 10 {
+                log.pop().unwrap();
+            }
+            assert_eq!(
+                log.iter().map(|it| *it).collect::<Vec<_>>(),
+                (41..51).collect::<Vec<_>>()
+            );
+
+            for i in 51..300u64 {
+                log.push(i).unwrap();
+            }
+            assert_eq!(
+                log.iter().map(|it| *it).collect::<Vec<_>>(),
+                (41..300).collect::<Vec<_>>()
+            );
+
+            log.prune_front(1000);
+            assert!(log.is_empty());
+            assert!(log.first().is_none());
+
+            for i in 0..10u64 {
+                log.push(i).unwrap();
+            }
+            log.prune_front(5);
+            assert_eq!(
+                log.iter().map(|it| *it).collect::<Vec<_>>(),
+                (5..10).collect::<Vec<_>>()
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn compact_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::<SBox<u64>>::new();
+            assert_eq!(log.compact().unwrap(), 0);
+
+            for i in 0..1000 {
+                log.push(SBox::new(i).unwrap()).unwrap();
+            }
+
+            log.prune_front(300);
+            for _ in 0..100 {
+                log.pop();
+            }
+
+            let before = get_allocated_size();
+            let reclaimed = log.compact().unwrap();
+
+            assert!(reclaimed > 0);
+            assert_eq!(get_allocated_size(), before - reclaimed);
+            assert_eq!(log.compact().unwrap(), 0);
+
+            assert_eq!(log.len(), 600);
+            for i in 0..600 {
+                assert_eq!(**log.get(i).unwrap(), i + 300);
+            }
+
+            for i in 900..1100 {
+                log.push(SBox::new(i).unwrap()).unwrap();
+            }
+            assert_eq!(
+                log.iter().map(|it| **it).collect::<Vec<_>>(),
+                (300..1100).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                log.iter().rev().map(|it| **it).collect::<Vec<_>>(),
+                (300..1100).rev().collect::<Vec<_>>()
+            );
+
+            // a single sector, which is both the first and the current one
+            log.prune_front(799);
+            log.compact().unwrap();
+            assert_eq!(**log.first().unwrap(), 1099);
+
+            for i in 0..10 {
+                log.push(SBox::new(i).unwrap()).unwrap();
+            }
+            assert_eq!(log.len(), 11);
+            assert_eq!(**log.last().unwrap(), 9);
+
+            // an empty log releases all its memory
+            log.clear();
+            log.compact().unwrap();
+            assert_eq!(get_allocated_size(), 0);
+
+            log.push(SBox::new(1).unwrap()).unwrap();
+            assert_eq!(**log.first().unwrap(), 1);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn push_bounded_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::new();
+
+            for i in 0..1000u64 {
+                log.push_bounded(SBox::new(i).unwrap(), 100).unwrap();
+
+                assert_eq!(log.len(), u64::min(i + 1, 100));
+                assert_eq!(**log.last().unwrap(), i);
+            }
+
+            assert_eq!(
+                log.iter().map(|it| **it).collect::<Vec<_>>(),
+                (900..1000).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                log.rev_iter().map(|it| **it).collect::<Vec<_>>(),
+                (900..1000).rev().collect::<Vec<_>>()
+            );
+
+            for i in 1000..1010u64 {
+                log.push_bounded(SBox::new(i).unwrap(), 3).unwrap();
+            }
+
+            assert_eq!(
+                log.into_iter()
+                    .map(|it| it.into_inner())
+                    .collect::<Vec<_>>(),
+                vec![1007, 1008, 1009]
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn export_chunk_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::new();
+
+            let chunk = log.export_chunk(0, 1024, |it: &SBox<String>| (**it).clone());
+            assert!(chunk.entries.is_empty());
+            assert_eq!(chunk.log_len, 0);
+            assert_eq!(chunk.next_cursor, None);
+
+            for i in 0..100 {
+                log.push(SBox::new(format!("entry {}", i)).unwrap())
+                    .unwrap();
+            }
+
+            let mut cursor = Some(10);
+            let mut chunks = 0;
+            let mut res = Vec::new();
+
+            while let Some(c) = cursor {
+                let chunk = log.export_chunk(c, 200, |it| (**it).clone());
+                assert!(!chunk.entries.is_empty());
+                assert_eq!(chunk.log_len, 100);
+
+                res.extend(chunk.entries);
+                cursor = chunk.next_cursor;
+                chunks += 1;
+            }
+
+            assert!(chunks > 1);
+            assert_eq!(
+                res,
+                (10..100)
+                    .map(|i| format!("entry {}", i))
+                    .collect::<Vec<_>>()
+            );
+
+            let chunk = log.export_chunk(99, 0, |it| (**it).clone());
+            assert_eq!(chunk.entries, vec![String::from("entry 99")]);
+            assert_eq!(chunk.next_cursor, None);
+
+            let chunk = log.export_chunk(100, 1024, |it| (**it).clone());
+            assert!(chunk.entries.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn into_iter_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let log = SLog::<u64>::new();
+            assert_eq!(log.into_iter().count(), 0);
+
+            let mut log = SLog::new();
+            log.push(SBox::new(1).unwrap()).unwrap();
+            log.pop();
+            assert_eq!(log.into_iter().count(), 0);
+
+            let mut log = SLog::new();
+            for i in 0..100 {
+                log.push(SBox::new(i).unwrap()).unwrap();
+            }
+            for _ in 0..10 {
+                log.pop();
+            }
+
+            let res = log
+                .into_iter()
+                .map(|it| it.into_inner())
+                .collect::<Vec<_>>();
+            assert_eq!(res, (0..90).collect::<Vec<_>>());
+
+            let mut log = SLog::new();
+            for i in 0..100 {
+                log.push(SBox::new(i).unwrap()).unwrap();
+            }
+
+            let mut iter = log.into_iter();
+            for i in 0..50 {
+                assert_eq!(iter.next().unwrap().into_inner(), i);
+            }
+            assert_eq!(iter.size_hint(), (50, Some(50)));
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     enum Action {
         Push,
         Pop,
+        PruneFront,
         Clear,
         CanisterUpgrade,
     }
@@ -745,13 +1733,22 @@ mod tests {
                         self.log.push(Action::Push);
                     }
                 }
-                // POP ~30%
-                61..=90 => {
+                // POP ~25%
+                61..=85 => {
                     self.it().pop();
                     self.example.pop();
 
                     self.log.push(Action::Pop);
                 }
+                // PRUNE FRONT ~5%
+                86..=90 => {
+                    let n = self.rng.gen_range(0..20usize);
+
+                    self.it().prune_front(n as u64);
+                    self.example.drain(..usize::min(n, self.example.len()));
+
+                    self.log.push(Action::PruneFront);
+                }
                 // CLEAR
                 91..=92 => {
                     self.it().clear();
@@ -0,0 +1,51 @@
+use crate::collections::lru_cache::SLruCache;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::hash::Hash;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+
+pub struct SLruCacheIter<'a, K, V> {
+    node: StablePtr,
+    _marker: PhantomData<&'a (K, V)>,
+}
+
+impl<'a, K, V> SLruCacheIter<'a, K, V> {
+    pub(crate) fn new(head: StablePtr) -> Self {
+        Self {
+            node: head,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Hash + Eq + 'a,
+        V: StableType + AsFixedSizeBytes + 'a,
+    > Iterator for SLruCacheIter<'a, K, V>
+{
+    type Item = (SRef<'a, K>, SRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.node == EMPTY_PTR {
+            return None;
+        }
+
+        let node = self.node;
+        self.node = SLruCache::<K, V>::next_of(node);
+
+        Some(SLruCache::<K, V>::node_refs(node))
+    }
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Hash + Eq + 'a,
+        V: StableType + AsFixedSizeBytes + 'a,
+    > FusedIterator for SLruCacheIter<'a, K, V>
+{
+}
@@ -0,0 +1,611 @@
+use crate::collections::hash_map::SHashMap;
+use crate::collections::lru_cache::iter::SLruCacheIter;
+use crate::encoding::{AsFixedSizeBytes, Buffer};
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use crate::{allocate, deallocate, SSlice};
+use std::borrow::Borrow;
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+#[doc(hidden)]
+pub mod iter;
+
+// Node layout:
+// PREV: u64
+// NEXT: u64
+// KEY: K (a non-owning copy, the key is owned by the map)
+// VALUE: V
+
+const PREV_OFFSET: u64 = 0;
+const NEXT_OFFSET: u64 = PREV_OFFSET + u64::SIZE as u64;
+const KEY_OFFSET: u64 = NEXT_OFFSET + u64::SIZE as u64;
+
+#[inline]
+const fn value_offset<K: AsFixedSizeBytes>() -> u64 {
+    KEY_OFFSET + K::SIZE as u64
+}
+
+/// Bounded cache with "least recently used" eviction policy
+///
+/// Built on top of [SHashMap], which maps keys to nodes of an intrusive doubly-linked list. Each
+/// node is a separate block of stable memory, which holds the value and is never moved, so
+/// promoting an entry (on [SLruCache::get]) only rewrites a couple of links, no matter how big the
+/// value is.
+///
+/// When the cache is full, inserting a new key evicts the least recently used entry. The evicted
+/// entry can be intercepted with [SLruCache::insert_with_eviction], otherwise it gets stable-dropped.
+///
+/// Both `K` and `V` have to implement [StableType] and [AsFixedSizeBytes] traits. [SLruCache] also
+/// implements these traits itself, so you can nest it inside other stable structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SLruCache;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut cache = SLruCache::<u64, u64>::new(2);
+///
+/// cache.insert(1, 10).expect("Out of memory");
+/// cache.insert(2, 20).expect("Out of memory");
+///
+/// // promotes the key 1
+/// assert_eq!(*cache.get(&1).unwrap(), 10);
+///
+/// // evicts the key 2
+/// cache.insert(3, 30).expect("Out of memory");
+///
+/// assert!(cache.peek(&2).is_none());
+/// assert_eq!(cache.len(), 2);
+/// ```
+pub struct SLruCache<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+{
+    map: SHashMap<K, u64>,
+    head: StablePtr,
+    tail: StablePtr,
+    cap: usize,
+    _marker: PhantomData<V>,
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+    SLruCache<K, V>
+{
+    /// Creates a new [SLruCache], which can hold up to `capacity` entries
+    ///
+    /// Does not allocate any heap or stable memory.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is `0`.
+    #[inline]
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be non-zero");
+
+        Self {
+            map: SHashMap::new(),
+            head: EMPTY_PTR,
+            tail: EMPTY_PTR,
+            cap: capacity,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the capacity of this [SLruCache]
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Returns the number of entries in this [SLruCache]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    /// Returns [true] if there are no entries in this [SLruCache]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Inserts a new entry, making it the most recently used one
+    ///
+    /// Same as [SLruCache::insert_with_eviction], but stable-drops the evicted entry.
+    #[inline]
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        self.insert_with_eviction(key, value, |_, _| {})
+    }
+
+    /// Inserts a new entry, making it the most recently used one
+    ///
+    /// If the key already exists, replaces its value and returns the previous one. Otherwise, if
+    /// this [SLruCache] is full, evicts the least recently used entry, passing it to the provided
+    /// lambda.
+    ///
+    /// Eviction happens before the insertion, so if the canister is out of stable memory, the
+    /// evicted entry is still passed to the lambda, but the new entry is returned back as [Err].
+    pub fn insert_with_eviction<F: FnOnce(K, V)>(
+        &mut self,
+        key: K,
+        mut value: V,
+        on_evict: F,
+    ) -> Result<Option<V>, (K, V)> {
+        if let Some(node) = self.map.get(&key).map(|it| *it) {
+            let value_ptr = Self::value_ptr(node);
+
+            let prev_value = unsafe { crate::mem::read_fixed_for_move(value_ptr) };
+            unsafe { crate::mem::write_fixed(value_ptr, &mut value) };
+
+            self.promote(node);
+
+            return Ok(Some(prev_value));
+        }
+
+        if self.len() == self.cap as u64 {
+            let (k, v) = self.pop_lru().unwrap();
+            on_evict(k, v);
+        }
+
+        let node = match unsafe { allocate(value_offset::<K>() + V::SIZE as u64) } {
+            Ok(slice) => slice.as_ptr(),
+            Err(_) => return Err((key, value)),
+        };
+
+        let mut key_buf = K::Buf::new(K::SIZE);
+        key.as_fixed_size_bytes(key_buf._deref_mut());
+
+        unsafe {
+            crate::mem::write_bytes(SSlice::_offset(node, KEY_OFFSET), key_buf._deref());
+            crate::mem::write_fixed(Self::value_ptr(node), &mut value);
+        }
+
+        if let Err((key, _)) = self.map.insert(key, node) {
+            let value = unsafe { crate::mem::read_fixed_for_move(Self::value_ptr(node)) };
+            deallocate(unsafe { SSlice::from_ptr(node).unwrap() });
+
+            return Err((key, value));
+        }
+
+        self.link_front(node);
+
+        Ok(None)
+    }
+
+    /// Returns an immutable reference [SRef] to a value stored by the key, making this entry the
+    /// most recently used one
+    ///
+    /// See also [SLruCache::peek], which does not affect the order of entries.
+    ///
+    /// If no such key-value pair is found, returns [None]
+    pub fn get<Q>(&mut self, key: &Q) -> Option<SRef<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = *self.map.get(key)?;
+        self.promote(node);
+
+        unsafe { Some(SRef::new(Self::value_ptr(node))) }
+    }
+
+    /// Returns a mutable reference [SRefMut] to a value stored by the key, making this entry the
+    /// most recently used one
+    ///
+    /// If no such key-value pair is found, returns [None]
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<SRefMut<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = *self.map.get(key)?;
+        self.promote(node);
+
+        unsafe { Some(SRefMut::new(Self::value_ptr(node))) }
+    }
+
+    /// Returns an immutable reference [SRef] to a value stored by the key, without making this entry
+    /// the most recently used one
+    ///
+    /// If no such key-value pair is found, returns [None]
+    pub fn peek<Q>(&self, key: &Q) -> Option<SRef<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = *self.map.get(key)?;
+
+        unsafe { Some(SRef::new(Self::value_ptr(node))) }
+    }
+
+    /// Returns [true] if there exists an entry with the provided key
+    ///
+    /// Does not affect the order of entries.
+    #[inline]
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Removes an entry by the key, returning its value
+    ///
+    /// If no such key-value pair is found, returns [None]
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let node = self.map.remove(key)?;
+
+        Some(self.destroy_node(node))
+    }
+
+    /// Removes the least recently used entry, returning it
+    ///
+    /// If this [SLruCache] is empty, returns [None]
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        if self.tail == EMPTY_PTR {
+            return None;
+        }
+
+        let node = self.tail;
+        let key_ref: K =
+            unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(node, KEY_OFFSET)) };
+
+        let (key, _) = self.map.remove_entry(&key_ref).unwrap();
+        let value = self.destroy_node(node);
+
+        Some((key, value))
+    }
+
+    /// Returns the least recently used entry, without removing it
+    ///
+    /// If this [SLruCache] is empty, returns [None]
+    pub fn peek_lru(&self) -> Option<(SRef<'_, K>, SRef<'_, V>)> {
+        if self.tail == EMPTY_PTR {
+            return None;
+        }
+
+        Some(Self::node_refs(self.tail))
+    }
+
+    /// Removes all entries from this [SLruCache], stable-dropping them
+    pub fn clear(&mut self) {
+        while self.pop_lru().is_some() {}
+    }
+
+    /// Returns an iterator over entries of this [SLruCache], from the most recently used one to
+    /// the least recently used one
+    ///
+    /// Does not affect the order of entries.
+    #[inline]
+    pub fn iter(&self) -> SLruCacheIter<'_, K, V> {
+        SLruCacheIter::new(self.head)
+    }
+
+    pub(crate) fn node_refs<'a>(node: StablePtr) -> (SRef<'a, K>, SRef<'a, V>) {
+        unsafe {
+            (
+                SRef::new(SSlice::_offset(node, KEY_OFFSET)),
+                SRef::new(Self::value_ptr(node)),
+            )
+        }
+    }
+
+    #[inline]
+    pub(crate) fn next_of(node: StablePtr) -> StablePtr {
+        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(node, NEXT_OFFSET)) }
+    }
+
+    #[inline]
+    fn prev_of(node: StablePtr) -> StablePtr {
+        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(node, PREV_OFFSET)) }
+    }
+
+    #[inline]
+    fn set_next(node: StablePtr, mut next: StablePtr) {
+        unsafe { crate::mem::write_fixed(SSlice::_offset(node, NEXT_OFFSET), &mut next) };
+    }
+
+    #[inline]
+    fn set_prev(node: StablePtr, mut prev: StablePtr) {
+        unsafe { crate::mem::write_fixed(SSlice::_offset(node, PREV_OFFSET), &mut prev) };
+    }
+
+    #[inline]
+    fn value_ptr(node: StablePtr) -> StablePtr {
+        SSlice::_offset(node, value_offset::<K>())
+    }
+
+    fn link_front(&mut self, node: StablePtr) {
+        Self::set_prev(node, EMPTY_PTR);
+        Self::set_next(node, self.head);
+
+        if self.head != EMPTY_PTR {
+            Self::set_prev(self.head, node);
+        } else {
+            self.tail = node;
+        }
+
+        self.head = node;
+    }
+
+    fn unlink(&mut self, node: StablePtr) {
+        let prev = Self::prev_of(node);
+        let next = Self::next_of(node);
+
+        if prev != EMPTY_PTR {
+            Self::set_next(prev, next);
+        } else {
+            self.head = next;
+        }
+
+        if next != EMPTY_PTR {
+            Self::set_prev(next, prev);
+        } else {
+            self.tail = prev;
+        }
+    }
+
+    fn promote(&mut self, node: StablePtr) {
+        if self.head == node {
+            return;
+        }
+
+        self.unlink(node);
+        self.link_front(node);
+    }
+
+    // the key should already be removed from the map
+    fn destroy_node(&mut self, node: StablePtr) -> V {
+        self.unlink(node);
+
+        let value = unsafe { crate::mem::read_fixed_for_move(Self::value_ptr(node)) };
+        deallocate(unsafe { SSlice::from_ptr(node).unwrap() });
+
+        value
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+    AsFixedSizeBytes for SLruCache<K, V>
+{
+    const SIZE: usize = SHashMap::<K, u64>::SIZE + u64::SIZE * 2 + usize::SIZE;
+    type Buf = Vec<u8>;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let map_size = SHashMap::<K, u64>::SIZE;
+
+        self.map.as_fixed_size_bytes(&mut buf[0..map_size]);
+        self.head
+            .as_fixed_size_bytes(&mut buf[map_size..(map_size + u64::SIZE)]);
+        self.tail
+            .as_fixed_size_bytes(&mut buf[(map_size + u64::SIZE)..(map_size + u64::SIZE * 2)]);
+        self.cap.as_fixed_size_bytes(
+            &mut buf[(map_size + u64::SIZE * 2)..(map_size + u64::SIZE * 2 + usize::SIZE)],
+        );
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let map_size = SHashMap::<K, u64>::SIZE;
+
+        let map = SHashMap::<K, u64>::from_fixed_size_bytes(&buf[0..map_size]);
+        let head = u64::from_fixed_size_bytes(&buf[map_size..(map_size + u64::SIZE)]);
+        let tail =
+            u64::from_fixed_size_bytes(&buf[(map_size + u64::SIZE)..(map_size + u64::SIZE * 2)]);
+        let cap = usize::from_fixed_size_bytes(
+            &buf[(map_size + u64::SIZE * 2)..(map_size + u64::SIZE * 2 + usize::SIZE)],
+        );
+
+        Self {
+            map,
+            head,
+            tail,
+            cap,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> StableType
+    for SLruCache<K, V>
+{
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.map.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.map.stable_drop_flag_off();
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.map.should_stable_drop()
+    }
+
+    #[inline]
+    unsafe fn stable_drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Drop
+    for SLruCache<K, V>
+{
+    fn drop(&mut self) {
+        // the map will stable-drop itself right after
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq + Debug,
+        V: StableType + AsFixedSizeBytes + Debug,
+    > Debug for SLruCache<K, V>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("{")?;
+        for (idx, (k, v)) in (0u64..).zip(self.iter()) {
+            k.fmt(f)?;
+            f.write_str(": ")?;
+            v.fmt(f)?;
+
+            if idx < self.len() - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::lru_cache::SLruCache;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn basic_flow_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut cache = SLruCache::new(3);
+
+            for i in 0..3u64 {
+                assert!(cache.insert(i, i * 10).unwrap().is_none());
+            }
+
+            // order: 2, 1, 0
+            assert_eq!(*cache.get(&0).unwrap(), 0);
+            // order: 0, 2, 1
+            assert_eq!(*cache.peek(&1).unwrap(), 10);
+            assert_eq!(*cache.peek_lru().unwrap().0, 1);
+
+            let mut evicted = None;
+            cache
+                .insert_with_eviction(3, 30, |k, v| evicted = Some((k, v)))
+                .unwrap();
+            assert_eq!(evicted, Some((1, 10)));
+
+            // order: 3, 0, 2
+            let keys = cache.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+            assert_eq!(keys, vec![3, 0, 2]);
+
+            assert_eq!(cache.insert(2, 21).unwrap(), Some(20));
+            *cache.get_mut(&0).unwrap() = 1;
+
+            // order: 0, 2, 3
+            let entries = cache.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+            assert_eq!(entries, vec![(0, 1), (2, 21), (3, 30)]);
+
+            assert_eq!(cache.remove(&2), Some(21));
+            assert!(cache.remove(&2).is_none());
+            assert_eq!(cache.pop_lru(), Some((3, 30)));
+            assert_eq!(cache.len(), 1);
+
+            let mut vec = SVec::new();
+            vec.push(cache).unwrap();
+
+            let mut cache = vec.pop().unwrap();
+            assert_eq!(cache.pop_lru(), Some((0, 1)));
+            assert!(cache.is_empty());
+            assert!(cache.pop_lru().is_none());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn random_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut cache = SLruCache::new(50);
+            // most recently used first
+            let mut check: Vec<(u64, u64)> = Vec::new();
+
+            for i in 0..5000u64 {
+                let key = rng.gen_range(0..100u64);
+
+                match rng.gen_range(0..4) {
+                    0 | 1 => {
+                        let mut evicted = None;
+                        let prev = cache
+                            .insert_with_eviction(
+                                SBox::new(key).unwrap(),
+                                SBox::new(i).unwrap(),
+                                |k, v| evicted = Some((k.into_inner(), v.into_inner())),
+                            )
+                            .unwrap()
+                            .map(|it| it.into_inner());
+
+                        let expected_prev = check
+                            .iter()
+                            .position(|(k, _)| *k == key)
+                            .map(|idx| check.remove(idx).1);
+                        assert_eq!(prev, expected_prev);
+
+                        let expected_evicted = if expected_prev.is_none() && check.len() == 50 {
+                            check.pop()
+                        } else {
+                            None
+                        };
+                        assert_eq!(evicted, expected_evicted);
+
+                        check.insert(0, (key, i));
+                    }
+                    2 => {
+                        let value = cache.get(&key).map(|it| **it);
+
+                        let expected = match check.iter().position(|(k, _)| *k == key) {
+                            Some(idx) => {
+                                let entry = check.remove(idx);
+                                check.insert(0, entry);
+
+                                Some(entry.1)
+                            }
+                            None => None,
+                        };
+                        assert_eq!(value, expected);
+                    }
+                    _ => {
+                        let value = cache.remove(&key).map(|it| it.into_inner());
+
+                        let expected = check
+                            .iter()
+                            .position(|(k, _)| *k == key)
+                            .map(|idx| check.remove(idx).1);
+                        assert_eq!(value, expected);
+                    }
+                }
+
+                assert_eq!(cache.len(), check.len() as u64);
+            }
+
+            let entries = cache.iter().map(|(k, v)| (**k, **v)).collect::<Vec<_>>();
+            assert_eq!(entries, check);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
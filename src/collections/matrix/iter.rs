@@ -0,0 +1,63 @@
+use crate::collections::vec::iter::size_hint;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+
+pub struct SMatrixRowIter<'a, T> {
+    row_ptr: StablePtr,
+    idx: u64,
+    end_idx: u64,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T> SMatrixRowIter<'a, T> {
+    #[inline]
+    pub(crate) fn new(row_ptr: StablePtr, len: u64) -> Self {
+        Self {
+            row_ptr,
+            idx: 0,
+            end_idx: len,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SMatrixRowIter<'a, T> {
+    type Item = SRef<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.idx == self.end_idx {
+            return None;
+        }
+
+        let ptr = self.row_ptr + self.idx * T::SIZE as u64;
+        self.idx += 1;
+
+        unsafe { Some(SRef::new(ptr)) }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        size_hint(self.end_idx - self.idx)
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> DoubleEndedIterator for SMatrixRowIter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.idx == self.end_idx {
+            return None;
+        }
+
+        self.end_idx -= 1;
+        let ptr = self.row_ptr + self.end_idx * T::SIZE as u64;
+
+        unsafe { Some(SRef::new(ptr)) }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> ExactSizeIterator for SMatrixRowIter<'a, T> {}
+
+impl<'a, T: StableType + AsFixedSizeBytes> FusedIterator for SMatrixRowIter<'a, T> {}
@@ -0,0 +1,450 @@
+use crate::collections::matrix::iter::SMatrixRowIter;
+use crate::encoding::counters::{decode_header, encode_header, header_size};
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use crate::utils::math::max_elements;
+use crate::{allocate, deallocate, OutOfMemory};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+#[doc(hidden)]
+pub mod iter;
+
+/// Fixed-size two-dimensional matrix, stored in a single memory block in row-major order
+///
+/// Element at `(row, col)` is stored at `row * cols + col` - elements of a single row are stored
+/// next to each other, which makes reading or writing a whole row a single stable memory operation
+/// (see [SMatrix::read_row] and [SMatrix::write_row]).
+///
+/// The size of the matrix is set at construction, the memory block is never reallocated, but the
+/// shape can be changed with [SMatrix::transpose].
+///
+/// `T` has to implement both [StableType] and [AsFixedSizeBytes]. [SMatrix] itself implements these
+/// traits and can be nested inside other stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SMatrix;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut grid = SMatrix::<bool>::new(3, 4, false).expect("Out of memory");
+///
+/// grid.write_row(1, &[true, false, true, false]);
+/// grid.replace(2, 3, true);
+///
+/// assert!(*grid.get(1, 2).unwrap());
+/// assert_eq!(grid.read_row(2), vec![false, false, false, true]);
+///
+/// grid.transpose();
+///
+/// assert_eq!((grid.rows(), grid.cols()), (4, 3));
+/// assert!(*grid.get(3, 2).unwrap());
+/// ```
+pub struct SMatrix<T: StableType + AsFixedSizeBytes> {
+    ptr: StablePtr,
+    rows: u64,
+    cols: u64,
+    stable_drop_flag: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T: StableType + AsFixedSizeBytes> SMatrix<T> {
+    /// Creates a new [SMatrix] of the requested shape, filled with clones of the value
+    ///
+    /// See [SMatrix::from_fn].
+    #[inline]
+    pub fn new(rows: u64, cols: u64, value: T) -> Result<Self, OutOfMemory>
+    where
+        T: Clone,
+    {
+        Self::from_fn(rows, cols, |_, _| value.clone())
+    }
+
+    /// Creates a new [SMatrix] of the requested shape, initializing each element with the closure
+    ///
+    /// Allocates a memory block for all elements at once, returning [OutOfMemory] if there is not
+    /// enough stable memory.
+    ///
+    /// # Panics
+    /// Panics if `rows` or `cols` is `0`, or if the matrix doesn't fit into a single memory block.
+    pub fn from_fn<F: FnMut(u64, u64) -> T>(
+        rows: u64,
+        cols: u64,
+        mut f: F,
+    ) -> Result<Self, OutOfMemory> {
+        assert!(rows > 0 && cols > 0, "the matrix can't be empty");
+        assert!(
+            rows.checked_mul(cols)
+                .filter(|it| *it <= max_elements(T::SIZE))
+                .is_some(),
+            "the matrix is too big"
+        );
+
+        let ptr = unsafe { allocate(rows * cols * T::SIZE as u64)?.as_ptr() };
+
+        let it = Self {
+            ptr,
+            rows,
+            cols,
+            stable_drop_flag: true,
+            _marker: PhantomData,
+        };
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let mut elem = f(row, col);
+                unsafe { crate::mem::write_fixed(it.elem_ptr(row, col), &mut elem) };
+            }
+        }
+
+        Ok(it)
+    }
+
+    /// Returns the number of rows of this [SMatrix]
+    #[inline]
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Returns the number of columns of this [SMatrix]
+    #[inline]
+    pub fn cols(&self) -> u64 {
+        self.cols
+    }
+
+    /// Returns an immutable reference [SRef] to the element at `(row, col)`
+    ///
+    /// If out of bounds, returns [None].
+    #[inline]
+    pub fn get(&self, row: u64, col: u64) -> Option<SRef<'_, T>> {
+        if row >= self.rows || col >= self.cols {
+            return None;
+        }
+
+        unsafe { Some(SRef::new(self.elem_ptr(row, col))) }
+    }
+
+    /// Returns a mutable reference [SRefMut] to the element at `(row, col)`
+    ///
+    /// If out of bounds, returns [None].
+    #[inline]
+    pub fn get_mut(&mut self, row: u64, col: u64) -> Option<SRefMut<'_, T>> {
+        if row >= self.rows || col >= self.cols {
+            return None;
+        }
+
+        unsafe { Some(SRefMut::new(self.elem_ptr(row, col))) }
+    }
+
+    /// Replaces the element at `(row, col)`, returning the previous one
+    ///
+    /// # Panics
+    /// Panics if out of bounds.
+    pub fn replace(&mut self, row: u64, col: u64, mut value: T) -> T {
+        assert!(row < self.rows && col < self.cols, "out of bounds");
+
+        let ptr = self.elem_ptr(row, col);
+        let prev = unsafe { crate::mem::read_fixed_for_move(ptr) };
+        unsafe { crate::mem::write_fixed(ptr, &mut value) };
+
+        prev
+    }
+
+    /// Returns an iterator over elements of the requested row
+    ///
+    /// # Panics
+    /// Panics if out of bounds.
+    #[inline]
+    pub fn row_iter(&self, row: u64) -> SMatrixRowIter<'_, T> {
+        assert!(row < self.rows, "out of bounds");
+
+        SMatrixRowIter::new(self.elem_ptr(row, 0), self.cols)
+    }
+
+    /// Reads a whole row with a single stable memory read
+    ///
+    /// Only available for [Copy] types, since returned elements are copies of the stored ones.
+    ///
+    /// # Panics
+    /// Panics if out of bounds.
+    pub fn read_row(&self, row: u64) -> Vec<T>
+    where
+        T: Copy,
+    {
+        assert!(row < self.rows, "out of bounds");
+
+        let cols = usize::try_from(self.cols).expect("the row doesn't fit into heap");
+
+        let mut buf = vec![0u8; cols * T::SIZE];
+        unsafe { crate::mem::read_bytes(self.elem_ptr(row, 0), &mut buf) };
+
+        buf.chunks_exact(T::SIZE.max(1))
+            .take(cols)
+            .map(T::from_fixed_size_bytes)
+            .collect()
+    }
+
+    /// Overwrites a whole row with a single stable memory write
+    ///
+    /// Only available for [Copy] types, since overwritten elements are not dropped.
+    ///
+    /// # Panics
+    /// Panics if out of bounds or if the length of `values` is not equal to the number of columns.
+    pub fn write_row(&mut self, row: u64, values: &[T])
+    where
+        T: Copy,
+    {
+        assert!(row < self.rows, "out of bounds");
+        assert_eq!(values.len() as u64, self.cols, "invalid row length");
+
+        let mut buf = vec![0u8; values.len() * T::SIZE];
+        for (idx, value) in values.iter().enumerate() {
+            value.as_fixed_size_bytes(&mut buf[(idx * T::SIZE)..((idx + 1) * T::SIZE)]);
+        }
+
+        unsafe { crate::mem::write_bytes(self.elem_ptr(row, 0), &buf) };
+    }
+
+    /// Transposes this [SMatrix] in place, swapping its rows and columns
+    ///
+    /// Square matrices are transposed by swapping elements across the main diagonal. Other
+    /// matrices are transposed by following permutation cycles, which requires a temporary heap
+    /// bitmap of `rows * cols` bits. Elements are moved as raw bytes, each at most once.
+    pub fn transpose(&mut self) {
+        if self.rows == self.cols {
+            for row in 0..self.rows {
+                for col in (row + 1)..self.cols {
+                    self.swap_raw(row * self.cols + col, col * self.cols + row);
+                }
+            }
+
+            return;
+        }
+
+        let n = self.rows * self.cols;
+        let mut visited = vec![0u64; n.div_ceil(64) as usize];
+
+        let mut cur = vec![0u8; T::SIZE];
+        let mut next = vec![0u8; T::SIZE];
+
+        // the first and the last elements always stay in place
+        for start in 1..(n - 1) {
+            if visited[(start / 64) as usize] & (1 << (start % 64)) != 0 {
+                continue;
+            }
+
+            unsafe { crate::mem::read_bytes(self.raw_ptr(start), &mut cur) };
+
+            // element at `idx` = (r, c) moves to (c, r) of the transposed matrix
+            let mut idx = start;
+            loop {
+                let target = (idx % self.cols) * self.rows + idx / self.cols;
+                visited[(target / 64) as usize] |= 1 << (target % 64);
+
+                unsafe {
+                    crate::mem::read_bytes(self.raw_ptr(target), &mut next);
+                    crate::mem::write_bytes(self.raw_ptr(target), &cur);
+                }
+
+                std::mem::swap(&mut cur, &mut next);
+
+                if target == start {
+                    break;
+                }
+
+                idx = target;
+            }
+        }
+
+        std::mem::swap(&mut self.rows, &mut self.cols);
+    }
+
+    fn swap_raw(&mut self, idx1: u64, idx2: u64) {
+        let mut buf1 = vec![0u8; T::SIZE];
+        let mut buf2 = vec![0u8; T::SIZE];
+
+        unsafe {
+            crate::mem::read_bytes(self.raw_ptr(idx1), &mut buf1);
+            crate::mem::read_bytes(self.raw_ptr(idx2), &mut buf2);
+
+            crate::mem::write_bytes(self.raw_ptr(idx1), &buf2);
+            crate::mem::write_bytes(self.raw_ptr(idx2), &buf1);
+        }
+    }
+
+    #[inline]
+    fn elem_ptr(&self, row: u64, col: u64) -> StablePtr {
+        self.raw_ptr(row * self.cols + col)
+    }
+
+    #[inline]
+    fn raw_ptr(&self, idx: u64) -> StablePtr {
+        SSlice::_offset(self.ptr, idx * T::SIZE as u64)
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SMatrix<T> {
+    // see encoding::counters for the layout of counters
+    const SIZE: usize = header_size(2);
+    type Buf = [u8; header_size(2)];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        encode_header(self.ptr, [self.rows, self.cols], buf);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let (ptr, [rows, cols]) = decode_header(buf);
+
+        Self {
+            ptr,
+            rows,
+            cols,
+            stable_drop_flag: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> StableType for SMatrix<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    unsafe fn stable_drop(&mut self) {
+        for idx in 0..(self.rows * self.cols) {
+            let _: T = crate::mem::read_fixed_for_move(self.raw_ptr(idx));
+        }
+
+        let slice = SSlice::from_ptr(self.ptr).unwrap();
+
+        deallocate(slice);
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Drop for SMatrix<T> {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Debug> Debug for SMatrix<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[")?;
+        for row in 0..self.rows {
+            f.write_str("[")?;
+            for (col, item) in (0u64..).zip(self.row_iter(row)) {
+                item.fmt(f)?;
+
+                if col < self.cols - 1 {
+                    f.write_str(", ")?;
+                }
+            }
+            f.write_str("]")?;
+
+            if row < self.rows - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::matrix::SMatrix;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut m = SMatrix::from_fn(3, 5, |r, c| r * 10 + c).unwrap();
+            assert_eq!(m.rows(), 3);
+            assert_eq!(m.cols(), 5);
+
+            assert_eq!(*m.get(2, 4).unwrap(), 24);
+            assert!(m.get(3, 0).is_none());
+            assert!(m.get(0, 5).is_none());
+
+            assert_eq!(m.read_row(1), vec![10, 11, 12, 13, 14]);
+            assert_eq!(
+                m.row_iter(2).map(|it| *it).collect::<Vec<_>>(),
+                vec![20, 21, 22, 23, 24]
+            );
+
+            m.write_row(0, &[5, 4, 3, 2, 1]);
+            assert_eq!(m.replace(0, 0, 100), 5);
+            *m.get_mut(0, 1).unwrap() += 1;
+            assert_eq!(m.read_row(0), vec![100, 5, 3, 2, 1]);
+
+            let mut vec = SVec::new();
+            vec.push(m).unwrap();
+            let m = vec.pop().unwrap();
+
+            assert_eq!(*m.get(1, 1).unwrap(), 11);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn transpose_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            for (rows, cols) in [(1, 1), (1, 7), (7, 1), (4, 4), (3, 5), (8, 13), (16, 3)] {
+                let mut m =
+                    SMatrix::from_fn(rows, cols, |r, c| SBox::new(r * 100 + c).unwrap()).unwrap();
+
+                m.transpose();
+                assert_eq!((m.rows(), m.cols()), (cols, rows));
+
+                for r in 0..cols {
+                    for c in 0..rows {
+                        assert_eq!(**m.get(r, c).unwrap(), c * 100 + r);
+                    }
+                }
+
+                m.transpose();
+                assert_eq!((m.rows(), m.cols()), (rows, cols));
+
+                for r in 0..rows {
+                    let row = m.row_iter(r).map(|it| **it).collect::<Vec<_>>();
+                    let expected = (0..cols).map(|c| r * 100 + c).collect::<Vec<_>>();
+
+                    assert_eq!(row, expected);
+                }
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -1,25 +1,91 @@
 #[doc(hidden)]
+pub mod binary_heap;
+#[doc(hidden)]
+pub mod bit_vec;
+#[doc(hidden)]
+pub mod bloom_filter;
+#[doc(hidden)]
 pub mod btree_map;
 #[doc(hidden)]
+pub mod btree_map_by;
+#[doc(hidden)]
 pub mod btree_set;
 #[doc(hidden)]
+pub mod bytes;
+#[doc(hidden)]
 pub mod certified_btree_map;
 #[doc(hidden)]
 pub mod certified_btree_set;
 #[doc(hidden)]
+pub mod certified_log;
+#[doc(hidden)]
+pub mod expiring_map;
+#[doc(hidden)]
+pub mod graph;
+#[doc(hidden)]
 pub mod hash_map;
 #[doc(hidden)]
 pub mod hash_set;
 #[doc(hidden)]
+pub mod index_map;
+#[doc(hidden)]
+pub mod interval_map;
+#[doc(hidden)]
 pub mod log;
 #[doc(hidden)]
+pub mod lru_cache;
+#[doc(hidden)]
+pub mod matrix;
+#[doc(hidden)]
+pub mod multiset;
+#[doc(hidden)]
+pub mod queue;
+#[doc(hidden)]
+pub mod ring_buffer;
+#[doc(hidden)]
+pub mod skip_list_map;
+#[doc(hidden)]
+pub mod slab_pool;
+#[doc(hidden)]
+pub mod sparse_vec;
+#[doc(hidden)]
+pub mod string;
+#[doc(hidden)]
+pub mod trie;
+#[doc(hidden)]
 pub mod vec;
+#[doc(hidden)]
+pub mod vec_deque;
 
-pub use btree_map::SBTreeMap;
+pub use binary_heap::SBinaryHeap;
+pub use bit_vec::SBitVec;
+pub use bloom_filter::SBloomFilter;
+pub use btree_map::{
+    cursor::SBTreeMapCursor, page::SBTreeMapPage, stats::SBTreeMapStats, CompositeKey, SBTreeMap,
+};
+pub use btree_map_by::{Comparator, SBTreeMapBy};
 pub use btree_set::SBTreeSet;
-pub use certified_btree_map::SCertifiedBTreeMap;
+pub use bytes::{SBytes, SBytesReader, SBytesRef};
+pub use certified_btree_map::{KeyExists, SCertifiedBTreeMap};
 pub use certified_btree_set::SCertifiedBTreeSet;
+pub use certified_log::SCertifiedLog;
+pub use expiring_map::SExpiringMap;
+pub use graph::{NodeId, SGraph};
 pub use hash_map::SHashMap;
 pub use hash_set::SHashSet;
+pub use index_map::SIndexMap;
+pub use interval_map::SIntervalMap;
 pub use log::SLog;
+pub use lru_cache::SLruCache;
+pub use matrix::SMatrix;
+pub use multiset::SMultiSet;
+pub use queue::SQueue;
+pub use ring_buffer::SRingBuffer;
+pub use skip_list_map::SSkipListMap;
+pub use slab_pool::SSlabPool;
+pub use sparse_vec::SSparseVec;
+pub use string::{SStr, SString};
+pub use trie::STrie;
+pub use vec::growth::{ChunkGrowth, DoubleGrowth, FactorGrowth, GrowthPolicy};
 pub use vec::SVec;
+pub use vec_deque::SVecDeque;
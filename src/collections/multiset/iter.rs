@@ -0,0 +1,60 @@
+use crate::collections::btree_map::iter::SBTreeMapIter;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+
+pub struct SMultiSetIter<'a, T> {
+    inner: SBTreeMapIter<'a, T, u64>,
+    // pointer to the current element and how many more times it should be yielded
+    current: Option<(StablePtr, u64)>,
+}
+
+impl<'a, T> SMultiSetIter<'a, T> {
+    #[inline]
+    pub(crate) fn new(inner: SBTreeMapIter<'a, T, u64>) -> Self {
+        Self {
+            inner,
+            current: None,
+        }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes + Ord> Iterator for SMultiSetIter<'a, T> {
+    type Item = SRef<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((ptr, remaining)) = &mut self.current {
+            if *remaining > 0 {
+                *remaining -= 1;
+
+                return unsafe { Some(SRef::new(*ptr)) };
+            }
+        }
+
+        let (elem, count) = self.inner.next()?;
+        self.current = Some((elem.as_ptr(), *count - 1));
+
+        Some(elem)
+    }
+}
+
+pub struct SMultiSetCountsIter<'a, T> {
+    inner: SBTreeMapIter<'a, T, u64>,
+}
+
+impl<'a, T> SMultiSetCountsIter<'a, T> {
+    #[inline]
+    pub(crate) fn new(inner: SBTreeMapIter<'a, T, u64>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes + Ord> Iterator for SMultiSetCountsIter<'a, T> {
+    type Item = (SRef<'a, T>, u64);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(elem, count)| (elem, *count))
+    }
+}
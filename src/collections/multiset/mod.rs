@@ -0,0 +1,320 @@
+use crate::collections::btree_map::SBTreeMap;
+use crate::collections::multiset::iter::{SMultiSetCountsIter, SMultiSetIter};
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::StableType;
+use std::borrow::Borrow;
+use std::fmt::{Debug, Formatter};
+
+#[doc(hidden)]
+pub mod iter;
+
+/// Ordered multiset, which can hold the same element multiple times
+///
+/// This is a wrapper around [SBTreeMap]`<T, u64>`, where each distinct element is stored only once,
+/// together with the number of its occurrences. Read [SBTreeMap]'s documentation for more info on
+/// the internals.
+///
+/// Handy for things like leaderboards, where multiple users can share the same score.
+///
+/// `T` has to implement [StableType], [AsFixedSizeBytes] and [Ord]. [SMultiSet] itself implements
+/// both [StableType] and [AsFixedSizeBytes] and can be nested inside other stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SMultiSet;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut scores = SMultiSet::<u32>::new();
+///
+/// scores.insert(100).expect("Out of memory");
+/// scores.insert(50).expect("Out of memory");
+/// scores.insert(100).expect("Out of memory");
+///
+/// assert_eq!(scores.len(), 3);
+/// assert_eq!(scores.count(&100), 2);
+///
+/// let all: Vec<u32> = scores.iter().map(|it| *it).collect();
+/// assert_eq!(all, vec![50, 100, 100]);
+/// ```
+pub struct SMultiSet<T: StableType + AsFixedSizeBytes + Ord> {
+    map: SBTreeMap<T, u64>,
+    len: u64,
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> SMultiSet<T> {
+    /// Creates a new empty [SMultiSet]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            map: SBTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the total number of elements in this [SMultiSet], counting duplicates
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the number of distinct elements in this [SMultiSet]
+    #[inline]
+    pub fn distinct_len(&self) -> u64 {
+        self.map.len()
+    }
+
+    /// Returns [true] if there are no elements in this [SMultiSet]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds one more occurrence of the element, returning the new number of its occurrences
+    ///
+    /// If an equal element is already stored, the provided one is dropped and only the counter gets
+    /// incremented, so this operation never allocates in that case. Otherwise, if the canister is
+    /// out of stable memory, returns [Err] with the element.
+    pub fn insert(&mut self, value: T) -> Result<u64, T> {
+        if let Some(mut count) = self.map.get_mut(&value) {
+            *count += 1;
+            self.len += 1;
+
+            return Ok(*count);
+        }
+
+        self.map.insert(value, 1).map_err(|(it, _)| it)?;
+        self.len += 1;
+
+        Ok(1)
+    }
+
+    /// Removes a single occurrence of the element, returning [true] if there was one
+    ///
+    /// When the last occurrence is removed, the stored element is stable-dropped.
+    pub fn remove_one<Q>(&mut self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let remaining = match self.map.get_mut(value) {
+            Some(mut count) => {
+                *count -= 1;
+                *count
+            }
+            None => return false,
+        };
+
+        if remaining == 0 {
+            self.map.remove(value);
+        }
+
+        self.len -= 1;
+
+        true
+    }
+
+    /// Removes all occurrences of the element, returning how many of them there were
+    pub fn remove_all<Q>(&mut self, value: &Q) -> u64
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let count = self.map.remove(value).unwrap_or_default();
+        self.len -= count;
+
+        count
+    }
+
+    /// Returns the number of occurrences of the element
+    #[inline]
+    pub fn count<Q>(&self, value: &Q) -> u64
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.map.get(value).map(|it| *it).unwrap_or_default()
+    }
+
+    /// Returns [true] if there is at least one occurrence of the element
+    #[inline]
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.map.contains_key(value)
+    }
+
+    /// Removes all elements from this [SMultiSet]
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.len = 0;
+    }
+
+    /// Returns an iterator over all elements in ascending order
+    ///
+    /// Each element is yielded as many times, as many occurrences of it there are.
+    #[inline]
+    pub fn iter(&self) -> SMultiSetIter<'_, T> {
+        SMultiSetIter::new(self.map.iter())
+    }
+
+    /// Returns an iterator over distinct elements in ascending order, together with the number of
+    /// their occurrences
+    #[inline]
+    pub fn iter_counts(&self) -> SMultiSetCountsIter<'_, T> {
+        SMultiSetCountsIter::new(self.map.iter())
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> Default for SMultiSet<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> AsFixedSizeBytes for SMultiSet<T> {
+    const SIZE: usize = SBTreeMap::<T, u64>::SIZE + u64::SIZE;
+    type Buf = Vec<u8>;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let map_size = SBTreeMap::<T, u64>::SIZE;
+
+        self.map.as_fixed_size_bytes(&mut buf[0..map_size]);
+        self.len.as_fixed_size_bytes(&mut buf[map_size..Self::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let map_size = SBTreeMap::<T, u64>::SIZE;
+
+        let map = SBTreeMap::<T, u64>::from_fixed_size_bytes(&buf[0..map_size]);
+        let len = u64::from_fixed_size_bytes(&buf[map_size..Self::SIZE]);
+
+        Self { map, len }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord> StableType for SMultiSet<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.map.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.map.stable_drop_flag_off();
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Ord + Debug> Debug for SMultiSet<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("(")?;
+        for (idx, elem) in self.iter().enumerate() {
+            elem.fmt(f)?;
+
+            if (idx as u64) < self.len - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str(")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::multiset::SMultiSet;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut set = SMultiSet::new();
+            let mut check = BTreeMap::<u64, u64>::new();
+
+            for _ in 0..5000 {
+                let elem = rng.gen_range(0..300u64);
+
+                match rng.gen_range(0..10) {
+                    0..=5 => {
+                        let count = set.insert(SBox::new(elem).unwrap()).unwrap();
+                        let entry = check.entry(elem).or_default();
+                        *entry += 1;
+
+                        assert_eq!(count, *entry);
+                    }
+                    6..=8 => {
+                        let removed = set.remove_one(&elem);
+                        let expected = match check.get_mut(&elem) {
+                            Some(count) => {
+                                *count -= 1;
+                                if *count == 0 {
+                                    check.remove(&elem);
+                                }
+
+                                true
+                            }
+                            None => false,
+                        };
+
+                        assert_eq!(removed, expected);
+                    }
+                    _ => {
+                        assert_eq!(
+                            set.remove_all(&elem),
+                            check.remove(&elem).unwrap_or_default()
+                        );
+                    }
+                }
+
+                assert_eq!(set.len(), check.values().sum::<u64>());
+                assert_eq!(set.distinct_len(), check.len() as u64);
+            }
+
+            for elem in 0..300u64 {
+                assert_eq!(
+                    set.count(&elem),
+                    check.get(&elem).copied().unwrap_or_default()
+                );
+                assert_eq!(set.contains(&elem), check.contains_key(&elem));
+            }
+
+            let expected = check
+                .iter()
+                .flat_map(|(elem, count)| std::iter::repeat_n(*elem, *count as usize))
+                .collect::<Vec<_>>();
+            let actual = set.iter().map(|it| **it).collect::<Vec<_>>();
+            assert_eq!(actual, expected);
+
+            let expected = check.iter().map(|(e, c)| (*e, *c)).collect::<Vec<_>>();
+            let actual = set.iter_counts().map(|(e, c)| (**e, c)).collect::<Vec<_>>();
+            assert_eq!(actual, expected);
+
+            let mut vec = SVec::new();
+            vec.push(set).unwrap();
+
+            let mut set = vec.pop().unwrap();
+            assert_eq!(set.len(), check.values().sum::<u64>());
+
+            set.clear();
+            assert!(set.is_empty());
+            assert!(set.iter().next().is_none());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
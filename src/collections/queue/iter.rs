@@ -0,0 +1,58 @@
+use crate::collections::queue::SQueue;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+
+pub struct SQueueIter<'a, T: StableType + AsFixedSizeBytes> {
+    chunk_ptr: StablePtr,
+    idx: usize,
+    remaining: u64,
+    _marker: PhantomData<&'a SQueue<T>>,
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> SQueueIter<'a, T> {
+    pub(crate) fn new(chunk_ptr: StablePtr, idx: usize, remaining: u64) -> Self {
+        Self {
+            chunk_ptr,
+            idx,
+            remaining,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SQueueIter<'a, T> {
+    type Item = SRef<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        if self.idx == SQueue::<T>::chunk_capacity() {
+            self.chunk_ptr = SQueue::<T>::read_next_ptr(self.chunk_ptr);
+            self.idx = 0;
+        }
+
+        let ptr = SQueue::<T>::element_ptr(self.chunk_ptr, self.idx);
+
+        self.idx += 1;
+        self.remaining -= 1;
+
+        unsafe { Some(SRef::new(ptr)) }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.remaining as usize;
+
+        (len, Some(len))
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> ExactSizeIterator for SQueueIter<'a, T> {}
+
+impl<'a, T: StableType + AsFixedSizeBytes> FusedIterator for SQueueIter<'a, T> {}
@@ -0,0 +1,448 @@
+use crate::collections::queue::iter::SQueueIter;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use crate::{allocate, deallocate, OutOfMemory};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+#[doc(hidden)]
+pub mod iter;
+
+/// Target size of a single chunk of elements in bytes
+pub(crate) const CHUNK_SIZE_BYTES: usize = 4096;
+
+/// Unbounded FIFO queue, stored as a singly linked list of fixed-size chunks
+///
+/// Elements are pushed to the back and popped from the front. When the last chunk is full, a new
+/// one is allocated and linked to it. When all elements of the first chunk are popped, this chunk
+/// is deallocated, so the memory used by the queue follows its length. The last remaining chunk is
+/// never deallocated, while the queue is alive, so a queue which is constantly drained and refilled
+/// does not allocate at all. Elements are never moved.
+///
+/// Useful for canister task queues or outboxes - both [SQueue::push_back] and [SQueue::pop_front]
+/// are O(1).
+///
+/// Each chunk holds as many elements as fit into 4 KB (at least one).
+///
+/// `T` has to implement both [StableType] and [AsFixedSizeBytes]. [SQueue] itself implements these
+/// traits and can be nested inside other stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SQueue;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut outbox = SQueue::<u64>::new();
+///
+/// outbox.push_back(1).expect("Out of memory");
+/// outbox.push_back(2).expect("Out of memory");
+///
+/// assert_eq!(outbox.pop_front(), Some(1));
+/// assert_eq!(*outbox.front().unwrap(), 2);
+/// ```
+pub struct SQueue<T: StableType + AsFixedSizeBytes> {
+    len: u64,
+    head_ptr: StablePtr,
+    head_idx: usize,
+    tail_ptr: StablePtr,
+    tail_len: usize,
+    stable_drop_flag: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T: StableType + AsFixedSizeBytes> SQueue<T> {
+    /// Creates a new [SQueue]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            len: 0,
+            head_ptr: EMPTY_PTR,
+            head_idx: 0,
+            tail_ptr: EMPTY_PTR,
+            tail_len: 0,
+            stable_drop_flag: true,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the length of this [SQueue]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if the length of this [SQueue] is `0`
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Inserts a new element at the back of this [SQueue]
+    ///
+    /// May allocate a new chunk. If the canister is out of stable memory, returns [Err] with the
+    /// element that was about to get inserted.
+    pub fn push_back(&mut self, mut element: T) -> Result<(), T> {
+        if self.tail_ptr == EMPTY_PTR {
+            let chunk = match Self::allocate_chunk() {
+                Ok(it) => it,
+                Err(_) => return Err(element),
+            };
+
+            self.head_ptr = chunk;
+            self.tail_ptr = chunk;
+        } else if self.tail_len == Self::chunk_capacity() {
+            let chunk = match Self::allocate_chunk() {
+                Ok(it) => it,
+                Err(_) => return Err(element),
+            };
+
+            Self::write_next_ptr(self.tail_ptr, chunk);
+
+            self.tail_ptr = chunk;
+            self.tail_len = 0;
+        }
+
+        let elem_ptr = Self::element_ptr(self.tail_ptr, self.tail_len);
+        unsafe { crate::mem::write_fixed(elem_ptr, &mut element) };
+
+        self.tail_len += 1;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Removes the element at the front of this [SQueue] and returns it
+    ///
+    /// If this element was the last one in its chunk, the chunk gets deallocated, freeing the memory.
+    ///
+    /// If the [SQueue] is empty, returns [None].
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let elem_ptr = Self::element_ptr(self.head_ptr, self.head_idx);
+        let it = unsafe { crate::mem::read_fixed_for_move(elem_ptr) };
+
+        self.head_idx += 1;
+        self.len -= 1;
+
+        if self.head_ptr == self.tail_ptr {
+            if self.len == 0 {
+                // reusing the only chunk from the beginning
+                self.head_idx = 0;
+                self.tail_len = 0;
+            }
+        } else if self.head_idx == Self::chunk_capacity() {
+            let next_ptr = Self::read_next_ptr(self.head_ptr);
+            deallocate(unsafe { SSlice::from_ptr(self.head_ptr).unwrap() });
+
+            self.head_ptr = next_ptr;
+            self.head_idx = 0;
+        }
+
+        Some(it)
+    }
+
+    /// Returns an immutable reference [SRef] to the front element of this [SQueue]
+    ///
+    /// If the [SQueue] is empty, returns [None].
+    #[inline]
+    pub fn front(&self) -> Option<SRef<'_, T>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        unsafe { Some(SRef::new(Self::element_ptr(self.head_ptr, self.head_idx))) }
+    }
+
+    /// Returns a mutable reference [SRefMut] to the front element of this [SQueue]
+    ///
+    /// If the [SQueue] is empty, returns [None].
+    #[inline]
+    pub fn front_mut(&mut self) -> Option<SRefMut<'_, T>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        unsafe {
+            Some(SRefMut::new(Self::element_ptr(
+                self.head_ptr,
+                self.head_idx,
+            )))
+        }
+    }
+
+    /// Returns an immutable reference [SRef] to the back element of this [SQueue]
+    ///
+    /// If the [SQueue] is empty, returns [None].
+    #[inline]
+    pub fn back(&self) -> Option<SRef<'_, T>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        unsafe {
+            Some(SRef::new(Self::element_ptr(
+                self.tail_ptr,
+                self.tail_len - 1,
+            )))
+        }
+    }
+
+    /// Removes all elements from this [SQueue]
+    ///
+    /// Deallocates all chunks, but the last one, freeing the memory.
+    #[inline]
+    pub fn clear(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+
+    /// Returns an iterator over elements of this [SQueue], from the front to the back
+    #[inline]
+    pub fn iter(&self) -> SQueueIter<'_, T> {
+        SQueueIter::new(self.head_ptr, self.head_idx, self.len)
+    }
+
+    /// Returns the number of elements stored in a single chunk
+    #[inline]
+    pub const fn chunk_capacity() -> usize {
+        if T::SIZE == 0 || T::SIZE >= CHUNK_SIZE_BYTES {
+            1
+        } else {
+            CHUNK_SIZE_BYTES / T::SIZE
+        }
+    }
+
+    fn allocate_chunk() -> Result<StablePtr, OutOfMemory> {
+        let size = u64::SIZE + Self::chunk_capacity() * T::SIZE;
+        let ptr = unsafe { allocate(size as u64)?.as_ptr() };
+
+        Self::write_next_ptr(ptr, EMPTY_PTR);
+
+        Ok(ptr)
+    }
+
+    #[inline]
+    pub(crate) fn read_next_ptr(chunk_ptr: StablePtr) -> StablePtr {
+        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(chunk_ptr, 0)) }
+    }
+
+    #[inline]
+    fn write_next_ptr(chunk_ptr: StablePtr, mut next_ptr: StablePtr) {
+        unsafe { crate::mem::write_fixed(SSlice::_offset(chunk_ptr, 0), &mut next_ptr) };
+    }
+
+    #[inline]
+    pub(crate) fn element_ptr(chunk_ptr: StablePtr, idx: usize) -> StablePtr {
+        SSlice::_offset(chunk_ptr, (u64::SIZE + idx * T::SIZE) as u64)
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Default for SQueue<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SQueue<T> {
+    const SIZE: usize = u64::SIZE * 3 + usize::SIZE * 2;
+    type Buf = [u8; u64::SIZE * 3 + usize::SIZE * 2];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let mut i = 0;
+
+        self.len.as_fixed_size_bytes(&mut buf[i..i + u64::SIZE]);
+        i += u64::SIZE;
+        self.head_ptr
+            .as_fixed_size_bytes(&mut buf[i..i + u64::SIZE]);
+        i += u64::SIZE;
+        self.head_idx
+            .as_fixed_size_bytes(&mut buf[i..i + usize::SIZE]);
+        i += usize::SIZE;
+        self.tail_ptr
+            .as_fixed_size_bytes(&mut buf[i..i + u64::SIZE]);
+        i += u64::SIZE;
+        self.tail_len
+            .as_fixed_size_bytes(&mut buf[i..i + usize::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let mut i = 0;
+
+        let len = u64::from_fixed_size_bytes(&buf[i..i + u64::SIZE]);
+        i += u64::SIZE;
+        let head_ptr = u64::from_fixed_size_bytes(&buf[i..i + u64::SIZE]);
+        i += u64::SIZE;
+        let head_idx = usize::from_fixed_size_bytes(&buf[i..i + usize::SIZE]);
+        i += usize::SIZE;
+        let tail_ptr = u64::from_fixed_size_bytes(&buf[i..i + u64::SIZE]);
+        i += u64::SIZE;
+        let tail_len = usize::from_fixed_size_bytes(&buf[i..i + usize::SIZE]);
+
+        Self {
+            len,
+            head_ptr,
+            head_idx,
+            tail_ptr,
+            tail_len,
+            stable_drop_flag: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> StableType for SQueue<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    unsafe fn stable_drop(&mut self) {
+        self.clear();
+
+        if self.tail_ptr != EMPTY_PTR {
+            deallocate(SSlice::from_ptr(self.tail_ptr).unwrap());
+
+            self.head_ptr = EMPTY_PTR;
+            self.tail_ptr = EMPTY_PTR;
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Drop for SQueue<T> {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Debug> Debug for SQueue<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[")?;
+        for (idx, item) in self.iter().enumerate() {
+            item.fmt(f)?;
+
+            if (idx as u64) < self.len - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::queue::SQueue;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+    use std::collections::VecDeque;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut queue = SQueue::new();
+            let mut check = VecDeque::new();
+
+            assert!(queue.pop_front().is_none());
+            assert!(queue.front().is_none());
+            assert!(queue.back().is_none());
+
+            for i in 0..20_000u64 {
+                if rng.gen_bool(0.6) {
+                    queue.push_back(SBox::new(i).unwrap()).unwrap();
+                    check.push_back(i);
+                } else {
+                    assert_eq!(
+                        queue.pop_front().map(|it| it.into_inner()),
+                        check.pop_front()
+                    );
+                }
+
+                assert_eq!(queue.len(), check.len() as u64);
+                assert_eq!(queue.front().map(|it| **it), check.front().copied());
+                assert_eq!(queue.back().map(|it| **it), check.back().copied());
+            }
+
+            let items = queue.iter().map(|it| **it).collect::<Vec<_>>();
+            assert_eq!(items, check.iter().copied().collect::<Vec<_>>());
+
+            let mut vec = SVec::new();
+            vec.push(queue).unwrap();
+
+            let mut queue = vec.pop().unwrap();
+            *queue.front_mut().unwrap() = SBox::new(100).unwrap();
+            assert_eq!(**queue.front().unwrap(), 100);
+
+            queue.clear();
+            assert!(queue.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn chunks_are_freed() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut queue = SQueue::<u64>::new();
+            let chunk_capacity = SQueue::<u64>::chunk_capacity() as u64;
+
+            queue.push_back(0).unwrap();
+            let one_chunk = get_allocated_size();
+
+            for i in 1..(chunk_capacity * 10) {
+                queue.push_back(i).unwrap();
+            }
+            assert!(get_allocated_size() >= one_chunk * 10);
+
+            for i in 0..(chunk_capacity * 9) {
+                assert_eq!(queue.pop_front(), Some(i));
+            }
+            assert_eq!(get_allocated_size(), one_chunk);
+
+            queue.clear();
+            assert_eq!(get_allocated_size(), one_chunk);
+
+            for i in 0..chunk_capacity {
+                queue.push_back(i).unwrap();
+            }
+            assert_eq!(get_allocated_size(), one_chunk);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,59 @@
+use crate::collections::ring_buffer::SRingBuffer;
+use crate::collections::vec::iter::size_hint;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::iter::FusedIterator;
+
+pub struct SRingBufferIter<'a, T: StableType + AsFixedSizeBytes> {
+    buf: &'a SRingBuffer<T>,
+    idx: u64,
+    end_idx: u64,
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> SRingBufferIter<'a, T> {
+    pub(crate) fn new(buf: &'a SRingBuffer<T>) -> Self {
+        Self {
+            buf,
+            idx: 0,
+            end_idx: buf.len(),
+        }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SRingBufferIter<'a, T> {
+    type Item = SRef<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.idx == self.end_idx {
+            return None;
+        }
+
+        let ptr = self.buf.physical_ptr(self.idx);
+        self.idx += 1;
+
+        unsafe { Some(SRef::new(ptr)) }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        size_hint(self.end_idx - self.idx)
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> DoubleEndedIterator for SRingBufferIter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.idx == self.end_idx {
+            return None;
+        }
+
+        self.end_idx -= 1;
+        let ptr = self.buf.physical_ptr(self.end_idx);
+
+        unsafe { Some(SRef::new(ptr)) }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> ExactSizeIterator for SRingBufferIter<'a, T> {}
+
+impl<'a, T: StableType + AsFixedSizeBytes> FusedIterator for SRingBufferIter<'a, T> {}
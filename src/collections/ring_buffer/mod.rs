@@ -0,0 +1,374 @@
+use crate::collections::ring_buffer::iter::SRingBufferIter;
+use crate::encoding::counters::{decode_header, encode_header, header_size};
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use crate::utils::math::max_elements;
+use crate::{allocate, deallocate, OutOfMemory};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+#[doc(hidden)]
+pub mod iter;
+
+/// Fixed-capacity ring buffer, holding the `capacity` most recently pushed elements
+///
+/// The underlying memory block is allocated once, at construction, and is never reallocated, so
+/// the amount of stable memory this collection uses is known in advance and pushes can't fail
+/// with [OutOfMemory]. Once the buffer is full, each push evicts the oldest element.
+///
+/// Useful for "recent N samples" telemetry or "last N events" history.
+///
+/// `T` has to implement both [StableType] and [AsFixedSizeBytes]. [SRingBuffer] itself implements
+/// these traits and can be nested inside other stable data structures.
+///
+/// Elements are indexed from the oldest (`0`) to the newest (`len - 1`).
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SRingBuffer;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut samples = SRingBuffer::<u64>::new(3).expect("Out of memory");
+///
+/// for i in 0..5 {
+///     samples.push(i);
+/// }
+///
+/// let last_samples: Vec<u64> = samples.iter().map(|it| *it).collect();
+/// assert_eq!(last_samples, vec![2, 3, 4]);
+/// ```
+pub struct SRingBuffer<T: StableType + AsFixedSizeBytes> {
+    ptr: u64,
+    head: u64,
+    len: u64,
+    cap: u64,
+    stable_drop_flag: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T: StableType + AsFixedSizeBytes> SRingBuffer<T> {
+    /// Creates a new [SRingBuffer] of the requested capacity
+    ///
+    /// Allocates a memory block for all `capacity` elements at once, returning [OutOfMemory] if
+    /// there is not enough stable memory.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is `0` or is greater than [SRingBuffer::max_capacity].
+    pub fn new(capacity: u64) -> Result<Self, OutOfMemory> {
+        assert!(capacity > 0, "capacity must be non-zero");
+        assert!(capacity <= Self::max_capacity());
+
+        Ok(Self {
+            ptr: unsafe { allocate(capacity * T::SIZE as u64)?.as_ptr() },
+            head: 0,
+            len: 0,
+            cap: capacity,
+            stable_drop_flag: true,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Returns the capacity of this [SRingBuffer]
+    #[inline]
+    pub fn capacity(&self) -> u64 {
+        self.cap
+    }
+
+    /// Returns the length of this [SRingBuffer]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if the length of this [SRingBuffer] is `0`
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns [true] if the length of this [SRingBuffer] is equal to its capacity
+    ///
+    /// In this state, each push evicts the oldest element.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len == self.cap
+    }
+
+    /// Returns the maximum possible capacity of this [SRingBuffer]
+    #[inline]
+    pub const fn max_capacity() -> u64 {
+        max_elements(T::SIZE)
+    }
+
+    /// Inserts a new element, as the newest one
+    ///
+    /// If the [SRingBuffer] is full, the oldest element is evicted and returned back. Never
+    /// allocates stable memory.
+    pub fn push(&mut self, mut element: T) -> Option<T> {
+        let evicted = if self.is_full() {
+            self.pop_oldest()
+        } else {
+            None
+        };
+
+        let elem_ptr = self.physical_ptr(self.len);
+        unsafe { crate::mem::write_fixed(elem_ptr, &mut element) };
+
+        self.len += 1;
+
+        evicted
+    }
+
+    /// Removes the oldest element of this [SRingBuffer]
+    ///
+    /// If the [SRingBuffer] is empty, returns [None].
+    pub fn pop_oldest(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let elem_ptr = self.physical_ptr(0);
+
+        self.head = (self.head + 1) % self.cap;
+        self.len -= 1;
+
+        Some(unsafe { crate::mem::read_fixed_for_move(elem_ptr) })
+    }
+
+    /// Removes the newest element of this [SRingBuffer]
+    ///
+    /// If the [SRingBuffer] is empty, returns [None].
+    pub fn pop_newest(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.len -= 1;
+        let elem_ptr = self.physical_ptr(self.len);
+
+        Some(unsafe { crate::mem::read_fixed_for_move(elem_ptr) })
+    }
+
+    /// Returns a [SRef] pointing to the element at requested index, where `0` is the oldest element
+    ///
+    /// If out of bounds, returns [None]
+    #[inline]
+    pub fn get(&self, idx: u64) -> Option<SRef<'_, T>> {
+        let ptr = self.get_element_ptr(idx)?;
+
+        unsafe { Some(SRef::new(ptr)) }
+    }
+
+    /// Returns a [SRefMut] pointing to the element at requested index, where `0` is the oldest element
+    ///
+    /// If out of bounds, returns [None]
+    #[inline]
+    pub fn get_mut(&mut self, idx: u64) -> Option<SRefMut<'_, T>> {
+        let ptr = self.get_element_ptr(idx)?;
+
+        unsafe { Some(SRefMut::new(ptr)) }
+    }
+
+    /// Returns a [SRef] pointing to the oldest element of this [SRingBuffer]
+    #[inline]
+    pub fn oldest(&self) -> Option<SRef<'_, T>> {
+        self.get(0)
+    }
+
+    /// Returns a [SRef] pointing to the newest element of this [SRingBuffer]
+    #[inline]
+    pub fn newest(&self) -> Option<SRef<'_, T>> {
+        self.get(self.len.checked_sub(1)?)
+    }
+
+    /// Removes all elements from this [SRingBuffer]
+    ///
+    /// The underlying memory block stays allocated.
+    #[inline]
+    pub fn clear(&mut self) {
+        while self.pop_newest().is_some() {}
+
+        self.head = 0;
+    }
+
+    /// Returns an iterator over elements of this [SRingBuffer], from the oldest to the newest
+    ///
+    /// The iterator is double-ended, use [Iterator::rev] to iterate from the newest to the oldest.
+    #[inline]
+    pub fn iter(&self) -> SRingBufferIter<'_, T> {
+        SRingBufferIter::new(self)
+    }
+
+    #[inline]
+    pub(crate) fn physical_ptr(&self, idx: u64) -> StablePtr {
+        let physical_idx = (self.head + idx) % self.cap;
+
+        SSlice::_offset(self.ptr, physical_idx * T::SIZE as u64)
+    }
+
+    #[inline]
+    fn get_element_ptr(&self, idx: u64) -> Option<StablePtr> {
+        if idx < self.len {
+            Some(self.physical_ptr(idx))
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Debug> Debug for SRingBuffer<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[")?;
+        for (idx, item) in (0u64..).zip(self.iter()) {
+            item.fmt(f)?;
+
+            if idx < self.len - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SRingBuffer<T> {
+    // see encoding::counters for the layout of counters
+    const SIZE: usize = header_size(3);
+    type Buf = [u8; header_size(3)];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        encode_header(self.ptr, [self.head, self.len, self.cap], buf);
+    }
+
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        let (ptr, [head, len, cap]) = decode_header(arr);
+
+        Self {
+            ptr,
+            head,
+            len,
+            cap,
+            stable_drop_flag: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> StableType for SRingBuffer<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    unsafe fn stable_drop(&mut self) {
+        self.clear();
+
+        let slice = SSlice::from_ptr(self.ptr).unwrap();
+
+        deallocate(slice);
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Drop for SRingBuffer<T> {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::ring_buffer::SRingBuffer;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use std::collections::VecDeque;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut buf = SRingBuffer::new(10).unwrap();
+            let allocated = get_allocated_size();
+
+            let mut check = VecDeque::new();
+
+            assert!(buf.is_empty());
+            assert!(buf.oldest().is_none());
+            assert!(buf.newest().is_none());
+
+            for i in 0..100u64 {
+                let evicted = buf.push(SBox::new(i).unwrap());
+
+                check.push_back(i);
+                let expected = if check.len() > 10 {
+                    check.pop_front()
+                } else {
+                    None
+                };
+
+                assert_eq!(evicted.map(|it| it.into_inner()), expected);
+                assert_eq!(buf.len(), check.len() as u64);
+                assert_eq!(**buf.newest().unwrap(), i);
+
+                if i % 7 == 0 {
+                    assert_eq!(
+                        buf.pop_oldest().map(|it| it.into_inner()),
+                        check.pop_front()
+                    );
+                }
+            }
+
+            assert_eq!(buf.capacity(), 10);
+            assert!(buf.is_full());
+
+            let elems = buf.iter().map(|it| **it).collect::<Vec<_>>();
+            assert_eq!(elems, check.iter().copied().collect::<Vec<_>>());
+
+            let rev_elems = buf.iter().rev().map(|it| **it).collect::<Vec<_>>();
+            assert_eq!(rev_elems, check.iter().rev().copied().collect::<Vec<_>>());
+
+            for (idx, it) in (0u64..).zip(check.iter()) {
+                assert_eq!(**buf.get(idx).unwrap(), *it);
+            }
+
+            assert_eq!(buf.pop_newest().map(|it| it.into_inner()), check.pop_back());
+
+            buf.clear();
+            assert!(buf.is_empty());
+
+            // boxes are released, only the buffer itself is left
+            assert_eq!(get_allocated_size(), allocated);
+
+            let mut vec = SVec::new();
+            buf.push(SBox::new(1).unwrap());
+            vec.push(buf).unwrap();
+
+            let buf = vec.pop().unwrap();
+            assert_eq!(**buf.oldest().unwrap(), 1);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
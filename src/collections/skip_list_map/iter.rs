@@ -0,0 +1,50 @@
+use crate::collections::skip_list_map::SSkipListMap;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+
+pub struct SSkipListMapIter<'a, K, V> {
+    node: StablePtr,
+    _marker: PhantomData<&'a (K, V)>,
+}
+
+impl<'a, K, V> SSkipListMapIter<'a, K, V> {
+    #[inline]
+    pub(crate) fn new(node: StablePtr) -> Self {
+        Self {
+            node,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Iterator
+    for SSkipListMapIter<'a, K, V>
+{
+    type Item = (SRef<'a, K>, SRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.node == EMPTY_PTR {
+            return None;
+        }
+
+        let node = self.node;
+        self.node = SSkipListMap::<K, V>::read_node_next(node);
+
+        unsafe {
+            Some((
+                SRef::new(SSkipListMap::<K, V>::key_ptr(node)),
+                SRef::new(SSkipListMap::<K, V>::value_ptr(node)),
+            ))
+        }
+    }
+}
+
+impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> FusedIterator
+    for SSkipListMapIter<'a, K, V>
+{
+}
@@ -0,0 +1,597 @@
+use crate::collections::skip_list_map::iter::SSkipListMapIter;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use crate::utils::math::shuffle_bits;
+use crate::{allocate, deallocate};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+#[doc(hidden)]
+pub mod iter;
+
+/// Maximum number of levels - enough for ~4 billion entries
+pub(crate) const MAX_LEVEL: usize = 16;
+
+const DEFAULT_SEED: u32 = 0x2545_f491;
+
+/// Ordered map, based on a skip list - an alternative to [SBTreeMap](crate::collections::SBTreeMap)
+/// for write-heavy workloads
+///
+/// Each entry is a separate memory block, holding the key, the value and from 1 to 16 forward
+/// pointers. The number of pointers (the level of the entry) is chosen randomly on insertion, each
+/// next level being 4 times less likely than the previous one, so on average an entry has 1.33
+/// pointers. Upper levels let searches skip over long runs of entries, which gives O(logN)
+/// expected complexity for all operations.
+///
+/// Unlike a B-tree, inserting or removing an entry never moves other entries and never splits or
+/// merges nodes - it only rewrites the forward pointers of a few preceding entries. On the other
+/// hand, searches perform more random stable memory reads, since keys are not grouped into nodes.
+///
+/// Levels are generated by a deterministic pseudo-random generator, which state is a part of this
+/// map, so the structure of the skip list is reproducible.
+///
+/// `K` has to implement [StableType], [AsFixedSizeBytes] and [Ord]. `V` has to implement both
+/// [StableType] and [AsFixedSizeBytes]. [SSkipListMap] itself implements these traits and can be
+/// nested inside other stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SSkipListMap;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut balances = SSkipListMap::<u64, u128>::new();
+///
+/// balances.insert(3, 300).expect("Out of memory");
+/// balances.insert(1, 100).expect("Out of memory");
+/// balances.insert(2, 200).expect("Out of memory");
+///
+/// assert_eq!(*balances.get(&2).unwrap(), 200);
+///
+/// let keys: Vec<u64> = balances.iter().map(|(k, _)| *k).collect();
+/// assert_eq!(keys, vec![1, 2, 3]);
+/// ```
+pub struct SSkipListMap<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> {
+    head: StablePtr,
+    len: u64,
+    level: usize,
+    seed: u32,
+    stable_drop_flag: bool,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> SSkipListMap<K, V> {
+    /// Creates a new [SSkipListMap]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            head: EMPTY_PTR,
+            len: 0,
+            level: 0,
+            seed: DEFAULT_SEED,
+            stable_drop_flag: true,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of entries in this [SSkipListMap]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if there are no entries in this [SSkipListMap]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Inserts a new entry, returning the previous value, if there was one
+    ///
+    /// Allocates a single memory block for a new entry. If the canister is out of stable memory,
+    /// returns [Err] with the entry that was about to get inserted.
+    pub fn insert(&mut self, mut key: K, mut value: V) -> Result<Option<V>, (K, V)> {
+        if self.head == EMPTY_PTR {
+            match unsafe { allocate((MAX_LEVEL * u64::SIZE) as u64) } {
+                Ok(slice) => self.head = slice.as_ptr(),
+                Err(_) => return Err((key, value)),
+            }
+
+            for i in 0..MAX_LEVEL {
+                self.write_next(self.head, i, EMPTY_PTR);
+            }
+        }
+
+        let mut update = self.find_predecessors(&key);
+        let candidate = self.read_next(update[0], 0);
+
+        if candidate != EMPTY_PTR && Self::cmp_key(candidate, &key) == Ordering::Equal {
+            let value_ptr = Self::value_ptr(candidate);
+
+            let prev = unsafe { crate::mem::read_fixed_for_move(value_ptr) };
+            unsafe { crate::mem::write_fixed(value_ptr, &mut value) };
+
+            return Ok(Some(prev));
+        }
+
+        let level = self.random_level();
+        let size = 1 + K::SIZE + V::SIZE + level * u64::SIZE;
+
+        let node = match unsafe { allocate(size as u64) } {
+            Ok(slice) => slice.as_ptr(),
+            Err(_) => return Err((key, value)),
+        };
+
+        if level > self.level {
+            for it in update.iter_mut().take(level).skip(self.level) {
+                *it = self.head;
+            }
+
+            self.level = level;
+        }
+
+        unsafe {
+            crate::mem::write_fixed(SSlice::_offset(node, 0), &mut (level as u8));
+            crate::mem::write_fixed(Self::key_ptr(node), &mut key);
+            crate::mem::write_fixed(Self::value_ptr(node), &mut value);
+        }
+
+        for (i, prev) in update.iter().enumerate().take(level) {
+            self.write_next(node, i, self.read_next(*prev, i));
+            self.write_next(*prev, i, node);
+        }
+
+        self.len += 1;
+
+        Ok(None)
+    }
+
+    /// Removes an entry by the key, returning its value
+    ///
+    /// Deallocates the memory block of the entry. If there is no such entry, returns [None].
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        if self.head == EMPTY_PTR {
+            return None;
+        }
+
+        let update = self.find_predecessors(key);
+        let node = self.read_next(update[0], 0);
+
+        if node == EMPTY_PTR || Self::cmp_key(node, key) != Ordering::Equal {
+            return None;
+        }
+
+        for (i, prev) in update.iter().enumerate().take(Self::node_level(node)) {
+            if self.read_next(*prev, i) == node {
+                self.write_next(*prev, i, self.read_next(node, i));
+            }
+        }
+
+        let (_, value) = Self::destroy_node(node);
+
+        while self.level > 0 && self.read_next(self.head, self.level - 1) == EMPTY_PTR {
+            self.level -= 1;
+        }
+
+        self.len -= 1;
+
+        Some(value)
+    }
+
+    /// Returns an immutable reference [SRef] to the value stored by the key
+    ///
+    /// If there is no such entry, returns [None].
+    #[inline]
+    pub fn get<Q>(&self, key: &Q) -> Option<SRef<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let node = self.find(key)?;
+
+        unsafe { Some(SRef::new(Self::value_ptr(node))) }
+    }
+
+    /// Returns a mutable reference [SRefMut] to the value stored by the key
+    ///
+    /// If there is no such entry, returns [None].
+    #[inline]
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<SRefMut<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let node = self.find(key)?;
+
+        unsafe { Some(SRefMut::new(Self::value_ptr(node))) }
+    }
+
+    /// Returns [true] if there is an entry stored by the key
+    #[inline]
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.find(key).is_some()
+    }
+
+    /// Returns the entry with the smallest key
+    ///
+    /// If the [SSkipListMap] is empty, returns [None].
+    #[inline]
+    pub fn first(&self) -> Option<(SRef<'_, K>, SRef<'_, V>)> {
+        self.iter().next()
+    }
+
+    /// Returns an iterator over entries of this [SSkipListMap] in ascending order of keys
+    #[inline]
+    pub fn iter(&self) -> SSkipListMapIter<'_, K, V> {
+        if self.head == EMPTY_PTR {
+            return SSkipListMapIter::new(EMPTY_PTR);
+        }
+
+        SSkipListMapIter::new(self.read_next(self.head, 0))
+    }
+
+    /// Removes all entries from this [SSkipListMap], deallocating all memory blocks
+    pub fn clear(&mut self) {
+        if self.head == EMPTY_PTR {
+            return;
+        }
+
+        let mut node = self.read_next(self.head, 0);
+        while node != EMPTY_PTR {
+            let next = self.read_next(node, 0);
+            Self::destroy_node(node);
+
+            node = next;
+        }
+
+        deallocate(unsafe { SSlice::from_ptr(self.head).unwrap() });
+
+        self.head = EMPTY_PTR;
+        self.level = 0;
+        self.len = 0;
+    }
+
+    fn find<Q>(&self, key: &Q) -> Option<StablePtr>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        if self.head == EMPTY_PTR {
+            return None;
+        }
+
+        let mut node = self.head;
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.read_next(node, i);
+                if next == EMPTY_PTR {
+                    break;
+                }
+
+                match Self::cmp_key(next, key) {
+                    Ordering::Less => node = next,
+                    Ordering::Equal => return Some(next),
+                    Ordering::Greater => break,
+                }
+            }
+        }
+
+        None
+    }
+
+    // for each level, finds the last node with a key less than the provided one
+    fn find_predecessors<Q>(&self, key: &Q) -> [StablePtr; MAX_LEVEL]
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut update = [self.head; MAX_LEVEL];
+        let mut node = self.head;
+
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.read_next(node, i);
+                if next == EMPTY_PTR || Self::cmp_key(next, key) != Ordering::Less {
+                    break;
+                }
+
+                node = next;
+            }
+
+            update[i] = node;
+        }
+
+        update
+    }
+
+    fn random_level(&mut self) -> usize {
+        self.seed = shuffle_bits(self.seed);
+
+        // each next level is 4 times less likely
+        usize::min(1 + (self.seed.trailing_zeros() / 2) as usize, MAX_LEVEL)
+    }
+
+    fn destroy_node(node: StablePtr) -> (K, V) {
+        let key = unsafe { crate::mem::read_fixed_for_move(Self::key_ptr(node)) };
+        let value = unsafe { crate::mem::read_fixed_for_move(Self::value_ptr(node)) };
+
+        deallocate(unsafe { SSlice::from_ptr(node).unwrap() });
+
+        (key, value)
+    }
+
+    #[inline]
+    fn cmp_key<Q>(node: StablePtr, key: &Q) -> Ordering
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let node_key: K = unsafe { crate::mem::read_fixed_for_reference(Self::key_ptr(node)) };
+
+        node_key.borrow().cmp(key)
+    }
+
+    #[inline]
+    fn node_level(node: StablePtr) -> usize {
+        let level: u8 = unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(node, 0)) };
+
+        level as usize
+    }
+
+    #[inline]
+    pub(crate) fn key_ptr(node: StablePtr) -> StablePtr {
+        SSlice::_offset(node, 1)
+    }
+
+    #[inline]
+    pub(crate) fn value_ptr(node: StablePtr) -> StablePtr {
+        SSlice::_offset(node, (1 + K::SIZE) as u64)
+    }
+
+    // the head only consists of forward pointers
+    #[inline]
+    fn next_slot(&self, node: StablePtr, level: usize) -> StablePtr {
+        if node == self.head {
+            SSlice::_offset(node, (level * u64::SIZE) as u64)
+        } else {
+            Self::node_next_slot(node, level)
+        }
+    }
+
+    #[inline]
+    fn node_next_slot(node: StablePtr, level: usize) -> StablePtr {
+        SSlice::_offset(node, (1 + K::SIZE + V::SIZE + level * u64::SIZE) as u64)
+    }
+
+    #[inline]
+    fn read_next(&self, node: StablePtr, level: usize) -> StablePtr {
+        unsafe { crate::mem::read_fixed_for_reference(self.next_slot(node, level)) }
+    }
+
+    #[inline]
+    pub(crate) fn read_node_next(node: StablePtr) -> StablePtr {
+        unsafe { crate::mem::read_fixed_for_reference(Self::node_next_slot(node, 0)) }
+    }
+
+    #[inline]
+    fn write_next(&self, node: StablePtr, level: usize, mut next: StablePtr) {
+        unsafe { crate::mem::write_fixed(self.next_slot(node, level), &mut next) };
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Default
+    for SSkipListMap<K, V>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> AsFixedSizeBytes
+    for SSkipListMap<K, V>
+{
+    const SIZE: usize = u64::SIZE * 2 + u8::SIZE + u32::SIZE;
+    type Buf = [u8; u64::SIZE * 2 + u8::SIZE + u32::SIZE];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let mut i = 0;
+
+        self.head.as_fixed_size_bytes(&mut buf[i..i + u64::SIZE]);
+        i += u64::SIZE;
+        self.len.as_fixed_size_bytes(&mut buf[i..i + u64::SIZE]);
+        i += u64::SIZE;
+        (self.level as u8).as_fixed_size_bytes(&mut buf[i..i + u8::SIZE]);
+        i += u8::SIZE;
+        self.seed.as_fixed_size_bytes(&mut buf[i..i + u32::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let mut i = 0;
+
+        let head = u64::from_fixed_size_bytes(&buf[i..i + u64::SIZE]);
+        i += u64::SIZE;
+        let len = u64::from_fixed_size_bytes(&buf[i..i + u64::SIZE]);
+        i += u64::SIZE;
+        let level = u8::from_fixed_size_bytes(&buf[i..i + u8::SIZE]) as usize;
+        i += u8::SIZE;
+        let seed = u32::from_fixed_size_bytes(&buf[i..i + u32::SIZE]);
+
+        Self {
+            head,
+            len,
+            level,
+            seed,
+            stable_drop_flag: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> StableType
+    for SSkipListMap<K, V>
+{
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    #[inline]
+    unsafe fn stable_drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Drop
+    for SSkipListMap<K, V>
+{
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord + Debug, V: StableType + AsFixedSizeBytes + Debug> Debug
+    for SSkipListMap<K, V>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("{")?;
+        for (idx, (k, v)) in self.iter().enumerate() {
+            k.fmt(f)?;
+            f.write_str(": ")?;
+            v.fmt(f)?;
+
+            if (idx as u64) < self.len - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::skip_list_map::SSkipListMap;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut map = SSkipListMap::new();
+            let mut check = BTreeMap::new();
+
+            assert!(map.get(&0).is_none());
+            assert!(map.remove(&0).is_none());
+            assert!(map.first().is_none());
+
+            for i in 0..10_000u64 {
+                let key = rng.gen_range(0..2000u64);
+
+                if rng.gen_bool(0.6) {
+                    let prev = map
+                        .insert(key, SBox::new(i).unwrap())
+                        .unwrap()
+                        .map(|it| it.into_inner());
+
+                    assert_eq!(prev, check.insert(key, i));
+                } else {
+                    assert_eq!(
+                        map.remove(&key).map(|it| it.into_inner()),
+                        check.remove(&key)
+                    );
+                }
+
+                assert_eq!(map.len(), check.len() as u64);
+            }
+
+            for key in 0..2000u64 {
+                assert_eq!(map.get(&key).map(|it| **it), check.get(&key).copied());
+                assert_eq!(map.contains_key(&key), check.contains_key(&key));
+            }
+
+            let entries = map.iter().map(|(k, v)| (*k, **v)).collect::<Vec<_>>();
+            assert_eq!(entries, check.clone().into_iter().collect::<Vec<_>>());
+
+            let (first_key, _) = check.iter().next().unwrap();
+            assert_eq!(*map.first().unwrap().0, *first_key);
+
+            *map.get_mut(first_key).unwrap() = SBox::new(0).unwrap();
+            assert_eq!(**map.get(first_key).unwrap(), 0);
+
+            let mut vec = SVec::new();
+            vec.push(map).unwrap();
+
+            let mut map = vec.pop().unwrap();
+            assert_eq!(map.len(), check.len() as u64);
+
+            map.clear();
+            assert!(map.is_empty());
+            assert!(map.iter().next().is_none());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn borrowed_keys_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SSkipListMap::new();
+
+            for i in 0..100u64 {
+                let key = SBox::new(format!("key-{:03}", i)).unwrap();
+                map.insert(key, i).unwrap();
+            }
+
+            assert_eq!(*map.get(&String::from("key-042")).unwrap(), 42);
+            assert_eq!(map.remove(&String::from("key-042")), Some(42));
+            assert!(map.get(&String::from("key-042")).is_none());
+            assert_eq!(map.len(), 99);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,379 @@
+use crate::collections::vec::SVec;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use crate::{allocate, deallocate};
+use std::collections::HashSet;
+use std::marker::PhantomData;
+
+// each chunk is roughly one page of stable memory, but holds at least a single slot
+const CHUNK_SIZE_BYTES: u64 = 64 * 1024;
+
+/// Pool of fixed-size slots for values of a single type, carved out of big memory blocks
+///
+/// Memory blocks (chunks) are requested from the allocator once in a while, and then split into
+/// slots of `T::SIZE` bytes (but not less than 8 bytes). Unlike values allocated one by one, slots
+/// have no headers and don't touch the allocator's free-list, so both [SSlabPool::alloc] and
+/// [SSlabPool::free] are O(1). Freed slots are linked into an intrusive free-list and are reused
+/// by subsequent allocations. Useful as a node storage for custom linked data structures (trees,
+/// lists, graphs), with thousands of identical nodes.
+///
+/// Slots are addressed by raw [StablePtr]s, which never change. Chunks are only returned to the
+/// allocator, when the pool itself is stable-dropped.
+///
+/// `T` has to implement both [StableType] and [AsFixedSizeBytes]. [SSlabPool] itself implements
+/// these traits and can be nested inside other stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SSlabPool;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// // a singly linked list: a value and a pointer to the next node
+/// let mut nodes = SSlabPool::<(u64, u64)>::new();
+///
+/// let tail = nodes.alloc((2, u64::MAX)).expect("Out of memory");
+/// let head = nodes.alloc((1, tail)).expect("Out of memory");
+///
+/// let (value, next) = *unsafe { nodes.get(head) };
+/// assert_eq!(value, 1);
+/// assert_eq!(unsafe { nodes.get(next) }.0, 2);
+///
+/// assert_eq!(unsafe { nodes.free(head) }, (1, tail));
+/// assert_eq!(nodes.len(), 1);
+/// ```
+pub struct SSlabPool<T: StableType + AsFixedSizeBytes> {
+    chunks: SVec<StablePtr>,
+    free_head: StablePtr,
+    next_slot: StablePtr,
+    chunk_end: StablePtr,
+    len: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T: StableType + AsFixedSizeBytes> SSlabPool<T> {
+    /// Size of a single slot in bytes
+    pub const SLOT_SIZE: u64 = if T::SIZE < StablePtr::SIZE {
+        StablePtr::SIZE as u64
+    } else {
+        T::SIZE as u64
+    };
+
+    const SLOTS_PER_CHUNK: u64 = if Self::SLOT_SIZE >= CHUNK_SIZE_BYTES {
+        1
+    } else {
+        CHUNK_SIZE_BYTES / Self::SLOT_SIZE
+    };
+
+    /// Creates a new [SSlabPool]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            chunks: SVec::new(),
+            free_head: EMPTY_PTR,
+            next_slot: 0,
+            chunk_end: 0,
+            len: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of occupied slots
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if there are no occupied slots
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the total number of slots in chunks, allocated by this pool
+    #[inline]
+    pub fn capacity(&self) -> u64 {
+        self.chunks.len() * Self::SLOTS_PER_CHUNK
+    }
+
+    /// Puts the value into a free slot, returning a pointer to that slot
+    ///
+    /// Allocates a new chunk only if there are no free slots left. If the canister is out of stable
+    /// memory, returns [Err] with the value.
+    pub fn alloc(&mut self, mut value: T) -> Result<StablePtr, T> {
+        let ptr = if self.free_head != EMPTY_PTR {
+            let ptr = self.free_head;
+            self.free_head = unsafe { crate::mem::read_fixed_for_reference(ptr) };
+
+            ptr
+        } else {
+            if self.next_slot == self.chunk_end && self.grow().is_err() {
+                return Err(value);
+            }
+
+            let ptr = self.next_slot;
+            self.next_slot += Self::SLOT_SIZE;
+
+            ptr
+        };
+
+        unsafe { crate::mem::write_fixed(ptr, &mut value) };
+        self.len += 1;
+
+        Ok(ptr)
+    }
+
+    /// Removes the value from the slot, returning it, and makes the slot free
+    ///
+    /// # Safety
+    /// Make sure the pointer was returned by [SSlabPool::alloc] of this pool and the slot was not
+    /// freed since then. Otherwise the pool will get corrupted.
+    pub unsafe fn free(&mut self, ptr: StablePtr) -> T {
+        let value = crate::mem::read_fixed_for_move(ptr);
+
+        crate::mem::write_fixed(ptr, &mut self.free_head);
+        self.free_head = ptr;
+        self.len -= 1;
+
+        value
+    }
+
+    /// Returns an immutable reference [SRef] to the value in the slot
+    ///
+    /// # Safety
+    /// Same as for [SSlabPool::free].
+    #[inline]
+    pub unsafe fn get(&self, ptr: StablePtr) -> SRef<'_, T> {
+        SRef::new(ptr)
+    }
+
+    /// Returns a mutable reference [SRefMut] to the value in the slot
+    ///
+    /// # Safety
+    /// Same as for [SSlabPool::free].
+    #[inline]
+    pub unsafe fn get_mut(&mut self, ptr: StablePtr) -> SRefMut<'_, T> {
+        SRefMut::new(ptr)
+    }
+
+    fn grow(&mut self) -> Result<(), ()> {
+        let slice = unsafe { allocate(Self::SLOTS_PER_CHUNK * Self::SLOT_SIZE) }.map_err(|_| ())?;
+
+        if self.chunks.push(slice.as_ptr()).is_err() {
+            deallocate(slice);
+            return Err(());
+        }
+
+        self.next_slot = slice.offset(0);
+        self.chunk_end = self.next_slot + Self::SLOTS_PER_CHUNK * Self::SLOT_SIZE;
+
+        Ok(())
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Default for SSlabPool<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SSlabPool<T> {
+    const SIZE: usize = SVec::<StablePtr>::SIZE + u64::SIZE * 4;
+    type Buf = Vec<u8>;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let mut from = SVec::<StablePtr>::SIZE;
+
+        self.chunks.as_fixed_size_bytes(&mut buf[0..from]);
+
+        for it in [self.free_head, self.next_slot, self.chunk_end, self.len] {
+            it.as_fixed_size_bytes(&mut buf[from..(from + u64::SIZE)]);
+            from += u64::SIZE;
+        }
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let vec_size = SVec::<StablePtr>::SIZE;
+        let field = |idx: usize| {
+            let from = vec_size + idx * u64::SIZE;
+            u64::from_fixed_size_bytes(&buf[from..(from + u64::SIZE)])
+        };
+
+        Self {
+            chunks: SVec::from_fixed_size_bytes(&buf[0..vec_size]),
+            free_head: field(0),
+            next_slot: field(1),
+            chunk_end: field(2),
+            len: field(3),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> StableType for SSlabPool<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.chunks.stable_drop_flag_off();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.chunks.stable_drop_flag_on();
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.chunks.should_stable_drop()
+    }
+
+    // the vector of chunks is released by itself
+    unsafe fn stable_drop(&mut self) {
+        // values without a Drop implementation can't own any stable memory, no need to read them
+        if std::mem::needs_drop::<T>() && self.len > 0 {
+            let mut free_slots = HashSet::new();
+            let mut ptr = self.free_head;
+
+            while ptr != EMPTY_PTR {
+                free_slots.insert(ptr);
+                ptr = crate::mem::read_fixed_for_reference(ptr);
+            }
+
+            for chunk_ptr in self.chunks.iter() {
+                let from = SSlice::_offset(*chunk_ptr, 0);
+                let to = if self.chunk_end == from + Self::SLOTS_PER_CHUNK * Self::SLOT_SIZE {
+                    self.next_slot
+                } else {
+                    from + Self::SLOTS_PER_CHUNK * Self::SLOT_SIZE
+                };
+
+                for slot in (from..to).step_by(Self::SLOT_SIZE as usize) {
+                    if !free_slots.contains(&slot) {
+                        drop(crate::mem::read_fixed_for_move::<T>(slot));
+                    }
+                }
+            }
+        }
+
+        while let Some(chunk_ptr) = self.chunks.pop() {
+            deallocate(SSlice::from_ptr(chunk_ptr).unwrap());
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Drop for SSlabPool<T> {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::slab_pool::SSlabPool;
+    use crate::collections::SVec;
+    use crate::mem::StablePtr;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::utils::DebuglessUnwrap;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::seq::SliceRandom;
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut pool = SSlabPool::new();
+            let mut check: Vec<(StablePtr, u64)> = Vec::new();
+
+            for i in 0..10_000u64 {
+                if check.is_empty() || rng.gen_bool(0.7) {
+                    let ptr = pool.alloc(SBox::new(i).unwrap()).unwrap();
+                    check.push((ptr, i));
+                } else {
+                    let idx = rng.gen_range(0..check.len());
+                    let (ptr, value) = check.swap_remove(idx);
+
+                    assert_eq!(unsafe { pool.free(ptr) }.into_inner(), value);
+
+                    // the last freed slot is reused first
+                    let new_ptr = pool.alloc(SBox::new(value).unwrap()).unwrap();
+                    assert_eq!(new_ptr, ptr);
+                    check.push((new_ptr, value));
+
+                    let (ptr, _) = check.pop().unwrap();
+                    unsafe { pool.free(ptr) };
+                }
+            }
+
+            assert_eq!(pool.len(), check.len() as u64);
+            assert!(pool.capacity() >= pool.len());
+
+            check.shuffle(&mut rng);
+            for (ptr, value) in &check {
+                assert_eq!(**unsafe { pool.get(*ptr) }, *value);
+            }
+
+            let (ptr, _) = check[0];
+            *unsafe { pool.get_mut(ptr) } = SBox::new(u64::MAX).unwrap();
+            check[0].1 = u64::MAX;
+
+            let mut vec = SVec::new();
+            vec.push(pool).debugless_unwrap();
+
+            // occupied slots are stable-dropped along with the pool
+            let pool = vec.pop().unwrap();
+            for (ptr, value) in check.iter().take(100) {
+                assert_eq!(**unsafe { pool.get(*ptr) }, *value);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn small_and_big_values_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut small = SSlabPool::<u8>::new();
+            assert_eq!(SSlabPool::<u8>::SLOT_SIZE, 8);
+
+            let ptrs = (0..100u8)
+                .map(|it| small.alloc(it).unwrap())
+                .collect::<Vec<_>>();
+
+            for ptr in ptrs.iter().rev() {
+                unsafe { small.free(*ptr) };
+            }
+            assert!(small.is_empty());
+
+            let mut big = SSlabPool::<[u8; 100_000]>::new();
+            let a = big.alloc([1; 100_000]).unwrap();
+            let b = big.alloc([2; 100_000]).unwrap();
+
+            assert_eq!(big.capacity(), 2);
+            assert_eq!(unsafe { big.get(a) }[99_999], 1);
+            assert_eq!(unsafe { big.get(b) }[99_999], 2);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
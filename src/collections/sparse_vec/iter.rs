@@ -0,0 +1,53 @@
+use crate::collections::btree_map::iter::SBTreeMapIter;
+use crate::collections::sparse_vec::{SSparseVec, PAGE_LEN};
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+
+pub struct SSparseVecIter<'a, T> {
+    pages: SBTreeMapIter<'a, u64, StablePtr>,
+    page_idx: u64,
+    page_ptr: StablePtr,
+    // bits of the current page that are not visited yet
+    bitmap: u64,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T> SSparseVecIter<'a, T> {
+    #[inline]
+    pub(crate) fn new(pages: SBTreeMapIter<'a, u64, StablePtr>) -> Self {
+        Self {
+            pages,
+            page_idx: 0,
+            page_ptr: 0,
+            bitmap: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SSparseVecIter<'a, T> {
+    type Item = (u64, SRef<'a, T>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.bitmap == 0 {
+            let (page_idx, page_ptr) = self.pages.next()?;
+
+            self.page_idx = *page_idx;
+            self.page_ptr = *page_ptr;
+            self.bitmap = SSparseVec::<T>::read_bitmap(self.page_ptr);
+        }
+
+        let slot = self.bitmap.trailing_zeros() as u64;
+        self.bitmap &= self.bitmap - 1;
+
+        let ptr = SSparseVec::<T>::slot_ptr(self.page_ptr, slot);
+
+        unsafe { Some((self.page_idx * PAGE_LEN + slot, SRef::new(ptr))) }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> FusedIterator for SSparseVecIter<'a, T> {}
@@ -0,0 +1,424 @@
+use crate::collections::btree_map::SBTreeMap;
+use crate::collections::sparse_vec::iter::SSparseVecIter;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use crate::{allocate, deallocate, OutOfMemory};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+#[doc(hidden)]
+pub mod iter;
+
+/// Number of slots in a single page - one per bit of the page's bitmap
+pub(crate) const PAGE_LEN: u64 = u64::BITS as u64;
+
+/// Sparse vector, mapping arbitrary [u64] indices to values
+///
+/// Slots are grouped into pages of 64 consecutive indices. Each page is a single memory block,
+/// holding a bitmap of occupied slots and the slots themselves. Pages are only allocated when
+/// something is inserted into them and are deallocated as soon as their last element is removed,
+/// so big gaps between indices (e.g. a structure indexed by block height) cost no memory at all.
+/// Pages are indexed by an [SBTreeMap], so iteration happens in ascending order of indices.
+///
+/// Both [SSparseVec::get] and [SSparseVec::insert] are O(logN), where N is the number of pages.
+/// Elements that are close to each other share a page and are accessed with the same page lookup
+/// during iteration.
+///
+/// `T` has to implement both [StableType] and [AsFixedSizeBytes]. [SSparseVec] itself implements
+/// these traits and can be nested inside other stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SSparseVec;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut blocks = SSparseVec::<u64>::new();
+///
+/// blocks.insert(10, 100).expect("Out of memory");
+/// blocks.insert(1_000_000_000, 200).expect("Out of memory");
+///
+/// assert_eq!(*blocks.get(1_000_000_000).unwrap(), 200);
+/// assert!(blocks.get(11).is_none());
+///
+/// let indices: Vec<u64> = blocks.iter().map(|(idx, _)| idx).collect();
+/// assert_eq!(indices, vec![10, 1_000_000_000]);
+/// ```
+pub struct SSparseVec<T: StableType + AsFixedSizeBytes> {
+    pages: SBTreeMap<u64, StablePtr>,
+    len: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T: StableType + AsFixedSizeBytes> SSparseVec<T> {
+    /// Creates a new [SSparseVec]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            pages: SBTreeMap::new(),
+            len: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of elements stored in this [SSparseVec]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if there are no elements stored in this [SSparseVec]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Stores a value at the requested index, returning the previous value, if there was one
+    ///
+    /// May allocate a new page. If the canister is out of stable memory, returns [Err] with the
+    /// value that was about to get inserted.
+    pub fn insert(&mut self, idx: u64, mut value: T) -> Result<Option<T>, T> {
+        let (page_idx, slot) = Self::split_idx(idx);
+
+        let page_ptr = match self.pages.get(&page_idx) {
+            Some(ptr) => *ptr,
+            None => {
+                let ptr = match Self::allocate_page() {
+                    Ok(ptr) => ptr,
+                    Err(_) => return Err(value),
+                };
+
+                if self.pages.insert(page_idx, ptr).is_err() {
+                    deallocate(unsafe { SSlice::from_ptr(ptr).unwrap() });
+
+                    return Err(value);
+                }
+
+                ptr
+            }
+        };
+
+        let bitmap = Self::read_bitmap(page_ptr);
+        let slot_ptr = Self::slot_ptr(page_ptr, slot);
+
+        let prev = if bitmap & (1 << slot) != 0 {
+            Some(unsafe { crate::mem::read_fixed_for_move(slot_ptr) })
+        } else {
+            Self::write_bitmap(page_ptr, bitmap | (1 << slot));
+            self.len += 1;
+
+            None
+        };
+
+        unsafe { crate::mem::write_fixed(slot_ptr, &mut value) };
+
+        Ok(prev)
+    }
+
+    /// Returns an immutable reference [SRef] to the value stored at the requested index
+    ///
+    /// If there is no such value, returns [None].
+    #[inline]
+    pub fn get(&self, idx: u64) -> Option<SRef<'_, T>> {
+        let ptr = self.find_slot_ptr(idx)?;
+
+        unsafe { Some(SRef::new(ptr)) }
+    }
+
+    /// Returns a mutable reference [SRefMut] to the value stored at the requested index
+    ///
+    /// If there is no such value, returns [None].
+    #[inline]
+    pub fn get_mut(&mut self, idx: u64) -> Option<SRefMut<'_, T>> {
+        let ptr = self.find_slot_ptr(idx)?;
+
+        unsafe { Some(SRefMut::new(ptr)) }
+    }
+
+    /// Returns [true] if there is a value stored at the requested index
+    #[inline]
+    pub fn contains(&self, idx: u64) -> bool {
+        self.find_slot_ptr(idx).is_some()
+    }
+
+    /// Removes the value stored at the requested index and returns it
+    ///
+    /// If it was the last value of its page, the page gets deallocated. If there is no such value,
+    /// returns [None].
+    pub fn remove(&mut self, idx: u64) -> Option<T> {
+        let (page_idx, slot) = Self::split_idx(idx);
+        let page_ptr = *self.pages.get(&page_idx)?;
+
+        let bitmap = Self::read_bitmap(page_ptr);
+        if bitmap & (1 << slot) == 0 {
+            return None;
+        }
+
+        let it = unsafe { crate::mem::read_fixed_for_move(Self::slot_ptr(page_ptr, slot)) };
+        let bitmap = bitmap & !(1 << slot);
+
+        if bitmap == 0 {
+            self.pages.remove(&page_idx);
+            deallocate(unsafe { SSlice::from_ptr(page_ptr).unwrap() });
+        } else {
+            Self::write_bitmap(page_ptr, bitmap);
+        }
+
+        self.len -= 1;
+
+        Some(it)
+    }
+
+    /// Removes all values from this [SSparseVec], deallocating all pages
+    pub fn clear(&mut self) {
+        let page_idxs = self.pages.iter().map(|(k, _)| *k).collect::<Vec<_>>();
+
+        for page_idx in page_idxs {
+            let page_ptr = self.pages.remove(&page_idx).unwrap();
+            Self::destroy_page(page_ptr);
+        }
+
+        self.len = 0;
+    }
+
+    /// Returns an iterator over `(index, value)` pairs of this [SSparseVec] in ascending order of
+    /// indices
+    #[inline]
+    pub fn iter(&self) -> SSparseVecIter<'_, T> {
+        SSparseVecIter::new(self.pages.iter())
+    }
+
+    fn find_slot_ptr(&self, idx: u64) -> Option<StablePtr> {
+        let (page_idx, slot) = Self::split_idx(idx);
+        let page_ptr = *self.pages.get(&page_idx)?;
+
+        if Self::read_bitmap(page_ptr) & (1 << slot) == 0 {
+            return None;
+        }
+
+        Some(Self::slot_ptr(page_ptr, slot))
+    }
+
+    fn allocate_page() -> Result<StablePtr, OutOfMemory> {
+        let size = u64::SIZE as u64 + PAGE_LEN * T::SIZE as u64;
+        let ptr = unsafe { allocate(size)?.as_ptr() };
+
+        Self::write_bitmap(ptr, 0);
+
+        Ok(ptr)
+    }
+
+    fn destroy_page(page_ptr: StablePtr) {
+        let mut bitmap = Self::read_bitmap(page_ptr);
+
+        while bitmap != 0 {
+            let slot = bitmap.trailing_zeros() as u64;
+            bitmap &= bitmap - 1;
+
+            let _: T = unsafe { crate::mem::read_fixed_for_move(Self::slot_ptr(page_ptr, slot)) };
+        }
+
+        deallocate(unsafe { SSlice::from_ptr(page_ptr).unwrap() });
+    }
+
+    #[inline]
+    fn split_idx(idx: u64) -> (u64, u64) {
+        (idx / PAGE_LEN, idx % PAGE_LEN)
+    }
+
+    #[inline]
+    pub(crate) fn read_bitmap(page_ptr: StablePtr) -> u64 {
+        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(page_ptr, 0)) }
+    }
+
+    #[inline]
+    fn write_bitmap(page_ptr: StablePtr, mut bitmap: u64) {
+        unsafe { crate::mem::write_fixed(SSlice::_offset(page_ptr, 0), &mut bitmap) };
+    }
+
+    #[inline]
+    pub(crate) fn slot_ptr(page_ptr: StablePtr, slot: u64) -> StablePtr {
+        SSlice::_offset(page_ptr, u64::SIZE as u64 + slot * T::SIZE as u64)
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Default for SSparseVec<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SSparseVec<T> {
+    const SIZE: usize = SBTreeMap::<u64, StablePtr>::SIZE + u64::SIZE;
+    type Buf = Vec<u8>;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let pages_size = SBTreeMap::<u64, StablePtr>::SIZE;
+
+        self.pages.as_fixed_size_bytes(&mut buf[0..pages_size]);
+        self.len
+            .as_fixed_size_bytes(&mut buf[pages_size..Self::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let pages_size = SBTreeMap::<u64, StablePtr>::SIZE;
+
+        let pages = SBTreeMap::<u64, StablePtr>::from_fixed_size_bytes(&buf[0..pages_size]);
+        let len = u64::from_fixed_size_bytes(&buf[pages_size..Self::SIZE]);
+
+        Self {
+            pages,
+            len,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> StableType for SSparseVec<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.pages.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.pages.stable_drop_flag_off();
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.pages.should_stable_drop()
+    }
+
+    #[inline]
+    unsafe fn stable_drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Drop for SSparseVec<T> {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Debug> Debug for SSparseVec<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("{")?;
+        for (i, (idx, value)) in self.iter().enumerate() {
+            idx.fmt(f)?;
+            f.write_str(": ")?;
+            value.fmt(f)?;
+
+            if (i as u64) < self.len - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::sparse_vec::SSparseVec;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut vec = SSparseVec::new();
+            let mut check = BTreeMap::new();
+
+            assert!(vec.get(0).is_none());
+            assert!(vec.remove(0).is_none());
+
+            for i in 0..5000u64 {
+                // dense clusters with huge gaps between them
+                let idx = rng.gen_range(0..10u64) * 1_000_000_000 + rng.gen_range(0..200u64);
+
+                if rng.gen_bool(0.6) {
+                    let prev = vec
+                        .insert(idx, SBox::new(i).unwrap())
+                        .unwrap()
+                        .map(|it| it.into_inner());
+
+                    assert_eq!(prev, check.insert(idx, i));
+                } else {
+                    assert_eq!(
+                        vec.remove(idx).map(|it| it.into_inner()),
+                        check.remove(&idx)
+                    );
+                }
+
+                assert_eq!(vec.len(), check.len() as u64);
+            }
+
+            for (idx, value) in check.iter() {
+                assert_eq!(**vec.get(*idx).unwrap(), *value);
+            }
+
+            let entries = vec.iter().map(|(idx, v)| (idx, **v)).collect::<Vec<_>>();
+            assert_eq!(entries, check.clone().into_iter().collect::<Vec<_>>());
+
+            let mut svec = SVec::new();
+            svec.push(vec).unwrap();
+
+            let mut vec = svec.pop().unwrap();
+            let (first_idx, _) = check.iter().next().unwrap();
+            *vec.get_mut(*first_idx).unwrap() = SBox::new(0).unwrap();
+            assert_eq!(**vec.get(*first_idx).unwrap(), 0);
+
+            vec.clear();
+            assert!(vec.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn empty_pages_are_freed() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SSparseVec::<u64>::new();
+            vec.insert(0, 0).unwrap();
+            let one_page = get_allocated_size();
+
+            for i in 1..64u64 {
+                vec.insert(i, i).unwrap();
+            }
+            assert_eq!(get_allocated_size(), one_page);
+
+            vec.insert(u64::MAX, 1).unwrap();
+            assert!(get_allocated_size() > one_page);
+
+            assert_eq!(vec.remove(u64::MAX), Some(1));
+            assert_eq!(get_allocated_size(), one_page);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,616 @@
+use crate::collections::vec::SVec;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::StablePtr;
+use crate::primitive::{StableType, TryClone};
+use crate::OutOfMemory;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+// how many bytes are read from stable memory at once when comparing or formatting strings
+const CHUNK_SIZE: usize = 256;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Stable analog of [String]
+///
+/// Stores UTF-8 bytes directly in stable memory, in a single memory block, so appending or reading
+/// a part of a large text does not require decoding and re-encoding the whole of it, as it
+/// happens with `SBox<String>`.
+///
+/// Along with the bytes, [SString] keeps a 64-bit FNV-1a hash of its content, which is updated
+/// incrementally on each append. This makes inequality checks of strings of the same length
+/// O(1) in most cases - bytes are only compared when both lengths and hashes are equal.
+///
+/// Parts of the string can be accessed without copying with [SString::as_str] and
+/// [SString::substring], which return a lightweight [SStr] view.
+///
+/// Like [str], [SString] is indexed with [usize], so on 32-bit targets it can't be longer than
+/// [usize::MAX] bytes. Appending more bytes will panic.
+///
+/// [SString] implements both [StableType] and [AsFixedSizeBytes] and can be nested inside other
+/// stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SString;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut greeting = SString::new();
+///
+/// greeting.push_str("Hello, ").expect("Out of memory");
+/// greeting.push_str("world!").expect("Out of memory");
+///
+/// assert_eq!(greeting.len(), 13);
+/// assert_eq!(greeting.substring(7..12), "world");
+/// assert_eq!(greeting.to_string(), "Hello, world!");
+/// ```
+pub struct SString {
+    bytes: SVec<u8>,
+    hash: u64,
+}
+
+impl SString {
+    /// Creates an empty [SString]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            bytes: SVec::new(),
+            hash: FNV_OFFSET_BASIS,
+        }
+    }
+
+    /// Returns the length of this [SString] in bytes
+    #[inline]
+    pub fn len(&self) -> usize {
+        // never bigger, see SString::push_str
+        self.bytes.len() as usize
+    }
+
+    /// Returns [true] if this [SString] has a length of zero
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Appends the provided [str] to the end of this [SString]
+    ///
+    /// Reallocates at most once. Does not read the existing content.
+    ///
+    /// # Errors
+    /// Returns [OutOfMemory] if the canister is out of stable memory. In that case this [SString]
+    /// stays untouched.
+    ///
+    /// # Panics
+    /// Panics if the new length exceeds [usize::MAX].
+    pub fn push_str(&mut self, s: &str) -> Result<(), OutOfMemory> {
+        assert!(
+            self.len().checked_add(s.len()).is_some(),
+            "string is too long"
+        );
+
+        self.bytes.extend_from_slice(s.as_bytes())?;
+
+        for b in s.bytes() {
+            self.hash ^= b as u64;
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+
+        Ok(())
+    }
+
+    /// Appends the provided [char] to the end of this [SString]
+    ///
+    /// # Errors
+    /// Returns [OutOfMemory] if the canister is out of stable memory.
+    #[inline]
+    pub fn push(&mut self, c: char) -> Result<(), OutOfMemory> {
+        self.push_str(c.encode_utf8(&mut [0u8; 4]))
+    }
+
+    /// Returns an [SStr] view of the whole content of this [SString]
+    #[inline]
+    pub fn as_str(&self) -> SStr<'_> {
+        SStr::new(self.data_ptr(), self.len())
+    }
+
+    /// Returns an [SStr] view of the provided byte range of this [SString]
+    ///
+    /// Nothing is copied from stable memory by this method.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds or if any of its ends is not on a char boundary, the
+    /// same way as [str] indexing does.
+    #[inline]
+    pub fn substring<R: RangeBounds<usize>>(&self, range: R) -> SStr<'_> {
+        self.as_str().substring(range)
+    }
+
+    /// Returns [true] if the byte with the provided index is the first byte of some char
+    ///
+    /// Works the same way as [str::is_char_boundary].
+    #[inline]
+    pub fn is_char_boundary(&self, idx: usize) -> bool {
+        self.as_str().is_char_boundary(idx)
+    }
+
+    /// Copies the content of this [SString] into a heap [Vec] of bytes
+    #[inline]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.as_str().to_bytes()
+    }
+
+    /// Removes all the content of this [SString]
+    ///
+    /// Keeps the allocated memory block.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.hash = FNV_OFFSET_BASIS;
+    }
+
+    #[inline]
+    fn data_ptr(&self) -> StablePtr {
+        // an empty string is never read, so any pointer will do
+        self.bytes.get_element_ptr(0).unwrap_or_default()
+    }
+}
+
+impl TryFrom<&str> for SString {
+    type Error = OutOfMemory;
+
+    /// Creates a new [SString] with the same content as the provided [str]
+    ///
+    /// # Errors
+    /// Returns [OutOfMemory] if the canister is out of stable memory.
+    #[inline]
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut it = Self::new();
+        it.push_str(value)?;
+
+        Ok(it)
+    }
+}
+
+impl TryClone for SString {
+    /// Copies the content into a new [SString]
+    fn try_clone(&self) -> Result<Self, OutOfMemory> {
+        let mut bytes = SVec::new();
+        bytes.extend_from_slice(&self.to_bytes())?;
+
+        Ok(Self {
+            bytes,
+            hash: self.hash,
+        })
+    }
+}
+
+impl Default for SString {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for SString {
+    fn eq(&self, other: &Self) -> bool {
+        if self.len() != other.len() || self.hash != other.hash {
+            return false;
+        }
+
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for SString {}
+
+impl PartialEq<str> for SString {
+    #[inline]
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for SString {
+    #[inline]
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialOrd for SString {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SString {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(&other.as_str())
+    }
+}
+
+impl Hash for SString {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        self.hash.hash(state);
+    }
+}
+
+impl Display for SString {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.as_str(), f)
+    }
+}
+
+impl Debug for SString {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.as_str(), f)
+    }
+}
+
+impl AsFixedSizeBytes for SString {
+    const SIZE: usize = SVec::<u8>::SIZE + u64::SIZE;
+    type Buf = [u8; SVec::<u8>::SIZE + u64::SIZE];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.bytes
+            .as_fixed_size_bytes(&mut buf[0..SVec::<u8>::SIZE]);
+        self.hash
+            .as_fixed_size_bytes(&mut buf[SVec::<u8>::SIZE..Self::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let bytes = SVec::<u8>::from_fixed_size_bytes(&buf[0..SVec::<u8>::SIZE]);
+        let hash = u64::from_fixed_size_bytes(&buf[SVec::<u8>::SIZE..Self::SIZE]);
+
+        Self { bytes, hash }
+    }
+}
+
+impl StableType for SString {
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.bytes.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.bytes.stable_drop_flag_off();
+    }
+}
+
+/// Immutable view of a part of an [SString]
+///
+/// Does not hold any data by itself, only a pointer and a length, so it is cheap to create and to
+/// narrow down with [SStr::substring]. The content is read from stable memory lazily, in small
+/// chunks, when the view is compared or formatted.
+#[derive(Copy, Clone)]
+pub struct SStr<'a> {
+    ptr: StablePtr,
+    len: usize,
+    _marker: PhantomData<&'a SString>,
+}
+
+impl<'a> SStr<'a> {
+    #[inline]
+    fn new(ptr: StablePtr, len: usize) -> Self {
+        Self {
+            ptr,
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the length of this view in bytes
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns [true] if this view is empty
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a narrower view, see [SString::substring]
+    pub fn substring<R: RangeBounds<usize>>(&self, range: R) -> SStr<'a> {
+        let start = match range.start_bound() {
+            Bound::Included(s) => *s,
+            Bound::Excluded(s) => *s + 1,
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(e) => *e + 1,
+            Bound::Excluded(e) => *e,
+            Bound::Unbounded => self.len,
+        };
+
+        assert!(
+            start <= end && end <= self.len,
+            "byte range {}..{} is out of bounds of string of length {}",
+            start,
+            end,
+            self.len
+        );
+        assert!(
+            self.is_char_boundary(start) && self.is_char_boundary(end),
+            "byte range {}..{} is not on char boundaries",
+            start,
+            end
+        );
+
+        SStr::new(self.ptr + start as u64, end - start)
+    }
+
+    /// Returns [true] if the byte with the provided index is the first byte of some char
+    pub fn is_char_boundary(&self, idx: usize) -> bool {
+        if idx == 0 || idx == self.len {
+            return true;
+        }
+
+        if idx > self.len {
+            return false;
+        }
+
+        let b: u8 = unsafe { crate::mem::read_fixed_for_reference(self.ptr + idx as u64) };
+
+        // continuation bytes are 0b10xxxxxx
+        (b as i8) >= -0x40
+    }
+
+    /// Copies the content of this view into a heap [Vec] of bytes
+    #[inline]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; self.len];
+        if self.len > 0 {
+            unsafe { crate::mem::read_bytes(self.ptr, &mut buf) };
+        }
+
+        buf
+    }
+
+    /// Returns [true] if this view starts with the provided [str]
+    #[inline]
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        prefix.len() <= self.len && self.bytes_eq(0, prefix.as_bytes())
+    }
+
+    /// Returns [true] if this view ends with the provided [str]
+    #[inline]
+    pub fn ends_with(&self, suffix: &str) -> bool {
+        suffix.len() <= self.len && self.bytes_eq(self.len - suffix.len(), suffix.as_bytes())
+    }
+
+    fn bytes_eq(&self, offset: usize, other: &[u8]) -> bool {
+        let mut buf = [0u8; CHUNK_SIZE];
+
+        for (i, chunk) in other.chunks(CHUNK_SIZE).enumerate() {
+            let buf = &mut buf[..chunk.len()];
+            unsafe {
+                crate::mem::read_bytes(self.ptr + (offset + i * CHUNK_SIZE) as u64, buf);
+            }
+
+            if buf != chunk {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn cmp_chunked(&self, other: &SStr) -> Ordering {
+        let mut buf1 = [0u8; CHUNK_SIZE];
+        let mut buf2 = [0u8; CHUNK_SIZE];
+
+        let common = usize::min(self.len, other.len);
+        let mut offset = 0;
+
+        while offset < common {
+            let size = usize::min(CHUNK_SIZE, common - offset);
+
+            let buf1 = &mut buf1[..size];
+            let buf2 = &mut buf2[..size];
+
+            unsafe {
+                crate::mem::read_bytes(self.ptr + offset as u64, buf1);
+                crate::mem::read_bytes(other.ptr + offset as u64, buf2);
+            }
+
+            match (*buf1).cmp(buf2) {
+                Ordering::Equal => offset += size,
+                o => return o,
+            }
+        }
+
+        self.len.cmp(&other.len)
+    }
+}
+
+impl<'a, 'b> PartialEq<SStr<'b>> for SStr<'a> {
+    #[inline]
+    fn eq(&self, other: &SStr<'b>) -> bool {
+        self.len == other.len && self.cmp_chunked(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for SStr<'a> {}
+
+impl<'a> PartialEq<str> for SStr<'a> {
+    #[inline]
+    fn eq(&self, other: &str) -> bool {
+        self.len == other.len() && self.bytes_eq(0, other.as_bytes())
+    }
+}
+
+impl<'a> PartialEq<&str> for SStr<'a> {
+    #[inline]
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+impl<'a> PartialOrd for SStr<'a> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Ord for SStr<'a> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp_chunked(other)
+    }
+}
+
+impl<'a> Display for SStr<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // chunks may split chars, so the whole view is copied at once
+        let bytes = self.to_bytes();
+        let s = std::str::from_utf8(&bytes).map_err(|_| std::fmt::Error)?;
+
+        f.write_str(s)
+    }
+}
+
+impl<'a> Debug for SStr<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let bytes = self.to_bytes();
+        let s = std::str::from_utf8(&bytes).map_err(|_| std::fmt::Error)?;
+
+        Debug::fmt(s, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::string::SString;
+    use crate::collections::SVec;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::distributions::Alphanumeric;
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut s = SString::new();
+            let mut check = String::new();
+
+            assert!(s.is_empty());
+            assert_eq!(s, "");
+            assert_eq!(s.to_string(), "");
+
+            s.push_str("Привет, ").unwrap();
+            s.push('м').unwrap();
+            s.push_str("ир! Hello, world!").unwrap();
+
+            check.push_str("Привет, ");
+            check.push('м');
+            check.push_str("ир! Hello, world!");
+
+            assert_eq!(s.len(), check.len());
+            assert_eq!(s.to_string(), check);
+            assert_eq!(s, check.as_str());
+            assert_eq!(format!("{:?}", s), format!("{:?}", check));
+
+            assert_eq!(s.substring(0..12), &check[0..12]);
+            assert_eq!(s.substring(14..), &check[14..]);
+            assert_eq!(s.substring(..=1), &check[..=1]);
+            assert_eq!(s.substring(14..).substring(8..13), "Hello");
+
+            assert!(!s.is_char_boundary(1));
+            assert!(s.is_char_boundary(2));
+
+            assert!(s.as_str().starts_with("Привет"));
+            assert!(s.as_str().ends_with("world!"));
+            assert!(!s.as_str().ends_with("world?"));
+
+            let same = SString::try_from(check.as_str()).unwrap();
+            let other = SString::try_from(check.replace('H', "J").as_str()).unwrap();
+
+            assert_eq!(s, same);
+            assert_ne!(s, other);
+            assert!(s < other);
+
+            let mut vec = SVec::new();
+            vec.push(s).unwrap();
+
+            let mut s = vec.pop().unwrap();
+            assert_eq!(s, same);
+
+            s.clear();
+            assert!(s.is_empty());
+            assert_eq!(s, SString::new());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn substring_panics_on_char_boundary() {
+        stable::clear();
+        stable_memory_init();
+
+        let s = SString::try_from("Привет").unwrap();
+        s.substring(1..4);
+    }
+
+    #[test]
+    fn random_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut strings = Vec::new();
+            let mut checks = Vec::new();
+
+            for _ in 0..100 {
+                let len = rng.gen_range(0..2000);
+                let check = (&mut rng)
+                    .sample_iter(&Alphanumeric)
+                    .take(len)
+                    .map(char::from)
+                    .collect::<String>();
+
+                let mut s = SString::new();
+                for chunk in check.as_bytes().chunks(77) {
+                    s.push_str(std::str::from_utf8(chunk).unwrap()).unwrap();
+                }
+
+                strings.push(s);
+                checks.push(check);
+            }
+
+            for i in 0..strings.len() {
+                for j in 0..strings.len() {
+                    assert_eq!(strings[i] == strings[j], checks[i] == checks[j]);
+                    assert_eq!(strings[i].cmp(&strings[j]), checks[i].cmp(&checks[j]));
+                }
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::collections::trie::STrie;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+
+pub struct STrieIter<'a, V: StableType + AsFixedSizeBytes> {
+    // (node, key before this node's label)
+    stack: Vec<(StablePtr, Vec<u8>)>,
+    _marker: PhantomData<&'a STrie<V>>,
+}
+
+impl<'a, V: StableType + AsFixedSizeBytes> STrieIter<'a, V> {
+    pub(crate) fn new(start: Option<(StablePtr, Vec<u8>)>) -> Self {
+        Self {
+            stack: start.into_iter().collect(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, V: StableType + AsFixedSizeBytes> Iterator for STrieIter<'a, V> {
+    type Item = (Vec<u8>, SRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((ptr, mut key)) = self.stack.pop() {
+            let node = STrie::<V>::read_node(ptr);
+
+            if !node.label.is_empty() {
+                key.extend(
+                    node.label
+                        .chunks(node.label.len() as usize)
+                        .next()
+                        .unwrap()
+                        .iter(),
+                );
+            }
+
+            // pushing in reverse, so children are visited in lexicographic order
+            for i in (0..node.children.len()).rev() {
+                let child_ptr = node.children.get(i).unwrap().1;
+                self.stack.push((child_ptr, key.clone()));
+            }
+
+            if node.value.is_some() {
+                return Some((key, unsafe { SRef::new(STrie::<V>::value_ptr(ptr)) }));
+            }
+        }
+
+        None
+    }
+}
+
+impl<'a, V: StableType + AsFixedSizeBytes> FusedIterator for STrieIter<'a, V> {}
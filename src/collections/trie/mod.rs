@@ -0,0 +1,714 @@
+use crate::collections::trie::iter::STrieIter;
+use crate::collections::vec::SVec;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use crate::{allocate, deallocate, SSlice};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+#[doc(hidden)]
+pub mod iter;
+
+/// Radix tree (compressed trie), keyed by byte strings
+///
+/// Each node holds a part of the key (an edge label), an optional value and a list of children,
+/// sorted by the first byte of their labels. Chains of nodes without values and with a single
+/// child are always merged into a single node, so the depth of the tree is bounded by the number
+/// of keys sharing a prefix, not by the length of keys.
+///
+/// Works best for prefix queries, like username or domain search - [STrie::iter_prefix] only visits
+/// nodes that actually start with the prefix, while iteration happens in lexicographic order of keys.
+///
+/// `V` has to implement both [StableType] and [AsFixedSizeBytes]. [STrie] itself implements these
+/// traits and can be nested inside other stable data structures. Keys are not stored as a whole,
+/// so they can be of any length.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::STrie;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut users = STrie::<u64>::new();
+///
+/// users.insert(b"alice", 1).expect("Out of memory");
+/// users.insert(b"alex", 2).expect("Out of memory");
+/// users.insert(b"bob", 3).expect("Out of memory");
+///
+/// let found: Vec<(Vec<u8>, u64)> = users
+///     .iter_prefix(b"al")
+///     .map(|(key, id)| (key, *id))
+///     .collect();
+///
+/// assert_eq!(found, vec![(b"alex".to_vec(), 2), (b"alice".to_vec(), 1)]);
+/// ```
+pub struct STrie<V: StableType + AsFixedSizeBytes> {
+    root: StablePtr,
+    len: u64,
+    stable_drop_flag: bool,
+    _marker: PhantomData<V>,
+}
+
+impl<V: StableType + AsFixedSizeBytes> STrie<V> {
+    /// Creates a new [STrie]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            root: EMPTY_PTR,
+            len: 0,
+            stable_drop_flag: true,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of keys in this [STrie]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if there are no keys in this [STrie]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Inserts a new key-value pair, returning the previous value, if there was one
+    ///
+    /// May split an existing node into two. If the canister is out of stable memory, returns [Err]
+    /// with the value that was about to get inserted, leaving this [STrie] logically unchanged.
+    pub fn insert(&mut self, key: &[u8], value: V) -> Result<Option<V>, V> {
+        if self.root == EMPTY_PTR {
+            match Self::create_node(&[], None, SVec::new()) {
+                Ok(ptr) => self.root = ptr,
+                Err(_) => return Err(value),
+            }
+        }
+
+        let mut cur = self.root;
+        let mut rest = key;
+
+        loop {
+            let mut node = Self::read_node(cur);
+
+            if rest.is_empty() {
+                let prev = node.value.replace(value).map(|mut it| {
+                    unsafe { it.stable_drop_flag_on() };
+                    it
+                });
+                Self::write_node(cur, &mut node);
+
+                if prev.is_none() {
+                    self.len += 1;
+                }
+
+                return Ok(prev);
+            }
+
+            let idx = match node.children.binary_search_by(|it| it.0.cmp(&rest[0])) {
+                Ok(idx) => idx,
+                Err(idx) => {
+                    let leaf = match Self::create_node(rest, Some(value), SVec::new()) {
+                        Ok(ptr) => ptr,
+                        Err(value) => return Err(value.unwrap()),
+                    };
+
+                    if node.children.insert(idx, (rest[0], leaf)).is_err() {
+                        return Err(Self::destroy_node(leaf).unwrap());
+                    }
+
+                    Self::write_node(cur, &mut node);
+                    self.len += 1;
+
+                    return Ok(None);
+                }
+            };
+
+            let child_ptr = node.children.get(idx).unwrap().1;
+            let mut child = Self::read_node(child_ptr);
+            let label = label_bytes(&child.label);
+            let common = common_prefix_len(&label, rest);
+
+            if common < label.len() {
+                // splitting the child into two nodes
+                let mut new_label = SVec::new();
+                if new_label.extend_from_slice(&label[common..]).is_err() {
+                    return Err(value);
+                }
+
+                let mut mid_children = match SVec::new_with_capacity(2) {
+                    Ok(it) => it,
+                    Err(_) => return Err(value),
+                };
+                mid_children.push((label[common], child_ptr)).unwrap();
+
+                let mid = match Self::create_node(&label[..common], None, mid_children) {
+                    Ok(ptr) => ptr,
+                    Err(_) => return Err(value),
+                };
+
+                let mut old_label = std::mem::replace(&mut child.label, new_label);
+                unsafe { old_label.stable_drop_flag_on() };
+                Self::write_node(child_ptr, &mut child);
+
+                node.children.replace(idx, (rest[0], mid));
+                Self::write_node(cur, &mut node);
+
+                cur = mid;
+            } else {
+                cur = child_ptr;
+            }
+
+            rest = &rest[common..];
+        }
+    }
+
+    /// Returns an immutable reference [SRef] to a value stored by the key
+    ///
+    /// If no such key-value pair is found, returns [None]
+    #[inline]
+    pub fn get(&self, key: &[u8]) -> Option<SRef<'_, V>> {
+        let ptr = self.find_value_ptr(key)?;
+
+        unsafe { Some(SRef::new(ptr)) }
+    }
+
+    /// Returns a mutable reference [SRefMut] to a value stored by the key
+    ///
+    /// If no such key-value pair is found, returns [None]
+    #[inline]
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<SRefMut<'_, V>> {
+        let ptr = self.find_value_ptr(key)?;
+
+        unsafe { Some(SRefMut::new(ptr)) }
+    }
+
+    /// Returns [true] if there exists a value stored by the key
+    #[inline]
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.find_value_ptr(key).is_some()
+    }
+
+    /// Removes a key-value pair, returning the value, if there was one
+    ///
+    /// Nodes which become useless get deallocated or merged with their only child.
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        let (ptr, mut path) = self.find_node(key)?;
+
+        let mut node = Self::read_node(ptr);
+        let mut value = node.value.take()?;
+        unsafe { value.stable_drop_flag_on() };
+
+        Self::write_node(ptr, &mut node);
+        self.len -= 1;
+
+        if node.children.is_empty() && ptr != self.root {
+            let (parent, idx) = path.pop().unwrap();
+
+            let mut parent_node = Self::read_node(parent);
+            parent_node.children.remove(idx);
+            Self::write_node(parent, &mut parent_node);
+
+            Self::destroy_node(ptr);
+
+            self.maybe_merge(parent, path.last().copied());
+        } else {
+            self.maybe_merge(ptr, path.last().copied());
+        }
+
+        Some(value)
+    }
+
+    /// Removes all key-value pairs from this [STrie], deallocating all nodes
+    #[inline]
+    pub fn clear(&mut self) {
+        self.destroy_subtree(self.root);
+
+        self.root = EMPTY_PTR;
+        self.len = 0;
+    }
+
+    /// Returns an iterator over all key-value pairs with keys starting with the provided prefix,
+    /// in lexicographic order of keys
+    ///
+    /// Keys are reconstructed from labels of nodes and returned as owned [Vec]s.
+    pub fn iter_prefix(&self, prefix: &[u8]) -> STrieIter<'_, V> {
+        if self.root == EMPTY_PTR {
+            return STrieIter::new(None);
+        }
+
+        let mut cur = self.root;
+        let mut key_before = Vec::new();
+        let mut rest = prefix;
+
+        loop {
+            if rest.is_empty() {
+                return STrieIter::new(Some((cur, key_before)));
+            }
+
+            let node = Self::read_node(cur);
+            let mut key_through = key_before;
+            key_through.extend(label_bytes(&node.label));
+
+            let child_ptr = match node.children.binary_search_by(|it| it.0.cmp(&rest[0])) {
+                Ok(idx) => node.children.get(idx).unwrap().1,
+                Err(_) => return STrieIter::new(None),
+            };
+
+            let child_label = label_bytes(&Self::read_node(child_ptr).label);
+
+            if rest.len() <= child_label.len() {
+                return if child_label.starts_with(rest) {
+                    STrieIter::new(Some((child_ptr, key_through)))
+                } else {
+                    STrieIter::new(None)
+                };
+            }
+
+            if !rest.starts_with(&child_label) {
+                return STrieIter::new(None);
+            }
+
+            key_before = key_through;
+            cur = child_ptr;
+            rest = &rest[child_label.len()..];
+        }
+    }
+
+    /// Returns an iterator over all key-value pairs of this [STrie], in lexicographic order of keys
+    #[inline]
+    pub fn iter(&self) -> STrieIter<'_, V> {
+        self.iter_prefix(&[])
+    }
+
+    // returns a pointer to the node and a path to it - a list of (parent, child index) pairs
+    fn find_node(&self, key: &[u8]) -> Option<(StablePtr, Vec<(StablePtr, u64)>)> {
+        if self.root == EMPTY_PTR {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        let mut cur = self.root;
+        let mut rest = key;
+
+        while !rest.is_empty() {
+            let node = Self::read_node(cur);
+            let idx = node
+                .children
+                .binary_search_by(|it| it.0.cmp(&rest[0]))
+                .ok()?;
+
+            let child_ptr = node.children.get(idx).unwrap().1;
+            let label = label_bytes(&Self::read_node(child_ptr).label);
+
+            if !rest.starts_with(&label) {
+                return None;
+            }
+
+            path.push((cur, idx));
+            cur = child_ptr;
+            rest = &rest[label.len()..];
+        }
+
+        Some((cur, path))
+    }
+
+    fn find_value_ptr(&self, key: &[u8]) -> Option<StablePtr> {
+        let (ptr, _) = self.find_node(key)?;
+
+        if Self::read_node(ptr).value.is_some() {
+            Some(Self::value_ptr(ptr))
+        } else {
+            None
+        }
+    }
+
+    // merges a node without a value and with a single child into that child
+    fn maybe_merge(&mut self, ptr: StablePtr, parent: Option<(StablePtr, u64)>) {
+        let (parent, idx) = match parent {
+            Some(it) if ptr != self.root => it,
+            _ => return,
+        };
+
+        let node = Self::read_node(ptr);
+        if node.value.is_some() || node.children.len() != 1 {
+            return;
+        }
+
+        let child_ptr = node.children.get(0).unwrap().1;
+        let mut child = Self::read_node(child_ptr);
+
+        let mut merged_label = label_bytes(&node.label);
+        merged_label.extend(label_bytes(&child.label));
+
+        // merging is optional - if there is not enough memory, the tree simply stays uncompressed
+        let mut new_label = SVec::new();
+        if new_label.extend_from_slice(&merged_label).is_err() {
+            return;
+        }
+
+        let mut old_label = std::mem::replace(&mut child.label, new_label);
+        unsafe { old_label.stable_drop_flag_on() };
+        Self::write_node(child_ptr, &mut child);
+
+        let mut parent_node = Self::read_node(parent);
+        parent_node
+            .children
+            .replace(idx, (merged_label[0], child_ptr));
+        Self::write_node(parent, &mut parent_node);
+
+        Self::destroy_node(ptr);
+    }
+
+    fn create_node(
+        label: &[u8],
+        value: Option<V>,
+        children: SVec<(u8, u64)>,
+    ) -> Result<StablePtr, Option<V>> {
+        let mut label_vec = SVec::new();
+        if label_vec.extend_from_slice(label).is_err() {
+            return Err(value);
+        }
+
+        let ptr = match unsafe { allocate(TrieNode::<V>::SIZE as u64) } {
+            Ok(slice) => slice.as_ptr(),
+            Err(_) => return Err(value),
+        };
+
+        let mut node = TrieNode {
+            value,
+            label: label_vec,
+            children,
+        };
+        Self::write_node(ptr, &mut node);
+
+        Ok(ptr)
+    }
+
+    // deallocates the node itself (but not its children), returning its value
+    fn destroy_node(ptr: StablePtr) -> Option<V> {
+        let node: TrieNode<V> = unsafe { crate::mem::read_fixed_for_move(SSlice::_offset(ptr, 0)) };
+        deallocate(unsafe { SSlice::from_ptr(ptr).unwrap() });
+
+        node.value
+    }
+
+    fn destroy_subtree(&mut self, root: StablePtr) {
+        if root == EMPTY_PTR {
+            return;
+        }
+
+        let mut stack = vec![root];
+
+        while let Some(ptr) = stack.pop() {
+            let node = Self::read_node(ptr);
+            stack.extend(node.children.iter().map(|it| it.1));
+
+            Self::destroy_node(ptr);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn read_node(ptr: StablePtr) -> TrieNode<V> {
+        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(ptr, 0)) }
+    }
+
+    #[inline]
+    fn write_node(ptr: StablePtr, node: &mut TrieNode<V>) {
+        unsafe { crate::mem::write_fixed(SSlice::_offset(ptr, 0), node) };
+    }
+
+    #[inline]
+    pub(crate) fn value_ptr(ptr: StablePtr) -> StablePtr {
+        // skipping the option flag
+        SSlice::_offset(ptr, 1)
+    }
+}
+
+pub(crate) struct TrieNode<V> {
+    pub(crate) value: Option<V>,
+    pub(crate) label: SVec<u8>,
+    pub(crate) children: SVec<(u8, u64)>,
+}
+
+impl<V: StableType + AsFixedSizeBytes> AsFixedSizeBytes for TrieNode<V> {
+    const SIZE: usize = Option::<V>::SIZE + SVec::<u8>::SIZE + SVec::<(u8, u64)>::SIZE;
+    type Buf = Vec<u8>;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let label_offset = Option::<V>::SIZE;
+        let children_offset = label_offset + SVec::<u8>::SIZE;
+
+        self.value.as_fixed_size_bytes(&mut buf[0..label_offset]);
+        self.label
+            .as_fixed_size_bytes(&mut buf[label_offset..children_offset]);
+        self.children
+            .as_fixed_size_bytes(&mut buf[children_offset..Self::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let label_offset = Option::<V>::SIZE;
+        let children_offset = label_offset + SVec::<u8>::SIZE;
+
+        Self {
+            value: Option::<V>::from_fixed_size_bytes(&buf[0..label_offset]),
+            label: SVec::<u8>::from_fixed_size_bytes(&buf[label_offset..children_offset]),
+            children: SVec::<(u8, u64)>::from_fixed_size_bytes(&buf[children_offset..Self::SIZE]),
+        }
+    }
+}
+
+impl<V: StableType + AsFixedSizeBytes> StableType for TrieNode<V> {
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.value.stable_drop_flag_on();
+        self.label.stable_drop_flag_on();
+        self.children.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.value.stable_drop_flag_off();
+        self.label.stable_drop_flag_off();
+        self.children.stable_drop_flag_off();
+    }
+}
+
+fn label_bytes(label: &SVec<u8>) -> Vec<u8> {
+    if label.is_empty() {
+        return Vec::new();
+    }
+
+    label.chunks(label.len() as usize).next().unwrap().to_vec()
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
+}
+
+impl<V: StableType + AsFixedSizeBytes> Default for STrie<V> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: StableType + AsFixedSizeBytes> AsFixedSizeBytes for STrie<V> {
+    const SIZE: usize = u64::SIZE * 2;
+    type Buf = [u8; u64::SIZE * 2];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.root.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+        self.len
+            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE * 2)]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let root = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
+        let len = u64::from_fixed_size_bytes(&buf[u64::SIZE..(u64::SIZE * 2)]);
+
+        Self {
+            root,
+            len,
+            stable_drop_flag: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<V: StableType + AsFixedSizeBytes> StableType for STrie<V> {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    #[inline]
+    unsafe fn stable_drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<V: StableType + AsFixedSizeBytes> Drop for STrie<V> {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+impl<V: StableType + AsFixedSizeBytes + Debug> Debug for STrie<V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("{")?;
+        for (idx, (k, v)) in self.iter().enumerate() {
+            k.fmt(f)?;
+            f.write_str(": ")?;
+            v.fmt(f)?;
+
+            if (idx as u64) < self.len - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::trie::STrie;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::utils::test::generate_random_string;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn basic_flow_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut trie = STrie::new();
+            assert!(trie.get(b"").is_none());
+            assert!(trie.remove(b"a").is_none());
+
+            assert!(trie.insert(b"romane", 1u64).unwrap().is_none());
+            assert!(trie.insert(b"romanus", 2).unwrap().is_none());
+            assert!(trie.insert(b"romulus", 3).unwrap().is_none());
+            assert!(trie.insert(b"rubens", 4).unwrap().is_none());
+            assert!(trie.insert(b"ruber", 5).unwrap().is_none());
+            assert!(trie.insert(b"rom", 6).unwrap().is_none());
+            assert!(trie.insert(b"", 7).unwrap().is_none());
+            assert_eq!(trie.insert(b"ruber", 8).unwrap(), Some(5));
+
+            assert_eq!(trie.len(), 7);
+            assert_eq!(*trie.get(b"rom").unwrap(), 6);
+            assert_eq!(*trie.get(b"").unwrap(), 7);
+            assert!(trie.get(b"ro").is_none());
+            assert!(trie.get(b"romanes").is_none());
+            assert!(!trie.contains_key(b"rub"));
+
+            *trie.get_mut(b"rubens").unwrap() = 40;
+
+            let keys = trie
+                .iter_prefix(b"rom")
+                .map(|(k, _)| String::from_utf8(k).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(keys, vec!["rom", "romane", "romanus", "romulus"]);
+
+            let entries = trie
+                .iter_prefix(b"rub")
+                .map(|(k, v)| (k, *v))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                entries,
+                vec![(b"rubens".to_vec(), 40), (b"ruber".to_vec(), 8)]
+            );
+
+            assert_eq!(trie.iter_prefix(b"ro").count(), 4);
+            assert_eq!(trie.iter_prefix(b"romanu").count(), 1);
+            assert_eq!(trie.iter_prefix(b"x").count(), 0);
+            assert_eq!(trie.iter_prefix(b"rubix").count(), 0);
+            assert_eq!(trie.iter().count(), 7);
+
+            assert_eq!(trie.remove(b"rom"), Some(6));
+            assert_eq!(trie.remove(b"romane"), Some(1));
+            assert!(trie.remove(b"romane").is_none());
+            assert_eq!(trie.len(), 5);
+            assert_eq!(*trie.get(b"romanus").unwrap(), 2);
+
+            let mut vec = SVec::new();
+            vec.push(trie).unwrap();
+
+            let mut trie = vec.pop().unwrap();
+            assert_eq!(*trie.get(b"romulus").unwrap(), 3);
+
+            trie.clear();
+            assert!(trie.is_empty());
+            assert!(trie.get(b"romulus").is_none());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn random_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut trie = STrie::new();
+            let mut check = BTreeMap::new();
+
+            let mut keys = Vec::new();
+            for _ in 0..300 {
+                let base = generate_random_string(&mut rng);
+                let len = rng.gen_range(0..=base.len().min(6));
+
+                keys.push(base.as_bytes()[..len].to_vec());
+            }
+
+            for i in 0..3000u64 {
+                let key = keys[rng.gen_range(0..keys.len())].clone();
+
+                if rng.gen_bool(0.6) {
+                    let prev = trie
+                        .insert(&key, SBox::new(i).unwrap())
+                        .unwrap()
+                        .map(|it| it.into_inner());
+
+                    assert_eq!(prev, check.insert(key, i));
+                } else {
+                    assert_eq!(
+                        trie.remove(&key).map(|it| it.into_inner()),
+                        check.remove(&key)
+                    );
+                }
+
+                assert_eq!(trie.len(), check.len() as u64);
+            }
+
+            let entries = trie.iter().map(|(k, v)| (k, **v)).collect::<Vec<_>>();
+            assert_eq!(entries, check.clone().into_iter().collect::<Vec<_>>());
+
+            for key in keys.iter().take(50) {
+                for len in 0..=key.len() {
+                    let prefix = &key[..len];
+
+                    let actual = trie.iter_prefix(prefix).map(|(k, _)| k).collect::<Vec<_>>();
+                    let expected = check
+                        .keys()
+                        .filter(|k| k.starts_with(prefix))
+                        .cloned()
+                        .collect::<Vec<_>>();
+
+                    assert_eq!(actual, expected);
+                }
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
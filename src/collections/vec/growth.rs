@@ -0,0 +1,78 @@
+//! Reallocation growth policies for [SVec](crate::collections::SVec).
+//!
+//! Each policy is a zero-sized type, which is a part of [SVec](crate::collections::SVec)'s type.
+//! This way the policy does not occupy any stable memory and is automatically preserved between
+//! canister upgrades.
+
+/// Defines how the capacity of [SVec](crate::collections::SVec) grows, when it runs out of it
+///
+/// If the returned capacity is not enough to fit new elements, the function is called again with
+/// the result of the previous call. If the returned capacity is not greater than the current one,
+/// the capacity is increased by one element.
+pub trait GrowthPolicy {
+    /// Returns the next capacity for a collection of the provided capacity
+    fn next_capacity(cur_capacity: u64) -> u64;
+}
+
+/// Doubles the capacity on each reallocation
+///
+/// This is the default policy. It makes pushes amortized O(1), but can waste up to a half of
+/// allocated stable memory.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DoubleGrowth;
+
+impl GrowthPolicy for DoubleGrowth {
+    #[inline]
+    fn next_capacity(cur_capacity: u64) -> u64 {
+        cur_capacity.saturating_mul(2)
+    }
+}
+
+/// Multiplies the capacity by `NUMERATOR / DENOMINATOR` on each reallocation
+///
+/// For example, `FactorGrowth<3, 2>` grows the capacity by 50% each time. `NUMERATOR` has to be
+/// greater than `DENOMINATOR` and `DENOMINATOR` has to be non-zero, which is checked at compile
+/// time:
+/// ```compile_fail
+/// # use ic_stable_memory::collections::{SVec, FactorGrowth};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # ic_stable_memory::stable_memory_init();
+/// let mut vec = SVec::<u64, _>::with_growth_policy(FactorGrowth::<1, 1>);
+/// vec.push(10).expect("out of memory");
+/// ```
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FactorGrowth<const NUMERATOR: usize, const DENOMINATOR: usize>;
+
+impl<const NUMERATOR: usize, const DENOMINATOR: usize> FactorGrowth<NUMERATOR, DENOMINATOR> {
+    const VALID: () = assert!(
+        NUMERATOR > DENOMINATOR && DENOMINATOR > 0,
+        "FactorGrowth requires NUMERATOR > DENOMINATOR > 0"
+    );
+}
+
+impl<const NUMERATOR: usize, const DENOMINATOR: usize> GrowthPolicy
+    for FactorGrowth<NUMERATOR, DENOMINATOR>
+{
+    #[inline]
+    fn next_capacity(cur_capacity: u64) -> u64 {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::VALID;
+
+        cur_capacity.saturating_mul(NUMERATOR as u64) / DENOMINATOR as u64
+    }
+}
+
+/// Increases the capacity by a fixed number of elements on each reallocation
+///
+/// Wastes at most `CHUNK` elements of stable memory, which makes it a good fit for huge
+/// append-mostly collections. Pushes are no longer amortized O(1), since each reallocation may
+/// have to move the data.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ChunkGrowth<const CHUNK: usize>;
+
+impl<const CHUNK: usize> GrowthPolicy for ChunkGrowth<CHUNK> {
+    #[inline]
+    fn next_capacity(cur_capacity: u64) -> u64 {
+        cur_capacity.saturating_add(CHUNK as u64)
+    }
+}
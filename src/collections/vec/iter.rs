@@ -1,19 +1,22 @@
-use crate::collections::vec::SVec;
+use crate::collections::vec::{GrowthPolicy, SVec};
 use crate::encoding::AsFixedSizeBytes;
 use crate::primitive::s_ref::SRef;
 use crate::primitive::StableType;
 use crate::SSlice;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::ops::Deref;
 
-pub struct SVecIter<'a, T: StableType + AsFixedSizeBytes> {
-    svec: &'a SVec<T>,
-    offset: usize,
-    max_offset: usize,
+pub struct SVecIter<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> {
+    svec: &'a SVec<T, G>,
+    offset: u64,
+    max_offset: u64,
 }
 
-impl<'a, T: AsFixedSizeBytes + StableType> SVecIter<'a, T> {
-    pub(crate) fn new(svec: &'a SVec<T>) -> Self {
+impl<'a, T: AsFixedSizeBytes + StableType, G: GrowthPolicy> SVecIter<'a, T, G> {
+    pub(crate) fn new(svec: &'a SVec<T, G>) -> Self {
         let offset = 0;
-        let max_offset = svec.len() * T::SIZE;
+        let max_offset = svec.len() * T::SIZE as u64;
 
         Self {
             svec,
@@ -23,7 +26,7 @@ impl<'a, T: AsFixedSizeBytes + StableType> SVecIter<'a, T> {
     }
 }
 
-impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SVecIter<'a, T> {
+impl<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> Iterator for SVecIter<'a, T, G> {
     type Item = SRef<'a, T>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -31,9 +34,300 @@ impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SVecIter<'a, T> {
             return None;
         }
 
-        let ptr = SSlice::_offset(self.svec.ptr, self.offset as u64);
-        self.offset += T::SIZE;
+        let ptr = SSlice::_offset(self.svec.ptr, self.offset);
+        self.offset += T::SIZE as u64;
 
-        unsafe { Some(SRef::new(ptr)) }
+        unsafe { Some(SRef::new_in(self.svec.ptr, ptr)) }
+    }
+}
+
+/// A batch of consecutive [SVec] elements, read from stable memory with a single read
+///
+/// Immutable access to the elements is provided by dereferencing into a slice.
+pub struct SVecChunk<'a, T: StableType + AsFixedSizeBytes> {
+    elems: Vec<T>,
+    _marker: PhantomData<&'a T>,
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> SVecChunk<'a, T> {
+    fn read<G: GrowthPolicy>(svec: &'a SVec<T, G>, idx: u64, len: usize) -> Self {
+        let ptr = SSlice::_offset(svec.ptr, idx * T::SIZE as u64);
+
+        let mut buf = vec![0u8; len * T::SIZE];
+        unsafe { crate::mem::read_bytes(ptr, &mut buf) };
+
+        Self::decode(&buf)
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        let elems = buf
+            .chunks_exact(T::SIZE)
+            .map(|b| {
+                let mut it = T::from_fixed_size_bytes(b);
+                unsafe { it.stable_drop_flag_off() };
+
+                it
+            })
+            .collect();
+
+        Self {
+            elems,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> Deref for SVecChunk<'a, T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.elems
+    }
+}
+
+pub struct SVecChunks<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> {
+    svec: &'a SVec<T, G>,
+    idx: u64,
+    chunk_size: usize,
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> SVecChunks<'a, T, G> {
+    pub(crate) fn new(svec: &'a SVec<T, G>, chunk_size: usize) -> Self {
+        assert!(chunk_size != 0, "chunk size must be non-zero");
+
+        Self {
+            svec,
+            idx: 0,
+            chunk_size,
+        }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> Iterator for SVecChunks<'a, T, G> {
+    type Item = SVecChunk<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.idx == self.svec.len() {
+            return None;
+        }
+
+        let len = u64::min(self.chunk_size as u64, self.svec.len() - self.idx) as usize;
+        let chunk = SVecChunk::read(self.svec, self.idx, len);
+
+        self.idx += len as u64;
+
+        Some(chunk)
+    }
+}
+
+pub struct SVecWindows<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> {
+    svec: &'a SVec<T, G>,
+    idx: u64,
+    window_size: usize,
+    // bytes of the previous window, so only one new element is read on each step
+    buf: Vec<u8>,
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> SVecWindows<'a, T, G> {
+    pub(crate) fn new(svec: &'a SVec<T, G>, window_size: usize) -> Self {
+        assert!(window_size != 0, "window size must be non-zero");
+
+        Self {
+            svec,
+            idx: 0,
+            window_size,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> Iterator for SVecWindows<'a, T, G> {
+    type Item = SVecChunk<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.window_size as u64 > self.svec.len() - self.idx {
+            return None;
+        }
+
+        if self.buf.is_empty() {
+            self.buf = vec![0u8; self.window_size * T::SIZE];
+
+            let ptr = SSlice::_offset(self.svec.ptr, self.idx * T::SIZE as u64);
+            unsafe { crate::mem::read_bytes(ptr, &mut self.buf) };
+        } else {
+            self.buf.drain(..T::SIZE);
+
+            let last_idx = self.idx + self.window_size as u64 - 1;
+            let ptr = SSlice::_offset(self.svec.ptr, last_idx * T::SIZE as u64);
+
+            let len = self.buf.len();
+            self.buf.resize(len + T::SIZE, 0);
+            unsafe { crate::mem::read_bytes(ptr, &mut self.buf[len..]) };
+        }
+
+        self.idx += 1;
+
+        Some(SVecChunk::decode(&self.buf))
+    }
+}
+
+pub struct SVecDrain<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> {
+    svec: &'a mut SVec<T, G>,
+    idx: u64,
+    end_idx: u64,
+    tail_idx: u64,
+    tail_len: u64,
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> SVecDrain<'a, T, G> {
+    pub(crate) fn new(svec: &'a mut SVec<T, G>, start: u64, end: u64) -> Self {
+        let tail_len = svec.len() - end;
+
+        // while draining, the vector only "owns" elements before the drained range
+        svec.len = start;
+
+        Self {
+            svec,
+            idx: start,
+            end_idx: end,
+            tail_idx: end,
+            tail_len,
+        }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> Iterator for SVecDrain<'a, T, G> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.idx == self.end_idx {
+            return None;
+        }
+
+        let ptr = SSlice::_offset(self.svec.ptr, self.idx * T::SIZE as u64);
+        self.idx += 1;
+
+        unsafe { Some(crate::mem::read_fixed_for_move(ptr)) }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        size_hint(self.end_idx - self.idx)
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> DoubleEndedIterator
+    for SVecDrain<'a, T, G>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.idx == self.end_idx {
+            return None;
+        }
+
+        self.end_idx -= 1;
+        let ptr = SSlice::_offset(self.svec.ptr, self.end_idx * T::SIZE as u64);
+
+        unsafe { Some(crate::mem::read_fixed_for_move(ptr)) }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> ExactSizeIterator
+    for SVecDrain<'a, T, G>
+{
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> FusedIterator for SVecDrain<'a, T, G> {}
+
+impl<'a, T: StableType + AsFixedSizeBytes, G: GrowthPolicy> Drop for SVecDrain<'a, T, G> {
+    fn drop(&mut self) {
+        // stable-dropping elements that were not consumed
+        for _ in self.by_ref() {}
+
+        let start = self.svec.len;
+
+        if self.tail_len > 0 && self.tail_idx != start {
+            let from = SSlice::_offset(self.svec.ptr, self.tail_idx * T::SIZE as u64);
+            let to = SSlice::_offset(self.svec.ptr, start * T::SIZE as u64);
+
+            let len = self.tail_len * T::SIZE as u64;
+            unsafe { crate::mem::move_bytes(from, to, len) };
+        }
+
+        self.svec.len = start + self.tail_len;
+    }
+}
+
+pub struct SVecIntoIter<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> {
+    svec: SVec<T, G>,
+    idx: u64,
+    end_idx: u64,
+}
+
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> SVecIntoIter<T, G> {
+    pub(crate) fn new(svec: SVec<T, G>) -> Self {
+        let end_idx = svec.len();
+
+        Self {
+            svec,
+            idx: 0,
+            end_idx,
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> Iterator for SVecIntoIter<T, G> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.idx == self.end_idx {
+            return None;
+        }
+
+        let ptr = SSlice::_offset(self.svec.ptr, self.idx * T::SIZE as u64);
+        self.idx += 1;
+
+        unsafe { Some(crate::mem::read_fixed_for_move(ptr)) }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        size_hint(self.end_idx - self.idx)
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> DoubleEndedIterator for SVecIntoIter<T, G> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.idx == self.end_idx {
+            return None;
+        }
+
+        self.end_idx -= 1;
+        let ptr = SSlice::_offset(self.svec.ptr, self.end_idx * T::SIZE as u64);
+
+        unsafe { Some(crate::mem::read_fixed_for_move(ptr)) }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> ExactSizeIterator for SVecIntoIter<T, G> {}
+
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> FusedIterator for SVecIntoIter<T, G> {}
+
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> Drop for SVecIntoIter<T, G> {
+    fn drop(&mut self) {
+        // stable-dropping elements that were not consumed
+        for _ in self.by_ref() {}
+
+        // all elements are moved out - the vector will only release its memory block
+        self.svec.len = 0;
+    }
+}
+
+// the length of a vector may not fit into usize on 32-bit targets
+#[inline]
+pub(crate) fn size_hint(len: u64) -> (usize, Option<usize>) {
+    match usize::try_from(len) {
+        Ok(it) => (it, Some(it)),
+        Err(_) => (usize::MAX, None),
     }
 }
@@ -1,38 +1,52 @@
-use crate::collections::vec::iter::SVecIter;
+use crate::collections::vec::growth::{DoubleGrowth, GrowthPolicy};
+use crate::collections::vec::iter::{SVecChunks, SVecDrain, SVecIntoIter, SVecIter, SVecWindows};
+use crate::encoding::counters::{decode_header, encode_header, header_size};
 use crate::encoding::{AsFixedSizeBytes, Buffer};
 use crate::mem::allocator::EMPTY_PTR;
 use crate::mem::s_slice::SSlice;
 use crate::mem::StablePtr;
 use crate::primitive::s_ref::SRef;
 use crate::primitive::s_ref_mut::SRefMut;
-use crate::primitive::StableType;
+use crate::primitive::{StableType, TryClone, TryFromIterator};
+use crate::utils::math::max_elements;
 use crate::{allocate, deallocate, reallocate, OutOfMemory};
+use candid::types::{Compound, Type};
+use candid::CandidType;
+use serde::ser::SerializeSeq;
+use serde::{Serialize, Serializer};
 use std::cmp::Ordering;
 use std::fmt::{Debug, Formatter};
 use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
 
+pub mod growth;
 #[doc(hidden)]
 pub mod iter;
 
-const DEFAULT_CAPACITY: usize = 4;
+const DEFAULT_CAPACITY: u64 = 4;
 
 /// Stable analog of [Vec]
 ///
 /// May reallocate on inserts. In this case will copy the underlying data to a new location.
 ///
-/// This is a "finite" data structure, it can only holp up to [u32::MAX] / `T::SIZE` elements. Putting
-/// more elements inside will panic.
+/// This is a "finite" data structure, it can only hold up to `1TB / T::SIZE` elements. Putting more
+/// elements inside will panic.
 ///
 /// `T` has to implement both [StableType] and [AsFixedSizeBytes]. [SVec] itself implements these
 /// traits and can be nested inside other stable data structures.
 ///
 /// When [SVec] is stable-dropped, its elements are also stable-dropped but in reverse order.
-pub struct SVec<T: StableType + AsFixedSizeBytes> {
+///
+/// `G` is a [GrowthPolicy] that defines how the capacity grows when the [SVec] is full. By default
+/// the capacity is doubled. Since the policy is a part of the type, it is persisted together with
+/// the [SVec] between canister upgrades. See [SVec::with_growth_policy].
+pub struct SVec<T: StableType + AsFixedSizeBytes, G: GrowthPolicy = DoubleGrowth> {
     ptr: u64,
-    len: usize,
-    cap: usize,
+    len: u64,
+    cap: u64,
     stable_drop_flag: bool,
     _marker_t: PhantomData<T>,
+    _marker_g: PhantomData<G>,
 }
 
 impl<T: StableType + AsFixedSizeBytes> SVec<T> {
@@ -56,7 +70,8 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
             cap: DEFAULT_CAPACITY,
             ptr: EMPTY_PTR,
             stable_drop_flag: true,
-            _marker_t: PhantomData::default(),
+            _marker_t: PhantomData,
+            _marker_g: PhantomData,
         }
     }
 
@@ -76,27 +91,77 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
     ///     .expect("Out of memory");
     /// ```
     #[inline]
-    pub fn new_with_capacity(capacity: usize) -> Result<Self, OutOfMemory> {
+    pub fn new_with_capacity(capacity: u64) -> Result<Self, OutOfMemory> {
+        assert!(capacity <= Self::max_capacity());
+
+        Ok(Self {
+            len: 0,
+            cap: capacity,
+            ptr: unsafe { allocate(capacity * T::SIZE as u64)?.as_ptr() },
+            stable_drop_flag: true,
+            _marker_t: PhantomData,
+            _marker_g: PhantomData,
+        })
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> SVec<T, G> {
+    /// Creates a [SVec] of capacity equal to 4 elements, that grows according to the provided [GrowthPolicy]
+    ///
+    /// Does not allocate any heap or stable memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// // grows by 1024 elements at a time, instead of doubling its capacity
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::collections::ChunkGrowth;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut events = SVec::<u64, _>::with_growth_policy(ChunkGrowth::<1024>);
+    ///
+    /// events.try_extend(0..5).expect("Out of memory");
+    ///
+    /// assert_eq!(events.capacity(), 4 + 1024);
+    /// ```
+    #[inline]
+    pub fn with_growth_policy(_policy: G) -> Self {
+        Self {
+            len: 0,
+            cap: DEFAULT_CAPACITY,
+            ptr: EMPTY_PTR,
+            stable_drop_flag: true,
+            _marker_t: PhantomData,
+            _marker_g: PhantomData,
+        }
+    }
+
+    /// Creates a [SVec] of requested capacity, that grows according to the provided [GrowthPolicy]
+    ///
+    /// Does allocate stable memory, returning [OutOfMemory] if there is not enough of it.
+    #[inline]
+    pub fn with_capacity_and_growth_policy(capacity: u64, _policy: G) -> Result<Self, OutOfMemory> {
         assert!(capacity <= Self::max_capacity());
 
         Ok(Self {
             len: 0,
             cap: capacity,
-            ptr: unsafe { allocate((capacity * T::SIZE) as u64)?.as_ptr() },
+            ptr: unsafe { allocate(capacity * T::SIZE as u64)?.as_ptr() },
             stable_drop_flag: true,
-            _marker_t: PhantomData::default(),
+            _marker_t: PhantomData,
+            _marker_g: PhantomData,
         })
     }
 
     /// Returns the capacity of this [SVec]
     #[inline]
-    pub fn capacity(&self) -> usize {
+    pub fn capacity(&self) -> u64 {
         self.cap
     }
 
     /// Returns the length of this [SVec]
     #[inline]
-    pub fn len(&self) -> usize {
+    pub fn len(&self) -> u64 {
         self.len
     }
 
@@ -108,8 +173,8 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
 
     /// Returns the maximum possible capacity of this [SVec]
     #[inline]
-    pub const fn max_capacity() -> usize {
-        u32::MAX as usize / T::SIZE
+    pub const fn max_capacity() -> u64 {
+        max_elements(T::SIZE)
     }
 
     /// Inserts a new element at the end of this [SVec]
@@ -119,7 +184,7 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
     #[inline]
     pub fn push(&mut self, mut element: T) -> Result<(), T> {
         if self.maybe_reallocate().is_ok() {
-            let elem_ptr = SSlice::_offset(self.ptr, (self.len * T::SIZE) as u64);
+            let elem_ptr = SSlice::_offset(self.ptr, self.len * T::SIZE as u64);
             unsafe { crate::mem::write_fixed(elem_ptr, &mut element) };
 
             self.len += 1;
@@ -130,6 +195,145 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         }
     }
 
+    /// Inserts all elements of the provided iterator at the end of this [SVec]
+    ///
+    /// Will try to reallocate once for the lower bound of the iterator's size hint and then again,
+    /// if that was not enough. If the canister is out of stable memory, will return [Err] with the
+    /// element that was about to get inserted. All elements inserted before it stay in this [SVec],
+    /// the rest of the iterator is left unconsumed.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::<u64>::new();
+    ///
+    /// vec.try_extend(0..100).expect("Out of memory");
+    ///
+    /// assert_eq!(vec.len(), 100);
+    /// ```
+    pub fn try_extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), T> {
+        let mut iter = iter.into_iter();
+
+        let (lower_bound, _) = iter.size_hint();
+        let additional = u64::min(lower_bound as u64, Self::max_capacity() - self.len);
+
+        if self.maybe_reallocate_for(additional).is_err() {
+            return match iter.next() {
+                Some(it) => Err(it),
+                None => Ok(()),
+            };
+        }
+
+        for it in iter {
+            self.push(it)?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure this [SVec] can hold at least `additional` more elements without reallocation
+    ///
+    /// The capacity grows according to the [GrowthPolicy]. A [SVec], created with [SVec::new],
+    /// allocates its memory here, even if `additional` is `0`. If the canister is out of stable
+    /// memory, returns [OutOfMemory] and leaves this [SVec] untouched.
+    ///
+    /// # Panics
+    /// Panics if the new capacity exceeds [SVec::max_capacity].
+    #[inline]
+    pub fn reserve(&mut self, additional: u64) -> Result<(), OutOfMemory> {
+        self.maybe_reallocate_for(additional)
+    }
+
+    /// Inserts a new element at the end of this [SVec], only if it fits into the current capacity
+    ///
+    /// Never (re)allocates. If this [SVec] is full or was created with [SVec::new] and has no memory
+    /// allocated yet, returns [Err] with the element. Useful together with [SVec::reserve], when a
+    /// hot loop should never reallocate unexpectedly.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::<u64>::new_with_capacity(2).expect("Out of memory");
+    ///
+    /// assert!(vec.push_within_capacity(1).is_ok());
+    /// assert!(vec.push_within_capacity(2).is_ok());
+    /// assert_eq!(vec.push_within_capacity(3), Err(3));
+    /// ```
+    #[inline]
+    pub fn push_within_capacity(&mut self, mut element: T) -> Result<(), T> {
+        if self.len == self.cap || self.ptr == EMPTY_PTR {
+            return Err(element);
+        }
+
+        let elem_ptr = SSlice::_offset(self.ptr, self.len * T::SIZE as u64);
+        unsafe { crate::mem::write_fixed(elem_ptr, &mut element) };
+
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Returns a pointer to the first element slot after the end of this [SVec]
+    ///
+    /// `(capacity - length) * T::SIZE` bytes, starting from this pointer, belong to this [SVec], but
+    /// do not hold any elements yet. Together with [SVec::set_len], this allows to write a whole batch
+    /// of encoded elements with a single [write_bytes](crate::mem::write_bytes) call. The pointer is
+    /// invalidated by any reallocation of this [SVec]. If this [SVec] has no memory allocated yet
+    /// (see [SVec::reserve]), the pointer must not be used at all.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::{stable_memory_init, AsFixedSizeBytes};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::<u64>::new();
+    /// vec.reserve(100).expect("Out of memory");
+    ///
+    /// let mut batch = vec![0u8; 100 * u64::SIZE];
+    /// for (i, chunk) in batch.chunks_mut(u64::SIZE).enumerate() {
+    ///     (i as u64).as_fixed_size_bytes(chunk);
+    /// }
+    ///
+    /// unsafe {
+    ///     ic_stable_memory::mem::write_bytes(vec.spare_capacity_ptr(), &batch);
+    ///     vec.set_len(100);
+    /// }
+    ///
+    /// assert_eq!(*vec.get(42).unwrap(), 42);
+    /// ```
+    #[inline]
+    pub fn spare_capacity_ptr(&self) -> StablePtr {
+        if self.ptr == EMPTY_PTR {
+            return EMPTY_PTR;
+        }
+
+        SSlice::_offset(self.ptr, self.len * T::SIZE as u64)
+    }
+
+    /// Sets the length of this [SVec], without touching its elements
+    ///
+    /// See [SVec::spare_capacity_ptr].
+    ///
+    /// # Safety
+    /// This [SVec] should have its memory allocated and `new_len` should be less than or equal to
+    /// [SVec::capacity]. When growing, all elements in
+    /// `[old_len, new_len)` should be written in advance with valid encoded `T` values - they are
+    /// owned by this [SVec] from now on. When shrinking, removed elements are not stable-dropped, so
+    /// their stable memory leaks, unless it is owned by something else.
+    #[inline]
+    pub unsafe fn set_len(&mut self, new_len: u64) {
+        debug_assert!(new_len <= self.cap && (new_len == 0 || self.ptr != EMPTY_PTR));
+
+        self.len = new_len;
+    }
+
     /// Removes the last element of the [SVec]
     ///
     /// If the [SVec] is empty, returns [None].
@@ -165,10 +369,10 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
     /// assert_eq!(*elem, 20);
     /// ```
     #[inline]
-    pub fn get(&self, idx: usize) -> Option<SRef<'_, T>> {
+    pub fn get(&self, idx: u64) -> Option<SRef<'_, T>> {
         let ptr = self.get_element_ptr(idx)?;
 
-        unsafe { Some(SRef::new(ptr)) }
+        unsafe { Some(SRef::new_in(self.ptr, ptr)) }
     }
 
     /// Returns [SRefMut] pointing to the element at requested index
@@ -190,10 +394,10 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
     /// *elem = 100;
     /// ```
     #[inline]
-    pub fn get_mut(&mut self, idx: usize) -> Option<SRefMut<'_, T>> {
+    pub fn get_mut(&mut self, idx: u64) -> Option<SRefMut<'_, T>> {
         let ptr = self.get_element_ptr(idx)?;
 
-        unsafe { Some(SRefMut::new(ptr)) }
+        unsafe { Some(SRefMut::new_in(self.ptr, ptr)) }
     }
 
     /// Replaces an element at requested index with a provided value
@@ -215,10 +419,10 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
     /// assert_eq!(prev, 10);
     /// assert_eq!(*vec.get(0).unwrap(), 20);
     /// ```
-    pub fn replace(&mut self, idx: usize, mut element: T) -> T {
+    pub fn replace(&mut self, idx: u64, mut element: T) -> T {
         assert!(idx < self.len(), "Out of bounds");
 
-        let elem_ptr = SSlice::_offset(self.ptr, (idx * T::SIZE) as u64);
+        let elem_ptr = SSlice::_offset(self.ptr, idx * T::SIZE as u64);
 
         let prev_element = unsafe { crate::mem::read_fixed_for_move(elem_ptr) };
         unsafe { crate::mem::write_fixed(elem_ptr, &mut element) };
@@ -233,7 +437,7 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
     ///
     /// # Panics
     /// Panics if out of bounds.
-    pub fn insert(&mut self, idx: usize, mut element: T) -> Result<(), T> {
+    pub fn insert(&mut self, idx: u64, mut element: T) -> Result<(), T> {
         if idx == self.len {
             return self.push(element);
         }
@@ -241,12 +445,11 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         assert!(idx < self.len, "out of bounds");
 
         if self.maybe_reallocate().is_ok() {
-            let elem_ptr = SSlice::_offset(self.ptr, (idx * T::SIZE) as u64);
+            let elem_ptr = SSlice::_offset(self.ptr, idx * T::SIZE as u64);
 
             // moving elements after idx one slot to the right
-            let mut buf = vec![0u8; (self.len - idx) * T::SIZE];
-            unsafe { crate::mem::read_bytes(elem_ptr, &mut buf) };
-            unsafe { crate::mem::write_bytes(elem_ptr + T::SIZE as u64, &buf) };
+            let len = (self.len - idx) * T::SIZE as u64;
+            unsafe { crate::mem::move_bytes(elem_ptr, elem_ptr + T::SIZE as u64, len) };
 
             // writing the element
             unsafe { crate::mem::write_fixed(elem_ptr, &mut element) };
@@ -263,19 +466,18 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
     ///
     /// # Panics
     /// Panics if out of bounds.
-    pub fn remove(&mut self, idx: usize) -> T {
+    pub fn remove(&mut self, idx: u64) -> T {
         assert!(idx < self.len, "out of bounds");
 
         if idx == self.len - 1 {
             return unsafe { self.pop().unwrap_unchecked() };
         }
 
-        let elem_ptr = SSlice::_offset(self.ptr, (idx * T::SIZE) as u64);
+        let elem_ptr = SSlice::_offset(self.ptr, idx * T::SIZE as u64);
         let elem = unsafe { crate::mem::read_fixed_for_move(elem_ptr) };
 
-        let mut buf = vec![0u8; (self.len - idx - 1) * T::SIZE];
-        unsafe { crate::mem::read_bytes(elem_ptr + T::SIZE as u64, &mut buf) };
-        unsafe { crate::mem::write_bytes(elem_ptr, &buf) };
+        let len = (self.len - idx - 1) * T::SIZE as u64;
+        unsafe { crate::mem::move_bytes(elem_ptr + T::SIZE as u64, elem_ptr, len) };
 
         self.len -= 1;
 
@@ -286,14 +488,14 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
     ///
     /// # Panics
     /// Panics if `idx1` == `idx2` or if any of indices are out of bounds.
-    pub fn swap(&mut self, idx1: usize, idx2: usize) {
+    pub fn swap(&mut self, idx1: u64, idx2: u64) {
         assert!(
             idx1 < self.len() && idx2 < self.len() && idx1 != idx2,
             "invalid idx"
         );
 
-        let ptr1 = SSlice::_offset(self.ptr, (idx1 * T::SIZE) as u64);
-        let ptr2 = SSlice::_offset(self.ptr, (idx2 * T::SIZE) as u64);
+        let ptr1 = SSlice::_offset(self.ptr, idx1 * T::SIZE as u64);
+        let ptr2 = SSlice::_offset(self.ptr, idx2 * T::SIZE as u64);
 
         let mut buf_1 = T::Buf::new(T::SIZE);
         let mut buf_2 = T::Buf::new(T::SIZE);
@@ -333,7 +535,7 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
     ///
     /// assert_eq!(idx, 10);
     /// ```
-    pub fn binary_search_by<FN>(&self, mut f: FN) -> Result<usize, usize>
+    pub fn binary_search_by<FN>(&self, mut f: FN) -> Result<u64, u64>
     where
         FN: FnMut(&T) -> Ordering,
     {
@@ -346,7 +548,7 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         let mut mid = (max - min) / 2;
 
         loop {
-            let elem_ptr = SSlice::_offset(self.ptr, (mid * T::SIZE) as u64);
+            let elem_ptr = SSlice::_offset(self.ptr, mid * T::SIZE as u64);
             let elem = unsafe { crate::mem::read_fixed_for_reference(elem_ptr) };
 
             let res = f(&elem);
@@ -398,10 +600,187 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
     /// }
     /// ```
     #[inline]
-    pub fn iter(&self) -> SVecIter<T> {
+    pub fn iter(&self) -> SVecIter<T, G> {
         SVecIter::new(self)
     }
 
+    /// Removes consecutive elements that resolve to the same key, keeping only the first one of each run
+    ///
+    /// See also [SVec::dedup] and [SVec::dedup_by].
+    ///
+    /// Works the same way as in [Vec]. If this [SVec] is sorted by the key, this removes all duplicates.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::<u64>::new();
+    /// vec.try_extend([10, 11, 20, 21, 22, 30]).expect("Out of memory");
+    ///
+    /// vec.dedup_by_key(|it| *it / 10);
+    ///
+    /// assert_eq!(vec.len(), 3);
+    /// assert_eq!(*vec.get(1).unwrap(), 20);
+    /// ```
+    #[inline]
+    pub fn dedup_by_key<K, F>(&mut self, mut key: F)
+    where
+        K: PartialEq,
+        F: FnMut(&T) -> K,
+    {
+        self.dedup_by(|a, b| key(a) == key(b))
+    }
+
+    /// Removes consecutive elements that satisfy the provided equality relation
+    ///
+    /// See also [SVec::dedup] and [SVec::dedup_by_key].
+    ///
+    /// The lambda is passed the element in question and the last element that was kept. If it returns
+    /// `true`, the element gets removed from this [SVec] and stable-dropped.
+    ///
+    /// Performs a single pass over stable memory: each element is read once and only kept elements
+    /// that have to be moved are written back.
+    pub fn dedup_by<F>(&mut self, mut same_bucket: F)
+    where
+        F: FnMut(&T, &T) -> bool,
+    {
+        if self.len < 2 {
+            return;
+        }
+
+        let first_ptr = SSlice::_offset(self.ptr, 0);
+        let mut prev = unsafe { crate::mem::read_fixed_for_reference::<T>(first_ptr) };
+        let mut write_idx = 1;
+
+        for read_idx in 1..self.len {
+            let mut buf = T::Buf::new(T::SIZE);
+            let read_ptr = SSlice::_offset(self.ptr, read_idx * T::SIZE as u64);
+            unsafe { crate::mem::read_bytes(read_ptr, buf._deref_mut()) };
+
+            let mut cur = T::from_fixed_size_bytes(buf._deref());
+
+            if same_bucket(&cur, &prev) {
+                // the duplicate is owned by nobody now
+                unsafe { cur.stable_drop_flag_on() };
+                continue;
+            }
+
+            unsafe { cur.stable_drop_flag_off() };
+
+            if read_idx != write_idx {
+                let write_ptr = SSlice::_offset(self.ptr, write_idx * T::SIZE as u64);
+                unsafe { crate::mem::write_bytes(write_ptr, buf._deref()) };
+            }
+
+            write_idx += 1;
+            prev = cur;
+        }
+
+        self.len = write_idx;
+    }
+
+    /// Removes the specified range of elements from this [SVec], returning them as an iterator
+    ///
+    /// Works the same way as in [Vec]. Elements are moved out of stable memory one by one, while
+    /// the iterator is consumed. Once the iterator is dropped, all elements of the range that were
+    /// not consumed are stable-dropped and the tail of the [SVec] is moved in place of the removed
+    /// range with a single read and a single write.
+    ///
+    /// # Panics
+    /// Panics if the start of the range is greater than its end, or if the end of the range is out of bounds.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::new_with_capacity(100).expect("Out of memory");
+    ///
+    /// for i in 0..100 {
+    ///     vec.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// let processed: Vec<u64> = vec.drain(..10).collect();
+    ///
+    /// assert_eq!(processed, (0..10).collect::<Vec<_>>());
+    /// assert_eq!(vec.len(), 90);
+    /// assert_eq!(*vec.get(0).unwrap(), 10);
+    /// ```
+    pub fn drain<R: RangeBounds<u64>>(&mut self, range: R) -> SVecDrain<'_, T, G> {
+        let start = match range.start_bound() {
+            Bound::Included(&idx) => idx,
+            Bound::Excluded(&idx) => idx.checked_add(1).expect("out of bounds"),
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(&idx) => idx.checked_add(1).expect("out of bounds"),
+            Bound::Excluded(&idx) => idx,
+            Bound::Unbounded => self.len,
+        };
+
+        assert!(start <= end, "invalid range");
+        assert!(end <= self.len, "out of bounds");
+
+        SVecDrain::new(self, start, end)
+    }
+
+    /// Returns an iterator over non-overlapping chunks of `chunk_size` elements of this [SVec]
+    ///
+    /// Works the same way as in [Vec] - the last chunk may be shorter. Each chunk is read from stable
+    /// memory with a single read and derefs into a slice, which makes it a lot cheaper than
+    /// [SVec::iter] for scanning large collections.
+    ///
+    /// # Panics
+    /// Panics if `chunk_size` is `0`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::<u64>::new();
+    /// vec.try_extend(0..10).expect("Out of memory");
+    ///
+    /// let sums: Vec<u64> = vec.chunks(4).map(|chunk| chunk.iter().sum()).collect();
+    ///
+    /// assert_eq!(sums, vec![6, 22, 17]);
+    /// ```
+    #[inline]
+    pub fn chunks(&self, chunk_size: usize) -> SVecChunks<'_, T, G> {
+        SVecChunks::new(self, chunk_size)
+    }
+
+    /// Returns an iterator over all overlapping windows of `window_size` elements of this [SVec]
+    ///
+    /// Works the same way as in [Vec]. Each window is read from stable memory with a single read and
+    /// derefs into a slice.
+    ///
+    /// # Panics
+    /// Panics if `window_size` is `0`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::<u64>::new();
+    /// vec.try_extend([1, 2, 4, 8]).expect("Out of memory");
+    ///
+    /// let diffs: Vec<u64> = vec.windows(2).map(|w| w[1] - w[0]).collect();
+    ///
+    /// assert_eq!(diffs, vec![1, 2, 4]);
+    /// ```
+    #[inline]
+    pub fn windows(&self, window_size: usize) -> SVecWindows<'_, T, G> {
+        SVecWindows::new(self, window_size)
+    }
+
     /// Prints byte representation of this collection
     ///
     /// Useful for tests
@@ -411,7 +790,7 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
             let mut b = T::Buf::new(T::SIZE);
             unsafe {
                 crate::mem::read_bytes(
-                    SSlice::_offset(self.ptr, (i * T::SIZE) as u64),
+                    SSlice::_offset(self.ptr, i * T::SIZE as u64),
                     b._deref_mut(),
                 )
             };
@@ -426,47 +805,261 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         println!("]");
     }
 
+    #[inline]
     fn maybe_reallocate(&mut self) -> Result<(), OutOfMemory> {
+        self.maybe_reallocate_for(1)
+    }
+
+    fn maybe_reallocate_for(&mut self, additional: u64) -> Result<(), OutOfMemory> {
+        let required_cap = self.len.checked_add(additional).unwrap();
+        assert!(required_cap <= Self::max_capacity());
+
+        let mut new_cap = self.cap;
+        while new_cap < required_cap {
+            new_cap = u64::max(G::next_capacity(new_cap), new_cap + 1);
+        }
+        let new_cap = u64::min(new_cap, Self::max_capacity());
+
         if self.ptr == EMPTY_PTR {
-            self.ptr = unsafe { allocate((self.capacity() * T::SIZE) as u64)?.as_ptr() };
+            self.ptr = unsafe { allocate(new_cap * T::SIZE as u64)?.as_ptr() };
+            self.cap = new_cap;
+
             return Ok(());
         }
 
-        if self.len() == self.capacity() {
-            self.cap = self.cap.checked_mul(2).unwrap();
-            assert!(self.cap <= Self::max_capacity());
+        if new_cap > self.cap {
+            let slice = unsafe { SSlice::from_ptr(self.ptr).unwrap() };
+
+            self.ptr = unsafe { reallocate(slice, new_cap * T::SIZE as u64)?.as_ptr() };
+            self.cap = new_cap;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn get_element_ptr(&self, idx: u64) -> Option<StablePtr> {
+        if idx < self.len() {
+            Some(SSlice::_offset(self.ptr, idx * T::SIZE as u64))
+        } else {
+            None
+        }
+    }
+}
+
+impl<G: GrowthPolicy> SVec<u8, G> {
+    /// Appends all bytes of the provided slice to the end of this [SVec]
+    ///
+    /// Reallocates at most once and then writes all bytes with a single write. If the canister is
+    /// out of stable memory, returns [OutOfMemory] and leaves this [SVec] untouched.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut bytes = SVec::<u8>::new();
+    ///
+    /// bytes.extend_from_slice(b"hello, world").expect("Out of memory");
+    ///
+    /// assert_eq!(bytes.len(), 12);
+    /// assert_eq!(*bytes.get(7).unwrap(), b'w');
+    /// ```
+    pub fn extend_from_slice(&mut self, slice: &[u8]) -> Result<(), OutOfMemory> {
+        if slice.is_empty() {
+            return Ok(());
+        }
+
+        self.maybe_reallocate_for(slice.len() as u64)?;
+
+        let ptr = SSlice::_offset(self.ptr, self.len);
+        unsafe { crate::mem::write_bytes(ptr, slice) };
+
+        self.len += slice.len() as u64;
+
+        Ok(())
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + PartialEq, G: GrowthPolicy> SVec<T, G> {
+    /// Removes consecutive repeated elements, stable-dropping them
+    ///
+    /// See also [SVec::dedup_by] and [SVec::dedup_by_key].
+    ///
+    /// Works the same way as in [Vec]. If this [SVec] is sorted, this removes all duplicates.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::<u64>::new();
+    /// vec.try_extend([1, 1, 2, 3, 3, 3, 4]).expect("Out of memory");
+    ///
+    /// vec.dedup();
+    ///
+    /// assert_eq!(vec.len(), 4);
+    /// ```
+    #[inline]
+    pub fn dedup(&mut self) {
+        self.dedup_by(|a, b| a == b)
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Clone, G: GrowthPolicy> SVec<T, G> {
+    /// Copies all elements into a heap [Vec]
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let vec = SVec::<u64>::try_from(vec![1, 2, 3]).expect("Out of memory");
+    ///
+    /// assert_eq!(vec.to_std(), vec![1, 2, 3]);
+    /// ```
+    pub fn to_std(&self) -> Vec<T> {
+        self.iter().map(|it| (*it).clone()).collect()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Default for SVec<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + TryClone, G: GrowthPolicy> TryClone for SVec<T, G> {
+    /// Copies all elements into a new [SVec] of the same length
+    fn try_clone(&self) -> Result<Self, OutOfMemory> {
+        let mut res = Self {
+            ptr: EMPTY_PTR,
+            len: 0,
+            cap: DEFAULT_CAPACITY,
+            stable_drop_flag: true,
+            _marker_t: PhantomData,
+            _marker_g: PhantomData,
+        };
+
+        if self.is_empty() {
+            return Ok(res);
+        }
+
+        res.maybe_reallocate_for(self.len)?;
+
+        for elem in self.iter() {
+            res.push(elem.try_clone()?).map_err(|_| OutOfMemory)?;
+        }
+
+        Ok(res)
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> TryFromIterator<T> for SVec<T, G> {
+    /// See [SVec::try_extend]
+    fn try_from_iter<I: IntoIterator<Item = T>>(iter: I) -> Result<Self, (Self, OutOfMemory)> {
+        let mut it = Self {
+            ptr: EMPTY_PTR,
+            len: 0,
+            cap: DEFAULT_CAPACITY,
+            stable_drop_flag: true,
+            _marker_t: PhantomData,
+            _marker_g: PhantomData,
+        };
+
+        match it.try_extend(iter) {
+            Ok(_) => Ok(it),
+            Err(_) => Err((it, OutOfMemory)),
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> TryFrom<Vec<T>> for SVec<T, G> {
+    type Error = OutOfMemory;
 
-            let slice = unsafe { SSlice::from_ptr(self.ptr).unwrap() };
+    /// Moves all elements of the provided [Vec] into a new [SVec]
+    ///
+    /// # Errors
+    /// Returns [OutOfMemory] if the canister is out of stable memory. Everything allocated so far is
+    /// released.
+    #[inline]
+    fn try_from(value: Vec<T>) -> Result<Self, Self::Error> {
+        Self::try_from_iter(value).map_err(|(_, e)| e)
+    }
+}
 
-            self.ptr = unsafe { reallocate(slice, (self.cap * T::SIZE) as u64)?.as_ptr() };
-        }
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> IntoIterator for SVec<T, G> {
+    type Item = T;
+    type IntoIter = SVecIntoIter<T, G>;
 
-        Ok(())
+    /// Returns a consuming iterator over elements of this [SVec]
+    ///
+    /// Elements are moved out of stable memory one by one. Once the iterator is dropped, all
+    /// elements that were not consumed are stable-dropped and the memory block of this [SVec] is
+    /// released.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::<u64>::new();
+    /// vec.try_extend(0..10).expect("Out of memory");
+    ///
+    /// let heap_vec: Vec<u64> = vec.into_iter().collect();
+    ///
+    /// assert_eq!(heap_vec, (0..10).collect::<Vec<_>>());
+    /// ```
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        SVecIntoIter::new(self)
     }
+}
 
-    pub(crate) fn get_element_ptr(&self, idx: usize) -> Option<StablePtr> {
-        if idx < self.len() {
-            Some(SSlice::_offset(self.ptr, (idx * T::SIZE) as u64))
-        } else {
-            None
+/// Serialized as a sequence, reading elements from stable memory one by one
+impl<T: StableType + AsFixedSizeBytes + Serialize, G: GrowthPolicy> Serialize for SVec<T, G> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(usize::try_from(self.len()).ok())?;
+        for it in self.iter() {
+            seq.serialize_element(&*it)?;
         }
+
+        seq.end()
     }
 }
 
-impl<T: StableType + AsFixedSizeBytes> Default for SVec<T> {
+/// Encoded exactly as `Vec<T>` (`vec T`), reading elements from stable memory one by one, so a
+/// reference to [SVec] can be replied from a query method without copying it to the heap first
+impl<T: StableType + AsFixedSizeBytes + CandidType, G: GrowthPolicy> CandidType for SVec<T, G> {
     #[inline]
-    fn default() -> Self {
-        Self::new()
+    fn _ty() -> Type {
+        <Vec<T>>::_ty()
+    }
+
+    fn idl_serialize<S>(&self, serializer: S) -> Result<(), S::Error>
+    where
+        S: candid::types::Serializer,
+    {
+        let mut ser = serializer.serialize_vec(usize::try_from(self.len()).unwrap())?;
+        for it in self.iter() {
+            Compound::serialize_element(&mut ser, &*it)?;
+        }
+
+        Ok(())
     }
 }
 
-impl<T: StableType + AsFixedSizeBytes + Debug> Debug for SVec<T> {
+impl<T: StableType + AsFixedSizeBytes + Debug, G: GrowthPolicy> Debug for SVec<T, G> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str("[")?;
         for (idx, item) in self.iter().enumerate() {
             item.fmt(f)?;
 
-            if idx < self.len - 1 {
+            if (idx as u64) < self.len - 1 {
                 f.write_str(", ")?;
             }
         }
@@ -474,37 +1067,30 @@ impl<T: StableType + AsFixedSizeBytes + Debug> Debug for SVec<T> {
     }
 }
 
-impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SVec<T> {
-    const SIZE: usize = u64::SIZE + usize::SIZE * 2;
-    type Buf = [u8; u64::SIZE + usize::SIZE * 2];
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> AsFixedSizeBytes for SVec<T, G> {
+    // see encoding::counters for the layout of counters
+    const SIZE: usize = header_size(2);
+    type Buf = [u8; header_size(2)];
 
     fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        self.ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
-        self.len
-            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE + usize::SIZE)]);
-        self.cap.as_fixed_size_bytes(
-            &mut buf[(u64::SIZE + usize::SIZE)..(u64::SIZE + usize::SIZE * 2)],
-        );
+        encode_header(self.ptr, [self.len, self.cap], buf);
     }
 
     fn from_fixed_size_bytes(arr: &[u8]) -> Self {
-        let ptr = u64::from_fixed_size_bytes(&arr[0..u64::SIZE]);
-        let len = usize::from_fixed_size_bytes(&arr[u64::SIZE..(u64::SIZE + usize::SIZE)]);
-        let cap = usize::from_fixed_size_bytes(
-            &arr[(u64::SIZE + usize::SIZE)..(u64::SIZE + usize::SIZE * 2)],
-        );
+        let (ptr, [len, cap]) = decode_header(arr);
 
         Self {
             ptr,
             len,
             cap,
             stable_drop_flag: false,
-            _marker_t: PhantomData::default(),
+            _marker_t: PhantomData,
+            _marker_g: PhantomData,
         }
     }
 }
 
-impl<T: StableType + AsFixedSizeBytes> StableType for SVec<T> {
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> StableType for SVec<T, G> {
     #[inline]
     unsafe fn stable_drop_flag_off(&mut self) {
         self.stable_drop_flag = false;
@@ -531,7 +1117,7 @@ impl<T: StableType + AsFixedSizeBytes> StableType for SVec<T> {
     }
 }
 
-impl<T: StableType + AsFixedSizeBytes> Drop for SVec<T> {
+impl<T: StableType + AsFixedSizeBytes, G: GrowthPolicy> Drop for SVec<T, G> {
     fn drop(&mut self) {
         if self.should_stable_drop() {
             unsafe {
@@ -543,10 +1129,11 @@ impl<T: StableType + AsFixedSizeBytes> Drop for SVec<T> {
 
 #[cfg(test)]
 mod tests {
+    use crate::collections::vec::growth::{ChunkGrowth, FactorGrowth};
     use crate::collections::vec::{SVec, DEFAULT_CAPACITY};
     use crate::encoding::{AsFixedSizeBytes, Buffer};
     use crate::primitive::s_box::SBox;
-    use crate::primitive::StableType;
+    use crate::primitive::{StableType, TryClone, TryFromIterator};
     use crate::utils::mem_context::stable;
     use crate::utils::test::generate_random_string;
     use crate::utils::DebuglessUnwrap;
@@ -558,6 +1145,7 @@ mod tests {
     use rand::rngs::ThreadRng;
     use rand::seq::SliceRandom;
     use rand::{thread_rng, Rng};
+    use serde_test::{assert_ser_tokens, Token};
     use std::fmt::Debug;
     use std::ops::Deref;
 
@@ -587,6 +1175,58 @@ mod tests {
 
     impl StableType for Test {}
 
+    #[test]
+    fn candid_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let vec = SVec::<u64>::try_from(vec![1, 2, 3]).unwrap();
+
+            let buf = candid::encode_one(&vec).unwrap();
+            assert_eq!(buf, candid::encode_one(vec![1u64, 2, 3]).unwrap());
+
+            let nested = SVec::<SVec<u8>>::try_from(vec![SVec::new()]).unwrap();
+            let buf = candid::encode_one(&nested).unwrap();
+            assert_eq!(
+                candid::decode_one::<Vec<Vec<u8>>>(&buf).unwrap(),
+                vec![Vec::<u8>::new()]
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn serialize_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::<SVec<u64>>::new();
+            vec.push(SVec::try_from(vec![1, 2]).unwrap()).unwrap();
+            vec.push(SVec::new()).unwrap();
+
+            assert_ser_tokens(
+                &vec,
+                &[
+                    Token::Seq { len: Some(2) },
+                    Token::Seq { len: Some(2) },
+                    Token::U64(1),
+                    Token::U64(2),
+                    Token::SeqEnd,
+                    Token::Seq { len: Some(0) },
+                    Token::SeqEnd,
+                    Token::SeqEnd,
+                ],
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[test]
     fn create_destroy_work_fine() {
         stable::clear();
@@ -621,23 +1261,23 @@ mod tests {
                 stable_vec.push(it).unwrap();
             }
 
-            assert_eq!(stable_vec.len(), count, "Invalid len after push");
+            assert_eq!(stable_vec.len(), count as u64, "Invalid len after push");
 
             for i in 0..count {
                 let it = Test { a: i, b: false };
 
-                stable_vec.replace(i, it);
+                stable_vec.replace(i as u64, it);
             }
 
             stable_vec.debug_print();
-            assert_eq!(stable_vec.len(), count, "Invalid len after push");
+            assert_eq!(stable_vec.len(), count as u64, "Invalid len after push");
 
             for i in 0..count {
                 println!("{} {}", i, stable_vec.len());
 
                 let it = stable_vec.pop().unwrap();
 
-                assert_eq!(stable_vec.len(), count - i - 1);
+                assert_eq!(stable_vec.len(), (count - i - 1) as u64);
                 assert_eq!(it.a, count - 1 - i);
                 assert!(!it.b);
             }
@@ -697,7 +1337,7 @@ mod tests {
 
             for i in 30..60 {
                 array.insert(30 + (i - 30), i).unwrap();
-                check.insert(30 + (i - 30), i);
+                check.insert(30 + (i - 30) as usize, i);
             }
 
             for i in 0..100 {
@@ -855,6 +1495,356 @@ mod tests {
         assert_eq!(get_allocated_size(), 0);
     }
 
+    #[test]
+    fn drain_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::new();
+            let mut check = Vec::new();
+
+            for i in 0..100 {
+                vec.push(SBox::new(i).unwrap()).unwrap();
+                check.push(i);
+            }
+
+            let drained = vec
+                .drain(..10)
+                .map(|it| it.into_inner())
+                .collect::<Vec<_>>();
+            let check_drained = check.drain(..10).collect::<Vec<_>>();
+            assert_eq!(drained, check_drained);
+
+            let drained = vec
+                .drain(20..=30)
+                .rev()
+                .map(|it| it.into_inner())
+                .collect::<Vec<_>>();
+            let check_drained = check.drain(20..=30).rev().collect::<Vec<_>>();
+            assert_eq!(drained, check_drained);
+
+            // partially consumed drain stable-drops the rest
+            let mut drain = vec.drain(50..);
+            assert_eq!(drain.len(), 29);
+            assert_eq!(drain.next().unwrap().into_inner(), 71);
+            drop(drain);
+            check.truncate(50);
+
+            vec.drain(10..10);
+            vec.drain(..0);
+
+            assert_eq!(vec.len(), check.len() as u64);
+            for (i, it) in vec.iter().enumerate() {
+                assert_eq!(**it, check[i]);
+            }
+
+            vec.drain(..);
+            assert!(vec.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn extend_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::<u64>::new();
+
+            vec.try_extend(0..3).unwrap();
+            assert_eq!(vec.capacity(), DEFAULT_CAPACITY);
+
+            vec.try_extend(3..100).unwrap();
+            vec.try_extend((100..200).filter(|it| it % 2 == 0)).unwrap();
+            vec.try_extend(Vec::new()).unwrap();
+
+            let check = (0..100).chain((100..200).step_by(2)).collect::<Vec<_>>();
+
+            assert_eq!(vec.len(), check.len() as u64);
+            assert!(vec.capacity() >= vec.len());
+            for (i, it) in vec.iter().enumerate() {
+                assert_eq!(*it, check[i]);
+            }
+
+            let mut bytes = SVec::<u8>::new();
+            let mut check = Vec::new();
+
+            bytes.extend_from_slice(&[]).unwrap();
+            assert_eq!(bytes.len(), 0);
+
+            for i in 0..50u8 {
+                let chunk = vec![i; i as usize];
+
+                bytes.extend_from_slice(&chunk).unwrap();
+                check.extend_from_slice(&chunk);
+            }
+
+            assert_eq!(bytes.len(), check.len() as u64);
+            for (i, it) in bytes.iter().enumerate() {
+                assert_eq!(*it, check[i]);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn spare_capacity_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::<u64>::new();
+            assert_eq!(vec.push_within_capacity(0), Err(0));
+
+            vec.reserve(10).unwrap();
+            assert!(vec.capacity() >= 10);
+
+            for i in 0..5 {
+                vec.push_within_capacity(i).unwrap();
+            }
+
+            let capacity = vec.capacity();
+            let spare = capacity - vec.len();
+
+            let mut batch = vec![0u8; spare as usize * u64::SIZE];
+            for (i, chunk) in batch.chunks_mut(u64::SIZE).enumerate() {
+                (5 + i as u64).as_fixed_size_bytes(chunk);
+            }
+
+            unsafe {
+                crate::mem::write_bytes(vec.spare_capacity_ptr(), &batch);
+                vec.set_len(capacity);
+            }
+
+            assert_eq!(vec.push_within_capacity(100), Err(100));
+            assert_eq!(vec.capacity(), capacity);
+
+            for (i, it) in vec.iter().enumerate() {
+                assert_eq!(*it, i as u64);
+            }
+
+            // boxes, written as raw bytes, are owned by the vec afterwards
+            let mut boxes = SVec::<SBox<u64>>::new();
+            boxes.reserve(3).unwrap();
+
+            let mut batch = vec![0u8; 3 * SBox::<u64>::SIZE];
+            for (i, chunk) in batch.chunks_mut(SBox::<u64>::SIZE).enumerate() {
+                let mut b = SBox::new(i as u64).unwrap();
+                b.as_fixed_size_bytes(chunk);
+
+                unsafe { b.stable_drop_flag_off() };
+            }
+
+            unsafe {
+                crate::mem::write_bytes(boxes.spare_capacity_ptr(), &batch);
+                boxes.set_len(3);
+            }
+
+            assert_eq!(**boxes.get(2).unwrap(), 2);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn try_from_iter_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let vec = SVec::<u64>::try_from_iter(0..100).unwrap();
+            assert_eq!(vec.len(), 100);
+            assert_eq!(*vec.get(99).unwrap(), 99);
+
+            let vec = SVec::<u64, FactorGrowth<3, 2>>::try_from_iter(Vec::new()).unwrap();
+            assert!(vec.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+
+        deinit_allocator().unwrap();
+        stable::clear();
+        init_allocator(1);
+
+        {
+            // reserves memory for the whole iterator at once, if its size is known
+            let (vec, _) = SVec::<u64>::try_from_iter(0..1_000_000).unwrap_err();
+            assert!(vec.is_empty());
+
+            let (vec, _) = SVec::<u64>::try_from_iter((0..1_000_000).filter(|_| true)).unwrap_err();
+
+            // the partially built vec is still valid
+            assert!(!vec.is_empty());
+            for (i, it) in vec.iter().enumerate() {
+                assert_eq!(*it, i as u64);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn dedup_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::<u64>::new();
+            let mut check = vec![0, 0, 1, 2, 2, 2, 3, 4, 4, 5, 6, 6];
+
+            vec.try_extend(check.iter().copied()).unwrap();
+            vec.dedup();
+            check.dedup();
+
+            assert_eq!(vec.len(), check.len() as u64);
+            for (i, it) in vec.iter().enumerate() {
+                assert_eq!(*it, check[i]);
+            }
+
+            let mut boxes = SVec::new();
+            for i in 0..100u64 {
+                boxes.push(SBox::new(i).unwrap()).unwrap();
+            }
+
+            boxes.dedup_by_key(|it| **it / 10);
+
+            assert_eq!(boxes.len(), 10);
+            for (i, it) in boxes.iter().enumerate() {
+                assert_eq!(**it, i as u64 * 10);
+            }
+
+            let mut single = SVec::<u64>::new();
+            single.push(1).unwrap();
+            single.dedup();
+            assert_eq!(single.len(), 1);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn chunks_and_windows_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::<u64>::new();
+            assert_eq!(vec.chunks(3).count(), 0);
+            assert_eq!(vec.windows(3).count(), 0);
+
+            let check = (0..100).collect::<Vec<_>>();
+            vec.try_extend(check.iter().copied()).unwrap();
+
+            assert_eq!(vec.windows(usize::MAX).count(), 0);
+
+            for size in [1, 3, 10, 99, 100, 150] {
+                let chunks = vec.chunks(size).map(|it| it.to_vec()).collect::<Vec<_>>();
+                let check_chunks = check.chunks(size).map(|it| it.to_vec()).collect::<Vec<_>>();
+                assert_eq!(chunks, check_chunks);
+
+                let windows = vec.windows(size).map(|it| it.to_vec()).collect::<Vec<_>>();
+                let check_windows = check
+                    .windows(size)
+                    .map(|it| it.to_vec())
+                    .collect::<Vec<_>>();
+                assert_eq!(windows, check_windows);
+            }
+
+            let mut boxes = SVec::new();
+            for i in 0..10 {
+                boxes.push(SBox::new(i).unwrap()).unwrap();
+            }
+
+            for chunk in boxes.chunks(3) {
+                for it in chunk.iter() {
+                    assert!(**it < 10);
+                }
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn into_iter_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::new();
+            for i in 0..100 {
+                vec.push(SBox::new(i).unwrap()).unwrap();
+            }
+
+            let res = vec
+                .into_iter()
+                .map(|it| it.into_inner())
+                .collect::<Vec<_>>();
+            assert_eq!(res, (0..100).collect::<Vec<_>>());
+
+            let mut vec = SVec::new();
+            for i in 0..100 {
+                vec.push(SBox::new(i).unwrap()).unwrap();
+            }
+
+            let mut iter = vec.into_iter();
+            assert_eq!(iter.next().unwrap().into_inner(), 0);
+            assert_eq!(iter.next_back().unwrap().into_inner(), 99);
+            assert_eq!(iter.len(), 98);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn growth_policies_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut chunked = SVec::<u64, _>::with_growth_policy(ChunkGrowth::<10>);
+            chunked.try_extend(0..5).unwrap();
+            assert_eq!(chunked.capacity(), DEFAULT_CAPACITY + 10);
+
+            chunked.try_extend(5..100).unwrap();
+            assert_eq!(chunked.capacity(), DEFAULT_CAPACITY + 100);
+
+            let mut factor =
+                SVec::with_capacity_and_growth_policy(10, FactorGrowth::<3, 2>).debugless_unwrap();
+            factor.try_extend(0..11u64).unwrap();
+            assert_eq!(factor.capacity(), 15);
+
+            // tiny factors still make progress
+            let mut slow = SVec::with_capacity_and_growth_policy(1, FactorGrowth::<101, 100>)
+                .debugless_unwrap();
+            slow.try_extend(0..3u64).unwrap();
+            assert_eq!(slow.capacity(), 3);
+
+            for (i, it) in chunked.iter().enumerate() {
+                assert_eq!(*it, i as u64);
+            }
+
+            let mut nested = SVec::new();
+            nested.push(chunked).unwrap();
+            nested.get_mut(0).unwrap().push(100).unwrap();
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[test]
     fn random_works_fine() {
         stable::clear();
@@ -923,15 +1913,15 @@ mod tests {
 
     #[derive(Debug)]
     enum Action {
-        Push(usize),
-        Pop(usize),
-        Insert(usize, usize),
-        Remove(usize, usize),
-        Swap(usize, usize, usize),
-        Replace(usize, usize),
+        Push(u64),
+        Pop(u64),
+        Insert(u64, u64),
+        Remove(u64, u64),
+        Swap(u64, u64, u64),
+        Replace(u64, u64),
         CanisterUpgrade,
-        GetMut(usize, usize),
-        Clear(usize),
+        GetMut(u64, u64),
+        Clear(u64),
     }
 
     struct Fuzzer {
@@ -978,7 +1968,10 @@ mod tests {
                             return;
                         };
 
-                        self.example.get_mut(outer_idx).unwrap().push(str.clone());
+                        self.example
+                            .get_mut(outer_idx as usize)
+                            .unwrap()
+                            .push(str.clone());
 
                         self.log.push(Action::Push(outer_idx));
                     }
@@ -1008,9 +2001,9 @@ mod tests {
                         }
 
                         self.example
-                            .get_mut(outer_idx)
+                            .get_mut(outer_idx as usize)
                             .unwrap()
-                            .insert(idx, str.clone());
+                            .insert(idx as usize, str.clone());
 
                         self.log.push(Action::Insert(outer_idx, idx));
                     }
@@ -1020,7 +2013,7 @@ mod tests {
                     let outer_idx = self.rng.gen_range(0..10);
 
                     self.vec().get_mut(outer_idx).unwrap().pop();
-                    self.example.get_mut(outer_idx).unwrap().pop();
+                    self.example.get_mut(outer_idx as usize).unwrap().pop();
 
                     self.log.push(Action::Pop(outer_idx));
                 }
@@ -1041,7 +2034,10 @@ mod tests {
                     };
 
                     self.vec().get_mut(outer_idx).unwrap().remove(idx);
-                    self.example.get_mut(outer_idx).unwrap().remove(idx);
+                    self.example
+                        .get_mut(outer_idx as usize)
+                        .unwrap()
+                        .remove(idx as usize);
 
                     self.log.push(Action::Remove(outer_idx, idx));
                 }
@@ -1067,7 +2063,10 @@ mod tests {
                     }
 
                     self.vec().get_mut(outer_idx).unwrap().swap(idx1, idx2);
-                    self.example.get_mut(outer_idx).unwrap().swap(idx1, idx2);
+                    self.example
+                        .get_mut(outer_idx as usize)
+                        .unwrap()
+                        .swap(idx1 as usize, idx2 as usize);
 
                     self.log.push(Action::Swap(outer_idx, idx1, idx2));
                 }
@@ -1089,9 +2088,9 @@ mod tests {
 
                         std::mem::replace(
                             self.example
-                                .get_mut(outer_idx)
+                                .get_mut(outer_idx as usize)
                                 .unwrap()
-                                .get_mut(idx)
+                                .get_mut(idx as usize)
                                 .unwrap(),
                             str.clone(),
                         );
@@ -1128,9 +2127,9 @@ mod tests {
 
                     *self
                         .example
-                        .get_mut(outer_idx)
+                        .get_mut(outer_idx as usize)
                         .unwrap()
-                        .get_mut(idx)
+                        .get_mut(idx as usize)
                         .unwrap() = str;
 
                     self.log.push(Action::GetMut(outer_idx, idx));
@@ -1140,7 +2139,7 @@ mod tests {
                     let outer_idx = self.rng.gen_range(0..10);
 
                     self.vec().get_mut(outer_idx).unwrap().clear();
-                    self.example.get_mut(outer_idx).unwrap().clear();
+                    self.example.get_mut(outer_idx as usize).unwrap().clear();
 
                     self.log.push(Action::Clear(outer_idx));
                 }
@@ -1164,18 +2163,18 @@ mod tests {
             }
 
             _debug_validate_allocator();
-            assert_eq!(self.vec().len(), self.example.len());
+            assert_eq!(self.vec().len(), self.example.len() as u64);
 
             for i in 0..self.vec().len() {
                 let svec = self.vec.as_ref().unwrap().get(i).unwrap();
-                let example = self.example.get(i).unwrap();
+                let example = self.example.get(i as usize).unwrap();
 
-                assert_eq!(svec.len(), example.len());
+                assert_eq!(svec.len(), example.len() as u64);
 
                 for j in 0..svec.len() {
                     assert_eq!(
                         svec.get(j).unwrap().clone(),
-                        example.get(j).unwrap().clone()
+                        example.get(j as usize).unwrap().clone()
                     );
                 }
             }
@@ -1213,4 +2212,39 @@ mod tests {
 
         assert_eq!(get_allocated_size(), 0);
     }
+
+    #[test]
+    fn try_clone_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::new();
+            for i in 0..10u64 {
+                let mut inner = SVec::new();
+                for j in 0..i {
+                    inner
+                        .push(SBox::new(format!("{} {}", i, j)).unwrap())
+                        .unwrap();
+                }
+
+                vec.push(inner).unwrap();
+            }
+
+            let copy = vec.try_clone().unwrap();
+            drop(vec);
+
+            assert_eq!(copy.len(), 10);
+            for (i, inner) in (0u64..).zip(copy.iter()) {
+                assert_eq!(inner.len(), i);
+
+                for (j, it) in inner.iter().enumerate() {
+                    assert_eq!(**it, format!("{} {}", i, j));
+                }
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 }
@@ -0,0 +1,59 @@
+use crate::collections::vec::iter::size_hint;
+use crate::collections::vec_deque::SVecDeque;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::iter::FusedIterator;
+
+pub struct SVecDequeIter<'a, T: StableType + AsFixedSizeBytes> {
+    deque: &'a SVecDeque<T>,
+    idx: u64,
+    end_idx: u64,
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> SVecDequeIter<'a, T> {
+    pub(crate) fn new(deque: &'a SVecDeque<T>) -> Self {
+        Self {
+            deque,
+            idx: 0,
+            end_idx: deque.len(),
+        }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SVecDequeIter<'a, T> {
+    type Item = SRef<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.idx == self.end_idx {
+            return None;
+        }
+
+        let ptr = self.deque.physical_ptr(self.idx);
+        self.idx += 1;
+
+        unsafe { Some(SRef::new_in(self.deque.ptr, ptr)) }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        size_hint(self.end_idx - self.idx)
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> DoubleEndedIterator for SVecDequeIter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.idx == self.end_idx {
+            return None;
+        }
+
+        self.end_idx -= 1;
+        let ptr = self.deque.physical_ptr(self.end_idx);
+
+        unsafe { Some(SRef::new_in(self.deque.ptr, ptr)) }
+    }
+}
+
+impl<'a, T: StableType + AsFixedSizeBytes> ExactSizeIterator for SVecDequeIter<'a, T> {}
+
+impl<'a, T: StableType + AsFixedSizeBytes> FusedIterator for SVecDequeIter<'a, T> {}
@@ -0,0 +1,542 @@
+use crate::collections::vec_deque::iter::SVecDequeIter;
+use crate::encoding::counters::{decode_header, encode_header, header_size};
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use crate::utils::math::max_elements;
+use crate::{allocate, deallocate, reallocate, OutOfMemory};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+#[doc(hidden)]
+pub mod iter;
+
+const DEFAULT_CAPACITY: u64 = 4;
+
+/// Stable analog of [VecDeque](std::collections::VecDeque)
+///
+/// A double-ended queue, backed by a circular buffer in stable memory. Inserting and removing
+/// elements at both ends is O(1) and only touches the element in question - no other elements are
+/// moved. When the buffer is full, it gets reallocated to a twice bigger one, moving (at most) a
+/// wrapped part of the buffer.
+///
+/// This is a "finite" data structure, it can only hold up to `1TB / T::SIZE` elements. Putting more
+/// elements inside will panic.
+///
+/// `T` has to implement both [StableType] and [AsFixedSizeBytes]. [SVecDeque] itself implements these
+/// traits and can be nested inside other stable data structures.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SVecDeque;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut deque = SVecDeque::<u64>::new();
+///
+/// deque.push_back(2).expect("Out of memory");
+/// deque.push_back(3).expect("Out of memory");
+/// deque.push_front(1).expect("Out of memory");
+///
+/// assert_eq!(*deque.front().unwrap(), 1);
+/// assert_eq!(*deque.back().unwrap(), 3);
+///
+/// assert_eq!(deque.pop_front(), Some(1));
+/// assert_eq!(deque.pop_back(), Some(3));
+/// assert_eq!(deque.len(), 1);
+/// ```
+pub struct SVecDeque<T: StableType + AsFixedSizeBytes> {
+    ptr: u64,
+    head: u64,
+    len: u64,
+    cap: u64,
+    stable_drop_flag: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T: StableType + AsFixedSizeBytes> SVecDeque<T> {
+    /// Creates a [SVecDeque] of capacity equal to 4 elements.
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            ptr: EMPTY_PTR,
+            head: 0,
+            len: 0,
+            cap: DEFAULT_CAPACITY,
+            stable_drop_flag: true,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a [SVecDeque] of requested capacity.
+    ///
+    /// Does allocate stable memory, returning [OutOfMemory] if there is not enough of it.
+    /// If this function returns [Ok], you are guaranteed to have enough stable memory to store at
+    /// least `capacity` elements in it.
+    #[inline]
+    pub fn new_with_capacity(capacity: u64) -> Result<Self, OutOfMemory> {
+        assert!(capacity <= Self::max_capacity());
+
+        Ok(Self {
+            ptr: unsafe { allocate(capacity * T::SIZE as u64)?.as_ptr() },
+            head: 0,
+            len: 0,
+            cap: capacity,
+            stable_drop_flag: true,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Returns the capacity of this [SVecDeque]
+    #[inline]
+    pub fn capacity(&self) -> u64 {
+        self.cap
+    }
+
+    /// Returns the length of this [SVecDeque]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if the length of this [SVecDeque] is `0`
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the maximum possible capacity of this [SVecDeque]
+    #[inline]
+    pub const fn max_capacity() -> u64 {
+        max_elements(T::SIZE)
+    }
+
+    /// Inserts a new element at the end of this [SVecDeque]
+    ///
+    /// Will try to reallocate if `capacity == length`. If the canister is out of stable memory,
+    /// will return [Err] with the element that was about to get inserted.
+    pub fn push_back(&mut self, mut element: T) -> Result<(), T> {
+        if self.maybe_reallocate().is_err() {
+            return Err(element);
+        }
+
+        let elem_ptr = self.physical_ptr(self.len);
+        unsafe { crate::mem::write_fixed(elem_ptr, &mut element) };
+
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Inserts a new element at the beginning of this [SVecDeque]
+    ///
+    /// Will try to reallocate if `capacity == length`. If the canister is out of stable memory,
+    /// will return [Err] with the element that was about to get inserted.
+    pub fn push_front(&mut self, mut element: T) -> Result<(), T> {
+        if self.maybe_reallocate().is_err() {
+            return Err(element);
+        }
+
+        self.head = (self.head + self.cap - 1) % self.cap;
+
+        let elem_ptr = self.physical_ptr(0);
+        unsafe { crate::mem::write_fixed(elem_ptr, &mut element) };
+
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Removes the last element of this [SVecDeque]
+    ///
+    /// If the [SVecDeque] is empty, returns [None].
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.len -= 1;
+        let elem_ptr = self.physical_ptr(self.len);
+
+        Some(unsafe { crate::mem::read_fixed_for_move(elem_ptr) })
+    }
+
+    /// Removes the first element of this [SVecDeque]
+    ///
+    /// If the [SVecDeque] is empty, returns [None].
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let elem_ptr = self.physical_ptr(0);
+
+        self.head = (self.head + 1) % self.cap;
+        self.len -= 1;
+
+        Some(unsafe { crate::mem::read_fixed_for_move(elem_ptr) })
+    }
+
+    /// Returns a [SRef] pointing to the element at requested index, counting from the front
+    ///
+    /// If out of bounds, returns [None]
+    #[inline]
+    pub fn get(&self, idx: u64) -> Option<SRef<'_, T>> {
+        let ptr = self.get_element_ptr(idx)?;
+
+        unsafe { Some(SRef::new_in(self.ptr, ptr)) }
+    }
+
+    /// Returns a [SRefMut] pointing to the element at requested index, counting from the front
+    ///
+    /// If out of bounds, returns [None]
+    #[inline]
+    pub fn get_mut(&mut self, idx: u64) -> Option<SRefMut<'_, T>> {
+        let ptr = self.get_element_ptr(idx)?;
+
+        unsafe { Some(SRefMut::new_in(self.ptr, ptr)) }
+    }
+
+    /// Returns a [SRef] pointing to the first element of this [SVecDeque]
+    #[inline]
+    pub fn front(&self) -> Option<SRef<'_, T>> {
+        self.get(0)
+    }
+
+    /// Returns a [SRefMut] pointing to the first element of this [SVecDeque]
+    #[inline]
+    pub fn front_mut(&mut self) -> Option<SRefMut<'_, T>> {
+        self.get_mut(0)
+    }
+
+    /// Returns a [SRef] pointing to the last element of this [SVecDeque]
+    #[inline]
+    pub fn back(&self) -> Option<SRef<'_, T>> {
+        self.get(self.len.checked_sub(1)?)
+    }
+
+    /// Returns a [SRefMut] pointing to the last element of this [SVecDeque]
+    #[inline]
+    pub fn back_mut(&mut self) -> Option<SRefMut<'_, T>> {
+        self.get_mut(self.len.checked_sub(1)?)
+    }
+
+    /// Removes all elements from this [SVecDeque]
+    ///
+    /// Does not reallocate or shrink the underlying memory block.
+    #[inline]
+    pub fn clear(&mut self) {
+        while self.pop_back().is_some() {}
+
+        self.head = 0;
+    }
+
+    /// Returns a front-to-back iterator over elements of this [SVecDeque]
+    ///
+    /// The iterator is double-ended, use [Iterator::rev] to iterate back-to-front.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVecDeque;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut deque = SVecDeque::<u64>::new();
+    ///
+    /// for i in 0..10 {
+    ///     deque.push_front(i).expect("Out of memory");
+    /// }
+    ///
+    /// let elems: Vec<u64> = deque.iter().rev().map(|it| *it).collect();
+    ///
+    /// assert_eq!(elems, (0..10).collect::<Vec<_>>());
+    /// ```
+    #[inline]
+    pub fn iter(&self) -> SVecDequeIter<'_, T> {
+        SVecDequeIter::new(self)
+    }
+
+    /// Prints byte representation of this collection
+    ///
+    /// Useful for tests
+    pub fn debug_print(&self) {
+        print!(
+            "SVecDeque(head={}, len={}, cap={})[",
+            self.head, self.len, self.cap
+        );
+        for i in 0..self.len {
+            let mut b = vec![0u8; T::SIZE];
+            unsafe { crate::mem::read_bytes(self.physical_ptr(i), &mut b) };
+
+            print!("{:?}", b);
+
+            if i < self.len - 1 {
+                print!(", ");
+            }
+        }
+
+        println!("]");
+    }
+
+    fn maybe_reallocate(&mut self) -> Result<(), OutOfMemory> {
+        if self.ptr == EMPTY_PTR {
+            self.ptr = unsafe { allocate(self.cap * T::SIZE as u64)?.as_ptr() };
+
+            return Ok(());
+        }
+
+        if self.len < self.cap {
+            return Ok(());
+        }
+
+        assert!(self.cap < Self::max_capacity());
+
+        let old_cap = self.cap;
+        let new_cap = u64::min(
+            u64::max(old_cap * 2, DEFAULT_CAPACITY),
+            Self::max_capacity(),
+        );
+
+        let slice = unsafe { SSlice::from_ptr(self.ptr).unwrap() };
+        self.ptr = unsafe { reallocate(slice, new_cap * T::SIZE as u64)?.as_ptr() };
+        self.cap = new_cap;
+
+        // the buffer is full, so, if the head is not at the beginning, the buffer is wrapped -
+        // moving the shortest part, so the elements are contiguous again
+        if self.head != 0 {
+            let wrapped_len = self.head;
+            let tail_len = old_cap - self.head;
+
+            if wrapped_len <= new_cap - old_cap {
+                unsafe {
+                    crate::mem::copy_bytes(
+                        SSlice::_offset(self.ptr, 0),
+                        SSlice::_offset(self.ptr, old_cap * T::SIZE as u64),
+                        wrapped_len * T::SIZE as u64,
+                    )
+                };
+            } else {
+                let new_head = new_cap - tail_len;
+
+                unsafe {
+                    crate::mem::move_bytes(
+                        SSlice::_offset(self.ptr, self.head * T::SIZE as u64),
+                        SSlice::_offset(self.ptr, new_head * T::SIZE as u64),
+                        tail_len * T::SIZE as u64,
+                    )
+                };
+
+                self.head = new_head;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn physical_ptr(&self, idx: u64) -> StablePtr {
+        let physical_idx = (self.head + idx) % self.cap;
+
+        SSlice::_offset(self.ptr, physical_idx * T::SIZE as u64)
+    }
+
+    #[inline]
+    pub(crate) fn get_element_ptr(&self, idx: u64) -> Option<StablePtr> {
+        if idx < self.len {
+            Some(self.physical_ptr(idx))
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Default for SVecDeque<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Debug> Debug for SVecDeque<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[")?;
+        for (idx, item) in (0u64..).zip(self.iter()) {
+            item.fmt(f)?;
+
+            if idx < self.len - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SVecDeque<T> {
+    // see encoding::counters for the layout of counters
+    const SIZE: usize = header_size(3);
+    type Buf = [u8; header_size(3)];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        encode_header(self.ptr, [self.head, self.len, self.cap], buf);
+    }
+
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        let (ptr, [head, len, cap]) = decode_header(arr);
+
+        Self {
+            ptr,
+            head,
+            len,
+            cap,
+            stable_drop_flag: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> StableType for SVecDeque<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    unsafe fn stable_drop(&mut self) {
+        if self.ptr != EMPTY_PTR {
+            self.clear();
+
+            let slice = SSlice::from_ptr(self.ptr).unwrap();
+
+            deallocate(slice);
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Drop for SVecDeque<T> {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::vec_deque::SVecDeque;
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use rand::{thread_rng, Rng};
+    use std::collections::VecDeque;
+
+    #[test]
+    fn basic_flow_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut deque = SVecDeque::new();
+            assert!(deque.is_empty());
+            assert!(deque.front().is_none());
+            assert!(deque.back().is_none());
+            assert!(deque.pop_front().is_none());
+            assert!(deque.pop_back().is_none());
+
+            for i in 0..10u64 {
+                deque.push_back(i).unwrap();
+                deque.push_front(100 + i).unwrap();
+            }
+
+            assert_eq!(deque.len(), 20);
+            assert_eq!(*deque.front().unwrap(), 109);
+            assert_eq!(*deque.back().unwrap(), 9);
+
+            *deque.front_mut().unwrap() = 200;
+            assert_eq!(deque.pop_front(), Some(200));
+
+            let elems = deque.iter().map(|it| *it).collect::<Vec<_>>();
+            let expected = (100..109u64).rev().chain(0..10).collect::<Vec<_>>();
+            assert_eq!(elems, expected);
+
+            let rev_elems = deque.iter().rev().map(|it| *it).collect::<Vec<_>>();
+            assert_eq!(rev_elems, expected.into_iter().rev().collect::<Vec<_>>());
+
+            deque.clear();
+            assert!(deque.is_empty());
+
+            let mut vec = SVec::new();
+            deque.push_back(1).unwrap();
+            vec.push(deque).unwrap();
+
+            let mut deque = vec.pop().unwrap();
+            assert_eq!(deque.pop_back(), Some(1));
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn random_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+            let mut deque = SVecDeque::new();
+            let mut check = VecDeque::new();
+
+            for i in 0..5000u64 {
+                match rng.gen_range(0..6) {
+                    0 | 1 => {
+                        deque.push_back(SBox::new(i).unwrap()).unwrap();
+                        check.push_back(i);
+                    }
+                    2 | 3 => {
+                        deque.push_front(SBox::new(i).unwrap()).unwrap();
+                        check.push_front(i);
+                    }
+                    4 => {
+                        assert_eq!(deque.pop_back().map(|it| it.into_inner()), check.pop_back());
+                    }
+                    _ => {
+                        assert_eq!(
+                            deque.pop_front().map(|it| it.into_inner()),
+                            check.pop_front()
+                        );
+                    }
+                }
+
+                assert_eq!(deque.len(), check.len() as u64);
+            }
+
+            for (idx, it) in (0u64..).zip(check.iter()) {
+                assert_eq!(**deque.get(idx).unwrap(), *it);
+            }
+
+            let elems = deque.iter().map(|it| **it).collect::<Vec<_>>();
+            assert_eq!(elems, check.into_iter().collect::<Vec<_>>());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
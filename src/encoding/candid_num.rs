@@ -0,0 +1,196 @@
+//! Bounded versions of [Nat] and [Int], which take as few bytes as the integer they wrap.
+//!
+//! [Nat] and [Int] are arbitrary precision integers, so their [AsFixedSizeBytes] implementation
+//! reserves 32 bytes and panics on bigger values. Most canisters (token ledgers, for example) know
+//! the bounds of their numbers in advance - [Nat64], [Nat128], [Int64] and [Int128] store such values
+//! as plain [u64], [u128], [i64] and [i128], but are still `nat` and `int` in the candid interface
+//! of the canister.
+//!
+//! # Example
+//! ```rust
+//! # use candid::Nat;
+//! # use ic_stable_memory::collections::SBTreeMap;
+//! # use ic_stable_memory::encoding::candid_num::Nat128;
+//! # use ic_stable_memory::stable_memory_init;
+//! # unsafe { ic_stable_memory::mem::clear(); }
+//! # stable_memory_init();
+//! let mut balances = SBTreeMap::<u64, Nat128>::new();
+//!
+//! let amount = Nat128::try_from(Nat::from(100u64)).expect("Too big");
+//! balances.insert(1, amount).expect("Out of memory");
+//!
+//! assert_eq!(Nat::from(*balances.get(&1).unwrap()), Nat::from(100u64));
+//! ```
+
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::StableType;
+use candid::types::{Serializer, Type};
+use candid::{CandidType, Int, Nat};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::fmt::{Display, Formatter};
+
+macro_rules! impl_bounded {
+    ($(#[$meta:meta])* $name:ident, $inner:ty, $big:ident, $candid_ty:expr) => {
+        $(#[$meta])*
+        #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub $inner);
+
+        impl From<$inner> for $name {
+            #[inline]
+            fn from(it: $inner) -> Self {
+                Self(it)
+            }
+        }
+
+        impl From<$name> for $inner {
+            #[inline]
+            fn from(it: $name) -> Self {
+                it.0
+            }
+        }
+
+        impl From<$name> for $big {
+            #[inline]
+            fn from(it: $name) -> Self {
+                $big::from(it.0)
+            }
+        }
+
+        /// Returns the original value back, if it doesn't fit
+        impl TryFrom<$big> for $name {
+            type Error = $big;
+
+            #[inline]
+            fn try_from(it: $big) -> Result<Self, Self::Error> {
+                match <$inner>::try_from(&it.0) {
+                    Ok(inner) => Ok(Self(inner)),
+                    Err(_) => Err(it),
+                }
+            }
+        }
+
+        impl Display for $name {
+            #[inline]
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl CandidType for $name {
+            #[inline]
+            fn _ty() -> Type {
+                $candid_ty
+            }
+
+            #[inline]
+            fn idl_serialize<S>(&self, serializer: S) -> Result<(), S::Error>
+            where
+                S: Serializer,
+            {
+                $big::from(self.0).idl_serialize(serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                Self::try_from($big::deserialize(deserializer)?).map_err(|it| {
+                    D::Error::custom(format!("{} doesn't fit into {}", it, stringify!($name)))
+                })
+            }
+        }
+
+        impl AsFixedSizeBytes for $name {
+            const SIZE: usize = <$inner>::SIZE;
+            type Buf = [u8; Self::SIZE];
+
+            #[inline]
+            fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+                self.0.as_fixed_size_bytes(buf)
+            }
+
+            #[inline]
+            fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+                Self(<$inner>::from_fixed_size_bytes(buf))
+            }
+        }
+
+        impl StableType for $name {}
+    };
+}
+
+impl_bounded!(
+    /// [Nat], that fits into [u64]
+    Nat64,
+    u64,
+    Nat,
+    Type::Nat
+);
+impl_bounded!(
+    /// [Nat], that fits into [u128]
+    Nat128,
+    u128,
+    Nat,
+    Type::Nat
+);
+impl_bounded!(
+    /// [Int], that fits into [i64]
+    Int64,
+    i64,
+    Int,
+    Type::Int
+);
+impl_bounded!(
+    /// [Int], that fits into [i128]
+    Int128,
+    i128,
+    Int,
+    Type::Int
+);
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::candid_num::{Int128, Nat128, Nat64};
+    use crate::encoding::AsFixedSizeBytes;
+    use candid::{decode_one, encode_one, Int, Nat};
+
+    #[test]
+    fn conversions_work_fine() {
+        let it = Nat128::try_from(Nat::from(u128::MAX)).unwrap();
+        assert_eq!(it, Nat128(u128::MAX));
+        assert_eq!(Nat::from(it), Nat::from(u128::MAX));
+
+        let too_big = Nat::from(u128::MAX) + 1u8;
+        assert_eq!(Nat128::try_from(too_big.clone()), Err(too_big));
+
+        let it = Int128::try_from(Int::from(i128::MIN)).unwrap();
+        assert_eq!(Int::from(it), Int::from(i128::MIN));
+        assert!(Int128::try_from(Int::from(i128::MIN) - 1).is_err());
+    }
+
+    #[test]
+    fn candid_works_fine() {
+        // encoded exactly as Nat, so the candid interface stays the same
+        let buf = encode_one(Nat64(10)).unwrap();
+        assert_eq!(buf, encode_one(Nat::from(10u64)).unwrap());
+        assert_eq!(decode_one::<Nat64>(&buf).unwrap(), Nat64(10));
+
+        let buf = encode_one(Nat::from(u128::MAX)).unwrap();
+        assert!(decode_one::<Nat64>(&buf).is_err());
+
+        let buf = encode_one(Int128(-10)).unwrap();
+        assert_eq!(decode_one::<Int>(&buf).unwrap(), Int::from(-10));
+    }
+
+    #[test]
+    fn encoding_works_fine() {
+        assert_eq!(Nat128::SIZE, 16);
+
+        let it = Nat128(u128::MAX - 1);
+        let buf = it.as_new_fixed_size_bytes();
+        assert_eq!(Nat128::from_fixed_size_bytes(&buf), it);
+    }
+}
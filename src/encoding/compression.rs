@@ -0,0 +1,372 @@
+//! Transparent [LZ4](https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md) compression of
+//! dynamically sized values.
+//!
+//! Wrap a value into [Compressed] (or use [SCompressedBox] instead of [SBox]) and its encoding will
+//! be compressed on write and decompressed on read, if it is at least [COMPRESSION_THRESHOLD] bytes
+//! long. Smaller values, as well as values that don't get any smaller after compression (already
+//! compressed images, for example), are stored as is, with just a single byte of overhead. Text
+//! documents, like JSON, usually shrink several times.
+
+use crate::encoding::{AsDynSizeBytes, AsFixedSizeBytes};
+use crate::primitive::s_box::SBox;
+use crate::primitive::StableType;
+use std::ops::{Deref, DerefMut};
+
+/// Encodings shorter than this number of bytes are never compressed
+pub const COMPRESSION_THRESHOLD: usize = 256;
+
+/// [SBox], which compresses its payload
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::encoding::compression::{Compressed, SCompressedBox};
+/// # use ic_stable_memory::{stable_memory_init, SBox};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let doc = r#"{"name": "Alice", "tags": ["a", "b"]}"#.repeat(100);
+///
+/// let mut b: SCompressedBox<String> = SBox::new(Compressed(doc.clone())).expect("Out of memory");
+/// assert_eq!(b.as_str(), doc);
+///
+/// b.with(|it| it.push_str("!")).expect("Out of memory");
+/// assert!(b.ends_with("]}!"));
+/// ```
+pub type SCompressedBox<T> = SBox<Compressed<T>>;
+
+/// A wrapper, compressing the dynamically sized encoding of `T`
+///
+/// See the [module-level documentation](crate::encoding::compression) for details.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Compressed<T>(pub T);
+
+impl<T> Compressed<T> {
+    /// Returns the wrapped value
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Compressed<T> {
+    #[inline]
+    fn from(it: T) -> Self {
+        Self(it)
+    }
+}
+
+impl<T> Deref for Compressed<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Compressed<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T: StableType> StableType for Compressed<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.0.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.0.stable_drop_flag_off();
+    }
+}
+
+const RAW: u8 = 0;
+const LZ4: u8 = 1;
+
+/// Encoded as a tag byte, followed either by the original encoding of `T`, or by its length ([u32])
+/// and the LZ4 block, containing it.
+impl<T: AsDynSizeBytes> AsDynSizeBytes for Compressed<T> {
+    fn as_dyn_size_bytes(&self) -> Vec<u8> {
+        let raw = self.0.as_dyn_size_bytes();
+
+        if raw.len() >= COMPRESSION_THRESHOLD {
+            let block = lz4_compress(&raw);
+
+            if block.len() + u32::SIZE < raw.len() {
+                let mut buf = Vec::with_capacity(1 + u32::SIZE + block.len());
+                buf.push(LZ4);
+                buf.extend((raw.len() as u32).to_le_bytes());
+                buf.extend(block);
+
+                return buf;
+            }
+        }
+
+        let mut buf = Vec::with_capacity(1 + raw.len());
+        buf.push(RAW);
+        buf.extend(raw);
+
+        buf
+    }
+
+    fn from_dyn_size_bytes(buf: &[u8]) -> Self {
+        match buf[0] {
+            RAW => Self(T::from_dyn_size_bytes(&buf[1..])),
+            LZ4 => {
+                let mut len_buf = [0u8; 4];
+                len_buf.copy_from_slice(&buf[1..(1 + u32::SIZE)]);
+
+                let raw = lz4_decompress(
+                    &buf[(1 + u32::SIZE)..],
+                    u32::from_le_bytes(len_buf) as usize,
+                );
+
+                Self(T::from_dyn_size_bytes(&raw))
+            }
+            it => panic!("Unknown compression tag {}", it),
+        }
+    }
+}
+
+const MIN_MATCH: usize = 4;
+// the last match should start at least 12 bytes before the end of the block
+const MF_LIMIT: usize = 12;
+// the last 5 bytes of the block are always literals
+const LAST_LITERALS: usize = 5;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+#[inline]
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+#[inline]
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_len_tail(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+
+    out.push(len as u8);
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], match_info: Option<(usize, usize)>) {
+    let lit_len = literals.len();
+    let match_len = match_info
+        .map(|(_, len)| len - MIN_MATCH)
+        .unwrap_or_default();
+
+    out.push(((lit_len.min(15) as u8) << 4) | match_len.min(15) as u8);
+    if lit_len >= 15 {
+        write_len_tail(out, lit_len - 15);
+    }
+    out.extend_from_slice(literals);
+
+    if let Some((offset, _)) = match_info {
+        out.extend((offset as u16).to_le_bytes());
+
+        if match_len >= 15 {
+            write_len_tail(out, match_len - 15);
+        }
+    }
+}
+
+/// Compresses the input into a single LZ4 block, using a greedy single-pass matcher
+pub(crate) fn lz4_compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2);
+    // positions are stored incremented by one, zero means "empty"
+    let mut table = vec![0usize; 1 << HASH_BITS];
+
+    let mut anchor = 0;
+    let mut i = 0;
+
+    if input.len() > MF_LIMIT {
+        let limit = input.len() - MF_LIMIT;
+        let match_limit = input.len() - LAST_LITERALS;
+
+        while i < limit {
+            let seq = read_u32(input, i);
+            let h = hash(seq);
+
+            let candidate = table[h];
+            table[h] = i + 1;
+
+            if candidate > 0
+                && i - (candidate - 1) <= MAX_OFFSET
+                && read_u32(input, candidate - 1) == seq
+            {
+                let from = candidate - 1;
+
+                let mut len = MIN_MATCH;
+                while i + len < match_limit && input[from + len] == input[i + len] {
+                    len += 1;
+                }
+
+                write_sequence(&mut out, &input[anchor..i], Some((i - from, len)));
+
+                i += len;
+                anchor = i;
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    write_sequence(&mut out, &input[anchor..], None);
+
+    out
+}
+
+/// Decompresses a single LZ4 block, which is known to contain exactly `len` bytes
+///
+/// # Panics
+/// Panics if the block is malformed.
+pub(crate) fn lz4_decompress(block: &[u8], len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 0;
+
+    let read_len_tail = |pos: &mut usize| {
+        let mut len = 0;
+
+        loop {
+            let b = block[*pos];
+            *pos += 1;
+            len += b as usize;
+
+            if b != 255 {
+                return len;
+            }
+        }
+    };
+
+    loop {
+        let token = block[pos];
+        pos += 1;
+
+        let mut lit_len = (token >> 4) as usize;
+        if lit_len == 15 {
+            lit_len += read_len_tail(&mut pos);
+        }
+
+        out.extend_from_slice(&block[pos..(pos + lit_len)]);
+        pos += lit_len;
+
+        if out.len() >= len {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([block[pos], block[pos + 1]]) as usize;
+        pos += 2;
+
+        let mut match_len = (token & 15) as usize;
+        if match_len == 15 {
+            match_len += read_len_tail(&mut pos);
+        }
+        match_len += MIN_MATCH;
+
+        assert!(offset > 0 && offset <= out.len(), "Invalid LZ4 block");
+
+        // matches may overlap with the bytes they produce, so copy byte by byte
+        let from = out.len() - offset;
+        for i in 0..match_len {
+            out.push(out[from + i]);
+        }
+    }
+
+    assert_eq!(out.len(), len, "Invalid LZ4 block");
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::compression::{
+        lz4_compress, lz4_decompress, Compressed, SCompressedBox, COMPRESSION_THRESHOLD,
+    };
+    use crate::encoding::AsDynSizeBytes;
+    use crate::primitive::s_box::SBox;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init};
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn lz4_works_fine() {
+        let mut rng = thread_rng();
+
+        let mut inputs = vec![
+            vec![],
+            vec![1u8; 5],
+            vec![1u8; 13],
+            vec![7u8; 100_000],
+            b"abcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabc".to_vec(),
+        ];
+
+        for _ in 0..100 {
+            let len = rng.gen_range(0..5000);
+            let alphabet = rng.gen_range(1..=255u8);
+
+            inputs.push((0..len).map(|_| rng.gen_range(0..alphabet)).collect());
+        }
+
+        for input in inputs {
+            let block = lz4_compress(&input);
+
+            assert_eq!(lz4_decompress(&block, input.len()), input);
+        }
+    }
+
+    #[test]
+    fn compression_works_fine() {
+        let doc =
+            r#"{"id": 1, "name": "Alice", "email": "alice@example.com", "tags": []}"#.repeat(50);
+
+        let compressed = Compressed(doc.clone()).as_dyn_size_bytes();
+        assert!(compressed.len() * 5 < doc.len());
+
+        let mut buf = compressed.clone();
+        buf.extend(vec![1u8; 10]);
+        assert_eq!(Compressed::<String>::from_dyn_size_bytes(&buf).0, doc);
+
+        // small values are stored as is
+        let small = String::from("small");
+        let raw = Compressed(small.clone()).as_dyn_size_bytes();
+        assert_eq!(raw.len(), small.as_dyn_size_bytes().len() + 1);
+        assert_eq!(Compressed::<String>::from_dyn_size_bytes(&raw).0, small);
+
+        // incompressible values too
+        let noise: Vec<u8> = (0..COMPRESSION_THRESHOLD * 2)
+            .map(|_| thread_rng().gen())
+            .collect();
+        let raw = Compressed(noise.clone()).as_dyn_size_bytes();
+        assert_eq!(raw.len(), noise.as_dyn_size_bytes().len() + 1);
+        assert_eq!(Compressed::<Vec<u8>>::from_dyn_size_bytes(&raw).0, noise);
+    }
+
+    #[test]
+    fn boxes_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let doc = "lorem ipsum dolor sit amet ".repeat(100);
+
+            let mut b: SCompressedBox<String> = SBox::new(Compressed(doc.clone())).unwrap();
+            assert_eq!(b.as_str(), doc);
+            assert!(get_allocated_size() * 10 < doc.len() as u64);
+
+            b.with(|it| it.push_str("the end")).unwrap();
+
+            let b = unsafe { SCompressedBox::<String>::from_ptr(b.as_ptr()) };
+            assert!(b.ends_with("amet the end"));
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,165 @@
+//! Fixed size encoding of collection headers - a pointer, followed by a couple of [u64] counters.
+//!
+//! Counters used to be [usize], which takes only 4 bytes on wasm32 (see the `wasm64` feature). To
+//! keep collections written by older versions readable, the header layout stays the same:
+//! `ptr: u64` and then `N` counters of `usize::SIZE` bytes each. When `usize` takes 8 bytes, counters
+//! are simply stored as is. Otherwise the low 32 bits of each counter go into their usual place, while
+//! the high 8 bits are packed into the upper bytes of the pointer - stable memory pointers never
+//! exceed 40 bits, so these bytes were always zeroed before. This way legacy headers are decoded
+//! exactly as they were written and get the new layout with the next update, no migration needed.
+//!
+//! Counters are therefore limited to 40 bits, which is exactly [MAX_BLOCK_SIZE] - no collection can
+//! store more elements than that anyway (see [max_elements](crate::utils::math::max_elements)).
+
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::s_slice::MAX_BLOCK_SIZE;
+use crate::mem::StablePtr;
+
+const PTR_BITS: u32 = 40;
+const PTR_MASK: u64 = (1 << PTR_BITS) - 1;
+const COUNTER_LOW_MASK: u64 = u32::MAX as u64;
+
+/// Size of a header of a pointer and `n` counters
+pub(crate) const fn header_size(n: usize) -> usize {
+    u64::SIZE + usize::SIZE * n
+}
+
+/// Writes the pointer and counters into `buf`, which should be [header_size] bytes long
+#[inline]
+pub(crate) fn encode_header<const N: usize>(ptr: StablePtr, counters: [u64; N], buf: &mut [u8]) {
+    if usize::SIZE == u64::SIZE {
+        encode_wide(ptr, counters, buf);
+    } else {
+        encode_packed(ptr, counters, buf);
+    }
+}
+
+/// Reads the pointer and counters, written by [encode_header]
+#[inline]
+pub(crate) fn decode_header<const N: usize>(buf: &[u8]) -> (StablePtr, [u64; N]) {
+    if usize::SIZE == u64::SIZE {
+        decode_wide(buf)
+    } else {
+        decode_packed(buf)
+    }
+}
+
+fn encode_wide<const N: usize>(ptr: StablePtr, counters: [u64; N], buf: &mut [u8]) {
+    ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+
+    for (i, counter) in counters.into_iter().enumerate() {
+        let from = u64::SIZE * (i + 1);
+        counter.as_fixed_size_bytes(&mut buf[from..(from + u64::SIZE)]);
+    }
+}
+
+fn decode_wide<const N: usize>(buf: &[u8]) -> (StablePtr, [u64; N]) {
+    let ptr = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
+    let counters = core::array::from_fn(|i| {
+        let from = u64::SIZE * (i + 1);
+        u64::from_fixed_size_bytes(&buf[from..(from + u64::SIZE)])
+    });
+
+    (ptr, counters)
+}
+
+fn encode_packed<const N: usize>(ptr: StablePtr, counters: [u64; N], buf: &mut [u8]) {
+    debug_assert!(N <= (u64::BITS - PTR_BITS) as usize / 8);
+
+    let mut packed_ptr = ptr;
+
+    if ptr != EMPTY_PTR {
+        debug_assert!(ptr <= PTR_MASK);
+
+        for (i, counter) in counters.iter().enumerate() {
+            packed_ptr |= (counter >> u32::BITS) << (PTR_BITS + i as u32 * 8);
+        }
+    }
+
+    packed_ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+
+    for (i, counter) in counters.into_iter().enumerate() {
+        debug_assert!(counter <= MAX_BLOCK_SIZE);
+        debug_assert!(ptr != EMPTY_PTR || counter <= COUNTER_LOW_MASK);
+
+        let from = u64::SIZE + u32::SIZE * i;
+        ((counter & COUNTER_LOW_MASK) as u32)
+            .as_fixed_size_bytes(&mut buf[from..(from + u32::SIZE)]);
+    }
+}
+
+fn decode_packed<const N: usize>(buf: &[u8]) -> (StablePtr, [u64; N]) {
+    let packed_ptr = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
+    let is_empty = packed_ptr == EMPTY_PTR;
+
+    let ptr = if is_empty {
+        EMPTY_PTR
+    } else {
+        packed_ptr & PTR_MASK
+    };
+
+    let counters = core::array::from_fn(|i| {
+        let from = u64::SIZE + u32::SIZE * i;
+        let low = u32::from_fixed_size_bytes(&buf[from..(from + u32::SIZE)]) as u64;
+
+        if is_empty {
+            low
+        } else {
+            let high = (packed_ptr >> (PTR_BITS + i as u32 * 8)) & 0xFF;
+            (high << u32::BITS) | low
+        }
+    });
+
+    (ptr, counters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_works_fine() {
+        let mut buf = [0u8; u64::SIZE * 4];
+
+        encode_wide(123, [u32::MAX as u64 + 10, 0, MAX_BLOCK_SIZE], &mut buf);
+        assert_eq!(
+            decode_wide::<3>(&buf),
+            (123, [u32::MAX as u64 + 10, 0, MAX_BLOCK_SIZE])
+        );
+    }
+
+    #[test]
+    fn packed_works_fine() {
+        let mut buf = [0u8; u64::SIZE + u32::SIZE * 3];
+
+        for (ptr, counters) in [
+            (PTR_MASK, [MAX_BLOCK_SIZE, u32::MAX as u64 + 1, 15]),
+            (8, [0, MAX_BLOCK_SIZE - 1, u32::MAX as u64]),
+            (EMPTY_PTR, [0, 4, u32::MAX as u64]),
+        ] {
+            encode_packed(ptr, counters, &mut buf);
+            assert_eq!(decode_packed::<3>(&buf), (ptr, counters));
+        }
+    }
+
+    #[test]
+    fn legacy_packed_headers_are_readable() {
+        // a header, written by a previous version with 4-byte usize counters
+        let mut buf = [0u8; u64::SIZE + u32::SIZE * 2];
+        buf[0..8].copy_from_slice(&1_000_000u64.to_le_bytes());
+        buf[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        buf[12..16].copy_from_slice(&7u32.to_le_bytes());
+
+        assert_eq!(decode_packed::<2>(&buf), (1_000_000, [u32::MAX as u64, 7]));
+
+        buf[0..8].copy_from_slice(&EMPTY_PTR.to_le_bytes());
+        buf[8..12].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(decode_packed::<2>(&buf), (EMPTY_PTR, [0, 7]));
+
+        // and the new layout reads the same bytes
+        let mut encoded = [0u8; u64::SIZE + u32::SIZE * 2];
+        encode_packed(EMPTY_PTR, [0, 7], &mut encoded);
+        assert_eq!(encoded, buf);
+    }
+}
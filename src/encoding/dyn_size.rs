@@ -1,6 +1,4 @@
-use candid::de::IDLDeserialize;
-use candid::utils::ArgumentDecoder;
-use candid::{CandidType, Deserialize, Result};
+use serde::{Deserialize, Serialize};
 
 /// Trait allowing encoding and decoding of unsized data.
 ///
@@ -16,6 +14,12 @@ use candid::{CandidType, Deserialize, Result};
 /// already implement [candid::CandidType] and [candid::Deserialize].
 /// 2. [derive::FixedSizeAsDynSizeBytes] implements this trait for types which already
 /// implement [AsFixedSizeBytes].
+/// 3. [derive::VersionedAsDynSizeBytes] works like [derive::CandidAsDynSizeBytes], but also
+///    prepends a version tag, so values of previous versions of a type can still be decoded.
+/// 4. [derive::CompactAsDynSizeBytes] implements this trait for types which fields already
+///    implement it, using a compact binary encoding instead of candid.
+/// 5. [derive::CborAsDynSizeBytes] implements this trait for types which already implement
+///    [serde::Serialize] and [serde::Deserialize], encoding them into CBOR.
 pub trait AsDynSizeBytes {
     /// Encodes self into vector of bytes
     ///
@@ -23,6 +27,19 @@ pub trait AsDynSizeBytes {
     /// Should panic if data encoding failed.
     fn as_dyn_size_bytes(&self) -> Vec<u8>;
 
+    /// Appends the encoding of self to the buffer
+    ///
+    /// Used by [SBox] to encode values straight into pooled buffers. The default implementation
+    /// copies the result of [AsDynSizeBytes::as_dyn_size_bytes] - override it, if the encoding
+    /// can be written without a temporary vector.
+    ///
+    /// # Panics
+    /// Should panic if data encoding failed.
+    #[inline]
+    fn as_dyn_size_bytes_into(&self, buf: &mut Vec<u8>) {
+        buf.extend(self.as_dyn_size_bytes());
+    }
+
     /// Decodes self from a slice of bytes.
     ///
     /// # Important
@@ -35,6 +52,53 @@ pub trait AsDynSizeBytes {
     fn from_dyn_size_bytes(buf: &[u8]) -> Self;
 }
 
+/// Trait converting a previous version of a type into the next one.
+///
+/// Used by [derive::VersionedAsDynSizeBytes] to decode values, which were encoded before the type
+/// has changed. Each previous version is upgraded step by step, until it becomes the current one.
+///
+/// # Example
+/// ```rust
+/// # use candid::{CandidType, Deserialize};
+/// # use ic_stable_memory::derive::VersionedAsDynSizeBytes;
+/// # use ic_stable_memory::encoding::Upgrade;
+/// # use ic_stable_memory::AsDynSizeBytes;
+/// #[derive(CandidType, Deserialize)]
+/// struct UserV1 {
+///     name: String,
+/// }
+///
+/// impl Upgrade for UserV1 {
+///     type Next = User;
+///
+///     fn upgrade(self) -> User {
+///         User { name: self.name, age: None }
+///     }
+/// }
+///
+/// #[derive(CandidType, Deserialize, VersionedAsDynSizeBytes, Debug, PartialEq)]
+/// #[version(2)]
+/// #[version(1, UserV1)]
+/// struct User {
+///     name: String,
+///     age: Option<u32>,
+/// }
+///
+/// // this is how a value looked like, when User had only one version
+/// let mut buf = 1u32.to_le_bytes().to_vec();
+/// buf.extend(candid::encode_one(UserV1 { name: String::from("Alice") }).unwrap());
+///
+/// let user = User::from_dyn_size_bytes(&buf);
+/// assert_eq!(user, User { name: String::from("Alice"), age: None });
+/// ```
+pub trait Upgrade {
+    /// The next version of this type
+    type Next;
+
+    /// Converts self into the next version
+    fn upgrade(self) -> Self::Next;
+}
+
 #[cfg(not(feature = "custom_dyn_encoding"))]
 use crate::encoding::AsFixedSizeBytes;
 
@@ -51,6 +115,14 @@ impl<T: AsFixedSizeBytes> AsDynSizeBytes for T {
         v
     }
 
+    #[inline]
+    fn as_dyn_size_bytes_into(&self, buf: &mut Vec<u8>) {
+        let from = buf.len();
+        buf.resize(from + T::SIZE, 0);
+
+        self.as_fixed_size_bytes(&mut buf[from..]);
+    }
+
     #[inline]
     fn from_dyn_size_bytes(buf: &[u8]) -> Self {
         Self::from_fixed_size_bytes(&buf[0..T::SIZE])
@@ -69,6 +141,12 @@ impl AsDynSizeBytes for Vec<u8> {
         v
     }
 
+    #[inline]
+    fn as_dyn_size_bytes_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.len().as_new_fixed_size_bytes());
+        buf.extend_from_slice(self);
+    }
+
     #[inline]
     fn from_dyn_size_bytes(buf: &[u8]) -> Self {
         let len = usize::from_fixed_size_bytes(&buf[0..usize::SIZE]);
@@ -92,6 +170,12 @@ impl AsDynSizeBytes for String {
         v
     }
 
+    #[inline]
+    fn as_dyn_size_bytes_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.len().as_new_fixed_size_bytes());
+        buf.extend_from_slice(self.as_bytes());
+    }
+
     #[inline]
     fn from_dyn_size_bytes(buf: &[u8]) -> Self {
         let len = usize::from_fixed_size_bytes(&buf[0..usize::SIZE]);
@@ -103,20 +187,207 @@ impl AsDynSizeBytes for String {
     }
 }
 
-pub fn candid_decode_args_allow_trailing<'a, Tuple>(bytes: &'a [u8]) -> Result<Tuple>
+/// Decodes candid arguments, ignoring trailing bytes
+///
+/// Works with types of the candid version, selected by `candid_0_8` (default), `candid_0_9` or
+/// `candid_0_10` feature, see [crate::candid]. Derive macros of this crate use it too, if one of
+/// these features is enabled. Otherwise, they decode values with the candid version of the user's
+/// crate.
+#[cfg(any(
+    feature = "candid_0_8",
+    feature = "candid_0_9",
+    feature = "candid_0_10"
+))]
+pub fn candid_decode_args_allow_trailing<'a, Tuple>(bytes: &'a [u8]) -> crate::candid::Result<Tuple>
 where
-    Tuple: ArgumentDecoder<'a>,
+    Tuple: crate::candid::utils::ArgumentDecoder<'a>,
 {
-    let mut de = IDLDeserialize::new(bytes)?;
-    let res = ArgumentDecoder::decode(&mut de)?;
+    let mut de = crate::candid::de::IDLDeserialize::new(bytes)?;
+    let res = Tuple::decode(&mut de)?;
 
     Ok(res)
 }
 
-pub fn candid_decode_one_allow_trailing<'a, T>(bytes: &'a [u8]) -> Result<T>
+/// Decodes a single candid value, ignoring trailing bytes
+///
+/// See [candid_decode_args_allow_trailing].
+#[cfg(any(
+    feature = "candid_0_8",
+    feature = "candid_0_9",
+    feature = "candid_0_10"
+))]
+pub fn candid_decode_one_allow_trailing<'a, T>(bytes: &'a [u8]) -> crate::candid::Result<T>
 where
-    T: Deserialize<'a> + CandidType,
+    T: crate::candid::Deserialize<'a> + crate::candid::CandidType,
 {
     let (res,) = candid_decode_args_allow_trailing(bytes)?;
     Ok(res)
 }
+
+/// Encodes the value into CBOR
+///
+/// Used by [derive::CborAsDynSizeBytes].
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::derive::{CborAsDynSizeBytes, StableType};
+/// # use ic_stable_memory::{stable_memory_init, AsDynSizeBytes, SBox};
+/// # use serde::{Deserialize, Serialize};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// #[derive(Serialize, Deserialize, CborAsDynSizeBytes, StableType, Debug, PartialEq)]
+/// struct Asset {
+///     content_type: String,
+///     #[serde(with = "serde_bytes")]
+///     body: Vec<u8>,
+/// }
+///
+/// let asset = Asset { content_type: String::from("text/plain"), body: b"hello".to_vec() };
+/// let asset = SBox::new(asset).expect("Out of memory");
+///
+/// // the same bytes can be served in an HTTP response
+/// let cbor = Asset::as_dyn_size_bytes(&asset);
+/// assert_eq!(serde_cbor::from_slice::<Asset>(&cbor).unwrap(), *asset);
+/// ```
+pub fn cbor_encode<T: Serialize>(value: &T) -> serde_cbor::Result<Vec<u8>> {
+    serde_cbor::to_vec(value)
+}
+
+/// Decodes a value from CBOR, ignoring trailing bytes
+///
+/// Used by [derive::CborAsDynSizeBytes].
+pub fn cbor_decode_allow_trailing<'a, T: Deserialize<'a>>(
+    bytes: &'a [u8],
+) -> serde_cbor::Result<T> {
+    let mut de = serde_cbor::Deserializer::from_slice(bytes);
+
+    T::deserialize(&mut de)
+}
+
+/// Appends the length to the buffer in a variable-length encoding (LEB128)
+///
+/// Lengths less than `128` take a single byte. Used by [derive::CompactAsDynSizeBytes].
+pub fn compact_encode_len(mut len: usize, buf: &mut Vec<u8>) {
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+
+        if len == 0 {
+            buf.push(byte);
+            break;
+        }
+
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Reads a length, encoded with [compact_encode_len], advancing the offset
+pub fn compact_decode_len(buf: &[u8], offset: &mut usize) -> usize {
+    let mut len = 0usize;
+    let mut shift = 0;
+
+    loop {
+        let byte = buf[*offset];
+        *offset += 1;
+
+        len |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return len;
+        }
+    }
+}
+
+/// Appends the encoding of the value to the buffer, prefixed with its length
+///
+/// Used by [derive::CompactAsDynSizeBytes].
+///
+/// # Example
+/// ```rust
+/// # use candid::{CandidType, Deserialize};
+/// # use ic_stable_memory::derive::CompactAsDynSizeBytes;
+/// # use ic_stable_memory::AsDynSizeBytes;
+/// #[derive(CompactAsDynSizeBytes, CandidType, Deserialize, Debug, PartialEq)]
+/// enum Event {
+///     Created { id: u64, title: String },
+///     Deleted(u64),
+/// }
+///
+/// let event = Event::Created { id: 1, title: String::from("Hello") };
+/// let buf = event.as_dyn_size_bytes();
+///
+/// assert_eq!(Event::from_dyn_size_bytes(&buf), event);
+/// assert!(buf.len() < candid::encode_one(&event).unwrap().len());
+/// ```
+pub fn compact_encode_field<T: AsDynSizeBytes>(value: &T, buf: &mut Vec<u8>) {
+    let bytes = value.as_dyn_size_bytes();
+
+    compact_encode_len(bytes.len(), buf);
+    buf.extend(bytes);
+}
+
+/// Reads a value, encoded with [compact_encode_field], advancing the offset
+pub fn compact_decode_field<T: AsDynSizeBytes>(buf: &[u8], offset: &mut usize) -> T {
+    let len = compact_decode_len(buf, offset);
+    let value = T::from_dyn_size_bytes(&buf[*offset..(*offset + len)]);
+    *offset += len;
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::dyn_size::{
+        compact_decode_field, compact_decode_len, compact_encode_field, compact_encode_len,
+    };
+    use crate::AsDynSizeBytes;
+
+    #[test]
+    fn compact_works_fine() {
+        let lens = [0usize, 1, 127, 128, 300, 16383, 16384, u32::MAX as usize];
+        let mut buf = Vec::new();
+
+        for len in lens {
+            compact_encode_len(len, &mut buf);
+        }
+
+        assert_eq!(&buf[0..3], &[0, 1, 127]);
+        assert_eq!(&buf[3..5], &[0x80, 0x01]);
+
+        let mut offset = 0;
+        for len in lens {
+            assert_eq!(compact_decode_len(&buf, &mut offset), len);
+        }
+        assert_eq!(offset, buf.len());
+
+        let mut buf = Vec::new();
+        compact_encode_field(&String::from("hello"), &mut buf);
+        compact_encode_field(&10u64, &mut buf);
+        buf.extend([1, 2, 3]);
+
+        let mut offset = 0;
+        assert_eq!(
+            compact_decode_field::<String>(&buf, &mut offset),
+            String::from("hello")
+        );
+        assert_eq!(compact_decode_field::<u64>(&buf, &mut offset), 10);
+        assert_eq!(offset, buf.len() - 3);
+    }
+
+    #[test]
+    fn encoding_into_buffer_works_fine() {
+        let mut buf = vec![1, 2, 3];
+
+        10u64.as_dyn_size_bytes_into(&mut buf);
+        vec![4u8, 5].as_dyn_size_bytes_into(&mut buf);
+        String::from("hello").as_dyn_size_bytes_into(&mut buf);
+
+        let mut expected = vec![1, 2, 3];
+        expected.extend(10u64.as_dyn_size_bytes());
+        expected.extend(vec![4u8, 5].as_dyn_size_bytes());
+        expected.extend(String::from("hello").as_dyn_size_bytes());
+
+        assert_eq!(buf, expected);
+    }
+}
@@ -6,8 +6,9 @@
 //! types in `[u8; N]`, which is very good, since arrays are stack-based data structures and don't
 //! involve expensive heap allocations, so most types encode themselves into such generic arrays.
 //!
-//! Generic types (such as [Option]) are not yet compatible with constant generics and therefore they
-//! are encoded to [Vec] of [u8].
+//! Generic types (such as [Option] and tuples) are not yet compatible with constant generics and
+//! therefore they are encoded to [Vec] of [u8]. Generic arrays are encoded to [ArrayBuf], which stays
+//! on stack for arrays of single-byte elements, such as `[u8; N]`.
 //!
 //! [AsFixedSizeBytes] trait encapusaltes these differences providing a simple API.
 
@@ -15,16 +16,26 @@ use candid::{Int, Nat, Principal};
 use ic_stable_memory_derive::{AsFixedSizeBytes, StableType};
 use num_bigint::{BigInt, BigUint, Sign};
 use ic_ledger_types::Subaccount;
+use std::cmp::Ordering;
+use std::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU128,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize,
+};
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Allows fast and space-efficient fixed size data encoding.
 ///
 /// This trait can be implemented by using [derive::AsFixedSizeBytes] macro.
 /// By default it is implemented for the following types:
 /// 1. All primitive types: [i8], [u8], [i16], [u16], [i32], [u32], [i64], [u64], [i128], [u128], [f32], [f64], [bool], [()]
-/// 2. Primitive type generic arrays: [i8; N], [u8; N], [i16; N], [u16; N], [i32; N], [u32; N], [i64: N], [u64; N], [i128; N], [u128; N], [f32; N], [f64; N], [bool; N], [(); N]
-/// 3. Tuples up to 6 elements, where each element implements [AsFixedSizeBytes]
-/// 4. [Option] of `T`, where `T`: [AsFixedSizeBytes]
-/// 5. IC native types: [candid::Principal], [candid::Nat], [candid::Int]
+/// 2. Generic arrays `[T; N]`, where `T`: [AsFixedSizeBytes]
+/// 3. Tuples up to 12 elements, where each element implements [AsFixedSizeBytes]
+/// 4. [Option] of `T` and [Result] of `T` and `E`, where `T`, `E`: [AsFixedSizeBytes]
+/// 5. Other std types: [NonZeroU64](std::num::NonZeroU64) (and the rest of `NonZero*` integers),
+///    [Duration](std::time::Duration), [SystemTime](std::time::SystemTime), [Ordering](std::cmp::Ordering), [char]
+/// 6. IC native types: [candid::Principal], [candid::Nat], [candid::Int] and their bounded versions
+///    from [candid_num](crate::encoding::candid_num)
 pub trait AsFixedSizeBytes {
     /// Size of self when encoded
     const SIZE: usize;
@@ -85,11 +96,41 @@ impl_for_number!(i64);
 impl_for_number!(u64);
 impl_for_number!(i128);
 impl_for_number!(u128);
+#[cfg(not(feature = "wasm64"))]
 impl_for_number!(isize);
+#[cfg(not(feature = "wasm64"))]
 impl_for_number!(usize);
 impl_for_number!(f32);
 impl_for_number!(f64);
 
+// with `wasm64` feature pointer-sized numbers always take 8 bytes, so the data written on wasm32
+// can still be read, once the canister is moved to wasm64
+#[cfg(feature = "wasm64")]
+macro_rules! impl_for_pointer_sized_number {
+    ($ty:ty, $wide:ty) => {
+        impl AsFixedSizeBytes for $ty {
+            const SIZE: usize = <$wide>::SIZE;
+            type Buf = [u8; Self::SIZE];
+
+            #[inline]
+            fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+                (*self as $wide).as_fixed_size_bytes(buf)
+            }
+
+            #[inline]
+            fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+                <$ty>::try_from(<$wide>::from_fixed_size_bytes(buf))
+                    .expect("The value does not fit into the pointer width of this target")
+            }
+        }
+    };
+}
+
+#[cfg(feature = "wasm64")]
+impl_for_pointer_sized_number!(isize, i64);
+#[cfg(feature = "wasm64")]
+impl_for_pointer_sized_number!(usize, u64);
+
 impl AsFixedSizeBytes for char {
     const SIZE: usize = u32::SIZE;
     type Buf = [u8; Self::SIZE];
@@ -135,6 +176,9 @@ impl AsFixedSizeBytes for bool {
     }
 }
 
+/// Encoded as a presence byte (`0` or `1`), followed by `T::SIZE` bytes of payload.
+///
+/// The payload of [None] is filled with zeroes, so equal values always have equal encodings.
 impl<T: AsFixedSizeBytes> AsFixedSizeBytes for Option<T> {
     const SIZE: usize = T::SIZE + 1;
     type Buf = Vec<u8>;
@@ -145,12 +189,14 @@ impl<T: AsFixedSizeBytes> AsFixedSizeBytes for Option<T> {
             buf[0] = 1;
             it.as_fixed_size_bytes(&mut buf[1..Self::SIZE]);
         } else {
-            buf[0] = 0;
+            buf[0..Self::SIZE].fill(0);
         }
     }
 
     #[inline]
     fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        assert!(buf[0] < 2);
+
         if buf[0] == 1 {
             Some(T::from_fixed_size_bytes(&buf[1..Self::SIZE]))
         } else {
@@ -159,292 +205,214 @@ impl<T: AsFixedSizeBytes> AsFixedSizeBytes for Option<T> {
     }
 }
 
-impl<const N: usize> AsFixedSizeBytes for [(); N] {
-    const SIZE: usize = 0;
-    type Buf = [u8; 0];
-
-    #[inline]
-    fn as_fixed_size_bytes(&self, _: &mut [u8]) {}
+/// Encoded as a tag byte (`0` for [Ok], `1` for [Err]), followed by `max(T::SIZE, E::SIZE)` bytes of
+/// payload.
+///
+/// Unused bytes of the payload are filled with zeroes, so equal values always have equal encodings.
+impl<T: AsFixedSizeBytes, E: AsFixedSizeBytes> AsFixedSizeBytes for Result<T, E> {
+    const SIZE: usize = if T::SIZE > E::SIZE {
+        T::SIZE + 1
+    } else {
+        E::SIZE + 1
+    };
+    type Buf = Vec<u8>;
 
     #[inline]
-    fn from_fixed_size_bytes(_: &[u8]) -> Self {
-        [(); N]
-    }
-}
-
-macro_rules! impl_for_single_byte_type_arr {
-    ($ty:ty, $zero:expr) => {
-        impl<const N: usize> AsFixedSizeBytes for [$ty; N] {
-            const SIZE: usize = N;
-            type Buf = [u8; N];
-
-            fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-                for i in 0..N {
-                    let from = i * <$ty>::SIZE;
-                    let to = from + <$ty>::SIZE;
-
-                    self[i].as_fixed_size_bytes(&mut buf[from..to]);
-                }
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        match self {
+            Ok(it) => {
+                buf[0] = 0;
+                it.as_fixed_size_bytes(&mut buf[1..(1 + T::SIZE)]);
+                buf[(1 + T::SIZE)..Self::SIZE].fill(0);
             }
+            Err(e) => {
+                buf[0] = 1;
+                e.as_fixed_size_bytes(&mut buf[1..(1 + E::SIZE)]);
+                buf[(1 + E::SIZE)..Self::SIZE].fill(0);
+            }
+        }
+    }
 
-            fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-                let mut it = [$zero; N];
-
-                for i in 0..N {
-                    let from = i * <$ty>::SIZE;
-                    let to = from + <$ty>::SIZE;
-
-                    it[i] = <$ty>::from_fixed_size_bytes(&buf[from..to]);
-                }
+    #[inline]
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        assert!(buf[0] < 2);
 
-                it
-            }
+        if buf[0] == 0 {
+            Ok(T::from_fixed_size_bytes(&buf[1..(1 + T::SIZE)]))
+        } else {
+            Err(E::from_fixed_size_bytes(&buf[1..(1 + E::SIZE)]))
         }
-    };
+    }
 }
 
-impl_for_single_byte_type_arr!(bool, false);
-impl_for_single_byte_type_arr!(i8, 0);
-impl_for_single_byte_type_arr!(u8, 0);
-
-macro_rules! impl_for_number_arr {
-    ($ty:ty, $zero:expr) => {
-        impl<const N: usize> AsFixedSizeBytes for [$ty; N] {
-            const SIZE: usize = N;
-            type Buf = Vec<u8>;
+macro_rules! impl_for_non_zero {
+    ($ty:ty, $inner:ty) => {
+        impl AsFixedSizeBytes for $ty {
+            const SIZE: usize = <$inner>::SIZE;
+            type Buf = [u8; Self::SIZE];
 
+            #[inline]
             fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-                for i in 0..N {
-                    let from = i * <$ty>::SIZE;
-                    let to = from + <$ty>::SIZE;
-
-                    self[i].as_fixed_size_bytes(&mut buf[from..to]);
-                }
+                self.get().as_fixed_size_bytes(buf)
             }
 
+            #[inline]
             fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-                let mut it = [$zero; N];
-
-                for i in 0..N {
-                    let from = i * <$ty>::SIZE;
-                    let to = from + <$ty>::SIZE;
-
-                    it[i] = <$ty>::from_fixed_size_bytes(&buf[from..to]);
-                }
-
-                it
+                <$ty>::new(<$inner>::from_fixed_size_bytes(buf)).unwrap()
             }
         }
     };
 }
 
-impl_for_number_arr!(i16, 0);
-impl_for_number_arr!(u16, 0);
-impl_for_number_arr!(i32, 0);
-impl_for_number_arr!(u32, 0);
-impl_for_number_arr!(i64, 0);
-impl_for_number_arr!(u64, 0);
-impl_for_number_arr!(i128, 0);
-impl_for_number_arr!(u128, 0);
-impl_for_number_arr!(isize, 0);
-impl_for_number_arr!(usize, 0);
-impl_for_number_arr!(f32, 0.0);
-impl_for_number_arr!(f64, 0.0);
-
-impl<const N: usize> AsFixedSizeBytes for [char; N] {
-    const SIZE: usize = N * char::SIZE;
-    type Buf = Vec<u8>;
+impl_for_non_zero!(NonZeroI8, i8);
+impl_for_non_zero!(NonZeroU8, u8);
+impl_for_non_zero!(NonZeroI16, i16);
+impl_for_non_zero!(NonZeroU16, u16);
+impl_for_non_zero!(NonZeroI32, i32);
+impl_for_non_zero!(NonZeroU32, u32);
+impl_for_non_zero!(NonZeroI64, i64);
+impl_for_non_zero!(NonZeroU64, u64);
+impl_for_non_zero!(NonZeroI128, i128);
+impl_for_non_zero!(NonZeroU128, u128);
+impl_for_non_zero!(NonZeroIsize, isize);
+impl_for_non_zero!(NonZeroUsize, usize);
+
+/// Encoded as whole seconds ([u64]), followed by the fractional part in nanoseconds ([u32]).
+impl AsFixedSizeBytes for Duration {
+    const SIZE: usize = u64::SIZE + u32::SIZE;
+    type Buf = [u8; Self::SIZE];
 
+    #[inline]
     fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        let mut from = 0;
-
-        for c in self {
-            c.as_fixed_size_bytes(&mut buf[from..(from + u32::SIZE)]);
-            from += u32::SIZE;
-        }
+        self.as_secs().as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+        self.subsec_nanos()
+            .as_fixed_size_bytes(&mut buf[u64::SIZE..Self::SIZE]);
     }
 
+    #[inline]
     fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        let mut s = [char::default(); N];
-
-        for from in 0..N {
-            s[from] =
-                char::from_fixed_size_bytes(&buf[(from * u32::SIZE)..((from + 1) * u32::SIZE)]);
-        }
-
-        s
+        Duration::new(
+            u64::from_fixed_size_bytes(&buf[0..u64::SIZE]),
+            u32::from_fixed_size_bytes(&buf[u64::SIZE..Self::SIZE]),
+        )
     }
 }
 
-impl<A: AsFixedSizeBytes> AsFixedSizeBytes for (A,) {
-    const SIZE: usize = A::SIZE;
-    type Buf = Vec<u8>;
+/// Encoded as nanoseconds since [UNIX_EPOCH] ([u64]), the same way `ic_cdk::api::time()` returns it.
+///
+/// # Panics
+/// Encoding panics for moments before [UNIX_EPOCH] and after the year 2554.
+impl AsFixedSizeBytes for SystemTime {
+    const SIZE: usize = u64::SIZE;
+    type Buf = [u8; Self::SIZE];
 
+    #[inline]
     fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        self.0.as_fixed_size_bytes(buf)
+        let nanos = self
+            .duration_since(UNIX_EPOCH)
+            .expect("SystemTime before UNIX_EPOCH can't be encoded")
+            .as_nanos();
+
+        u64::try_from(nanos)
+            .expect("SystemTime is too far in the future")
+            .as_fixed_size_bytes(buf)
     }
 
+    #[inline]
     fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        (A::from_fixed_size_bytes(buf),)
+        UNIX_EPOCH + Duration::from_nanos(u64::from_fixed_size_bytes(buf))
     }
 }
-impl<A: AsFixedSizeBytes, B: AsFixedSizeBytes> AsFixedSizeBytes for (A, B) {
-    const SIZE: usize = A::SIZE + B::SIZE;
-    type Buf = Vec<u8>;
 
+/// Encoded as an [i8]: `-1`, `0` or `1`.
+impl AsFixedSizeBytes for Ordering {
+    const SIZE: usize = i8::SIZE;
+    type Buf = [u8; Self::SIZE];
+
+    #[inline]
     fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        self.0.as_fixed_size_bytes(&mut buf[0..A::SIZE]);
-        self.1
-            .as_fixed_size_bytes(&mut buf[A::SIZE..(A::SIZE + B::SIZE)]);
+        (*self as i8).as_fixed_size_bytes(buf)
     }
 
+    #[inline]
     fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        (
-            A::from_fixed_size_bytes(&buf[0..A::SIZE]),
-            B::from_fixed_size_bytes(&buf[A::SIZE..(A::SIZE + B::SIZE)]),
-        )
+        match i8::from_fixed_size_bytes(buf) {
+            -1 => Ordering::Less,
+            0 => Ordering::Equal,
+            1 => Ordering::Greater,
+            it => panic!("Invalid Ordering {}", it),
+        }
     }
 }
-impl<A: AsFixedSizeBytes, B: AsFixedSizeBytes, C: AsFixedSizeBytes> AsFixedSizeBytes for (A, B, C) {
-    const SIZE: usize = A::SIZE + B::SIZE + C::SIZE;
-    type Buf = Vec<u8>;
+
+/// Encoded as `N` consecutive encodings of `T`.
+impl<T: AsFixedSizeBytes, const N: usize> AsFixedSizeBytes for [T; N] {
+    const SIZE: usize = N * T::SIZE;
+    type Buf = ArrayBuf<N>;
 
     fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        self.0.as_fixed_size_bytes(&mut buf[0..A::SIZE]);
-        self.1
-            .as_fixed_size_bytes(&mut buf[A::SIZE..(A::SIZE + B::SIZE)]);
-        self.2
-            .as_fixed_size_bytes(&mut buf[(A::SIZE + B::SIZE)..(A::SIZE + B::SIZE + C::SIZE)]);
+        for (i, it) in self.iter().enumerate() {
+            let from = i * T::SIZE;
+
+            it.as_fixed_size_bytes(&mut buf[from..(from + T::SIZE)]);
+        }
     }
 
     fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        (
-            A::from_fixed_size_bytes(&buf[0..A::SIZE]),
-            B::from_fixed_size_bytes(&buf[A::SIZE..(A::SIZE + B::SIZE)]),
-            C::from_fixed_size_bytes(&buf[(A::SIZE + B::SIZE)..(A::SIZE + B::SIZE + C::SIZE)]),
-        )
+        std::array::from_fn(|i| {
+            let from = i * T::SIZE;
+
+            T::from_fixed_size_bytes(&buf[from..(from + T::SIZE)])
+        })
     }
 }
-impl<A: AsFixedSizeBytes, B: AsFixedSizeBytes, C: AsFixedSizeBytes, D: AsFixedSizeBytes>
-    AsFixedSizeBytes for (A, B, C, D)
-{
-    const SIZE: usize = A::SIZE + B::SIZE + C::SIZE + D::SIZE;
-    type Buf = Vec<u8>;
 
-    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        self.0.as_fixed_size_bytes(&mut buf[0..A::SIZE]);
-        self.1
-            .as_fixed_size_bytes(&mut buf[A::SIZE..(A::SIZE + B::SIZE)]);
-        self.2
-            .as_fixed_size_bytes(&mut buf[(A::SIZE + B::SIZE)..(A::SIZE + B::SIZE + C::SIZE)]);
-        self.3.as_fixed_size_bytes(
-            &mut buf[(A::SIZE + B::SIZE + C::SIZE)..(A::SIZE + B::SIZE + C::SIZE + D::SIZE)],
-        );
-    }
+/// Encoded as a concatenation of encodings of its elements.
+macro_rules! impl_for_tuple {
+    ($($t:ident $idx:tt),+) => {
+        impl<$($t: AsFixedSizeBytes),+> AsFixedSizeBytes for ($($t,)+) {
+            const SIZE: usize = 0 $(+ $t::SIZE)+;
+            type Buf = Vec<u8>;
 
-    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        (
-            A::from_fixed_size_bytes(&buf[0..A::SIZE]),
-            B::from_fixed_size_bytes(&buf[A::SIZE..(A::SIZE + B::SIZE)]),
-            C::from_fixed_size_bytes(&buf[(A::SIZE + B::SIZE)..(A::SIZE + B::SIZE + C::SIZE)]),
-            D::from_fixed_size_bytes(
-                &buf[(A::SIZE + B::SIZE + C::SIZE)..(A::SIZE + B::SIZE + C::SIZE + D::SIZE)],
-            ),
-        )
-    }
-}
-impl<
-        A: AsFixedSizeBytes,
-        B: AsFixedSizeBytes,
-        C: AsFixedSizeBytes,
-        D: AsFixedSizeBytes,
-        E: AsFixedSizeBytes,
-    > AsFixedSizeBytes for (A, B, C, D, E)
-{
-    const SIZE: usize = A::SIZE + B::SIZE + C::SIZE + D::SIZE + E::SIZE;
-    type Buf = Vec<u8>;
+            #[allow(unused_assignments)]
+            fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+                let mut from = 0;
 
-    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        self.0.as_fixed_size_bytes(&mut buf[0..A::SIZE]);
-        self.1
-            .as_fixed_size_bytes(&mut buf[A::SIZE..(A::SIZE + B::SIZE)]);
-        self.2
-            .as_fixed_size_bytes(&mut buf[(A::SIZE + B::SIZE)..(A::SIZE + B::SIZE + C::SIZE)]);
-        self.3.as_fixed_size_bytes(
-            &mut buf[(A::SIZE + B::SIZE + C::SIZE)..(A::SIZE + B::SIZE + C::SIZE + D::SIZE)],
-        );
-        self.4.as_fixed_size_bytes(
-            &mut buf[(A::SIZE + B::SIZE + C::SIZE + D::SIZE)
-                ..(A::SIZE + B::SIZE + C::SIZE + D::SIZE + E::SIZE)],
-        );
-    }
+                $(
+                    self.$idx.as_fixed_size_bytes(&mut buf[from..(from + $t::SIZE)]);
+                    from += $t::SIZE;
+                )+
+            }
 
-    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        (
-            A::from_fixed_size_bytes(&buf[0..A::SIZE]),
-            B::from_fixed_size_bytes(&buf[A::SIZE..(A::SIZE + B::SIZE)]),
-            C::from_fixed_size_bytes(&buf[(A::SIZE + B::SIZE)..(A::SIZE + B::SIZE + C::SIZE)]),
-            D::from_fixed_size_bytes(
-                &buf[(A::SIZE + B::SIZE + C::SIZE)..(A::SIZE + B::SIZE + C::SIZE + D::SIZE)],
-            ),
-            E::from_fixed_size_bytes(
-                &buf[(A::SIZE + B::SIZE + C::SIZE + D::SIZE)
-                    ..(A::SIZE + B::SIZE + C::SIZE + D::SIZE + E::SIZE)],
-            ),
-        )
-    }
-}
-impl<
-        A: AsFixedSizeBytes,
-        B: AsFixedSizeBytes,
-        C: AsFixedSizeBytes,
-        D: AsFixedSizeBytes,
-        E: AsFixedSizeBytes,
-        F: AsFixedSizeBytes,
-    > AsFixedSizeBytes for (A, B, C, D, E, F)
-{
-    const SIZE: usize = A::SIZE + B::SIZE + C::SIZE + D::SIZE + E::SIZE + F::SIZE;
-    type Buf = Vec<u8>;
+            #[allow(unused_assignments)]
+            fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+                let mut from = 0;
 
-    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        self.0.as_fixed_size_bytes(&mut buf[0..A::SIZE]);
-        self.1
-            .as_fixed_size_bytes(&mut buf[A::SIZE..(A::SIZE + B::SIZE)]);
-        self.2
-            .as_fixed_size_bytes(&mut buf[(A::SIZE + B::SIZE)..(A::SIZE + B::SIZE + C::SIZE)]);
-        self.3.as_fixed_size_bytes(
-            &mut buf[(A::SIZE + B::SIZE + C::SIZE)..(A::SIZE + B::SIZE + C::SIZE + D::SIZE)],
-        );
-        self.4.as_fixed_size_bytes(
-            &mut buf[(A::SIZE + B::SIZE + C::SIZE + D::SIZE)
-                ..(A::SIZE + B::SIZE + C::SIZE + D::SIZE + E::SIZE)],
-        );
-        self.5.as_fixed_size_bytes(
-            &mut buf[(A::SIZE + B::SIZE + C::SIZE + D::SIZE + E::SIZE)
-                ..(A::SIZE + B::SIZE + C::SIZE + D::SIZE + E::SIZE + F::SIZE)],
-        );
-    }
+                ($({
+                    let it = $t::from_fixed_size_bytes(&buf[from..(from + $t::SIZE)]);
+                    from += $t::SIZE;
 
-    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        (
-            A::from_fixed_size_bytes(&buf[0..A::SIZE]),
-            B::from_fixed_size_bytes(&buf[A::SIZE..(A::SIZE + B::SIZE)]),
-            C::from_fixed_size_bytes(&buf[(A::SIZE + B::SIZE)..(A::SIZE + B::SIZE + C::SIZE)]),
-            D::from_fixed_size_bytes(
-                &buf[(A::SIZE + B::SIZE + C::SIZE)..(A::SIZE + B::SIZE + C::SIZE + D::SIZE)],
-            ),
-            E::from_fixed_size_bytes(
-                &buf[(A::SIZE + B::SIZE + C::SIZE + D::SIZE)
-                    ..(A::SIZE + B::SIZE + C::SIZE + D::SIZE + E::SIZE)],
-            ),
-            F::from_fixed_size_bytes(
-                &buf[(A::SIZE + B::SIZE + C::SIZE + D::SIZE + E::SIZE)
-                    ..(A::SIZE + B::SIZE + C::SIZE + D::SIZE + E::SIZE + F::SIZE)],
-            ),
-        )
-    }
+                    it
+                },)+)
+            }
+        }
+    };
 }
 
+impl_for_tuple!(A 0);
+impl_for_tuple!(A 0, B 1);
+impl_for_tuple!(A 0, B 1, C 2);
+impl_for_tuple!(A 0, B 1, C 2, D 3);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
+/// Encoded as a length byte, followed by up to 29 bytes of the principal, padded with zeroes.
 impl AsFixedSizeBytes for Principal {
     const SIZE: usize = 30;
     type Buf = [u8; Self::SIZE];
@@ -454,6 +422,7 @@ impl AsFixedSizeBytes for Principal {
 
         buf[0] = slice.len() as u8;
         buf[1..(1 + slice.len())].copy_from_slice(slice);
+        buf[(1 + slice.len())..Self::SIZE].fill(0);
     }
 
     fn from_fixed_size_bytes(buf: &[u8]) -> Self {
@@ -479,28 +448,42 @@ impl AsFixedSizeBytes for Subaccount{
     }
 }
 
+/// Encoded as 32 little-endian bytes.
+///
+/// # Panics
+/// Encoding panics for values that don't fit into 32 bytes. Use bounded wrappers from
+/// [candid_num](crate::encoding::candid_num), when the bounds of the value are known in advance.
 impl AsFixedSizeBytes for Nat {
     const SIZE: usize = 32;
     type Buf = [u8; Self::SIZE];
 
     fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
         let vec = self.0.to_bytes_le();
+        assert!(vec.len() <= Self::SIZE, "Nat {} doesn't fit into 32 bytes", self);
+
         buf[0..vec.len()].copy_from_slice(&vec);
+        buf[vec.len()..Self::SIZE].fill(0);
     }
 
     fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        let it = BigUint::from_bytes_le(buf);
+        let it = BigUint::from_bytes_le(&buf[0..Self::SIZE]);
 
         Nat(it)
     }
 }
 
+/// Encoded as a sign byte, followed by 31 little-endian bytes of the absolute value.
+///
+/// # Panics
+/// Encoding panics for values that don't fit into 31 bytes. Use bounded wrappers from
+/// [candid_num](crate::encoding::candid_num), when the bounds of the value are known in advance.
 impl AsFixedSizeBytes for Int {
     const SIZE: usize = 32;
     type Buf = [u8; Self::SIZE];
 
     fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
         let (sign, bytes) = self.0.to_bytes_le();
+        assert!(bytes.len() < Self::SIZE, "Int {} doesn't fit into 32 bytes", self);
 
         buf[0] = match sign {
             Sign::Plus => 0u8,
@@ -509,6 +492,7 @@ impl AsFixedSizeBytes for Int {
         };
 
         buf[1..(1 + bytes.len())].copy_from_slice(&bytes);
+        buf[(1 + bytes.len())..Self::SIZE].fill(0);
     }
 
     fn from_fixed_size_bytes(buf: &[u8]) -> Self {
@@ -519,15 +503,15 @@ impl AsFixedSizeBytes for Int {
             _ => unreachable!(),
         };
 
-        let it = BigInt::from_bytes_le(sign, &buf[1..]);
+        let it = BigInt::from_bytes_le(sign, &buf[1..Self::SIZE]);
 
         Int(it)
     }
 }
 
-/// Either [u8; N] or [Vec] of [u8]
+/// Either [u8; N], [Vec] of [u8] or [ArrayBuf]
 ///
-/// You can't implement this trait for any other type than these two.
+/// You can't implement this trait for any other type than these three.
 pub trait Buffer: private::Sealed {
     #[doc(hidden)]
     fn new(size: usize) -> Self;
@@ -571,11 +555,78 @@ impl Buffer for Vec<u8> {
     }
 }
 
+/// Buffer of generic arrays `[T; N]`
+///
+/// If `T` takes a single byte (like [u8], [i8] or [bool]), the buffer is a `[u8; N]` on stack.
+/// Otherwise the size of the buffer (`N * T::SIZE`) can't be expressed with constant generics yet,
+/// so it is allocated on heap. Dereferences to a slice of [u8].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArrayBuf<const N: usize>(ArrayBufInner<N>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ArrayBufInner<const N: usize> {
+    Stack([u8; N]),
+    Heap(Vec<u8>),
+}
+
+impl<const N: usize> Buffer for ArrayBuf<N> {
+    #[inline]
+    fn new(size: usize) -> Self {
+        if size == N {
+            Self(ArrayBufInner::Stack([0u8; N]))
+        } else {
+            Self(ArrayBufInner::Heap(vec![0u8; size]))
+        }
+    }
+
+    #[inline]
+    fn _deref(&self) -> &[u8] {
+        match &self.0 {
+            ArrayBufInner::Stack(it) => it,
+            ArrayBufInner::Heap(it) => it,
+        }
+    }
+
+    #[inline]
+    fn _deref_mut(&mut self) -> &mut [u8] {
+        match &mut self.0 {
+            ArrayBufInner::Stack(it) => it,
+            ArrayBufInner::Heap(it) => it,
+        }
+    }
+}
+
+impl<const N: usize> Deref for ArrayBuf<N> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self._deref()
+    }
+}
+
+impl<const N: usize> DerefMut for ArrayBuf<N> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self._deref_mut()
+    }
+}
+
+impl<const N: usize> AsRef<[u8]> for ArrayBuf<N> {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self._deref()
+    }
+}
+
 mod private {
+    use super::ArrayBuf;
+
     pub trait Sealed {}
 
     impl<const N: usize> Sealed for [u8; N] {}
     impl Sealed for Vec<u8> {}
+    impl<const N: usize> Sealed for ArrayBuf<N> {}
 }
 
 #[test]
@@ -586,4 +637,117 @@ fn subaccount_test() {
   let acc_copy = Subaccount::from_fixed_size_bytes(&buf);
 
   assert_eq!(acc, acc_copy);
-}
\ No newline at end of file
+}
+#[test]
+fn option_test() {
+    assert_eq!(Option::<u64>::SIZE, 9);
+
+    let mut buf = Some(u64::MAX).as_new_fixed_size_bytes();
+    assert_eq!(Option::<u64>::from_fixed_size_bytes(&buf), Some(u64::MAX));
+
+    None::<u64>.as_fixed_size_bytes(&mut buf);
+    assert_eq!(buf, vec![0u8; 9]);
+    assert_eq!(Option::<u64>::from_fixed_size_bytes(&buf), None);
+
+    let nested = Some((None::<u32>, 10u16));
+    let buf = nested.as_new_fixed_size_bytes();
+    assert_eq!(Option::<(Option<u32>, u16)>::from_fixed_size_bytes(&buf), nested);
+}
+
+#[test]
+fn arrays_and_tuples_test() {
+    type Big = (
+        u8,
+        u16,
+        u32,
+        u64,
+        u128,
+        i8,
+        i16,
+        i32,
+        i64,
+        i128,
+        bool,
+        [u8; 2],
+    );
+
+    assert_eq!(<[u8; 29]>::SIZE, 29);
+    assert_eq!(<[u64; 3]>::SIZE, 24);
+    assert_eq!(<[(); 10]>::SIZE, 0);
+    assert_eq!(<(Principal, u64)>::SIZE, 38);
+    assert_eq!(Big::SIZE, 65);
+
+    let arr = [u64::MAX, 0, 10];
+    let buf = arr.as_new_fixed_size_bytes();
+    assert_eq!(<[u64; 3]>::from_fixed_size_bytes(&buf), arr);
+
+    // arrays of single-byte elements are still encoded on stack
+    let arr = [1u8, 2, 3];
+    let buf = arr.as_new_fixed_size_bytes();
+    assert_eq!(buf, ArrayBuf(ArrayBufInner::Stack(arr)));
+    assert_eq!(<[u8; 3]>::from_fixed_size_bytes(&buf), arr);
+
+    let arr = [true, false];
+    assert_eq!(&*arr.as_new_fixed_size_bytes(), &[1u8, 0]);
+    assert_eq!(&*[-1i8; 2].as_new_fixed_size_bytes(), &[255u8, 255]);
+
+    let arr = [Some(Principal::management_canister()), None];
+    let buf = arr.as_new_fixed_size_bytes();
+    assert_eq!(<[Option<Principal>; 2]>::from_fixed_size_bytes(&buf), arr);
+
+    let key = (Principal::management_canister(), 10u64);
+    let buf = key.as_new_fixed_size_bytes();
+    assert_eq!(<(Principal, u64)>::from_fixed_size_bytes(&buf), key);
+
+    let big: Big = (1, 2, 3, 4, 5, 6, 7, 8, 9, 10, true, [11; 2]);
+    let buf = big.as_new_fixed_size_bytes();
+    assert_eq!(Big::from_fixed_size_bytes(&buf), big);
+}
+
+#[test]
+fn std_types_test() {
+    assert_eq!(Result::<u64, u8>::SIZE, 9);
+    assert_eq!(Result::<(), u16>::SIZE, 3);
+
+    let mut buf = Result::<u64, u8>::Ok(u64::MAX).as_new_fixed_size_bytes();
+    assert_eq!(Result::<u64, u8>::from_fixed_size_bytes(&buf), Ok(u64::MAX));
+
+    Result::<u64, u8>::Err(10).as_fixed_size_bytes(&mut buf);
+    assert_eq!(buf, vec![1, 10, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(Result::<u64, u8>::from_fixed_size_bytes(&buf), Err(10));
+
+    let it = NonZeroU64::new(10).unwrap();
+    let buf = it.as_new_fixed_size_bytes();
+    assert_eq!(NonZeroU64::from_fixed_size_bytes(&buf), it);
+    assert_eq!(Option::<NonZeroU64>::SIZE, 9);
+
+    let it = Duration::new(100, 999_999_999);
+    let buf = it.as_new_fixed_size_bytes();
+    assert_eq!(Duration::from_fixed_size_bytes(&buf), it);
+
+    let it = UNIX_EPOCH + Duration::from_nanos(1_670_000_000_123_456_789);
+    let buf = it.as_new_fixed_size_bytes();
+    assert_eq!(SystemTime::from_fixed_size_bytes(&buf), it);
+
+    for it in [Ordering::Less, Ordering::Equal, Ordering::Greater] {
+        let buf = it.as_new_fixed_size_bytes();
+        assert_eq!(Ordering::from_fixed_size_bytes(&buf), it);
+    }
+}
+
+#[test]
+fn ic_types_test() {
+    let mut buf = Principal::from_slice(&[1u8; 29]).as_new_fixed_size_bytes();
+    let it = Principal::management_canister();
+    it.as_fixed_size_bytes(&mut buf);
+    assert_eq!(buf, [0u8; 30]);
+    assert_eq!(Principal::from_fixed_size_bytes(&buf), it);
+
+    let mut buf = Nat::from(u128::MAX).as_new_fixed_size_bytes();
+    Nat::from(10u64).as_fixed_size_bytes(&mut buf);
+    assert_eq!(Nat::from_fixed_size_bytes(&buf), Nat::from(10u64));
+
+    let mut buf = Int::from(i128::MIN).as_new_fixed_size_bytes();
+    Int::from(-10).as_fixed_size_bytes(&mut buf);
+    assert_eq!(Int::from_fixed_size_bytes(&buf), Int::from(-10));
+}
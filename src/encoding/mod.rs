@@ -12,14 +12,39 @@
 //! // Cargo.toml
 //! 
 //! [dependencies]
-//! ic-stable-memory = { version: "0.4", features = ["custom_dyn_encoding"] } 
+//! ic-stable-memory = { version = "0.4", features = ["custom_dyn_encoding"] } 
 //! ```
 //! 
 //! This will disable all default implementations of [AsDynSizeBytes] trait allowing you to implement
 //! this trait by yourself in whatever way you prefer.
+//!
+//! If your data types already implement [serde](https://docs.rs/serde/latest/serde/) traits, enable
+//! `serde_encoding` feature and wrap them into `encoding::serde_encoding::SerdeEncoded`, which
+//! implements [AsDynSizeBytes] for any serde type.
+//!
+//! Fixed size encoding of [usize] and [isize] depends on the pointer width of the target - they take
+//! 4 bytes on wasm32 and 8 bytes on wasm64. The same is true for hashes, used by [SHashMap](crate::collections::SHashMap)
+//! and other hash-based collections. If your canister is going to move to wasm64 at some point, enable
+//! `wasm64` feature, before the first deployment:
+//!
+//! ```toml
+//! // Cargo.toml
+//!
+//! [dependencies]
+//! ic-stable-memory = { version = "0.4", features = ["wasm64"] }
+//! ```
+//!
+//! This makes the layout of stable memory the same on both targets. The rest of the memory layer
+//! already uses 64-bit pointers and stable64 APIs everywhere. Don't switch this feature on or off for an
+//! already deployed wasm32 canister - the data written before won't be readable anymore.
 
+pub mod candid_num;
+pub mod compression;
+pub(crate) mod counters;
 pub mod dyn_size;
 pub mod fixed_size;
+#[cfg(feature = "serde_encoding")]
+pub mod serde_encoding;
 
-pub use dyn_size::AsDynSizeBytes;
+pub use dyn_size::{AsDynSizeBytes, Upgrade};
 pub use fixed_size::{AsFixedSizeBytes, Buffer};
@@ -0,0 +1,133 @@
+//! [AsDynSizeBytes] for any [serde] type, enabled with `serde_encoding` feature.
+//!
+//! A blanket implementation of [AsDynSizeBytes] for every [Serialize] type is impossible, because
+//! [AsDynSizeBytes] is already implemented for every [AsFixedSizeBytes](crate::AsFixedSizeBytes)
+//! type. Instead, existing serde models can be wrapped into [SerdeEncoded] and put into an
+//! [SBox](crate::SBox) (or any other stable collection) as is, without any additional trait
+//! implementations.
+//!
+//! Values are encoded with [bincode] using variable-length integers, which is much more compact than
+//! candid for small values.
+
+use crate::encoding::AsDynSizeBytes;
+use crate::primitive::StableType;
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::ops::{Deref, DerefMut};
+
+/// A wrapper, implementing [AsDynSizeBytes] for any type that implements [Serialize] and [DeserializeOwned]
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::encoding::serde_encoding::SerdeEncoded;
+/// # use ic_stable_memory::{stable_memory_init, SBox};
+/// # use serde::{Deserialize, Serialize};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// // an existing model, that knows nothing about stable memory
+/// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// struct Post {
+///     title: String,
+///     tags: Vec<String>,
+/// }
+///
+/// let mut posts = SVec::new();
+///
+/// let post = Post { title: String::from("Hello"), tags: vec![String::from("news")] };
+/// posts.push(SBox::new(SerdeEncoded(post)).expect("Out of memory")).expect("Out of memory");
+///
+/// assert_eq!(posts.get(0).unwrap().title, "Hello");
+/// ```
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SerdeEncoded<T>(pub T);
+
+impl<T> SerdeEncoded<T> {
+    /// Returns the wrapped value
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for SerdeEncoded<T> {
+    #[inline]
+    fn from(it: T) -> Self {
+        Self(it)
+    }
+}
+
+impl<T> Deref for SerdeEncoded<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for SerdeEncoded<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T> StableType for SerdeEncoded<T> {}
+
+impl<T: Serialize + DeserializeOwned> AsDynSizeBytes for SerdeEncoded<T> {
+    #[inline]
+    fn as_dyn_size_bytes(&self) -> Vec<u8> {
+        options().serialize(&self.0).unwrap()
+    }
+
+    #[inline]
+    fn from_dyn_size_bytes(buf: &[u8]) -> Self {
+        Self(options().deserialize(buf).unwrap())
+    }
+}
+
+#[inline]
+fn options() -> impl Options {
+    bincode::DefaultOptions::new().allow_trailing_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::serde_encoding::SerdeEncoded;
+    use crate::encoding::AsDynSizeBytes;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+    enum Role {
+        Admin,
+        User { since: u64 },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+    struct Account {
+        name: String,
+        roles: Vec<Role>,
+        balances: BTreeMap<String, u128>,
+        referrer: Option<u64>,
+    }
+
+    #[test]
+    fn works_fine() {
+        let account = SerdeEncoded(Account {
+            name: String::from("Alice"),
+            roles: vec![Role::Admin, Role::User { since: 10 }],
+            balances: BTreeMap::from([(String::from("ICP"), 100), (String::from("BTC"), 1)]),
+            referrer: None,
+        });
+
+        let mut buf = account.as_dyn_size_bytes();
+        let len = buf.len();
+        buf.extend(vec![1u8; 10]);
+
+        assert_eq!(SerdeEncoded::<Account>::from_dyn_size_bytes(&buf), account);
+        assert!(len < 30);
+    }
+}
@@ -17,11 +17,19 @@
 //! 4. Supported stable data structures: box, vec, log, hash-map, hash-set, btree-map, btree-set, certified-map.
 //! 5. In addition to these data structures, this crate provides you with a fully featured toolset
 //! to build your own data structure, if you need something more domain-specific.
-use crate::mem::allocator::StableMemoryAllocator;
+use crate::mem::allocator::{
+    AllocationStrategy, AllocatorLayout, AllocatorStats, CustomDataError, DefragmentationReport,
+    Reservation, StableMemoryAllocator,
+};
+use crate::mem::hooks::AllocationEventKind;
+use crate::mem::region::{with_current_region, RegionGuard, RegionStats};
+use crate::mem::StablePtr;
 use mem::s_slice::SSlice;
 use std::cell::RefCell;
 
-mod benches;
+/// Benchmark harness, comparing stable collections with `std` ones
+#[cfg(any(test, feature = "benches"))]
+pub mod benches;
 /// All collections provided by this crate
 pub mod collections;
 /// Traits and algorithms for internal data encoding
@@ -30,20 +38,51 @@ pub mod encoding;
 pub mod mem;
 /// Stable memory smart-pointers
 pub mod primitive;
+/// Proptest strategies and model-based state machines for stable collections
+#[cfg(feature = "testing")]
+pub mod testing;
 /// Various utilities: certification, stable memory API wrapper etc.
 pub mod utils;
 
 pub use ic_stable_memory_derive as derive;
 
+/// The candid version, selected with one of `candid_0_8` (default), `candid_0_9` or `candid_0_10` features
+///
+/// If several of these features are enabled, the newest version is selected. Used by
+/// [candid_decode_one_allow_trailing](encoding::dyn_size::candid_decode_one_allow_trailing)
+/// and by the code, generated by candid derive macros. Internally this crate always uses candid 0.8.
+#[cfg(all(
+    feature = "candid_0_8",
+    not(any(feature = "candid_0_9", feature = "candid_0_10"))
+))]
+pub use candid;
+#[cfg(feature = "candid_0_10")]
+pub use candid_0_10 as candid;
+#[cfg(all(feature = "candid_0_9", not(feature = "candid_0_10")))]
+pub use candid_0_9 as candid;
+
 use crate::utils::isoprint;
 pub use crate::utils::mem_context::{stable, OutOfMemory, PAGE_SIZE_BYTES};
 pub use encoding::{AsDynSizeBytes, AsFixedSizeBytes, Buffer};
-pub use primitive::s_box::SBox;
-pub use primitive::StableType;
+pub use mem::arena::{with_stable_arena, StableArena};
+pub use mem::hooks::{register_allocation_hook, unregister_allocation_hook, with_allocation_tag};
+pub use primitive::s_box::{
+    clear_decode_cache, reset_value_transform, set_value_transform, CorruptedPayload, SBox,
+};
+pub use primitive::s_cell::SCell;
+pub use primitive::s_cow::SCow;
+pub use primitive::s_rc::{SRc, SWeak};
+pub use primitive::{StableType, TryClone, TryFromIterator};
+pub use utils::backup::{backup_chunk, restore_chunk};
 pub use utils::certification::{
     empty, empty_hash, fork, fork_hash, labeled, labeled_hash, leaf, leaf_hash, AsHashTree,
     AsHashableBytes,
 };
+#[cfg(feature = "io_stats")]
+pub use utils::io_stats::{get_io_stats, reset_io_stats, IoStats};
+pub use utils::migration::{get_schema_version, set_schema_version};
+pub use utils::snapshot::{discard_snapshot, has_snapshot, restore_snapshot, take_snapshot};
+pub use utils::transaction::{is_in_transaction, with_transaction};
 
 thread_local! {
     static STABLE_MEMORY_ALLOCATOR: RefCell<Option<StableMemoryAllocator>> = RefCell::new(None);
@@ -108,12 +147,18 @@ pub fn stable_memory_init() {
 ///
 /// This function should be called as the last step of the `#[pre_ugrade]` canister method.
 ///
+/// Before persisting the allocator, it also persists every [SCell] that was accessed since the
+/// last upgrade.
+///
 /// It works by first writing the allocator to an `SBox` and then writing a pointer to that `SBox` into
 /// frist 8 bytes of stable memory (offsets [0..8)). `thread_local!` static variable that stores the
 /// allocator also gets cleared, if this function is executed successfully.
 ///
 /// If it was impossible to allocate a memory block of required size, this function returns an [OutOfMemory]
 /// error. For tips on possible ways of resolving an [OutOfMemory] error visit [this page](https://github.com/seniorjoinu/ic-stable-memory/docs/out-of-memory-error-handling.md).
+/// To avoid allocating the allocator's state during an upgrade, reserve an upgrade header in advance with
+/// [reserve_upgrade_header]. Then this function can only fail, if the header couldn't be grown along
+/// with the state because of an [OutOfMemory] earlier, or while persisting [SCell]s.
 ///
 /// This function is an alias for [deinit_allocator()].
 ///
@@ -199,6 +244,45 @@ pub fn init_allocator(max_pages: u64) {
     })
 }
 
+/// The same as [init_allocator], but also allows choosing how the allocator picks free memory blocks.
+///
+/// See [AllocationStrategy](mem::allocator::AllocationStrategy) for available options. The strategy is
+/// persisted between canister upgrades, so there is no need to pass it again after an upgrade.
+/// [init_allocator_with_strategy(0, AllocationStrategy::BestFit)] works exactly the same as [stable_memory_init()].
+///
+/// Internally calls [StableMemoryAllocator::init_with_strategy](mem::allocator::StableMemoryAllocator::init_with_strategy).
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{get_allocation_strategy, init_allocator_with_strategy};
+/// # use ic_stable_memory::mem::allocator::AllocationStrategy;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// #[ic_cdk_macros::init]
+/// fn init() {
+///     // lots of small values of the same size are going to be stored
+///     init_allocator_with_strategy(0, AllocationStrategy::SizeClasses);
+///
+///     // the rest of canister's initialization
+/// }
+/// # init();
+/// # assert_eq!(get_allocation_strategy(), AllocationStrategy::SizeClasses);
+/// ```
+///
+/// # Panics
+/// Panics if the allocator is already initialized.
+#[inline]
+pub fn init_allocator_with_strategy(max_pages: u64, strategy: AllocationStrategy) {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if it.borrow().is_none() {
+            let allocator = StableMemoryAllocator::init_with_strategy(max_pages, strategy);
+
+            *it.borrow_mut() = Some(allocator);
+        } else {
+            unreachable!("StableMemoryAllocator can only be initialized once");
+        }
+    })
+}
+
 /// An alias for [stable_memory_pre_upgrade].
 ///
 /// Internally calls [StableMemoryAllocator::store](mem::allocator::StableMemoryAllocator::store).
@@ -207,6 +291,8 @@ pub fn init_allocator(max_pages: u64) {
 /// Panics if there is no initialized stable memory allocator.
 #[inline]
 pub fn deinit_allocator() -> Result<(), OutOfMemory> {
+    primitive::s_cell::persist_cells()?;
+
     STABLE_MEMORY_ALLOCATOR.with(|it: &RefCell<Option<StableMemoryAllocator>>| {
         if let Some(mut alloc) = it.take() {
             let res = alloc.store();
@@ -257,6 +343,7 @@ pub fn reinit_allocator() {
 /// [stable_memory_pre_upgrade()] invocation. This function can be used multiple times, but one should
 /// make sure they always keep track of keys they are assigning custom data to. An attempt to assign
 /// two values to a single key will lead to losing the data that was assigned first. *Be careful!*
+/// [store_named_custom_data] is a safer alternative, which detects such collisions.
 ///
 /// Internally calls [StableMemoryAllocator::store_custom_data](mem::allocator::StableMemoryAllocator::store_custom_data).
 ///
@@ -361,6 +448,82 @@ pub fn retrieve_custom_data<T: StableType + AsDynSizeBytes>(idx: usize) -> Optio
     })
 }
 
+/// Persists a pointer to an [SBox] between canister upgrades under a string key, along with its type.
+///
+/// See also [retrieve_named_custom_data].
+///
+/// Works the same way as [store_custom_data], but string keys are much less likely to collide, when
+/// different modules of a canister persist their state independently. If the key is already
+/// occupied, the data is not stored and is returned back. The name of `T` is stored along with the
+/// pointer, so retrieving it as a different type is detected. Type names include module paths, so
+/// moving a type to another module is also considered a type change.
+///
+/// Internally calls [StableMemoryAllocator::store_named_custom_data](mem::allocator::StableMemoryAllocator::store_named_custom_data).
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::mem::allocator::CustomDataError;
+/// # use ic_stable_memory::{retrieve_named_custom_data, SBox, stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade, store_named_custom_data};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// const USERS: &str = "users";
+///
+/// let users = SBox::new(SVec::<u64>::new()).expect("Out of memory");
+/// store_named_custom_data(USERS, users).unwrap_or_else(|_| panic!("Key is occupied"));
+///
+/// stable_memory_pre_upgrade().expect("Out of memory");
+/// stable_memory_post_upgrade();
+///
+/// let err = retrieve_named_custom_data::<SVec<u32>>(USERS).err().unwrap();
+/// assert!(matches!(err, CustomDataError::TypeMismatch { .. }));
+///
+/// let users = retrieve_named_custom_data::<SVec<u64>>(USERS)
+///     .expect("Unable to retrieve users")
+///     .into_inner();
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn store_named_custom_data<T: StableType + AsDynSizeBytes>(
+    key: &'static str,
+    data: SBox<T>,
+) -> Result<(), SBox<T>> {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.store_named_custom_data(key, data)
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Retrieves an [SBox], stored previously with [store_named_custom_data].
+///
+/// After retrieval, the key gets "forgotten", allowing reusing it again for other data. If there is
+/// no data under this key, returns [CustomDataError::NotFound](mem::allocator::CustomDataError::NotFound).
+/// If the data was stored with a different type, returns [CustomDataError::TypeMismatch](mem::allocator::CustomDataError::TypeMismatch)
+/// and leaves the data in place.
+///
+/// Internally calls [StableMemoryAllocator::retrieve_named_custom_data](mem::allocator::StableMemoryAllocator::retrieve_named_custom_data).
+///
+/// # Examples
+/// See examples of [store_named_custom_data].
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn retrieve_named_custom_data<T: StableType + AsDynSizeBytes>(
+    key: &'static str,
+) -> Result<SBox<T>, CustomDataError> {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.retrieve_named_custom_data(key)
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
 /// Attempts to allocate a new [SSlice] of at least the required size or returns an [OutOfMemory] error
 /// if there is no continuous stable memory memory block of that size can be allocated.
 ///
@@ -380,6 +543,10 @@ pub fn retrieve_custom_data<T: StableType + AsDynSizeBytes>(idx: usize) -> Optio
 /// stable memory in a subnet or due to reaching `max_pages` limit set earlier - it will return an
 /// [OutOfMemory] error.
 ///
+/// If called inside [with_region], the memory block is allocated inside that region instead.
+///
+/// Invokes [allocation hooks](mem::hooks), if the memory block was allocated.
+///
 /// Internally calls [StableMemoryAllocator::allocate](mem::allocator::StableMemoryAllocator::allocate).
 ///
 /// # Example
@@ -400,13 +567,57 @@ pub fn retrieve_custom_data<T: StableType + AsDynSizeBytes>(idx: usize) -> Optio
 /// Don't forget to [deallocate] the memory block, when you're done!
 #[inline]
 pub unsafe fn allocate(size: u64) -> Result<SSlice, OutOfMemory> {
-    STABLE_MEMORY_ALLOCATOR.with(|it| {
+    #[cfg(not(target_family = "wasm"))]
+    if stable::should_fail_allocation() {
+        return Err(OutOfMemory);
+    }
+
+    let slice = STABLE_MEMORY_ALLOCATOR.with(|it| {
         if let Some(alloc) = &mut *it.borrow_mut() {
-            alloc.allocate(size)
+            with_current_region(|region| match region {
+                Some(name) => alloc.allocate_in(name, size),
+                None => alloc.allocate(size),
+            })
         } else {
             unreachable!("StableMemoryAllocator is not initialized");
         }
-    })
+    })?;
+
+    mem::hooks::emit(
+        AllocationEventKind::Allocate,
+        slice.as_ptr(),
+        slice.get_size_bytes(),
+    );
+
+    Ok(slice)
+}
+
+/// Allocates a memory block, tagging this allocation with the provided tag.
+///
+/// Works the same way as [allocate] called inside [with_allocation_tag]. Any [Display](std::fmt::Display)
+/// value can be a tag, for example, a string or a numeric id.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{allocate_tagged, stable_memory_init};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// # unsafe {
+/// let slice = allocate_tagged(100, "images").expect("Not enough stable memory");
+/// # }
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+///
+/// # Safety
+/// Don't forget to [deallocate] the memory block, when you're done!
+#[inline]
+pub unsafe fn allocate_tagged<T: std::fmt::Display>(
+    size: u64,
+    tag: T,
+) -> Result<SSlice, OutOfMemory> {
+    with_allocation_tag(tag, || allocate(size))
 }
 
 /// Deallocates an already allocated [SSlice] freeing it's memory.
@@ -414,6 +625,8 @@ pub unsafe fn allocate(size: u64) -> Result<SSlice, OutOfMemory> {
 /// Supplied [SSlice] get's transformed into [FreeBlock](mem::free_block::FreeBlock) and then an
 /// attempt to merge it with neighboring (physically) free blocks is performed.
 ///
+/// Invokes [allocation hooks](mem::hooks).
+///
 /// Internally calls [StableMemoryAllocator::deallocate](mem::allocator::StableMemoryAllocator::deallocate).
 ///
 /// # Example
@@ -437,7 +650,13 @@ pub fn deallocate(slice: SSlice) {
         } else {
             unreachable!("StableMemoryAllocator is not initialized");
         }
-    })
+    });
+
+    mem::hooks::emit(
+        AllocationEventKind::Deallocate,
+        slice.as_ptr(),
+        slice.get_size_bytes(),
+    );
 }
 
 /// Attempts to reallocate a memory block growing its size and possibly moving its content to a new
@@ -456,9 +675,14 @@ pub fn deallocate(slice: SSlice) {
 /// 5. Return it as a result.
 /// This process moves the data.
 ///
+/// Memory blocks bigger than 1MB are not buffered on heap - instead, a new [SSlice] is allocated
+/// first and the data is copied into it in batches, before the old one gets deallocated.
+///
 /// If the requested new size is less than the actual size of the [SSlice] passed as an argument,
 /// the function does nothing and returns this [SSlice] as a result back.
 ///
+/// Invokes [allocation hooks](mem::hooks), if the memory block was reallocated.
+///
 /// Internally calls [StableMemoryAllocator::reallocate](mem::allocator::StableMemoryAllocator::reallocate).
 ///
 /// # Example
@@ -474,19 +698,34 @@ pub fn deallocate(slice: SSlice) {
 ///
 /// # Panics
 /// Panics if there is no initialized stable memory allocator.
-/// Reallocating [SSlice]s bigger than [u32::MAX] bytes will also panic.
 ///
 /// # Safety
 /// Don't forget to [deallocate] the memory block, when you're done!
 #[inline]
 pub unsafe fn reallocate(slice: SSlice, new_size: u64) -> Result<SSlice, OutOfMemory> {
-    STABLE_MEMORY_ALLOCATOR.with(|it| {
+    #[cfg(not(target_family = "wasm"))]
+    if stable::should_fail_allocation() {
+        return Err(OutOfMemory);
+    }
+
+    let new_slice = STABLE_MEMORY_ALLOCATOR.with(|it| {
         if let Some(alloc) = &mut *it.borrow_mut() {
             alloc.reallocate(slice, new_size)
         } else {
             unreachable!("StableMemoryAllocator is not initialized");
         }
-    })
+    })?;
+
+    mem::hooks::emit(
+        AllocationEventKind::Reallocate {
+            old_ptr: slice.as_ptr(),
+            old_size: slice.get_size_bytes(),
+        },
+        new_slice.as_ptr(),
+        new_slice.get_size_bytes(),
+    );
+
+    Ok(new_slice)
 }
 
 /// Checks if it would be possible to allocate a block of stable memory of the provided size right now.
@@ -497,6 +736,8 @@ pub unsafe fn reallocate(slice: SSlice, new_size: u64) -> Result<SSlice, OutOfMe
 /// Returns `true` if a block was found. Returns `false` if an attempt to grow stable memory resulted in
 /// an [OutOfMemory] error.
 ///
+/// If called inside [with_region], checks that region instead.
+///
 /// Internally calls [StableMemoryAllocator::make_sure_can_allocate](mem::allocator::StableMemoryAllocator::make_sure_can_allocate).
 ///
 /// # Example
@@ -515,7 +756,147 @@ pub unsafe fn reallocate(slice: SSlice, new_size: u64) -> Result<SSlice, OutOfMe
 pub fn make_sure_can_allocate(size: u64) -> bool {
     STABLE_MEMORY_ALLOCATOR.with(|it| {
         if let Some(alloc) = &mut *it.borrow_mut() {
-            alloc.make_sure_can_allocate(size)
+            with_current_region(|region| match region {
+                Some(name) => alloc.make_sure_can_allocate_in(name, size),
+                None => alloc.make_sure_can_allocate(size),
+            })
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Sets aside a memory block of the provided size, growing stable memory right away, if needed.
+///
+/// Unlike [make_sure_can_allocate], gives a guarantee: while the returned [Reservation] is alive,
+/// subsequent allocations of up to `size` bytes in total will succeed, even if stable memory can't grow
+/// anymore. Keep in mind, that each memory block also takes 16 bytes of service data. Reserved memory
+/// is only used, when an allocation can't be done otherwise. Once it is used (see [Reservation::is_active](mem::allocator::Reservation::is_active)),
+/// the memory is available to everyone, so only the first allocations after that are guaranteed to
+/// succeed.
+///
+/// The reservation is released, when the [Reservation] is dropped or right before the allocator is
+/// persisted during an upgrade. Reserved memory is always taken from the global free-list, even inside
+/// [with_region], and counts as allocated.
+///
+/// If it was impossible to reserve memory, returns an [OutOfMemory] error.
+///
+/// Internally calls [StableMemoryAllocator::reserve](mem::allocator::StableMemoryAllocator::reserve).
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{reserve, stable_memory_init, SBox};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let reservation = reserve(1024).expect("Out of memory");
+///
+/// // some heavy computation, which may exhaust stable memory
+///
+/// // a value of ~900 bytes can still be stored
+/// let result = SBox::new(vec![0u8; 900]).expect("Unreachable");
+/// # drop(result);
+///
+/// drop(reservation);
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn reserve(size: u64) -> Result<Reservation, OutOfMemory> {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            let ptr = alloc.reserve(size)?;
+
+            Ok(Reservation::new(ptr, size))
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+// reservations can be dropped when the allocator is already deinitialized or destroyed
+pub(crate) fn is_reserved(ptr: StablePtr) -> bool {
+    STABLE_MEMORY_ALLOCATOR
+        .try_with(|it| match &*it.borrow() {
+            Some(alloc) => alloc.is_reserved(ptr),
+            None => false,
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) fn release_reservation(ptr: StablePtr) {
+    let _ = STABLE_MEMORY_ALLOCATOR.try_with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.release_reservation(ptr);
+        }
+    });
+}
+
+/// Allocates a memory block of `capacity` bytes, where the allocator will store its state during upgrades.
+///
+/// By default, [stable_memory_pre_upgrade] allocates a new memory block for the allocator's state,
+/// which can fail with [OutOfMemory] at the worst possible moment. With an upgrade header, the state
+/// is simply written into this block, so nothing gets allocated. Whenever the state outgrows the
+/// header, the header is grown to be twice as big as the state, so it always fits. Only if that
+/// fails with [OutOfMemory], the state can end up being stored the usual way. The header stays
+/// allocated between upgrades.
+///
+/// The state mostly consists of free blocks and custom data pointers, a few KB is usually enough. The
+/// current capacity can be checked with [get_upgrade_header_capacity]. If there already is a header,
+/// it only gets bigger. Keep in mind, that [SCell]s are still persisted with regular allocations.
+///
+/// If it was impossible to allocate a memory block of required size, returns an [OutOfMemory] error.
+///
+/// Internally calls [StableMemoryAllocator::reserve_upgrade_header](mem::allocator::StableMemoryAllocator::reserve_upgrade_header).
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{reserve_upgrade_header, stable_memory_init};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// #[ic_cdk_macros::init]
+/// fn init() {
+///     stable_memory_init();
+///     reserve_upgrade_header(16 * 1024).expect("Out of memory");
+/// }
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn reserve_upgrade_header(capacity: u64) -> Result<(), OutOfMemory> {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.reserve_upgrade_header(capacity)
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Deallocates the upgrade header, returning [false], if there is none.
+///
+/// See also [reserve_upgrade_header].
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn release_upgrade_header() -> bool {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.release_upgrade_header()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Returns the capacity of the upgrade header in bytes, or [None] if there is no upgrade header.
+///
+/// See also [reserve_upgrade_header].
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn get_upgrade_header_capacity() -> Option<u64> {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            alloc.get_upgrade_header_capacity()
         } else {
             unreachable!("StableMemoryAllocator is not initialized");
         }
@@ -541,6 +922,182 @@ pub fn get_available_size() -> u64 {
     })
 }
 
+/// Moves allocated memory blocks closer to each other, making free stable memory more contiguous.
+///
+/// Long-living canisters tend to fragment their stable memory - there can be plenty of free bytes,
+/// but no free block big enough to satisfy a large allocation. This function relocates allocated
+/// memory blocks towards the beginning of stable memory, one by one, until `budget` bytes are moved
+/// or there is nothing left to move. Since each move costs instructions, it is a good idea to call
+/// this function periodically (e.g. from a timer) with a moderate budget.
+///
+/// The allocator does not know, who owns a memory block, so before each move it calls
+/// `relocate(old_ptr, new_ptr)`. If the block can be moved, this callback should update all
+/// pointers to it and return `true`. Otherwise, it should return `false` and the block stays in place.
+/// Blocks stored with [store_custom_data] are relocated automatically.
+///
+/// Each move is reported to [allocation hooks](mem::hooks) as [AllocationEventKind::Reallocate] of
+/// the same size.
+///
+/// Returns a [report](mem::allocator::DefragmentationReport) of what was done.
+///
+/// Internally calls [StableMemoryAllocator::defragment](mem::allocator::StableMemoryAllocator::defragment).
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{allocate, deallocate, defragment, stable_memory_init};
+/// # use ic_stable_memory::mem::s_slice::SSlice;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut slices = (0..10)
+///     .map(|_| unsafe { allocate(100).expect("Out of memory") }.as_ptr())
+///     .collect::<Vec<_>>();
+///
+/// // free every other block, creating gaps
+/// for ptr in slices.iter().step_by(2) {
+///     deallocate(unsafe { SSlice::from_ptr(*ptr).unwrap() });
+/// }
+/// slices = slices.into_iter().skip(1).step_by(2).collect();
+///
+/// let report = defragment(u64::MAX, |old_ptr, new_ptr| {
+///     let idx = slices.iter().position(|it| *it == old_ptr).unwrap();
+///     slices[idx] = new_ptr;
+///
+///     true
+/// });
+///
+/// assert_eq!(report.moved_blocks, 5);
+/// assert!(report.reclaimed_bytes() > 0);
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator, or if `relocate` tries to allocate
+/// or deallocate stable memory.
+pub fn defragment<F: FnMut(StablePtr, StablePtr) -> bool>(
+    budget: u64,
+    relocate: F,
+) -> DefragmentationReport {
+    let mut moves = Vec::new();
+
+    let report = STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.defragment_tracked(budget, relocate, |old_ptr, new_ptr, size| {
+                moves.push((old_ptr, new_ptr, size))
+            })
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    });
+
+    for (old_ptr, new_ptr, size) in moves {
+        mem::hooks::emit(
+            AllocationEventKind::Reallocate {
+                old_ptr,
+                old_size: size,
+            },
+            new_ptr,
+            size,
+        );
+    }
+
+    // cached values are keyed by pointers, which are not valid anymore
+    if report.moved_blocks > 0 {
+        clear_decode_cache();
+    }
+
+    report
+}
+
+/// Creates a named region of stable memory, or updates the quota of an existing one.
+///
+/// Regions allow different subsystems of a canister (e.g. "users", "logs", "indexes") to keep their
+/// data in separate address ranges, with independent quotas and statistics. Memory blocks of a
+/// region are allocated inside its own chunks, which are taken from the allocator on demand and
+/// returned back, once they are empty. A region can't take more than `quota` bytes of stable memory
+/// (`0` means unlimited) - instead, allocations inside it fail with [OutOfMemory].
+///
+/// Regions are persisted between upgrades together with the allocator, so this function can be
+/// safely called on each canister start.
+///
+/// Use [with_region] to allocate memory inside a region.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::{get_region_stats, init_region, stable_memory_init, with_region};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// init_region("logs", 1024);
+///
+/// let logs = with_region("logs", || {
+///     let mut logs = SVec::<u64>::new_with_capacity(10).expect("Out of memory");
+///     logs.push(10).expect("Out of memory");
+///
+///     // the quota is exceeded
+///     assert!(SVec::<u64>::new_with_capacity(1000).is_err());
+///
+///     logs
+/// });
+///
+/// let stats = get_region_stats("logs").unwrap();
+/// assert!(stats.allocated_size > 0);
+/// assert!(stats.available_size <= 1024);
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn init_region(name: &str, quota: u64) {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.init_region(name, quota);
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Runs the lambda function, allocating all new memory blocks inside the provided region.
+///
+/// Every call to [allocate] (and therefore every stable collection, created or grown inside the
+/// lambda) takes memory from the region, instead of the global free-list. Memory blocks are always
+/// deallocated and reallocated inside the region they belong to, so a stable collection, created
+/// inside a region, can be used and dropped outside of this function, but it only grows inside the
+/// region, while this function is active. Calls can be nested.
+///
+/// See [init_region] for an example.
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator, or if the region was not created with
+/// [init_region].
+pub fn with_region<R, F: FnOnce() -> R>(name: &str, func: F) -> R {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            assert!(alloc.has_region(name), "Region {} does not exist", name);
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    });
+
+    let _guard = RegionGuard::enter(name);
+
+    func()
+}
+
+/// Returns statistics of the provided region, or [None] if there is no such region.
+///
+/// Internally calls [StableMemoryAllocator::get_region_stats](mem::allocator::StableMemoryAllocator::get_region_stats).
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn get_region_stats(name: &str) -> Option<RegionStats> {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            alloc.get_region_stats(name)
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
 /// Returns the amount of free stable memory in bytes.
 ///
 /// Internally calls [StableMemoryAllocator::get_free_size](mem::allocator::StableMemoryAllocator::get_free_size).
@@ -594,6 +1151,89 @@ pub fn get_max_pages() -> u64 {
     })
 }
 
+/// Returns the allocation strategy, the allocator was initialized with.
+///
+/// See [init_allocator_with_strategy] for more details.
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+#[inline]
+pub fn get_allocation_strategy() -> AllocationStrategy {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            alloc.get_strategy()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Returns statistics about free and allocated stable memory.
+///
+/// Includes the number of free blocks, their size histogram, the largest free block and the
+/// fragmentation ratio. Unlike [_debug_print_allocator], the result is structured data, which can be
+/// returned from a metrics query endpoint. Takes `O(N)` time, where `N` is the number of free blocks.
+///
+/// Internally calls [StableMemoryAllocator::get_stats](mem::allocator::StableMemoryAllocator::get_stats).
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{allocate, get_allocator_stats, stable_memory_init};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let slice = unsafe { allocate(100).expect("Out of memory") };
+///
+/// let stats = get_allocator_stats();
+/// assert_eq!(stats.free_blocks_count, 1);
+/// assert_eq!(stats.fragmentation_ratio, 0.0);
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn get_allocator_stats() -> AllocatorStats {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            alloc.get_stats()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Returns a map of all memory blocks, managed by the allocator, both free and allocated.
+///
+/// Unlike [_debug_print_allocator], the result is structured data with offsets and sizes of every
+/// block, which can be serialized and used to visualize fragmentation off-chain. Takes `O(N)` time,
+/// where `N` is the total number of memory blocks, so it is only meant for debugging.
+///
+/// Internally calls [StableMemoryAllocator::export_layout](mem::allocator::StableMemoryAllocator::export_layout).
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{allocate, export_allocator_layout, stable_memory_init};
+/// # use ic_stable_memory::mem::allocator::BlockKind;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let slice = unsafe { allocate(100).expect("Out of memory") };
+///
+/// let layout = export_allocator_layout();
+/// let block = layout.blocks.iter().find(|it| it.offset == slice.as_ptr()).unwrap();
+///
+/// assert_eq!(block.kind, BlockKind::Allocated);
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn export_allocator_layout() -> AllocatorLayout {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            alloc.export_layout()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
 #[inline]
 pub fn _debug_validate_allocator() {
     STABLE_MEMORY_ALLOCATOR.with(|it: &RefCell<Option<StableMemoryAllocator>>| {
@@ -618,12 +1258,16 @@ pub fn _debug_print_allocator() {
 
 #[cfg(test)]
 mod tests {
+    use crate::mem::allocator::CustomDataError;
     use crate::{
         _debug_print_allocator, allocate, deallocate, get_allocated_size, get_free_size,
-        init_allocator, reallocate, retrieve_custom_data, stable_memory_init,
+        init_allocator, reallocate, reserve, retrieve_custom_data, stable_memory_init,
         stable_memory_post_upgrade, stable_memory_pre_upgrade, store_custom_data, SBox,
     };
-    use crate::{deinit_allocator, reinit_allocator, SSlice};
+    use crate::{
+        defragment, deinit_allocator, reinit_allocator, retrieve_named_custom_data,
+        store_named_custom_data, SSlice,
+    };
 
     #[test]
     fn basic_flow_works_fine() {
@@ -647,6 +1291,80 @@ mod tests {
         _debug_print_allocator();
     }
 
+    #[test]
+    fn reservation_works_fine() {
+        stable_memory_init();
+
+        let reservation = reserve(1000).unwrap();
+        assert!(reservation.is_active());
+        assert_eq!(reservation.size(), 1000);
+        assert!(get_allocated_size() >= 1000);
+
+        drop(reservation);
+        assert_eq!(get_allocated_size(), 0);
+
+        // reservations are released before upgrades
+        let reservation = reserve(1000).unwrap();
+        stable_memory_pre_upgrade().unwrap();
+
+        assert!(!reservation.is_active());
+        drop(reservation);
+
+        stable_memory_post_upgrade();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn named_custom_data_works_fine() {
+        stable_memory_init();
+
+        assert_eq!(
+            retrieve_named_custom_data::<u64>("a").err(),
+            Some(CustomDataError::NotFound)
+        );
+
+        let gap = unsafe { allocate(100).unwrap() };
+        assert!(store_named_custom_data("a", SBox::new(10u64).unwrap()).is_ok());
+
+        // occupied keys are not overwritten
+        let b = store_named_custom_data("a", SBox::new(20u64).unwrap()).unwrap_err();
+        assert_eq!(*b, 20);
+        drop(b);
+
+        store_custom_data(0, SBox::new(30u64).unwrap());
+
+        // named custom data is relocated by the allocator itself
+        deallocate(gap);
+        let report = defragment(u64::MAX, |_, _| false);
+        assert_eq!(report.moved_blocks, 2);
+
+        stable_memory_pre_upgrade().unwrap();
+        stable_memory_post_upgrade();
+
+        match retrieve_named_custom_data::<u32>("a") {
+            Err(CustomDataError::TypeMismatch {
+                stored_type,
+                requested_type,
+            }) => {
+                assert_eq!(stored_type, "u64");
+                assert_eq!(requested_type, "u32");
+            }
+            _ => unreachable!(),
+        }
+
+        assert_eq!(
+            retrieve_named_custom_data::<u64>("a").unwrap().into_inner(),
+            10
+        );
+        assert_eq!(retrieve_custom_data::<u64>(0).unwrap().into_inner(), 30);
+        assert_eq!(
+            retrieve_named_custom_data::<u64>("a").err(),
+            Some(CustomDataError::NotFound)
+        );
+
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[test]
     #[should_panic]
     fn init_allocator_twice_should_panic() {
@@ -9,35 +9,94 @@
 //!
 //! This allocator shouldn't be used directly - instead use top-level functions exposed by this crate.
 
-use crate::encoding::dyn_size::candid_decode_one_allow_trailing;
 use crate::encoding::{AsDynSizeBytes, AsFixedSizeBytes, Buffer};
 use crate::mem::free_block::FreeBlock;
-use crate::mem::s_slice::SSlice;
+use crate::mem::region::{Region, RegionStats};
+use crate::mem::s_slice::{
+    get_next_generation, set_next_generation, SSlice, CANARY_SIZE, MAX_BLOCK_SIZE,
+};
 use crate::mem::StablePtr;
 use crate::primitive::s_box::SBox;
 use crate::primitive::StableType;
+use crate::utils::maintenance::ScheduledTask;
 use crate::utils::math::ceil_div;
 use crate::{stable, OutOfMemory, PAGE_SIZE_BYTES};
+use candid::de::IDLDeserialize;
 use candid::{encode_one, CandidType, Deserialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 pub(crate) const ALLOCATOR_PTR: StablePtr = 0;
 pub(crate) const MIN_PTR: StablePtr = u64::SIZE as u64;
 pub(crate) const EMPTY_PTR: StablePtr = u64::MAX;
 
+// set in the pointer at ALLOCATOR_PTR, when the allocator is stored in the upgrade header
+const UPGRADE_HEADER_FLAG: StablePtr = 1 << 63;
+
+// the most a single allocation or deallocation can grow the encoded state by - a free block in a
+// new size bucket, plus a few length prefixes getting a byte longer
+const MAX_STATE_GROWTH: u64 = 64;
+
+// bigger memory blocks are not buffered on heap during reallocation
+const MAX_REALLOCATE_BUFFER_SIZE: u64 = 1024 * 1024;
+
+// bigger memory blocks are not rounded up to a size class
+const MAX_SIZE_CLASS: u64 = 4096;
+
+/// Defines how the allocator picks a free block for a new allocation
+///
+/// Set once, during [init_allocator_with_strategy](crate::init_allocator_with_strategy), and persisted
+/// between upgrades along with the allocator.
+#[derive(Debug, Default, Copy, Clone, CandidType, Deserialize, Eq, PartialEq)]
+pub enum AllocationStrategy {
+    /// Takes the smallest free block that fits. This is the default.
+    #[default]
+    BestFit,
+    /// Takes the free block with the lowest address that fits, keeping the data closer to the
+    /// beginning of stable memory.
+    FirstFit,
+    /// Rounds small allocations (up to 4KB) up to one of a few size classes (four per each power
+    /// of two) and doesn't merge small blocks with their neighbors on deallocation. Freed blocks are
+    /// then reused as-is by allocations of the same class, which practically eliminates fragmentation
+    /// for workloads that allocate lots of similarly sized values.
+    SizeClasses,
+}
+
 #[doc(hidden)]
-#[derive(Debug, CandidType, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, CandidType, Deserialize, Eq, PartialEq)]
 pub struct StableMemoryAllocator {
-    free_blocks: BTreeMap<u64, Vec<FreeBlock>>,
+    free_blocks: FreeBlocks,
     custom_data_pointers: HashMap<usize, StablePtr>,
     free_size: u64,
     available_size: u64,
     max_ptr: StablePtr,
     max_pages: u64,
+    // optional, so allocators persisted by previous versions can still be decoded
+    regions: Option<BTreeMap<String, Region>>,
+    strategy: Option<AllocationStrategy>,
+    reservations: Option<BTreeSet<StablePtr>>,
+    snapshot: Option<StablePtr>,
+    upgrade_header: Option<StablePtr>,
+    named_custom_data: Option<BTreeMap<String, NamedCustomData>>,
+    schema_versions: Option<BTreeMap<String, u32>>,
+    maintenance_queue: Option<Vec<ScheduledTask>>,
+    next_generation: Option<u32>,
+    // how much the state can grow, before it has to be checked against the upgrade header again
+    upgrade_header_slack: Option<u64>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Eq, PartialEq)]
+struct NamedCustomData {
+    ptr: StablePtr,
+    type_name: String,
 }
 
 impl StableMemoryAllocator {
+    #[inline]
     pub fn init(max_pages: u64) -> Self {
+        Self::init_with_strategy(max_pages, AllocationStrategy::default())
+    }
+
+    pub fn init_with_strategy(max_pages: u64, strategy: AllocationStrategy) -> Self {
         let mut it = Self {
             max_ptr: MIN_PTR,
             free_blocks: BTreeMap::default(),
@@ -45,6 +104,16 @@ impl StableMemoryAllocator {
             free_size: 0,
             available_size: 0,
             max_pages,
+            regions: None,
+            strategy: Some(strategy),
+            reservations: None,
+            snapshot: None,
+            upgrade_header: None,
+            named_custom_data: None,
+            schema_versions: None,
+            maintenance_queue: None,
+            next_generation: Some(get_next_generation()),
+            upgrade_header_slack: None,
         };
 
         let available_pages = stable::size_pages();
@@ -65,8 +134,20 @@ impl StableMemoryAllocator {
         it
     }
 
-    pub fn make_sure_can_allocate(&mut self, mut size: u64) -> bool {
-        size = Self::pad_size(size);
+    pub fn make_sure_can_allocate(&mut self, size: u64) -> bool {
+        loop {
+            if self.make_sure_can_allocate_unreserved(size) {
+                return true;
+            }
+
+            if !self.release_any_reservation() {
+                return false;
+            }
+        }
+    }
+
+    fn make_sure_can_allocate_unreserved(&mut self, mut size: u64) -> bool {
+        size = self.class_size(Self::pad_size(size));
 
         if self.free_blocks.range(size..).next().is_some() {
             return true;
@@ -93,9 +174,35 @@ impl StableMemoryAllocator {
         }
     }
 
+    pub fn allocate(&mut self, size: u64) -> Result<SSlice, OutOfMemory> {
+        if size > MAX_BLOCK_SIZE {
+            return Err(OutOfMemory);
+        }
+
+        loop {
+            match self.allocate_unreserved(size) {
+                Ok(slice) => {
+                    self.track_state_growth(MAX_STATE_GROWTH);
+
+                    return Ok(slice);
+                }
+                // reserved memory is only used, when there is no other way to allocate
+                Err(e) => {
+                    if !self.release_any_reservation() {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
     #[allow(clippy::never_loop)]
-    pub fn allocate(&mut self, mut size: u64) -> Result<SSlice, OutOfMemory> {
-        size = Self::pad_size(size);
+    fn allocate_unreserved(&mut self, mut size: u64) -> Result<SSlice, OutOfMemory> {
+        size = self.class_size(Self::pad_size(size));
+
+        if size > MAX_BLOCK_SIZE {
+            return Err(OutOfMemory);
+        }
 
         // searching for a free block that is equal or bigger in size, than asked
         let free_block = loop {
@@ -139,29 +246,64 @@ impl StableMemoryAllocator {
         };
 
         self.less_free_size(slice.get_total_size_bytes());
+        slice.write_canaries();
 
         Ok(slice)
     }
 
     #[inline]
     pub fn deallocate(&mut self, slice: SSlice) {
+        debug_assert!(slice.is_alive(), "Double deallocate: {:?}", slice);
+        Self::check_canaries(&slice);
+
+        if let Some(name) = self.find_region(slice.as_ptr()) {
+            return self.in_region(&name, |region, it| region.deallocate(slice, it));
+        }
+
         let free_block = slice.to_free_block();
 
         self.more_free_size(free_block.get_total_size_bytes());
-        self.push_free_block(free_block);
+
+        // small blocks are kept intact, so they can be reused by allocations of the same class
+        if self.get_strategy() == AllocationStrategy::SizeClasses
+            && free_block.get_size_bytes() <= MAX_SIZE_CLASS
+        {
+            insert_free_block(&mut self.free_blocks, free_block);
+        } else {
+            self.push_free_block(free_block);
+        }
+
+        self.track_state_growth(MAX_STATE_GROWTH);
+    }
+
+    pub fn reallocate(&mut self, slice: SSlice, new_size: u64) -> Result<SSlice, OutOfMemory> {
+        debug_assert!(slice.is_alive(), "Reallocate after deallocate: {:?}", slice);
+        Self::check_canaries(&slice);
+
+        let new_slice = if let Some(name) = self.find_region(slice.as_ptr()) {
+            self.in_region(&name, |region, it| region.reallocate(slice, new_size, it))?
+        } else {
+            self.reallocate_block(slice, new_size)?
+        };
+
+        // the rear canary moves together with the end of the memory block
+        new_slice.write_canaries();
+        self.track_state_growth(MAX_STATE_GROWTH * 2);
+
+        Ok(new_slice)
     }
 
-    pub fn reallocate(&mut self, slice: SSlice, mut new_size: u64) -> Result<SSlice, OutOfMemory> {
-        new_size = Self::pad_size(new_size);
+    fn reallocate_block(&mut self, slice: SSlice, new_size: u64) -> Result<SSlice, OutOfMemory> {
+        let padded_size = self.class_size(Self::pad_size(new_size));
 
-        if new_size <= slice.get_size_bytes() {
+        if padded_size <= slice.get_raw_size_bytes() {
             return Ok(slice);
         }
 
         let free_block = slice.to_free_block();
 
         // if it is possible to simply "grow" the slice, by merging it with the next neighbor - do that
-        if let Ok(fb) = self.try_reallocate_in_place(free_block, new_size) {
+        if let Ok(fb) = self.try_reallocate_in_place(free_block, padded_size) {
             return Ok(fb);
         }
 
@@ -170,8 +312,20 @@ impl StableMemoryAllocator {
             return Err(OutOfMemory);
         }
 
+        // big blocks are copied to a new location directly, both blocks exist for a moment
+        if slice.get_size_bytes() > MAX_REALLOCATE_BUFFER_SIZE {
+            let new_slice = self.allocate(new_size)?;
+            unsafe {
+                crate::mem::copy_bytes(slice.offset(0), new_slice.offset(0), slice.get_size_bytes())
+            };
+
+            self.deallocate(slice);
+
+            return Ok(new_slice);
+        }
+
         // othewise, get ready for move and copy the data
-        let mut b = vec![0u8; slice.get_size_bytes().try_into().unwrap()];
+        let mut b = vec![0u8; slice.get_size_bytes() as usize];
         unsafe { crate::mem::read_bytes(slice.offset(0), &mut b) };
 
         // deallocate the slice
@@ -187,13 +341,29 @@ impl StableMemoryAllocator {
         Ok(new_slice)
     }
 
+    // with an upgrade header nothing gets allocated here, since the header is grown along with the
+    // state; only fails without one, or if the header couldn't be grown earlier because of OutOfMemory
     pub fn store(&mut self) -> Result<(), OutOfMemory> {
+        // reservations can't outlive the canister's heap, where their handles are stored; they are
+        // paid for in advance, so releasing them can't outgrow the upgrade header
+        let slack = self.upgrade_header_slack.take();
+        self.release_reservations();
+        self.upgrade_header_slack = slack;
+
+        self.next_generation = Some(get_next_generation());
+
+        if self.store_to_upgrade_header() {
+            return Ok(());
+        }
+
         // first encode is simply to calculate the required size
         let buf = self.as_dyn_size_bytes();
 
         // reserving 100 extra bytes in order for the allocator to grow while allocating memory for itself
         let slice = self.allocate(buf.len() as u64 + 100)?;
 
+        // the allocation above took a generation
+        self.next_generation = Some(get_next_generation());
         let buf = self.as_dyn_size_bytes();
 
         unsafe { crate::mem::write_bytes(slice.offset(0), &buf) };
@@ -203,18 +373,158 @@ impl StableMemoryAllocator {
     }
 
     pub fn retrieve() -> Self {
-        let slice_ptr = unsafe { crate::mem::read_fixed_for_reference(0) };
+        let slice_ptr: StablePtr = unsafe { crate::mem::read_fixed_for_reference(0) };
+
+        if slice_ptr & UPGRADE_HEADER_FLAG != 0 {
+            return Self::retrieve_from_upgrade_header(slice_ptr & !UPGRADE_HEADER_FLAG);
+        }
+
         let slice = unsafe { SSlice::from_ptr(slice_ptr).unwrap() };
 
         let mut buf = vec![0u8; slice.get_size_bytes() as usize];
         unsafe { crate::mem::read_bytes(slice.offset(0), &mut buf) };
 
         let mut it = Self::from_dyn_size_bytes(&buf);
+        it.restore_next_generation();
         it.deallocate(slice);
+        it.fit_upgrade_header();
+
+        it
+    }
+
+    // the upgrade header is a memory block, allocated in advance, where the allocator's state is
+    // written during an upgrade, so nothing has to be allocated at that moment
+    pub fn reserve_upgrade_header(&mut self, capacity: u64) -> Result<(), OutOfMemory> {
+        self.upgrade_header_slack = None;
+        let res = self.reserve_upgrade_header_block(capacity);
+        self.fit_upgrade_header();
+
+        res
+    }
+
+    fn reserve_upgrade_header_block(&mut self, capacity: u64) -> Result<(), OutOfMemory> {
+        match self.upgrade_header {
+            Some(ptr) => {
+                let slice = unsafe { SSlice::from_ptr(ptr).unwrap() };
+                if slice.get_size_bytes() >= capacity {
+                    return Ok(());
+                }
+
+                let slice = self.reallocate(slice, capacity)?;
+                self.upgrade_header = Some(slice.as_ptr());
+            }
+            None => {
+                let slice = self.allocate(capacity)?;
+                self.upgrade_header = Some(slice.as_ptr());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn release_upgrade_header(&mut self) -> bool {
+        self.upgrade_header_slack = None;
+
+        match self.upgrade_header.take() {
+            Some(ptr) => {
+                self.deallocate(unsafe { SSlice::from_ptr(ptr).unwrap() });
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[inline]
+    pub fn get_upgrade_header_capacity(&self) -> Option<u64> {
+        self.upgrade_header
+            .map(|ptr| unsafe { SSlice::from_ptr(ptr).unwrap() }.get_size_bytes())
+    }
+
+    // returns false, if there is no upgrade header or the state doesn't fit into it
+    fn store_to_upgrade_header(&mut self) -> bool {
+        let slice = match self.upgrade_header {
+            Some(ptr) => unsafe { SSlice::from_ptr(ptr).unwrap() },
+            None => return false,
+        };
+
+        let buf = self.as_dyn_size_bytes();
+        if u64::SIZE as u64 + buf.len() as u64 > slice.get_size_bytes() {
+            return false;
+        }
+
+        unsafe {
+            crate::mem::write_fixed(slice.offset(0), &mut (buf.len() as u64));
+            crate::mem::write_bytes(slice.offset(u64::SIZE as u64), &buf);
+            crate::mem::write_fixed(ALLOCATOR_PTR, &mut (slice.as_ptr() | UPGRADE_HEADER_FLAG));
+        }
+
+        true
+    }
+
+    fn retrieve_from_upgrade_header(ptr: StablePtr) -> Self {
+        let slice = unsafe { SSlice::from_ptr(ptr).unwrap() };
+
+        let len: u64 = unsafe { crate::mem::read_fixed_for_reference(slice.offset(0)) };
+        let mut buf = vec![0u8; len as usize];
+        unsafe { crate::mem::read_bytes(slice.offset(u64::SIZE as u64), &mut buf) };
+
+        let mut it = Self::from_dyn_size_bytes(&buf);
+        it.restore_next_generation();
+        it.fit_upgrade_header();
 
         it
     }
 
+    // allocators persisted by previous versions don't have it, then generations simply continue
+    fn restore_next_generation(&mut self) {
+        match self.next_generation {
+            Some(generation) => set_next_generation(generation),
+            None => self.next_generation = Some(get_next_generation()),
+        }
+    }
+
+    // the state is only encoded once it might have outgrown the upgrade header, otherwise the
+    // growth is simply subtracted from the slack
+    #[inline]
+    fn track_state_growth(&mut self, max_growth: u64) {
+        match self.upgrade_header_slack {
+            Some(slack) if slack >= max_growth => {
+                self.upgrade_header_slack = Some(slack - max_growth)
+            }
+            Some(_) => self.fit_upgrade_header(),
+            None => {}
+        }
+    }
+
+    // grows the upgrade header twice as big as the state, once the state doesn't fit into it; it is
+    // fine to fail here - then the state is stored the usual way, if it still doesn't fit, and the
+    // state is not tracked anymore, until the header is reserved explicitly again
+    fn fit_upgrade_header(&mut self) {
+        // the state is not tracked, while the header itself is reallocated
+        self.upgrade_header_slack = None;
+
+        while let Some(capacity) = self.get_upgrade_header_capacity() {
+            // the slack itself is not encoded yet, and reservations are released during store
+            let reservations = self
+                .reservations
+                .as_ref()
+                .map(|it| it.len())
+                .unwrap_or_default();
+            let required = (u64::SIZE * 2) as u64
+                + self.as_dyn_size_bytes().len() as u64
+                + reservations as u64 * MAX_STATE_GROWTH;
+
+            if capacity >= required {
+                self.upgrade_header_slack = Some(capacity - required);
+                break;
+            }
+
+            if self.reserve_upgrade_header_block(required * 2).is_err() {
+                break;
+            }
+        }
+    }
+
     #[inline]
     pub fn get_allocated_size(&self) -> u64 {
         self.available_size - self.free_size
@@ -254,6 +564,7 @@ impl StableMemoryAllocator {
         unsafe { data.stable_drop_flag_off() };
 
         self.custom_data_pointers.insert(idx, data.as_ptr());
+        self.track_state_growth(MAX_STATE_GROWTH);
     }
 
     #[inline]
@@ -267,203 +578,1015 @@ impl StableMemoryAllocator {
         Some(b)
     }
 
-    #[inline]
-    pub fn get_max_pages(&self) -> u64 {
-        self.max_pages
+    // the type name is stored along with the pointer, so it can be checked during retrieval
+    pub fn store_named_custom_data<T: AsDynSizeBytes + StableType>(
+        &mut self,
+        key: &str,
+        mut data: SBox<T>,
+    ) -> Result<(), SBox<T>> {
+        let entries = self.named_custom_data.get_or_insert_with(BTreeMap::default);
+        if entries.contains_key(key) {
+            return Err(data);
+        }
+
+        unsafe { data.stable_drop_flag_off() };
+
+        let type_name = std::any::type_name::<T>();
+        entries.insert(
+            String::from(key),
+            NamedCustomData {
+                ptr: data.as_ptr(),
+                type_name: String::from(type_name),
+            },
+        );
+        self.track_state_growth((key.len() + type_name.len()) as u64 + MAX_STATE_GROWTH);
+
+        Ok(())
     }
 
-    fn try_reallocate_in_place(
+    pub fn retrieve_named_custom_data<T: AsDynSizeBytes + StableType>(
         &mut self,
-        mut free_block: FreeBlock,
-        new_size: u64,
-    ) -> Result<SSlice, Result<FreeBlock, OutOfMemory>> {
-        if let Some(mut next_neighbor) = free_block.next_neighbor_is_free(self.max_ptr) {
-            let mut merged_size = FreeBlock::merged_size(&free_block, &next_neighbor);
-
-            if merged_size < new_size {
-                if next_neighbor.get_next_neighbor_ptr() != self.max_ptr {
-                    return Err(Ok(free_block));
-                }
+        key: &str,
+    ) -> Result<SBox<T>, CustomDataError> {
+        let entries = self
+            .named_custom_data
+            .as_mut()
+            .ok_or(CustomDataError::NotFound)?;
+        let entry = entries.get(key).ok_or(CustomDataError::NotFound)?;
+
+        let requested_type = std::any::type_name::<T>();
+        if entry.type_name != requested_type {
+            return Err(CustomDataError::TypeMismatch {
+                stored_type: entry.type_name.clone(),
+                requested_type: String::from(requested_type),
+            });
+        }
 
-                let fb = self.grow(new_size).map_err(Err)?;
+        let entry = entries.remove(key).unwrap();
 
-                self.more_available_size(fb.get_total_size_bytes());
+        let mut b = unsafe { SBox::from_ptr(entry.ptr) };
+        unsafe { SBox::<T>::stable_drop_flag_on(&mut b) };
 
-                self.less_free_size(next_neighbor.get_total_size_bytes());
-                self.remove_free_block(&next_neighbor);
+        Ok(b)
+    }
 
-                next_neighbor = FreeBlock::merge(next_neighbor, fb);
-                merged_size = FreeBlock::merged_size(&free_block, &next_neighbor);
-            } else {
-                self.less_free_size(next_neighbor.get_total_size_bytes());
-                self.remove_free_block(&next_neighbor);
-            }
+    #[inline]
+    pub fn get_schema_version(&self, key: &str) -> Option<u32> {
+        self.schema_versions.as_ref()?.get(key).copied()
+    }
 
-            free_block = FreeBlock::merge(free_block, next_neighbor);
+    #[inline]
+    pub fn set_schema_version(&mut self, key: &str, version: u32) {
+        self.schema_versions
+            .get_or_insert_with(BTreeMap::default)
+            .insert(String::from(key), version);
+        self.track_state_growth(key.len() as u64 + MAX_STATE_GROWTH);
+    }
 
-            if !FreeBlock::can_split(merged_size, new_size) {
-                return Ok(free_block.to_allocated());
-            }
+    #[inline]
+    pub(crate) fn get_maintenance_queue(&self) -> &[ScheduledTask] {
+        self.maintenance_queue.as_deref().unwrap_or_default()
+    }
 
-            let (free_block, b) = free_block.split(new_size);
+    pub(crate) fn with_maintenance_queue<R, F: FnOnce(&mut Vec<ScheduledTask>) -> R>(
+        &mut self,
+        func: F,
+    ) -> R {
+        let queue = self.maintenance_queue.get_or_insert_with(Vec::default);
+        let size_before = queue.iter().map(|it| it.encoded_size_bound()).sum::<u64>();
 
-            let slice = free_block.to_allocated();
+        let res = func(queue);
 
-            self.more_free_size(b.get_total_size_bytes());
-            self.push_free_block(b);
+        let size_after = queue.iter().map(|it| it.encoded_size_bound()).sum::<u64>();
+        self.track_state_growth(size_after.saturating_sub(size_before) + MAX_STATE_GROWTH);
 
-            return Ok(slice);
-        }
+        res
+    }
 
-        Err(Ok(free_block))
+    #[inline]
+    pub fn get_max_ptr(&self) -> StablePtr {
+        self.max_ptr
     }
 
-    fn try_merge_with_neighbors(&mut self, mut free_block: FreeBlock) -> FreeBlock {
-        if let Some(prev_neighbor) = free_block.prev_neighbor_is_free() {
-            self.remove_free_block(&prev_neighbor);
+    #[inline]
+    pub fn get_max_pages(&self) -> u64 {
+        self.max_pages
+    }
 
-            free_block = FreeBlock::merge(prev_neighbor, free_block);
-        };
+    #[inline]
+    pub fn get_strategy(&self) -> AllocationStrategy {
+        self.strategy.unwrap_or_default()
+    }
 
-        if let Some(next_neighbor) = free_block.next_neighbor_is_free(self.max_ptr) {
-            self.remove_free_block(&next_neighbor);
+    /// Creates a new region, or updates the quota of an existing one
+    pub fn init_region(&mut self, name: &str, quota: u64) {
+        let regions = self.regions.get_or_insert_with(BTreeMap::default);
 
-            free_block = FreeBlock::merge(free_block, next_neighbor);
+        match regions.get_mut(name) {
+            Some(region) => region.set_quota(quota),
+            None => {
+                regions.insert(String::from(name), Region::new(quota));
+                self.track_state_growth(name.len() as u64 + MAX_STATE_GROWTH * 2);
+            }
         }
+    }
 
-        free_block
+    #[inline]
+    pub fn has_region(&self, name: &str) -> bool {
+        self.get_region_stats(name).is_some()
     }
 
-    fn push_free_block(&mut self, mut free_block: FreeBlock) {
-        free_block = self.try_merge_with_neighbors(free_block);
+    pub fn get_region_stats(&self, name: &str) -> Option<RegionStats> {
+        self.regions.as_ref()?.get(name).map(|it| it.get_stats())
+    }
 
-        free_block.persist();
+    /// Returns statistics of all regions, ordered by their names
+    pub fn get_all_region_stats(&self) -> Vec<(String, RegionStats)> {
+        self.regions
+            .iter()
+            .flatten()
+            .map(|(name, region)| (name.clone(), region.get_stats()))
+            .collect()
+    }
 
-        let blocks = self
-            .free_blocks
-            .entry(free_block.get_size_bytes())
-            .or_default();
+    pub fn make_sure_can_allocate_in(&mut self, region: &str, size: u64) -> bool {
+        self.in_region(region, |region, it| region.make_sure_can_allocate(size, it))
+    }
 
-        let idx = match blocks.binary_search(&free_block) {
-            Ok(_) => unreachable!("there can't be two blocks of the same ptr"),
-            Err(idx) => idx,
-        };
+    pub fn allocate_in(&mut self, region: &str, size: u64) -> Result<SSlice, OutOfMemory> {
+        let slice = self.in_region(region, |region, it| region.allocate(size, it))?;
+        slice.write_canaries();
 
-        blocks.insert(idx, free_block);
+        Ok(slice)
     }
 
-    fn pop_free_block(&mut self, size: u64) -> Option<FreeBlock> {
-        let (&actual_size, blocks) = self.free_blocks.range_mut(size..).next()?;
+    // the region is taken out of the map, so it can allocate its chunks from this allocator
+    fn in_region<R, F: FnOnce(&mut Region, &mut Self) -> R>(&mut self, name: &str, func: F) -> R {
+        let mut region = self
+            .regions
+            .as_mut()
+            .and_then(|it| it.remove(name))
+            .unwrap_or_else(|| panic!("Region {} does not exist", name));
 
-        let free_block = unsafe { blocks.pop().unwrap_unchecked() };
+        // the state is incomplete without the region, so it is tracked once the region is back
+        let slack = self.upgrade_header_slack.take();
+        let res = func(&mut region, self);
 
-        if blocks.is_empty() {
-            self.free_blocks.remove(&actual_size);
+        if let Some(regions) = &mut self.regions {
+            regions.insert(String::from(name), region);
         }
 
-        Some(free_block)
-    }
-
-    fn remove_free_block(&mut self, block: &FreeBlock) {
-        let blocks = self.free_blocks.get_mut(&block.get_size_bytes()).unwrap();
-
-        match blocks.binary_search(block) {
-            Ok(idx) => {
-                blocks.remove(idx);
+        // a new chunk and a few free blocks at most
+        self.upgrade_header_slack = slack;
+        self.track_state_growth(MAX_STATE_GROWTH * 3);
 
-                if blocks.is_empty() {
-                    self.free_blocks.remove(&block.get_size_bytes());
-                }
-            }
-            Err(_) => unreachable!("Free block not found {:?} {:?}", block, self.free_blocks),
-        };
+        res
     }
 
-    fn grow(&mut self, mut size: u64) -> Result<FreeBlock, OutOfMemory> {
-        size = FreeBlock::to_total_size(size);
-        let pages_to_grow = ceil_div(size, PAGE_SIZE_BYTES);
-        let available_pages = stable::size_pages();
+    fn find_region(&self, ptr: StablePtr) -> Option<String> {
+        self.regions
+            .as_ref()?
+            .iter()
+            .find(|(_, region)| region.contains(ptr))
+            .map(|(name, _)| name.clone())
+    }
 
-        if self.max_pages != 0 && available_pages + pages_to_grow > self.max_pages {
-            return Err(OutOfMemory);
+    fn is_region_chunk(&self, ptr: StablePtr) -> bool {
+        match &self.regions {
+            Some(regions) => regions.values().any(|it| it.is_chunk(ptr)),
+            None => false,
         }
+    }
 
-        if stable::grow(pages_to_grow).is_err() {
-            return Err(OutOfMemory);
-        }
+    fn find_region_by_chunk(&self, ptr: StablePtr) -> Option<String> {
+        self.regions
+            .as_ref()?
+            .iter()
+            .find(|(_, region)| region.is_chunk(ptr))
+            .map(|(name, _)| name.clone())
+    }
 
-        let new_max_ptr = (available_pages + pages_to_grow) * PAGE_SIZE_BYTES;
-        let it = FreeBlock::new_total_size(self.max_ptr, new_max_ptr - self.max_ptr);
+    // reserved memory blocks are simply allocated ones, which get deallocated on demand
+    pub fn reserve(&mut self, size: u64) -> Result<StablePtr, OutOfMemory> {
+        let slice = self.allocate_unreserved(size)?;
 
-        self.max_ptr = new_max_ptr;
+        self.reservations
+            .get_or_insert_with(BTreeSet::default)
+            .insert(slice.as_ptr());
 
-        Ok(it)
+        // twice, since the reservation gets released during store, where nothing can be allocated
+        self.track_state_growth(MAX_STATE_GROWTH * 2);
+
+        Ok(slice.as_ptr())
     }
 
-    pub fn debug_validate_free_blocks(&self) {
-        assert!(
-            self.available_size == 0
-                || self.available_size == stable::size_pages() * PAGE_SIZE_BYTES - MIN_PTR
-        );
+    pub fn is_reserved(&self, ptr: StablePtr) -> bool {
+        match &self.reservations {
+            Some(reservations) => reservations.contains(&ptr),
+            None => false,
+        }
+    }
 
-        let mut total_free_size = 0u64;
-        for blocks in self.free_blocks.values() {
-            for free_block in blocks {
-                free_block.debug_validate();
+    pub fn release_reservation(&mut self, ptr: StablePtr) -> bool {
+        let removed = match &mut self.reservations {
+            Some(reservations) => reservations.remove(&ptr),
+            None => false,
+        };
 
-                total_free_size += free_block.get_total_size_bytes();
-            }
+        if removed {
+            self.deallocate(unsafe { SSlice::from_ptr(ptr).unwrap() });
         }
 
-        assert_eq!(total_free_size, self.free_size);
+        removed
     }
 
-    pub fn _free_blocks_count(&self) -> usize {
-        let mut count = 0;
+    pub fn release_reservations(&mut self) {
+        while self.release_any_reservation() {}
+    }
 
-        for blocks in self.free_blocks.values() {
-            for _ in blocks {
-                count += 1;
+    fn release_any_reservation(&mut self) -> bool {
+        match self.reservations.as_mut().and_then(|it| it.pop_last()) {
+            Some(ptr) => {
+                self.deallocate(unsafe { SSlice::from_ptr(ptr).unwrap() });
+                true
             }
+            None => false,
         }
-
-        count
     }
 
-    // minimum size is 16 bytes (32 bytes total size)
-    // otherwise size is ceiled to the nearest multiple of 8
-    #[inline]
-    fn pad_size(size: u64) -> u64 {
-        if size < (StablePtr::SIZE * 2) as u64 {
-            return (StablePtr::SIZE * 2) as u64;
+    // a snapshot is a single memory block, holding the allocator's state, followed by a copy of
+    // all the other memory, managed by the allocator
+    pub fn take_snapshot(&mut self) -> Result<(), OutOfMemory> {
+        self.discard_snapshot();
+
+        // the snapshot block itself is not copied, so the rest of the memory can only grow by less
+        // than a page, left after the snapshot block is split from a grown free block
+        let state_size = self.as_dyn_size_bytes().len() as u64 + 100;
+        let size = u64::SIZE as u64 + state_size + (self.max_ptr - MIN_PTR) + PAGE_SIZE_BYTES;
+
+        let slice = self.allocate(size)?;
+        self.snapshot = Some(slice.as_ptr());
+
+        let state = self.as_dyn_size_bytes();
+        let ranges = self.snapshot_ranges(&slice);
+
+        let required_size = u64::SIZE as u64 + state.len() as u64 + ranges[0].1 + ranges[1].1;
+        if required_size > slice.get_size_bytes() {
+            self.snapshot = None;
+            self.deallocate(slice);
+
+            return Err(OutOfMemory);
         }
 
-        (size + 7) & !7
-    }
-}
+        unsafe {
+            crate::mem::write_fixed(slice.offset(0), &mut (state.len() as u64));
+            crate::mem::write_bytes(slice.offset(u64::SIZE as u64), &state);
+        }
 
-impl AsDynSizeBytes for StableMemoryAllocator {
-    #[inline]
-    fn as_dyn_size_bytes(&self) -> Vec<u8> {
-        encode_one(self).unwrap()
-    }
+        let mut offset = slice.offset(u64::SIZE as u64 + state.len() as u64);
+        for (ptr, len) in ranges {
+            unsafe { crate::mem::copy_bytes(ptr, offset, len) };
+            offset += len;
+        }
 
-    #[inline]
-    fn from_dyn_size_bytes(buf: &[u8]) -> Self {
-        candid_decode_one_allow_trailing(buf).unwrap()
+        Ok(())
     }
-}
+
+    // the snapshot is kept, so it is possible to restore it again
+    pub fn restore_snapshot(&mut self) -> bool {
+        let slice = match self.snapshot {
+            Some(ptr) => unsafe { SSlice::from_ptr(ptr).unwrap() },
+            None => return false,
+        };
+
+        let state_len: u64 = unsafe { crate::mem::read_fixed_for_reference(slice.offset(0)) };
+        let mut state = vec![0u8; state_len as usize];
+        unsafe { crate::mem::read_bytes(slice.offset(u64::SIZE as u64), &mut state) };
+
+        let restored = Self::from_dyn_size_bytes(&state);
+
+        let mut offset = slice.offset(u64::SIZE as u64 + state_len);
+        for (ptr, len) in restored.snapshot_ranges(&slice) {
+            unsafe { crate::mem::copy_bytes(offset, ptr, len) };
+            offset += len;
+        }
+
+        *self = restored;
+        self.reclaim_grown_pages();
+        self.fit_upgrade_header();
+
+        true
+    }
+
+    pub fn discard_snapshot(&mut self) -> bool {
+        match self.snapshot.take() {
+            Some(ptr) => {
+                self.deallocate(unsafe { SSlice::from_ptr(ptr).unwrap() });
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[inline]
+    pub fn has_snapshot(&self) -> bool {
+        self.snapshot.is_some()
+    }
+
+    // everything before and after the snapshot block
+    fn snapshot_ranges(&self, slice: &SSlice) -> [(StablePtr, u64); 2] {
+        let end = slice.as_ptr() + slice.get_total_size_bytes();
+
+        [
+            (MIN_PTR, slice.as_ptr() - MIN_PTR),
+            (end, self.max_ptr - end),
+        ]
+    }
+
+    pub fn get_stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats {
+            free_size: self.free_size,
+            allocated_size: self.get_allocated_size(),
+            available_size: self.available_size,
+            ..Default::default()
+        };
+
+        for free_block in self.free_blocks.values().flatten() {
+            let total_size = free_block.get_total_size_bytes();
+
+            // total size is never zero, so the bucket is floor(log2(total_size))
+            let bucket = (u64::BITS - 1 - total_size.leading_zeros()) as usize;
+            if stats.free_block_histogram.len() <= bucket {
+                stats.free_block_histogram.resize(bucket + 1, 0);
+            }
+
+            stats.free_block_histogram[bucket] += 1;
+            stats.free_blocks_count += 1;
+            stats.largest_free_block = stats.largest_free_block.max(total_size);
+        }
+
+        if self.free_size > 0 {
+            stats.fragmentation_ratio =
+                1.0 - stats.largest_free_block as f64 / self.free_size as f64;
+        }
+
+        stats
+    }
+
+    pub fn export_layout(&self) -> AllocatorLayout {
+        let mut layout = AllocatorLayout {
+            min_ptr: MIN_PTR,
+            max_ptr: self.max_ptr,
+            blocks: Vec::new(),
+        };
+
+        let mut ptr = MIN_PTR;
+        while ptr < self.max_ptr {
+            let (size, kind) = match unsafe { SSlice::from_ptr(ptr) } {
+                Some(slice) => {
+                    let kind = if let Some(name) = self.find_region_by_chunk(ptr) {
+                        BlockKind::RegionChunk(name)
+                    } else if self
+                        .reservations
+                        .as_ref()
+                        .map(|it| it.contains(&ptr))
+                        .unwrap_or_default()
+                    {
+                        BlockKind::Reserved
+                    } else {
+                        BlockKind::Allocated
+                    };
+
+                    (slice.get_total_size_bytes(), kind)
+                }
+                None => (
+                    FreeBlock::from_ptr(ptr).unwrap().get_total_size_bytes(),
+                    BlockKind::Free,
+                ),
+            };
+
+            layout.blocks.push(BlockLayout {
+                offset: ptr,
+                size,
+                kind,
+            });
+
+            ptr += size;
+        }
+
+        layout
+    }
+
+    fn try_reallocate_in_place(
+        &mut self,
+        mut free_block: FreeBlock,
+        new_size: u64,
+    ) -> Result<SSlice, Result<FreeBlock, OutOfMemory>> {
+        if let Some(mut next_neighbor) = free_block.next_neighbor_is_free(self.max_ptr) {
+            let mut merged_size = FreeBlock::merged_size(&free_block, &next_neighbor);
+
+            if merged_size < new_size {
+                if next_neighbor.get_next_neighbor_ptr() != self.max_ptr {
+                    return Err(Ok(free_block));
+                }
+
+                let fb = self.grow(new_size).map_err(Err)?;
+
+                self.more_available_size(fb.get_total_size_bytes());
+
+                self.less_free_size(next_neighbor.get_total_size_bytes());
+                self.remove_free_block(&next_neighbor);
+
+                next_neighbor = FreeBlock::merge(next_neighbor, fb);
+                merged_size = FreeBlock::merged_size(&free_block, &next_neighbor);
+            } else {
+                self.less_free_size(next_neighbor.get_total_size_bytes());
+                self.remove_free_block(&next_neighbor);
+            }
+
+            free_block = FreeBlock::merge(free_block, next_neighbor);
+
+            if !FreeBlock::can_split(merged_size, new_size) {
+                return Ok(free_block.to_allocated());
+            }
+
+            let (free_block, b) = free_block.split(new_size);
+
+            let slice = free_block.to_allocated();
+
+            self.more_free_size(b.get_total_size_bytes());
+            self.push_free_block(b);
+
+            return Ok(slice);
+        }
+
+        Err(Ok(free_block))
+    }
+
+    #[inline]
+    fn try_merge_with_neighbors(&mut self, free_block: FreeBlock) -> FreeBlock {
+        try_merge_with_neighbors(&mut self.free_blocks, free_block, self.max_ptr)
+    }
+
+    #[inline]
+    fn push_free_block(&mut self, free_block: FreeBlock) {
+        let free_block = self.try_merge_with_neighbors(free_block);
+
+        insert_free_block(&mut self.free_blocks, free_block);
+    }
+
+    fn pop_free_block(&mut self, size: u64) -> Option<FreeBlock> {
+        if self.get_strategy() != AllocationStrategy::FirstFit {
+            return pop_free_block(&mut self.free_blocks, size);
+        }
+
+        // blocks of the same size are sorted by address, so only the first one of each size is checked
+        let free_block = *self
+            .free_blocks
+            .range(size..)
+            .filter_map(|(_, blocks)| blocks.first())
+            .min()?;
+
+        self.remove_free_block(&free_block);
+
+        Some(free_block)
+    }
+
+    #[inline]
+    fn remove_free_block(&mut self, block: &FreeBlock) {
+        remove_free_block(&mut self.free_blocks, block);
+    }
+
+    fn grow(&mut self, mut size: u64) -> Result<FreeBlock, OutOfMemory> {
+        size = FreeBlock::to_total_size(size);
+        let pages_to_grow = ceil_div(size, PAGE_SIZE_BYTES);
+        let available_pages = stable::size_pages();
+
+        if self.max_pages != 0 && available_pages + pages_to_grow > self.max_pages {
+            return Err(OutOfMemory);
+        }
+
+        if stable::grow(pages_to_grow).is_err() {
+            return Err(OutOfMemory);
+        }
+
+        let new_max_ptr = (available_pages + pages_to_grow) * PAGE_SIZE_BYTES;
+        let it = FreeBlock::new_total_size(self.max_ptr, new_max_ptr - self.max_ptr);
+
+        self.max_ptr = new_max_ptr;
+
+        Ok(it)
+    }
+
+    /// Relocates allocated memory blocks towards the beginning of stable memory, closing gaps
+    /// between them, until `budget` bytes are moved
+    ///
+    /// Free blocks are processed in address order - the allocated block, that immediately follows
+    /// a free block, is moved down into it, so the free space "bubbles up" and merges with the next
+    /// free block. Before each move `relocate(old_ptr, new_ptr)` is called - it should either update
+    /// all pointers to the block and return `true`, or return `false`, if the block can't be moved.
+    /// Blocks, stored as custom data, are relocated automatically.
+    #[inline]
+    pub fn defragment<F: FnMut(StablePtr, StablePtr) -> bool>(
+        &mut self,
+        budget: u64,
+        relocate: F,
+    ) -> DefragmentationReport {
+        self.defragment_tracked(budget, relocate, |_, _, _| {})
+    }
+
+    // the same as defragment, but also calls on_move(old_ptr, new_ptr, size) after each move
+    pub(crate) fn defragment_tracked<F, M>(
+        &mut self,
+        budget: u64,
+        mut relocate: F,
+        mut on_move: M,
+    ) -> DefragmentationReport
+    where
+        F: FnMut(StablePtr, StablePtr) -> bool,
+        M: FnMut(StablePtr, StablePtr, u64),
+    {
+        let mut report = DefragmentationReport {
+            largest_free_block_before: self.largest_free_block_size(),
+            ..Default::default()
+        };
+
+        let mut free_block_ptrs = self
+            .free_blocks
+            .values()
+            .flatten()
+            .map(|it| it.as_ptr())
+            .collect::<Vec<_>>();
+        free_block_ptrs.sort_unstable();
+
+        let mut free_block_ptrs = free_block_ptrs.into_iter();
+        let mut current = None;
+
+        loop {
+            let free_block = match current.take() {
+                Some(it) => it,
+                // blocks that were merged with others during previous moves are skipped
+                None => match free_block_ptrs.find_map(|ptr| self.find_free_block(ptr)) {
+                    Some(it) => it,
+                    None => break,
+                },
+            };
+
+            let next_ptr = free_block.get_next_neighbor_ptr();
+            if next_ptr >= self.max_ptr {
+                break;
+            }
+
+            let slice = match unsafe { SSlice::from_ptr(next_ptr) } {
+                Some(it) => it,
+                // small free blocks are not merged eagerly, when size classes are used
+                None => {
+                    self.remove_free_block(&free_block);
+                    let free_block = self.try_merge_with_neighbors(free_block);
+                    insert_free_block(&mut self.free_blocks, free_block);
+
+                    current = Some(free_block);
+                    continue;
+                }
+            };
+            if report.moved_bytes + slice.get_size_bytes() > budget {
+                break;
+            }
+
+            // a chunk can't be moved, since all memory blocks inside it would move too
+            if self.is_region_chunk(slice.as_ptr())
+                || self.is_reserved(slice.as_ptr())
+                || self.snapshot == Some(slice.as_ptr())
+                || self.upgrade_header == Some(slice.as_ptr())
+            {
+                continue;
+            }
+
+            let is_custom_data = self.custom_data_ptrs().any(|it| *it == slice.as_ptr());
+
+            if !is_custom_data && !relocate(slice.as_ptr(), free_block.as_ptr()) {
+                continue;
+            }
+
+            current = Some(self.move_down(free_block, slice));
+            on_move(slice.as_ptr(), free_block.as_ptr(), slice.get_size_bytes());
+
+            if is_custom_data {
+                for ptr in self.custom_data_ptrs_mut() {
+                    if *ptr == slice.as_ptr() {
+                        *ptr = free_block.as_ptr();
+                    }
+                }
+            }
+
+            report.moved_blocks += 1;
+            report.moved_bytes += slice.get_size_bytes();
+        }
+
+        report.largest_free_block_after = self.largest_free_block_size();
+
+        report
+    }
+
+    fn custom_data_ptrs(&self) -> impl Iterator<Item = &StablePtr> {
+        self.custom_data_pointers.values().chain(
+            self.named_custom_data
+                .iter()
+                .flatten()
+                .map(|(_, it)| &it.ptr),
+        )
+    }
+
+    fn custom_data_ptrs_mut(&mut self) -> impl Iterator<Item = &mut StablePtr> {
+        self.custom_data_pointers.values_mut().chain(
+            self.named_custom_data
+                .iter_mut()
+                .flatten()
+                .map(|(_, it)| &mut it.ptr),
+        )
+    }
+
+    // swaps the free block with the allocated block, that follows it, returning the new free block
+    fn move_down(&mut self, free_block: FreeBlock, slice: SSlice) -> FreeBlock {
+        let mut buf = vec![0u8; slice.get_size_bytes() as usize];
+        unsafe { crate::mem::read_bytes(slice.offset(0), &mut buf) };
+
+        self.remove_free_block(&free_block);
+
+        let new_slice = SSlice::new(free_block.as_ptr(), slice.get_raw_size_bytes(), true);
+        unsafe { crate::mem::write_bytes(new_slice.offset(0), &buf) };
+        new_slice.write_canaries();
+
+        let free_block = FreeBlock::new_total_size(
+            free_block.as_ptr() + new_slice.get_total_size_bytes(),
+            free_block.get_total_size_bytes(),
+        );
+
+        let free_block = self.try_merge_with_neighbors(free_block);
+        self.push_free_block(free_block);
+
+        free_block
+    }
+
+    // returns a free block, only if it is actually tracked by the allocator
+    fn find_free_block(&self, ptr: StablePtr) -> Option<FreeBlock> {
+        let free_block = FreeBlock::from_ptr(ptr)?;
+        let blocks = self.free_blocks.get(&free_block.get_size_bytes())?;
+
+        blocks.binary_search(&free_block).ok().map(|_| free_block)
+    }
+
+    #[inline]
+    fn largest_free_block_size(&self) -> u64 {
+        self.free_blocks
+            .keys()
+            .next_back()
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // stable memory can't shrink, so if it was grown after this allocator's state was saved (e.g.
+    // during a rolled back transaction), the tail should become a free block again
+    pub fn reclaim_grown_pages(&mut self) {
+        let real_max_ptr = stable::size_pages() * PAGE_SIZE_BYTES;
+        if real_max_ptr <= self.max_ptr {
+            return;
+        }
+
+        let free_block = FreeBlock::new_total_size(self.max_ptr, real_max_ptr - self.max_ptr);
+        self.more_free_size(free_block.get_total_size_bytes());
+        self.more_available_size(free_block.get_total_size_bytes());
+
+        self.max_ptr = real_max_ptr;
+        self.push_free_block(free_block);
+    }
+
+    pub fn debug_validate_free_blocks(&self) {
+        assert!(
+            self.available_size == 0
+                || self.available_size == stable::size_pages() * PAGE_SIZE_BYTES - MIN_PTR
+        );
+
+        let mut total_free_size = 0u64;
+        for blocks in self.free_blocks.values() {
+            for free_block in blocks {
+                free_block.debug_validate();
+
+                total_free_size += free_block.get_total_size_bytes();
+            }
+        }
+
+        assert_eq!(total_free_size, self.free_size);
+
+        if let Some(regions) = &self.regions {
+            for region in regions.values() {
+                region.debug_validate_free_blocks();
+            }
+        }
+
+        if cfg!(feature = "debug_canaries") {
+            self.debug_validate_canaries(MIN_PTR, self.max_ptr);
+        }
+    }
+
+    // walks all memory blocks between the two pointers, including the ones inside region chunks
+    fn debug_validate_canaries(&self, from: StablePtr, to: StablePtr) {
+        let mut ptr = from;
+
+        while ptr < to {
+            let total_size = match unsafe { SSlice::from_ptr(ptr) } {
+                Some(slice) => {
+                    Self::check_canaries(&slice);
+
+                    if self.is_region_chunk(ptr) {
+                        self.debug_validate_canaries(
+                            slice.offset(0),
+                            slice.offset(slice.get_size_bytes()),
+                        );
+                    }
+
+                    slice.get_total_size_bytes()
+                }
+                None => FreeBlock::from_ptr(ptr).unwrap().get_total_size_bytes(),
+            };
+
+            ptr += total_size;
+        }
+    }
+
+    pub fn _free_blocks_count(&self) -> usize {
+        let mut count = 0;
+
+        for blocks in self.free_blocks.values() {
+            for _ in blocks {
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    // with size classes, small sizes are ceiled to the nearest class: there are four classes
+    // between each two powers of two, but all of them are multiples of 8
+    #[inline]
+    fn class_size(&self, size: u64) -> u64 {
+        if self.get_strategy() != AllocationStrategy::SizeClasses || size > MAX_SIZE_CLASS {
+            return size;
+        }
+
+        let step = u64::max(size.next_power_of_two() / 4, 8);
+
+        ceil_div(size, step) * step
+    }
+
+    // minimum size is 16 bytes (32 bytes total size)
+    // otherwise size is ceiled to the nearest multiple of 8
+    // canaries (if enabled) are added on top
+    #[inline]
+    pub(crate) fn pad_size(size: u64) -> u64 {
+        if size < (StablePtr::SIZE * 2) as u64 {
+            return (StablePtr::SIZE * 2) as u64 + CANARY_SIZE * 2;
+        }
+
+        ((size + 7) & !7) + CANARY_SIZE * 2
+    }
+
+    fn check_canaries(slice: &SSlice) {
+        assert!(
+            slice.canaries_are_intact(),
+            "Buffer overrun detected: {:?}",
+            slice
+        );
+    }
+}
+
+// free-list operations are shared with regions, which keep their own free-lists inside their chunks
+
+pub(crate) type FreeBlocks = BTreeMap<u64, Vec<FreeBlock>>;
+
+pub(crate) fn try_merge_with_neighbors(
+    free_blocks: &mut FreeBlocks,
+    mut free_block: FreeBlock,
+    max_ptr: StablePtr,
+) -> FreeBlock {
+    if let Some(prev_neighbor) = free_block.prev_neighbor_is_free() {
+        remove_free_block(free_blocks, &prev_neighbor);
+
+        free_block = FreeBlock::merge(prev_neighbor, free_block);
+    };
+
+    if let Some(next_neighbor) = free_block.next_neighbor_is_free(max_ptr) {
+        remove_free_block(free_blocks, &next_neighbor);
+
+        free_block = FreeBlock::merge(free_block, next_neighbor);
+    }
+
+    free_block
+}
+
+pub(crate) fn insert_free_block(free_blocks: &mut FreeBlocks, mut free_block: FreeBlock) {
+    free_block.persist();
+
+    let blocks = free_blocks.entry(free_block.get_size_bytes()).or_default();
+
+    let idx = match blocks.binary_search(&free_block) {
+        Ok(_) => unreachable!("there can't be two blocks of the same ptr"),
+        Err(idx) => idx,
+    };
+
+    blocks.insert(idx, free_block);
+}
+
+pub(crate) fn pop_free_block(free_blocks: &mut FreeBlocks, size: u64) -> Option<FreeBlock> {
+    let (&actual_size, blocks) = free_blocks.range_mut(size..).next()?;
+
+    let free_block = unsafe { blocks.pop().unwrap_unchecked() };
+
+    if blocks.is_empty() {
+        free_blocks.remove(&actual_size);
+    }
+
+    Some(free_block)
+}
+
+pub(crate) fn remove_free_block(free_blocks: &mut FreeBlocks, block: &FreeBlock) {
+    let blocks = free_blocks.get_mut(&block.get_size_bytes()).unwrap();
+
+    match blocks.binary_search(block) {
+        Ok(idx) => {
+            blocks.remove(idx);
+
+            if blocks.is_empty() {
+                free_blocks.remove(&block.get_size_bytes());
+            }
+        }
+        Err(_) => unreachable!("Free block not found {:?} {:?}", block, free_blocks),
+    };
+}
+
+/// Allocator statistics, returned by [get_allocator_stats](crate::get_allocator_stats)
+///
+/// All sizes are in bytes and include memory block metadata. Implements [CandidType], so it can be
+/// returned from a metrics query as is.
+#[derive(Debug, Default, Clone, PartialEq, CandidType, Deserialize)]
+pub struct AllocatorStats {
+    /// The number of free blocks
+    pub free_blocks_count: u64,
+    /// The size of the largest free block
+    pub largest_free_block: u64,
+    /// The total size of all free blocks
+    pub free_size: u64,
+    /// The total size of all allocated blocks
+    pub allocated_size: u64,
+    /// The total amount of stable memory, managed by the allocator
+    pub available_size: u64,
+    /// The number of free blocks, grouped by their size - the `i`-th element is the number of free
+    /// blocks with size in `[2^i, 2^(i + 1))`
+    pub free_block_histogram: Vec<u64>,
+    /// `1 - largest_free_block / free_size` - `0.0` means that all free memory is contiguous, values
+    /// close to `1.0` mean that free memory is scattered across many small blocks
+    pub fragmentation_ratio: f64,
+}
+
+/// A map of the whole stable memory, managed by the allocator, returned by
+/// [export_allocator_layout](crate::export_allocator_layout)
+///
+/// Implements both [CandidType] and [serde::Serialize], so it can be returned from a debug query or
+/// dumped to JSON, in order to visualize fragmentation off-chain.
+#[derive(Debug, Default, Clone, PartialEq, Eq, CandidType, Deserialize, serde::Serialize)]
+pub struct AllocatorLayout {
+    /// The offset of the first memory block
+    pub min_ptr: u64,
+    /// The offset right after the last memory block
+    pub max_ptr: u64,
+    /// All memory blocks, sorted by their offsets - together they cover `[min_ptr, max_ptr)` with
+    /// no gaps
+    pub blocks: Vec<BlockLayout>,
+}
+
+/// A single memory block of [AllocatorLayout]
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize, serde::Serialize)]
+pub struct BlockLayout {
+    /// The offset of this memory block
+    pub offset: u64,
+    /// The size of this memory block in bytes, including its metadata
+    pub size: u64,
+    /// What this memory block is used for
+    pub kind: BlockKind,
+}
+
+/// See [BlockLayout]
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize, serde::Serialize)]
+pub enum BlockKind {
+    /// The memory block is free
+    Free,
+    /// The memory block is allocated
+    Allocated,
+    /// The memory block is allocated by a [Reservation]
+    Reserved,
+    /// The memory block is a chunk of a [region](crate::with_region) with this name - blocks inside
+    /// it are not listed
+    RegionChunk(String),
+}
+
+/// An error, returned by [retrieve_named_custom_data](crate::retrieve_named_custom_data)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CustomDataError {
+    /// There is no data stored under this key
+    NotFound,
+    /// The data, stored under this key, has a different type - it is left in place
+    TypeMismatch {
+        /// The name of the type, the data was stored with
+        stored_type: String,
+        /// The name of the type, that was requested
+        requested_type: String,
+    },
+}
+
+/// The result of [defragment](crate::defragment)
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DefragmentationReport {
+    /// The number of relocated memory blocks
+    pub moved_blocks: u64,
+    /// The total size of relocated memory blocks in bytes
+    pub moved_bytes: u64,
+    /// The size of the largest free block in bytes, before the defragmentation
+    pub largest_free_block_before: u64,
+    /// The size of the largest free block in bytes, after the defragmentation
+    pub largest_free_block_after: u64,
+}
+
+impl DefragmentationReport {
+    /// Returns how many bytes were added to the largest contiguous free block
+    #[inline]
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.largest_free_block_after
+            .saturating_sub(self.largest_free_block_before)
+    }
+}
+
+/// Stable memory, set aside by [reserve](crate::reserve)
+///
+/// Released automatically, when dropped.
+#[derive(Debug)]
+pub struct Reservation {
+    ptr: StablePtr,
+    size: u64,
+}
+
+impl Reservation {
+    pub(crate) fn new(ptr: StablePtr, size: u64) -> Self {
+        Self { ptr, size }
+    }
+
+    /// Returns the reserved size in bytes
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns [true], if reserved memory was not yet used by the allocator
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        crate::is_reserved(self.ptr)
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        crate::release_reservation(self.ptr);
+    }
+}
+
+impl AsDynSizeBytes for StableMemoryAllocator {
+    #[inline]
+    fn as_dyn_size_bytes(&self) -> Vec<u8> {
+        encode_one(self).unwrap()
+    }
+
+    #[inline]
+    fn from_dyn_size_bytes(buf: &[u8]) -> Self {
+        // always candid 0.8, whichever version is selected for the users
+        IDLDeserialize::new(buf).unwrap().get_value().unwrap()
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::encoding::AsDynSizeBytes;
-    use crate::mem::allocator::StableMemoryAllocator;
+    use crate::encoding::{AsDynSizeBytes, AsFixedSizeBytes};
+    use crate::mem::allocator::{
+        AllocationStrategy, AllocatorLayout, BlockKind, StableMemoryAllocator, ALLOCATOR_PTR,
+        MAX_REALLOCATE_BUFFER_SIZE, MIN_PTR, UPGRADE_HEADER_FLAG,
+    };
+    use crate::mem::s_slice::MAX_BLOCK_SIZE;
+    use crate::mem::StablePtr;
     use crate::primitive::s_box::SBox;
     use crate::utils::mem_context::stable;
     use crate::SSlice;
     use rand::rngs::ThreadRng;
     use rand::seq::SliceRandom;
     use rand::{thread_rng, Rng};
+    use std::collections::HashMap;
 
     #[test]
     fn encoding_works_fine() {
@@ -649,6 +1772,531 @@ mod tests {
         }
     }
 
+    // relies on the exact memory layout, which is different with canaries
+    #[cfg(not(feature = "debug_canaries"))]
+    #[test]
+    fn strategies_work_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init_with_strategy(0, AllocationStrategy::FirstFit);
+        assert_eq!(sma.get_strategy(), AllocationStrategy::FirstFit);
+
+        let a = sma.allocate(200).unwrap();
+        let x = sma.allocate(16).unwrap();
+        let b = sma.allocate(104).unwrap();
+        let y = sma.allocate(16).unwrap();
+
+        sma.deallocate(a);
+        sma.deallocate(b);
+
+        // best-fit would take the block of exactly the same size
+        let c = sma.allocate(104).unwrap();
+        assert_eq!(c.as_ptr(), a.as_ptr());
+
+        for slice in [c, x, y] {
+            sma.deallocate(slice);
+        }
+
+        sma.debug_validate_free_blocks();
+        assert_eq!(sma.get_allocated_size(), 0);
+
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init_with_strategy(0, AllocationStrategy::SizeClasses);
+
+        assert_eq!(sma.allocate(16).unwrap().get_size_bytes(), 16);
+        assert_eq!(sma.allocate(64).unwrap().get_size_bytes(), 64);
+        assert_eq!(sma.allocate(65).unwrap().get_size_bytes(), 96);
+        assert_eq!(sma.allocate(1000).unwrap().get_size_bytes(), 1024);
+        assert_eq!(sma.allocate(5000).unwrap().get_size_bytes(), 5000);
+
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init_with_strategy(0, AllocationStrategy::SizeClasses);
+
+        let nodes = (0..100)
+            .map(|_| sma.allocate(64).unwrap())
+            .collect::<Vec<_>>();
+
+        let free_blocks_before = sma._free_blocks_count();
+
+        // neighboring small blocks are not merged
+        for node in &nodes[10..20] {
+            sma.deallocate(*node);
+        }
+
+        assert_eq!(sma._free_blocks_count(), free_blocks_before + 10);
+
+        // freed blocks are reused as-is
+        let reused = (0..10)
+            .map(|_| sma.allocate(64).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(sma._free_blocks_count(), free_blocks_before);
+
+        for node in reused.iter().chain(&nodes[..10]).chain(&nodes[20..]) {
+            sma.deallocate(*node);
+        }
+
+        sma.debug_validate_free_blocks();
+        assert_eq!(sma.get_allocated_size(), 0);
+
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init_with_strategy(0, AllocationStrategy::SizeClasses);
+
+        // unmerged free blocks are merged during defragmentation
+        let nodes = (0..10)
+            .map(|_| sma.allocate(64).unwrap())
+            .collect::<Vec<_>>();
+        let last = sma.allocate(64).unwrap();
+        let first_ptr = nodes[0].as_ptr();
+
+        for node in nodes {
+            sma.deallocate(node);
+        }
+
+        let report = sma.defragment(u64::MAX, |from, to| {
+            assert_eq!((from, to), (last.as_ptr(), first_ptr));
+            true
+        });
+        assert_eq!(report.moved_blocks, 1);
+        assert_eq!(sma._free_blocks_count(), 1);
+
+        sma.deallocate(unsafe { SSlice::from_ptr(first_ptr).unwrap() });
+
+        sma.debug_validate_free_blocks();
+        assert_eq!(sma.get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn reservations_work_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(1);
+        let ptr = sma.reserve(10_000).unwrap();
+        assert!(sma.is_reserved(ptr));
+
+        // all the memory, except the reserved block, gets exhausted first
+        let mut slices = Vec::new();
+        while sma.is_reserved(ptr) {
+            slices.push(sma.allocate(1000).unwrap());
+        }
+
+        for _ in 0..8 {
+            slices.push(sma.allocate(1000).unwrap());
+        }
+
+        assert!(!sma.release_reservation(ptr));
+
+        for slice in slices {
+            sma.deallocate(slice);
+        }
+
+        let ptr = sma.reserve(10_000).unwrap();
+        assert!(sma.make_sure_can_allocate(50_000));
+        assert!(sma.is_reserved(ptr));
+
+        // reserved memory is released, when there is no other way
+        assert!(sma.make_sure_can_allocate(60_000));
+        assert!(!sma.is_reserved(ptr));
+
+        let ptr = sma.reserve(10_000).unwrap();
+        assert!(sma.release_reservation(ptr));
+        assert!(!sma.release_reservation(ptr));
+
+        let ptr = sma.reserve(1000).unwrap();
+        sma.store().unwrap();
+
+        let sma = StableMemoryAllocator::retrieve();
+        assert!(!sma.is_reserved(ptr));
+
+        sma.debug_validate_free_blocks();
+        assert_eq!(sma.get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn upgrade_header_works_fine() {
+        stable::clear();
+
+        let is_in_header = || {
+            let ptr: StablePtr = unsafe { crate::mem::read_fixed_for_reference(ALLOCATOR_PTR) };
+            ptr & UPGRADE_HEADER_FLAG != 0
+        };
+
+        let mut sma = StableMemoryAllocator::init(0);
+        sma.reserve_upgrade_header(1000).unwrap();
+        assert!(sma.get_upgrade_header_capacity().unwrap() >= 1000);
+
+        let slices = (0..200)
+            .map(|_| sma.allocate(100).unwrap())
+            .collect::<Vec<_>>();
+        let allocated = sma.get_allocated_size();
+
+        // nothing gets allocated, when the state fits
+        sma.store().unwrap();
+        assert!(is_in_header());
+
+        let mut sma = StableMemoryAllocator::retrieve();
+        sma.debug_validate_free_blocks();
+        assert_eq!(sma.get_allocated_size(), allocated);
+
+        // lots of free blocks don't fit anymore, so the header grows along with them
+        let mut kept = Vec::new();
+        for (i, slice) in slices.into_iter().enumerate() {
+            if i % 2 == 0 {
+                sma.deallocate(slice);
+            } else {
+                kept.push(slice);
+            }
+        }
+
+        let capacity = sma.get_upgrade_header_capacity().unwrap();
+        assert!(capacity > 1000);
+
+        let allocated = sma.get_allocated_size();
+        sma.store().unwrap();
+        assert!(is_in_header());
+
+        let mut sma = StableMemoryAllocator::retrieve();
+        sma.debug_validate_free_blocks();
+        assert_eq!(sma.get_upgrade_header_capacity(), Some(capacity));
+        assert_eq!(sma.get_allocated_size(), allocated);
+
+        sma.store().unwrap();
+        assert!(is_in_header());
+
+        let mut sma = StableMemoryAllocator::retrieve();
+        sma.debug_validate_free_blocks();
+
+        for slice in kept {
+            sma.deallocate(slice);
+        }
+
+        assert!(sma.release_upgrade_header());
+        assert!(!sma.release_upgrade_header());
+        assert!(sma.get_upgrade_header_capacity().is_none());
+
+        sma.debug_validate_free_blocks();
+        assert_eq!(sma.get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn upgrade_header_keeps_up_with_state() {
+        stable::clear();
+
+        let is_in_header = || {
+            let ptr: StablePtr = unsafe { crate::mem::read_fixed_for_reference(ALLOCATOR_PTR) };
+            ptr & UPGRADE_HEADER_FLAG != 0
+        };
+
+        let mut rng = thread_rng();
+        let mut sma = StableMemoryAllocator::init(0);
+        sma.reserve_upgrade_header(100).unwrap();
+        sma.init_region("region", 1024 * 1024);
+
+        let mut slices = Vec::new();
+
+        for round in 0..10 {
+            for _ in 0..300 {
+                match rng.gen_range(0..6) {
+                    0 | 1 => slices.push(sma.allocate(rng.gen_range(8..300)).unwrap()),
+                    2 if !slices.is_empty() => {
+                        let slice = slices.swap_remove(rng.gen_range(0..slices.len()));
+                        sma.deallocate(slice);
+                    }
+                    3 if !slices.is_empty() => {
+                        let idx = rng.gen_range(0..slices.len());
+                        slices[idx] = sma.reallocate(slices[idx], rng.gen_range(8..600)).unwrap();
+                    }
+                    4 => slices.push(sma.allocate_in("region", rng.gen_range(8..100)).unwrap()),
+                    _ => {
+                        sma.reserve(rng.gen_range(8..100)).unwrap();
+                    }
+                }
+            }
+
+            sma.set_schema_version(&format!("schema {}", round), round);
+
+            // nothing gets allocated, no matter how much the state has grown
+            let pages = stable::size_pages();
+            sma.store().unwrap();
+            assert!(is_in_header());
+            assert_eq!(stable::size_pages(), pages);
+
+            sma = StableMemoryAllocator::retrieve();
+            sma.debug_validate_free_blocks();
+        }
+
+        for slice in slices {
+            sma.deallocate(slice);
+        }
+        sma.release_upgrade_header();
+
+        sma.debug_validate_free_blocks();
+    }
+
+    #[test]
+    fn upgrade_header_stops_tracking_when_out_of_memory() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(1);
+        sma.reserve_upgrade_header(100).unwrap();
+        assert!(sma.upgrade_header_slack.is_some());
+
+        let slice = sma.allocate(60_000).unwrap();
+
+        // the header can't grow anymore, so each next change doesn't try to grow it again
+        sma.set_schema_version(&"a".repeat(5000), 1);
+        assert!(sma.upgrade_header_slack.is_none());
+
+        sma.set_schema_version(&"b".repeat(5000), 1);
+        assert!(sma.upgrade_header_slack.is_none());
+
+        sma.deallocate(slice);
+        sma.reserve_upgrade_header(100).unwrap();
+        assert!(sma.upgrade_header_slack.is_some());
+
+        sma.release_upgrade_header();
+        sma.debug_validate_free_blocks();
+    }
+
+    #[cfg(feature = "debug_canaries")]
+    #[test]
+    fn canaries_work_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+        sma.init_region("users", 0);
+
+        let slice = sma.allocate(100).unwrap();
+        let region_slice = sma.allocate_in("users", 100).unwrap();
+        let blocker = sma.allocate(10).unwrap();
+
+        for s in [slice, region_slice] {
+            unsafe { crate::mem::write_bytes(s.offset(0), &[1u8; 104]) };
+        }
+        sma.debug_validate_free_blocks();
+
+        let slice = sma.reallocate(slice, 1000).unwrap();
+        let region_slice = sma.reallocate(region_slice, 1000).unwrap();
+
+        for s in [slice, region_slice] {
+            let mut buf = [0u8; 104];
+            unsafe { crate::mem::read_bytes(s.offset(0), &mut buf) };
+            assert_eq!(buf, [1u8; 104]);
+        }
+        sma.debug_validate_free_blocks();
+
+        sma.deallocate(slice);
+        sma.deallocate(region_slice);
+        sma.deallocate(blocker);
+
+        sma.debug_validate_free_blocks();
+        assert_eq!(sma.get_allocated_size(), 0);
+    }
+
+    #[cfg(feature = "debug_canaries")]
+    #[test]
+    #[should_panic(expected = "Buffer overrun detected")]
+    fn overrun_should_panic() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+        let slice = sma.allocate(100).unwrap();
+
+        unsafe { crate::mem::write_bytes(slice.offset(0), &[1u8; 105]) };
+
+        sma.deallocate(slice);
+    }
+
+    #[test]
+    fn big_blocks_work_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+
+        assert!(sma.allocate(MAX_BLOCK_SIZE + 1).is_err());
+
+        let size = MAX_REALLOCATE_BUFFER_SIZE * 3;
+        let slice = sma.allocate(size).unwrap();
+        let data = (0..size).map(|it| it as u8).collect::<Vec<_>>();
+        unsafe { crate::mem::write_bytes(slice.offset(0), &data) };
+
+        // prevent in-place reallocation
+        let blocker = sma.allocate(100).unwrap();
+
+        let new_slice = sma.reallocate(slice, size * 2).unwrap();
+        assert_ne!(new_slice.as_ptr(), slice.as_ptr());
+
+        let mut buf = vec![0u8; size as usize];
+        unsafe { crate::mem::read_bytes(new_slice.offset(0), &mut buf) };
+        assert_eq!(buf, data);
+
+        sma.deallocate(new_slice);
+        sma.deallocate(blocker);
+
+        sma.debug_validate_free_blocks();
+        assert_eq!(sma.get_allocated_size(), 0);
+    }
+
+    // relies on the exact memory layout, which is different with canaries
+    #[cfg(not(feature = "debug_canaries"))]
+    #[test]
+    fn stats_work_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+
+        let stats = sma.get_stats();
+        assert_eq!(stats.free_blocks_count, 0);
+        assert_eq!(stats.fragmentation_ratio, 0.0);
+
+        let slices = (0..10)
+            .map(|_| sma.allocate(100).unwrap())
+            .collect::<Vec<_>>();
+
+        let stats = sma.get_stats();
+        assert_eq!(stats.free_blocks_count, 1);
+        assert_eq!(stats.largest_free_block, stats.free_size);
+        assert_eq!(stats.fragmentation_ratio, 0.0);
+        assert_eq!(stats.free_size + stats.allocated_size, stats.available_size);
+
+        for slice in slices.iter().step_by(2) {
+            sma.deallocate(*slice);
+        }
+
+        let stats = sma.get_stats();
+        assert_eq!(stats.free_blocks_count, 6);
+        assert_eq!(stats.free_block_histogram.iter().sum::<u64>(), 6);
+        // 5 blocks of 120 bytes each
+        assert_eq!(stats.free_block_histogram[6], 5);
+        assert!(stats.fragmentation_ratio > 0.0 && stats.fragmentation_ratio < 1.0);
+        assert_eq!(stats.free_size, sma.get_free_size());
+    }
+
+    #[test]
+    fn export_layout_works_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+        assert!(sma.export_layout().blocks.is_empty());
+
+        let slices = (0..10)
+            .map(|_| sma.allocate(100).unwrap())
+            .collect::<Vec<_>>();
+        let reservation = sma.reserve(100).unwrap();
+
+        for slice in slices.iter().step_by(2) {
+            sma.deallocate(*slice);
+        }
+
+        let layout = sma.export_layout();
+        assert_eq!(layout.min_ptr, MIN_PTR);
+
+        // blocks cover the whole memory with no gaps
+        let mut ptr = layout.min_ptr;
+        for block in &layout.blocks {
+            assert_eq!(block.offset, ptr);
+            ptr += block.size;
+        }
+        assert_eq!(ptr, layout.max_ptr);
+
+        let count = |kind: BlockKind| layout.blocks.iter().filter(|it| it.kind == kind).count();
+        assert_eq!(count(BlockKind::Allocated), 5);
+        assert_eq!(count(BlockKind::Reserved), 1);
+        assert_eq!(
+            count(BlockKind::Free),
+            sma.get_stats().free_blocks_count as usize
+        );
+
+        for slice in slices.iter().skip(1).step_by(2) {
+            let block = layout.blocks.iter().find(|it| it.offset == slice.as_ptr());
+            assert_eq!(block.unwrap().size, slice.get_total_size_bytes());
+        }
+        assert!(layout.blocks.iter().any(|it| it.offset == reservation));
+
+        let bytes = serde_cbor::to_vec(&layout).unwrap();
+        let decoded: AllocatorLayout = serde_cbor::from_slice(&bytes).unwrap();
+        assert_eq!(decoded, layout);
+    }
+
+    #[test]
+    fn defragment_works_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+        let mut rng = thread_rng();
+        let mut slices = HashMap::new();
+
+        for i in 0..1000u64 {
+            let slice = sma.allocate(rng.gen_range(8..500)).unwrap();
+            unsafe { crate::mem::write_fixed(slice.offset(0), &mut { i }) };
+
+            slices.insert(slice.as_ptr(), i);
+        }
+
+        let custom_data = sma.allocate(u64::SIZE as u64).unwrap();
+        unsafe { crate::mem::write_fixed(custom_data.offset(0), &mut 12345u64) };
+        sma.custom_data_pointers.insert(1, custom_data.as_ptr());
+
+        let mut ptrs = slices.keys().copied().collect::<Vec<_>>();
+        ptrs.shuffle(&mut rng);
+        for ptr in ptrs.iter().take(500) {
+            slices.remove(ptr);
+            sma.deallocate(unsafe { SSlice::from_ptr(*ptr).unwrap() });
+        }
+
+        let free_size = sma.get_free_size();
+        let free_blocks_count = sma._free_blocks_count();
+
+        // nothing is moved, if the budget is too small or blocks can't be moved
+        let report = sma.defragment(0, |_, _| true);
+        assert_eq!(report.moved_blocks, 0);
+
+        // custom data is moved automatically
+        let report = sma.defragment(u64::MAX, |_, _| false);
+        assert!(report.moved_blocks <= 1);
+        assert!(sma._free_blocks_count() <= free_blocks_count);
+        sma.debug_validate_free_blocks();
+
+        let mut moved = 0;
+        let report = sma.defragment(1000, |old_ptr, new_ptr| {
+            let it = slices.remove(&old_ptr).unwrap();
+            slices.insert(new_ptr, it);
+            moved += 1;
+
+            true
+        });
+
+        assert!(report.moved_bytes <= 1000);
+        assert!(moved <= report.moved_blocks);
+        sma.debug_validate_free_blocks();
+
+        let report = sma.defragment(u64::MAX, |old_ptr, new_ptr| {
+            let it = slices.remove(&old_ptr).unwrap();
+            slices.insert(new_ptr, it);
+
+            true
+        });
+
+        assert!(report.reclaimed_bytes() > 0);
+        assert_eq!(sma._free_blocks_count(), 1);
+        assert_eq!(sma.get_free_size(), free_size);
+        sma.debug_validate_free_blocks();
+
+        for (ptr, i) in &slices {
+            let slice = unsafe { SSlice::from_ptr(*ptr).unwrap() };
+            let it: u64 = unsafe { crate::mem::read_fixed_for_reference(slice.offset(0)) };
+
+            assert_eq!(it, *i);
+        }
+
+        let custom_data = unsafe { SSlice::from_ptr(sma.custom_data_pointers[&1]).unwrap() };
+        let it: u64 = unsafe { crate::mem::read_fixed_for_reference(custom_data.offset(0)) };
+        assert_eq!(it, 12345);
+    }
+
     #[test]
     fn random_works_fine() {
         stable::clear();
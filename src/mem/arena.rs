@@ -0,0 +1,218 @@
+//! A bump allocator for short-lived scratch data.
+//!
+//! See [with_stable_arena].
+
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::{allocate, deallocate, OutOfMemory};
+
+/// Temporary bump allocator, backed by a single memory block
+///
+/// Only available inside [with_stable_arena]. Each [StableArena::alloc] simply moves the arena's
+/// cursor forward, there is no way to release a single allocation - everything is released at once,
+/// when the closure returns (or when [StableArena::reset] is called). This way scratch buffers don't
+/// touch the allocator's free-list at all.
+///
+/// The arena is meant for raw bytes: values stored in it are never stable-dropped.
+pub struct StableArena {
+    slice: SSlice,
+    used: u64,
+}
+
+impl StableArena {
+    /// Allocates `size` bytes in this arena, returning a pointer to them
+    ///
+    /// Allocations are aligned to 8 bytes. If there is not enough space left, returns
+    /// [OutOfMemory].
+    pub fn alloc(&mut self, size: u64) -> Result<StablePtr, OutOfMemory> {
+        let padded_size = size.checked_add(7).ok_or(OutOfMemory)? & !7;
+
+        if padded_size > self.remaining() {
+            return Err(OutOfMemory);
+        }
+
+        let ptr = self.slice.offset(self.used);
+        self.used += padded_size;
+
+        Ok(ptr)
+    }
+
+    /// Allocates space for the provided bytes and writes them there, returning a pointer to them
+    pub fn alloc_bytes(&mut self, data: &[u8]) -> Result<StablePtr, OutOfMemory> {
+        let ptr = self.alloc(data.len() as u64)?;
+        unsafe { crate::mem::write_bytes(ptr, data) };
+
+        Ok(ptr)
+    }
+
+    /// Reads bytes, previously allocated in this arena
+    ///
+    /// # Panics
+    /// Panics if the read is out of the used part of this arena.
+    pub fn read(&self, ptr: StablePtr, buf: &mut [u8]) {
+        self.check_bounds(ptr, buf.len());
+        unsafe { crate::mem::read_bytes(ptr, buf) };
+    }
+
+    /// Writes bytes to the space, previously allocated in this arena
+    ///
+    /// # Panics
+    /// Panics if the write is out of the used part of this arena.
+    pub fn write(&mut self, ptr: StablePtr, data: &[u8]) {
+        self.check_bounds(ptr, data.len());
+        unsafe { crate::mem::write_bytes(ptr, data) };
+    }
+
+    /// Releases all allocations at once, making the whole arena available again
+    #[inline]
+    pub fn reset(&mut self) {
+        self.used = 0;
+    }
+
+    /// Returns the total size of this arena in bytes
+    #[inline]
+    pub fn capacity(&self) -> u64 {
+        self.slice.get_size_bytes()
+    }
+
+    /// Returns the number of bytes, allocated in this arena so far
+    #[inline]
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    /// Returns the number of bytes, that can still be allocated in this arena
+    #[inline]
+    pub fn remaining(&self) -> u64 {
+        self.capacity() - self.used
+    }
+
+    fn check_bounds(&self, ptr: StablePtr, len: usize) {
+        let from = self.slice.offset(0);
+
+        assert!(
+            ptr >= from && ptr + len as u64 <= from + self.used,
+            "Out of bounds: {} + {}",
+            ptr,
+            len
+        );
+    }
+}
+
+/// Runs the closure with a temporary [StableArena] of (at least) `size` bytes
+///
+/// The arena is allocated as a single memory block before the closure is called and is deallocated
+/// right after it returns, releasing everything that was allocated inside. Useful for per-message
+/// scratch buffers, which would otherwise churn the allocator's free-list.
+///
+/// If it was impossible to allocate the arena, returns [OutOfMemory] without calling the closure.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::{get_allocated_size, stable_memory_init, with_stable_arena};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let sum = with_stable_arena(1024, |arena| {
+///     let a = arena.alloc_bytes(&[1, 2, 3]).expect("Arena is full");
+///     let b = arena.alloc_bytes(&[4, 5, 6]).expect("Arena is full");
+///
+///     let mut buf = [0u8; 3];
+///     arena.read(a, &mut buf);
+///     let mut sum = buf.iter().sum::<u8>();
+///
+///     arena.read(b, &mut buf);
+///     sum += buf.iter().sum::<u8>();
+///
+///     sum
+/// })
+/// .expect("Out of memory");
+///
+/// assert_eq!(sum, 21);
+/// assert_eq!(get_allocated_size(), 0);
+/// ```
+pub fn with_stable_arena<R, F: FnOnce(&mut StableArena) -> R>(
+    size: u64,
+    func: F,
+) -> Result<R, OutOfMemory> {
+    let slice = unsafe { allocate(size)? };
+    let mut arena = StableArena { slice, used: 0 };
+
+    let result = func(&mut arena);
+
+    deallocate(arena.slice);
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mem::arena::with_stable_arena;
+    use crate::utils::mem_context::stable;
+    use crate::{
+        _debug_validate_allocator, allocate, deallocate, get_allocated_size, get_allocator_stats,
+        init_allocator, stable_memory_init,
+    };
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        let slice = unsafe { allocate(100).unwrap() };
+        let stats = get_allocator_stats();
+
+        with_stable_arena(100, |arena| {
+            assert!(arena.capacity() >= 100);
+
+            let a = arena.alloc(10).unwrap();
+            assert_eq!(arena.used(), 16);
+
+            arena.write(a, &[1; 10]);
+
+            let b = arena.alloc_bytes(&[2; 20]).unwrap();
+            assert_eq!(b, a + 16);
+
+            let mut buf = [0u8; 10];
+            arena.read(a, &mut buf);
+            assert_eq!(buf, [1; 10]);
+
+            assert!(arena.alloc(arena.remaining() + 1).is_err());
+
+            arena.reset();
+            assert_eq!(arena.used(), 0);
+            assert_eq!(arena.alloc(10).unwrap(), a);
+        })
+        .unwrap();
+
+        assert_eq!(get_allocator_stats(), stats);
+
+        deallocate(slice);
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn out_of_memory_works_fine() {
+        stable::clear();
+        init_allocator(1);
+
+        assert!(with_stable_arena(100_000, |_| unreachable!()).is_err());
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_bounds_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        with_stable_arena(100, |arena| {
+            let ptr = arena.alloc(8).unwrap();
+            arena.write(ptr, &[0; 9]);
+        })
+        .unwrap();
+    }
+}
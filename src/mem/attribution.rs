@@ -0,0 +1,241 @@
+//! Per-tag breakdown of allocated stable memory.
+//!
+//! Once [enable_allocation_attribution] is called, every memory block is attributed to the
+//! [tag](crate::with_allocation_tag), it was allocated with. A reallocated memory block keeps its
+//! original tag, so a growing [SVec](crate::collections::SVec) stays attributed to the tag of its
+//! first push. [get_allocation_breakdown] then tells how much memory each tag holds right now - tag
+//! operations on each collection with its own name or id and you'll know, which one of them takes
+//! the most memory.
+//!
+//! Built on top of [allocation hooks](crate::mem::hooks), so memory blocks, allocated before the
+//! attribution was enabled, are not counted. The attribution lives on heap and takes a few bytes per
+//! memory block - it is not persisted between upgrades.
+
+use crate::mem::hooks::{AllocationEvent, AllocationEventKind, AllocationHookId};
+use crate::mem::StablePtr;
+use crate::{register_allocation_hook, unregister_allocation_hook};
+use candid::{CandidType, Deserialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+thread_local! {
+    static ATTRIBUTION: RefCell<Option<Attribution>> = RefCell::default();
+}
+
+/// Allocated memory per tag, returned by [get_allocation_breakdown]
+#[derive(Debug, Default, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct AllocationBreakdown {
+    /// Allocated bytes of each tag, the biggest first
+    pub tagged: Vec<(String, u64)>,
+    /// Allocated bytes of memory blocks, allocated without a tag
+    pub untagged: u64,
+}
+
+/// Starts attributing allocated memory blocks to their tags
+///
+/// Does nothing, if the attribution is already enabled.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SHashMap;
+/// # use ic_stable_memory::{stable_memory_init, with_allocation_tag};
+/// use ic_stable_memory::mem::attribution::{enable_allocation_attribution, get_allocation_breakdown};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+///
+/// enable_allocation_attribution();
+///
+/// let mut balances = SHashMap::<u64, u64>::new();
+/// for i in 0..100 {
+///     with_allocation_tag("balances", || balances.insert(i, i)).expect("Out of memory");
+/// }
+///
+/// let breakdown = get_allocation_breakdown().unwrap();
+///
+/// assert_eq!(breakdown.tagged[0].0, "balances");
+/// ```
+pub fn enable_allocation_attribution() {
+    if ATTRIBUTION.with(|it| it.borrow().is_some()) {
+        return;
+    }
+
+    let hook_id = register_allocation_hook(|event| {
+        ATTRIBUTION.with(|it| {
+            if let Some(attribution) = &mut *it.borrow_mut() {
+                attribution.record(event);
+            }
+        })
+    });
+
+    ATTRIBUTION.with(|it| *it.borrow_mut() = Some(Attribution::new(hook_id)));
+}
+
+/// Stops the attribution and forgets everything it has recorded
+pub fn disable_allocation_attribution() {
+    if let Some(attribution) = ATTRIBUTION.with(|it| it.borrow_mut().take()) {
+        unregister_allocation_hook(attribution.hook_id);
+    }
+}
+
+/// Returns the amount of memory, currently allocated with each tag, or [None], if the attribution
+/// is not enabled
+///
+/// Takes `O(T * logT)` time, where `T` is the number of tags.
+pub fn get_allocation_breakdown() -> Option<AllocationBreakdown> {
+    ATTRIBUTION.with(|it| it.borrow().as_ref().map(Attribution::breakdown))
+}
+
+struct Attribution {
+    hook_id: AllocationHookId,
+    // tags are interned, so each memory block only stores an index
+    tags: Vec<String>,
+    tag_indices: HashMap<String, usize>,
+    tagged_sizes: Vec<u64>,
+    untagged_size: u64,
+    blocks: BTreeMap<StablePtr, Option<usize>>,
+}
+
+impl Attribution {
+    fn new(hook_id: AllocationHookId) -> Self {
+        Self {
+            hook_id,
+            tags: Vec::new(),
+            tag_indices: HashMap::new(),
+            tagged_sizes: Vec::new(),
+            untagged_size: 0,
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    fn record(&mut self, event: &AllocationEvent) {
+        match event.kind {
+            AllocationEventKind::Allocate => {
+                let tag_idx = event.tag.map(|tag| self.intern(tag));
+
+                *self.size_mut(tag_idx) += event.size;
+                self.blocks.insert(event.ptr, tag_idx);
+            }
+            // memory blocks, allocated before the attribution was enabled, are not tracked
+            AllocationEventKind::Deallocate => {
+                if let Some(tag_idx) = self.blocks.remove(&event.ptr) {
+                    *self.size_mut(tag_idx) -= event.size;
+                }
+            }
+            AllocationEventKind::Reallocate { old_ptr, old_size } => {
+                if let Some(tag_idx) = self.blocks.remove(&old_ptr) {
+                    let size = self.size_mut(tag_idx);
+                    *size = *size - old_size + event.size;
+
+                    self.blocks.insert(event.ptr, tag_idx);
+                }
+            }
+        }
+    }
+
+    fn intern(&mut self, tag: &str) -> usize {
+        if let Some(idx) = self.tag_indices.get(tag) {
+            return *idx;
+        }
+
+        let idx = self.tags.len();
+
+        self.tags.push(String::from(tag));
+        self.tag_indices.insert(String::from(tag), idx);
+        self.tagged_sizes.push(0);
+
+        idx
+    }
+
+    fn size_mut(&mut self, tag_idx: Option<usize>) -> &mut u64 {
+        match tag_idx {
+            Some(idx) => &mut self.tagged_sizes[idx],
+            None => &mut self.untagged_size,
+        }
+    }
+
+    fn breakdown(&self) -> AllocationBreakdown {
+        let mut tagged = self
+            .tags
+            .iter()
+            .cloned()
+            .zip(self.tagged_sizes.iter().copied())
+            .filter(|(_, size)| *size > 0)
+            .collect::<Vec<_>>();
+
+        tagged.sort_by(|(a_tag, a_size), (b_tag, b_size)| {
+            b_size.cmp(a_size).then_with(|| a_tag.cmp(b_tag))
+        });
+
+        AllocationBreakdown {
+            tagged,
+            untagged: self.untagged_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::{SBTreeMap, SVec};
+    use crate::mem::attribution::{
+        disable_allocation_attribution, enable_allocation_attribution, get_allocation_breakdown,
+    };
+    use crate::utils::mem_context::stable;
+    use crate::{
+        _debug_validate_allocator, allocate, allocate_tagged, deallocate, get_allocated_size,
+        stable_memory_init, with_allocation_tag,
+    };
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        assert!(get_allocation_breakdown().is_none());
+
+        let before = unsafe { allocate(100).unwrap() };
+
+        enable_allocation_attribution();
+        enable_allocation_attribution();
+
+        {
+            let mut vec = SVec::<u64>::new();
+            let mut map = SBTreeMap::<u64, u64>::new();
+
+            with_allocation_tag("vec", || vec.push(0)).unwrap();
+
+            for i in 0..1000 {
+                // the vec grows by reallocation, so its tag is kept
+                vec.push(i).unwrap();
+                with_allocation_tag(2, || map.insert(i, i)).unwrap();
+            }
+
+            let slice = unsafe { allocate_tagged(10, "slice").unwrap() };
+            let untagged = unsafe { allocate(10).unwrap() };
+
+            let breakdown = get_allocation_breakdown().unwrap();
+            let tags = breakdown
+                .tagged
+                .iter()
+                .map(|(tag, _)| tag.as_str())
+                .collect::<Vec<_>>();
+
+            assert_eq!(tags, vec!["2", "vec", "slice"]);
+            assert_eq!(breakdown.tagged[2].1, slice.get_size_bytes());
+            assert_eq!(breakdown.untagged, untagged.get_size_bytes());
+
+            deallocate(slice);
+            deallocate(untagged);
+        }
+
+        // memory blocks, allocated before the attribution was enabled, are ignored
+        deallocate(before);
+
+        assert_eq!(get_allocation_breakdown().unwrap(), Default::default());
+
+        disable_allocation_attribution();
+        assert!(get_allocation_breakdown().is_none());
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,362 @@
+//! Callbacks, invoked on each allocation, deallocation and reallocation.
+//!
+//! Hooks are registered with [register_allocation_hook] and receive an [AllocationEvent] right
+//! after [allocate](crate::allocate), [deallocate](crate::deallocate) or [reallocate](crate::reallocate)
+//! is done. Since every stable collection in this crate uses these functions, hooks see every memory
+//! block, a canister allocates. This is enough to build custom accounting, leak detection or
+//! per-feature memory attribution (see [with_allocation_tag]).
+//!
+//! Memory blocks, moved by [defragment](crate::defragment), are reported as reallocations. But
+//! [restore_snapshot](crate::restore_snapshot) and rolled back [transactions](crate::with_transaction)
+//! replace the whole state of the allocator without emitting any events, so after them anything,
+//! tracked by hooks and keyed by pointers (like [attribution](crate::mem::attribution) or
+//! [leak checkpoints](crate::mem::leaks)), becomes invalid and should be started over.
+//!
+//! Hooks live on heap and are not persisted between upgrades - register them in both `#[init]` and
+//! `#[post_upgrade]`.
+
+use crate::mem::StablePtr;
+use std::cell::{Cell, RefCell};
+use std::fmt::Display;
+use std::rc::Rc;
+
+/// Identifier of a registered hook, used to unregister it
+pub type AllocationHookId = u64;
+
+type AllocationHook = Rc<dyn Fn(&AllocationEvent)>;
+
+thread_local! {
+    static HOOKS: RefCell<Vec<(AllocationHookId, AllocationHook)>> = RefCell::default();
+    static NEXT_HOOK_ID: Cell<AllocationHookId> = const { Cell::new(0) };
+    static CURRENT_TAG: RefCell<Option<String>> = RefCell::default();
+    static IS_IN_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The kind of operation, that triggered an [AllocationEvent]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AllocationEventKind {
+    /// A new memory block was allocated
+    Allocate,
+    /// The memory block was deallocated
+    Deallocate,
+    /// The memory block was reallocated - it might have moved from the old location
+    Reallocate {
+        /// The pointer to the memory block before the reallocation
+        old_ptr: StablePtr,
+        /// The size of the memory block before the reallocation
+        old_size: u64,
+    },
+}
+
+/// An event, passed to each registered hook
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AllocationEvent<'a> {
+    /// What happened
+    pub kind: AllocationEventKind,
+    /// The pointer to the memory block
+    pub ptr: StablePtr,
+    /// The actual size of the memory block in bytes (may be bigger, than requested)
+    pub size: u64,
+    /// The tag, set by the innermost [with_allocation_tag], if any
+    pub tag: Option<&'a str>,
+}
+
+/// Registers a hook, which will be invoked on each allocation, deallocation and reallocation
+///
+/// Hooks are invoked in registration order. Memory operations, performed by a hook itself, don't
+/// trigger any hooks.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::mem::hooks::AllocationEventKind;
+/// # use ic_stable_memory::{register_allocation_hook, stable_memory_init, with_allocation_tag, SBox};
+/// # use std::cell::Cell;
+/// # use std::rc::Rc;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let users_memory = Rc::new(Cell::new(0i64));
+/// let counter = users_memory.clone();
+///
+/// register_allocation_hook(move |event| {
+///     if event.tag != Some("users") {
+///         return;
+///     }
+///
+///     match event.kind {
+///         AllocationEventKind::Allocate => counter.set(counter.get() + event.size as i64),
+///         AllocationEventKind::Deallocate => counter.set(counter.get() - event.size as i64),
+///         AllocationEventKind::Reallocate { old_size, .. } => {
+///             counter.set(counter.get() + event.size as i64 - old_size as i64)
+///         }
+///     }
+/// });
+///
+/// let _user = with_allocation_tag("users", || SBox::new(String::from("Alice")))
+///     .expect("Out of memory");
+///
+/// assert!(users_memory.get() > 0);
+/// ```
+pub fn register_allocation_hook<F: Fn(&AllocationEvent) + 'static>(hook: F) -> AllocationHookId {
+    let id = NEXT_HOOK_ID.with(|it| it.replace(it.get() + 1));
+    HOOKS.with(|it| it.borrow_mut().push((id, Rc::new(hook))));
+
+    id
+}
+
+/// Unregisters a hook, returning [true] if it was registered
+pub fn unregister_allocation_hook(id: AllocationHookId) -> bool {
+    HOOKS.with(|it| {
+        let mut hooks = it.borrow_mut();
+        let len_before = hooks.len();
+
+        hooks.retain(|(hook_id, _)| *hook_id != id);
+
+        hooks.len() != len_before
+    })
+}
+
+/// Runs the lambda function, tagging all memory operations inside it with the provided tag
+///
+/// The tag is passed to hooks with each [AllocationEvent]. Calls can be nested - the innermost tag
+/// is used. Any [Display] value can be a tag, so collections can be tagged with either names or
+/// numeric ids - both are passed to hooks as strings.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::{stable_memory_init, with_allocation_tag};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// const USERS_ID: u32 = 1;
+///
+/// let mut users = SVec::<u64>::new();
+/// with_allocation_tag(USERS_ID, || users.push(10)).expect("Out of memory");
+/// ```
+pub fn with_allocation_tag<T: Display, R, F: FnOnce() -> R>(tag: T, func: F) -> R {
+    let _guard = TagGuard::enter(tag.to_string());
+
+    func()
+}
+
+pub(crate) fn emit(kind: AllocationEventKind, ptr: StablePtr, size: u64) {
+    if IS_IN_HOOK.with(|it| it.get()) {
+        return;
+    }
+
+    // cloned, so hooks could register or unregister other hooks; stable values can also be
+    // dropped during thread destruction, when hooks are already gone
+    let hooks = HOOKS
+        .try_with(|it| {
+            it.borrow()
+                .iter()
+                .map(|(_, hook)| hook.clone())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    if hooks.is_empty() {
+        return;
+    }
+
+    let tag = CURRENT_TAG.with(|it| it.borrow().clone());
+    let event = AllocationEvent {
+        kind,
+        ptr,
+        size,
+        tag: tag.as_deref(),
+    };
+
+    IS_IN_HOOK.with(|it| it.set(true));
+
+    for hook in hooks {
+        hook(&event);
+    }
+
+    IS_IN_HOOK.with(|it| it.set(false));
+}
+
+struct TagGuard {
+    prev: Option<String>,
+}
+
+impl TagGuard {
+    fn enter(tag: String) -> Self {
+        let prev = CURRENT_TAG.with(|it| it.replace(Some(tag)));
+
+        Self { prev }
+    }
+}
+
+impl Drop for TagGuard {
+    fn drop(&mut self) {
+        CURRENT_TAG.with(|it| *it.borrow_mut() = self.prev.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SVec;
+    use crate::mem::hooks::AllocationEventKind;
+    use crate::utils::mem_context::stable;
+    use crate::{
+        _debug_validate_allocator, allocate, deallocate, defragment, get_allocated_size,
+        reallocate, register_allocation_hook, stable_memory_init, unregister_allocation_hook,
+        with_allocation_tag,
+    };
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let events_1 = events.clone();
+
+        let id = register_allocation_hook(move |event| {
+            events_1.borrow_mut().push((
+                event.kind,
+                event.ptr,
+                event.size,
+                event.tag.map(String::from),
+            ));
+
+            // memory operations inside a hook are not reported
+            let slice = unsafe { allocate(10).unwrap() };
+            deallocate(slice);
+        });
+
+        let slice = unsafe { allocate(100).unwrap() };
+        let blocker = with_allocation_tag("a", || {
+            with_allocation_tag("b", || unsafe { allocate(10).unwrap() })
+        });
+        let new_slice = with_allocation_tag("a", || unsafe { reallocate(slice, 200).unwrap() });
+
+        deallocate(new_slice);
+        deallocate(blocker);
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                (AllocationEventKind::Allocate, slice.as_ptr(), 104, None),
+                (
+                    AllocationEventKind::Allocate,
+                    blocker.as_ptr(),
+                    16,
+                    Some(String::from("b"))
+                ),
+                (
+                    AllocationEventKind::Reallocate {
+                        old_ptr: slice.as_ptr(),
+                        old_size: 104
+                    },
+                    new_slice.as_ptr(),
+                    new_slice.get_size_bytes(),
+                    Some(String::from("a"))
+                ),
+                (
+                    AllocationEventKind::Deallocate,
+                    new_slice.as_ptr(),
+                    new_slice.get_size_bytes(),
+                    None
+                ),
+                (AllocationEventKind::Deallocate, blocker.as_ptr(), 16, None),
+            ]
+        );
+
+        assert!(unregister_allocation_hook(id));
+        assert!(!unregister_allocation_hook(id));
+
+        {
+            let mut vec = SVec::new();
+            vec.push(10u64).unwrap();
+        }
+
+        assert_eq!(events.borrow().len(), 5);
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn defragmentation_is_reported() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let gap = unsafe { allocate(100).unwrap() };
+            let slice = unsafe { allocate(100).unwrap() };
+            deallocate(gap);
+
+            let events = Rc::new(RefCell::new(Vec::new()));
+            let events_1 = events.clone();
+
+            register_allocation_hook(move |event| {
+                events_1
+                    .borrow_mut()
+                    .push((event.kind, event.ptr, event.size));
+            });
+
+            let mut new_ptr = slice.as_ptr();
+            let report = defragment(u64::MAX, |_, ptr| {
+                new_ptr = ptr;
+                true
+            });
+
+            assert_eq!(report.moved_blocks, 1);
+            assert_eq!(
+                *events.borrow(),
+                vec![(
+                    AllocationEventKind::Reallocate {
+                        old_ptr: slice.as_ptr(),
+                        old_size: slice.get_size_bytes()
+                    },
+                    new_ptr,
+                    slice.get_size_bytes()
+                )]
+            );
+
+            deallocate(unsafe { crate::mem::s_slice::SSlice::from_ptr(new_ptr).unwrap() });
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn leak_detection_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        let live = Rc::new(RefCell::new(Vec::new()));
+        let live_1 = live.clone();
+
+        register_allocation_hook(move |event| {
+            let mut live = live_1.borrow_mut();
+
+            match event.kind {
+                AllocationEventKind::Allocate => live.push(event.ptr),
+                AllocationEventKind::Deallocate => live.retain(|it| *it != event.ptr),
+                AllocationEventKind::Reallocate { old_ptr, .. } => {
+                    live.retain(|it| *it != old_ptr);
+                    live.push(event.ptr);
+                }
+            }
+        });
+
+        {
+            let mut vec = SVec::new();
+            for i in 0..1000u64 {
+                vec.push(i).unwrap();
+            }
+
+            assert_eq!(live.borrow().len(), 1);
+        }
+
+        assert!(live.borrow().is_empty());
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
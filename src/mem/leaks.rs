@@ -0,0 +1,220 @@
+//! Leak detection between two points of a test.
+//!
+//! `assert_eq!(get_allocated_size(), 0)` only tells that some memory is leaked. A [LeakCheckpoint]
+//! tells which memory blocks exactly: it records every memory block, allocated after the checkpoint
+//! was created, together with its [tag](crate::with_allocation_tag) and (if `RUST_BACKTRACE` is
+//! set) the backtrace of the allocation. Memory blocks, which are still alive, when
+//! [LeakCheckpoint::leaks] is called, are reported.
+//!
+//! Built on top of [allocation hooks](crate::mem::hooks), so only memory blocks, allocated with
+//! [allocate](crate::allocate) or [reallocate](crate::reallocate), are tracked.
+
+use crate::mem::hooks::{AllocationEvent, AllocationEventKind, AllocationHookId};
+use crate::mem::StablePtr;
+use crate::{register_allocation_hook, unregister_allocation_hook};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// A memory block, which was allocated after a [LeakCheckpoint] and is still alive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeakedBlock {
+    /// The pointer to the memory block
+    pub ptr: StablePtr,
+    /// The actual size of the memory block in bytes
+    pub size: u64,
+    /// The tag, set by [with_allocation_tag](crate::with_allocation_tag) during the allocation
+    pub tag: Option<String>,
+    /// The backtrace of the allocation, only captured when `RUST_BACKTRACE` is set
+    pub backtrace: Option<String>,
+}
+
+impl Display for LeakedBlock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes at {}", self.size, self.ptr)?;
+
+        if let Some(tag) = &self.tag {
+            write!(f, " tagged '{}'", tag)?;
+        }
+
+        if let Some(backtrace) = &self.backtrace {
+            write!(f, ", allocated at:\n{}", backtrace)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Records memory blocks, allocated after this checkpoint was created
+///
+/// Stops recording, when dropped.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{stable_memory_init, with_allocation_tag, SBox};
+/// use ic_stable_memory::mem::leaks::LeakCheckpoint;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+///
+/// let checkpoint = LeakCheckpoint::new();
+///
+/// let forgotten = with_allocation_tag("forgotten", || SBox::new(String::from("leak")))
+///     .expect("Out of memory");
+/// std::mem::forget(forgotten);
+///
+/// let leaks = checkpoint.leaks();
+///
+/// assert_eq!(leaks.len(), 1);
+/// assert_eq!(leaks[0].tag.as_deref(), Some("forgotten"));
+/// ```
+pub struct LeakCheckpoint {
+    hook_id: AllocationHookId,
+    alive: Rc<RefCell<BTreeMap<StablePtr, LeakedBlock>>>,
+}
+
+impl LeakCheckpoint {
+    /// Creates a new checkpoint and starts recording allocations
+    pub fn new() -> Self {
+        let alive = Rc::new(RefCell::new(BTreeMap::new()));
+        let alive_1 = alive.clone();
+
+        let hook_id = register_allocation_hook(move |event| Self::record(&alive_1, event));
+
+        Self { hook_id, alive }
+    }
+
+    /// Returns memory blocks, allocated after this checkpoint and still alive, sorted by pointers
+    pub fn leaks(&self) -> Vec<LeakedBlock> {
+        self.alive.borrow().values().cloned().collect()
+    }
+
+    /// Panics, listing all the leaked memory blocks, if there are any
+    pub fn assert_no_leaks(&self) {
+        let leaks = self.leaks();
+
+        if leaks.is_empty() {
+            return;
+        }
+
+        let list = leaks
+            .iter()
+            .map(|it| it.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        panic!("{} memory blocks leaked:\n{}", leaks.len(), list);
+    }
+
+    fn record(alive: &RefCell<BTreeMap<StablePtr, LeakedBlock>>, event: &AllocationEvent) {
+        let mut alive = alive.borrow_mut();
+
+        match event.kind {
+            AllocationEventKind::Allocate => {
+                alive.insert(event.ptr, Self::block(event));
+            }
+            // memory blocks, allocated before the checkpoint, are not tracked
+            AllocationEventKind::Deallocate => {
+                alive.remove(&event.ptr);
+            }
+            AllocationEventKind::Reallocate { old_ptr, .. } => {
+                if let Some(mut block) = alive.remove(&old_ptr) {
+                    block.ptr = event.ptr;
+                    block.size = event.size;
+
+                    alive.insert(event.ptr, block);
+                }
+            }
+        }
+    }
+
+    fn block(event: &AllocationEvent) -> LeakedBlock {
+        let backtrace = Backtrace::capture();
+        let backtrace = match backtrace.status() {
+            BacktraceStatus::Captured => Some(backtrace.to_string()),
+            _ => None,
+        };
+
+        LeakedBlock {
+            ptr: event.ptr,
+            size: event.size,
+            tag: event.tag.map(String::from),
+            backtrace,
+        }
+    }
+}
+
+impl Default for LeakCheckpoint {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for LeakCheckpoint {
+    fn drop(&mut self) {
+        unregister_allocation_hook(self.hook_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SVec;
+    use crate::mem::leaks::LeakCheckpoint;
+    use crate::utils::mem_context::stable;
+    use crate::{
+        _debug_validate_allocator, allocate, deallocate, get_allocated_size, reallocate,
+        stable_memory_init, with_allocation_tag, SBox,
+    };
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        let before = unsafe { allocate(100).unwrap() };
+        let checkpoint = LeakCheckpoint::new();
+
+        // blocks, allocated before the checkpoint, are ignored
+        deallocate(before);
+
+        {
+            let mut vec = SVec::new();
+            for i in 0..100u64 {
+                vec.push(SBox::new(i).unwrap()).unwrap();
+            }
+        }
+        checkpoint.assert_no_leaks();
+
+        let slice = with_allocation_tag("a", || unsafe { allocate(10).unwrap() });
+        let slice = unsafe { reallocate(slice, 1000).unwrap() };
+
+        let leaks = checkpoint.leaks();
+        assert_eq!(leaks.len(), 1);
+        assert_eq!(leaks[0].ptr, slice.as_ptr());
+        assert_eq!(leaks[0].size, slice.get_size_bytes());
+        assert_eq!(leaks[0].tag.as_deref(), Some("a"));
+        assert!(leaks[0].to_string().contains("tagged 'a'"));
+
+        deallocate(slice);
+        checkpoint.assert_no_leaks();
+
+        drop(checkpoint);
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn leaks_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let checkpoint = LeakCheckpoint::new();
+        std::mem::forget(SBox::new(10u64).unwrap());
+
+        checkpoint.assert_no_leaks();
+    }
+}
@@ -25,10 +25,20 @@
 use crate::encoding::{AsFixedSizeBytes, Buffer};
 use crate::primitive::StableType;
 use crate::stable;
+use crate::utils::buf_pool::PooledBuf;
 
 pub mod allocator;
+pub mod arena;
+pub mod attribution;
 pub mod free_block;
+pub mod hooks;
+pub mod leaks;
+pub mod region;
 pub mod s_slice;
+#[cfg(feature = "ic-stable-structures")]
+pub mod stable_structures;
+pub mod virtual_memory;
+pub mod write_coalescing;
 
 /// A pointer to something is stable memory.
 ///
@@ -36,6 +46,10 @@ pub mod s_slice;
 pub type StablePtr = u64;
 pub(crate) type StablePtrBuf = <u64 as AsFixedSizeBytes>::Buf;
 
+const COPY_BATCH_SIZE: u64 = 64 * 1024;
+// it is cheaper to read some extra bytes, than to make one more syscall
+const MAX_VECTORED_READ_GAP: u64 = 256;
+
 #[inline]
 pub(crate) fn stable_ptr_buf() -> StablePtrBuf {
     StablePtrBuf::new(<StablePtr as AsFixedSizeBytes>::SIZE)
@@ -67,11 +81,184 @@ pub unsafe fn write_bytes(ptr: StablePtr, buf: &[u8]) {
     stable::write(ptr, buf);
 }
 
+/// Reads raw bytes from multiple locations of stable memory.
+///
+/// Each buffer is filled with bytes, starting from the paired pointer. Locations, which are close
+/// enough to each other, are read with a single syscall, no matter in which order they are listed.
+///
+/// # Safety
+/// Same as for [read_bytes].
+pub unsafe fn read_bytes_vectored(bufs: &mut [(StablePtr, &mut [u8])]) {
+    let order = sorted_by_ptr(bufs.iter().map(|(ptr, buf)| (*ptr, buf.len())));
+    let mut tmp = Vec::new();
+
+    for (from, to, group) in group_ranges(&order, MAX_VECTORED_READ_GAP) {
+        if let [(idx, _, _)] = group {
+            stable::read(from, bufs[*idx].1);
+            continue;
+        }
+
+        tmp.resize((to - from) as usize, 0);
+        stable::read(from, &mut tmp);
+
+        for (idx, ptr, len) in group {
+            let offset = (ptr - from) as usize;
+            bufs[*idx].1.copy_from_slice(&tmp[offset..offset + len]);
+        }
+    }
+}
+
+/// Writes raw bytes to multiple locations of stable memory.
+///
+/// Locations, which are adjacent to each other, are written with a single syscall, no matter in
+/// which order they are listed.
+///
+/// # Safety
+/// Same as for [write_bytes]. Locations should not overlap.
+pub unsafe fn write_bytes_vectored(bufs: &[(StablePtr, &[u8])]) {
+    let order = sorted_by_ptr(bufs.iter().map(|(ptr, buf)| (*ptr, buf.len())));
+    let mut tmp = Vec::new();
+
+    for (from, to, group) in group_ranges(&order, 0) {
+        if let [(idx, _, _)] = group {
+            stable::write(from, bufs[*idx].1);
+            continue;
+        }
+
+        tmp.resize((to - from) as usize, 0);
+
+        for (idx, ptr, len) in group {
+            let offset = (ptr - from) as usize;
+            tmp[offset..offset + len].copy_from_slice(bufs[*idx].1);
+        }
+
+        stable::write(from, &tmp);
+    }
+}
+
+// (index, ptr, len) of each location, sorted by ptr
+fn sorted_by_ptr(
+    locations: impl Iterator<Item = (StablePtr, usize)>,
+) -> Vec<(usize, StablePtr, usize)> {
+    let mut order = locations
+        .enumerate()
+        .map(|(idx, (ptr, len))| (idx, ptr, len))
+        .collect::<Vec<_>>();
+
+    order.sort_by_key(|(_, ptr, _)| *ptr);
+
+    order
+}
+
+// splits sorted locations into groups, that span continuous ranges of memory
+fn group_ranges(
+    order: &[(usize, StablePtr, usize)],
+    max_gap: u64,
+) -> impl Iterator<Item = (StablePtr, StablePtr, &[(usize, StablePtr, usize)])> {
+    let mut rest = order;
+
+    std::iter::from_fn(move || {
+        let (_, from, len) = *rest.first()?;
+        let mut to = from + len as u64;
+
+        let mut group_len = 1;
+        for (_, ptr, len) in &rest[1..] {
+            if *ptr > to + max_gap {
+                break;
+            }
+
+            to = to.max(ptr + *len as u64);
+            group_len += 1;
+        }
+
+        let (group, r) = rest.split_at(group_len);
+        rest = r;
+
+        Some((from, to, group))
+    })
+}
+
+/// Copies `len` bytes from one location of stable memory to another.
+///
+/// Copies in batches, so it does not need a heap buffer of size `len`.
+///
+/// # Safety
+/// Same as for [write_bytes]. Memory regions should not overlap.
+pub unsafe fn copy_bytes(from: StablePtr, to: StablePtr, len: u64) {
+    let mut buf = PooledBuf::zeroed(len.min(COPY_BATCH_SIZE) as usize);
+
+    let mut offset = 0;
+    while offset < len {
+        let batch = (len - offset).min(COPY_BATCH_SIZE) as usize;
+        stable::read(from + offset, &mut buf[..batch]);
+        stable::write(to + offset, &buf[..batch]);
+
+        offset += batch as u64;
+    }
+}
+
+/// Moves `len` bytes from one location of stable memory to another, like [copy_bytes], but the
+/// memory regions are allowed to overlap.
+///
+/// Moves in batches, so it does not need a heap buffer of size `len`.
+///
+/// # Safety
+/// Same as for [write_bytes].
+pub unsafe fn move_bytes(from: StablePtr, to: StablePtr, len: u64) {
+    if to <= from {
+        return copy_bytes(from, to, len);
+    }
+
+    // moving towards the end, so the batches are copied starting from the last one
+    let mut buf = PooledBuf::zeroed(len.min(COPY_BATCH_SIZE) as usize);
+
+    let mut end = len;
+    while end > 0 {
+        let batch = end.min(COPY_BATCH_SIZE) as usize;
+        end -= batch as u64;
+
+        stable::read(from + end, &mut buf[..batch]);
+        stable::write(to + end, &buf[..batch]);
+    }
+}
+
+/// Writes `len` zero bytes to stable memory.
+///
+/// Writes in small batches, so it does not need a heap buffer of size `len`.
+///
+/// # Safety
+/// Same as for [write_bytes].
+pub unsafe fn write_zeroes(ptr: StablePtr, len: u64) {
+    let zeroes = [0u8; 1024];
+
+    let mut offset = 0;
+    while offset < len {
+        let batch = (len - offset).min(zeroes.len() as u64) as usize;
+        stable::write(ptr + offset, &zeroes[..batch]);
+
+        offset += batch as u64;
+    }
+}
+
+// most values are small enough to be encoded on stack, bigger ones are encoded into a pooled buffer
+const SCRATCH_SIZE: usize = 64;
+
+fn with_scratch<R, F: FnOnce(&mut [u8]) -> R>(size: usize, func: F) -> R {
+    if size <= SCRATCH_SIZE {
+        let mut scratch = [0u8; SCRATCH_SIZE];
+
+        func(&mut scratch[..size])
+    } else {
+        func(&mut PooledBuf::zeroed(size))
+    }
+}
+
 fn read_fixed<T: AsFixedSizeBytes>(ptr: StablePtr) -> T {
-    let mut b = T::Buf::new(T::SIZE);
-    stable::read(ptr, b._deref_mut());
+    with_scratch(T::SIZE, |b| {
+        stable::read(ptr, b);
 
-    T::from_fixed_size_bytes(b._deref())
+        T::from_fixed_size_bytes(b)
+    })
 }
 
 /// Reads a [StableType](crate::StableType) value *that won't move* implementing [AsFixedSizeBytes](crate::AsFixedSizeBytes) trait from stable memory.
@@ -127,7 +314,11 @@ pub unsafe fn read_fixed_for_move<T: AsFixedSizeBytes + StableType>(ptr: StableP
 #[inline]
 pub unsafe fn write_fixed<T: AsFixedSizeBytes + StableType>(ptr: StablePtr, it: &mut T) {
     it.stable_drop_flag_off();
-    stable::write(ptr, it.as_new_fixed_size_bytes()._deref())
+
+    with_scratch(T::SIZE, |b| {
+        it.as_fixed_size_bytes(b);
+        stable::write(ptr, b);
+    })
 }
 
 /// Wipes out stable memory, making it zero pages again.
@@ -141,3 +332,65 @@ pub unsafe fn write_fixed<T: AsFixedSizeBytes + StableType>(ptr: StablePtr, it:
 pub unsafe fn clear() {
     stable::clear();
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::mem::{
+        move_bytes, read_bytes, read_bytes_vectored, write_bytes, write_bytes_vectored,
+    };
+    use crate::stable;
+
+    #[test]
+    fn vectored_works_fine() {
+        stable::clear();
+        stable::grow(1).unwrap();
+
+        unsafe {
+            write_bytes_vectored(&[
+                (8, &[2; 8]),
+                (0, &[1; 8]),
+                (1000, &[3; 4]),
+                (16, &[]),
+                (16, &[4; 2]),
+            ]);
+
+            let mut buf = [0u8; 18];
+            read_bytes(0, &mut buf);
+            assert_eq!(buf, [1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 4, 4]);
+
+            write_bytes(200, &[5; 4]);
+
+            let (mut a, mut b, mut c, mut d) = ([0u8; 4], [0u8; 4], [0u8; 10], [0u8; 4]);
+            read_bytes_vectored(&mut [(1000, &mut a), (200, &mut b), (4, &mut c), (2000, &mut d)]);
+
+            assert_eq!(a, [3; 4]);
+            assert_eq!(b, [5; 4]);
+            assert_eq!(c, [1, 1, 1, 1, 2, 2, 2, 2, 2, 2]);
+            assert_eq!(d, [0; 4]);
+        }
+    }
+
+    #[test]
+    fn move_bytes_works_fine() {
+        stable::clear();
+        stable::grow(4).unwrap();
+
+        // bigger than a single batch, so overlapping batches are moved too
+        let data = (0..150_000u32).map(|it| it as u8).collect::<Vec<_>>();
+
+        unsafe {
+            write_bytes(0, &data);
+
+            move_bytes(0, 1000, data.len() as u64);
+
+            let mut buf = vec![0u8; data.len()];
+            read_bytes(1000, &mut buf);
+            assert_eq!(buf, data);
+
+            move_bytes(1000, 10, data.len() as u64);
+
+            read_bytes(10, &mut buf);
+            assert_eq!(buf, data);
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Named stable memory regions with independent quotas and statistics.
+//!
+//! A region is a set of memory blocks (chunks), allocated from the [StableMemoryAllocator]. Each
+//! chunk is managed by the region's own free-list, so memory blocks of different regions never share
+//! a chunk and each region occupies its own address ranges. A chunk is returned back to the
+//! allocator, once all memory blocks inside it are deallocated.
+//!
+//! Like the allocator itself, regions are persisted between canister upgrades. Use top-level
+//! functions ([init_region](crate::init_region), [with_region](crate::with_region) and
+//! [get_region_stats](crate::get_region_stats)) to work with regions.
+
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::{
+    insert_free_block, pop_free_block, remove_free_block, try_merge_with_neighbors, FreeBlocks,
+    StableMemoryAllocator,
+};
+use crate::mem::free_block::FreeBlock;
+use crate::mem::s_slice::{SSlice, CANARY_SIZE};
+use crate::mem::StablePtr;
+use crate::{OutOfMemory, PAGE_SIZE_BYTES};
+use candid::{CandidType, Deserialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+// small regions should not take a whole page, but big ones should not produce too many chunks
+const MIN_CHUNK_SIZE: u64 = PAGE_SIZE_BYTES / 4;
+
+thread_local! {
+    static CURRENT_REGION: RefCell<Option<String>> = RefCell::default();
+}
+
+#[doc(hidden)]
+#[derive(Debug, Clone, CandidType, Deserialize, Eq, PartialEq)]
+pub struct Region {
+    free_blocks: FreeBlocks,
+    // chunk ptr -> chunk size
+    chunks: BTreeMap<StablePtr, u64>,
+    quota: u64,
+    free_size: u64,
+    available_size: u64,
+}
+
+impl Region {
+    pub fn new(quota: u64) -> Self {
+        Self {
+            free_blocks: FreeBlocks::default(),
+            chunks: BTreeMap::default(),
+            quota,
+            free_size: 0,
+            available_size: 0,
+        }
+    }
+
+    #[inline]
+    pub fn set_quota(&mut self, quota: u64) {
+        self.quota = quota;
+    }
+
+    /// Returns [true] if the pointer points inside one of this region's chunks
+    pub fn contains(&self, ptr: StablePtr) -> bool {
+        match self.chunks.range(..ptr).next_back() {
+            Some((&chunk_ptr, &chunk_size)) => ptr < SSlice::_offset(chunk_ptr, chunk_size),
+            None => false,
+        }
+    }
+
+    #[inline]
+    pub fn is_chunk(&self, ptr: StablePtr) -> bool {
+        self.chunks.contains_key(&ptr)
+    }
+
+    pub fn make_sure_can_allocate(
+        &mut self,
+        mut size: u64,
+        allocator: &mut StableMemoryAllocator,
+    ) -> bool {
+        size = StableMemoryAllocator::pad_size(size);
+
+        if self.free_blocks.range(size..).next().is_some() {
+            return true;
+        }
+
+        match self.allocate_chunk(size, allocator) {
+            Ok(fb) => {
+                insert_free_block(&mut self.free_blocks, fb);
+
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn allocate(
+        &mut self,
+        mut size: u64,
+        allocator: &mut StableMemoryAllocator,
+    ) -> Result<SSlice, OutOfMemory> {
+        size = StableMemoryAllocator::pad_size(size);
+
+        let free_block = match pop_free_block(&mut self.free_blocks, size) {
+            Some(fb) => fb,
+            None => self.allocate_chunk(size, allocator)?,
+        };
+
+        let slice = if FreeBlock::can_split(free_block.get_size_bytes(), size) {
+            let (a, b) = free_block.split(size);
+            let s = a.to_allocated();
+
+            insert_free_block(&mut self.free_blocks, b);
+
+            s
+        } else {
+            free_block.to_allocated()
+        };
+
+        self.free_size -= slice.get_total_size_bytes();
+
+        Ok(slice)
+    }
+
+    pub fn deallocate(&mut self, slice: SSlice, allocator: &mut StableMemoryAllocator) {
+        let free_block = slice.to_free_block();
+        self.free_size += free_block.get_total_size_bytes();
+
+        // chunk metadata is always "allocated", so free blocks never get merged across chunks
+        let free_block =
+            try_merge_with_neighbors(&mut self.free_blocks, free_block, StablePtr::MAX);
+
+        let chunk_ptr = free_block.as_ptr() - StablePtr::SIZE as u64 - CANARY_SIZE;
+        if self.chunks.get(&chunk_ptr) == Some(&free_block.get_total_size_bytes()) {
+            self.chunks.remove(&chunk_ptr);
+
+            self.free_size -= free_block.get_total_size_bytes();
+            self.available_size -= free_block.get_total_size_bytes();
+
+            allocator.deallocate(unsafe { SSlice::from_ptr(chunk_ptr).unwrap() });
+        } else {
+            insert_free_block(&mut self.free_blocks, free_block);
+        }
+    }
+
+    pub fn reallocate(
+        &mut self,
+        slice: SSlice,
+        new_size: u64,
+        allocator: &mut StableMemoryAllocator,
+    ) -> Result<SSlice, OutOfMemory> {
+        let padded_size = StableMemoryAllocator::pad_size(new_size);
+
+        if padded_size <= slice.get_raw_size_bytes() {
+            return Ok(slice);
+        }
+
+        let free_block = slice.to_free_block();
+
+        if let Some(next_neighbor) = free_block.next_neighbor_is_free(StablePtr::MAX) {
+            let merged_size = FreeBlock::merged_size(&free_block, &next_neighbor);
+
+            if merged_size >= padded_size {
+                remove_free_block(&mut self.free_blocks, &next_neighbor);
+                self.free_size -= next_neighbor.get_total_size_bytes();
+
+                let free_block = FreeBlock::merge(free_block, next_neighbor);
+
+                if !FreeBlock::can_split(merged_size, padded_size) {
+                    return Ok(free_block.to_allocated());
+                }
+
+                let (a, b) = free_block.split(padded_size);
+                let s = a.to_allocated();
+
+                self.free_size += b.get_total_size_bytes();
+                insert_free_block(&mut self.free_blocks, b);
+
+                return Ok(s);
+            }
+        }
+
+        // the old slice is only deallocated after the data is copied, so it stays intact on error
+        let new_slice = self.allocate(new_size, allocator)?;
+        unsafe {
+            crate::mem::copy_bytes(slice.offset(0), new_slice.offset(0), slice.get_size_bytes())
+        };
+
+        self.deallocate(slice, allocator);
+
+        Ok(new_slice)
+    }
+
+    pub fn get_stats(&self) -> RegionStats {
+        RegionStats {
+            quota: self.quota,
+            chunks_count: self.chunks.len() as u64,
+            free_size: self.free_size,
+            allocated_size: self.available_size - self.free_size,
+            available_size: self.available_size,
+        }
+    }
+
+    pub fn debug_validate_free_blocks(&self) {
+        let mut total_free_size = 0u64;
+        for free_block in self.free_blocks.values().flatten() {
+            free_block.debug_validate();
+            assert!(self.contains(free_block.as_ptr()));
+
+            total_free_size += free_block.get_total_size_bytes();
+        }
+
+        assert_eq!(total_free_size, self.free_size);
+        assert_eq!(self.chunks.values().sum::<u64>(), self.available_size);
+    }
+
+    // returns a transient free block, spanning the whole new chunk
+    fn allocate_chunk(
+        &mut self,
+        size: u64,
+        allocator: &mut StableMemoryAllocator,
+    ) -> Result<FreeBlock, OutOfMemory> {
+        let required_size = FreeBlock::to_total_size(size);
+        let mut chunk_size = required_size.max(MIN_CHUNK_SIZE);
+
+        if self.quota != 0 {
+            let remaining = self.quota.saturating_sub(self.available_size) & !7;
+
+            if remaining < required_size {
+                return Err(OutOfMemory);
+            }
+
+            chunk_size = chunk_size.min(remaining);
+        }
+
+        let chunk = allocator.allocate(chunk_size)?;
+        let free_block = FreeBlock::new_total_size(chunk.offset(0), chunk.get_size_bytes());
+
+        self.chunks.insert(chunk.as_ptr(), chunk.get_size_bytes());
+        self.available_size += chunk.get_size_bytes();
+        self.free_size += chunk.get_size_bytes();
+
+        Ok(free_block)
+    }
+}
+
+/// Region statistics, returned by [get_region_stats](crate::get_region_stats)
+///
+/// All sizes are in bytes and include memory block metadata.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct RegionStats {
+    /// The maximum amount of memory this region can take (`0` means unlimited)
+    pub quota: u64,
+    /// The number of chunks, this region's memory is split into
+    pub chunks_count: u64,
+    /// The total size of all free blocks inside this region
+    pub free_size: u64,
+    /// The total size of all allocated blocks inside this region
+    pub allocated_size: u64,
+    /// The total amount of memory, taken by this region
+    pub available_size: u64,
+}
+
+pub(crate) fn with_current_region<R, F: FnOnce(Option<&str>) -> R>(func: F) -> R {
+    CURRENT_REGION.with(|it| func(it.borrow().as_deref()))
+}
+
+pub(crate) struct RegionGuard {
+    prev: Option<String>,
+}
+
+impl RegionGuard {
+    pub(crate) fn enter(name: &str) -> Self {
+        let prev = CURRENT_REGION.with(|it| it.replace(Some(String::from(name))));
+
+        Self { prev }
+    }
+}
+
+impl Drop for RegionGuard {
+    fn drop(&mut self) {
+        CURRENT_REGION.with(|it| *it.borrow_mut() = self.prev.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::{SBTreeMap, SVec};
+    use crate::mem::allocator::{AllocationStrategy, StableMemoryAllocator};
+    use crate::mem::free_block::FreeBlock;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::{
+        _debug_validate_allocator, allocate, deallocate, defragment, get_allocated_size,
+        get_region_stats, init_region, reallocate, stable_memory_init, stable_memory_post_upgrade,
+        stable_memory_pre_upgrade, with_region, OutOfMemory,
+    };
+    use candid::{decode_one, encode_one, CandidType};
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            init_region("users", 0);
+            init_region("logs", 10_000);
+
+            let mut users = with_region("users", || {
+                let mut users = SBTreeMap::new();
+                for i in 0..1000u64 {
+                    users
+                        .insert(i, SBox::new(format!("user {}", i)).unwrap())
+                        .unwrap();
+                }
+
+                users
+            });
+
+            let mut logs = with_region("logs", SVec::<u64>::new);
+            let res = with_region("logs", || {
+                for i in 0..10_000 {
+                    logs.push(i).map_err(|_| OutOfMemory)?;
+                }
+
+                Ok::<(), OutOfMemory>(())
+            });
+
+            assert!(res.is_err());
+            assert!(!logs.is_empty());
+
+            let users_stats = get_region_stats("users").unwrap();
+            let logs_stats = get_region_stats("logs").unwrap();
+
+            assert!(users_stats.chunks_count > 0);
+            assert!(users_stats.allocated_size > 0);
+            assert!(logs_stats.available_size <= 10_000);
+            assert!(get_region_stats("indexes").is_none());
+            _debug_validate_allocator();
+
+            // nested regions and allocations outside of any region
+            let slice = with_region("users", || {
+                with_region("logs", || assert!(unsafe { allocate(20_000) }.is_err()));
+
+                unsafe { allocate(100).unwrap() }
+            });
+            let outside = unsafe { allocate(100).unwrap() };
+
+            let users_allocated = get_region_stats("users").unwrap().allocated_size;
+
+            // reallocation and deallocation happen in the owning region, even outside of it
+            let slice = unsafe { reallocate(slice, 1000).unwrap() };
+            assert!(get_region_stats("users").unwrap().allocated_size > users_allocated);
+
+            deallocate(slice);
+            deallocate(outside);
+            assert_eq!(
+                get_region_stats("users").unwrap().allocated_size,
+                users_allocated - slice_total_size(100)
+            );
+
+            for i in 0..500u64 {
+                users.remove(&i);
+            }
+            _debug_validate_allocator();
+
+            // chunks are never moved
+            defragment(u64::MAX, |_, _| true);
+            _debug_validate_allocator();
+
+            let users_stats = get_region_stats("users").unwrap();
+
+            stable_memory_pre_upgrade().unwrap();
+            stable_memory_post_upgrade();
+
+            assert_eq!(get_region_stats("users").unwrap(), users_stats);
+            for i in 500..1000u64 {
+                assert_eq!(*users.get(&i).unwrap().clone(), format!("user {}", i));
+            }
+
+            drop(users);
+            drop(logs);
+
+            // empty chunks are returned back
+            assert_eq!(get_region_stats("users").unwrap().chunks_count, 0);
+            assert_eq!(get_region_stats("logs").unwrap().available_size, 0);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    fn slice_total_size(size: u64) -> u64 {
+        StableMemoryAllocator::pad_size(size) + 16
+    }
+
+    #[derive(CandidType)]
+    struct OldStableMemoryAllocator {
+        free_blocks: BTreeMap<u64, Vec<FreeBlock>>,
+        custom_data_pointers: HashMap<usize, u64>,
+        free_size: u64,
+        available_size: u64,
+        max_ptr: u64,
+        max_pages: u64,
+    }
+
+    #[test]
+    fn decodes_allocators_without_regions() {
+        let old = OldStableMemoryAllocator {
+            free_blocks: BTreeMap::default(),
+            custom_data_pointers: HashMap::default(),
+            free_size: 0,
+            available_size: 0,
+            max_ptr: 8,
+            max_pages: 0,
+        };
+
+        let it: StableMemoryAllocator = decode_one(&encode_one(old).unwrap()).unwrap();
+        assert!(!it.has_region("users"));
+        assert_eq!(it.get_strategy(), AllocationStrategy::BestFit);
+    }
+}
@@ -10,34 +10,80 @@ use crate::mem::allocator::EMPTY_PTR;
 use crate::mem::free_block::FreeBlock;
 use crate::mem::{StablePtr, StablePtrBuf};
 use crate::utils::mem_context::stable;
+use std::cell::Cell;
 
 pub(crate) const ALLOCATED: u64 = 2u64.pow(u64::BITS - 1); // first biggest bit set to 1, other set to 0
 pub(crate) const FREE: u64 = ALLOCATED - 1; // first biggest bit set to 0, other set to 1
 
+// 40 bits are enough to address 1TB, which is more than a canister can have
+const SIZE_BITS: u32 = 40;
+const SIZE_MASK: u64 = (1 << SIZE_BITS) - 1;
+/// The maximum size of a memory block in bytes (1TB)
+pub const MAX_BLOCK_SIZE: u64 = SIZE_MASK;
+const GENERATION_MASK: u32 = (FREE >> SIZE_BITS) as u32;
+
+/// The size of a single canary in bytes - `8` with `debug_canaries` feature enabled, `0` otherwise
+pub const CANARY_SIZE: u64 = if cfg!(feature = "debug_canaries") {
+    8
+} else {
+    0
+};
+const CANARY: u64 = 0xC0FF_EE15_DEAD_BEEF;
+
+thread_local! {
+    // generation 0 means "unknown" - blocks allocated before generations were introduced have it;
+    // the counter is persisted between upgrades along with the allocator
+    static NEXT_GENERATION: Cell<u32> = const { Cell::new(1) };
+}
+
 /// An allocated block of stable memory.
 ///
 /// Represented by a pointer to the first byte of the memory block and a [u64] size of this block in
 /// bytes. It implements [Copy], but using it after deallocation is undefined behavior.
 ///
 /// In stable memory each memory block has the following layout:
-/// - bytes `0..8` - `size` + `allocated bit flag` (the flag uses the first bit of little endian encoded size)
+/// - bytes `0..8` - `size` + `generation` + `allocated bit flag` (the flag uses the first bit of
+///   little endian encoded size, the generation uses the next 23 bits)
 /// - bytes `8..(size + 8)` - the data
-/// - bytes `(size + 8)..(size + 16)` - another `size` + `allocated bit flag`
+/// - bytes `(size + 8)..(size + 16)` - the same metadata again
 /// So, a memory block is simply `size` bytes of data wrapped with some metadata from both sides.
-/// [FreeBlock](mem::free_block::FreeBlock) is stored exactly in a same way.
+/// [FreeBlock](mem::free_block::FreeBlock) is stored exactly in a same way, but without a generation.
+///
+/// Each time a memory block is allocated (or resized), it gets a new generation. An [SSlice]
+/// remembers the generation of the memory block it was created for, which allows detecting
+/// use-after-deallocate bugs with [SSlice::is_alive]. In debug builds this check is performed
+/// automatically by [SSlice::offset], by the allocator and by [SRef](crate::primitive::s_ref::SRef)
+/// and [SRefMut](crate::primitive::s_ref_mut::SRefMut), created with `new_in`.
+///
+/// With `debug_canaries` feature enabled, the data is also bracketed by two [CANARY_SIZE] byte
+/// canaries, which are validated by the allocator, when the memory block is deallocated or
+/// reallocated, and by [_debug_validate_allocator](crate::_debug_validate_allocator). A broken canary
+/// means, that somebody wrote outside of their memory block. Canaries are invisible for users: [SSlice::offset]
+/// skips the first one and [SSlice::get_size_bytes] does not include them. Since the layout of
+/// memory blocks changes, this feature can't be switched on or off for an already deployed canister.
 #[derive(Debug, Copy, Clone)]
 pub struct SSlice {
     ptr: StablePtr,
     size: u64,
+    generation: u32,
 }
 
 impl SSlice {
     pub(crate) fn new(ptr: StablePtr, size: u64, write_size: bool) -> Self {
-        if write_size {
-            Self::write_size(ptr, size);
-        }
+        let generation = if write_size {
+            let generation = next_generation();
+            Self::write_size(ptr, size, generation);
 
-        Self { ptr, size }
+            generation
+        } else {
+            0
+        };
+
+        Self {
+            ptr,
+            size,
+            generation,
+        }
     }
 
     /// Recreate an [SSlice] from a pointer to the front of the memory block.
@@ -54,9 +100,13 @@ impl SSlice {
             return None;
         }
 
-        let size = Self::read_size(ptr)?;
+        let (size, generation) = Self::read_meta(ptr)?;
 
-        Some(Self::new(ptr, size, false))
+        Some(Self {
+            ptr,
+            size,
+            generation,
+        })
     }
 
     /// Recreate an [SSlice] from a pointer to the back of the memory block.
@@ -70,13 +120,13 @@ impl SSlice {
             return None;
         }
 
-        let size = Self::read_size(ptr)?;
+        let (size, generation) = Self::read_meta(ptr)?;
 
-        Some(Self::new(
-            ptr - (StablePtr::SIZE as u64) - size,
+        Some(Self {
+            ptr: ptr - (StablePtr::SIZE as u64) - size,
             size,
-            false,
-        ))
+            generation,
+        })
     }
 
     /// Returns a pointer to the memory block.
@@ -91,13 +141,41 @@ impl SSlice {
     /// Returns the size of the data in this memory block in bytes.
     #[inline]
     pub fn get_size_bytes(&self) -> u64 {
+        self.size - CANARY_SIZE * 2
+    }
+
+    // includes canaries
+    #[inline]
+    pub(crate) fn get_raw_size_bytes(&self) -> u64 {
         self.size
     }
 
+    /// Returns the generation of the memory block, this [SSlice] was created for.
+    ///
+    /// `0` means that the generation is unknown - the block was allocated by an older version of
+    /// this crate.
+    #[inline]
+    pub fn get_generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Returns [true] if the memory block is still allocated and was not reallocated since this
+    /// [SSlice] was created.
+    ///
+    /// A block, that was deallocated and then allocated again at the same address, is detected
+    /// by its generation. Since generations are 23 bits wide and wrap around, this check is not
+    /// exact, but it is good enough to catch use-after-deallocate bugs.
+    pub fn is_alive(&self) -> bool {
+        match Self::read_meta(self.ptr) {
+            Some((size, generation)) => size == self.size && generation == self.generation,
+            None => false,
+        }
+    }
+
     /// Returns the size of the whole memory block in bytes (including metadata).
     #[inline]
     pub fn get_total_size_bytes(&self) -> u64 {
-        self.get_size_bytes() + StablePtr::SIZE as u64 * 2
+        self.size + StablePtr::SIZE as u64 * 2
     }
 
     /// Static analog of [SSlice::offset].
@@ -107,7 +185,7 @@ impl SSlice {
     pub fn _offset(self_ptr: u64, offset: u64) -> StablePtr {
         debug_assert_ne!(self_ptr, EMPTY_PTR);
 
-        self_ptr + (StablePtr::SIZE as u64) + offset
+        self_ptr + (StablePtr::SIZE as u64) + CANARY_SIZE + offset
     }
 
     /// Returns a pointer to the data inside [SSlice].
@@ -116,7 +194,8 @@ impl SSlice {
     /// [mem::write_bytes].
     ///
     /// # Panics
-    /// Panics if boundary check fails (if the offset is outside the memory block).
+    /// Panics if boundary check fails (if the offset is outside the memory block). In debug builds
+    /// also panics if the memory block is not [alive](SSlice::is_alive) anymore.
     ///
     /// # Example
     /// ```rust
@@ -133,39 +212,65 @@ impl SSlice {
     #[inline]
     pub fn offset(&self, offset: u64) -> StablePtr {
         let ptr = Self::_offset(self.as_ptr(), offset);
-        assert!(ptr <= self.as_ptr() + StablePtr::SIZE as u64 + self.get_size_bytes());
+        assert!(ptr <= Self::_offset(self.as_ptr(), self.get_size_bytes()));
+        debug_assert!(self.is_alive(), "Use after deallocate: {:?}", self);
 
         ptr
     }
 
+    pub(crate) fn write_canaries(&self) {
+        if CANARY_SIZE == 0 {
+            return;
+        }
+
+        let canary = (CANARY ^ self.ptr).to_le_bytes();
+
+        stable::write(self.ptr + StablePtr::SIZE as u64, &canary);
+        stable::write(self.offset(self.get_size_bytes()), &canary);
+    }
+
+    pub(crate) fn canaries_are_intact(&self) -> bool {
+        if CANARY_SIZE == 0 {
+            return true;
+        }
+
+        let mut front = StablePtrBuf::new(StablePtr::SIZE);
+        let mut rear = StablePtrBuf::new(StablePtr::SIZE);
+
+        stable::read(self.ptr + StablePtr::SIZE as u64, &mut front);
+        stable::read(self.offset(self.get_size_bytes()), &mut rear);
+
+        let canary = (CANARY ^ self.ptr).to_le_bytes();
+
+        front == canary && rear == canary
+    }
+
     #[inline]
     pub(crate) fn to_free_block(self) -> FreeBlock {
         FreeBlock::new(self.ptr, self.size)
     }
 
-    fn read_size(ptr: StablePtr) -> Option<u64> {
+    fn read_meta(ptr: StablePtr) -> Option<(u64, u32)> {
         let mut meta = StablePtrBuf::new(StablePtr::SIZE);
         stable::read(ptr, &mut meta);
 
         let encoded_size = u64::from_le_bytes(meta);
-        let mut size = encoded_size;
 
-        let allocated = if encoded_size & ALLOCATED == ALLOCATED {
-            size &= FREE;
-            true
-        } else {
-            false
-        };
+        if encoded_size & ALLOCATED == ALLOCATED {
+            let size = encoded_size & SIZE_MASK;
+            let generation = ((encoded_size & FREE) >> SIZE_BITS) as u32;
 
-        if allocated {
-            Some(size)
+            Some((size, generation))
         } else {
             None
         }
     }
 
-    fn write_size(ptr: StablePtr, size: u64) {
-        let encoded_size = size | ALLOCATED;
+    fn write_size(ptr: StablePtr, size: u64, generation: u32) {
+        // an oversized size would overwrite generation bits
+        assert!(size <= MAX_BLOCK_SIZE, "The memory block is too big");
+
+        let encoded_size = size | ((generation as u64) << SIZE_BITS) | ALLOCATED;
 
         let meta = encoded_size.to_le_bytes();
 
@@ -174,14 +279,136 @@ impl SSlice {
     }
 }
 
+#[inline]
+pub(crate) fn get_next_generation() -> u32 {
+    NEXT_GENERATION.with(|it| it.get())
+}
+
+#[inline]
+pub(crate) fn set_next_generation(generation: u32) {
+    NEXT_GENERATION.with(|it| it.set(generation));
+}
+
+fn next_generation() -> u32 {
+    NEXT_GENERATION.with(|it| {
+        let generation = it.get();
+
+        // wrapping around, skipping 0
+        it.set(if generation == GENERATION_MASK {
+            1
+        } else {
+            generation + 1
+        });
+
+        generation
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use crate::encoding::AsFixedSizeBytes;
     use crate::mem::allocator::MIN_PTR;
-    use crate::mem::s_slice::SSlice;
+    use crate::mem::s_slice::{get_next_generation, set_next_generation, SSlice};
     use crate::mem::StablePtr;
+    use crate::primitive::s_ref::SRef;
     use crate::utils::mem_context::stable;
+    use crate::{
+        allocate, deallocate, reallocate, stable_memory_init, stable_memory_post_upgrade,
+        stable_memory_pre_upgrade,
+    };
+
+    #[test]
+    fn generations_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        let a = unsafe { allocate(100).unwrap() };
+        let a_copy = unsafe { SSlice::from_ptr(a.as_ptr()).unwrap() };
+
+        assert_ne!(a.get_generation(), 0);
+        assert_eq!(a.get_generation(), a_copy.get_generation());
+        assert!(a_copy.is_alive());
+
+        deallocate(a);
+        assert!(!a_copy.is_alive());
+
+        // the same memory block is reused, but the generation is different
+        let b = unsafe { allocate(100).unwrap() };
+        assert_eq!(b.as_ptr(), a_copy.as_ptr());
+        assert_ne!(b.get_generation(), a_copy.get_generation());
+        assert!(!a_copy.is_alive());
+        assert!(b.is_alive());
+
+        let b_copy = b;
+        let b = unsafe { reallocate(b, 200).unwrap() };
+        assert!(!b_copy.is_alive());
+        assert!(b.is_alive());
+
+        let c = unsafe { SSlice::from_rear_ptr(b.as_ptr() + b.get_total_size_bytes() - 8) };
+        assert_eq!(c.unwrap().get_generation(), b.get_generation());
+
+        deallocate(b);
+    }
+
+    #[test]
+    fn generations_survive_upgrade() {
+        stable::clear();
+        stable_memory_init();
+
+        let a = unsafe { allocate(100).unwrap() };
+
+        stable_memory_pre_upgrade().unwrap();
+        let next_generation = get_next_generation();
+
+        // a fresh heap after the upgrade
+        set_next_generation(1);
+        stable_memory_post_upgrade();
+
+        assert_eq!(get_next_generation(), next_generation);
+
+        let a_copy = unsafe { SSlice::from_ptr(a.as_ptr()).unwrap() };
+        deallocate(a_copy);
+
+        // generations don't restart from 1
+        let b = unsafe { allocate(100).unwrap() };
+        assert!(b.get_generation() > a.get_generation());
+
+        deallocate(b);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn double_deallocate_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let a = unsafe { allocate(100).unwrap() };
+        deallocate(a);
+        deallocate(a);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Use after deallocate")]
+    fn use_after_deallocate_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let a = unsafe { allocate(100).unwrap() };
+        let r = unsafe { SRef::<u64>::new_in(a.as_ptr(), a.offset(0)) };
+
+        deallocate(a);
+
+        // the same memory block gets allocated again, but with another generation
+        let b = unsafe { allocate(100).unwrap() };
+        assert_eq!(b.as_ptr(), a.as_ptr());
+
+        let _ = *r;
+    }
 
+    // relies on the exact memory layout, which is different with canaries
+    #[cfg(not(feature = "debug_canaries"))]
     #[test]
     fn read_write_work_fine() {
         stable::clear();
@@ -0,0 +1,230 @@
+//! Interoperability with [ic-stable-structures](https://crates.io/crates/ic-stable-structures).
+//!
+//! Enabled by `ic-stable-structures` feature. Allows using both libraries in a single canister
+//! (e.g. during a migration from one to another), without them trampling each other's pages. This
+//! works in both directions:
+//! 1. [VirtualMemory] implements `ic_stable_structures::Memory`, so data structures of
+//!    `ic-stable-structures` can live inside memory blocks, allocated by this crate.
+//! 2. [set_backing_memory] makes this crate use any `ic_stable_structures::Memory` (e.g. a virtual
+//!    memory of `ic_stable_structures::memory_manager::MemoryManager`) instead of raw stable memory.
+//!
+//! # Examples
+//! ```rust
+//! # use ic_stable_memory::mem::virtual_memory::VirtualMemory;
+//! # use ic_stable_memory::stable_memory_init;
+//! # use ic_stable_structures::StableBTreeMap;
+//! # use std::rc::Rc;
+//! # unsafe { ic_stable_memory::mem::clear(); }
+//! # stable_memory_init();
+//! let memory = Rc::new(VirtualMemory::new());
+//! let mut map = StableBTreeMap::<u64, u64, _>::init(Rc::clone(&memory));
+//!
+//! map.insert(1, 10);
+//!
+//! assert_eq!(map.get(&1), Some(10));
+//! assert!(memory.size() > 0);
+//! ```
+
+use crate::mem::virtual_memory::VirtualMemory;
+use crate::utils::mem_context::{MemContext, OutOfMemory};
+use ic_stable_structures::Memory;
+use std::cell::RefCell;
+
+thread_local! {
+    static BACKING_MEMORY: RefCell<Option<Box<dyn Memory>>> = RefCell::new(None);
+}
+
+impl Memory for VirtualMemory {
+    #[inline]
+    fn size(&self) -> u64 {
+        VirtualMemory::size(self)
+    }
+
+    #[inline]
+    fn grow(&self, pages: u64) -> i64 {
+        VirtualMemory::grow(self, pages)
+    }
+
+    #[inline]
+    fn read(&self, offset: u64, dst: &mut [u8]) {
+        VirtualMemory::read(self, offset, dst)
+    }
+
+    #[inline]
+    fn write(&self, offset: u64, src: &[u8]) {
+        VirtualMemory::write(self, offset, src)
+    }
+}
+
+/// Makes this crate store everything in the provided memory, instead of raw stable memory
+///
+/// Should be called before anything else touches stable memory - before
+/// [stable_memory_init](crate::stable_memory_init) or [stable_memory_post_upgrade](crate::stable_memory_post_upgrade),
+/// and then again after each upgrade, since the memory is only kept on heap. It has to be the same
+/// memory each time, otherwise the data won't be found.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::mem::stable_structures::{reset_backing_memory, set_backing_memory};
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::stable_memory_init;
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// // in a canister this would be a virtual memory of MemoryManager
+/// let memory = Rc::new(RefCell::new(Vec::new()));
+///
+/// set_backing_memory(Rc::clone(&memory));
+/// stable_memory_init();
+///
+/// let mut vec = SVec::<u64>::new();
+/// vec.push(10).expect("Out of memory");
+///
+/// assert!(!memory.borrow().is_empty());
+/// # drop(vec);
+/// # reset_backing_memory();
+/// ```
+pub fn set_backing_memory<M: Memory + 'static>(memory: M) {
+    BACKING_MEMORY.with(|it| *it.borrow_mut() = Some(Box::new(memory)));
+}
+
+/// Makes this crate use raw stable memory again, returning [true] if there was a backing memory
+///
+/// See also [set_backing_memory].
+pub fn reset_backing_memory() -> bool {
+    BACKING_MEMORY.with(|it| it.borrow_mut().take().is_some())
+}
+
+#[inline]
+pub(crate) fn is_backed() -> bool {
+    BACKING_MEMORY.with(|it| it.borrow().is_some())
+}
+
+// routes raw stable memory calls into the backing memory, only used when it is set
+pub(crate) struct BackingMemContext;
+
+impl BackingMemContext {
+    #[inline]
+    fn with<R, F: FnOnce(&dyn Memory) -> R>(f: F) -> R {
+        BACKING_MEMORY.with(|it| f(it.borrow().as_deref().expect("No backing memory")))
+    }
+}
+
+impl MemContext for BackingMemContext {
+    #[inline]
+    fn size_pages(&self) -> u64 {
+        Self::with(|it| it.size())
+    }
+
+    #[inline]
+    fn grow(&mut self, new_pages: u64) -> Result<u64, OutOfMemory> {
+        match Self::with(|it| it.grow(new_pages)) {
+            -1 => Err(OutOfMemory),
+            prev_pages => Ok(prev_pages as u64),
+        }
+    }
+
+    #[inline]
+    fn read(&self, offset: u64, buf: &mut [u8]) {
+        Self::with(|it| it.read(offset, buf))
+    }
+
+    #[inline]
+    fn write(&mut self, offset: u64, buf: &[u8]) {
+        Self::with(|it| it.write(offset, buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SBTreeMap;
+    use crate::mem::stable_structures::{reset_backing_memory, set_backing_memory};
+    use crate::mem::virtual_memory::VirtualMemory;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::utils::DebuglessUnwrap;
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, retrieve_custom_data, stable_memory_init,
+        stable_memory_post_upgrade, stable_memory_pre_upgrade, store_custom_data,
+    };
+    use ic_stable_structures::{Memory, StableBTreeMap};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn virtual_memory_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let memory = Rc::new(VirtualMemory::new());
+
+            {
+                let mut map = StableBTreeMap::<u64, u64, _>::init(Rc::clone(&memory));
+                for i in 0..1000 {
+                    map.insert(i, i * 2);
+                }
+            }
+
+            store_custom_data(
+                1,
+                SBox::new(Rc::try_unwrap(memory).ok().unwrap()).debugless_unwrap(),
+            );
+
+            stable_memory_pre_upgrade().unwrap();
+            stable_memory_post_upgrade();
+
+            let memory = retrieve_custom_data::<VirtualMemory>(1)
+                .unwrap()
+                .into_inner();
+
+            let map = StableBTreeMap::<u64, u64, _>::init(memory);
+            assert_eq!(map.len(), 1000);
+
+            for i in 0..1000 {
+                assert_eq!(map.get(&i), Some(i * 2));
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn backing_memory_works_fine() {
+        stable::clear();
+
+        let memory = Rc::new(RefCell::new(Vec::new()));
+        set_backing_memory(Rc::clone(&memory));
+
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMap::new();
+            for i in 0..1000u64 {
+                map.insert(i, i * 2).unwrap();
+            }
+
+            store_custom_data(1, SBox::new(map).unwrap());
+
+            stable_memory_pre_upgrade().unwrap();
+            stable_memory_post_upgrade();
+
+            let map = retrieve_custom_data::<SBTreeMap<u64, u64>>(1)
+                .unwrap()
+                .into_inner();
+
+            for i in 0..1000u64 {
+                assert_eq!(*map.get(&i).unwrap(), i * 2);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+
+        // raw stable memory was not touched at all
+        assert_eq!(stable::size_pages(), memory.size());
+        assert!(reset_backing_memory());
+        assert_eq!(stable::size_pages(), 0);
+        assert!(!reset_backing_memory());
+    }
+}
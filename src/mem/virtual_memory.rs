@@ -0,0 +1,296 @@
+//! A linear, page-addressed memory, allocated from this crate's allocator.
+//!
+//! Meant to be used as a compatibility layer with [ic-stable-structures](https://crates.io/crates/ic-stable-structures),
+//! which expects its data structures to own a linear memory, that starts at address `0` and grows
+//! by pages. With `ic-stable-structures` feature enabled, [VirtualMemory] implements
+//! `ic_stable_structures::Memory` trait, but each of its pages is a memory block, allocated with
+//! [allocate](crate::allocate). This way both libraries can be used in a single canister without
+//! trampling each other's pages. See `mem::stable_structures` module for more info.
+
+use crate::collections::SVec;
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::primitive::StableType;
+use crate::{allocate, deallocate, PAGE_SIZE_BYTES};
+use std::cell::RefCell;
+
+/// Growable linear memory, backed by memory blocks of stable memory
+///
+/// Works the same way as `ic_stable_structures::Memory` trait, which it implements with
+/// `ic-stable-structures` feature enabled: `size` and `grow` work with 64KB pages, while `read`
+/// and `write` accept offsets from the beginning of this memory.
+///
+/// Pages are allocated with [allocate](crate::allocate), so they can be put into a dedicated
+/// region by growing the memory inside [with_region](crate::with_region). New pages are
+/// zero-filled, just like pages of real stable memory.
+///
+/// [VirtualMemory] implements [StableType] and [AsFixedSizeBytes], so it can be persisted between
+/// upgrades like any other stable data structure (e.g. with [SBox](crate::SBox) or
+/// [SCell](crate::SCell)). Once dropped, it releases all of its pages.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::mem::virtual_memory::VirtualMemory;
+/// # use ic_stable_memory::{init_region, stable_memory_init, with_region};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// init_region("stable-structures", 0);
+///
+/// let memory = VirtualMemory::new();
+/// assert_eq!(with_region("stable-structures", || memory.grow(2)), 0);
+/// assert_eq!(memory.size(), 2);
+///
+/// memory.write(65530, b"hello world");
+///
+/// let mut buf = [0u8; 11];
+/// memory.read(65530, &mut buf);
+/// assert_eq!(&buf, b"hello world");
+/// ```
+pub struct VirtualMemory {
+    pages: RefCell<SVec<StablePtr>>,
+}
+
+impl VirtualMemory {
+    /// Creates a new empty [VirtualMemory]
+    ///
+    /// Does not allocate anything, until [VirtualMemory::grow] is called.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            pages: RefCell::new(SVec::new()),
+        }
+    }
+
+    /// Returns the size of this memory in pages
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.pages.borrow().len()
+    }
+
+    /// Grows this memory by the provided number of pages
+    ///
+    /// Returns the previous size in pages, or `-1` if there is not enough stable memory. In the
+    /// latter case, the size of this memory stays the same.
+    pub fn grow(&self, pages: u64) -> i64 {
+        let prev_size = self.size();
+
+        for _ in 0..pages {
+            let slice = match unsafe { allocate(PAGE_SIZE_BYTES) } {
+                Ok(it) => it,
+                Err(_) => {
+                    self.shrink_to(prev_size);
+                    return -1;
+                }
+            };
+
+            unsafe { crate::mem::write_zeroes(slice.offset(0), PAGE_SIZE_BYTES) };
+
+            if self.pages.borrow_mut().push(slice.as_ptr()).is_err() {
+                deallocate(slice);
+                self.shrink_to(prev_size);
+
+                return -1;
+            }
+        }
+
+        prev_size as i64
+    }
+
+    /// Reads bytes from this memory, starting from the provided offset
+    ///
+    /// # Panics
+    /// Panics if the read is out of bounds.
+    pub fn read(&self, offset: u64, dst: &mut [u8]) {
+        self.for_each_page(offset, dst.len(), |ptr, from, to| unsafe {
+            crate::mem::read_bytes(ptr, &mut dst[from..to]);
+        });
+    }
+
+    /// Writes bytes to this memory, starting from the provided offset
+    ///
+    /// # Panics
+    /// Panics if the write is out of bounds.
+    pub fn write(&self, offset: u64, src: &[u8]) {
+        self.for_each_page(offset, src.len(), |ptr, from, to| unsafe {
+            crate::mem::write_bytes(ptr, &src[from..to]);
+        });
+    }
+
+    // calls the lambda for each page the range touches, with a pointer and a range of the buffer
+    fn for_each_page<F: FnMut(StablePtr, usize, usize)>(
+        &self,
+        offset: u64,
+        len: usize,
+        mut func: F,
+    ) {
+        let end = offset
+            .checked_add(len as u64)
+            .filter(|it| *it <= self.size() * PAGE_SIZE_BYTES);
+
+        assert!(end.is_some(), "Out of bounds: {} + {}", offset, len);
+
+        let pages = self.pages.borrow();
+        let mut from = 0usize;
+
+        while from < len {
+            let addr = offset + from as u64;
+            let page_ptr = *pages.get(addr / PAGE_SIZE_BYTES).unwrap();
+            let page_offset = addr % PAGE_SIZE_BYTES;
+
+            let to = len.min(from + (PAGE_SIZE_BYTES - page_offset) as usize);
+            func(SSlice::_offset(page_ptr, page_offset), from, to);
+
+            from = to;
+        }
+    }
+
+    fn shrink_to(&self, size: u64) {
+        let mut pages = self.pages.borrow_mut();
+
+        while pages.len() > size {
+            let ptr = pages.pop().unwrap();
+            deallocate(unsafe { SSlice::from_ptr(ptr).unwrap() });
+        }
+    }
+}
+
+impl Default for VirtualMemory {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsFixedSizeBytes for VirtualMemory {
+    const SIZE: usize = SVec::<StablePtr>::SIZE;
+    type Buf = <SVec<StablePtr> as AsFixedSizeBytes>::Buf;
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.pages.borrow().as_fixed_size_bytes(buf)
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        Self {
+            pages: RefCell::new(SVec::from_fixed_size_bytes(arr)),
+        }
+    }
+}
+
+impl StableType for VirtualMemory {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.pages.get_mut().stable_drop_flag_off();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.pages.get_mut().stable_drop_flag_on();
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.pages.borrow().should_stable_drop()
+    }
+
+    // the vector of pages is released by itself
+    unsafe fn stable_drop(&mut self) {
+        self.shrink_to(0);
+    }
+}
+
+impl Drop for VirtualMemory {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mem::virtual_memory::VirtualMemory;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::utils::DebuglessUnwrap;
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, init_allocator, retrieve_custom_data,
+        stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
+        store_custom_data, PAGE_SIZE_BYTES,
+    };
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let memory = VirtualMemory::new();
+            assert_eq!(memory.size(), 0);
+            assert_eq!(memory.grow(3), 0);
+            assert_eq!(memory.grow(1), 3);
+            assert_eq!(memory.size(), 4);
+
+            let mut buf = vec![1u8; PAGE_SIZE_BYTES as usize * 4];
+            memory.read(0, &mut buf);
+            assert!(buf.iter().all(|it| *it == 0));
+
+            let data = (0..PAGE_SIZE_BYTES * 2)
+                .map(|it| it as u8)
+                .collect::<Vec<_>>();
+            memory.write(PAGE_SIZE_BYTES / 2, &data);
+
+            let mut buf = vec![0u8; data.len()];
+            memory.read(PAGE_SIZE_BYTES / 2, &mut buf);
+            assert_eq!(buf, data);
+
+            store_custom_data(1, SBox::new(memory).debugless_unwrap());
+
+            stable_memory_pre_upgrade().unwrap();
+            stable_memory_post_upgrade();
+
+            let memory = retrieve_custom_data::<VirtualMemory>(1)
+                .unwrap()
+                .into_inner();
+            assert_eq!(memory.size(), 4);
+
+            let mut buf = vec![0u8; data.len()];
+            memory.read(PAGE_SIZE_BYTES / 2, &mut buf);
+            assert_eq!(buf, data);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn grow_fails_atomically() {
+        stable::clear();
+        init_allocator(5);
+
+        {
+            let memory = VirtualMemory::new();
+            assert_eq!(memory.grow(2), 0);
+            assert_eq!(memory.grow(10), -1);
+            assert_eq!(memory.size(), 2);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_bounds_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let memory = VirtualMemory::new();
+        memory.grow(1);
+        memory.write(PAGE_SIZE_BYTES - 1, &[1, 2]);
+    }
+}
@@ -0,0 +1,292 @@
+//! Write-combining buffer, that merges small adjacent writes into fewer stable memory syscalls.
+//!
+//! Inside [with_write_coalescing] small writes to stable memory are not performed right away.
+//! Instead, they are put into a buffer, where adjacent and overlapping writes are merged together.
+//! The buffer is flushed, when the outermost [with_write_coalescing] returns (or when it gets too
+//! big). Reads see buffered writes, so this is completely transparent for the code inside.
+//!
+//! This is useful for operations, that edit a lot of small fields of the same memory block - e.g.
+//! B-tree node edits, which rewrite the length, keys and values of a node one by one.
+
+use crate::mem::StablePtr;
+use crate::stable;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+// bigger writes are not buffered - they are already efficient enough
+const MAX_COALESCED_WRITE_SIZE: usize = 256;
+// the buffer is flushed, once it holds more bytes than this
+const MAX_BUFFER_SIZE: usize = 64 * 1024;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    // disjoint and non-adjacent segments: start ptr -> bytes
+    static SEGMENTS: RefCell<BTreeMap<StablePtr, Vec<u8>>> = RefCell::default();
+    static BUFFERED_BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Runs the lambda function, coalescing all small writes to stable memory made inside it
+///
+/// Calls can be nested - writes are only flushed, when the outermost call returns (even if the
+/// lambda panics). Writes made inside a [transaction](crate::with_transaction) are never buffered.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::mem::write_coalescing::with_write_coalescing;
+/// # use ic_stable_memory::{allocate, deallocate, stable_memory_init};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let slice = unsafe { allocate(24).expect("Out of memory") };
+///
+/// with_write_coalescing(|| {
+///     // these three writes become a single one
+///     for mut i in 0..3u64 {
+///         unsafe { ic_stable_memory::mem::write_fixed(slice.offset(i * 8), &mut i) };
+///     }
+///
+///     // buffered writes are visible right away
+///     let it: u64 = unsafe { ic_stable_memory::mem::read_fixed_for_reference(slice.offset(8)) };
+///     assert_eq!(it, 1);
+/// });
+///
+/// deallocate(slice);
+/// ```
+pub fn with_write_coalescing<R, F: FnOnce() -> R>(func: F) -> R {
+    let _guard = CoalescingGuard::enter();
+
+    func()
+}
+
+/// Returns [true] if writes are currently coalesced
+#[inline]
+pub fn is_coalescing_writes() -> bool {
+    DEPTH.try_with(|it| it.get()).unwrap_or_default() > 0
+}
+
+// returns true, if the write was buffered; otherwise the caller should write it itself
+pub(crate) fn try_buffer(offset: StablePtr, buf: &[u8]) -> bool {
+    if !is_coalescing_writes() {
+        return false;
+    }
+
+    // the order of writes should be preserved, so everything buffered goes first
+    if buf.len() > MAX_COALESCED_WRITE_SIZE || crate::is_in_transaction() {
+        flush();
+
+        return false;
+    }
+
+    let buffered_bytes = SEGMENTS.with(|it| merge(&mut it.borrow_mut(), offset, buf));
+    let buffered_bytes = BUFFERED_BYTES.with(|it| {
+        it.set(it.get() + buffered_bytes);
+        it.get()
+    });
+
+    if buffered_bytes > MAX_BUFFER_SIZE {
+        flush();
+    }
+
+    true
+}
+
+// copies buffered bytes on top of the data, that was just read from stable memory
+pub(crate) fn overlay(offset: StablePtr, buf: &mut [u8]) {
+    if BUFFERED_BYTES.try_with(|it| it.get()).unwrap_or_default() == 0 {
+        return;
+    }
+
+    let end = offset + buf.len() as u64;
+
+    // segments never overlap, so walking back from the end can stop at the first one, that ends
+    // before the read starts
+    SEGMENTS.with(|it| {
+        for (&start, data) in it.borrow().range(..end).rev() {
+            let seg_end = start + data.len() as u64;
+            if seg_end <= offset {
+                break;
+            }
+
+            let from = start.max(offset);
+            let to = seg_end.min(end);
+
+            buf[(from - offset) as usize..(to - offset) as usize]
+                .copy_from_slice(&data[(from - start) as usize..(to - start) as usize]);
+        }
+    });
+}
+
+pub(crate) fn flush() {
+    let segments = match SEGMENTS.try_with(|it| it.take()) {
+        Ok(it) => it,
+        Err(_) => return,
+    };
+    BUFFERED_BYTES.with(|it| it.set(0));
+
+    for (ptr, data) in segments {
+        stable::write_through(ptr, &data);
+    }
+}
+
+// merges the write with all the segments it overlaps or touches, returns the number of new bytes;
+// the segment, that the write starts in, is extended in place
+fn merge(segments: &mut BTreeMap<StablePtr, Vec<u8>>, offset: StablePtr, buf: &[u8]) -> usize {
+    let end = offset + buf.len() as u64;
+
+    let mut neighbors = segments
+        .range(..=end)
+        .rev()
+        .take_while(|(&s, data)| s + data.len() as u64 >= offset)
+        .map(|(&s, _)| s)
+        .collect::<Vec<_>>();
+
+    let (start, mut merged) = match neighbors.last() {
+        Some(&s) if s <= offset => {
+            neighbors.pop();
+            (s, segments.remove(&s).unwrap())
+        }
+        _ => (offset, Vec::with_capacity(buf.len())),
+    };
+    let prev_len = merged.len();
+
+    let from = (offset - start) as usize;
+    let overlap = usize::min(merged.len() - from, buf.len());
+    merged[from..from + overlap].copy_from_slice(&buf[..overlap]);
+    merged.extend_from_slice(&buf[overlap..]);
+
+    // the rest of neighbors start inside the write, only their tails are left
+    let mut removed_len = 0;
+    for s in neighbors.into_iter().rev() {
+        let data = segments.remove(&s).unwrap();
+        removed_len += data.len();
+
+        let merged_end = start + merged.len() as u64;
+        let seg_end = s + data.len() as u64;
+
+        if seg_end > merged_end {
+            merged.extend_from_slice(&data[(merged_end - s) as usize..]);
+        }
+    }
+
+    let new_len = merged.len();
+    segments.insert(start, merged);
+
+    new_len - prev_len - removed_len
+}
+
+struct CoalescingGuard;
+
+impl CoalescingGuard {
+    fn enter() -> Self {
+        DEPTH.with(|it| it.set(it.get() + 1));
+
+        Self
+    }
+}
+
+impl Drop for CoalescingGuard {
+    fn drop(&mut self) {
+        let depth = DEPTH.with(|it| {
+            it.set(it.get() - 1);
+            it.get()
+        });
+
+        if depth == 0 {
+            flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SBTreeMap;
+    use crate::mem::write_coalescing::{is_coalescing_writes, merge, with_write_coalescing};
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable::grow(1).unwrap();
+
+        let mut buf = [0u8; 16];
+
+        with_write_coalescing(|| {
+            assert!(is_coalescing_writes());
+
+            stable::write(8, &[2; 8]);
+            stable::write(0, &[1; 8]);
+            stable::write(20, &[3; 4]);
+            stable::write(4, &[4; 8]);
+
+            with_write_coalescing(|| stable::write(16, &[5; 2]));
+
+            stable::read(0, &mut buf);
+            assert_eq!(buf, [1, 1, 1, 1, 4, 4, 4, 4, 4, 4, 4, 4, 2, 2, 2, 2]);
+
+            // big writes go directly, but after everything buffered before them
+            stable::write(14, &[6; 1000]);
+        });
+
+        assert!(!is_coalescing_writes());
+
+        let mut buf = [0u8; 24];
+        stable::read(0, &mut buf);
+        assert_eq!(
+            buf,
+            [1, 1, 1, 1, 4, 4, 4, 4, 4, 4, 4, 4, 2, 2, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6]
+        );
+    }
+
+    #[test]
+    fn merge_works_fine() {
+        let mut segments = BTreeMap::new();
+
+        assert_eq!(merge(&mut segments, 10, &[1; 4]), 4);
+        assert_eq!(merge(&mut segments, 20, &[2; 4]), 4);
+
+        // extends the segment it starts in
+        assert_eq!(merge(&mut segments, 12, &[3; 4]), 2);
+        assert_eq!(segments[&10], vec![1, 1, 3, 3, 3, 3]);
+
+        // starts before any segment, swallowing the next one and the head of another
+        assert_eq!(merge(&mut segments, 8, &[4; 14]), 6);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[&8], [vec![4; 14], vec![2; 2]].concat());
+
+        // fully inside
+        assert_eq!(merge(&mut segments, 9, &[5; 2]), 0);
+        assert_eq!(segments[&8][..4], [4, 5, 5, 4]);
+
+        // touches the end
+        assert_eq!(merge(&mut segments, 24, &[6; 2]), 2);
+        assert_eq!(segments[&8].len(), 18);
+    }
+
+    #[test]
+    fn btree_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMap::new();
+
+            with_write_coalescing(|| {
+                for i in 0..1000u64 {
+                    map.insert(i, i).unwrap();
+                }
+
+                for i in 0..500u64 {
+                    assert_eq!(map.remove(&i).unwrap(), i);
+                }
+            });
+
+            for i in 500..1000u64 {
+                assert_eq!(*map.get(&i).unwrap(), i);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
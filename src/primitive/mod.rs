@@ -2,18 +2,37 @@
 
 use candid::{Int, Nat, Principal};
 use serde_bytes::ByteBuf;
+use std::cmp::Ordering;
 use std::collections::{BTreeSet, HashSet};
+use std::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU128,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize,
+};
+use std::time::{Duration, SystemTime};
 use ic_ledger_types::Subaccount;
 
 /// [SBox] smart-pointer that allows storing dynamically-sized data to stable memory
 pub mod s_box;
 
+/// Lazily-initialized value, that is automatically persisted between canister upgrades
+pub mod s_cell;
+
+/// Copy-on-write smart-pointer, sharing the same data between snapshots until it is mutated
+pub mod s_cow;
+
+/// Reference-counted [SBox](s_box::SBox) analog, allowing to share the same data, and its weak
+/// counterpart
+pub mod s_rc;
+
 /// Immutable reference to fixed size data on stable memory
 pub mod s_ref;
 
 /// Mutable reference to fixed size data on stable memory
 pub mod s_ref_mut;
 
+/// Certified cell, holding a single value together with its root hash
+pub mod s_certified_cell;
+
 /// Anything that can be stored on stable memory should implement this trait.
 ///
 /// *None of methods of this trait should be called manually, unless you're implementing your own
@@ -182,28 +201,21 @@ impl StableType for char {}
 impl StableType for Principal {}
 impl StableType for Nat {}
 impl StableType for Int {}
-
-impl<const N: usize> StableType for [(); N] {}
-impl<const N: usize> StableType for [bool; N] {}
-impl<const N: usize> StableType for [u8; N] {}
-impl<const N: usize> StableType for [i8; N] {}
-impl<const N: usize> StableType for [u16; N] {}
-impl<const N: usize> StableType for [i16; N] {}
-impl<const N: usize> StableType for [u32; N] {}
-impl<const N: usize> StableType for [i32; N] {}
-impl<const N: usize> StableType for [u64; N] {}
-impl<const N: usize> StableType for [i64; N] {}
-impl<const N: usize> StableType for [u128; N] {}
-impl<const N: usize> StableType for [i128; N] {}
-impl<const N: usize> StableType for [usize; N] {}
-impl<const N: usize> StableType for [isize; N] {}
-impl<const N: usize> StableType for [f32; N] {}
-impl<const N: usize> StableType for [f64; N] {}
-impl<const N: usize> StableType for [char; N] {}
-impl<const N: usize> StableType for [String; N] {}
-impl<const N: usize> StableType for [Principal; N] {}
-impl<const N: usize> StableType for [Nat; N] {}
-impl<const N: usize> StableType for [Int; N] {}
+impl StableType for NonZeroI8 {}
+impl StableType for NonZeroU8 {}
+impl StableType for NonZeroI16 {}
+impl StableType for NonZeroU16 {}
+impl StableType for NonZeroI32 {}
+impl StableType for NonZeroU32 {}
+impl StableType for NonZeroI64 {}
+impl StableType for NonZeroU64 {}
+impl StableType for NonZeroI128 {}
+impl StableType for NonZeroU128 {}
+impl StableType for NonZeroIsize {}
+impl StableType for NonZeroUsize {}
+impl StableType for Duration {}
+impl StableType for SystemTime {}
+impl StableType for Ordering {}
 
 impl StableType for ByteBuf {}
 impl<T: StableType> StableType for Option<T> {
@@ -221,113 +233,69 @@ impl<T: StableType> StableType for Option<T> {
         }
     }
 }
-impl<const N: usize> StableType for [ByteBuf; N] {}
-
-impl<A: StableType> StableType for (A,) {
-    #[inline]
-    unsafe fn stable_drop_flag_on(&mut self) {
-        self.0.stable_drop_flag_on();
-    }
-
-    #[inline]
-    unsafe fn stable_drop_flag_off(&mut self) {
-        self.0.stable_drop_flag_off();
-    }
-}
-
-impl<A: StableType, B: StableType> StableType for (A, B) {
-    #[inline]
-    unsafe fn stable_drop_flag_on(&mut self) {
-        self.0.stable_drop_flag_on();
-        self.1.stable_drop_flag_on();
-    }
-
-    #[inline]
-    unsafe fn stable_drop_flag_off(&mut self) {
-        self.0.stable_drop_flag_off();
-        self.1.stable_drop_flag_off();
-    }
-}
 
-impl<A: StableType, B: StableType, C: StableType> StableType for (A, B, C) {
+impl<T: StableType, E: StableType> StableType for Result<T, E> {
     #[inline]
     unsafe fn stable_drop_flag_on(&mut self) {
-        self.0.stable_drop_flag_on();
-        self.1.stable_drop_flag_on();
-        self.2.stable_drop_flag_on();
+        match self {
+            Ok(it) => it.stable_drop_flag_on(),
+            Err(e) => e.stable_drop_flag_on(),
+        }
     }
 
     #[inline]
     unsafe fn stable_drop_flag_off(&mut self) {
-        self.0.stable_drop_flag_off();
-        self.1.stable_drop_flag_off();
-        self.2.stable_drop_flag_off();
+        match self {
+            Ok(it) => it.stable_drop_flag_off(),
+            Err(e) => e.stable_drop_flag_off(),
+        }
     }
 }
 
-impl<A: StableType, B: StableType, C: StableType, D: StableType> StableType for (A, B, C, D) {
+impl<T: StableType, const N: usize> StableType for [T; N] {
     #[inline]
     unsafe fn stable_drop_flag_on(&mut self) {
-        self.0.stable_drop_flag_on();
-        self.1.stable_drop_flag_on();
-        self.2.stable_drop_flag_on();
-        self.3.stable_drop_flag_on();
+        for it in self {
+            it.stable_drop_flag_on();
+        }
     }
 
     #[inline]
     unsafe fn stable_drop_flag_off(&mut self) {
-        self.0.stable_drop_flag_off();
-        self.1.stable_drop_flag_off();
-        self.2.stable_drop_flag_off();
-        self.3.stable_drop_flag_off();
+        for it in self {
+            it.stable_drop_flag_off();
+        }
     }
 }
 
-impl<A: StableType, B: StableType, C: StableType, D: StableType, E: StableType> StableType
-    for (A, B, C, D, E)
-{
-    #[inline]
-    unsafe fn stable_drop_flag_on(&mut self) {
-        self.0.stable_drop_flag_on();
-        self.1.stable_drop_flag_on();
-        self.2.stable_drop_flag_on();
-        self.3.stable_drop_flag_on();
-        self.4.stable_drop_flag_on();
-    }
+macro_rules! impl_for_tuple {
+    ($($t:ident $idx:tt),+) => {
+        impl<$($t: StableType),+> StableType for ($($t,)+) {
+            #[inline]
+            unsafe fn stable_drop_flag_on(&mut self) {
+                $(self.$idx.stable_drop_flag_on();)+
+            }
 
-    #[inline]
-    unsafe fn stable_drop_flag_off(&mut self) {
-        self.0.stable_drop_flag_off();
-        self.1.stable_drop_flag_off();
-        self.2.stable_drop_flag_off();
-        self.3.stable_drop_flag_off();
-        self.4.stable_drop_flag_off();
-    }
+            #[inline]
+            unsafe fn stable_drop_flag_off(&mut self) {
+                $(self.$idx.stable_drop_flag_off();)+
+            }
+        }
+    };
 }
 
-impl<A: StableType, B: StableType, C: StableType, D: StableType, E: StableType, F: StableType>
-    StableType for (A, B, C, D, E, F)
-{
-    #[inline]
-    unsafe fn stable_drop_flag_on(&mut self) {
-        self.0.stable_drop_flag_on();
-        self.1.stable_drop_flag_on();
-        self.2.stable_drop_flag_on();
-        self.3.stable_drop_flag_on();
-        self.4.stable_drop_flag_on();
-        self.5.stable_drop_flag_on();
-    }
-
-    #[inline]
-    unsafe fn stable_drop_flag_off(&mut self) {
-        self.0.stable_drop_flag_off();
-        self.1.stable_drop_flag_off();
-        self.2.stable_drop_flag_off();
-        self.3.stable_drop_flag_off();
-        self.4.stable_drop_flag_off();
-        self.5.stable_drop_flag_off();
-    }
-}
+impl_for_tuple!(A 0);
+impl_for_tuple!(A 0, B 1);
+impl_for_tuple!(A 0, B 1, C 2);
+impl_for_tuple!(A 0, B 1, C 2, D 3);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
 
 impl StableType for String {}
 impl StableType for Vec<u8> {}
@@ -398,4 +366,69 @@ impl StableType for BTreeSet<Principal> {}
 impl StableType for BTreeSet<Nat> {}
 impl StableType for BTreeSet<Int> {}
 impl StableType for BTreeSet<ByteBuf> {}
-impl StableType for Subaccount{}
\ No newline at end of file
+impl StableType for Subaccount{}
+
+/// Deep copy of a value, which may fail because of the lack of stable memory
+///
+/// Stable collections and smart-pointers can't implement [Clone], since copying them means
+/// allocating new stable memory. This trait is implemented for them instead: [TryClone::try_clone]
+/// copies all the data (including nested stable structures) into newly allocated stable memory,
+/// returning [OutOfMemory](crate::OutOfMemory) if there is not enough of it. In that case,
+/// everything allocated during the copying is released.
+///
+/// Any [Clone] type implements this trait automatically, so plain data can be stored inside
+/// cloneable stable structures without any additional effort.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::{stable_memory_init, SBox, TryClone};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut vec = SVec::new();
+/// vec.push(SBox::new(String::from("hello")).unwrap()).expect("Out of memory");
+///
+/// let copy = vec.try_clone().expect("Out of memory");
+///
+/// assert_eq!(copy.get(0).unwrap().as_str(), "hello");
+/// assert_ne!(copy.get(0).unwrap().as_ptr(), vec.get(0).unwrap().as_ptr());
+/// ```
+pub trait TryClone: Sized {
+    /// Returns a deep copy of this value
+    fn try_clone(&self) -> Result<Self, crate::OutOfMemory>;
+}
+
+impl<T: Clone> TryClone for T {
+    #[inline]
+    fn try_clone(&self) -> Result<Self, crate::OutOfMemory> {
+        Ok(self.clone())
+    }
+}
+
+/// Fallible analog of [FromIterator] for stable collections
+///
+/// Building a stable collection may fail at any element, because of the lack of stable memory.
+/// [TryFromIterator::try_from_iter] stops at the first such element and returns the collection
+/// built so far, along with [OutOfMemory](crate::OutOfMemory), so it can either be used as is, or
+/// dropped, releasing the memory. The element, which didn't fit, and the rest of the iterator are
+/// dropped.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::{SBTreeMap, SVec};
+/// # use ic_stable_memory::{stable_memory_init, TryFromIterator};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let vec: SVec<u64> = SVec::try_from_iter(0..100).expect("Out of memory");
+/// assert_eq!(vec.len(), 100);
+///
+/// let map = SBTreeMap::try_from_iter(vec.iter().map(|it| (*it, *it * 2)))
+///     .expect("Out of memory");
+/// assert_eq!(*map.get(&10).unwrap(), 20);
+/// ```
+pub trait TryFromIterator<T>: Sized {
+    /// Creates a new collection, inserting all elements of the iterator into it
+    fn try_from_iter<I: IntoIterator<Item = T>>(
+        iter: I,
+    ) -> Result<Self, (Self, crate::OutOfMemory)>;
+}
@@ -1,18 +1,195 @@
 use crate::encoding::{AsDynSizeBytes, AsFixedSizeBytes};
 use crate::mem::s_slice::SSlice;
-use crate::primitive::StableType;
+use crate::primitive::{StableType, TryClone};
+use crate::utils::buf_pool::PooledBuf;
 use crate::utils::certification::{AsHashTree, AsHashableBytes, HashTree};
 use crate::{allocate, deallocate, reallocate, OutOfMemory};
 use candid::types::{Serializer, Type, TypeId};
 use candid::CandidType;
 use serde::{Deserialize, Deserializer};
+use std::any::Any;
 use std::borrow::Borrow;
-use std::cell::UnsafeCell;
+use std::cell::{RefCell, UnsafeCell};
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::hash::{Hash, Hasher};
 use std::mem::ManuallyDrop;
 use std::ops::Deref;
+use std::rc::Rc;
+
+type ValueTransform = Rc<dyn Fn(&[u8]) -> Vec<u8>>;
+
+thread_local! {
+    static DECODE_CACHE: RefCell<HashMap<u64, Rc<dyn Any>>> = RefCell::new(HashMap::new());
+    static VALUE_TRANSFORM: RefCell<Option<(ValueTransform, ValueTransform)>> = RefCell::default();
+}
+
+/// Removes all values, decoded by [SBox::cached], from the heap cache
+///
+/// The cache is never cleared automatically, so call this function at the end of each message
+/// (or whenever heap memory has to be reclaimed), to keep it from growing indefinitely.
+#[inline]
+pub fn clear_decode_cache() {
+    DECODE_CACHE.with(|it| it.borrow_mut().clear());
+}
+
+/// Sets a pair of functions, transforming [SBox] payloads on their way to and from stable memory
+///
+/// `encode` is applied to the encoded value right before it is written to stable memory and `decode`
+/// is applied to the stored bytes right before the value is decoded, so the pair can be used to
+/// encrypt values at rest (for example, with a key derived via vetKeys). `decode` receives exactly
+/// the bytes, returned by `encode`.
+///
+/// The transform lives on heap and is not persisted between upgrades - set it in both `#[init]` and
+/// `#[post_upgrade]`, before any [SBox] is accessed. Values, stored with one transform, can only be
+/// read (and stable-dropped) with the same transform, so never change it, while such values exist.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{set_value_transform, stable_memory_init, SBox};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// // use a real cipher instead
+/// let xor = |buf: &[u8]| buf.iter().map(|it| it ^ 0b1010_1010).collect::<Vec<_>>();
+///
+/// set_value_transform(xor, xor);
+///
+/// let secret = SBox::new(String::from("secret")).expect("Out of memory");
+/// assert_eq!(&*secret, "secret");
+/// ```
+pub fn set_value_transform<E, D>(encode: E, decode: D)
+where
+    E: Fn(&[u8]) -> Vec<u8> + 'static,
+    D: Fn(&[u8]) -> Vec<u8> + 'static,
+{
+    clear_decode_cache();
+    VALUE_TRANSFORM.with(|it| *it.borrow_mut() = Some((Rc::new(encode), Rc::new(decode))));
+}
+
+/// Removes the transform, set by [set_value_transform]
+pub fn reset_value_transform() {
+    clear_decode_cache();
+    VALUE_TRANSFORM.with(|it| *it.borrow_mut() = None);
+}
+
+#[inline]
+fn invalidate_decode_cache(ptr: u64) {
+    DECODE_CACHE.with(|it| it.borrow_mut().remove(&ptr));
+}
+
+/// Indicates that the payload of an [SBox] doesn't match its checksum - the memory block was
+/// overwritten by something else.
+///
+/// Only returned with `sbox_checksums` feature enabled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CorruptedPayload {
+    /// Pointer to the corrupted [SBox]
+    pub ptr: u64,
+}
+
+// with sbox_checksums feature enabled the payload is prefixed with its length and CRC32 checksum
+#[cfg(feature = "sbox_checksums")]
+const HEADER_SIZE: usize = u32::SIZE * 2;
+
+#[cfg(not(feature = "sbox_checksums"))]
+const HEADER_SIZE: usize = 0;
+
+// fills the header, reserved in front of the payload
+#[cfg(feature = "sbox_checksums")]
+fn add_checksum(buf: &mut [u8]) {
+    let len = (buf.len() - HEADER_SIZE) as u32;
+    let checksum = crc32fast::hash(&buf[HEADER_SIZE..]);
+
+    buf[0..u32::SIZE].copy_from_slice(&len.to_le_bytes());
+    buf[u32::SIZE..HEADER_SIZE].copy_from_slice(&checksum.to_le_bytes());
+}
+
+#[cfg(not(feature = "sbox_checksums"))]
+#[inline]
+fn add_checksum(_buf: &mut [u8]) {}
+
+#[cfg(feature = "sbox_checksums")]
+fn read_checked(slice: &SSlice) -> Result<PooledBuf, CorruptedPayload> {
+    let corrupted = CorruptedPayload {
+        ptr: slice.as_ptr(),
+    };
+
+    let mut header = [0u8; HEADER_SIZE];
+    unsafe { crate::mem::read_bytes(slice.offset(0), &mut header) };
+
+    let len = u32::from_fixed_size_bytes(&header[0..u32::SIZE]) as u64;
+    let checksum = u32::from_fixed_size_bytes(&header[u32::SIZE..HEADER_SIZE]);
+
+    if len > slice.get_size_bytes() - HEADER_SIZE as u64 {
+        return Err(corrupted);
+    }
+
+    let mut buf = PooledBuf::zeroed(len as usize);
+    unsafe { crate::mem::read_bytes(slice.offset(HEADER_SIZE as u64), &mut buf) };
+
+    if crc32fast::hash(&buf) != checksum {
+        return Err(corrupted);
+    }
+
+    Ok(buf)
+}
+
+#[cfg(not(feature = "sbox_checksums"))]
+#[inline]
+fn read_checked(slice: &SSlice) -> Result<PooledBuf, CorruptedPayload> {
+    let mut buf = PooledBuf::zeroed(slice.get_size_bytes() as usize);
+    unsafe { crate::mem::read_bytes(slice.offset(0), &mut buf) };
+
+    Ok(buf)
+}
+
+// with a value transform set, the transformed payload is prefixed with its length, since the
+// memory block may contain some trailing bytes
+fn encode_payload<T: AsDynSizeBytes>(value: &T) -> PooledBuf {
+    let encode = VALUE_TRANSFORM.with(|it| it.borrow().as_ref().map(|(e, _)| e.clone()));
+
+    let mut buf = PooledBuf::zeroed(HEADER_SIZE);
+
+    match encode {
+        Some(encode) => {
+            let mut payload = PooledBuf::new();
+            value.as_dyn_size_bytes_into(&mut payload);
+
+            let transformed = encode(&payload);
+
+            buf.extend((transformed.len() as u32).to_le_bytes());
+            buf.extend(transformed);
+        }
+        None => value.as_dyn_size_bytes_into(&mut buf),
+    }
+
+    add_checksum(&mut buf);
+
+    buf
+}
+
+fn read_payload(slice: &SSlice) -> Result<PooledBuf, CorruptedPayload> {
+    let buf = read_checked(slice)?;
+    let decode = VALUE_TRANSFORM.with(|it| it.borrow().as_ref().map(|(_, d)| d.clone()));
+
+    match decode {
+        Some(decode) => {
+            let len = u32::from_fixed_size_bytes(&buf[0..u32::SIZE]) as usize;
+
+            Ok(PooledBuf::from(decode(&buf[u32::SIZE..(u32::SIZE + len)])))
+        }
+        None => Ok(buf),
+    }
+}
+
+#[inline]
+fn expect_payload(slice: &SSlice) -> PooledBuf {
+    match read_payload(slice) {
+        Ok(it) => it,
+        Err(e) => panic!("SBox payload at {} is corrupted", e.ptr),
+    }
+}
 
 /// Smart-pointer that allows storing any dynamic sized data on stable memory.
 ///
@@ -58,7 +235,7 @@ impl<T: AsDynSizeBytes + StableType> SBox<T> {
     /// Returns `Err` and the data, if the canister is `OutOfMemory`.
     #[inline]
     pub fn new(mut it: T) -> Result<Self, T> {
-        let buf = it.as_dyn_size_bytes();
+        let buf = encode_payload(&it);
         if let Ok(slice) = unsafe { allocate(buf.len() as u64) } {
             unsafe {
                 crate::mem::write_bytes(slice.offset(0), &buf);
@@ -132,6 +309,44 @@ impl<T: AsDynSizeBytes + StableType> SBox<T> {
         }
     }
 
+    /// Returns a copy of the serialized underlying data, without deserializing it.
+    ///
+    /// The returned bytes are the whole content of the underlying [SSlice]. Since memory blocks
+    /// are padded and are never shrunk, there may be some trailing bytes after the encoded value,
+    /// which are ignored by [AsDynSizeBytes::from_dyn_size_bytes]. With `sbox_checksums` feature
+    /// enabled, the returned bytes are exactly the encoded value.
+    ///
+    /// # Panics
+    /// With `sbox_checksums` feature enabled, panics if the payload doesn't match its checksum.
+    ///
+    /// See also [SBox::with_bytes].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{SBox, stable_memory_init, AsDynSizeBytes};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let b = SBox::new(String::from("blob")).expect("Out of memory");
+    ///
+    /// let bytes = b.as_bytes();
+    ///
+    /// assert_eq!(String::from_dyn_size_bytes(&bytes), "blob");
+    /// ```
+    #[inline]
+    pub fn as_bytes(&self) -> Vec<u8> {
+        expect_payload(self.slice.as_ref().unwrap()).into_vec()
+    }
+
+    /// Provides immutable access to the serialized underlying data, without deserializing it, by
+    /// accepting a lambda function.
+    ///
+    /// Useful when the data should just be forwarded somewhere as is. Read [SBox::as_bytes] for
+    /// details on the content of the buffer.
+    #[inline]
+    pub fn with_bytes<R, F: FnOnce(&[u8]) -> R>(&self, func: F) -> R {
+        func(&expect_payload(self.slice.as_ref().unwrap()))
+    }
+
     /// Provides mutable access to the underlying data, by accepting a lambda function.
     ///
     /// Returns [OutOfMemory] error if it was impossible to reallocate the underlying [SSlice] to
@@ -168,7 +383,156 @@ impl<T: AsDynSizeBytes + StableType> SBox<T> {
         }
     }
 
+    /// Transforms the underlying data into a value of another type, by accepting a lambda function,
+    /// reusing the underlying [SSlice] of stable memory.
+    ///
+    /// The [SSlice] is only reallocated, if the new value does not fit into it. If the canister
+    /// is out of stable memory, the [SSlice] is released and `Err` with the new value is returned.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{SBox, stable_memory_init};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let b = SBox::new(10u64).expect("Out of memory");
+    ///
+    /// let b = b.try_map(|it| format!("value: {}", it)).expect("Out of memory");
+    ///
+    /// assert_eq!(&*b, "value: 10");
+    /// ```
+    pub fn try_map<U: AsDynSizeBytes + StableType, F: FnOnce(T) -> U>(
+        mut self,
+        func: F,
+    ) -> Result<SBox<U>, U> {
+        unsafe {
+            self.lazy_read(true);
+            self.stable_drop_flag_off();
+        }
+
+        let it = self.inner.get_mut().take().unwrap();
+        let mut slice = self.slice.take().unwrap();
+        invalidate_decode_cache(slice.as_ptr());
+
+        let mut res = func(it);
+        let buf = encode_payload(&res);
+
+        if slice.get_size_bytes() < buf.len() as u64 {
+            match unsafe { reallocate(slice, buf.len() as u64) } {
+                Ok(s) => {
+                    slice = s;
+                }
+                Err(_) => {
+                    deallocate(slice);
+                    return Err(res);
+                }
+            }
+        }
+
+        unsafe {
+            crate::mem::write_bytes(slice.offset(0), &buf);
+            res.stable_drop_flag_off();
+        }
+
+        Ok(SBox {
+            slice: Some(slice),
+            inner: UnsafeCell::new(Some(res)),
+            stable_drop_flag: true,
+        })
+    }
+
+    /// Returns the underlying data, decoding it at most once until the next [clear_decode_cache] call.
+    ///
+    /// Dereferencing an [SBox] decodes the data each time a new [SBox] is read from a stable
+    /// collection, even if it points to the same memory block. This method instead keeps decoded
+    /// values in a heap cache keyed by the pointer, so hot values are decoded only once. The cache
+    /// is opt-in - values only get there via this method. Mutating or stable-dropping an [SBox]
+    /// removes its value from the cache.
+    ///
+    /// Values, returned by this method, are detached from stable memory - they won't reflect any
+    /// changes made after they were returned.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::{clear_decode_cache, SBox, stable_memory_init};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::new();
+    /// vec.push(SBox::new(String::from("hot value")).unwrap()).expect("Out of memory");
+    ///
+    /// // decoded only once
+    /// for _ in 0..10 {
+    ///     let it = vec.get(0).unwrap().cached();
+    ///     assert_eq!(it.as_str(), "hot value");
+    /// }
+    ///
+    /// clear_decode_cache();
+    /// ```
+    pub fn cached(&self) -> Rc<T>
+    where
+        T: 'static,
+    {
+        let ptr = self.as_ptr();
+
+        let hit = DECODE_CACHE.with(|it| it.borrow().get(&ptr).cloned());
+        if let Some(Ok(it)) = hit.map(|it| it.downcast::<T>()) {
+            return it;
+        }
+
+        let buf = expect_payload(self.slice.as_ref().unwrap());
+
+        let mut inner = T::from_dyn_size_bytes(&buf);
+        unsafe { inner.stable_drop_flag_off() };
+
+        let res = Rc::new(inner);
+        DECODE_CACHE.with(|it| it.borrow_mut().insert(ptr, res.clone()));
+
+        res
+    }
+
+    /// Checks the payload against its checksum, without decoding it
+    ///
+    /// Only available with `sbox_checksums` feature enabled. With this feature, the checksum is
+    /// also verified each time the payload is read from stable memory - dereferencing an [SBox]
+    /// with a corrupted payload panics. Use [SBox::try_get] to handle such cases.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{stable_memory_init, SBox};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let b = SBox::new(String::from("checked")).expect("Out of memory");
+    ///
+    /// assert!(b.verify().is_ok());
+    /// assert_eq!(b.try_get().unwrap(), "checked");
+    /// ```
+    #[cfg(feature = "sbox_checksums")]
+    #[inline]
+    pub fn verify(&self) -> Result<(), CorruptedPayload> {
+        read_checked(self.slice.as_ref().unwrap()).map(|_| ())
+    }
+
+    /// Returns the underlying data, or [CorruptedPayload] error, if the payload doesn't match its
+    /// checksum
+    ///
+    /// Only available with `sbox_checksums` feature enabled. See [SBox::verify].
+    #[cfg(feature = "sbox_checksums")]
+    #[inline]
+    pub fn try_get(&self) -> Result<&T, CorruptedPayload> {
+        unsafe {
+            self.try_lazy_read(false)?;
+
+            Ok((*self.inner.get()).as_ref().unwrap())
+        }
+    }
+
     unsafe fn lazy_read(&self, drop_flag: bool) {
+        if let Err(e) = self.try_lazy_read(drop_flag) {
+            panic!("SBox payload at {} is corrupted", e.ptr);
+        }
+    }
+
+    unsafe fn try_lazy_read(&self, drop_flag: bool) -> Result<(), CorruptedPayload> {
         if let Some(it) = (*self.inner.get()).as_mut() {
             if drop_flag {
                 it.stable_drop_flag_on();
@@ -176,12 +540,10 @@ impl<T: AsDynSizeBytes + StableType> SBox<T> {
                 it.stable_drop_flag_off();
             }
 
-            return;
+            return Ok(());
         }
 
-        let slice = self.slice.as_ref().unwrap();
-        let mut buf = vec![0u8; slice.get_size_bytes() as usize];
-        unsafe { crate::mem::read_bytes(slice.offset(0), &mut buf) };
+        let buf = read_payload(self.slice.as_ref().unwrap())?;
 
         let mut inner = T::from_dyn_size_bytes(&buf);
         if drop_flag {
@@ -191,11 +553,15 @@ impl<T: AsDynSizeBytes + StableType> SBox<T> {
         }
 
         *self.inner.get() = Some(inner);
+
+        Ok(())
     }
 
     fn repersist(&mut self) -> Result<(), OutOfMemory> {
         let mut slice = self.slice.take().unwrap();
-        let buf = self.inner.get_mut().as_ref().unwrap().as_dyn_size_bytes();
+        invalidate_decode_cache(slice.as_ptr());
+
+        let buf = encode_payload(self.inner.get_mut().as_ref().unwrap());
 
         unsafe { self.inner.get_mut().stable_drop_flag_off() };
 
@@ -254,7 +620,10 @@ impl<T: AsDynSizeBytes + StableType> StableType for SBox<T> {
 
     #[inline]
     unsafe fn stable_drop(&mut self) {
-        deallocate(self.slice.take().unwrap());
+        let slice = self.slice.take().unwrap();
+        invalidate_decode_cache(slice.as_ptr());
+
+        deallocate(slice);
     }
 }
 
@@ -269,6 +638,15 @@ impl<T: AsDynSizeBytes + StableType> Drop for SBox<T> {
     }
 }
 
+impl<T: TryClone + AsDynSizeBytes + StableType> TryClone for SBox<T> {
+    /// Copies the underlying data into a new [SBox]
+    fn try_clone(&self) -> Result<Self, OutOfMemory> {
+        let it = self.deref().try_clone()?;
+
+        SBox::new(it).map_err(|_| OutOfMemory)
+    }
+}
+
 impl<T: AsHashableBytes + AsDynSizeBytes + StableType> AsHashableBytes for SBox<T> {
     #[inline]
     fn as_hashable_bytes(&self) -> Vec<u8> {
@@ -400,6 +778,12 @@ impl<T: AsDynSizeBytes + StableType> Deref for SBox<T> {
 
     #[inline]
     fn deref(&self) -> &Self::Target {
+        // the value may be already cached, so the block is checked even if it is not read
+        #[cfg(debug_assertions)]
+        if let Some(slice) = &self.slice {
+            debug_assert!(slice.is_alive(), "Use after deallocate: {:?}", slice);
+        }
+
         unsafe {
             self.lazy_read(false);
 
@@ -411,7 +795,10 @@ impl<T: AsDynSizeBytes + StableType> Deref for SBox<T> {
 #[cfg(test)]
 mod tests {
     use crate::collections::SVec;
-    use crate::primitive::s_box::SBox;
+    use crate::encoding::AsDynSizeBytes;
+    use crate::primitive::s_box::{
+        clear_decode_cache, reset_value_transform, set_value_transform, SBox,
+    };
     use crate::{
         _debug_validate_allocator, get_allocated_size, retrieve_custom_data, stable,
         stable_memory_init, store_custom_data,
@@ -419,6 +806,7 @@ mod tests {
     use candid::encode_one;
     use std::cmp::Ordering;
     use std::ops::Deref;
+    use std::rc::Rc;
 
     #[test]
     fn sboxes_work_fine() {
@@ -529,4 +917,165 @@ mod tests {
             assert_eq!(bytes.len(), 17);
         }
     }
+
+    #[test]
+    fn bytes_access_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut b = SBox::new(String::from("short")).unwrap();
+            b.with(|it| *it = String::from("a bit longer string"))
+                .unwrap();
+            b.with(|it| *it = String::from("tiny")).unwrap();
+
+            let bytes = b.as_bytes();
+            assert_eq!(String::from_dyn_size_bytes(&bytes), "tiny");
+
+            let len = b.with_bytes(|it| it.len());
+            assert_eq!(len, bytes.len());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn try_map_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let b = SBox::new(10u64).unwrap();
+            let ptr = b.as_ptr();
+
+            let b = b.try_map(|it| it as u8).unwrap();
+            assert_eq!(*b, 10);
+            assert_eq!(b.as_ptr(), ptr);
+
+            let b = b
+                .try_map(|it| format!("a very long string with {} inside", it))
+                .unwrap();
+            assert_eq!(&*b, "a very long string with 10 inside");
+
+            let mut vec = SVec::new();
+            vec.push(b).unwrap();
+            let b = vec.pop().unwrap();
+
+            let b = b.try_map(|it| SBox::new(it.len() as u64).unwrap()).unwrap();
+            assert_eq!(**b, 33);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn decode_cache_works_fine() {
+        stable::clear();
+        stable_memory_init();
+        clear_decode_cache();
+
+        {
+            let mut vec = SVec::new();
+            vec.push(SBox::new(String::from("first")).unwrap()).unwrap();
+
+            let a = vec.get(0).unwrap().cached();
+            let b = vec.get(0).unwrap().cached();
+            assert!(Rc::ptr_eq(&a, &b));
+            assert_eq!(a.as_str(), "first");
+
+            vec.get_mut(0)
+                .unwrap()
+                .with(|it| *it = String::from("second"))
+                .unwrap();
+
+            let c = vec.get(0).unwrap().cached();
+            assert!(!Rc::ptr_eq(&a, &c));
+            assert_eq!(c.as_str(), "second");
+
+            clear_decode_cache();
+
+            let d = vec.get(0).unwrap().cached();
+            assert!(!Rc::ptr_eq(&c, &d));
+            assert_eq!(d.as_str(), "second");
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn value_transform_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let xor = |buf: &[u8]| buf.iter().map(|it| it ^ 0xff).collect::<Vec<_>>();
+            set_value_transform(xor, xor);
+
+            let mut b = SBox::new(String::from("secret")).unwrap();
+            b.with(|it| it.push_str(" message")).unwrap();
+
+            let slice = b.slice.unwrap();
+            let mut raw = vec![0u8; slice.get_size_bytes() as usize];
+            unsafe { crate::mem::read_bytes(slice.offset(0), &mut raw) };
+            assert!(!raw.windows(6).any(|it| it == b"secret"));
+
+            let copy = unsafe { SBox::<String>::from_ptr(b.as_ptr()) };
+            assert_eq!(&*copy, "secret message");
+            assert_eq!(String::from_dyn_size_bytes(&b.as_bytes()), "secret message");
+
+            let mut vec = SVec::new();
+            vec.push(b).unwrap();
+            assert_eq!(vec.get(0).unwrap().cached().as_str(), "secret message");
+
+            reset_value_transform();
+            vec.push(SBox::new(String::from("plain")).unwrap()).unwrap();
+            assert_eq!(&*vec.pop().unwrap(), "plain");
+
+            // the first value can only be read (and dropped) with the same transform
+            set_value_transform(xor, xor);
+            assert_eq!(&**vec.get(0).unwrap(), "secret message");
+        }
+
+        reset_value_transform();
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[cfg(feature = "sbox_checksums")]
+    #[test]
+    fn checksums_work_fine() {
+        use crate::primitive::s_box::CorruptedPayload;
+
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let b = SBox::new(String::from("important data")).unwrap();
+            assert!(b.verify().is_ok());
+
+            // flip a byte of the payload behind the box's back
+            let offset = b.slice.as_ref().unwrap().offset(10);
+            let mut byte = [0u8; 1];
+            unsafe { crate::mem::read_bytes(offset, &mut byte) };
+            unsafe { crate::mem::write_bytes(offset, &[!byte[0]]) };
+
+            let copy = unsafe { SBox::<String>::from_ptr(b.as_ptr()) };
+            let err = CorruptedPayload { ptr: b.as_ptr() };
+
+            assert_eq!(copy.verify(), Err(err));
+            assert_eq!(copy.try_get(), Err(err));
+
+            unsafe { crate::mem::write_bytes(offset, &byte) };
+
+            assert!(copy.verify().is_ok());
+            assert_eq!(copy.try_get().unwrap(), "important data");
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 }
@@ -0,0 +1,229 @@
+use crate::encoding::AsDynSizeBytes;
+use crate::primitive::s_box::SBox;
+use crate::primitive::StableType;
+use crate::{retrieve_custom_data, store_custom_data, OutOfMemory};
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+type PersistHook = Box<dyn Fn() -> Result<(), OutOfMemory>>;
+
+thread_local! {
+    static PERSIST_HOOKS: RefCell<Vec<PersistHook>> = RefCell::default();
+}
+
+/// Lazily-initialized value, that is automatically persisted between canister upgrades
+///
+/// Meant to be stored in a `thread_local!` static variable - use [stable_cell!](crate::stable_cell)
+/// macro to declare one. Replaces the usual `thread_local! + Option + store_custom_data` ceremony.
+///
+/// On first access, the value is restored via [retrieve_custom_data] by its index. If there is no
+/// such value (e.g. it is the first time this canister runs), it is created with the provided
+/// initializer function. During [stable_memory_pre_upgrade](crate::stable_memory_pre_upgrade) every
+/// [SCell], that was accessed since the last upgrade, puts its value into an [SBox] and persists it
+/// via [store_custom_data] by the same index.
+///
+/// Indices are shared with [store_custom_data], so make sure they do not collide with each other.
+///
+/// `T` should implement both [StableType] and [AsDynSizeBytes], just like for [SBox].
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::{stable_cell, stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// stable_cell! {
+///     static LOG: SVec<u64> = 1 => SVec::new();
+/// }
+///
+/// LOG.with(|it| it.with_mut(|log| log.push(10).expect("Out of memory")));
+///
+/// // no need to persist the log manually
+/// stable_memory_pre_upgrade().expect("Out of memory");
+/// stable_memory_post_upgrade();
+///
+/// LOG.with(|it| it.with(|log| assert_eq!(*log.get(0).unwrap(), 10)));
+/// ```
+pub struct SCell<T: StableType + AsDynSizeBytes + 'static> {
+    idx: usize,
+    init: fn() -> T,
+    inner: Rc<RefCell<Option<T>>>,
+}
+
+impl<T: StableType + AsDynSizeBytes + 'static> SCell<T> {
+    /// Creates a new empty [SCell], bound to the provided custom data index
+    ///
+    /// Does not touch stable memory until the first access.
+    #[inline]
+    pub fn new(idx: usize, init: fn() -> T) -> Self {
+        Self {
+            idx,
+            init,
+            inner: Rc::default(),
+        }
+    }
+
+    /// Allows reading the value, by accepting a lambda function
+    ///
+    /// Restores or initializes the value, if it was not accessed yet.
+    ///
+    /// # Panics
+    /// Panics if there is no initialized stable memory allocator, or if called recursively.
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, func: F) -> R {
+        self.lazy_init();
+
+        func(self.inner.borrow().as_ref().unwrap())
+    }
+
+    /// Allows mutation of the value, by accepting a lambda function
+    ///
+    /// Restores or initializes the value, if it was not accessed yet.
+    ///
+    /// # Panics
+    /// Panics if there is no initialized stable memory allocator, or if called recursively.
+    pub fn with_mut<R, F: FnOnce(&mut T) -> R>(&self, func: F) -> R {
+        self.lazy_init();
+
+        func(self.inner.borrow_mut().as_mut().unwrap())
+    }
+
+    /// Returns [true] if the value was already restored or initialized
+    #[inline]
+    pub fn is_initialized(&self) -> bool {
+        self.inner.borrow().is_some()
+    }
+
+    fn lazy_init(&self) {
+        if self.is_initialized() {
+            return;
+        }
+
+        let it = match retrieve_custom_data::<T>(self.idx) {
+            Some(b) => b.into_inner(),
+            None => (self.init)(),
+        };
+
+        *self.inner.borrow_mut() = Some(it);
+
+        let idx = self.idx;
+        let inner = Rc::downgrade(&self.inner);
+
+        PERSIST_HOOKS.with(|it| it.borrow_mut().push(Box::new(move || persist(idx, &inner))));
+    }
+}
+
+fn persist<T: StableType + AsDynSizeBytes>(
+    idx: usize,
+    inner: &Weak<RefCell<Option<T>>>,
+) -> Result<(), OutOfMemory> {
+    // the cell is already dropped, together with its value
+    let inner = match inner.upgrade() {
+        Some(it) => it,
+        None => return Ok(()),
+    };
+
+    let it = match inner.borrow_mut().take() {
+        Some(it) => it,
+        None => return Ok(()),
+    };
+
+    match SBox::new(it) {
+        Ok(b) => {
+            store_custom_data(idx, b);
+
+            Ok(())
+        }
+        Err(it) => {
+            *inner.borrow_mut() = Some(it);
+
+            Err(OutOfMemory)
+        }
+    }
+}
+
+// each cell is persisted only once - after that it is empty again and will be restored on next access
+pub(crate) fn persist_cells() -> Result<(), OutOfMemory> {
+    let hooks = PERSIST_HOOKS.with(|it| it.take());
+
+    for (i, hook) in hooks.iter().enumerate() {
+        if let Err(e) = hook() {
+            PERSIST_HOOKS.with(|it| it.borrow_mut().extend(hooks.into_iter().skip(i)));
+
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Declares `thread_local!` static [SCell]-s
+///
+/// Each declaration looks like `static NAME: Type = index => initializer;`, where `index` is a
+/// custom data index (see [store_custom_data]) and `initializer` is an expression, that creates the
+/// initial value, when there is nothing to restore yet.
+///
+/// See [SCell] for an example.
+#[macro_export]
+macro_rules! stable_cell {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $idx:expr => $init:expr;)*) => {
+        thread_local! {
+            $($(#[$attr])* $vis static $name: $crate::SCell<$t> = $crate::SCell::new($idx, || $init);)*
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SVec;
+    use crate::primitive::s_cell::SCell;
+    use crate::utils::mem_context::stable;
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, stable_memory_init,
+        stable_memory_post_upgrade, stable_memory_pre_upgrade,
+    };
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let vec = SCell::new(1, SVec::<u64>::new);
+            let string = SCell::new(2, || String::from("initial"));
+            let untouched = SCell::new(3, || 10u64);
+
+            assert!(!vec.is_initialized());
+            vec.with_mut(|it| {
+                for i in 0..100 {
+                    it.push(i).unwrap();
+                }
+            });
+            string.with_mut(|it| it.push_str(" changed"));
+            assert!(vec.is_initialized());
+
+            stable_memory_pre_upgrade().unwrap();
+            assert!(!vec.is_initialized());
+            assert!(!string.is_initialized());
+
+            stable_memory_post_upgrade();
+
+            vec.with(|it| {
+                assert_eq!(it.len(), 100);
+                for i in 0..100 {
+                    assert_eq!(*it.get(i).unwrap(), i);
+                }
+            });
+            string.with(|it| assert_eq!(it, "initial changed"));
+
+            stable_memory_pre_upgrade().unwrap();
+            stable_memory_post_upgrade();
+
+            assert_eq!(string.with(|it| it.clone()), "initial changed");
+            assert_eq!(vec.with(|it| it.len()), 100);
+            assert_eq!(untouched.with(|it| *it), 10);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::StableType;
+use crate::utils::certification::{AsHashTree, Hash, HashTree};
+use std::fmt::{Debug, Formatter};
+use std::ops::Deref;
+
+/// Certified cell, holding a single value together with its root hash
+///
+/// Useful for certifying single values, like "the latest exchange rate" or "canister metadata",
+/// without spinning up a whole [SCertifiedBTreeMap](crate::collections::SCertifiedBTreeMap).
+///
+/// `T` has to implement [StableType], [AsFixedSizeBytes] and [AsHashTree]. For dynamically sized
+/// values, use [SBox](crate::SBox). The root hash is recalculated eagerly, on each modification,
+/// so reading it (for example, in order to set the certified data) is cheap. [SCertifiedCell] also
+/// implements [AsHashTree], so you can put it inside other certified data structures.
+///
+/// You can access the underlying value by dereferencing it, for immutable access. For mutable access
+/// use [SCertifiedCell::with] method.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::{leaf, leaf_hash, stable_memory_init, AsHashTree, SBox};
+/// # use ic_stable_memory::primitive::s_certified_cell::SCertifiedCell;
+/// # use ic_stable_memory::utils::certification::{Hash, HashTree};
+/// # use ic_stable_memory::derive::{CandidAsDynSizeBytes, StableType};
+/// # use candid::{CandidType, Deserialize};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// #[derive(CandidType, Deserialize, CandidAsDynSizeBytes, StableType, Debug)]
+/// struct ExchangeRate(u64);
+///
+/// impl AsHashTree for ExchangeRate {
+///     fn root_hash(&self) -> Hash {
+///         leaf_hash(&self.0.to_le_bytes())
+///     }
+///
+///     fn hash_tree(&self) -> HashTree {
+///         leaf(self.0.to_le_bytes().to_vec())
+///     }
+/// }
+///
+/// let rate = SBox::new(ExchangeRate(100)).expect("Out of memory");
+/// let mut cell = SCertifiedCell::new(rate);
+///
+/// cell.with(|it| it.with(|rate| rate.0 = 110)).expect("Out of memory");
+///
+/// assert_eq!(cell.witness().reconstruct(), cell.root_hash());
+/// ```
+pub struct SCertifiedCell<T: StableType + AsFixedSizeBytes + AsHashTree> {
+    value: T,
+    root_hash: Hash,
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree> SCertifiedCell<T> {
+    /// Creates a new [SCertifiedCell], calculating the root hash of the value
+    ///
+    /// Does not allocate any stable memory by itself.
+    #[inline]
+    pub fn new(value: T) -> Self {
+        let root_hash = value.root_hash();
+
+        Self { value, root_hash }
+    }
+
+    /// Replaces the value, returning the previous one and recalculating the root hash
+    #[inline]
+    pub fn set(&mut self, value: T) -> T {
+        let prev = std::mem::replace(&mut self.value, value);
+        self.root_hash = self.value.root_hash();
+
+        prev
+    }
+
+    /// Provides mutable access to the value, by accepting a lambda function
+    ///
+    /// Recalculates the root hash after the lambda is executed.
+    #[inline]
+    pub fn with<R, F: FnOnce(&mut T) -> R>(&mut self, func: F) -> R {
+        let res = func(&mut self.value);
+        self.root_hash = self.value.root_hash();
+
+        res
+    }
+
+    /// Returns a [HashTree] witness of the value, which is also the full [HashTree] of this cell
+    #[inline]
+    pub fn witness(&self) -> HashTree {
+        self.value.hash_tree()
+    }
+
+    /// Returns the underlying value
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree> AsHashTree for SCertifiedCell<T> {
+    #[inline]
+    fn root_hash(&self) -> Hash {
+        self.root_hash
+    }
+
+    #[inline]
+    fn hash_tree(&self) -> HashTree {
+        self.witness()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree> Deref for SCertifiedCell<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree> AsFixedSizeBytes for SCertifiedCell<T> {
+    const SIZE: usize = T::SIZE + Hash::SIZE;
+    type Buf = Vec<u8>;
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.value.as_fixed_size_bytes(&mut buf[0..T::SIZE]);
+        self.root_hash
+            .as_fixed_size_bytes(&mut buf[T::SIZE..(T::SIZE + Hash::SIZE)]);
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let value = T::from_fixed_size_bytes(&buf[0..T::SIZE]);
+        let root_hash = Hash::from_fixed_size_bytes(&buf[T::SIZE..(T::SIZE + Hash::SIZE)]);
+
+        Self { value, root_hash }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree> StableType for SCertifiedCell<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.value.stable_drop_flag_on();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.value.stable_drop_flag_off();
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.value.should_stable_drop()
+    }
+
+    #[inline]
+    unsafe fn stable_drop(&mut self) {
+        self.value.stable_drop();
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + AsHashTree + Debug> Debug for SCertifiedCell<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SVec;
+    use crate::encoding::AsDynSizeBytes;
+    use crate::primitive::s_certified_cell::SCertifiedCell;
+    use crate::primitive::StableType;
+    use crate::utils::certification::{leaf, leaf_hash, AsHashTree, Hash, HashTree};
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init, SBox};
+
+    #[derive(Debug, PartialEq)]
+    struct Rate(u64);
+
+    impl StableType for Rate {}
+
+    impl AsDynSizeBytes for Rate {
+        fn as_dyn_size_bytes(&self) -> Vec<u8> {
+            self.0.to_le_bytes().to_vec()
+        }
+
+        fn from_dyn_size_bytes(buf: &[u8]) -> Self {
+            Rate(u64::from_le_bytes(buf[0..8].try_into().unwrap()))
+        }
+    }
+
+    impl AsHashTree for Rate {
+        fn root_hash(&self) -> Hash {
+            leaf_hash(&self.0.to_le_bytes())
+        }
+
+        fn hash_tree(&self) -> HashTree {
+            leaf(self.0.to_le_bytes().to_vec())
+        }
+    }
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut cell = SCertifiedCell::new(SBox::new(Rate(100)).unwrap());
+            assert_eq!(cell.root_hash(), leaf_hash(&100u64.to_le_bytes()));
+            assert_eq!(cell.witness().reconstruct(), cell.root_hash());
+
+            cell.with(|it| it.with(|rate| rate.0 = 110)).unwrap();
+            assert_eq!(cell.root_hash(), leaf_hash(&110u64.to_le_bytes()));
+            assert_eq!(cell.hash_tree().reconstruct(), cell.root_hash());
+
+            let prev = cell.set(SBox::new(Rate(120)).unwrap());
+            assert_eq!(prev.into_inner(), Rate(110));
+            assert_eq!(cell.root_hash(), leaf_hash(&120u64.to_le_bytes()));
+            assert_eq!(cell.0, 120);
+
+            let mut vec = SVec::new();
+            vec.push(cell).unwrap();
+
+            let cell = vec.pop().unwrap();
+            assert_eq!(cell.root_hash(), leaf_hash(&120u64.to_le_bytes()));
+            assert_eq!(cell.into_inner().into_inner(), Rate(120));
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
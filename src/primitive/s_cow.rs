@@ -0,0 +1,270 @@
+use crate::encoding::{AsDynSizeBytes, AsFixedSizeBytes};
+use crate::primitive::s_rc::SRc;
+use crate::primitive::{StableType, TryClone};
+use crate::OutOfMemory;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+/// Copy-on-write smart-pointer to dynamic sized data on stable memory
+///
+/// This is a thin wrapper around [SRc]: [SCow::snapshot] (or [Clone]) only increments the reference
+/// counter, so all snapshots share the same memory block. The data is only copied, once it is
+/// mutated via [SCow::to_mut] while some other snapshot is still alive - other snapshots keep
+/// seeing the old data. If this is the only reference, the data is mutated in place.
+///
+/// Handy for things like a config value, that is read by each message, but rarely changes - each
+/// message can take a cheap snapshot of it, without worrying about concurrent updates.
+///
+/// `T` should implement [StableType], [AsDynSizeBytes] and [TryClone]. [SCow] itself implements
+/// [StableType] and [AsFixedSizeBytes], so you can put it in any other stable structure.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::SCow;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut config = SCow::new(String::from("v1")).expect("Out of memory");
+/// let snapshot = config.snapshot();
+///
+/// config.to_mut(|it| it.push_str(".1")).expect("Out of memory");
+///
+/// assert_eq!(&*config, "v1.1");
+/// assert_eq!(&*snapshot, "v1");
+/// ```
+pub struct SCow<T: AsDynSizeBytes + StableType> {
+    rc: SRc<T>,
+}
+
+impl<T: AsDynSizeBytes + StableType> SCow<T> {
+    /// Stores dynamic sized data on stable memory
+    ///
+    /// Returns `Err` and the data, if the canister is `OutOfMemory`.
+    #[inline]
+    pub fn new(it: T) -> Result<Self, T> {
+        SRc::new(it).map(|rc| Self { rc })
+    }
+
+    /// Returns another [SCow], sharing the same data
+    ///
+    /// Does not copy the underlying data. Same as [Clone::clone].
+    #[inline]
+    pub fn snapshot(&self) -> Self {
+        self.clone()
+    }
+
+    /// Returns [true] if there are other [SCow]-s sharing the same data
+    #[inline]
+    pub fn is_shared(&self) -> bool {
+        self.rc.strong_count() > 1
+    }
+
+    /// Returns [true] if both [SCow]-s share the same data
+    #[inline]
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        SRc::ptr_eq(&this.rc, &other.rc)
+    }
+
+    /// Allows mutation of the underlying data, by accepting a lambda function
+    ///
+    /// If the data is shared with other [SCow]-s, it is copied first via [TryClone] and this [SCow]
+    /// starts pointing to the copy, so other snapshots are left untouched. Otherwise, the data is
+    /// mutated in place, just like with [SBox::with](crate::SBox::with).
+    ///
+    /// Returns [OutOfMemory] error, if it was impossible to allocate or reallocate the underlying
+    /// memory block.
+    pub fn to_mut<R, F: FnOnce(&mut T) -> R>(&mut self, func: F) -> Result<R, OutOfMemory>
+    where
+        T: TryClone,
+    {
+        if self.is_shared() {
+            let copy = self.rc.deref().try_clone()?;
+            let mut rc = SRc::new(copy).map_err(|_| OutOfMemory)?;
+
+            // the new reference takes the place of the old one, so it should be owned the same way
+            unsafe {
+                if !self.rc.should_stable_drop() {
+                    rc.stable_drop_flag_off();
+                }
+
+                let mut old = std::mem::replace(&mut self.rc, rc);
+                old.stable_drop();
+                old.stable_drop_flag_off();
+            }
+        }
+
+        self.rc.with_unique(func)
+    }
+
+    /// Returns the underlying data, if this is the only reference to it
+    ///
+    /// Otherwise, returns `Err` with this [SCow].
+    #[inline]
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        SRc::try_unwrap(this.rc).map_err(|rc| Self { rc })
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> Clone for SCow<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            rc: self.rc.clone(),
+        }
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> AsFixedSizeBytes for SCow<T> {
+    const SIZE: usize = SRc::<T>::SIZE;
+    type Buf = <SRc<T> as AsFixedSizeBytes>::Buf;
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.rc.as_fixed_size_bytes(buf)
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        Self {
+            rc: SRc::from_fixed_size_bytes(arr),
+        }
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> StableType for SCow<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.rc.stable_drop_flag_off();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.rc.stable_drop_flag_on();
+    }
+}
+
+impl<T: PartialEq + AsDynSizeBytes + StableType> PartialEq for SCow<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.rc.eq(&other.rc)
+    }
+}
+
+impl<T: Eq + PartialEq + AsDynSizeBytes + StableType> Eq for SCow<T> {}
+
+impl<T: PartialOrd + AsDynSizeBytes + StableType> PartialOrd for SCow<T> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.rc.partial_cmp(&other.rc)
+    }
+}
+
+impl<T: Ord + PartialOrd + AsDynSizeBytes + StableType> Ord for SCow<T> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rc.cmp(&other.rc)
+    }
+}
+
+impl<T: Hash + AsDynSizeBytes + StableType> Hash for SCow<T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rc.hash(state);
+    }
+}
+
+impl<T: Debug + AsDynSizeBytes + StableType> Debug for SCow<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SCow(")?;
+        self.deref().fmt(f)?;
+        f.write_str(")")
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> Borrow<T> for SCow<T> {
+    #[inline]
+    fn borrow(&self) -> &T {
+        self.deref()
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> Deref for SCow<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.rc.deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SVec;
+    use crate::primitive::s_cow::SCow;
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut cow = SCow::new(String::from("123")).unwrap();
+            assert!(!cow.is_shared());
+
+            let ptr = cow.rc.as_ptr();
+            cow.to_mut(|it| it.push('4')).unwrap();
+            assert_eq!(cow.rc.as_ptr(), ptr);
+            assert_eq!(&*cow, "1234");
+
+            let snapshot = cow.snapshot();
+            assert!(cow.is_shared());
+            assert!(SCow::ptr_eq(&cow, &snapshot));
+
+            cow.to_mut(|it| it.push('5')).unwrap();
+            assert!(!cow.is_shared());
+            assert!(!snapshot.is_shared());
+            assert!(!SCow::ptr_eq(&cow, &snapshot));
+            assert_eq!(&*cow, "12345");
+            assert_eq!(&*snapshot, "1234");
+
+            let mut vec = SVec::new();
+            vec.push(cow.snapshot()).unwrap();
+            vec.push(cow).unwrap();
+
+            // mutating a stored copy should not affect the other one
+            vec.get_mut(0)
+                .unwrap()
+                .to_mut(|it| it.push_str(&"0".repeat(1000)))
+                .unwrap();
+
+            assert_eq!(vec.get(0).unwrap().len(), 1005);
+            assert_eq!(&**vec.get(1).unwrap(), "12345");
+            assert!(!vec.get(0).unwrap().is_shared());
+            assert!(!vec.get(1).unwrap().is_shared());
+
+            // not shared anymore - mutated in place, possibly reallocating
+            vec.get_mut(1)
+                .unwrap()
+                .to_mut(|it| it.push_str(&"0".repeat(1000)))
+                .unwrap();
+            assert_eq!(vec.get(1).unwrap().len(), 1005);
+
+            let it = vec.pop().unwrap();
+            assert_eq!(SCow::try_unwrap(it).unwrap().len(), 1005);
+
+            let snapshot_copy = snapshot.clone();
+            let snapshot = SCow::try_unwrap(snapshot).unwrap_err();
+            drop(snapshot_copy);
+
+            assert_eq!(SCow::try_unwrap(snapshot).unwrap(), "1234");
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,619 @@
+use crate::encoding::{AsDynSizeBytes, AsFixedSizeBytes};
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::primitive::StableType;
+use crate::{allocate, deallocate, reallocate, OutOfMemory};
+use std::borrow::Borrow;
+use std::cell::UnsafeCell;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+const STRONG_COUNT_OFFSET: u64 = 0;
+const WEAK_COUNT_OFFSET: u64 = STRONG_COUNT_OFFSET + u64::SIZE as u64;
+const DATA_OFFSET: u64 = WEAK_COUNT_OFFSET + u64::SIZE as u64;
+
+/// Reference-counted smart-pointer that allows sharing the same dynamic sized data between several
+/// stable data structures
+///
+/// Works like [Rc](std::rc::Rc), but both the data and the reference counter live in stable memory,
+/// in a single memory block. Each [SRc], which is stored somewhere (in a stable collection or
+/// in a local variable), is a single reference. [Clone]-ing an [SRc] increments the counter,
+/// stable-dropping it decrements the counter. When the last reference is stable-dropped, the
+/// underlying data is also stable-dropped and the memory block is released.
+///
+/// `T` should implement both [StableType] and [AsDynSizeBytes], just like for [SBox](crate::SBox).
+/// [SRc] itself implements [StableType] and [AsFixedSizeBytes], so you can put it in any other
+/// stable structure.
+///
+/// Like [SBox](crate::SBox), it is lazy on reads - the data is deserialized only once it is
+/// accessed via [Deref]. Since the data is shared, it is immutable. Use [SRc::try_unwrap] to take
+/// the data back, when there are no other references to it.
+///
+/// Non-owning references can be created with [SRc::downgrade]. See [SWeak].
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::SRc;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let blob = SRc::new(vec![42u8; 1000]).expect("Out of memory");
+///
+/// let mut first = SVec::new();
+/// let mut second = SVec::new();
+///
+/// first.push(blob.clone()).expect("Out of memory");
+/// second.push(blob).expect("Out of memory");
+///
+/// assert_eq!(first.get(0).unwrap().strong_count(), 2);
+///
+/// drop(first);
+///
+/// assert_eq!(second.get(0).unwrap().strong_count(), 1);
+/// assert_eq!(second.get(0).unwrap().len(), 1000);
+/// ```
+pub struct SRc<T: AsDynSizeBytes + StableType> {
+    ptr: StablePtr,
+    inner: UnsafeCell<Option<T>>,
+    stable_drop_flag: bool,
+}
+
+impl<T: AsDynSizeBytes + StableType> SRc<T> {
+    /// Stores dynamic sized data on stable memory, with the reference counter set to `1`
+    ///
+    /// Returns `Err` and the data, if the canister is `OutOfMemory`.
+    pub fn new(mut it: T) -> Result<Self, T> {
+        let buf = it.as_dyn_size_bytes();
+
+        if let Ok(slice) = unsafe { allocate(DATA_OFFSET + buf.len() as u64) } {
+            unsafe {
+                crate::mem::write_fixed(slice.offset(STRONG_COUNT_OFFSET), &mut 1u64);
+                crate::mem::write_fixed(slice.offset(WEAK_COUNT_OFFSET), &mut 0u64);
+                crate::mem::write_bytes(slice.offset(DATA_OFFSET), &buf);
+                it.stable_drop_flag_off();
+            }
+
+            Ok(Self {
+                ptr: slice.as_ptr(),
+                inner: UnsafeCell::new(Some(it)),
+                stable_drop_flag: true,
+            })
+        } else {
+            Err(it)
+        }
+    }
+
+    /// Returns the number of references to the underlying data
+    #[inline]
+    pub fn strong_count(&self) -> u64 {
+        read_count(self.ptr, STRONG_COUNT_OFFSET)
+    }
+
+    /// Returns the number of [SWeak] references to the underlying data
+    #[inline]
+    pub fn weak_count(&self) -> u64 {
+        read_count(self.ptr, WEAK_COUNT_OFFSET)
+    }
+
+    /// Creates a new [SWeak] reference to the underlying data, incrementing the weak counter
+    #[inline]
+    pub fn downgrade(this: &Self) -> SWeak<T> {
+        write_count(this.ptr, WEAK_COUNT_OFFSET, this.weak_count() + 1);
+
+        SWeak::new(this.ptr)
+    }
+
+    /// Returns [true] if both [SRc]-s point to the same data
+    #[inline]
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    /// Returns a pointer to the underlying memory block
+    #[inline]
+    pub fn as_ptr(&self) -> u64 {
+        self.ptr
+    }
+
+    /// Returns the underlying data, releasing occupied stable memory, if this is the only reference
+    /// to it
+    ///
+    /// Otherwise, returns `Err` with this [SRc]. If there are [SWeak] references to the data, they
+    /// can no longer be upgraded after this call.
+    pub fn try_unwrap(mut this: Self) -> Result<T, Self> {
+        if this.strong_count() != 1 {
+            return Err(this);
+        }
+
+        unsafe {
+            this.lazy_read(true);
+        }
+
+        let res = this.inner.get_mut().take().unwrap();
+
+        write_count(this.ptr, STRONG_COUNT_OFFSET, 0);
+        release_if_unused(this.ptr);
+
+        unsafe { this.stable_drop_flag_off() };
+
+        Ok(res)
+    }
+
+    // Mutates the data in place. Only valid for the only reference without any [SWeak]-s, since the
+    // memory block can be moved during reallocation.
+    pub(crate) fn with_unique<R, F: FnOnce(&mut T) -> R>(
+        &mut self,
+        func: F,
+    ) -> Result<R, OutOfMemory> {
+        debug_assert_eq!(self.strong_count(), 1);
+        debug_assert_eq!(self.weak_count(), 0);
+
+        unsafe { self.lazy_read(true) };
+
+        let it = self.inner.get_mut().as_mut().unwrap();
+        let res = func(it);
+        let buf = it.as_dyn_size_bytes();
+
+        unsafe { it.stable_drop_flag_off() };
+
+        let mut slice = unsafe { SSlice::from_ptr(self.ptr).unwrap() };
+        if slice.get_size_bytes() < DATA_OFFSET + buf.len() as u64 {
+            slice = unsafe { reallocate(slice, DATA_OFFSET + buf.len() as u64)? };
+            self.ptr = slice.as_ptr();
+        }
+
+        unsafe { crate::mem::write_bytes(slice.offset(DATA_OFFSET), &buf) };
+
+        Ok(res)
+    }
+
+    unsafe fn lazy_read(&self, drop_flag: bool) {
+        if let Some(it) = (*self.inner.get()).as_mut() {
+            if drop_flag {
+                it.stable_drop_flag_on();
+            } else {
+                it.stable_drop_flag_off();
+            }
+
+            return;
+        }
+
+        let slice = SSlice::from_ptr(self.ptr).unwrap();
+        let mut buf = vec![0u8; (slice.get_size_bytes() - DATA_OFFSET) as usize];
+        crate::mem::read_bytes(slice.offset(DATA_OFFSET), &mut buf);
+
+        let mut inner = T::from_dyn_size_bytes(&buf);
+        if drop_flag {
+            inner.stable_drop_flag_on();
+        } else {
+            inner.stable_drop_flag_off();
+        }
+
+        *self.inner.get() = Some(inner);
+    }
+}
+
+#[inline]
+fn read_count(ptr: StablePtr, offset: u64) -> u64 {
+    unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(ptr, offset)) }
+}
+
+#[inline]
+fn write_count(ptr: StablePtr, offset: u64, mut count: u64) {
+    unsafe { crate::mem::write_fixed(SSlice::_offset(ptr, offset), &mut count) };
+}
+
+// the memory block is only released, when there are no references of any kind left
+#[inline]
+fn release_if_unused(ptr: StablePtr) {
+    if read_count(ptr, STRONG_COUNT_OFFSET) == 0 && read_count(ptr, WEAK_COUNT_OFFSET) == 0 {
+        deallocate(unsafe { SSlice::from_ptr(ptr).unwrap() });
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> Clone for SRc<T> {
+    /// Creates another reference to the same data, incrementing the reference counter
+    ///
+    /// Does not copy the underlying data.
+    #[inline]
+    fn clone(&self) -> Self {
+        write_count(self.ptr, STRONG_COUNT_OFFSET, self.strong_count() + 1);
+
+        Self {
+            ptr: self.ptr,
+            inner: UnsafeCell::default(),
+            stable_drop_flag: true,
+        }
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> AsFixedSizeBytes for SRc<T> {
+    const SIZE: usize = u64::SIZE;
+    type Buf = [u8; u64::SIZE];
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.ptr.as_fixed_size_bytes(buf)
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        Self {
+            ptr: u64::from_fixed_size_bytes(arr),
+            inner: UnsafeCell::default(),
+            stable_drop_flag: false,
+        }
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> StableType for SRc<T> {
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    unsafe fn stable_drop(&mut self) {
+        let count = self.strong_count() - 1;
+        write_count(self.ptr, STRONG_COUNT_OFFSET, count);
+
+        if count > 0 {
+            // other references are still alive, the data should stay untouched
+            if let Some(it) = self.inner.get_mut() {
+                it.stable_drop_flag_off();
+            }
+
+            return;
+        }
+
+        // the data gets stable-dropped together with this smart-pointer
+        self.lazy_read(true);
+        release_if_unused(self.ptr);
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> Drop for SRc<T> {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+impl<T: PartialEq + AsDynSizeBytes + StableType> PartialEq for SRc<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        Self::ptr_eq(self, other) || self.deref().eq(other.deref())
+    }
+}
+
+impl<T: Eq + PartialEq + AsDynSizeBytes + StableType> Eq for SRc<T> {}
+
+impl<T: PartialOrd + AsDynSizeBytes + StableType> PartialOrd for SRc<T> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.deref().partial_cmp(other.deref())
+    }
+}
+
+impl<T: Ord + PartialOrd + AsDynSizeBytes + StableType> Ord for SRc<T> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.deref().cmp(other.deref())
+    }
+}
+
+impl<T: Hash + AsDynSizeBytes + StableType> Hash for SRc<T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.deref().hash(state);
+    }
+}
+
+impl<T: Debug + AsDynSizeBytes + StableType> Debug for SRc<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SRc(")?;
+        self.deref().fmt(f)?;
+        f.write_str(")")
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> Borrow<T> for SRc<T> {
+    #[inline]
+    fn borrow(&self) -> &T {
+        self.deref()
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> Deref for SRc<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        unsafe {
+            self.lazy_read(false);
+
+            (*self.inner.get()).as_ref().unwrap()
+        }
+    }
+}
+
+/// Non-owning reference to the data of an [SRc]
+///
+/// Works like [Weak](std::rc::Weak) - it does not keep the data alive, but can be upgraded to an
+/// [SRc] with [SWeak::upgrade], if there is at least one [SRc] pointing to the data. Once the last
+/// [SRc] is stable-dropped, the data is stable-dropped as well, but the memory block holding the
+/// counters is only released when the last [SWeak] is also stable-dropped.
+///
+/// Useful for caches and indexes that should not keep large values alive on their own.
+///
+/// [SWeak] implements [StableType] and [AsFixedSizeBytes], so you can put it in any other stable
+/// structure.
+///
+/// # Examples
+/// ```rust
+/// # use ic_stable_memory::{SRc, stable_memory_init};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let value = SRc::new(String::from("cached")).expect("Out of memory");
+/// let weak = SRc::downgrade(&value);
+///
+/// assert_eq!(weak.upgrade().unwrap().as_str(), "cached");
+///
+/// drop(value);
+///
+/// assert!(weak.upgrade().is_none());
+/// ```
+pub struct SWeak<T: AsDynSizeBytes + StableType> {
+    ptr: StablePtr,
+    stable_drop_flag: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T: AsDynSizeBytes + StableType> SWeak<T> {
+    #[inline]
+    fn new(ptr: StablePtr) -> Self {
+        Self {
+            ptr,
+            stable_drop_flag: true,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a new [SRc] to the data, if it is still alive
+    ///
+    /// Increments the reference counter.
+    #[inline]
+    pub fn upgrade(&self) -> Option<SRc<T>> {
+        let strong_count = self.strong_count();
+        if strong_count == 0 {
+            return None;
+        }
+
+        write_count(self.ptr, STRONG_COUNT_OFFSET, strong_count + 1);
+
+        Some(SRc {
+            ptr: self.ptr,
+            inner: UnsafeCell::default(),
+            stable_drop_flag: true,
+        })
+    }
+
+    /// Returns the number of [SRc] references to the data
+    ///
+    /// If it is `0`, then the data is already stable-dropped.
+    #[inline]
+    pub fn strong_count(&self) -> u64 {
+        read_count(self.ptr, STRONG_COUNT_OFFSET)
+    }
+
+    /// Returns the number of [SWeak] references to the data
+    #[inline]
+    pub fn weak_count(&self) -> u64 {
+        read_count(self.ptr, WEAK_COUNT_OFFSET)
+    }
+
+    /// Returns [true] if both [SWeak]-s point to the same memory block
+    #[inline]
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> Clone for SWeak<T> {
+    /// Creates another weak reference to the same data, incrementing the weak counter
+    #[inline]
+    fn clone(&self) -> Self {
+        write_count(self.ptr, WEAK_COUNT_OFFSET, self.weak_count() + 1);
+
+        Self::new(self.ptr)
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> AsFixedSizeBytes for SWeak<T> {
+    const SIZE: usize = u64::SIZE;
+    type Buf = [u8; u64::SIZE];
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.ptr.as_fixed_size_bytes(buf)
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        Self {
+            ptr: u64::from_fixed_size_bytes(arr),
+            stable_drop_flag: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> StableType for SWeak<T> {
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    unsafe fn stable_drop(&mut self) {
+        write_count(self.ptr, WEAK_COUNT_OFFSET, self.weak_count() - 1);
+        release_if_unused(self.ptr);
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> Drop for SWeak<T> {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+impl<T: AsDynSizeBytes + StableType> Debug for SWeak<T> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("(SWeak)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::{SBTreeMap, SVec};
+    use crate::primitive::s_box::SBox;
+    use crate::primitive::s_rc::{SRc, SWeak};
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init};
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let rc = SRc::new(String::from("shared")).unwrap();
+            assert_eq!(rc.strong_count(), 1);
+
+            let mut first = SVec::new();
+            let mut second = SBTreeMap::new();
+
+            for i in 0..10u64 {
+                first.push(rc.clone()).unwrap();
+                second.insert(i, rc.clone()).unwrap();
+            }
+
+            assert_eq!(rc.strong_count(), 21);
+            assert_eq!(&**first.get(5).unwrap(), "shared");
+            assert!(SRc::ptr_eq(&first.get(3).unwrap(), &rc));
+
+            let rc = SRc::try_unwrap(rc).unwrap_err();
+
+            first.clear();
+            assert_eq!(rc.strong_count(), 11);
+
+            let popped = second.remove(&3).unwrap();
+            assert_eq!(popped, rc);
+            drop(popped);
+            assert_eq!(rc.strong_count(), 10);
+
+            drop(second);
+            assert_eq!(SRc::try_unwrap(rc).unwrap(), "shared");
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+
+        {
+            // nested stable structures get stable-dropped with the last reference
+            let rc = SRc::new(SBox::new(100u64).unwrap()).unwrap();
+
+            let mut vec = SVec::new();
+            vec.push(rc.clone()).unwrap();
+            vec.push(rc).unwrap();
+
+            assert_eq!(***vec.get(1).unwrap(), 100);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn weak_refs_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let rc = SRc::new(SBox::new(String::from("value")).unwrap()).unwrap();
+
+            let mut index = SVec::<SWeak<SBox<String>>>::new();
+            for _ in 0..5 {
+                index.push(SRc::downgrade(&rc)).unwrap();
+            }
+
+            assert_eq!(rc.weak_count(), 5);
+            assert_eq!(rc.strong_count(), 1);
+
+            {
+                let upgraded = index.get(2).unwrap().upgrade().unwrap();
+
+                assert_eq!(upgraded.as_str(), "value");
+                assert_eq!(rc.strong_count(), 2);
+            }
+
+            assert_eq!(rc.strong_count(), 1);
+
+            let weak = index.pop().unwrap();
+            let another = weak.clone();
+            assert!(SWeak::ptr_eq(&weak, &another));
+            assert_eq!(another.weak_count(), 6);
+            drop(weak);
+
+            drop(rc);
+
+            assert_eq!(another.strong_count(), 0);
+            assert!(another.upgrade().is_none());
+            assert!(index.get(0).unwrap().upgrade().is_none());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+
+        {
+            let rc = SRc::new(100u64).unwrap();
+            let weak = SRc::downgrade(&rc);
+
+            assert_eq!(SRc::try_unwrap(rc).unwrap(), 100);
+            assert!(weak.upgrade().is_none());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
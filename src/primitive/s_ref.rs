@@ -1,4 +1,7 @@
 use crate::encoding::AsFixedSizeBytes;
+#[cfg(debug_assertions)]
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
 use crate::primitive::StableType;
 use candid::types::{Serializer, Type, TypeId};
 use candid::CandidType;
@@ -16,6 +19,9 @@ use std::ops::Deref;
 /// `T` has to implement [StableType] and [AsFixedSizeBytes].
 pub struct SRef<'o, T> {
     ptr: u64,
+    // the memory block, this reference points into, with its generation
+    #[cfg(debug_assertions)]
+    block: Option<SSlice>,
     inner: UnsafeCell<Option<T>>,
     _marker: PhantomData<&'o T>,
 }
@@ -29,10 +35,46 @@ impl<'o, T> SRef<'o, T> {
     pub unsafe fn new(ptr: u64) -> Self {
         Self {
             ptr,
+            #[cfg(debug_assertions)]
+            block: None,
             inner: UnsafeCell::new(None),
             _marker: PhantomData::default(),
         }
     }
+
+    /// Creates reference from raw pointer, that points inside the memory block at `block_ptr`.
+    ///
+    /// In debug builds remembers the generation of the memory block and panics on access, if the
+    /// block was deallocated or reallocated since then.
+    ///
+    /// # Safety
+    /// Make sure your raw pointers point to valid locations.
+    #[inline]
+    pub unsafe fn new_in(block_ptr: StablePtr, ptr: u64) -> Self {
+        #[cfg(not(debug_assertions))]
+        let _ = block_ptr;
+
+        Self {
+            ptr,
+            #[cfg(debug_assertions)]
+            block: SSlice::from_ptr(block_ptr),
+            inner: UnsafeCell::new(None),
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn debug_check_alive(&self) {
+        #[cfg(debug_assertions)]
+        if let Some(block) = &self.block {
+            debug_assert!(block.is_alive(), "Use after deallocate: {:?}", block);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> u64 {
+        self.ptr
+    }
 }
 
 impl<'o, T: StableType + AsFixedSizeBytes> SRef<'o, T> {
@@ -49,6 +91,8 @@ impl<'o, T: StableType + AsFixedSizeBytes> Deref for SRef<'o, T> {
 
     #[inline]
     fn deref(&self) -> &Self::Target {
+        self.debug_check_alive();
+
         unsafe {
             self.read();
 
@@ -0,0 +1,427 @@
+//! [proptest] strategies and model-based state machines for stable collections, enabled with
+//! `testing` feature.
+//!
+//! Strategies ([svec], [shash_map], [sbtree_map]) generate stable collections, filled with values
+//! of provided strategies. Op strategies ([vec_ops], [map_ops]) generate random sequences of
+//! operations, which can be applied to a [StateMachine] - a stable collection together with its
+//! `std` reference model. After each operation, the machine checks, that both of them are still
+//! equal. The same approach can be used to fuzz your own stable state: implement [StateMachine] for
+//! it and run it with [run_state_machine].
+//!
+//! Stable memory should be initialized on the thread, which runs the test.
+//!
+//! # Example
+//! ```rust
+//! # use ic_stable_memory::stable_memory_init;
+//! use ic_stable_memory::testing::{run_state_machine, vec_ops, VecMachine};
+//! use proptest::prelude::*;
+//! # unsafe { ic_stable_memory::mem::clear(); }
+//! # stable_memory_init();
+//!
+//! proptest!(|(ops in vec_ops(any::<u64>(), 0..100))| {
+//!     let mut machine = VecMachine::new();
+//!     run_state_machine(&mut machine, &ops);
+//! });
+//! ```
+
+use crate::collections::{SBTreeMap, SHashMap, SVec};
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::StableType;
+use proptest::collection::{btree_map, hash_map, vec, SizeRange};
+use proptest::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// Generates [SVec]s of elements, generated by the provided strategy
+///
+/// # Panics
+/// Panics if the canister is out of stable memory.
+pub fn svec<S: Strategy>(
+    element: S,
+    size: impl Into<SizeRange>,
+) -> impl Strategy<Value = SVec<S::Value>>
+where
+    S::Value: StableType + AsFixedSizeBytes,
+{
+    vec(element, size).prop_map(|it| SVec::try_from(it).expect("Out of memory"))
+}
+
+/// Generates [SHashMap]s of entries, generated by the provided strategies
+///
+/// # Panics
+/// Panics if the canister is out of stable memory.
+pub fn shash_map<K: Strategy, V: Strategy>(
+    key: K,
+    value: V,
+    size: impl Into<SizeRange>,
+) -> impl Strategy<Value = SHashMap<K::Value, V::Value>>
+where
+    K::Value: StableType + AsFixedSizeBytes + Hash + Eq,
+    V::Value: StableType + AsFixedSizeBytes,
+{
+    hash_map(key, value, size).prop_map(|it| SHashMap::try_from(it).expect("Out of memory"))
+}
+
+/// Generates [SBTreeMap]s of entries, generated by the provided strategies
+///
+/// # Panics
+/// Panics if the canister is out of stable memory.
+pub fn sbtree_map<K: Strategy, V: Strategy>(
+    key: K,
+    value: V,
+    size: impl Into<SizeRange>,
+) -> impl Strategy<Value = SBTreeMap<K::Value, V::Value>>
+where
+    K::Value: StableType + AsFixedSizeBytes + Ord,
+    V::Value: StableType + AsFixedSizeBytes,
+{
+    btree_map(key, value, size).prop_map(|it| SBTreeMap::try_from(it).expect("Out of memory"))
+}
+
+/// A stable state together with its reference model, see [run_state_machine]
+pub trait StateMachine {
+    /// An operation, which can be applied to this machine
+    type Op: Debug;
+
+    /// Applies the operation to both: the stable state and the model
+    fn apply(&mut self, op: &Self::Op);
+
+    /// Panics, if the stable state differs from the model
+    fn check(&self);
+}
+
+/// Applies operations one by one, checking the machine after each of them
+pub fn run_state_machine<M: StateMachine>(machine: &mut M, ops: &[M::Op]) {
+    machine.check();
+
+    for op in ops {
+        machine.apply(op);
+        machine.check();
+    }
+}
+
+/// An operation of [VecMachine]
+///
+/// Indices are taken modulo the length of the vector. Operations, which would panic (like removing
+/// from an empty vector or swapping an element with itself), are skipped.
+#[derive(Debug, Clone)]
+pub enum VecOp<T> {
+    /// [SVec::push]
+    Push(T),
+    /// [SVec::pop]
+    Pop,
+    /// [SVec::insert]
+    Insert(usize, T),
+    /// [SVec::remove]
+    Remove(usize),
+    /// [SVec::replace]
+    Replace(usize, T),
+    /// [SVec::swap]
+    Swap(usize, usize),
+    /// [SVec::clear]
+    Clear,
+}
+
+/// Generates sequences of [VecOp]s with elements, generated by the provided strategy
+pub fn vec_ops<S: Strategy + Clone>(
+    element: S,
+    len: impl Into<SizeRange>,
+) -> impl Strategy<Value = Vec<VecOp<S::Value>>>
+where
+    S::Value: Clone,
+{
+    let op = prop_oneof![
+        10 => element.clone().prop_map(VecOp::Push),
+        5 => Just(VecOp::Pop),
+        5 => (any::<usize>(), element.clone()).prop_map(|(idx, it)| VecOp::Insert(idx, it)),
+        5 => any::<usize>().prop_map(VecOp::Remove),
+        5 => (any::<usize>(), element).prop_map(|(idx, it)| VecOp::Replace(idx, it)),
+        3 => (any::<usize>(), any::<usize>()).prop_map(|(a, b)| VecOp::Swap(a, b)),
+        1 => Just(VecOp::Clear),
+    ];
+
+    vec(op, len)
+}
+
+/// [SVec] together with [Vec] as its model
+pub struct VecMachine<T: StableType + AsFixedSizeBytes> {
+    /// The stable state
+    pub stable: SVec<T>,
+    /// The reference model
+    pub model: Vec<T>,
+}
+
+impl<T: StableType + AsFixedSizeBytes> VecMachine<T> {
+    /// Creates a machine with both collections empty
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            stable: SVec::new(),
+            model: Vec::new(),
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Default for VecMachine<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Clone + PartialEq + Debug> StateMachine for VecMachine<T> {
+    type Op = VecOp<T>;
+
+    fn apply(&mut self, op: &Self::Op) {
+        let len = self.model.len();
+
+        match op {
+            VecOp::Push(it) => {
+                if self.stable.push(it.clone()).is_err() {
+                    panic!("Out of memory");
+                }
+                self.model.push(it.clone());
+            }
+            VecOp::Pop => assert_eq!(self.stable.pop(), self.model.pop()),
+            VecOp::Insert(idx, it) => {
+                let idx = idx % (len + 1);
+
+                if self.stable.insert(idx, it.clone()).is_err() {
+                    panic!("Out of memory");
+                }
+                self.model.insert(idx, it.clone());
+            }
+            VecOp::Remove(idx) if len > 0 => {
+                assert_eq!(self.stable.remove(idx % len), self.model.remove(idx % len))
+            }
+            VecOp::Replace(idx, it) if len > 0 => {
+                let prev = std::mem::replace(&mut self.model[idx % len], it.clone());
+                assert_eq!(self.stable.replace(idx % len, it.clone()), prev);
+            }
+            VecOp::Swap(a, b) if len > 0 && a % len != b % len => {
+                self.stable.swap(a % len, b % len);
+                self.model.swap(a % len, b % len);
+            }
+            VecOp::Clear => {
+                self.stable.clear();
+                self.model.clear();
+            }
+            _ => {}
+        }
+    }
+
+    fn check(&self) {
+        assert_eq!(self.stable.len(), self.model.len());
+
+        for (stable, model) in self.stable.iter().zip(self.model.iter()) {
+            assert_eq!(&*stable, model);
+        }
+    }
+}
+
+/// An operation of [HashMapMachine] and [BTreeMapMachine]
+#[derive(Debug, Clone)]
+pub enum MapOp<K, V> {
+    /// Inserts the entry, replacing the previous value
+    Insert(K, V),
+    /// Removes the entry by the key
+    Remove(K),
+}
+
+/// Generates sequences of [MapOp]s with entries, generated by the provided strategies
+///
+/// Use a strategy with a narrow range of keys to make inserts and removes hit the same keys.
+pub fn map_ops<K: Strategy + Clone, V: Strategy>(
+    key: K,
+    value: V,
+    len: impl Into<SizeRange>,
+) -> impl Strategy<Value = Vec<MapOp<K::Value, V::Value>>> {
+    let op = prop_oneof![
+        2 => (key.clone(), value).prop_map(|(k, v)| MapOp::Insert(k, v)),
+        1 => key.prop_map(MapOp::Remove),
+    ];
+
+    vec(op, len)
+}
+
+/// [SHashMap] together with [HashMap] as its model
+pub struct HashMapMachine<
+    K: StableType + AsFixedSizeBytes + Hash + Eq,
+    V: StableType + AsFixedSizeBytes,
+> {
+    /// The stable state
+    pub stable: SHashMap<K, V>,
+    /// The reference model
+    pub model: HashMap<K, V>,
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
+    HashMapMachine<K, V>
+{
+    /// Creates a machine with both collections empty
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            stable: SHashMap::new(),
+            model: HashMap::new(),
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Default
+    for HashMapMachine<K, V>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq + Clone + Debug,
+        V: StableType + AsFixedSizeBytes + Clone + PartialEq + Debug,
+    > StateMachine for HashMapMachine<K, V>
+{
+    type Op = MapOp<K, V>;
+
+    fn apply(&mut self, op: &Self::Op) {
+        match op {
+            MapOp::Insert(k, v) => {
+                let prev = self
+                    .stable
+                    .insert(k.clone(), v.clone())
+                    .unwrap_or_else(|_| panic!("Out of memory"));
+
+                assert_eq!(prev, self.model.insert(k.clone(), v.clone()));
+            }
+            MapOp::Remove(k) => assert_eq!(self.stable.remove(k), self.model.remove(k)),
+        }
+    }
+
+    fn check(&self) {
+        assert_eq!(self.stable.len(), self.model.len());
+
+        for (k, v) in self.model.iter() {
+            assert_eq!(self.stable.get(k).as_deref(), Some(v));
+        }
+    }
+}
+
+/// [SBTreeMap] together with [BTreeMap] as its model
+pub struct BTreeMapMachine<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes>
+{
+    /// The stable state
+    pub stable: SBTreeMap<K, V>,
+    /// The reference model
+    pub model: BTreeMap<K, V>,
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes>
+    BTreeMapMachine<K, V>
+{
+    /// Creates a machine with both collections empty
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            stable: SBTreeMap::new(),
+            model: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Default
+    for BTreeMapMachine<K, V>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Ord + Clone + Debug,
+        V: StableType + AsFixedSizeBytes + Clone + PartialEq + Debug,
+    > StateMachine for BTreeMapMachine<K, V>
+{
+    type Op = MapOp<K, V>;
+
+    fn apply(&mut self, op: &Self::Op) {
+        match op {
+            MapOp::Insert(k, v) => {
+                let prev = self
+                    .stable
+                    .insert(k.clone(), v.clone())
+                    .unwrap_or_else(|_| panic!("Out of memory"));
+
+                assert_eq!(prev, self.model.insert(k.clone(), v.clone()));
+            }
+            MapOp::Remove(k) => assert_eq!(self.stable.remove(k), self.model.remove(k)),
+        }
+    }
+
+    fn check(&self) {
+        assert_eq!(self.stable.len() as usize, self.model.len());
+
+        // the order matters too
+        for ((stable_k, stable_v), (model_k, model_v)) in self.stable.iter().zip(self.model.iter())
+        {
+            assert_eq!(&*stable_k, model_k);
+            assert_eq!(&*stable_v, model_v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{
+        map_ops, run_state_machine, sbtree_map, shash_map, svec, vec_ops, BTreeMapMachine,
+        HashMapMachine, VecMachine,
+    };
+    use crate::utils::mem_context::stable;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use proptest::prelude::*;
+
+    #[test]
+    fn machines_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        proptest!(|(ops in vec_ops(any::<u64>(), 0..200))| {
+            let mut machine = VecMachine::new();
+            run_state_machine(&mut machine, &ops);
+        });
+
+        proptest!(|(ops in map_ops(0..50u64, any::<u64>(), 0..200))| {
+            let mut machine = HashMapMachine::new();
+            run_state_machine(&mut machine, &ops);
+        });
+
+        proptest!(|(ops in map_ops(0..50u64, any::<u64>(), 0..200))| {
+            let mut machine = BTreeMapMachine::new();
+            run_state_machine(&mut machine, &ops);
+        });
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn strategies_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        proptest!(|(
+            vec in svec(any::<u32>(), 0..50),
+            hash_map in shash_map(any::<u32>(), any::<u64>(), 0..50),
+            btree_map in sbtree_map(any::<u32>(), any::<u64>(), 0..50),
+        )| {
+            prop_assert!(vec.len() < 50);
+            prop_assert!(hash_map.len() < 50);
+            prop_assert!(btree_map.len() < 50);
+        });
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
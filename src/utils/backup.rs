@@ -0,0 +1,261 @@
+//! Streaming the whole stable memory out of a canister and back in, over multiple messages.
+//!
+//! A backup is a sequence of chunks, each starting with an 8 byte header. The first chunk holds the
+//! state of the [allocator](crate::mem::allocator::StableMemoryAllocator), the rest hold the memory,
+//! managed by it, [BACKUP_CHUNK_SIZE] bytes at most. Chunks are opaque - an operator only has to save them
+//! somewhere off-chain and pass them back to [restore_chunk] in the same order, in order to recover
+//! the canister.
+
+use crate::encoding::{AsDynSizeBytes, AsFixedSizeBytes};
+use crate::mem::allocator::{StableMemoryAllocator, MIN_PTR};
+use crate::primitive::s_box::clear_decode_cache;
+use crate::utils::math::ceil_div;
+use crate::{stable, OutOfMemory, PAGE_SIZE_BYTES, STABLE_MEMORY_ALLOCATOR};
+use std::cell::RefCell;
+
+/// The maximum number of bytes of memory in a single backup chunk
+///
+/// Small enough to fit into a single response of a canister.
+pub const BACKUP_CHUNK_SIZE: u64 = 1024 * 1024;
+
+thread_local! {
+    static RESTORED_ALLOCATOR: RefCell<Option<StableMemoryAllocator>> = RefCell::default();
+}
+
+/// Returns the backup chunk at the provided cursor, together with the cursor of the next chunk
+///
+/// Start with the cursor `0` and continue with returned cursors, until there is [None]. Stable
+/// memory should not be modified in between, otherwise chunks won't be consistent with each other -
+/// so the canister should reject all updates until the backup is done. Only data, that is stored in
+/// stable memory is backed up - heap state (including [SCell](crate::SCell) values, which are only
+/// persisted during upgrades) should be stored with [store_custom_data](crate::store_custom_data) first.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{backup_chunk, restore_chunk, retrieve_custom_data, stable_memory_init, store_custom_data, SBox};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// store_custom_data(0, SBox::new(String::from("important")).expect("Out of memory"));
+///
+/// let mut chunks = Vec::new();
+/// let mut cursor = Some(0);
+///
+/// while let Some(c) = cursor {
+///     let (chunk, next_cursor) = backup_chunk(c);
+///
+///     chunks.push(chunk);
+///     cursor = next_cursor;
+/// }
+///
+/// // somewhere in a fresh canister
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// for chunk in chunks {
+///     restore_chunk(&chunk).expect("Out of memory");
+/// }
+///
+/// let data = retrieve_custom_data::<String>(0).unwrap();
+/// assert_eq!(*data, "important");
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn backup_chunk(cursor: u64) -> (Vec<u8>, Option<u64>) {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            let max_ptr = alloc.get_max_ptr();
+
+            let mut chunk = cursor.as_new_fixed_size_bytes().to_vec();
+
+            let next_cursor = if cursor == 0 {
+                chunk.extend(alloc.as_dyn_size_bytes());
+
+                MIN_PTR
+            } else {
+                let len = (max_ptr - cursor).min(BACKUP_CHUNK_SIZE) as usize;
+                let from = chunk.len();
+
+                chunk.resize(from + len, 0);
+                stable::read(cursor, &mut chunk[from..]);
+
+                cursor + len as u64
+            };
+
+            let next_cursor = if next_cursor < max_ptr {
+                Some(next_cursor)
+            } else {
+                None
+            };
+
+            (chunk, next_cursor)
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Applies a backup chunk, produced by [backup_chunk], returning [true] once the backup is fully restored
+///
+/// Chunks should be passed in the same order, they were produced. The first chunk deinitializes the
+/// current allocator (if any) and grows stable memory to fit the backup - if that is impossible,
+/// returns [OutOfMemory]. All the data, previously stored in stable memory, is lost, so this should
+/// only be done in an empty canister. Stable memory can't be used until the last chunk is applied,
+/// after that the allocator is initialized again and stored data can be accessed via
+/// [retrieve_custom_data](crate::retrieve_custom_data).
+///
+/// # Panics
+/// Panics if the chunk is malformed or if chunks are passed in the wrong order.
+pub fn restore_chunk(chunk: &[u8]) -> Result<bool, OutOfMemory> {
+    assert!(chunk.len() >= u64::SIZE, "Malformed backup chunk");
+
+    let (header, data) = chunk.split_at(u64::SIZE);
+    let cursor = u64::from_fixed_size_bytes(header);
+
+    if cursor == 0 {
+        let alloc = StableMemoryAllocator::from_dyn_size_bytes(data);
+        let max_ptr = alloc.get_max_ptr();
+
+        let required_pages = ceil_div(max_ptr, PAGE_SIZE_BYTES);
+        let pages = stable::size_pages();
+        if pages < required_pages {
+            stable::grow(required_pages - pages)?;
+        }
+
+        // the current data is being overwritten, nobody should be able to allocate anymore
+        STABLE_MEMORY_ALLOCATOR.with(|it| it.take());
+        RESTORED_ALLOCATOR.with(|it| *it.borrow_mut() = Some(alloc));
+
+        if max_ptr <= MIN_PTR {
+            finish_restore();
+
+            return Ok(true);
+        }
+
+        return Ok(false);
+    }
+
+    let max_ptr = RESTORED_ALLOCATOR.with(|it| {
+        it.borrow()
+            .as_ref()
+            .expect("The first backup chunk should be restored first")
+            .get_max_ptr()
+    });
+
+    assert!(
+        cursor >= MIN_PTR && cursor + data.len() as u64 <= max_ptr,
+        "Malformed backup chunk"
+    );
+
+    stable::write(cursor, data);
+
+    if cursor + data.len() as u64 == max_ptr {
+        finish_restore();
+
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+fn finish_restore() {
+    let mut alloc = RESTORED_ALLOCATOR.with(|it| it.take()).unwrap();
+
+    // handles of backed up reservations were left in the other canister
+    alloc.reclaim_grown_pages();
+    alloc.release_reservations();
+
+    STABLE_MEMORY_ALLOCATOR.with(|it| *it.borrow_mut() = Some(alloc));
+    clear_decode_cache();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SVec;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::backup::{backup_chunk, restore_chunk, BACKUP_CHUNK_SIZE};
+    use crate::utils::mem_context::stable;
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, init_region, reserve, retrieve_custom_data,
+        stable_memory_init, store_custom_data, with_region,
+    };
+
+    fn backup() -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        let mut cursor = Some(0);
+
+        while let Some(c) = cursor {
+            let (chunk, next_cursor) = backup_chunk(c);
+            assert!(chunk.len() as u64 <= BACKUP_CHUNK_SIZE + 8);
+
+            chunks.push(chunk);
+            cursor = next_cursor;
+        }
+
+        chunks
+    }
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        init_region("logs", 0);
+        let mut vec = with_region("logs", SVec::<u64>::new);
+        for i in 0..300_000u64 {
+            vec.push(i).unwrap();
+        }
+
+        store_custom_data(0, SBox::new(vec).unwrap());
+        let reservation = reserve(1000).unwrap();
+        let allocated = get_allocated_size();
+
+        let chunks = backup();
+        assert!(chunks.len() > 2);
+
+        stable::clear();
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert_eq!(restore_chunk(chunk).unwrap(), i == chunks.len() - 1);
+        }
+
+        _debug_validate_allocator();
+        assert!(!reservation.is_active());
+        assert!(get_allocated_size() < allocated);
+
+        let vec = retrieve_custom_data::<SVec<u64>>(0).unwrap().into_inner();
+        assert_eq!(vec.len(), 300_000);
+        for i in 0..300_000u64 {
+            assert_eq!(*vec.get(i).unwrap(), i);
+        }
+
+        drop(vec);
+        drop(reservation);
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn empty_memory_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        let chunks = backup();
+        assert_eq!(chunks.len(), 1);
+
+        assert!(restore_chunk(&chunks[0]).unwrap());
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn wrong_order_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        store_custom_data(0, SBox::new(10u64).unwrap());
+
+        let chunks = backup();
+        restore_chunk(&chunks[1]).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+//! Thread-local pool of heap buffers, reused by encoding and collection internals.
+//!
+//! Almost every operation on stable memory needs a temporary heap buffer to read bytes into, or to
+//! encode a value before writing it. Instead of allocating a fresh [Vec] each time, this crate takes
+//! a [PooledBuf] from the pool, and the buffer goes back to the pool, once it is dropped. After a
+//! few warm-up operations, such buffers don't allocate heap memory at all.
+//!
+//! The pool keeps at most [MAX_POOLED_BUFFERS] buffers of at most [MAX_POOLED_CAPACITY] bytes each,
+//! so it never pins much of heap memory. Bigger buffers are simply released.
+
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+
+/// Maximum number of buffers, kept by the pool
+pub const MAX_POOLED_BUFFERS: usize = 16;
+
+/// Buffers with a bigger capacity are not returned to the pool
+pub const MAX_POOLED_CAPACITY: usize = 64 * 1024;
+
+thread_local! {
+    static POOL: RefCell<Vec<Vec<u8>>> = RefCell::default();
+}
+
+/// A [Vec] of [u8], taken from the thread-local pool, which returns to the pool, when dropped
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::utils::buf_pool::{pooled_buffers_count, PooledBuf};
+/// {
+///     let mut buf = PooledBuf::zeroed(100);
+///     buf[0] = 1;
+/// }
+///
+/// assert_eq!(pooled_buffers_count(), 1);
+///
+/// // the same buffer is reused, without allocating
+/// let buf = PooledBuf::zeroed(10);
+/// assert_eq!(buf.len(), 10);
+/// assert_eq!(pooled_buffers_count(), 0);
+/// ```
+#[derive(Debug)]
+pub struct PooledBuf(Vec<u8>);
+
+impl PooledBuf {
+    /// Takes an empty buffer from the pool
+    #[inline]
+    pub fn new() -> Self {
+        Self(POOL.with(|it| it.borrow_mut().pop()).unwrap_or_default())
+    }
+
+    /// Takes a buffer from the pool and fills it with `len` zeroes
+    #[inline]
+    pub fn zeroed(len: usize) -> Self {
+        let mut it = Self::new();
+        it.0.resize(len, 0);
+
+        it
+    }
+
+    /// Detaches the underlying [Vec] from the pool
+    #[inline]
+    pub fn into_vec(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+impl Default for PooledBuf {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wraps the buffer, so it goes to the pool, once dropped
+impl From<Vec<u8>> for PooledBuf {
+    #[inline]
+    fn from(it: Vec<u8>) -> Self {
+        Self(it)
+    }
+}
+
+impl Deref for PooledBuf {
+    type Target = Vec<u8>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for PooledBuf {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        let capacity = self.0.capacity();
+        if capacity == 0 || capacity > MAX_POOLED_CAPACITY {
+            return;
+        }
+
+        let mut buf = std::mem::take(&mut self.0);
+        buf.clear();
+
+        // the pool may already be destroyed, when thread-locals are dropped
+        let _ = POOL.try_with(|it| {
+            let mut pool = it.borrow_mut();
+
+            if pool.len() < MAX_POOLED_BUFFERS {
+                pool.push(buf);
+            }
+        });
+    }
+}
+
+/// Returns the number of buffers, currently waiting in the pool
+#[inline]
+pub fn pooled_buffers_count() -> usize {
+    POOL.with(|it| it.borrow().len())
+}
+
+/// Releases all buffers, kept by the pool
+#[inline]
+pub fn clear_buf_pool() {
+    POOL.with(|it| it.borrow_mut().clear());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::{SBTreeMap, SVec};
+    use crate::utils::buf_pool::{
+        clear_buf_pool, pooled_buffers_count, PooledBuf, MAX_POOLED_BUFFERS, MAX_POOLED_CAPACITY,
+    };
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init, SBox};
+
+    #[test]
+    fn works_fine() {
+        clear_buf_pool();
+
+        let mut buf = PooledBuf::zeroed(100);
+        buf[99] = 1;
+        let ptr = buf.as_ptr();
+        drop(buf);
+
+        assert_eq!(pooled_buffers_count(), 1);
+
+        // reused and zeroed again
+        let buf = PooledBuf::zeroed(50);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_eq!(*buf, vec![0u8; 50]);
+        drop(buf);
+
+        // big and detached buffers are not pooled
+        clear_buf_pool();
+        drop(PooledBuf::zeroed(MAX_POOLED_CAPACITY + 1));
+        let vec = PooledBuf::zeroed(10).into_vec();
+        assert_eq!(vec.len(), 10);
+        assert_eq!(pooled_buffers_count(), 0);
+
+        let bufs = (0..MAX_POOLED_BUFFERS * 2)
+            .map(|_| PooledBuf::zeroed(10))
+            .collect::<Vec<_>>();
+        drop(bufs);
+        assert_eq!(pooled_buffers_count(), MAX_POOLED_BUFFERS);
+
+        clear_buf_pool();
+    }
+
+    #[test]
+    fn collections_reuse_buffers() {
+        stable::clear();
+        stable_memory_init();
+        clear_buf_pool();
+
+        {
+            let mut map = SBTreeMap::new();
+            let mut vec = SVec::new();
+
+            for i in 0..1000u64 {
+                map.insert(i, SBox::new(format!("value {}", i)).unwrap())
+                    .unwrap();
+                vec.insert(0, i).unwrap();
+            }
+
+            assert!(pooled_buffers_count() > 0);
+
+            // once the pool is warmed up, reads and writes take buffers from it and put them back
+            let count = pooled_buffers_count();
+            for i in 0..1000u64 {
+                assert_eq!(*map.get(&i).unwrap().as_str(), format!("value {}", i));
+                vec.remove(0);
+            }
+            assert_eq!(pooled_buffers_count(), count);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -225,6 +225,227 @@ impl HashTree {
             Self::Pruned(h) => *h,
         }
     }
+
+    /// Encodes this [HashTree] into self-describing CBOR
+    ///
+    /// This is exactly the encoding the IC uses for the `tree` field of a `Certificate`, so the
+    /// result can be returned from a certified query (together with [data_certificate()](ic_cdk::api::data_certificate))
+    /// and verified by [agent-js library](https://github.com/dfinity/agent-js) as is.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{labeled, leaf};
+    /// let tree = labeled(b"greeting".to_vec(), leaf(b"hello".to_vec()));
+    /// let cbor = tree.to_cbor();
+    ///
+    /// // self-describe tag
+    /// assert_eq!(&cbor[0..3], &[0xd9, 0xd9, 0xf7]);
+    /// ```
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.write_cbor(&mut buf)
+            .expect("Unable to encode HashTree to CBOR");
+
+        buf
+    }
+
+    /// Same as [HashTree::to_cbor], but writes the encoded [HashTree] into the provided writer
+    pub fn write_cbor<W: std::io::Write>(&self, writer: W) -> Result<(), serde_cbor::Error> {
+        let mut serializer = serde_cbor::Serializer::new(serde_cbor::ser::IoWrite::new(writer));
+        serializer.self_describe()?;
+
+        self.serialize(&mut serializer)
+    }
+
+    /// Replaces all nodes deeper than `max_depth` with [pruned] nodes
+    ///
+    /// The root node has depth `0`, so `prune_to_depth(0)` returns a single pruned node. The
+    /// reconstructed hash of the resulting [HashTree] stays the same.
+    pub fn prune_to_depth(self, max_depth: usize) -> HashTree {
+        match self {
+            Self::Empty | Self::Pruned(_) => self,
+            it if max_depth == 0 => pruned(it.reconstruct()),
+            Self::Fork(f) => {
+                let (l, r) = *f;
+
+                fork(
+                    l.prune_to_depth(max_depth - 1),
+                    r.prune_to_depth(max_depth - 1),
+                )
+            }
+            Self::Labeled(l, t) => labeled(l, t.prune_to_depth(max_depth - 1)),
+            it @ Self::Leaf(_) => it,
+        }
+    }
+
+    /// Replaces subtrees with [pruned] nodes, until the [CBOR encoding](HashTree::to_cbor) of
+    /// this [HashTree] fits into `max_bytes`
+    ///
+    /// Subtrees are revealed left-to-right, so the leftmost (in case of certified collections -
+    /// the smallest) keys are the ones that stay revealed. The reconstructed hash of the resulting
+    /// [HashTree] stays the same. If even a single pruned node does not fit into `max_bytes`,
+    /// returns a single pruned node.
+    pub fn prune_to_size(self, max_bytes: usize) -> HashTree {
+        self.prune_to_size_inner(max_bytes.saturating_sub(CBOR_SELF_DESCRIBE_SIZE))
+    }
+
+    fn prune_to_size_inner(self, budget: usize) -> HashTree {
+        if self.cbor_size() <= budget {
+            return self;
+        }
+
+        match self {
+            Self::Fork(f) => {
+                let (l, r) = *f;
+                let l = l.prune_to_size_inner(
+                    budget.saturating_sub(CBOR_NODE_OVERHEAD + r.min_cbor_size()),
+                );
+                let r = r
+                    .prune_to_size_inner(budget.saturating_sub(CBOR_NODE_OVERHEAD + l.cbor_size()));
+
+                let it = fork(l, r);
+                if it.cbor_size() <= budget {
+                    it
+                } else {
+                    pruned(it.reconstruct())
+                }
+            }
+            Self::Labeled(l, t) => {
+                let inner_budget =
+                    budget.saturating_sub(CBOR_NODE_OVERHEAD + cbor_bytes_size(l.len()));
+
+                if t.min_cbor_size() > inner_budget {
+                    pruned(labeled_hash(&l, &t.reconstruct()))
+                } else {
+                    labeled(l, t.prune_to_size_inner(inner_budget))
+                }
+            }
+            it => pruned(it.reconstruct()),
+        }
+    }
+
+    /// Returns a subtree, labeled with the provided path
+    ///
+    /// Each segment of the path is searched among labeled nodes of the current level (which may be
+    /// spread among multiple fork nodes). An empty path returns this [HashTree] itself.
+    pub fn lookup_subtree(&self, path: &[&[u8]]) -> Option<&HashTree> {
+        let mut it = self;
+
+        for segment in path {
+            match it.find_labeled(segment)? {
+                Self::Labeled(_, t) => it = t,
+                _ => unreachable!(),
+            }
+        }
+
+        Some(it)
+    }
+
+    /// Replaces a subtree, labeled with the provided path, with another [HashTree]
+    ///
+    /// Useful for composing certification layouts, like putting a witness of a certified collection
+    /// under `labeled(b"http_assets", ...)`. Returns the previous subtree, or [None], if there is no
+    /// such path in this [HashTree] (in that case this [HashTree] stays unchanged).
+    pub fn graft(&mut self, path: &[&[u8]], subtree: HashTree) -> Option<HashTree> {
+        let mut it = self;
+
+        for segment in path {
+            match it.find_labeled_mut(segment)? {
+                Self::Labeled(_, t) => it = t,
+                _ => unreachable!(),
+            }
+        }
+
+        Some(mem::replace(it, subtree))
+    }
+
+    /// Changes the last label of the provided path to the new one, returning the previous label
+    ///
+    /// Returns [None], if there is no such path in this [HashTree] (in that case this [HashTree]
+    /// stays unchanged). Keep in mind, that labels at the same level should stay sorted, in order
+    /// for the [HashTree] to be verifiable by lookups.
+    ///
+    /// # Panics
+    /// Panics if the path is empty.
+    pub fn relabel(&mut self, path: &[&[u8]], new_label: Vec<u8>) -> Option<Vec<u8>> {
+        let (last, path) = path.split_last().expect("The path is empty");
+
+        let mut it = self;
+
+        for segment in path {
+            match it.find_labeled_mut(segment)? {
+                Self::Labeled(_, t) => it = t,
+                _ => unreachable!(),
+            }
+        }
+
+        match it.find_labeled_mut(last)? {
+            Self::Labeled(l, _) => Some(mem::replace(l, new_label)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn find_labeled(&self, label: &[u8]) -> Option<&HashTree> {
+        match self {
+            Self::Labeled(l, _) if l.as_slice() == label => Some(self),
+            Self::Fork(f) => f.0.find_labeled(label).or_else(|| f.1.find_labeled(label)),
+            _ => None,
+        }
+    }
+
+    fn find_labeled_mut(&mut self, label: &[u8]) -> Option<&mut HashTree> {
+        match self {
+            Self::Labeled(l, _) if l.as_slice() == label => Some(self),
+            Self::Fork(f) => {
+                let (lh, rh) = &mut **f;
+
+                match lh.find_labeled_mut(label) {
+                    Some(it) => Some(it),
+                    None => rh.find_labeled_mut(label),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // the size of this tree, encoded with CBOR, without the self-describe tag
+    fn cbor_size(&self) -> usize {
+        match self {
+            Self::Empty => CBOR_NODE_OVERHEAD,
+            Self::Fork(f) => CBOR_NODE_OVERHEAD + f.0.cbor_size() + f.1.cbor_size(),
+            Self::Labeled(l, t) => CBOR_NODE_OVERHEAD + cbor_bytes_size(l.len()) + t.cbor_size(),
+            Self::Leaf(data) => CBOR_NODE_OVERHEAD + cbor_bytes_size(data.len()),
+            Self::Pruned(_) => CBOR_PRUNED_SIZE,
+        }
+    }
+
+    // the smallest size this tree can get pruned to
+    #[inline]
+    fn min_cbor_size(&self) -> usize {
+        self.cbor_size().min(CBOR_PRUNED_SIZE)
+    }
+}
+
+// self-describe tag 55799
+const CBOR_SELF_DESCRIBE_SIZE: usize = 3;
+// array header + node type tag
+const CBOR_NODE_OVERHEAD: usize = 2;
+const CBOR_PRUNED_SIZE: usize = CBOR_NODE_OVERHEAD + 2 + 32;
+
+fn cbor_bytes_size(len: usize) -> usize {
+    let header = if len < 24 {
+        1
+    } else if len <= u8::MAX as usize {
+        2
+    } else if len <= u16::MAX as usize {
+        3
+    } else if len <= u32::MAX as usize {
+        5
+    } else {
+        9
+    };
+
+    header + len
 }
 
 impl Serialize for HashTree {
@@ -279,6 +500,9 @@ fn domain_sep(s: &str) -> Sha256 {
 
 /// Trait that is used to serialize labels of a [HashTree] into bytes.
 ///
+/// Can be derived with [AsHashableBytes](crate::derive::AsHashableBytes) for types that implement
+/// [AsFixedSizeBytes](crate::AsFixedSizeBytes) - their fixed size encoding is used as is.
+///
 /// See also [SCertifiedBTreeMap](crate::collections::SCertifiedBTreeMap)
 pub trait AsHashableBytes {
     #[doc(hidden)]
@@ -301,7 +525,37 @@ impl AsHashableBytes for () {
 
 /// Trait that is used to hash a leaf value of a [HashTree].
 ///
-/// This trait should **always** be implemented on user-side.
+/// This trait should **always** be implemented on user-side. For types that implement
+/// [CandidType](candid::CandidType), it can be derived with [CandidAsHashTree](crate::derive::CandidAsHashTree) -
+/// the tree is then a single leaf, containing the candid encoding of the value.
+///
+/// # Example
+/// ```rust
+/// # use candid::{CandidType, Deserialize};
+/// # use ic_stable_memory::collections::SCertifiedBTreeMap;
+/// # use ic_stable_memory::derive::{AsFixedSizeBytes, AsHashableBytes, CandidAsDynSizeBytes, CandidAsHashTree, StableType};
+/// # use ic_stable_memory::{stable_memory_init, AsHashTree, SBox};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// #[derive(StableType, AsFixedSizeBytes, AsHashableBytes, Ord, PartialOrd, Eq, PartialEq, Debug)]
+/// struct TicketId(u64);
+///
+/// #[derive(StableType, CandidType, Deserialize, CandidAsDynSizeBytes, CandidAsHashTree, Debug)]
+/// struct Ticket {
+///     owner: String,
+///     seat: u32,
+/// }
+///
+/// let mut tickets = SCertifiedBTreeMap::<TicketId, SBox<Ticket>>::new();
+/// let ticket = Ticket { owner: String::from("Alice"), seat: 10 };
+///
+/// tickets
+///     .insert_and_commit(TicketId(1), SBox::new(ticket).expect("Out of memory"))
+///     .expect("Out of memory");
+///
+/// let witness = tickets.witness(&TicketId(1));
+/// assert_eq!(witness.reconstruct(), tickets.root_hash());
+/// ```
 ///
 /// See also [SCertifiedBTreeMap](crate::collections::SCertifiedBTreeMap)
 pub trait AsHashTree {
@@ -327,8 +581,9 @@ impl AsHashTree for () {
 mod tests {
     use crate::utils::certification::{
         domain_sep, empty, fork, fork_hash, labeled, labeled_hash, leaf, leaf_hash, pruned, Hash,
-        EMPTY_HASH,
+        HashTree, EMPTY_HASH,
     };
+    use serde::Serialize;
     use serde_test::{assert_ser_tokens, Token};
     use sha2::Digest;
 
@@ -435,4 +690,132 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn cbor_works_fine() {
+        let tree = fork(
+            labeled(b"a".to_vec(), leaf(b"hello".to_vec())),
+            fork(pruned([1u8; 32]), labeled(b"b".to_vec(), empty())),
+        );
+
+        let expected_tree = ic_certified_map::fork(
+            ic_certified_map::labeled(
+                b"a",
+                ic_certified_map::HashTree::Leaf(std::borrow::Cow::Borrowed(b"hello")),
+            ),
+            ic_certified_map::fork(
+                ic_certified_map::HashTree::Pruned([1u8; 32]),
+                ic_certified_map::labeled(b"b", ic_certified_map::HashTree::Empty),
+            ),
+        );
+
+        let mut serializer = serde_cbor::Serializer::new(Vec::new());
+        serializer.self_describe().unwrap();
+        expected_tree.serialize(&mut serializer).unwrap();
+
+        assert_eq!(tree.to_cbor(), serializer.into_inner());
+    }
+
+    fn sample_tree() -> HashTree {
+        fork(
+            labeled(
+                b"http_assets".to_vec(),
+                fork(
+                    labeled(b"/index.html".to_vec(), leaf(vec![1u8; 100])),
+                    labeled(b"/style.css".to_vec(), leaf(vec![2u8; 100])),
+                ),
+            ),
+            labeled(b"time".to_vec(), leaf(10u64.to_le_bytes().to_vec())),
+        )
+    }
+
+    #[test]
+    fn prune_to_depth_works_fine() {
+        let tree = sample_tree();
+        let root_hash = tree.reconstruct();
+
+        for depth in 0..6 {
+            let pruned_tree = tree.clone().prune_to_depth(depth);
+            assert_eq!(pruned_tree.reconstruct(), root_hash);
+        }
+
+        assert!(matches!(
+            tree.clone().prune_to_depth(0),
+            HashTree::Pruned(_)
+        ));
+        assert!(tree
+            .clone()
+            .prune_to_depth(2)
+            .lookup_subtree(&[b"time"])
+            .is_some());
+        assert!(matches!(
+            tree.clone().prune_to_depth(1).lookup_subtree(&[]),
+            Some(HashTree::Fork(_))
+        ));
+        assert!(tree.prune_to_depth(1).lookup_subtree(&[b"time"]).is_none());
+    }
+
+    #[test]
+    fn prune_to_size_works_fine() {
+        let tree = sample_tree();
+        let root_hash = tree.reconstruct();
+        let full_size = tree.to_cbor().len();
+
+        for max_bytes in 0..(full_size + 10) {
+            let pruned_tree = tree.clone().prune_to_size(max_bytes);
+            assert_eq!(pruned_tree.reconstruct(), root_hash);
+
+            if max_bytes >= 39 {
+                assert!(pruned_tree.to_cbor().len() <= max_bytes);
+            }
+        }
+
+        let pruned_tree = tree.clone().prune_to_size(full_size - 1);
+        assert!(pruned_tree
+            .lookup_subtree(&[b"http_assets", b"/index.html"])
+            .is_some());
+        assert!(matches!(
+            pruned_tree.lookup_subtree(&[b"http_assets", b"/style.css"]),
+            Some(HashTree::Pruned(_))
+        ));
+
+        assert_eq!(
+            tree.clone().prune_to_size(full_size).to_cbor().len(),
+            full_size
+        );
+    }
+
+    #[test]
+    fn graft_and_relabel_work_fine() {
+        let mut tree = sample_tree();
+
+        let prev = tree
+            .graft(&[b"http_assets", b"/style.css"], leaf(vec![3u8; 10]))
+            .unwrap();
+        assert_eq!(prev.reconstruct(), leaf_hash(&[2u8; 100]));
+        assert_eq!(
+            tree.lookup_subtree(&[b"http_assets", b"/style.css"])
+                .unwrap()
+                .reconstruct(),
+            leaf_hash(&[3u8; 10])
+        );
+
+        assert!(tree.graft(&[b"unknown"], empty()).is_none());
+
+        let prev = tree.relabel(&[b"time"], b"timestamp".to_vec()).unwrap();
+        assert_eq!(prev, b"time".to_vec());
+        assert!(tree.lookup_subtree(&[b"time"]).is_none());
+        assert!(tree.lookup_subtree(&[b"timestamp"]).is_some());
+
+        assert!(tree
+            .relabel(&[b"http_assets", b"/app.js"], vec![])
+            .is_none());
+
+        let mut root = labeled(b"http_assets".to_vec(), empty());
+        root.graft(&[b"http_assets"], tree.clone()).unwrap();
+        assert_eq!(
+            root.reconstruct(),
+            labeled_hash(b"http_assets", &tree.reconstruct())
+        );
+    }
 }
@@ -0,0 +1,160 @@
+//! Deterministic hashing for stable data structures.
+//!
+//! Hashes of stable hash-based collections are never stored, but they define, where elements are
+//! located, so they have to stay the same between canister upgrades. [zwohash](https://github.com/jix/zwohash)
+//! is deterministic, but depends on the pointer width of the target - a hash map, filled by a wasm32
+//! canister, won't be readable by the same canister compiled to wasm64. With `wasm64` feature enabled,
+//! a 64-bit version of zwohash is used on every target instead.
+
+use std::hash::Hasher;
+
+/// The hasher, used by stable hash-based collections
+#[cfg(not(feature = "wasm64"))]
+pub(crate) type StableHasher = zwohash::ZwoHasher;
+
+/// The hasher, used by stable hash-based collections
+#[cfg(feature = "wasm64")]
+pub(crate) type StableHasher = Zwo64Hasher;
+
+// same constants, as zwohash uses on 64-bit targets
+const M: u64 = 0x2545f4914f6cdd1d;
+const R: u32 = 41;
+
+/// [zwohash](https://github.com/jix/zwohash), which always works with 64-bit words
+///
+/// Produces exactly the same hashes, as the original, on 64-bit targets.
+#[derive(Default)]
+pub struct Zwo64Hasher {
+    state: u64,
+}
+
+impl Hasher for Zwo64Hasher {
+    #[inline]
+    fn finish(&self) -> u64 {
+        let wide = self.state as u128 * M as u128;
+
+        (wide as u64).wrapping_sub((wide >> u64::BITS) as u64)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        // chunks may overlap, which is fine for hashing
+        if bytes.len() >= 8 {
+            let mut bytes_left = bytes;
+            while bytes_left.len() > 8 {
+                self.write_u64(u64::from_le_bytes(bytes_left[..8].try_into().unwrap()));
+                bytes_left = &bytes_left[8..];
+            }
+
+            self.write_u64(u64::from_le_bytes(
+                bytes[bytes.len() - 8..].try_into().unwrap(),
+            ));
+        } else if bytes.len() >= 4 {
+            let low = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as u64;
+            let high = u32::from_le_bytes(bytes[bytes.len() - 4..].try_into().unwrap()) as u64;
+
+            self.write_u64(low | (high << 32));
+        } else if bytes.len() >= 2 {
+            let low = u16::from_le_bytes(bytes[..2].try_into().unwrap()) as u64;
+            let high = u16::from_le_bytes(bytes[bytes.len() - 2..].try_into().unwrap()) as u64;
+
+            self.write_u64(low | (high << 16));
+        } else if !bytes.is_empty() {
+            self.write_u64(bytes[0] as u64);
+        }
+    }
+
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.write_u64(i as u64);
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.write_u64(i as u64);
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(i as u64);
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.state = self.state.wrapping_mul(M).rotate_right(R) ^ i;
+    }
+
+    #[inline]
+    fn write_u128(&mut self, i: u128) {
+        self.write_u64(i as u64);
+        self.write_u64((i >> 64) as u64);
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    #[inline]
+    fn write_i8(&mut self, i: i8) {
+        self.write_u8(i as u8);
+    }
+
+    #[inline]
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    #[inline]
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    #[inline]
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    #[inline]
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    // sign-extended, so negative values hash the same way on 32-bit targets
+    #[inline]
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::hasher::Zwo64Hasher;
+    use std::hash::{Hash, Hasher};
+    use zwohash::ZwoHasher;
+
+    fn hash<T: Hash + ?Sized, H: Hasher + Default>(val: &T) -> u64 {
+        let mut hasher = H::default();
+        val.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn same_as_zwohash() {
+        for i in 0..1000u64 {
+            assert_eq!(hash::<_, Zwo64Hasher>(&i), hash::<_, ZwoHasher>(&i));
+            assert_eq!(
+                hash::<_, Zwo64Hasher>(&(i as usize, -(i as isize))),
+                hash::<_, ZwoHasher>(&(i as usize, -(i as isize)))
+            );
+
+            let s = "a".repeat(i as usize % 20);
+            assert_eq!(hash::<_, Zwo64Hasher>(&s), hash::<_, ZwoHasher>(&s));
+            assert_eq!(
+                hash::<_, Zwo64Hasher>(&(i as u128 * u64::MAX as u128)),
+                hash::<_, ZwoHasher>(&(i as u128 * u64::MAX as u128))
+            );
+        }
+    }
+}
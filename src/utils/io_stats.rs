@@ -0,0 +1,143 @@
+//! Counters of stable memory syscalls, enabled with `io_stats` feature.
+//!
+//! Every read, write and grow of stable memory, made by this crate, is counted, together with the
+//! number of bytes (or pages) it touched. Writes, that are still buffered by
+//! [write coalescing](crate::mem::write_coalescing), are counted once they are flushed, so the
+//! numbers always reflect the real traffic. Counters live on heap and are not persisted between
+//! canister upgrades.
+//!
+//! This is useful to find out which endpoint of a canister causes the most stable memory traffic:
+//! reset the counters in the beginning of a call and check them in the end.
+
+use candid::{CandidType, Deserialize};
+use std::cell::Cell;
+
+/// Stable memory traffic statistics, returned by [get_io_stats]
+///
+/// Implements [CandidType], so it can be returned from a metrics query as is.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct IoStats {
+    /// The number of stable memory reads
+    pub reads: u64,
+    /// The total number of bytes read from stable memory
+    pub read_bytes: u64,
+    /// The number of stable memory writes
+    pub writes: u64,
+    /// The total number of bytes written to stable memory
+    pub written_bytes: u64,
+    /// The number of successful stable memory grows
+    pub grows: u64,
+    /// The total number of pages stable memory was grown by
+    pub grown_pages: u64,
+}
+
+thread_local! {
+    static STATS: Cell<IoStats> = Cell::new(IoStats::default());
+}
+
+/// Returns stable memory traffic statistics, collected since the start (or the last [reset_io_stats])
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::utils::io_stats::{get_io_stats, reset_io_stats};
+/// # use ic_stable_memory::{stable_memory_init, SBox};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// reset_io_stats();
+///
+/// let b = SBox::new(10u64).expect("Out of memory");
+///
+/// let stats = get_io_stats();
+/// assert!(stats.writes > 0);
+/// assert!(stats.written_bytes >= 8);
+/// ```
+#[inline]
+pub fn get_io_stats() -> IoStats {
+    STATS.with(|it| it.get())
+}
+
+/// Sets all the stable memory traffic counters to zero
+#[inline]
+pub fn reset_io_stats() {
+    STATS.with(|it| it.set(IoStats::default()));
+}
+
+#[inline]
+pub(crate) fn record_read(len: usize) {
+    update(|it| {
+        it.reads += 1;
+        it.read_bytes += len as u64;
+    });
+}
+
+#[inline]
+pub(crate) fn record_write(len: usize) {
+    update(|it| {
+        it.writes += 1;
+        it.written_bytes += len as u64;
+    });
+}
+
+#[inline]
+pub(crate) fn record_grow(pages: u64) {
+    update(|it| {
+        it.grows += 1;
+        it.grown_pages += pages;
+    });
+}
+
+fn update<F: FnOnce(&mut IoStats)>(f: F) {
+    // may be called during thread shutdown, when the counters are already gone
+    let _ = STATS.try_with(|it| {
+        let mut stats = it.get();
+        f(&mut stats);
+        it.set(stats);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mem::write_coalescing::with_write_coalescing;
+    use crate::utils::io_stats::{get_io_stats, reset_io_stats, IoStats};
+    use crate::utils::mem_context::stable;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        reset_io_stats();
+
+        stable::grow(2).unwrap();
+        stable::write(0, &[1; 100]);
+
+        let mut buf = [0u8; 10];
+        stable::read(10, &mut buf);
+        stable::read(20, &mut buf);
+
+        assert_eq!(
+            get_io_stats(),
+            IoStats {
+                reads: 2,
+                read_bytes: 20,
+                writes: 1,
+                written_bytes: 100,
+                grows: 1,
+                grown_pages: 2,
+            }
+        );
+
+        reset_io_stats();
+
+        // only flushed writes are counted
+        with_write_coalescing(|| {
+            for i in 0..10 {
+                stable::write(i * 8, &[2; 8]);
+            }
+
+            assert_eq!(get_io_stats().writes, 0);
+        });
+
+        let stats = get_io_stats();
+        assert_eq!(stats.writes, 1);
+        assert_eq!(stats.written_bytes, 80);
+    }
+}
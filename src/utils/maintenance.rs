@@ -0,0 +1,412 @@
+//! Incremental background maintenance, executed in small slices.
+//!
+//! Some maintenance work is too expensive for a single message: [defragmentation](crate::defragment)
+//! of a big stable memory, [committing](crate::collections::SCertifiedBTreeMap::commit_with_budget)
+//! a large batch of certified map changes, rebuilding an oversized hash map into a smaller one. This
+//! module allows to [schedule](schedule_maintenance) such work as tasks and execute it a bit at a
+//! time from a timer (e.g. `ic_cdk_timers::set_timer_interval`) or a heartbeat, between messages of
+//! canister's users.
+//!
+//! A task is a kind (a string) and some state bytes, which its handler can use to remember where it
+//! stopped. The queue of scheduled tasks is persisted in the
+//! [allocator](crate::mem::allocator::StableMemoryAllocator), so pending work survives upgrades.
+//! Handlers are code - a canister should construct the same [Maintenance] whenever it runs the queue.
+//! Tasks are executed in a round-robin fashion: a task, which is not done yet, goes to the end of the
+//! queue.
+//!
+//! # Example
+//! ```rust
+//! use ic_stable_memory::collections::SCertifiedBTreeMap;
+//! use ic_stable_memory::utils::maintenance::{schedule_maintenance, Maintenance, MaintenanceStep};
+//! use std::cell::RefCell;
+//!
+//! const COMMIT_ACCOUNTS: &str = "commit_accounts";
+//!
+//! thread_local! {
+//!     static ACCOUNTS: RefCell<Option<SCertifiedBTreeMap<[u8; 32], ()>>> = RefCell::default();
+//! }
+//!
+//! fn maintenance() -> Maintenance {
+//!     Maintenance::new()
+//!         // only custom data is relocated automatically, other blocks stay in place
+//!         .defragment(|_, _| false)
+//!         .task(COMMIT_ACCOUNTS, |_| {
+//!             ACCOUNTS.with(|it| {
+//!                 let mut accounts = it.borrow_mut();
+//!
+//!                 if accounts.as_mut().unwrap().commit_with_budget(100) {
+//!                     MaintenanceStep::Continue
+//!                 } else {
+//!                     MaintenanceStep::Done
+//!                 }
+//!             })
+//!         })
+//! }
+//!
+//! #[ic_cdk_macros::update]
+//! fn register_accounts() {
+//!     ACCOUNTS.with(|it| {
+//!         let mut accounts = it.borrow_mut();
+//!
+//!         for i in 0..200 {
+//!             accounts.as_mut().unwrap().insert([i; 32], ()).expect("Out of memory");
+//!         }
+//!     });
+//!
+//!     schedule_maintenance(COMMIT_ACCOUNTS, Vec::new());
+//! }
+//!
+//! #[ic_cdk_macros::heartbeat]
+//! fn heartbeat() {
+//!     // at most ~1B instructions per round
+//!     maintenance().run_slice(1_000_000_000);
+//! }
+//! # unsafe { ic_stable_memory::mem::clear(); }
+//! # ic_stable_memory::stable_memory_init();
+//! # ACCOUNTS.with(|it| *it.borrow_mut() = Some(SCertifiedBTreeMap::new()));
+//! # register_accounts();
+//! # heartbeat();
+//! ```
+
+use crate::mem::StablePtr;
+use crate::utils::cost_counter;
+use crate::STABLE_MEMORY_ALLOCATOR;
+use candid::{CandidType, Deserialize};
+use std::collections::BTreeMap;
+
+/// The kind of the built-in defragmentation task, see [Maintenance::defragment]
+pub const DEFRAGMENT: &str = "defragment";
+
+// how many bytes are moved by a single defragmentation step
+const DEFRAGMENT_STEP_BYTES: u64 = 64 * 1024;
+
+/// The result of a single call of a task handler
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MaintenanceStep {
+    /// There is more work to do - the handler should be called again
+    Continue,
+    /// The task is complete and can be removed from the queue
+    Done,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, Eq, PartialEq)]
+pub(crate) struct ScheduledTask {
+    kind: String,
+    state: Vec<u8>,
+}
+
+impl ScheduledTask {
+    // both fields are length-prefixed, so the task can't take more than that, once encoded
+    #[inline]
+    pub(crate) fn encoded_size_bound(&self) -> u64 {
+        (self.kind.len() + self.state.len()) as u64 + 20
+    }
+}
+
+type Handler = Box<dyn FnMut(&mut Vec<u8>) -> MaintenanceStep>;
+
+/// Adds a task to the end of the maintenance queue
+///
+/// Returns [false] and does nothing, if a task of the same kind is already scheduled - it may
+/// already have some progress, stored in its state.
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn schedule_maintenance(kind: &str, state: Vec<u8>) -> bool {
+    with_queue(|queue| {
+        if queue.iter().any(|it| it.kind == kind) {
+            return false;
+        }
+
+        queue.push(ScheduledTask {
+            kind: String::from(kind),
+            state,
+        });
+
+        true
+    })
+}
+
+/// Removes a task of the provided kind from the maintenance queue
+///
+/// Returns [true], if the task was scheduled.
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn cancel_maintenance(kind: &str) -> bool {
+    with_queue(|queue| {
+        let len_before = queue.len();
+        queue.retain(|it| it.kind != kind);
+
+        queue.len() != len_before
+    })
+}
+
+/// Returns kinds of all scheduled tasks in execution order
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn get_scheduled_maintenance() -> Vec<String> {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            alloc
+                .get_maintenance_queue()
+                .iter()
+                .map(|it| it.kind.clone())
+                .collect()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Returns [true], if there are scheduled tasks
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+#[inline]
+pub fn is_maintenance_pending() -> bool {
+    with_queue(|queue| !queue.is_empty())
+}
+
+/// A set of handlers, which execute scheduled maintenance tasks
+///
+/// Handlers are not persisted - they are code, so a canister should construct the same set of
+/// handlers whenever it needs to run the queue.
+#[derive(Default)]
+pub struct Maintenance {
+    handlers: BTreeMap<String, Handler>,
+}
+
+impl Maintenance {
+    /// Creates an empty set of handlers
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a handler for tasks of the provided kind
+    ///
+    /// Each call of the handler should do a small chunk of work. The handler receives the state of
+    /// the task, which it can modify to remember its progress.
+    pub fn task<F: FnMut(&mut Vec<u8>) -> MaintenanceStep + 'static>(
+        mut self,
+        kind: &str,
+        handler: F,
+    ) -> Self {
+        self.handlers.insert(String::from(kind), Box::new(handler));
+
+        self
+    }
+
+    /// Adds a handler for [DEFRAGMENT] tasks
+    ///
+    /// Each step [defragments](crate::defragment) up to 64KB of memory blocks, using the provided
+    /// `relocate` callback. The task is done, once nothing can be moved anymore.
+    pub fn defragment<F: FnMut(StablePtr, StablePtr) -> bool + 'static>(
+        self,
+        mut relocate: F,
+    ) -> Self {
+        self.task(DEFRAGMENT, move |_| {
+            let report = crate::defragment(DEFRAGMENT_STEP_BYTES, &mut relocate);
+
+            if report.moved_blocks > 0 {
+                MaintenanceStep::Continue
+            } else {
+                MaintenanceStep::Done
+            }
+        })
+    }
+
+    /// Executes scheduled tasks step by step, until the queue is empty or the slice takes more than
+    /// `instructions` instructions
+    ///
+    /// At least one step is always executed. Locally (not in a canister) the slice is measured in
+    /// nanoseconds instead of instructions.
+    ///
+    /// Returns [true], if there are more tasks left to execute.
+    ///
+    /// # Panics
+    /// Panics if there is no initialized stable memory allocator, or if there is no handler for
+    /// one of the scheduled tasks.
+    pub fn run_slice(&mut self, instructions: u64) -> bool {
+        let started_at = cost_counter();
+
+        loop {
+            let kind = match with_queue(|queue| queue.first().map(|it| it.kind.clone())) {
+                Some(it) => it,
+                None => return false,
+            };
+
+            let handler = self
+                .handlers
+                .get_mut(&kind)
+                .unwrap_or_else(|| panic!("No handler for maintenance task {}", kind));
+
+            // the queue is released, so handlers could allocate and schedule other tasks
+            let mut task = with_queue(|queue| queue.remove(0));
+
+            if handler(&mut task.state) == MaintenanceStep::Continue {
+                with_queue(|queue| queue.push(task));
+            }
+
+            if cost_counter() - started_at >= instructions {
+                return is_maintenance_pending();
+            }
+        }
+    }
+}
+
+fn with_queue<R, F: FnOnce(&mut Vec<ScheduledTask>) -> R>(func: F) -> R {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.with_maintenance_queue(func)
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mem::s_slice::SSlice;
+    use crate::utils::maintenance::{
+        cancel_maintenance, get_scheduled_maintenance, is_maintenance_pending,
+        schedule_maintenance, Maintenance, MaintenanceStep, DEFRAGMENT,
+    };
+    use crate::utils::mem_context::stable;
+    use crate::{
+        _debug_validate_allocator, allocate, deallocate, get_allocated_size, get_allocator_stats,
+        stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
+    };
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // counts its steps in the state, done after 3 of them
+    fn count(state: &mut Vec<u8>) -> MaintenanceStep {
+        if state.is_empty() {
+            state.push(0);
+        }
+
+        state[0] += 1;
+
+        if state[0] == 3 {
+            MaintenanceStep::Done
+        } else {
+            MaintenanceStep::Continue
+        }
+    }
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let steps = Rc::new(RefCell::new(Vec::new()));
+            let steps_1 = steps.clone();
+            let steps_2 = steps.clone();
+
+            let maintenance = move || {
+                let steps_1 = steps_1.clone();
+                let steps_2 = steps_2.clone();
+
+                Maintenance::new()
+                    .task("a", move |state| {
+                        steps_1.borrow_mut().push("a");
+                        count(state)
+                    })
+                    .task("b", move |state| {
+                        steps_2.borrow_mut().push("b");
+                        count(state)
+                    })
+            };
+
+            assert!(!maintenance().run_slice(u64::MAX));
+
+            assert!(schedule_maintenance("a", Vec::new()));
+            assert!(schedule_maintenance("b", vec![1]));
+            assert!(!schedule_maintenance("a", Vec::new()));
+            assert!(schedule_maintenance("c", Vec::new()));
+            assert!(cancel_maintenance("c"));
+            assert!(!cancel_maintenance("c"));
+
+            assert_eq!(get_scheduled_maintenance(), vec!["a", "b"]);
+
+            // a single step per slice
+            assert!(maintenance().run_slice(0));
+            assert!(maintenance().run_slice(0));
+
+            // the queue and the state of tasks survive upgrades
+            stable_memory_pre_upgrade().unwrap();
+            stable_memory_post_upgrade();
+
+            assert!(is_maintenance_pending());
+            assert!(!maintenance().run_slice(u64::MAX));
+            assert!(!is_maintenance_pending());
+
+            assert_eq!(*steps.borrow(), vec!["a", "b", "a", "b", "a"]);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn defragment_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        let slices = (0..1000)
+            .map(|_| unsafe { allocate(100).unwrap() })
+            .collect::<Vec<_>>();
+
+        // free every other block, creating gaps
+        let slices = slices
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, slice)| {
+                if i % 2 == 0 {
+                    return Some(slice.as_ptr());
+                }
+
+                deallocate(slice);
+                None
+            })
+            .collect::<Vec<_>>();
+        let slices = Rc::new(RefCell::new(slices));
+
+        let largest_free_block_before = get_allocator_stats().largest_free_block;
+
+        let slices_1 = slices.clone();
+        let mut maintenance = Maintenance::new().defragment(move |old_ptr, new_ptr| {
+            let mut slices = slices_1.borrow_mut();
+            let idx = slices.iter().position(|it| *it == old_ptr).unwrap();
+            slices[idx] = new_ptr;
+
+            true
+        });
+
+        assert!(schedule_maintenance(DEFRAGMENT, Vec::new()));
+        while maintenance.run_slice(0) {}
+
+        assert!(get_allocator_stats().largest_free_block > largest_free_block_before);
+
+        for ptr in slices.borrow().iter() {
+            deallocate(unsafe { SSlice::from_ptr(*ptr).unwrap() });
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unknown_task_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        schedule_maintenance("unknown", Vec::new());
+        Maintenance::new().run_slice(u64::MAX);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::mem::s_slice::MAX_BLOCK_SIZE;
+
 /// Efficient ceiling division of [u64]
 ///
 /// # Important
@@ -7,6 +9,19 @@ pub fn ceil_div(a: u64, b: u64) -> u64 {
     (a + b - 1) / b
 }
 
+/// Returns the maximum number of elements of the provided size, that fit into a single memory block
+///
+/// Never exceeds [MAX_BLOCK_SIZE], even for zero-sized elements, so collection counters always fit
+/// into 40 bits.
+#[inline]
+pub const fn max_elements(elem_size: usize) -> u64 {
+    if elem_size == 0 {
+        return MAX_BLOCK_SIZE;
+    }
+
+    MAX_BLOCK_SIZE / elem_size as u64
+}
+
 /// Pseudo-randomly shuffles bits of [u32] number.
 /// Function from the "Xorshift RNGs" paper by George Marsaglia.
 #[inline]
@@ -17,6 +32,25 @@ pub fn shuffle_bits(mut seed: u32) -> u32 {
     seed
 }
 
+/// Advances the state of a "SplitMix64" pseudo-random generator, returning the next number.
+/// Unlike [shuffle_bits], produces a good sequence from any state, even `0`.
+#[inline]
+pub fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+
+    z ^ (z >> 31)
+}
+
+/// Returns the next pseudo-random number of [splitmix64] generator, scaled to `[0, 1)`
+#[inline]
+pub fn splitmix64_f64(state: &mut u64) -> f64 {
+    (splitmix64(state) >> 11) as f64 / (1u64 << 53) as f64
+}
+
 /// Constant analog to [usize::max] function
 #[inline]
 pub const fn max_usize(a: usize, b: usize) -> usize {
@@ -56,14 +56,88 @@ impl MemContext for StableMemContext {
     }
 }
 
+/// Programmable failures of the emulated stable memory, see [stable::inject_failures]
+///
+/// Unlike limiting the allocator with `init_allocator(max_pages)`, which only simulates total
+/// exhaustion, these make it possible to fail a single operation in the middle of some algorithm and
+/// check that it leaves a data structure in a consistent state.
+#[cfg(not(target_family = "wasm"))]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FailurePolicy {
+    /// Nothing fails
+    Never,
+    /// The `n`-th (starting from `1`) call to [allocate](crate::allocate) or
+    /// [reallocate](crate::reallocate), made after the policy was set, fails - all others succeed
+    NthAllocation(u64),
+    /// Stable memory can't grow beyond this number of pages
+    GrowAfterPages(u64),
+    /// Each allocation, reallocation and grow fails with the provided probability
+    Random {
+        /// Failures are pseudo-random, but always the same for the same seed
+        seed: u64,
+        /// From `0.0` (never fails) to `1.0` (always fails)
+        probability: f64,
+    },
+}
+
 #[derive(Clone)]
 pub(crate) struct TestMemContext {
     pub pages: Vec<[u8; PAGE_SIZE_BYTES as usize]>,
+    #[cfg(not(target_family = "wasm"))]
+    failure_policy: FailurePolicy,
+    #[cfg(not(target_family = "wasm"))]
+    allocations: u64,
+    #[cfg(not(target_family = "wasm"))]
+    rng_state: u64,
 }
 
 impl TestMemContext {
     const fn default() -> Self {
-        Self { pages: Vec::new() }
+        Self {
+            pages: Vec::new(),
+            #[cfg(not(target_family = "wasm"))]
+            failure_policy: FailurePolicy::Never,
+            #[cfg(not(target_family = "wasm"))]
+            allocations: 0,
+            #[cfg(not(target_family = "wasm"))]
+            rng_state: 0,
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl TestMemContext {
+    fn set_failure_policy(&mut self, policy: FailurePolicy) {
+        self.failure_policy = policy;
+        self.allocations = 0;
+        self.rng_state = match policy {
+            FailurePolicy::Random { seed, .. } => seed,
+            _ => 0,
+        };
+    }
+
+    fn should_fail_allocation(&mut self) -> bool {
+        match self.failure_policy {
+            FailurePolicy::NthAllocation(n) => {
+                self.allocations += 1;
+                self.allocations == n
+            }
+            FailurePolicy::Random { probability, .. } => self.roll(probability),
+            _ => false,
+        }
+    }
+
+    fn should_fail_grow(&mut self, new_pages: u64) -> bool {
+        match self.failure_policy {
+            FailurePolicy::GrowAfterPages(k) => self.size_pages() + new_pages > k,
+            FailurePolicy::Random { probability, .. } => self.roll(probability),
+            _ => false,
+        }
+    }
+
+    // splitmix64, so any seed (even 0) produces a good sequence
+    fn roll(&mut self, probability: f64) -> bool {
+        crate::utils::math::splitmix64_f64(&mut self.rng_state) < probability
     }
 }
 
@@ -74,6 +148,11 @@ impl MemContext for TestMemContext {
     }
 
     fn grow(&mut self, new_pages: u64) -> Result<u64, OutOfMemory> {
+        #[cfg(not(target_family = "wasm"))]
+        if self.should_fail_grow(new_pages) {
+            return Err(OutOfMemory);
+        }
+
         let prev_pages = self.size_pages();
 
         for _ in 0..new_pages {
@@ -162,23 +241,74 @@ impl MemContext for TestMemContext {
 pub mod stable {
     use crate::utils::mem_context::{MemContext, OutOfMemory, StableMemContext};
 
+    #[cfg(feature = "ic-stable-structures")]
+    use crate::mem::stable_structures::{is_backed, BackingMemContext};
+
     #[inline]
     pub fn size_pages() -> u64 {
+        #[cfg(feature = "ic-stable-structures")]
+        if is_backed() {
+            return MemContext::size_pages(&BackingMemContext);
+        }
+
         MemContext::size_pages(&StableMemContext)
     }
 
     #[inline]
     pub fn grow(new_pages: u64) -> Result<u64, OutOfMemory> {
-        MemContext::grow(&mut StableMemContext, new_pages)
+        #[cfg(feature = "ic-stable-structures")]
+        let prev_pages = if is_backed() {
+            MemContext::grow(&mut BackingMemContext, new_pages)?
+        } else {
+            MemContext::grow(&mut StableMemContext, new_pages)?
+        };
+
+        #[cfg(not(feature = "ic-stable-structures"))]
+        let prev_pages = MemContext::grow(&mut StableMemContext, new_pages)?;
+
+        #[cfg(feature = "io_stats")]
+        crate::utils::io_stats::record_grow(new_pages);
+
+        Ok(prev_pages)
     }
 
     #[inline]
     pub fn read(offset: u64, buf: &mut [u8]) {
-        MemContext::read(&StableMemContext, offset, buf)
+        #[cfg(feature = "io_stats")]
+        crate::utils::io_stats::record_read(buf.len());
+
+        #[cfg(feature = "ic-stable-structures")]
+        if is_backed() {
+            MemContext::read(&BackingMemContext, offset, buf);
+        } else {
+            MemContext::read(&StableMemContext, offset, buf);
+        }
+
+        #[cfg(not(feature = "ic-stable-structures"))]
+        MemContext::read(&StableMemContext, offset, buf);
+
+        crate::mem::write_coalescing::overlay(offset, buf);
     }
 
     #[inline]
     pub fn write(offset: u64, buf: &[u8]) {
+        if !crate::mem::write_coalescing::try_buffer(offset, buf) {
+            write_through(offset, buf);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn write_through(offset: u64, buf: &[u8]) {
+        #[cfg(feature = "io_stats")]
+        crate::utils::io_stats::record_write(buf.len());
+
+        crate::utils::transaction::journal(offset, buf.len());
+
+        #[cfg(feature = "ic-stable-structures")]
+        if is_backed() {
+            return MemContext::write(&mut BackingMemContext, offset, buf);
+        }
+
         MemContext::write(&mut StableMemContext, offset, buf)
     }
 }
@@ -188,41 +318,218 @@ pub mod stable {
     use crate::utils::mem_context::{MemContext, OutOfMemory, TestMemContext};
     use std::cell::RefCell;
 
+    pub use crate::utils::mem_context::FailurePolicy;
+
+    #[cfg(feature = "ic-stable-structures")]
+    use crate::mem::stable_structures::{is_backed, BackingMemContext};
+
     thread_local! {
         static CONTEXT: RefCell<TestMemContext> = RefCell::new(TestMemContext::default());
     }
 
+    /// Releases all emulated stable memory and resets the [FailurePolicy] to [FailurePolicy::Never]
     #[inline]
     pub fn clear() {
-        CONTEXT.with(|it| it.borrow_mut().pages.clear())
+        CONTEXT.with(|it| {
+            let mut it = it.borrow_mut();
+
+            it.pages.clear();
+            it.set_failure_policy(FailurePolicy::Never);
+        })
+    }
+
+    /// Makes the emulated stable memory fail according to the provided [FailurePolicy]
+    ///
+    /// Replaces the previous policy and resets its counters.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// use ic_stable_memory::stable::{inject_failures, FailurePolicy};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    ///
+    /// let mut vec = SVec::<u64>::new();
+    ///
+    /// inject_failures(FailurePolicy::NthAllocation(1));
+    /// assert!(vec.push(10).is_err());
+    /// assert!(vec.push(10).is_ok());
+    ///
+    /// inject_failures(FailurePolicy::Never);
+    /// ```
+    #[inline]
+    pub fn inject_failures(policy: FailurePolicy) {
+        CONTEXT.with(|it| it.borrow_mut().set_failure_policy(policy))
+    }
+
+    #[inline]
+    pub(crate) fn should_fail_allocation() -> bool {
+        CONTEXT.with(|it| it.borrow_mut().should_fail_allocation())
     }
 
     #[inline]
     pub fn size_pages() -> u64 {
+        #[cfg(feature = "ic-stable-structures")]
+        if is_backed() {
+            return BackingMemContext.size_pages();
+        }
+
         CONTEXT.with(|it| it.borrow().size_pages())
     }
 
     #[inline]
     pub fn grow(new_pages: u64) -> Result<u64, OutOfMemory> {
-        CONTEXT.with(|it| it.borrow_mut().grow(new_pages))
+        #[cfg(feature = "ic-stable-structures")]
+        let prev_pages = if is_backed() {
+            BackingMemContext.grow(new_pages)?
+        } else {
+            CONTEXT.with(|it| it.borrow_mut().grow(new_pages))?
+        };
+
+        #[cfg(not(feature = "ic-stable-structures"))]
+        let prev_pages = CONTEXT.with(|it| it.borrow_mut().grow(new_pages))?;
+
+        #[cfg(feature = "io_stats")]
+        crate::utils::io_stats::record_grow(new_pages);
+
+        Ok(prev_pages)
     }
 
     #[inline]
     pub fn read(offset: u64, buf: &mut [u8]) {
-        CONTEXT.with(|it| it.borrow().read(offset, buf))
+        #[cfg(feature = "io_stats")]
+        crate::utils::io_stats::record_read(buf.len());
+
+        #[cfg(feature = "ic-stable-structures")]
+        if is_backed() {
+            BackingMemContext.read(offset, buf);
+        } else {
+            CONTEXT.with(|it| it.borrow().read(offset, buf));
+        }
+
+        #[cfg(not(feature = "ic-stable-structures"))]
+        CONTEXT.with(|it| it.borrow().read(offset, buf));
+
+        crate::mem::write_coalescing::overlay(offset, buf);
     }
 
     #[inline]
     pub fn write(offset: u64, buf: &[u8]) {
+        if !crate::mem::write_coalescing::try_buffer(offset, buf) {
+            write_through(offset, buf);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn write_through(offset: u64, buf: &[u8]) {
+        #[cfg(feature = "io_stats")]
+        crate::utils::io_stats::record_write(buf.len());
+
+        crate::utils::transaction::journal(offset, buf.len());
+
+        #[cfg(feature = "ic-stable-structures")]
+        if is_backed() {
+            return BackingMemContext.write(offset, buf);
+        }
+
         CONTEXT.with(|it| it.borrow_mut().write(offset, buf))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{stable, PAGE_SIZE_BYTES};
+    use crate::collections::SHashMap;
+    use crate::primitive::s_box::SBox;
+    use crate::stable::{inject_failures, FailurePolicy};
+    use crate::{
+        _debug_validate_allocator, allocate, deallocate, get_allocated_size, reallocate, stable,
+        stable_memory_init, PAGE_SIZE_BYTES,
+    };
     use rand::seq::SliceRandom;
     use rand::{thread_rng, Rng};
+    use std::collections::HashMap;
+
+    #[test]
+    fn failure_injection_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        unsafe {
+            inject_failures(FailurePolicy::NthAllocation(2));
+
+            let s1 = allocate(100).unwrap();
+            assert!(allocate(100).is_err());
+            let s2 = allocate(100).unwrap();
+
+            let s2 = reallocate(s2, 200).unwrap();
+
+            deallocate(s1);
+            deallocate(s2);
+
+            inject_failures(FailurePolicy::GrowAfterPages(stable::size_pages()));
+
+            // fits into already grown memory
+            let s1 = allocate(100).unwrap();
+            assert!(allocate(PAGE_SIZE_BYTES * 2).is_err());
+            deallocate(s1);
+
+            let pattern = |seed| {
+                inject_failures(FailurePolicy::Random {
+                    seed,
+                    probability: 0.3,
+                });
+
+                (0..100)
+                    .map(|_| match allocate(10) {
+                        Ok(it) => {
+                            deallocate(it);
+                            true
+                        }
+                        Err(_) => false,
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            let p1 = pattern(42);
+            assert_eq!(p1, pattern(42));
+            assert_ne!(p1, pattern(43));
+
+            let failures = p1.iter().filter(|it| !**it).count();
+            assert!(failures > 10 && failures < 50);
+        }
+
+        {
+            inject_failures(FailurePolicy::Random {
+                seed: 0,
+                probability: 0.1,
+            });
+
+            let mut map = SHashMap::new();
+            let mut check = HashMap::new();
+
+            for i in 0..1000u64 {
+                let key = SBox::new(i);
+                let value = SBox::new(i);
+
+                if let (Ok(key), Ok(value)) = (key, value) {
+                    if map.insert(key, value).is_ok() {
+                        check.insert(i, i);
+                    }
+                }
+            }
+
+            inject_failures(FailurePolicy::Never);
+
+            assert_eq!(map.len(), check.len() as u64);
+            for (k, v) in check {
+                assert_eq!(**map.get(&k).unwrap(), v);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
     #[test]
     fn random_works_fine() {
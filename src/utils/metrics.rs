@@ -0,0 +1,360 @@
+//! All stable memory metrics of a canister in one place.
+//!
+//! [collect_metrics] gathers [allocator stats](crate::get_allocator_stats), [region stats](crate::get_region_stats),
+//! [per-tag allocated memory](crate::mem::attribution) and (with `io_stats` feature enabled)
+//! [I/O counters](crate::utils::io_stats) into a single [StableMemoryMetrics] struct. Stable collections are not registered anywhere, so their sizes
+//! should be added manually, with [StableMemoryMetrics::with_collection].
+//!
+//! The result implements [CandidType], so it can be returned from a query as is, or it can be
+//! rendered in [Prometheus text format](https://prometheus.io/docs/instrumenting/exposition_formats/)
+//! with [StableMemoryMetrics::to_prometheus] and served from an HTTP metrics endpoint.
+
+use crate::mem::allocator::AllocatorStats;
+use crate::mem::attribution::get_allocation_breakdown;
+use crate::mem::region::RegionStats;
+use crate::STABLE_MEMORY_ALLOCATOR;
+use candid::{CandidType, Deserialize};
+use std::fmt::{Display, Write};
+
+/// The number of elements in a stable collection
+#[derive(Debug, Default, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct CollectionMetrics {
+    /// The name of the collection
+    pub name: String,
+    /// The number of elements in the collection
+    pub len: u64,
+}
+
+/// Stable memory metrics, returned by [collect_metrics]
+#[derive(Debug, Default, Clone, PartialEq, CandidType, Deserialize)]
+pub struct StableMemoryMetrics {
+    /// Statistics of the whole allocator
+    pub allocator: AllocatorStats,
+    /// Statistics of each region, ordered by region names
+    pub regions: Vec<(String, RegionStats)>,
+    /// Allocated bytes of each tag, empty if the [attribution](crate::mem::attribution) is not enabled
+    pub tags: Vec<(String, u64)>,
+    /// Sizes of collections, added with [StableMemoryMetrics::with_collection]
+    pub collections: Vec<CollectionMetrics>,
+    /// Stable memory traffic counters
+    #[cfg(feature = "io_stats")]
+    pub io: crate::utils::io_stats::IoStats,
+}
+
+/// Gathers all stable memory metrics, tracked by this crate
+///
+/// Takes `O(N)` time, where `N` is the number of free blocks.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::stable_memory_init;
+/// # use ic_stable_memory::utils::metrics::collect_metrics;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut users = SVec::<u64>::new();
+/// users.push(1).expect("Out of memory");
+///
+/// let metrics = collect_metrics().with_collection("users", users.len() as u64);
+/// let text = metrics.to_prometheus();
+///
+/// assert!(text.contains("stable_collection_len{name=\"users\"} 1\n"));
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn collect_metrics() -> StableMemoryMetrics {
+    let (allocator, regions) = STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            (alloc.get_stats(), alloc.get_all_region_stats())
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    });
+
+    StableMemoryMetrics {
+        allocator,
+        regions,
+        tags: get_allocation_breakdown()
+            .map(|it| it.tagged)
+            .unwrap_or_default(),
+        collections: Vec::new(),
+        #[cfg(feature = "io_stats")]
+        io: crate::utils::io_stats::get_io_stats(),
+    }
+}
+
+impl StableMemoryMetrics {
+    /// Adds the number of elements of a stable collection to these metrics
+    pub fn with_collection(mut self, name: &str, len: u64) -> Self {
+        self.collections.push(CollectionMetrics {
+            name: String::from(name),
+            len,
+        });
+
+        self
+    }
+
+    /// Renders these metrics in Prometheus text format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = PrometheusWriter::default();
+        let a = &self.allocator;
+
+        out.metric(
+            "stable_memory_available_bytes",
+            "gauge",
+            "Stable memory, managed by the allocator",
+            &[("", a.available_size)],
+        );
+        out.metric(
+            "stable_memory_allocated_bytes",
+            "gauge",
+            "Allocated stable memory",
+            &[("", a.allocated_size)],
+        );
+        out.metric(
+            "stable_memory_free_bytes",
+            "gauge",
+            "Free stable memory",
+            &[("", a.free_size)],
+        );
+        out.metric(
+            "stable_memory_largest_free_block_bytes",
+            "gauge",
+            "The size of the largest free block",
+            &[("", a.largest_free_block)],
+        );
+        out.metric(
+            "stable_memory_free_blocks",
+            "gauge",
+            "The number of free blocks",
+            &[("", a.free_blocks_count)],
+        );
+
+        let histogram = a
+            .free_block_histogram
+            .iter()
+            .enumerate()
+            .map(|(i, count)| (label("log2_size", i), *count))
+            .collect::<Vec<_>>();
+        out.metric(
+            "stable_memory_free_blocks_by_size",
+            "gauge",
+            "The number of free blocks with size in [2^log2_size, 2^(log2_size + 1))",
+            &histogram,
+        );
+        out.metric(
+            "stable_memory_fragmentation_ratio",
+            "gauge",
+            "1 - largest free block / free memory",
+            &[("", a.fragmentation_ratio)],
+        );
+
+        let regions = |f: fn(&RegionStats) -> u64| {
+            self.regions
+                .iter()
+                .map(|(name, stats)| (label("region", name), f(stats)))
+                .collect::<Vec<_>>()
+        };
+        out.metric(
+            "stable_memory_region_available_bytes",
+            "gauge",
+            "Stable memory, taken by the region",
+            &regions(|it| it.available_size),
+        );
+        out.metric(
+            "stable_memory_region_allocated_bytes",
+            "gauge",
+            "Allocated stable memory of the region",
+            &regions(|it| it.allocated_size),
+        );
+        out.metric(
+            "stable_memory_region_free_bytes",
+            "gauge",
+            "Free stable memory of the region",
+            &regions(|it| it.free_size),
+        );
+        out.metric(
+            "stable_memory_region_quota_bytes",
+            "gauge",
+            "The maximum amount of memory the region can take, 0 means unlimited",
+            &regions(|it| it.quota),
+        );
+
+        let tags = self
+            .tags
+            .iter()
+            .map(|(tag, size)| (label("tag", tag), *size))
+            .collect::<Vec<_>>();
+        out.metric(
+            "stable_memory_tag_allocated_bytes",
+            "gauge",
+            "Stable memory, allocated with the tag",
+            &tags,
+        );
+
+        let collections = self
+            .collections
+            .iter()
+            .map(|it| (label("name", &it.name), it.len))
+            .collect::<Vec<_>>();
+        out.metric(
+            "stable_collection_len",
+            "gauge",
+            "The number of elements in the collection",
+            &collections,
+        );
+
+        #[cfg(feature = "io_stats")]
+        {
+            let io = &self.io;
+
+            out.metric(
+                "stable_memory_reads_total",
+                "counter",
+                "Stable memory reads",
+                &[("", io.reads)],
+            );
+            out.metric(
+                "stable_memory_read_bytes_total",
+                "counter",
+                "Bytes read from stable memory",
+                &[("", io.read_bytes)],
+            );
+            out.metric(
+                "stable_memory_writes_total",
+                "counter",
+                "Stable memory writes",
+                &[("", io.writes)],
+            );
+            out.metric(
+                "stable_memory_written_bytes_total",
+                "counter",
+                "Bytes written to stable memory",
+                &[("", io.written_bytes)],
+            );
+            out.metric(
+                "stable_memory_grows_total",
+                "counter",
+                "Stable memory grows",
+                &[("", io.grows)],
+            );
+            out.metric(
+                "stable_memory_grown_pages_total",
+                "counter",
+                "Pages stable memory was grown by",
+                &[("", io.grown_pages)],
+            );
+        }
+
+        out.0
+    }
+}
+
+// renders a label set with a single label, escaping its value
+fn label<T: Display>(name: &str, value: T) -> String {
+    let mut escaped = String::new();
+
+    for c in value.to_string().chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+
+    format!("{{{}=\"{}\"}}", name, escaped)
+}
+
+#[derive(Default)]
+struct PrometheusWriter(String);
+
+impl PrometheusWriter {
+    // metrics without samples are skipped
+    fn metric<L: AsRef<str>, V: Display>(
+        &mut self,
+        name: &str,
+        kind: &str,
+        help: &str,
+        samples: &[(L, V)],
+    ) {
+        if samples.is_empty() {
+            return;
+        }
+
+        writeln!(self.0, "# HELP {} {}", name, help).unwrap();
+        writeln!(self.0, "# TYPE {} {}", name, kind).unwrap();
+
+        for (labels, value) in samples {
+            writeln!(self.0, "{}{} {}", name, labels.as_ref(), value).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SBTreeMap;
+    use crate::mem::attribution::enable_allocation_attribution;
+    use crate::utils::mem_context::stable;
+    use crate::utils::metrics::{collect_metrics, label};
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, init_region, stable_memory_init,
+        with_allocation_tag, with_region,
+    };
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            init_region("users", 0);
+
+            enable_allocation_attribution();
+
+            let mut map = with_region("users", SBTreeMap::<u64, u64>::new);
+            for i in 0..100u64 {
+                with_allocation_tag("users", || map.insert(i, i)).unwrap();
+            }
+
+            let metrics = collect_metrics().with_collection("users \"map\"", map.len());
+
+            assert_eq!(metrics.regions.len(), 1);
+            assert_eq!(metrics.regions[0].0, "users");
+            assert_eq!(metrics.tags[0].0, "users");
+            assert_eq!(metrics.collections[0].len, 100);
+
+            let text = metrics.to_prometheus();
+
+            assert!(text.contains("# TYPE stable_memory_allocated_bytes gauge\n"));
+            assert!(text.contains(&format!(
+                "stable_memory_allocated_bytes {}\n",
+                metrics.allocator.allocated_size
+            )));
+            assert!(text.contains(&format!(
+                "stable_memory_region_allocated_bytes{{region=\"users\"}} {}\n",
+                metrics.regions[0].1.allocated_size
+            )));
+            assert!(text.contains(&format!(
+                "stable_memory_tag_allocated_bytes{{tag=\"users\"}} {}\n",
+                metrics.tags[0].1
+            )));
+            assert!(text.contains("stable_collection_len{name=\"users \\\"map\\\"\"} 100\n"));
+
+            for line in text.lines().filter(|it| !it.starts_with('#')) {
+                let value = line.rsplit(' ').next().unwrap();
+                assert!(value.parse::<f64>().is_ok(), "{}", line);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn label_escaping_works_fine() {
+        assert_eq!(label("name", "a\\b\"c\nd"), "{name=\"a\\\\b\\\"c\\nd\"}");
+        assert_eq!(label("log2_size", 10), "{log2_size=\"10\"}");
+    }
+}
@@ -0,0 +1,392 @@
+//! Versioned schema migrations, that can run in chunks across multiple messages.
+//!
+//! Enum-versioned values inside [SBox](crate::SBox)-es are enough to evolve individual values, but
+//! structural changes, like re-keying a map or splitting a collection in two, have to touch every
+//! element of a collection. This module keeps a schema version for every string key (usually the
+//! same key the data is [stored with](crate::store_named_custom_data)) in the
+//! [allocator](crate::mem::allocator::StableMemoryAllocator), so it is persisted between upgrades
+//! together with the data itself.
+//!
+//! [Migrations] is a list of `migrate_vN_to_vN+1` functions for a key. Each call of such a function
+//! processes a chunk of work and reports whether it is [done](MigrationStep::Done). The version is
+//! bumped only after a migration is done, so a migration that is too big for a single message can be
+//! continued in the next ones (or even after another upgrade). Taking a
+//! [snapshot](crate::utils::snapshot) before a risky migration allows to roll it back.
+//!
+//! Schema versions start from `1`: data without a version, e.g. stored before migrations were
+//! introduced to a canister, is considered to have version `1`.
+//!
+//! # Example
+//! ```rust
+//! use ic_stable_memory::collections::SBTreeMap;
+//! use ic_stable_memory::utils::migration::{MigrationStep, Migrations};
+//! use ic_stable_memory::{
+//!     retrieve_named_custom_data, store_named_custom_data, AsDynSizeBytes, SBox, StableType,
+//! };
+//!
+//! const BALANCES: &str = "balances";
+//! const BALANCES_V2: &str = "balances_v2";
+//!
+//! // v1 keyed balances by u64 account ids, v2 keys them by u128 ones
+//! fn migrate_v1_to_v2() -> MigrationStep {
+//!     let mut old = retrieve_named_custom_data::<SBTreeMap<u64, u64>>(BALANCES)
+//!         .unwrap()
+//!         .into_inner();
+//!     let mut new = match retrieve_named_custom_data::<SBTreeMap<u128, u64>>(BALANCES_V2) {
+//!         Ok(it) => it.into_inner(),
+//!         Err(_) => SBTreeMap::new(),
+//!     };
+//!
+//!     // moving at most 100 entries per call
+//!     let keys = old.iter().take(100).map(|(k, _)| *k).collect::<Vec<_>>();
+//!     for key in keys {
+//!         let balance = old.remove(&key).unwrap();
+//!         new.insert(key as u128, balance).expect("Out of memory");
+//!     }
+//!
+//!     if old.is_empty() {
+//!         store(BALANCES, new);
+//!
+//!         MigrationStep::Done
+//!     } else {
+//!         store(BALANCES, old);
+//!         store(BALANCES_V2, new);
+//!
+//!         MigrationStep::Continue
+//!     }
+//! }
+//!
+//! fn store<T: StableType + AsDynSizeBytes>(key: &'static str, data: T) {
+//!     let boxed = SBox::new(data).unwrap_or_else(|_| panic!("Out of memory"));
+//!     store_named_custom_data(key, boxed).unwrap_or_else(|_| panic!("Key {} is occupied", key));
+//! }
+//!
+//! fn migrations() -> Migrations {
+//!     Migrations::new(BALANCES).migration(1, migrate_v1_to_v2)
+//! }
+//!
+//! #[ic_cdk_macros::post_upgrade]
+//! fn post_upgrade() {
+//!     ic_stable_memory::stable_memory_post_upgrade();
+//!
+//!     migrations().run_chunk();
+//! }
+//!
+//! #[ic_cdk_macros::heartbeat]
+//! fn heartbeat() {
+//!     let migrations = migrations();
+//!
+//!     if migrations.is_pending() {
+//!         migrations.run_chunk();
+//!     }
+//! }
+//! # unsafe { ic_stable_memory::mem::clear(); }
+//! # ic_stable_memory::stable_memory_init();
+//! # let mut balances = SBTreeMap::<u64, u64>::new();
+//! # for i in 0..250 { balances.insert(i, i).unwrap(); }
+//! # store(BALANCES, balances);
+//! # ic_stable_memory::stable_memory_pre_upgrade().unwrap();
+//! # post_upgrade();
+//! # while migrations().is_pending() { heartbeat(); }
+//! # assert_eq!(migrations().current_version(), 2);
+//! # let balances = retrieve_named_custom_data::<SBTreeMap<u128, u64>>(BALANCES).unwrap();
+//! # assert_eq!(balances.len(), 250);
+//! ```
+
+use crate::STABLE_MEMORY_ALLOCATOR;
+
+/// Returns the schema version of the data under the key, or [None] if it was never set
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn get_schema_version(key: &str) -> Option<u32> {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            alloc.get_schema_version(key)
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Sets the schema version of the data under the key
+///
+/// Usually there is no need to call it directly - [Migrations] manage versions automatically.
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn set_schema_version(key: &str, version: u32) {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.set_schema_version(key, version)
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// The result of a single call of a migration function
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MigrationStep {
+    /// There is more work to do - the function should be called again
+    Continue,
+    /// The migration is complete
+    Done,
+}
+
+/// An ordered list of migrations of the data under a single key
+///
+/// Migrations are not persisted - they are code, so a canister should construct the same list
+/// whenever it needs to run them.
+pub struct Migrations {
+    key: &'static str,
+    migrations: Vec<fn() -> MigrationStep>,
+}
+
+impl Migrations {
+    /// Creates an empty list of migrations of the data under the key
+    #[inline]
+    pub fn new(key: &'static str) -> Self {
+        Self {
+            key,
+            migrations: Vec::new(),
+        }
+    }
+
+    /// Adds a function, migrating the data from `from_version` to `from_version + 1`
+    ///
+    /// # Panics
+    /// Panics if migrations are not added one after another, starting from version `1`.
+    pub fn migration(mut self, from_version: u32, migrate: fn() -> MigrationStep) -> Self {
+        assert_eq!(
+            from_version,
+            self.latest_version(),
+            "Migrations should be added in order"
+        );
+
+        self.migrations.push(migrate);
+
+        self
+    }
+
+    /// Returns the version the data will have, once all migrations are done
+    #[inline]
+    pub fn latest_version(&self) -> u32 {
+        self.migrations.len() as u32 + 1
+    }
+
+    /// Returns the persisted version of the data, or `1` if there is none
+    #[inline]
+    pub fn current_version(&self) -> u32 {
+        get_schema_version(self.key).unwrap_or(1)
+    }
+
+    /// Returns [true], if some of the migrations are not done yet
+    #[inline]
+    pub fn is_pending(&self) -> bool {
+        self.current_version() < self.latest_version()
+    }
+
+    /// Marks the data as being of the latest version, skipping all migrations
+    ///
+    /// Should be called in the `#[init]` canister method, since there is nothing to migrate yet.
+    #[inline]
+    pub fn init(&self) {
+        set_schema_version(self.key, self.latest_version());
+    }
+
+    /// Calls the current migration function once
+    ///
+    /// If the migration is done, bumps the version. Returns [MigrationStep::Done], if there are no
+    /// migrations left after this call.
+    ///
+    /// # Panics
+    /// Panics if the persisted version is greater than [Migrations::latest_version] (e.g. after a
+    /// downgrade).
+    pub fn run_chunk(&self) -> MigrationStep {
+        let version = self.current_version();
+
+        assert!(
+            version <= self.latest_version(),
+            "Data under key {} has version {}, which is newer than the latest known version {}",
+            self.key,
+            version,
+            self.latest_version()
+        );
+
+        if version == self.latest_version() {
+            return MigrationStep::Done;
+        }
+
+        if (self.migrations[version as usize - 1])() == MigrationStep::Done {
+            set_schema_version(self.key, version + 1);
+        }
+
+        if self.is_pending() {
+            MigrationStep::Continue
+        } else {
+            MigrationStep::Done
+        }
+    }
+
+    /// Runs all pending migrations to the end in a single message
+    ///
+    /// Only suitable if the amount of data is small enough to fit into the instruction limit.
+    pub fn run(&self) {
+        while self.run_chunk() == MigrationStep::Continue {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::{SBTreeMap, SVec};
+    use crate::utils::mem_context::stable;
+    use crate::utils::migration::{
+        get_schema_version, set_schema_version, MigrationStep, Migrations,
+    };
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, retrieve_named_custom_data,
+        stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
+        store_named_custom_data, SBox,
+    };
+
+    const KEY: &str = "users";
+    const TMP_KEY: &str = "users_tmp";
+
+    // v1: a vec of user ids
+    // v2: a map from user id to its index in the vec
+    // v3: a map from user id to its index + 1
+    fn migrate_v1_to_v2() -> MigrationStep {
+        let mut vec = retrieve_named_custom_data::<SVec<u64>>(KEY)
+            .unwrap()
+            .into_inner();
+        let mut map = match retrieve_named_custom_data::<SBTreeMap<u64, u64>>(TMP_KEY) {
+            Ok(it) => it.into_inner(),
+            Err(_) => SBTreeMap::new(),
+        };
+
+        for _ in 0..10 {
+            match vec.pop() {
+                Some(id) => {
+                    map.insert(id, vec.len()).unwrap();
+                }
+                None => break,
+            }
+        }
+
+        if vec.is_empty() {
+            store_named_custom_data(KEY, SBox::new(map).unwrap()).unwrap_or_else(|_| panic!());
+
+            MigrationStep::Done
+        } else {
+            store_named_custom_data(KEY, SBox::new(vec).unwrap()).unwrap_or_else(|_| panic!());
+            store_named_custom_data(TMP_KEY, SBox::new(map).unwrap()).unwrap_or_else(|_| panic!());
+
+            MigrationStep::Continue
+        }
+    }
+
+    fn migrate_v2_to_v3() -> MigrationStep {
+        let mut map = retrieve_named_custom_data::<SBTreeMap<u64, u64>>(KEY)
+            .unwrap()
+            .into_inner();
+
+        for i in 0..map.len() {
+            *map.get_mut(&(i * 2)).unwrap() += 1;
+        }
+
+        store_named_custom_data(KEY, SBox::new(map).unwrap()).unwrap_or_else(|_| panic!());
+
+        MigrationStep::Done
+    }
+
+    fn migrations() -> Migrations {
+        Migrations::new(KEY)
+            .migration(1, migrate_v1_to_v2)
+            .migration(2, migrate_v2_to_v3)
+    }
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::new();
+            for i in 0..25u64 {
+                vec.push(i * 2).unwrap();
+            }
+
+            store_named_custom_data(KEY, SBox::new(vec).unwrap()).unwrap_or_else(|_| panic!());
+
+            let migrations = migrations();
+            assert_eq!(migrations.latest_version(), 3);
+            assert_eq!(migrations.current_version(), 1);
+            assert!(get_schema_version(KEY).is_none());
+
+            assert_eq!(migrations.run_chunk(), MigrationStep::Continue);
+            assert_eq!(migrations.run_chunk(), MigrationStep::Continue);
+            assert_eq!(migrations.current_version(), 1);
+
+            // the migration continues after an upgrade
+            stable_memory_pre_upgrade().unwrap();
+            stable_memory_post_upgrade();
+
+            assert_eq!(migrations.run_chunk(), MigrationStep::Continue);
+            assert_eq!(get_schema_version(KEY), Some(2));
+
+            assert_eq!(migrations.run_chunk(), MigrationStep::Done);
+            assert_eq!(migrations.current_version(), 3);
+            assert!(!migrations.is_pending());
+
+            assert_eq!(migrations.run_chunk(), MigrationStep::Done);
+
+            let map = retrieve_named_custom_data::<SBTreeMap<u64, u64>>(KEY)
+                .unwrap()
+                .into_inner();
+            assert_eq!(map.len(), 25);
+            for i in 0..25u64 {
+                assert_eq!(*map.get(&(i * 2)).unwrap(), i + 1);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn init_and_run_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let migrations = migrations();
+            migrations.init();
+
+            assert_eq!(get_schema_version(KEY), Some(3));
+            assert!(!migrations.is_pending());
+
+            set_schema_version(KEY, 1);
+
+            let mut vec = SVec::new();
+            for i in 0..25u64 {
+                vec.push(i * 2).unwrap();
+            }
+            store_named_custom_data(KEY, SBox::new(vec).unwrap()).unwrap_or_else(|_| panic!());
+
+            migrations.run();
+            assert_eq!(migrations.current_version(), 3);
+
+            retrieve_named_custom_data::<SBTreeMap<u64, u64>>(KEY).unwrap();
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_order_migrations_panic() {
+        Migrations::new(KEY).migration(2, migrate_v2_to_v3);
+    }
+}
@@ -1,12 +1,24 @@
 //! Various utilities used by this crate
 
+pub mod backup;
+pub mod buf_pool;
 #[doc(hidden)]
 pub mod certification;
 #[doc(hidden)]
+pub mod hasher;
+#[cfg(feature = "io_stats")]
+pub mod io_stats;
+pub mod maintenance;
+#[doc(hidden)]
 pub mod math;
 pub mod mem_context;
+pub mod metrics;
+pub mod migration;
 #[cfg(test)]
 pub mod test;
+pub mod snapshot;
+pub mod stable_state;
+pub mod transaction;
 
 #[cfg(target_family = "wasm")]
 use ic_cdk::print;
@@ -24,6 +36,22 @@ pub fn isoprint(str: &str) {
     println!("{}", str)
 }
 
+// the number of instructions, executed in the current message; locally - wall time in nanoseconds
+#[cfg(target_family = "wasm")]
+#[inline]
+pub(crate) fn cost_counter() -> u64 {
+    ic_cdk::api::performance_counter(0)
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[inline]
+pub(crate) fn cost_counter() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
 /// Unwraps a [Result], but does not require [Debug] to be implemented on `T`
 pub trait DebuglessUnwrap<T> {
     #[doc(hidden)]
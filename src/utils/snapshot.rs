@@ -0,0 +1,192 @@
+//! Snapshots, that allow rolling the whole stable memory back to a previously saved state.
+//!
+//! Unlike [transactions](crate::utils::transaction), which journal each write on heap and only live
+//! within a single call, a snapshot is a full copy of the memory, managed by the
+//! [allocator](crate::mem::allocator::StableMemoryAllocator), kept in stable memory itself. It
+//! survives between messages and canister upgrades, until it is discarded. This makes it suitable for
+//! "try this risky migration, revert on failure" flows, which can take multiple messages.
+//!
+//! Only one snapshot can exist at a time.
+
+use crate::primitive::s_box::clear_decode_cache;
+use crate::{OutOfMemory, STABLE_MEMORY_ALLOCATOR};
+
+/// Saves a copy of all the memory, managed by the allocator, replacing the previous snapshot
+///
+/// The copy is stored in a single memory block, which is a bit bigger, than the managed memory
+/// itself, so a snapshot roughly doubles the amount of used stable memory. If it is impossible to
+/// allocate such a memory block, returns [OutOfMemory]. Copying takes time proportional to the
+/// amount of stable memory, so keep instruction limits in mind for large canisters.
+///
+/// The snapshot is kept until [discard_snapshot] is called, even after it was restored.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::{discard_snapshot, restore_snapshot, retrieve_custom_data, stable_memory_init, store_custom_data, take_snapshot, SBox};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut vec = SVec::<u64>::new();
+/// vec.push(1).expect("Out of memory");
+///
+/// // heap handles are not restored, so the state should be reachable from custom data
+/// store_custom_data(0, SBox::new(vec).expect("Out of memory"));
+/// take_snapshot().expect("Out of memory");
+///
+/// let mut vec = retrieve_custom_data::<SVec<u64>>(0).unwrap().into_inner();
+/// vec.clear();
+/// store_custom_data(0, SBox::new(vec).expect("Out of memory"));
+///
+/// // the migration went wrong - rolling back
+/// assert!(restore_snapshot());
+/// assert!(discard_snapshot());
+///
+/// let vec = retrieve_custom_data::<SVec<u64>>(0).unwrap().into_inner();
+/// assert_eq!(*vec.get(0).unwrap(), 1);
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn take_snapshot() -> Result<(), OutOfMemory> {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.take_snapshot()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Rolls stable memory and the allocator back to the state they had, when [take_snapshot] was called
+///
+/// Returns [false], if there is no snapshot. Only stable memory is restored - all stable data
+/// structures on heap become invalid and should be forgotten (e.g. with [std::mem::forget]) instead
+/// of being dropped. Read them again from [custom data](crate::retrieve_custom_data) after that.
+///
+/// Restoring doesn't trigger [allocation hooks](crate::mem::hooks), so it invalidates anything
+/// they track.
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn restore_snapshot() -> bool {
+    let restored = STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.restore_snapshot()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    });
+
+    if restored {
+        clear_decode_cache();
+    }
+
+    restored
+}
+
+/// Releases the memory taken by the snapshot, returning [false], if there is no snapshot
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn discard_snapshot() -> bool {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.discard_snapshot()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Returns [true], if there is a snapshot, that can be restored
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn has_snapshot() -> bool {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            alloc.has_snapshot()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SBTreeMap;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::utils::snapshot::{discard_snapshot, has_snapshot, restore_snapshot, take_snapshot};
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, init_region, retrieve_custom_data,
+        stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
+        store_custom_data, with_region,
+    };
+
+    type Map = SBTreeMap<u64, SBox<String>>;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        assert!(!restore_snapshot());
+        assert!(!discard_snapshot());
+
+        {
+            init_region("users", 0);
+
+            let mut map = with_region("users", Map::new);
+            for i in 0..100u64 {
+                map.insert(i, SBox::new(format!("{}", i)).unwrap()).unwrap();
+            }
+
+            store_custom_data(0, SBox::new(map).unwrap());
+            let allocated = get_allocated_size();
+
+            take_snapshot().unwrap();
+            assert!(has_snapshot());
+            _debug_validate_allocator();
+
+            // snapshots survive upgrades
+            stable_memory_pre_upgrade().unwrap();
+            stable_memory_post_upgrade();
+            assert!(has_snapshot());
+
+            let mut map = retrieve_custom_data::<Map>(0).unwrap().into_inner();
+            for i in 0..50u64 {
+                map.remove(&i);
+            }
+            for i in 100..10_000u64 {
+                map.insert(i, SBox::new(format!("{}", i)).unwrap()).unwrap();
+            }
+            store_custom_data(0, SBox::new(map).unwrap());
+
+            // it is possible to restore the same snapshot multiple times
+            for _ in 0..2 {
+                assert!(restore_snapshot());
+                _debug_validate_allocator();
+
+                let mut map = retrieve_custom_data::<Map>(0).unwrap().into_inner();
+                assert_eq!(map.len(), 100);
+                for i in 0..100u64 {
+                    assert_eq!(*map.get(&i).unwrap().clone(), format!("{}", i));
+                }
+
+                map.insert(100, SBox::new(String::from("100")).unwrap())
+                    .unwrap();
+                store_custom_data(0, SBox::new(map).unwrap());
+            }
+
+            assert!(discard_snapshot());
+            assert!(!has_snapshot());
+            assert!(get_allocated_size() > allocated);
+
+            retrieve_custom_data::<Map>(0).unwrap();
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
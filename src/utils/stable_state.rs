@@ -0,0 +1,183 @@
+//! The root state of a canister, persisted between upgrades automatically.
+//!
+//! Almost every canister, built with this crate, keeps its stable collections in a single root state
+//! struct, stored in a `thread_local!` variable, and moves it in and out of stable memory during
+//! upgrades with [store_custom_data](crate::store_custom_data) and [retrieve_custom_data](crate::retrieve_custom_data).
+//! [StableState] implements this routine once. Usually it is not used directly - instead the
+//! [stable_state](crate::derive::stable_state) attribute macro is applied to the root state struct.
+//!
+//! # Example
+//! ```rust
+//! use ic_stable_memory::collections::{SBTreeMap, SVec};
+//! use ic_stable_memory::derive::{stable_state, AsFixedSizeBytes, StableType};
+//!
+//! #[stable_state]
+//! #[derive(StableType, AsFixedSizeBytes)]
+//! struct State {
+//!     tasks: SVec<u64>,
+//!     owners: SBTreeMap<u64, u64>,
+//! }
+//!
+//! #[ic_cdk_macros::init]
+//! fn init() {
+//!     init_state(State {
+//!         tasks: SVec::new(),
+//!         owners: SBTreeMap::new(),
+//!     });
+//! }
+//!
+//! #[ic_cdk_macros::pre_upgrade]
+//! fn pre_upgrade() {
+//!     pre_upgrade_state();
+//! }
+//!
+//! #[ic_cdk_macros::post_upgrade]
+//! fn post_upgrade() {
+//!     post_upgrade_state();
+//! }
+//!
+//! #[ic_cdk_macros::update]
+//! fn add_task(task: u64, owner: u64) {
+//!     let mut state = state_mut();
+//!
+//!     state.tasks.push(task).expect("Out of memory");
+//!     state.owners.insert(task, owner).expect("Out of memory");
+//! }
+//! # unsafe { ic_stable_memory::mem::clear(); }
+//! # init_state(State { tasks: SVec::new(), owners: SBTreeMap::new() });
+//! # state_mut().tasks.push(10).unwrap();
+//! # pre_upgrade_state();
+//! # post_upgrade_state();
+//! # assert_eq!(*state().tasks.get(0).unwrap(), 10);
+//! ```
+
+use crate::encoding::AsDynSizeBytes;
+use crate::primitive::s_box::SBox;
+use crate::primitive::StableType;
+use crate::{
+    retrieve_named_custom_data, stable_memory_init, stable_memory_post_upgrade,
+    stable_memory_pre_upgrade, store_named_custom_data,
+};
+use std::cell::{Ref, RefCell, RefMut};
+
+/// A holder of the root state of a canister
+///
+/// Between upgrades the state is persisted with [store_named_custom_data] under the provided key, so
+/// the key should not be used for anything else.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::utils::stable_state::StableState;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// thread_local! {
+///     static STATE: &'static StableState<SVec<u64>> = Box::leak(Box::new(StableState::new("state")));
+/// }
+///
+/// let state = STATE.with(|it| *it);
+///
+/// // in #[init]
+/// state.init(SVec::new());
+/// state.get_mut().push(10).expect("Out of memory");
+///
+/// // in #[pre_upgrade] and #[post_upgrade]
+/// state.pre_upgrade();
+/// state.post_upgrade();
+///
+/// assert_eq!(*state.get().get(0).unwrap(), 10);
+/// ```
+pub struct StableState<T> {
+    key: &'static str,
+    state: RefCell<Option<T>>,
+}
+
+impl<T: StableType + AsDynSizeBytes> StableState<T> {
+    /// Creates an empty holder, which persists the state under the provided key
+    #[inline]
+    pub const fn new(key: &'static str) -> Self {
+        Self {
+            key,
+            state: RefCell::new(None),
+        }
+    }
+
+    /// Initializes stable memory and sets the state
+    ///
+    /// Should be called in the `#[init]` canister method.
+    ///
+    /// # Panics
+    /// Panics if stable memory is already initialized.
+    pub fn init(&self, state: T) {
+        stable_memory_init();
+
+        *self.state.borrow_mut() = Some(state);
+    }
+
+    /// Moves the state to stable memory and persists the allocator
+    ///
+    /// Should be called as the last step of the `#[pre_upgrade]` canister method.
+    ///
+    /// # Panics
+    /// Panics if there is no state, or if the canister is out of stable memory - a trap in
+    /// `#[pre_upgrade]` cancels the upgrade, so the state stays intact.
+    pub fn pre_upgrade(&self) {
+        let state = self
+            .state
+            .borrow_mut()
+            .take()
+            .expect("Stable state is not initialized");
+
+        let boxed_state = match SBox::new(state) {
+            Ok(it) => it,
+            Err(_) => panic!("Out of memory"),
+        };
+        if store_named_custom_data(self.key, boxed_state).is_err() {
+            panic!("Custom data key {} is occupied", self.key);
+        }
+
+        stable_memory_pre_upgrade().expect("Out of memory");
+    }
+
+    /// Retrieves the allocator and the state from stable memory
+    ///
+    /// Should be called as the first step of the `#[post_upgrade]` canister method.
+    ///
+    /// # Panics
+    /// Panics if the state was not persisted with [StableState::pre_upgrade] or has a different type.
+    pub fn post_upgrade(&self) {
+        stable_memory_post_upgrade();
+
+        let state = match retrieve_named_custom_data::<T>(self.key) {
+            Ok(it) => it.into_inner(),
+            Err(e) => panic!("Unable to retrieve stable state {}: {:?}", self.key, e),
+        };
+
+        *self.state.borrow_mut() = Some(state);
+    }
+
+    /// Returns [true], if the state is set
+    #[inline]
+    pub fn is_initialized(&self) -> bool {
+        self.state.borrow().is_some()
+    }
+
+    /// Borrows the state
+    ///
+    /// # Panics
+    /// Panics if the state is not set, or if it is mutably borrowed.
+    pub fn get(&self) -> Ref<'_, T> {
+        Ref::map(self.state.borrow(), |it| {
+            it.as_ref().expect("Stable state is not initialized")
+        })
+    }
+
+    /// Mutably borrows the state
+    ///
+    /// # Panics
+    /// Panics if the state is not set, or if it is already borrowed.
+    pub fn get_mut(&self) -> RefMut<'_, T> {
+        RefMut::map(self.state.borrow_mut(), |it| {
+            it.as_mut().expect("Stable state is not initialized")
+        })
+    }
+}
@@ -0,0 +1,353 @@
+//! Transactions, that allow rolling back all changes made to stable memory.
+//!
+//! While a transaction is active, each write to stable memory is journaled - previous contents of
+//! the memory region are saved on heap before they get overwritten. If the transaction is not
+//! committed, the journal is replayed in reverse order, together with restoring the state of the
+//! [allocator](crate::mem::allocator::StableMemoryAllocator).
+
+use crate::encoding::{AsFixedSizeBytes, Buffer};
+use crate::mem::allocator::StableMemoryAllocator;
+use crate::primitive::s_box::clear_decode_cache;
+use crate::primitive::StableType;
+use crate::{stable, STABLE_MEMORY_ALLOCATOR};
+use std::cell::{Cell, RefCell};
+
+thread_local! {
+    static JOURNAL: RefCell<Vec<(u64, Vec<u8>)>> = RefCell::default();
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Runs the lambda function in a transaction over the provided stable data structure
+///
+/// If the lambda returns [Ok], all the changes are committed. If it returns [Err] or panics, every
+/// write to stable memory made since the start of the transaction is rolled back, memory allocated
+/// during the transaction is released and `state` is restored to its previous value. Transactions
+/// can be nested - the outer one can roll back changes committed by the inner one.
+///
+/// Only stable memory and the allocator are transactional - heap values, other than `state`, are
+/// not restored. So any stable data structure modified inside the lambda should either be passed as
+/// `state`, or be reachable from it (e.g. be stored inside it). When `state` is a reference obtained
+/// from another stable collection (via [SRefMut](crate::primitive::s_ref_mut::SRefMut)), the
+/// restored value is written back once the reference is dropped, as usual. Stable data
+/// structures created inside a rolled back transaction should not escape it (e.g. via [Err]).
+///
+/// Keep in mind, that on a canister a trap rolls back the whole message anyway, so this is mostly
+/// useful for recoverable errors, like [OutOfMemory](crate::OutOfMemory) in the middle of a batch
+/// of updates.
+///
+/// A rollback doesn't trigger [allocation hooks](crate::mem::hooks) - memory blocks, allocated or
+/// deallocated inside a rolled back transaction, are still reported by the events, emitted
+/// during it.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SVec;
+/// # use ic_stable_memory::{stable_memory_init, with_transaction};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut vec = SVec::<u64>::new();
+/// vec.push(1).expect("Out of memory");
+///
+/// let res: Result<(), &str> = with_transaction(&mut vec, |vec| {
+///     vec.push(2).expect("Out of memory");
+///     *vec.get_mut(0).unwrap() = 10;
+///
+///     Err("something went wrong")
+/// });
+///
+/// assert!(res.is_err());
+/// assert_eq!(vec.len(), 1);
+/// assert_eq!(*vec.get(0).unwrap(), 1);
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+pub fn with_transaction<S, R, E, F>(state: &mut S, func: F) -> Result<R, E>
+where
+    S: StableType + AsFixedSizeBytes,
+    F: FnOnce(&mut S) -> Result<R, E>,
+{
+    let mut guard = TransactionGuard::begin(state);
+    let res = func(&mut *guard.state);
+
+    if res.is_ok() {
+        guard.committed = true;
+    }
+
+    res
+}
+
+/// Returns [true] if there is an active transaction
+#[inline]
+pub fn is_in_transaction() -> bool {
+    DEPTH.with(|it| it.get()) > 0
+}
+
+// saves the previous contents of the memory region, if there is an active transaction
+#[inline]
+pub(crate) fn journal(offset: u64, len: usize) {
+    if !is_in_transaction() {
+        return;
+    }
+
+    let mut prev = vec![0u8; len];
+    stable::read(offset, &mut prev);
+
+    JOURNAL.with(|it| it.borrow_mut().push((offset, prev)));
+}
+
+struct TransactionGuard<'a, S: StableType + AsFixedSizeBytes> {
+    state: &'a mut S,
+    state_buf: S::Buf,
+    allocator: StableMemoryAllocator,
+    journal_start: usize,
+    committed: bool,
+}
+
+impl<'a, S: StableType + AsFixedSizeBytes> TransactionGuard<'a, S> {
+    fn begin(state: &'a mut S) -> Self {
+        let allocator = STABLE_MEMORY_ALLOCATOR.with(|it| {
+            if let Some(alloc) = &*it.borrow() {
+                alloc.clone()
+            } else {
+                unreachable!("StableMemoryAllocator is not initialized");
+            }
+        });
+
+        // buffered writes were made before this transaction, so they should not be journaled
+        crate::mem::write_coalescing::flush();
+
+        let journal_start = JOURNAL.with(|it| it.borrow().len());
+        DEPTH.with(|it| it.set(it.get() + 1));
+
+        Self {
+            state_buf: state.as_new_fixed_size_bytes(),
+            state,
+            allocator,
+            journal_start,
+            committed: false,
+        }
+    }
+
+    fn rollback(&mut self) {
+        let entries = JOURNAL.with(|it| it.borrow_mut().split_off(self.journal_start));
+
+        // replayed writes are not journaled - after them the memory is exactly the same, as it was at
+        // the start of this transaction, which is already covered by outer transactions' journal
+        let depth = DEPTH.with(|it| it.replace(0));
+        for (offset, prev) in entries.into_iter().rev() {
+            stable::write(offset, &prev);
+        }
+        DEPTH.with(|it| it.set(depth));
+
+        STABLE_MEMORY_ALLOCATOR.with(|it| {
+            if let Some(alloc) = &mut *it.borrow_mut() {
+                *alloc = self.allocator.clone();
+                alloc.reclaim_grown_pages();
+            } else {
+                unreachable!("StableMemoryAllocator is not initialized");
+            }
+        });
+
+        clear_decode_cache();
+
+        // the restored value takes ownership of the data, the current one should not release anything
+        unsafe {
+            let mut restored = S::from_fixed_size_bytes(self.state_buf._deref());
+            restored.stable_drop_flag_on();
+
+            let mut current = std::mem::replace(self.state, restored);
+            current.stable_drop_flag_off();
+        }
+    }
+}
+
+impl<'a, S: StableType + AsFixedSizeBytes> Drop for TransactionGuard<'a, S> {
+    fn drop(&mut self) {
+        let depth = DEPTH.with(|it| {
+            it.set(it.get() - 1);
+            it.get()
+        });
+
+        if !self.committed {
+            self.rollback();
+        }
+
+        if depth == 0 {
+            JOURNAL.with(|it| it.borrow_mut().clear());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::{SBTreeMap, SVec};
+    use crate::mem::write_coalescing::with_write_coalescing;
+    use crate::primitive::s_box::SBox;
+    use crate::utils::mem_context::stable;
+    use crate::utils::transaction::{is_in_transaction, with_transaction};
+    use crate::{_debug_validate_allocator, get_allocated_size, stable_memory_init};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMap::new();
+            for i in 0..100u64 {
+                map.insert(i, SBox::new(format!("{}", i)).unwrap()).unwrap();
+            }
+
+            let allocated = get_allocated_size();
+
+            let res: Result<(), ()> = with_transaction(&mut map, |map| {
+                for i in 0..50u64 {
+                    map.remove(&i);
+                }
+                for i in 100..10_000u64 {
+                    map.insert(i, SBox::new(format!("{}", i)).unwrap()).unwrap();
+                }
+
+                assert!(is_in_transaction());
+
+                Err(())
+            });
+
+            assert!(res.is_err());
+            assert!(!is_in_transaction());
+            assert_eq!(get_allocated_size(), allocated);
+            _debug_validate_allocator();
+
+            assert_eq!(map.len(), 100);
+            for i in 0..100u64 {
+                assert_eq!(*map.get(&i).unwrap().clone(), format!("{}", i));
+            }
+
+            let res: Result<u64, ()> = with_transaction(&mut map, |map| {
+                map.remove(&0);
+                map.insert(100, SBox::new(String::from("100")).unwrap())
+                    .unwrap();
+
+                Ok(map.len())
+            });
+
+            assert_eq!(res, Ok(100));
+            assert!(!map.contains_key(&0));
+            assert!(map.contains_key(&100));
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn inside_write_coalescing_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::new();
+            for i in 0..10u64 {
+                vec.push(i).unwrap();
+            }
+
+            with_write_coalescing(|| {
+                // buffered before the transaction, so the rollback should keep it
+                *vec.get_mut(0).unwrap() = 100;
+
+                let res: Result<(), ()> = with_transaction(&mut vec, |vec| {
+                    *vec.get_mut(0).unwrap() = 200;
+                    *vec.get_mut(1).unwrap() = 201;
+                    vec.push(10).unwrap();
+
+                    Err(())
+                });
+
+                assert!(res.is_err());
+                assert_eq!(vec.len(), 10);
+                assert_eq!(*vec.get(0).unwrap(), 100);
+                assert_eq!(*vec.get(1).unwrap(), 1);
+
+                let res: Result<(), ()> = with_transaction(&mut vec, |vec| {
+                    *vec.get_mut(1).unwrap() = 301;
+                    Ok(())
+                });
+
+                assert!(res.is_ok());
+                *vec.get_mut(2).unwrap() = 302;
+            });
+
+            assert_eq!(
+                vec.iter().map(|it| *it).collect::<Vec<_>>(),
+                [vec![100, 301, 302], (3..10).collect::<Vec<_>>()].concat()
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn nested_and_panics_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::new();
+            for i in 0..10u64 {
+                vec.push(i).unwrap();
+            }
+
+            let res: Result<(), ()> = with_transaction(&mut vec, |vec| {
+                vec.push(10).unwrap();
+
+                // committed inner transaction is still rolled back by the outer one
+                let inner: Result<(), ()> = with_transaction(vec, |vec| {
+                    vec.push(11).unwrap();
+                    Ok(())
+                });
+                assert!(inner.is_ok());
+
+                let inner: Result<(), ()> = with_transaction(vec, |vec| {
+                    for i in 0..1000 {
+                        vec.push(i).unwrap();
+                    }
+                    Err(())
+                });
+                assert!(inner.is_err());
+                _debug_validate_allocator();
+                assert_eq!(vec.len(), 12);
+
+                Err(())
+            });
+
+            assert!(res.is_err());
+            _debug_validate_allocator();
+            assert_eq!(
+                vec.iter().map(|it| *it).collect::<Vec<_>>(),
+                (0..10).collect::<Vec<_>>()
+            );
+
+            let res = catch_unwind(AssertUnwindSafe(|| {
+                let _: Result<(), ()> = with_transaction(&mut vec, |vec| {
+                    *vec.get_mut(0).unwrap() = 100;
+                    vec.push(10).unwrap();
+
+                    panic!("boom");
+                });
+            }));
+
+            assert!(res.is_err());
+            assert!(!is_in_transaction());
+            assert_eq!(vec.len(), 10);
+            assert_eq!(*vec.get(0).unwrap(), 0);
+
+            _debug_validate_allocator();
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}